@@ -1,16 +1,19 @@
 //! The inner data of the consensus state machine. This is shared between the different roles.
 use crate::PayloadManager;
-use std::sync::Arc;
+use std::{ops::RangeInclusive, sync::Arc};
+use zksync_concurrency::{sync, time};
+use zksync_consensus_network as network;
 use zksync_consensus_roles::validator;
 use zksync_consensus_storage as storage;
 
 /// Configuration of the bft actor.
 #[derive(Debug)]
 pub struct Config {
-    /// The validator's secret key.
-    pub secret_key: validator::SecretKey,
+    /// Signer of the validator messages.
+    pub signer: Arc<dyn validator::ValidatorSigner>,
     /// The maximum size of the payload of a block, in bytes. We will
     /// reject blocks with payloads larger than this.
+    /// Overridden by the limit from genesis, if present.
     pub max_payload_size: usize,
     /// Block store.
     pub block_store: Arc<storage::BlockStore>,
@@ -18,11 +21,123 @@ pub struct Config {
     pub replica_store: Box<dyn storage::ReplicaStore>,
     /// Payload manager.
     pub payload_manager: Box<dyn PayloadManager>,
+    /// Whether to build a payload whenever another validator is the leader (without broadcasting it),
+    /// to collect statistics of the payload builder and compare them against the actual blocks.
+    /// Opt-in: takes effect only if `payload_manager` supports shadow proposals as well.
+    pub shadow_proposer: bool,
+    /// Number of tasks verifying the signatures and the QCs of the received messages in parallel.
+    pub verifier_threads: usize,
+    /// If the stored blocks are more than this many blocks behind the highest QC,
+    /// the replica enters the catch-up mode: it stops voting and proposing until
+    /// it syncs all the blocks up to the highest QC. `None` disables the catch-up mode.
+    pub catch_up_threshold: Option<u64>,
+    /// Whether to run in the observer mode: the validator verifies and tracks the consensus
+    /// messages like any other replica, but never signs votes or proposals.
+    /// Useful for rehearsing the onboarding of a validator without affecting the quorum.
+    pub observer: bool,
+    /// If the payload manager has nothing to propose (returns an empty payload),
+    /// the leader retries for up to this long before proposing an empty block.
+    /// It avoids chains full of empty blocks when the transactions are produced
+    /// slower than the views. Should be well below the view timeout.
+    /// `None` proposes the payload immediately.
+    pub max_payload_wait: Option<time::Duration>,
+    /// Checkpointing of the replica state. `None` disables it.
+    pub checkpoint: Option<CheckpointConfig>,
+    /// Source of the time for the view timeouts and the timestamps of the view history.
+    /// Should be shared with the network actor.
+    pub time_source: network::TimeSource,
+    /// Recorder of the consensus messages received and sent by the actor,
+    /// for debugging (see `record::replay()`). `None` disables recording.
+    pub recorder: Option<Arc<crate::record::Recorder>>,
+    /// Rotation of this validator's key, which the validator proposes to commit to the chain
+    /// whenever it is the leader, until a block committing it is finalized
+    /// or the activation view of the rotation passes.
+    pub key_rotation: Option<validator::KeyRotationCert>,
+    /// Certificate of the protocol upgrade, which the validator proposes to commit to the chain
+    /// whenever it is the leader, until a block committing it is finalized or the activation
+    /// block is reached. Usually fed by `network::Network::subscribe_upgrade_qc()`.
+    /// `None` never proposes an upgrade.
+    pub protocol_upgrade: Option<sync::watch::Receiver<Option<Arc<validator::ProtocolUpgradeQC>>>>,
+    /// History of the view transitions of the replica.
+    pub view_history: crate::ViewHistory,
+}
+
+/// Checkpointing of the replica state, see `storage::ReplicaCheckpoint`.
+#[derive(Debug)]
+pub struct CheckpointConfig {
+    /// Storage of the checkpoints. It should be separate from the replica store,
+    /// so that it doesn't get rolled back together with it.
+    pub store: Box<dyn storage::CheckpointStore>,
+    /// Min interval between the checkpoints.
+    pub interval: time::Duration,
+    /// Operator override: start even if the replica state is older than the checkpoint.
+    /// The state is then fast-forwarded to the checkpoint, but the replica may still
+    /// sign conflicting messages in the views it has voted in after the checkpoint.
+    pub allow_stale_state: bool,
 }
 
 impl Config {
     /// Genesis.
-    pub fn genesis(&self) -> &validator::Genesis {
+    pub fn genesis(&self) -> Arc<validator::Genesis> {
         self.block_store.genesis()
     }
+
+    /// Protocol versions of the consensus messages supported by this node:
+    /// from the version of the genesis up to `crate::PROTOCOL_VERSION`.
+    pub fn supported_protocol_versions(&self) -> RangeInclusive<validator::ProtocolVersion> {
+        self.genesis().protocol_version..=crate::PROTOCOL_VERSION
+    }
+
+    /// Checks whether the consensus messages of the given protocol version are supported.
+    pub fn supports(&self, version: validator::ProtocolVersion) -> bool {
+        self.supported_protocol_versions().contains(&version)
+    }
+
+    /// Checks the protocol version of a consensus message which is not about a specific block
+    /// (i.e. a timeout): the replicas stamp it with the version of their next block, which
+    /// depends on their high QC, so any supported version is accepted.
+    /// Returns the accepted versions on failure.
+    pub(crate) fn check_protocol_version(
+        &self,
+        version: validator::ProtocolVersion,
+    ) -> Result<(), RangeInclusive<validator::ProtocolVersion>> {
+        let supported = self.supported_protocol_versions();
+        match supported.contains(&version) {
+            true => Ok(()),
+            false => Err(supported),
+        }
+    }
+
+    /// Checks the protocol version of a consensus message about the block `number`
+    /// (proposing it, voting for it, or building on the block preceding it):
+    /// the message has to carry the version active at that block, see `Genesis::protocol_version_at`.
+    /// Returns the accepted versions on failure.
+    pub(crate) fn check_protocol_version_at(
+        &self,
+        version: validator::ProtocolVersion,
+        number: validator::BlockNumber,
+    ) -> Result<(), RangeInclusive<validator::ProtocolVersion>> {
+        let want = self.genesis().protocol_version_at(number);
+        match version == want && self.supports(want) {
+            true => Ok(()),
+            false => Err(want..=want),
+        }
+    }
+
+    /// The maximum size of the payload of a block, in bytes.
+    pub fn payload_size_limit(&self) -> usize {
+        self.genesis().payload_size_limit(self.max_payload_size)
+    }
+
+    /// Whether the shadow proposer should run: it has to be enabled in the config
+    /// and supported by the payload manager.
+    pub(crate) fn shadow_proposer_enabled(&self) -> bool {
+        self.shadow_proposer && self.payload_manager.supports_shadow_proposals()
+    }
+
+    /// Max number of the inbound consensus messages buffered by the leader
+    /// and by the replica (each) before they are processed.
+    pub(crate) fn inbound_capacity(&self) -> usize {
+        crate::INBOUND_MSGS_PER_VALIDATOR * self.genesis().validators.len()
+    }
 }
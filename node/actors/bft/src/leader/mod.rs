@@ -4,6 +4,7 @@
 
 pub(crate) mod replica_commit;
 pub(crate) mod replica_prepare;
+pub(crate) mod replica_timeout;
 mod state_machine;
 #[cfg(test)]
 mod tests;
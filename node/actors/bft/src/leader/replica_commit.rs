@@ -1,9 +1,9 @@
 //! Handler of a ReplicaCommit message.
 use super::StateMachine;
 use crate::metrics;
-use std::collections::HashMap;
+use std::{collections::HashMap, ops::RangeInclusive};
 use tracing::instrument;
-use zksync_concurrency::{ctx, metrics::LatencyHistogramExt as _};
+use zksync_concurrency::{ctx, error::Wrap, metrics::LatencyHistogramExt as _};
 use zksync_consensus_network::io::{ConsensusInputMessage, Target};
 use zksync_consensus_roles::validator::{self, CommitQC, ProtocolVersion};
 
@@ -11,12 +11,12 @@ use zksync_consensus_roles::validator::{self, CommitQC, ProtocolVersion};
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum Error {
     /// Incompatible protocol version.
-    #[error("incompatible protocol version (message version: {message_version:?}, local version: {local_version:?}")]
+    #[error("incompatible protocol version (message version: {message_version:?}, supported versions: {supported_versions:?})")]
     IncompatibleProtocolVersion {
         /// Message version.
         message_version: ProtocolVersion,
-        /// Local version.
-        local_version: ProtocolVersion,
+        /// Versions accepted for this message.
+        supported_versions: RangeInclusive<ProtocolVersion>,
     },
     /// Message signer isn't part of the validator set.
     #[error("Message signer isn't part of the validator set (signer: {signer:?})")]
@@ -47,11 +47,26 @@ pub(crate) enum Error {
     /// Invalid message signature.
     #[error("invalid signature: {0:#}")]
     InvalidSignature(#[source] validator::Error),
+    /// Internal error. Unlike other error types, this one isn't supposed to be easily recoverable.
+    #[error(transparent)]
+    Internal(#[from] ctx::Error),
+}
+
+impl Wrap for Error {
+    fn with_wrap<C: std::fmt::Display + Send + Sync + 'static, F: FnOnce() -> C>(
+        self,
+        f: F,
+    ) -> Self {
+        match self {
+            Error::Internal(err) => Error::Internal(err.with_wrap(f)),
+            err => err,
+        }
+    }
 }
 
 impl StateMachine {
     #[instrument(level = "trace", skip(self), ret)]
-    pub(crate) fn process_replica_commit(
+    pub(crate) async fn process_replica_commit(
         &mut self,
         ctx: &ctx::Ctx,
         signed_message: validator::Signed<validator::ReplicaCommit>,
@@ -62,20 +77,25 @@ impl StateMachine {
         let message = &signed_message.msg;
         let author = &signed_message.key;
 
-        // Check protocol version compatibility.
-        if !crate::PROTOCOL_VERSION.compatible(&message.view.protocol_version) {
-            return Err(Error::IncompatibleProtocolVersion {
+        // Check protocol version compatibility. The vote has to carry the version
+        // which is active at the height of the proposal.
+        self.config
+            .check_protocol_version_at(message.view.protocol_version, message.proposal.number)
+            .map_err(|supported_versions| Error::IncompatibleProtocolVersion {
                 message_version: message.view.protocol_version,
-                local_version: crate::PROTOCOL_VERSION,
-            });
-        }
+                supported_versions,
+            })?;
 
         // Check that the message signer is in the validator set.
-        if !self.config.genesis().validators.contains(author) {
+        let genesis = self.config.genesis();
+        let Some(signer) = genesis.signer(author, message.view.number) else {
             return Err(Error::NonValidatorSigner {
                 signer: author.clone(),
             });
-        }
+        };
+        // Messages are cached by the genesis key of the signer, so that a validator
+        // which has rotated its key cannot be counted twice.
+        let author = genesis.validators.get(signer.index).unwrap();
 
         // If the message is from the "past", we discard it.
         if (message.view.number, validator::Phase::Commit) < (self.view, self.phase) {
@@ -86,12 +106,10 @@ impl StateMachine {
         }
 
         // If the message is for a view when we are not a leader, we discard it.
-        if self
+        if !self
             .config
             .genesis()
-            .validators
-            .view_leader(message.view.number)
-            != self.config.secret_key.public()
+            .is_view_leader(&self.config.signer.public(), message.view.number)
         {
             return Err(Error::NotLeaderInView);
         }
@@ -110,10 +128,12 @@ impl StateMachine {
         // ----------- Checking the signed part of the message --------------
 
         // Check the signature on the message.
-        signed_message.verify().map_err(Error::InvalidSignature)?;
+        self.verifier
+            .verify(&signed_message)
+            .map_err(Error::InvalidSignature)?;
 
         message
-            .verify(self.config.genesis())
+            .verify(&self.config.genesis())
             .map_err(Error::InvalidMessage)?;
 
         // ----------- All checks finished. Now we process the message. --------------
@@ -124,8 +144,8 @@ impl StateMachine {
         // We add the message to the incrementally-constructed QC.
         self.commit_qcs
             .entry(message.view.number)
-            .or_insert_with(|| CommitQC::new(message.clone(), self.config.genesis()))
-            .add(&signed_message, self.config.genesis());
+            .or_insert_with(|| CommitQC::new(message.clone(), &self.config.genesis()))
+            .add(&signed_message, &self.config.genesis());
 
         // We store the message in our cache.
         let cache_entry = self
@@ -170,17 +190,25 @@ impl StateMachine {
         let output_message = ConsensusInputMessage {
             message: self
                 .config
-                .secret_key
-                .sign_msg(validator::ConsensusMsg::LeaderCommit(
-                    validator::LeaderCommit { justification },
-                )),
+                .signer
+                .sign_msg(
+                    ctx,
+                    validator::ConsensusMsg::LeaderCommit(validator::LeaderCommit {
+                        justification,
+                    }),
+                )
+                .await
+                .wrap("sign_msg()")?,
             recipient: Target::Broadcast,
+            trace: self.trace,
         };
         self.outbound_pipe.send(output_message.into());
 
         // Clean the caches.
-        self.prepare_message_cache.retain(|k, _| k >= &self.view);
+        self.prepare_message_cache
+            .retain(|k, _| k.number >= self.view);
         self.commit_message_cache.retain(|k, _| k >= &self.view);
+        self.timeout_qcs.retain(|k, _| k.number >= self.view);
 
         Ok(())
     }
@@ -1,5 +1,6 @@
 //! Handler of a ReplicaPrepare message.
 use super::StateMachine;
+use std::ops::RangeInclusive;
 use tracing::instrument;
 use zksync_concurrency::{ctx, error::Wrap};
 use zksync_consensus_roles::validator::{self, ProtocolVersion};
@@ -8,12 +9,12 @@ use zksync_consensus_roles::validator::{self, ProtocolVersion};
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum Error {
     /// Incompatible protocol version.
-    #[error("incompatible protocol version (message version: {message_version:?}, local version: {local_version:?}")]
+    #[error("incompatible protocol version (message version: {message_version:?}, supported versions: {supported_versions:?})")]
     IncompatibleProtocolVersion {
         /// Message version.
         message_version: ProtocolVersion,
-        /// Local version.
-        local_version: ProtocolVersion,
+        /// Versions accepted for this message.
+        supported_versions: RangeInclusive<ProtocolVersion>,
     },
     /// Message signer isn't part of the validator set.
     #[error("Message signer isn't part of the validator set (signer: {signer:?})")]
@@ -74,20 +75,29 @@ impl StateMachine {
         let message = signed_message.msg.clone();
         let author = &signed_message.key;
 
-        // Check protocol version compatibility.
-        if !crate::PROTOCOL_VERSION.compatible(&message.view.protocol_version) {
-            return Err(Error::IncompatibleProtocolVersion {
+        // Check protocol version compatibility. The message has to carry the version
+        // of the block following its high QC, which is the block to be proposed.
+        let next = match &message.high_qc {
+            Some(qc) => qc.header().number.next(),
+            None => self.config.genesis().fork.first_block,
+        };
+        self.config
+            .check_protocol_version_at(message.view.protocol_version, next)
+            .map_err(|supported_versions| Error::IncompatibleProtocolVersion {
                 message_version: message.view.protocol_version,
-                local_version: crate::PROTOCOL_VERSION,
-            });
-        }
+                supported_versions,
+            })?;
 
         // Check that the message signer is in the validator set.
-        if !self.config.genesis().validators.contains(author) {
+        let genesis = self.config.genesis();
+        let Some(signer) = genesis.signer(author, message.view.number) else {
             return Err(Error::NonValidatorSigner {
                 signer: author.clone(),
             });
-        }
+        };
+        // Messages are cached by the genesis key of the signer, so that a validator
+        // which has rotated its key cannot be counted twice.
+        let author = genesis.validators.get(signer.index).unwrap();
 
         // If the message is from the "past", we discard it.
         if (message.view.number, validator::Phase::Prepare) < (self.view, self.phase) {
@@ -98,12 +108,10 @@ impl StateMachine {
         }
 
         // If the message is for a view when we are not a leader, we discard it.
-        if self
+        if !self
             .config
             .genesis()
-            .validators
-            .view_leader(message.view.number)
-            != self.config.secret_key.public()
+            .is_view_leader(&self.config.signer.public(), message.view.number)
         {
             return Err(Error::NotLeaderInView);
         }
@@ -111,7 +119,7 @@ impl StateMachine {
         // If we already have a message from the same validator and for the same view, we discard it.
         if let Some(existing_message) = self
             .prepare_message_cache
-            .get(&message.view.number)
+            .get(&message.view)
             .and_then(|x| x.get(author))
         {
             return Err(Error::Exists {
@@ -122,33 +130,32 @@ impl StateMachine {
         // ----------- Checking the signed part of the message --------------
 
         // Check the signature on the message.
-        signed_message.verify().map_err(Error::InvalidSignature)?;
+        self.verifier
+            .verify(&signed_message)
+            .map_err(Error::InvalidSignature)?;
 
-        // Verify the message.
+        // Verify the message. The high QC has been most likely verified by the pool already.
+        let genesis = self.config.genesis();
         message
-            .verify(self.config.genesis())
+            .verify_with(&genesis, |qc| self.verifier.verify_high_qc(&genesis, qc))
             .map_err(Error::InvalidMessage)?;
 
         // ----------- All checks finished. Now we process the message. --------------
 
         // We add the message to the incrementally-constructed QC.
         self.prepare_qcs
-            .entry(message.view.number)
+            .entry(message.view.clone())
             .or_insert_with(|| validator::PrepareQC::new(message.view.clone()))
-            .add(&signed_message, self.config.genesis());
+            .add(&signed_message, &self.config.genesis());
 
         // We store the message in our cache.
         self.prepare_message_cache
-            .entry(message.view.number)
+            .entry(message.view.clone())
             .or_default()
             .insert(author.clone(), signed_message);
 
         // Now we check if we have enough messages to continue.
-        let num_messages = self
-            .prepare_message_cache
-            .get(&message.view.number)
-            .unwrap()
-            .len();
+        let num_messages = self.prepare_message_cache.get(&message.view).unwrap().len();
 
         if num_messages < self.config.genesis().validators.threshold() {
             return Ok(());
@@ -156,7 +163,7 @@ impl StateMachine {
 
         // Remove replica prepare messages for this view, so that we don't create a new block proposal
         // for this same view if we receive another replica prepare message after this.
-        self.prepare_message_cache.remove(&message.view.number);
+        self.prepare_message_cache.remove(&message.view);
 
         debug_assert_eq!(num_messages, self.config.genesis().validators.threshold());
 
@@ -167,9 +174,20 @@ impl StateMachine {
         self.phase_start = ctx.now();
 
         // Consume the incrementally-constructed QC for this view.
-        let justification = self.prepare_qcs.remove(&message.view.number).unwrap();
-
-        self.prepare_qc.send_replace(Some(justification));
+        let justification = self.prepare_qcs.remove(&message.view).unwrap();
+        // Attach the TimeoutQC of the previous view, if we have aggregated it,
+        // to prove that we have entered the view because the previous one has timed out.
+        let threshold = self.config.genesis().validators.threshold();
+        let timeout_qc = self
+            .timeout_qcs
+            .values()
+            .find(|qc| {
+                qc.view().number.next() == message.view.number && qc.signers.count() >= threshold
+            })
+            .cloned();
+
+        self.prepare_qc
+            .send_replace(Some((justification, timeout_qc)));
         Ok(())
     }
 }
@@ -0,0 +1,172 @@
+//! Handler of a ReplicaTimeout message.
+use super::StateMachine;
+use std::ops::RangeInclusive;
+use tracing::instrument;
+use zksync_concurrency::{ctx, error::Wrap};
+use zksync_consensus_network::io::{ConsensusInputMessage, Target};
+use zksync_consensus_roles::validator::{self, ProtocolVersion, TimeoutQC};
+
+/// Errors that can occur when processing a "replica timeout" message.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    /// Incompatible protocol version.
+    #[error("incompatible protocol version (message version: {message_version:?}, supported versions: {supported_versions:?})")]
+    IncompatibleProtocolVersion {
+        /// Message version.
+        message_version: ProtocolVersion,
+        /// Versions accepted for this message.
+        supported_versions: RangeInclusive<ProtocolVersion>,
+    },
+    /// Message signer isn't part of the validator set.
+    #[error("Message signer isn't part of the validator set (signer: {signer:?})")]
+    NonValidatorSigner {
+        /// Signer of the message.
+        signer: validator::PublicKey,
+    },
+    /// Past view.
+    #[error("past view (current view: {current_view:?})")]
+    Old {
+        /// Current view.
+        current_view: validator::ViewNumber,
+    },
+    /// The processing node is not a lead for the view following the message's view.
+    #[error("we are not a leader for the next view")]
+    NotLeaderInNextView,
+    /// Invalid message.
+    #[error("invalid message: {0:#}")]
+    InvalidMessage(anyhow::Error),
+    /// Duplicate message from a replica.
+    #[error("duplicate message from a replica")]
+    DuplicateMessage,
+    /// Invalid message signature.
+    #[error("invalid signature: {0:#}")]
+    InvalidSignature(#[source] validator::Error),
+    /// Internal error. Unlike other error types, this one isn't supposed to be easily recoverable.
+    #[error(transparent)]
+    Internal(#[from] ctx::Error),
+}
+
+impl Wrap for Error {
+    fn with_wrap<C: std::fmt::Display + Send + Sync + 'static, F: FnOnce() -> C>(
+        self,
+        f: F,
+    ) -> Self {
+        match self {
+            Error::Internal(err) => Error::Internal(err.with_wrap(f)),
+            err => err,
+        }
+    }
+}
+
+impl StateMachine {
+    /// Processes a timeout vote of a replica. Votes are aggregated by the leader
+    /// of the view following the timed out view. Once a quorum is reached,
+    /// the resulting TimeoutQC is broadcasted, so that the lagging replicas can move on.
+    #[instrument(level = "trace", skip(self), ret)]
+    pub(crate) async fn process_replica_timeout(
+        &mut self,
+        ctx: &ctx::Ctx,
+        signed_message: validator::Signed<validator::ReplicaTimeout>,
+    ) -> Result<(), Error> {
+        // ----------- Checking origin of the message --------------
+
+        // Unwrap message.
+        let message = &signed_message.msg;
+        let author = &signed_message.key;
+
+        // Check protocol version compatibility. A timeout is not about a specific block,
+        // so it is checked against the whole supported range.
+        self.config
+            .check_protocol_version(message.view.protocol_version)
+            .map_err(|supported_versions| Error::IncompatibleProtocolVersion {
+                message_version: message.view.protocol_version,
+                supported_versions,
+            })?;
+
+        // Check that the message signer is in the validator set.
+        let Some(signer) = self.config.genesis().signer(author, message.view.number) else {
+            return Err(Error::NonValidatorSigner {
+                signer: author.clone(),
+            });
+        };
+
+        // If the message is from the "past", we discard it.
+        if message.view.number < self.view {
+            return Err(Error::Old {
+                current_view: self.view,
+            });
+        }
+
+        // If we are not the leader of the next view, we discard it.
+        if !self
+            .config
+            .genesis()
+            .is_view_leader(&self.config.signer.public(), message.view.number.next())
+        {
+            return Err(Error::NotLeaderInNextView);
+        }
+
+        // If we already have a message from the same validator and for the same view, we discard it.
+        if self
+            .timeout_qcs
+            .get(&message.view)
+            .is_some_and(|qc| qc.signers.0[signer.index])
+        {
+            return Err(Error::DuplicateMessage);
+        }
+
+        // ----------- Checking the signed part of the message --------------
+
+        // Check the signature on the message.
+        self.verifier
+            .verify(&signed_message)
+            .map_err(Error::InvalidSignature)?;
+
+        message
+            .verify(&self.config.genesis())
+            .map_err(Error::InvalidMessage)?;
+
+        // ----------- All checks finished. Now we process the message. --------------
+
+        // We add the message to the incrementally-constructed QC.
+        let qc = self
+            .timeout_qcs
+            .entry(message.view.clone())
+            .or_insert_with(|| TimeoutQC::new(message.clone(), &self.config.genesis()));
+        qc.add(&signed_message, &self.config.genesis());
+
+        // Now we check if we have just reached the threshold. The QC is kept in the cache,
+        // so that we don't create a new leader timeout for this same view
+        // if we receive another replica timeout message after this.
+        if qc.signers.count() != self.config.genesis().validators.threshold() {
+            return Ok(());
+        }
+        let justification = qc.clone();
+
+        // ----------- Prepare our message and send it. --------------
+
+        // Broadcast the leader timeout message to all replicas (ourselves included).
+        let output_message = ConsensusInputMessage {
+            message: self
+                .config
+                .signer
+                .sign_msg(
+                    ctx,
+                    validator::ConsensusMsg::LeaderTimeout(validator::LeaderTimeout {
+                        justification,
+                    }),
+                )
+                .await
+                .wrap("sign_msg()")?,
+            recipient: Target::Broadcast,
+            trace: self.trace,
+        };
+        self.outbound_pipe.send(output_message.into());
+
+        // Clean the cache.
+        self.timeout_qcs
+            .retain(|k, _| k.number >= message.view.number);
+
+        Ok(())
+    }
+}
@@ -1,14 +1,39 @@
-use crate::{metrics, Config, OutputSender};
+use crate::{
+    inbound_priority, metrics,
+    verifier::{Verified, Verifier},
+    Config, OutputSender,
+};
+use anyhow::Context as _;
 use std::{
     collections::{BTreeMap, HashMap},
     sync::Arc,
     unreachable,
 };
-use tracing::instrument;
-use zksync_concurrency::{ctx, error::Wrap as _, metrics::LatencyHistogramExt as _, sync, time};
-use zksync_consensus_network::io::{ConsensusInputMessage, ConsensusReq, Target};
+use tracing::{instrument, Instrument as _};
+use zksync_concurrency::{
+    ctx, error::Wrap as _, metrics::LatencyHistogramExt as _, scope, sync, time,
+};
+use zksync_consensus_network::{
+    io::{ConsensusInputMessage, ConsensusReq, Target},
+    TraceContext,
+};
 use zksync_consensus_roles::validator::{self, ConsensusMsg, Signed};
 
+/// Max number of the received messages verified in a single batch.
+const MAX_VERIFY_BATCH: usize = 256;
+/// Interval between the attempts to build a non-empty payload, see `Config::max_payload_wait`.
+const PAYLOAD_RETRY_INTERVAL: time::Duration = time::Duration::milliseconds(100);
+
+/// Justification of a proposal: the PrepareQC of the view and the TimeoutQC
+/// of the preceding view, if the leader has aggregated it.
+pub(crate) type Justification = (validator::PrepareQC, Option<validator::TimeoutQC>);
+
+/// Batch of the received messages together with their verified signatures.
+struct VerifiedBatch {
+    reqs: Vec<ConsensusReq>,
+    verified: Verified,
+}
+
 /// The StateMachine struct contains the state of the leader. This is a simple state machine. We just store
 /// replica messages and produce leader messages (including proposing blocks) when we reach the threshold for
 /// those messages. When participating in consensus we are not the leader most of the time.
@@ -18,7 +43,8 @@ pub(crate) struct StateMachine {
     /// Pipe through which leader sends network messages.
     pub(crate) outbound_pipe: OutputSender,
     /// Pipe through which leader receives network requests.
-    inbound_pipe: sync::prunable_mpsc::Receiver<ConsensusReq>,
+    /// Taken over by the verification task in `run()`.
+    inbound_pipe: Option<sync::prunable_mpsc::Receiver<ConsensusReq>>,
     /// The current view number. This might not match the replica's view number, we only have this here
     /// to make the leader advance monotonically in time and stop it from accepting messages from the past.
     pub(crate) view: validator::ViewNumber,
@@ -27,15 +53,17 @@ pub(crate) struct StateMachine {
     pub(crate) phase: validator::Phase,
     /// Time when the current phase has started.
     pub(crate) phase_start: time::Instant,
-    /// A cache of replica prepare messages indexed by view number and validator.
-    pub(crate) prepare_message_cache: BTreeMap<
-        validator::ViewNumber,
-        HashMap<validator::PublicKey, Signed<validator::ReplicaPrepare>>,
-    >,
-    /// Prepare QCs indexed by view number.
-    pub(crate) prepare_qcs: BTreeMap<validator::ViewNumber, validator::PrepareQC>,
-    /// Newest prepare QC composed from the `ReplicaPrepare` messages.
-    pub(crate) prepare_qc: sync::watch::Sender<Option<validator::PrepareQC>>,
+    /// A cache of replica prepare messages indexed by view and validator.
+    /// Indexed by the whole view, since around the activation of a protocol upgrade
+    /// the replicas may stamp the same view number with different protocol versions,
+    /// and only the messages for the same view can be aggregated.
+    pub(crate) prepare_message_cache:
+        BTreeMap<validator::View, HashMap<validator::PublicKey, Signed<validator::ReplicaPrepare>>>,
+    /// Prepare QCs indexed by view.
+    pub(crate) prepare_qcs: BTreeMap<validator::View, validator::PrepareQC>,
+    /// Newest prepare QC composed from the `ReplicaPrepare` messages,
+    /// together with the TimeoutQC of the preceding view, if it has been aggregated.
+    pub(crate) prepare_qc: sync::watch::Sender<Option<Justification>>,
     /// A cache of replica commit messages indexed by view number and validator.
     pub(crate) commit_message_cache: BTreeMap<
         validator::ViewNumber,
@@ -43,6 +71,14 @@ pub(crate) struct StateMachine {
     >,
     /// Commit QCs indexed by view number.
     pub(crate) commit_qcs: BTreeMap<validator::ViewNumber, validator::CommitQC>,
+    /// Timeout QCs indexed by the timed out view.
+    /// Aggregated only for the views followed by a view led by this validator.
+    pub(crate) timeout_qcs: BTreeMap<validator::View, validator::TimeoutQC>,
+    /// Pool verifying the signatures and the high QCs of the received votes.
+    pub(crate) verifier: Arc<Verifier>,
+    /// Trace context of the message being processed.
+    /// It is propagated to the messages sent in response.
+    pub(crate) trace: Option<TraceContext>,
 }
 
 impl StateMachine {
@@ -57,8 +93,13 @@ impl StateMachine {
         config: Arc<Config>,
         outbound_pipe: OutputSender,
     ) -> (Self, sync::prunable_mpsc::Sender<ConsensusReq>) {
-        let (send, recv) = sync::prunable_mpsc::channel(StateMachine::inbound_pruning_predicate);
+        let (send, recv) = sync::prunable_mpsc::bounded(
+            config.inbound_capacity(),
+            StateMachine::inbound_pruning_predicate,
+            inbound_priority(&config.block_store),
+        );
 
+        let verifier = Arc::new(Verifier::new(config.verifier_threads));
         let this = StateMachine {
             config,
             outbound_pipe,
@@ -70,7 +111,10 @@ impl StateMachine {
             commit_message_cache: BTreeMap::new(),
             prepare_qc: sync::watch::channel(None).0,
             commit_qcs: BTreeMap::new(),
-            inbound_pipe: recv,
+            timeout_qcs: BTreeMap::new(),
+            inbound_pipe: Some(recv),
+            verifier,
+            trace: None,
         };
 
         (this, send)
@@ -79,45 +123,115 @@ impl StateMachine {
     /// Runs a loop to process incoming messages.
     /// This is the main entry point for the state machine,
     /// potentially triggering state modifications and message sending to the executor.
+    ///
+    /// Messages are processed in a pipeline: the signatures of the next batch of votes are
+    /// verified on a separate task, while the current batch is being aggregated, so that
+    /// the votes arriving during a slow phase (e.g. while the replicas verify a large payload)
+    /// are ready to be aggregated as soon as they are needed.
     pub(crate) async fn run(mut self, ctx: &ctx::Ctx) -> ctx::Result<()> {
-        loop {
-            let req = self.inbound_pipe.recv(ctx).await?;
-
-            let now = ctx.now();
-            let label = match &req.msg.msg {
-                ConsensusMsg::ReplicaPrepare(_) => {
-                    let res = match self
-                        .process_replica_prepare(ctx, req.msg.cast().unwrap())
-                        .await
-                        .wrap("process_replica_prepare()")
-                    {
-                        Ok(()) => Ok(()),
-                        Err(super::replica_prepare::Error::Internal(err)) => {
-                            return Err(err);
-                        }
-                        Err(err) => {
-                            tracing::warn!("process_replica_prepare: {err:#}");
-                            Err(())
-                        }
-                    };
-                    metrics::ConsensusMsgLabel::ReplicaPrepare.with_result(&res)
+        let mut inbound_pipe = self.inbound_pipe.take().context("run() called twice")?;
+        let verifier = self.verifier.clone();
+        let config = self.config.clone();
+        // A single batch is buffered, so that the verification task doesn't run ahead
+        // of the processing and the pruning of the inbound pipe stays effective.
+        let (batch_send, mut batch_recv) = ctx::channel::bounded(1);
+        scope::run!(ctx, |ctx, s| async {
+            s.spawn::<()>(async {
+                loop {
+                    let reqs = inbound_pipe.recv_many(ctx, MAX_VERIFY_BATCH).await?;
+                    let msgs: Vec<_> = reqs.iter().map(|req| &req.msg).collect();
+                    let genesis = config.genesis();
+                    let verified = verifier.verify_batch(ctx, &genesis, &msgs).await?;
+                    batch_send
+                        .send(ctx, VerifiedBatch { reqs, verified })
+                        .await?;
                 }
-                ConsensusMsg::ReplicaCommit(_) => {
-                    let res = self
-                        .process_replica_commit(ctx, req.msg.cast().unwrap())
-                        .map_err(|err| {
-                            tracing::warn!("process_replica_commit: {err:#}");
-                        });
-                    metrics::ConsensusMsgLabel::ReplicaCommit.with_result(&res)
+            });
+            loop {
+                let batch = batch_recv.recv(ctx).await?;
+                self.verifier.load(batch.verified);
+                for req in batch.reqs {
+                    self.process(ctx, req).await?;
                 }
-                _ => unreachable!(),
-            };
-            metrics::METRICS.leader_processing_latency[&label].observe_latency(ctx.now() - now);
+                self.verifier.clear();
+            }
+        })
+        .await
+    }
 
-            // Notify network actor that the message has been processed.
-            // Ignore sending error.
-            let _ = req.ack.send(());
-        }
+    /// Processes a single message with a verified signature.
+    async fn process(&mut self, ctx: &ctx::Ctx, req: ConsensusReq) -> ctx::Result<()> {
+        let now = ctx.now();
+        // Continue the trace of the sender.
+        self.trace = req.trace.map(|trace| trace.child(&mut ctx.rng()));
+        let span = self
+            .trace
+            .as_ref()
+            .map_or_else(tracing::Span::none, TraceContext::span);
+        let label = match &req.msg.msg {
+            ConsensusMsg::ReplicaPrepare(_) => {
+                let res = match self
+                    .process_replica_prepare(ctx, req.msg.cast().unwrap())
+                    .instrument(span)
+                    .await
+                    .wrap("process_replica_prepare()")
+                {
+                    Ok(()) => Ok(()),
+                    Err(super::replica_prepare::Error::Internal(err)) => {
+                        return Err(err);
+                    }
+                    Err(err) => {
+                        tracing::warn!("process_replica_prepare: {err:#}");
+                        Err(())
+                    }
+                };
+                metrics::ConsensusMsgLabel::ReplicaPrepare.with_result(&res)
+            }
+            ConsensusMsg::ReplicaCommit(_) => {
+                let res = match self
+                    .process_replica_commit(ctx, req.msg.cast().unwrap())
+                    .instrument(span)
+                    .await
+                    .wrap("process_replica_commit()")
+                {
+                    Ok(()) => Ok(()),
+                    Err(super::replica_commit::Error::Internal(err)) => {
+                        return Err(err);
+                    }
+                    Err(err) => {
+                        tracing::warn!("process_replica_commit: {err:#}");
+                        Err(())
+                    }
+                };
+                metrics::ConsensusMsgLabel::ReplicaCommit.with_result(&res)
+            }
+            ConsensusMsg::ReplicaTimeout(_) => {
+                let res = match self
+                    .process_replica_timeout(ctx, req.msg.cast().unwrap())
+                    .instrument(span)
+                    .await
+                    .wrap("process_replica_timeout()")
+                {
+                    Ok(()) => Ok(()),
+                    Err(super::replica_timeout::Error::Internal(err)) => {
+                        return Err(err);
+                    }
+                    Err(err) => {
+                        tracing::warn!("process_replica_timeout: {err:#}");
+                        Err(())
+                    }
+                };
+                metrics::ConsensusMsgLabel::ReplicaTimeout.with_result(&res)
+            }
+            _ => unreachable!(),
+        };
+        self.trace = None;
+        metrics::METRICS.leader_processing_latency[&label].observe_latency(ctx.now() - now);
+
+        // Notify network actor that the message has been processed.
+        // Ignore sending error.
+        let _ = req.ack.send(());
+        Ok(())
     }
 
     /// In a loop, receives a PrepareQC and sends a LeaderPrepare containing it.
@@ -127,60 +241,172 @@ impl StateMachine {
     pub(crate) async fn run_proposer(
         ctx: &ctx::Ctx,
         config: &Config,
-        mut prepare_qc: sync::watch::Receiver<Option<validator::PrepareQC>>,
+        mut prepare_qc: sync::watch::Receiver<Option<Justification>>,
+        catching_up: sync::watch::Receiver<bool>,
         pipe: &OutputSender,
     ) -> ctx::Result<()> {
         let mut next_view = validator::ViewNumber(0);
         loop {
-            let Some(prepare_qc) = sync::changed(ctx, &mut prepare_qc).await?.clone() else {
+            let Some((prepare_qc, timeout_qc)) = sync::changed(ctx, &mut prepare_qc).await?.clone()
+            else {
                 continue;
             };
             if prepare_qc.view.number < next_view {
                 continue;
             };
+            // A validator which is catching up cannot build on top of the missing blocks.
+            if *catching_up.borrow() {
+                tracing::info!(
+                    "not proposing in view {:?}: catching up",
+                    prepare_qc.view.number
+                );
+                continue;
+            }
             next_view = prepare_qc.view.number.next();
-            Self::propose(ctx, config, prepare_qc, pipe).await?;
+            Self::propose(ctx, config, prepare_qc, timeout_qc, pipe).await?;
+        }
+    }
+
+    /// In a loop, receives the numbers of the blocks expected to be proposed by other validators
+    /// and builds the payload that this validator would have proposed instead, without broadcasting it.
+    /// Payload builder errors are only logged, so that they don't affect the consensus.
+    pub(crate) async fn run_shadow_proposer(
+        ctx: &ctx::Ctx,
+        cfg: &Config,
+        mut shadow_block: sync::watch::Receiver<Option<validator::BlockNumber>>,
+    ) -> ctx::Result<()> {
+        // The replica might have entered the first view before this task started.
+        shadow_block.mark_changed();
+        let mut next = validator::BlockNumber(0);
+        loop {
+            let Some(number) = *sync::changed(ctx, &mut shadow_block).await? else {
+                continue;
+            };
+            if number < next {
+                continue;
+            }
+            next = number.next();
+            match Self::shadow_propose(ctx, cfg, number).await {
+                Ok(()) => {}
+                Err(ctx::Error::Canceled(err)) => return Err(err.into()),
+                Err(ctx::Error::Internal(err)) => {
+                    tracing::warn!("shadow_propose({number:?}): {err:#}");
+                }
+            }
         }
     }
 
-    /// Sends a LeaderPrepare for the given PrepareQC.
+    /// Builds a payload for the given block and records its size and the time it took to build.
+    /// Then waits for the block to be finalized and compares the payloads.
+    async fn shadow_propose(
+        ctx: &ctx::Ctx,
+        cfg: &Config,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<()> {
+        if let Some(prev) = number.prev() {
+            cfg.block_store.wait_until_persisted(ctx, prev).await?;
+        }
+        let start = ctx.now();
+        let payload = cfg.payload_manager.propose(ctx, number).await?;
+        metrics::METRICS
+            .shadow_proposal_latency
+            .observe_latency(ctx.now() - start);
+        metrics::METRICS
+            .shadow_proposal_payload_size
+            .observe(payload.0.len());
+        if payload.0.len() > cfg.payload_size_limit() {
+            tracing::warn!(
+                "shadow payload for block {number:?} too large: got {}B, max {}B",
+                payload.0.len(),
+                cfg.payload_size_limit()
+            );
+        }
+
+        // Compare against the block that has been actually finalized.
+        cfg.block_store.wait_until_queued(ctx, number).await?;
+        let Some(block) = cfg.block_store.block(ctx, number).await? else {
+            return Ok(());
+        };
+        if cfg.genesis().view_leader(block.justification.view().number) == cfg.signer.public() {
+            // This validator has ended up proposing the block itself.
+            return Ok(());
+        }
+        let label = match block.payload == payload {
+            true => metrics::ShadowProposalLabel::Same,
+            false => metrics::ShadowProposalLabel::Different,
+        };
+        metrics::METRICS.shadow_proposals[&label].inc();
+        tracing::debug!(
+            "shadow payload for block {number:?}: {}B, finalized payload: {}B, {label:?}",
+            payload.0.len(),
+            block.payload.0.len(),
+        );
+        Ok(())
+    }
+
+    /// Sends a LeaderPrepare for the given PrepareQC, attaching the TimeoutQC of the previous view if any.
     /// Uses `payload_source` to generate a payload if needed.
     pub(crate) async fn propose(
         ctx: &ctx::Ctx,
         cfg: &Config,
         justification: validator::PrepareQC,
+        timeout_qc: Option<validator::TimeoutQC>,
         pipe: &OutputSender,
     ) -> ctx::Result<()> {
-        let high_vote = justification.high_vote(cfg.genesis());
+        let high_vote = justification.high_vote(&cfg.genesis());
         let high_qc = justification.high_qc();
 
         // Create the block proposal to send to the replicas,
         // and the commit vote to store in our block proposal cache.
-        let (proposal, payload) = match high_vote {
+        let (proposal, payload, key_rotation, protocol_upgrade) = match high_vote {
             // The previous block was not finalized, so we need to propose it again.
             // For this we only need the header, since we are guaranteed that at least
             // f+1 honest replicas have the block and can broadcast it when finalized
             // (2f+1 have stated that they voted for the block, at most f are malicious).
             Some(proposal) if Some(&proposal) != high_qc.map(|qc| &qc.message.proposal) => {
-                (proposal, None)
+                (proposal, None, None, None)
             }
             // The previous block was finalized, so we can propose a new block.
             _ => {
-                let fork = &cfg.genesis().fork;
+                let genesis = cfg.genesis();
                 let (parent, number) = match high_qc {
-                    Some(qc) => (Some(qc.header().hash()), qc.header().number.next()),
-                    None => (fork.first_parent, fork.first_block),
+                    Some(qc) => (
+                        Some(genesis.header_hash(qc.header())),
+                        qc.header().number.next(),
+                    ),
+                    None => (genesis.fork.first_parent, genesis.fork.first_block),
                 };
                 // Defensively assume that PayloadManager cannot propose until the previous block is stored.
                 if let Some(prev) = number.prev() {
                     cfg.block_store.wait_until_persisted(ctx, prev).await?;
                 }
-                let payload = cfg.payload_manager.propose(ctx, number).await?;
-                if payload.0.len() > cfg.max_payload_size {
+                // The genesis includes the rotations committed by the preceding blocks by now.
+                let genesis = cfg.genesis();
+                let key_rotation = cfg
+                    .key_rotation
+                    .as_ref()
+                    .filter(|cert| {
+                        cert.rotation.msg.activation > justification.view().number
+                            && genesis.check_key_rotation(cert).is_ok()
+                    })
+                    .cloned();
+                // The genesis includes the upgrades committed by the preceding blocks as well,
+                // so an upgrade is not proposed again once it has been committed.
+                let protocol_upgrade = cfg.protocol_upgrade.as_ref().and_then(|qc| {
+                    qc.borrow()
+                        .as_deref()
+                        .filter(|qc| {
+                            qc.message.version <= crate::PROTOCOL_VERSION
+                                && genesis.check_protocol_upgrade(qc, number).is_ok()
+                        })
+                        .cloned()
+                });
+                let payload = Self::build_payload(ctx, cfg, number).await?;
+                if payload.0.len() > cfg.payload_size_limit() {
                     return Err(anyhow::format_err!(
                         "proposed payload too large: got {}B, max {}B",
                         payload.0.len(),
-                        cfg.max_payload_size
+                        cfg.payload_size_limit()
                     )
                     .into());
                 }
@@ -191,33 +417,77 @@ impl StateMachine {
                     number,
                     parent,
                     payload: payload.hash(),
+                    payload_root: genesis
+                        .protocol_version_at(number)
+                        .commits_payload_root()
+                        .then(|| payload.root()),
+                    key_rotation: key_rotation.as_ref().map(|cert| cert.hash()),
+                    protocol_upgrade: protocol_upgrade.as_ref().map(|qc| qc.hash()),
                 };
-                (proposal, Some(payload))
+                (proposal, Some(payload), key_rotation, protocol_upgrade)
             }
         };
 
         // ----------- Prepare our message and send it --------------
 
+        // The proposal starts a new trace, which is continued by the replicas.
+        let trace = TraceContext::new_root(&mut ctx.rng());
+        tracing::info!("proposing block {:?}, trace {trace}", proposal.number);
+
         // Broadcast the leader prepare message to all replicas (ourselves included).
         let msg = cfg
-            .secret_key
-            .sign_msg(validator::ConsensusMsg::LeaderPrepare(
-                validator::LeaderPrepare {
+            .signer
+            .sign_msg(
+                ctx,
+                validator::ConsensusMsg::LeaderPrepare(validator::LeaderPrepare {
                     proposal,
                     proposal_payload: payload,
                     justification,
-                },
-            ));
+                    proposal_key_rotation: key_rotation,
+                    timeout_qc,
+                    proposal_protocol_upgrade: protocol_upgrade,
+                }),
+            )
+            .await
+            .wrap("sign_msg()")?;
         pipe.send(
             ConsensusInputMessage {
                 message: msg,
                 recipient: Target::Broadcast,
+                trace: Some(trace),
             }
             .into(),
         );
         Ok(())
     }
 
+    /// Builds the payload of a new block.
+    /// An empty payload is retried for up to `max_payload_wait`.
+    async fn build_payload(
+        ctx: &ctx::Ctx,
+        cfg: &Config,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<validator::Payload> {
+        let deadline = cfg.max_payload_wait.map(|wait| ctx.now() + wait);
+        loop {
+            let payload = cfg.payload_manager.propose(ctx, number).await?;
+            let Some(deadline) = deadline else {
+                return Ok(payload);
+            };
+            if !payload.0.is_empty() {
+                return Ok(payload);
+            }
+            let now = ctx.now();
+            if now >= deadline {
+                tracing::info!("no payload for block {number:?}, proposing an empty block");
+                metrics::METRICS.leader_empty_proposals.inc();
+                return Ok(payload);
+            }
+            ctx.sleep(PAYLOAD_RETRY_INTERVAL.min(deadline - now))
+                .await?;
+        }
+    }
+
     #[allow(clippy::match_like_matches_macro)]
     fn inbound_pruning_predicate(pending_req: &ConsensusReq, new_req: &ConsensusReq) -> bool {
         if pending_req.msg.key != new_req.msg.key {
@@ -226,6 +496,7 @@ impl StateMachine {
         match (&pending_req.msg.msg, &new_req.msg.msg) {
             (ConsensusMsg::ReplicaPrepare(_), ConsensusMsg::ReplicaPrepare(_)) => true,
             (ConsensusMsg::ReplicaCommit(_), ConsensusMsg::ReplicaCommit(_)) => true,
+            (ConsensusMsg::ReplicaTimeout(_), ConsensusMsg::ReplicaTimeout(_)) => true,
             _ => false,
         }
     }
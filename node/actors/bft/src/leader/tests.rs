@@ -3,7 +3,7 @@ use crate::testonly::ut_harness::UTHarness;
 use assert_matches::assert_matches;
 use pretty_assertions::assert_eq;
 use rand::Rng;
-use zksync_concurrency::{ctx, scope};
+use zksync_concurrency::{ctx, scope, time};
 use zksync_consensus_roles::validator::{self, Phase, ViewNumber};
 
 #[tokio::test]
@@ -98,15 +98,41 @@ async fn replica_prepare_incompatible_protocol_version() {
         let res = util.process_replica_prepare(ctx, util.sign(replica_prepare)).await;
         assert_matches!(
             res,
-            Err(replica_prepare::Error::IncompatibleProtocolVersion { message_version, local_version }) => {
+            Err(replica_prepare::Error::IncompatibleProtocolVersion { message_version, supported_versions }) => {
                 assert_eq!(message_version, incompatible_protocol_version);
-                assert_eq!(local_version, util.protocol_version());
+                assert_eq!(supported_versions, util.protocol_version()..=util.protocol_version());
             }
         );
         Ok(())
     }).await.unwrap();
 }
 
+/// Messages of the versions older than the genesis version are rejected.
+#[tokio::test]
+async fn replica_prepare_protocol_version_older_than_genesis() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) =
+            UTHarness::new_with_protocol_version(ctx, 1, validator::ProtocolVersion::PAYLOAD_ROOT)
+                .await;
+        s.spawn_bg(runner.run(ctx));
+
+        let mut replica_prepare = util.new_replica_prepare();
+        replica_prepare.view.protocol_version = validator::ProtocolVersion::EARLIEST;
+        let res = util
+            .process_replica_prepare(ctx, util.sign(replica_prepare))
+            .await;
+        assert_matches!(
+            res,
+            Err(replica_prepare::Error::IncompatibleProtocolVersion { .. })
+        );
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
 #[tokio::test]
 async fn replica_prepare_non_validator_signer() {
     zksync_concurrency::testonly::abort_on_panic();
@@ -396,9 +422,9 @@ async fn replica_commit_incompatible_protocol_version() {
             .await;
         assert_matches!(
             res,
-            Err(replica_commit::Error::IncompatibleProtocolVersion { message_version, local_version }) => {
+            Err(replica_commit::Error::IncompatibleProtocolVersion { message_version, supported_versions }) => {
                 assert_eq!(message_version, incompatible_protocol_version);
-                assert_eq!(local_version, util.protocol_version());
+                assert_eq!(supported_versions, util.protocol_version()..=util.protocol_version());
             }
         );
         Ok(())
@@ -579,3 +605,217 @@ async fn replica_commit_unexpected_proposal() {
     .await
     .unwrap();
 }
+
+/// Leader should wait for a non-empty payload, up to `max_payload_wait`.
+#[tokio::test]
+async fn propose_waits_for_nonempty_payload() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let payload_manager = crate::testonly::DelayedPayload {
+            empty: 2.into(),
+            size: 1000,
+        };
+        let (mut util, runner) = UTHarness::new_with_max_payload_wait(
+            ctx,
+            1,
+            Box::new(payload_manager),
+            time::Duration::seconds(10),
+        )
+        .await;
+        s.spawn_bg(runner.run(ctx));
+
+        let replica_prepare = util.new_replica_prepare();
+        let leader_prepare = util
+            .process_replica_prepare(ctx, util.sign(replica_prepare))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(1000, leader_prepare.msg.proposal_payload.unwrap().0.len());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+/// Leader should commit to the payload root iff the protocol version requires it.
+#[tokio::test]
+async fn propose_with_payload_root() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    for version in [
+        validator::ProtocolVersion::EARLIEST,
+        validator::ProtocolVersion::PAYLOAD_ROOT,
+    ] {
+        scope::run!(ctx, |ctx, s| async {
+            let (mut util, runner) = UTHarness::new_with_protocol_version(ctx, 1, version).await;
+            s.spawn_bg(runner.run(ctx));
+
+            let replica_prepare = util.new_replica_prepare();
+            let leader_prepare = util
+                .process_replica_prepare(ctx, util.sign(replica_prepare))
+                .await
+                .unwrap()
+                .unwrap();
+            let payload = leader_prepare.msg.proposal_payload.as_ref().unwrap();
+            assert_eq!(
+                version.commits_payload_root().then(|| payload.root()),
+                leader_prepare.msg.proposal.payload_root
+            );
+            leader_prepare.msg.verify(&util.genesis()).unwrap();
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+}
+
+/// Leader should propose an empty block once `max_payload_wait` elapses.
+#[tokio::test]
+async fn propose_empty_payload_after_wait() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new_with_max_payload_wait(
+            ctx,
+            1,
+            Box::new(crate::testonly::RejectPayload),
+            time::Duration::milliseconds(300),
+        )
+        .await;
+        s.spawn_bg(runner.run(ctx));
+
+        let replica_prepare = util.new_replica_prepare();
+        let leader_prepare = util
+            .process_replica_prepare(ctx, util.sign(replica_prepare))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            Some(validator::Payload(vec![])),
+            leader_prepare.msg.proposal_payload
+        );
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn replica_timeout_sanity_yield_leader_timeout() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new_many(ctx).await;
+        s.spawn_bg(runner.run(ctx));
+
+        let replica_timeout = util.new_replica_timeout();
+        let threshold = util.genesis().validators.threshold();
+        for key in &util.keys.clone()[..threshold - 1] {
+            let res = util
+                .process_timeout_vote(ctx, key.sign_msg(replica_timeout.clone()))
+                .await
+                .unwrap();
+            assert!(res.is_none());
+        }
+        let leader_timeout = util
+            .process_timeout_vote(
+                ctx,
+                util.keys[threshold - 1].sign_msg(replica_timeout.clone()),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(leader_timeout.msg.justification.message, replica_timeout);
+        leader_timeout.msg.verify(&util.genesis()).unwrap();
+
+        // Votes past the threshold don't produce another leader timeout.
+        for key in &util.keys.clone()[threshold..] {
+            let res = util
+                .process_timeout_vote(ctx, key.sign_msg(replica_timeout.clone()))
+                .await
+                .unwrap();
+            assert!(res.is_none());
+        }
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn replica_timeout_not_leader_in_next_view() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new_many(ctx).await;
+        s.spawn_bg(runner.run(ctx));
+
+        let mut replica_timeout = util.new_replica_timeout();
+        replica_timeout.view.number = replica_timeout.view.number.next();
+        let res = util
+            .process_timeout_vote(ctx, util.sign(replica_timeout))
+            .await;
+        assert_matches!(res, Err(replica_timeout::Error::NotLeaderInNextView));
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn replica_timeout_already_exists() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new_many(ctx).await;
+        s.spawn_bg(runner.run(ctx));
+
+        let replica_timeout = util.sign(util.new_replica_timeout());
+        assert!(util
+            .process_timeout_vote(ctx, replica_timeout.clone())
+            .await
+            .unwrap()
+            .is_none());
+        let res = util.process_timeout_vote(ctx, replica_timeout).await;
+        assert_matches!(res, Err(replica_timeout::Error::DuplicateMessage));
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn replica_prepare_after_timeout_yields_leader_prepare_with_timeout_qc() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new_many(ctx).await;
+        s.spawn_bg(runner.run(ctx));
+
+        // Aggregate a TimeoutQC for the view preceding a view led by the owner.
+        let replica_timeout = util.new_replica_timeout();
+        let threshold = util.genesis().validators.threshold();
+        for key in &util.keys.clone()[..threshold] {
+            util.process_timeout_vote(ctx, key.sign_msg(replica_timeout.clone()))
+                .await
+                .unwrap();
+        }
+        util.clear_outbound();
+
+        // The proposal for the next view carries the TimeoutQC.
+        util.set_replica_view(replica_timeout.view.number.next());
+        let replica_prepare = util.new_replica_prepare();
+        assert_eq!(
+            replica_prepare.view.number,
+            replica_timeout.view.number.next()
+        );
+        let leader_prepare = util.process_replica_prepare_all(ctx, replica_prepare).await;
+        let timeout_qc = leader_prepare.msg.timeout_qc.as_ref().unwrap();
+        assert_eq!(timeout_qc.message, replica_timeout);
+        leader_prepare.msg.verify(util.genesis()).unwrap();
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
@@ -16,23 +16,62 @@
 //! - Blog posts explaining [safety](https://seafooler.com/2022/01/24/understanding-safety-hotstuff/) and [responsiveness](https://seafooler.com/2022/04/02/understanding-responsiveness-hotstuff/)
 
 use crate::io::{InputMessage, OutputMessage};
-pub use config::Config;
+pub use config::{CheckpointConfig, Config};
 use std::sync::Arc;
-use zksync_concurrency::{ctx, scope};
+pub use view_history::{ViewChangeReason, ViewHistory, ViewTransition, VIEW_HISTORY_SIZE};
+use zksync_concurrency::{ctx, scope, sync::prunable_mpsc::SendResult};
+use zksync_consensus_network::io::ConsensusReq;
 use zksync_consensus_roles::validator::{self, ConsensusMsg};
+use zksync_consensus_storage as storage;
 use zksync_consensus_utils::pipe::ActorPipe;
 
 mod config;
 pub mod io;
 mod leader;
 mod metrics;
+pub mod record;
 mod replica;
 pub mod testonly;
 #[cfg(test)]
 mod tests;
+mod verifier;
+mod view_history;
 
-/// Protocol version of this BFT implementation.
-pub const PROTOCOL_VERSION: validator::ProtocolVersion = validator::ProtocolVersion::EARLIEST;
+/// Latest protocol version supported by this BFT implementation.
+/// Consensus messages of the versions between the genesis version and this one are accepted.
+/// Validators announce support of this version with `validator::ProtocolUpgrade`.
+pub const PROTOCOL_VERSION: validator::ProtocolVersion = validator::ProtocolVersion::BLAKE2_HASHING;
+
+/// Max number of the inbound consensus messages buffered per validator of the genesis,
+/// by the leader and by the replica each. The buffered messages are deduplicated per signer
+/// and message type, so the honest validators never fill the buffers; on overflow (e.g. a flood
+/// of messages signed with unknown keys) the messages for the oldest views are shed first.
+const INBOUND_MSGS_PER_VALIDATOR: usize = 4;
+
+/// Number of views after the view of the last committed block, for which the buffered
+/// inbound messages are ranked by their view. See `inbound_priority`.
+const INBOUND_VIEWS_AHEAD: u64 = 1000;
+
+/// Priority of a buffered inbound message: messages for older views are shed first.
+/// Messages for the views more than `INBOUND_VIEWS_AHEAD` after the last committed block
+/// are shed first as well, so that the far-future views can't outrank the current one.
+fn inbound_priority(
+    block_store: &storage::BlockStore,
+) -> impl 'static + Sync + Send + Fn(&ConsensusReq) -> u64 {
+    let state = block_store.subscribe();
+    move |req| {
+        let committed = state
+            .borrow()
+            .last
+            .as_ref()
+            .map_or(0, |qc| qc.view().number.0);
+        let view = req.msg.msg.view().number.0;
+        match view <= committed.saturating_add(INBOUND_VIEWS_AHEAD) {
+            true => view,
+            false => 0,
+        }
+    }
+}
 
 /// Payload proposal and verification trait.
 #[async_trait::async_trait]
@@ -50,6 +89,12 @@ pub trait PayloadManager: std::fmt::Debug + Send + Sync {
         number: validator::BlockNumber,
         payload: &validator::Payload,
     ) -> ctx::Result<()>;
+    /// Whether `propose` may be called for the blocks proposed by other validators,
+    /// i.e. whether it is free of side effects (like taking transactions out of a mempool).
+    /// The shadow proposer runs only for payload managers which opt in.
+    fn supports_shadow_proposals(&self) -> bool {
+        false
+    }
 }
 
 /// Channel through which bft actor sends network messages.
@@ -61,26 +106,89 @@ impl Config {
     pub async fn run(
         self,
         ctx: &ctx::Ctx,
+        pipe: ActorPipe<InputMessage, OutputMessage>,
+    ) -> anyhow::Result<()> {
+        Arc::new(self).run_shared(ctx, pipe).await
+    }
+
+    /// Same as `run()`, but doesn't consume the config, so that the actor can be restarted
+    /// after a failure. The replica state is reloaded from `replica_store` on every start.
+    pub async fn run_shared(
+        self: Arc<Self>,
+        ctx: &ctx::Ctx,
         mut pipe: ActorPipe<InputMessage, OutputMessage>,
     ) -> anyhow::Result<()> {
-        let cfg = Arc::new(self);
-        let (leader, leader_send) = leader::StateMachine::new(ctx, cfg.clone(), pipe.send.clone());
+        let cfg = self;
+        if cfg.shadow_proposer && !cfg.shadow_proposer_enabled() {
+            tracing::warn!("shadow proposer is not supported by the payload manager, disabling it");
+        }
+        // The node has to be upgraded before the genesis schedules an unsupported version.
+        let latest = cfg.genesis().latest_protocol_version();
+        anyhow::ensure!(
+            latest <= PROTOCOL_VERSION,
+            "genesis schedules protocol version {latest:?}, but only versions up to {PROTOCOL_VERSION:?} are supported"
+        );
+        // With a recorder, the sent messages are recorded on their way to the network.
+        let (recorded_send, mut recorded_recv) = ctx::channel::unbounded();
+        let send = match &cfg.recorder {
+            Some(_) => recorded_send,
+            None => pipe.send.clone(),
+        };
+        let (leader, leader_send) = leader::StateMachine::new(ctx, cfg.clone(), send.clone());
         let (replica, replica_send) =
-            replica::StateMachine::start(ctx, cfg.clone(), pipe.send.clone()).await?;
+            replica::StateMachine::start(ctx, cfg.clone(), send.clone()).await?;
 
         let res = scope::run!(ctx, |ctx, s| async {
             let prepare_qc_recv = leader.prepare_qc.subscribe();
+            let shadow_block_recv = replica.shadow_block.subscribe();
+            let catching_up_recv = replica.catching_up.subscribe();
+            let checkpoint_recv = replica.checkpoint.subscribe();
 
+            if let Some(recorder) = &cfg.recorder {
+                s.spawn_bg(recorder.run(ctx));
+                let pipe_send = &pipe.send;
+                s.spawn_bg(async {
+                    loop {
+                        let msg = recorded_recv.recv(ctx).await?;
+                        let OutputMessage::Network(m) = &msg;
+                        recorder.record(record::Event {
+                            time: cfg.time_source.now_utc(ctx),
+                            direction: record::Direction::Sent,
+                            msg: m.message.clone(),
+                        });
+                        pipe_send.send(msg);
+                    }
+                });
+            }
             s.spawn_bg(replica.run(ctx));
             s.spawn_bg(leader.run(ctx));
-            s.spawn_bg(leader::StateMachine::run_proposer(
-                ctx,
-                &cfg,
-                prepare_qc_recv,
-                &pipe.send,
-            ));
+            // An observer never proposes.
+            if !cfg.observer {
+                s.spawn_bg(leader::StateMachine::run_proposer(
+                    ctx,
+                    &cfg,
+                    prepare_qc_recv,
+                    catching_up_recv,
+                    &send,
+                ));
+            }
+            if cfg.shadow_proposer_enabled() {
+                s.spawn_bg(leader::StateMachine::run_shadow_proposer(
+                    ctx,
+                    &cfg,
+                    shadow_block_recv,
+                ));
+            }
+
+            if let Some(checkpoint) = &cfg.checkpoint {
+                s.spawn_bg(replica::StateMachine::run_checkpointer(
+                    ctx,
+                    checkpoint,
+                    checkpoint_recv,
+                ));
+            }
 
-            tracing::info!("Starting consensus actor {:?}", cfg.secret_key.public());
+            tracing::info!("Starting consensus actor {:?}", cfg.signer.public());
 
             // This is the infinite loop where the consensus actually runs. The validator waits for either
             // a message from the network or for a timeout, and processes each accordingly.
@@ -94,14 +202,37 @@ impl Config {
                 }
 
                 let InputMessage::Network(req) = input.unwrap();
-                match &req.msg.msg {
-                    ConsensusMsg::ReplicaPrepare(_) | ConsensusMsg::ReplicaCommit(_) => {
-                        leader_send.send(req);
+                if let Some(recorder) = &cfg.recorder {
+                    recorder.record(record::Event {
+                        time: cfg.time_source.now_utc(ctx),
+                        direction: record::Direction::Received,
+                        msg: req.msg.clone(),
+                    });
+                }
+                let (send, queue) = match &req.msg.msg {
+                    ConsensusMsg::ReplicaPrepare(_)
+                    | ConsensusMsg::ReplicaCommit(_)
+                    | ConsensusMsg::ReplicaTimeout(_) => {
+                        (&leader_send, metrics::InboundQueueLabel::Leader)
                     }
-                    ConsensusMsg::LeaderPrepare(_) | ConsensusMsg::LeaderCommit(_) => {
-                        replica_send.send(req);
+                    ConsensusMsg::LeaderPrepare(_)
+                    | ConsensusMsg::LeaderCommit(_)
+                    | ConsensusMsg::LeaderTimeout(_) => {
+                        (&replica_send, metrics::InboundQueueLabel::Replica)
                     }
+                };
+                // Messages signed by the keys outside of the validator set are never processed,
+                // so they are rejected before they take the place of the honest ones in the queue.
+                if !cfg.genesis().accepts_validator_key(&req.msg.key) {
+                    metrics::METRICS.inbound_rejected[&queue].inc();
+                    continue;
+                }
+                match send.send(req) {
+                    SendResult::Sent => {}
+                    SendResult::Shed => metrics::METRICS.inbound_shed[&queue].inc(),
+                    SendResult::Dropped => metrics::METRICS.inbound_dropped[&queue].inc(),
                 }
+                metrics::METRICS.inbound_queue_len[&queue].set(send.len());
             }
         })
         .await;
@@ -1,7 +1,10 @@
 //! Metrics for the consensus module.
 
+use crate::ViewChangeReason;
 use std::time::Duration;
-use vise::{Buckets, EncodeLabelSet, EncodeLabelValue, Family, Gauge, Histogram, Metrics, Unit};
+use vise::{
+    Buckets, Counter, EncodeLabelSet, EncodeLabelValue, Family, Gauge, Histogram, Metrics, Unit,
+};
 
 const PAYLOAD_SIZE_BUCKETS: Buckets = Buckets::exponential(
     (4 * zksync_protobuf::kB) as f64..=(4 * zksync_protobuf::MB) as f64,
@@ -20,6 +23,10 @@ pub(crate) enum ConsensusMsgLabel {
     ReplicaPrepare,
     /// Label for a `ReplicaCommit` message.
     ReplicaCommit,
+    /// Label for a `LeaderTimeout` message.
+    LeaderTimeout,
+    /// Label for a `ReplicaTimeout` message.
+    ReplicaTimeout,
 }
 
 impl ConsensusMsgLabel {
@@ -49,6 +56,35 @@ pub(crate) struct ProcessingLatencyLabels {
     result: ResultLabel,
 }
 
+/// Outcome of comparing a shadow proposal against the finalized block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EncodeLabelValue, EncodeLabelSet)]
+#[metrics(label = "result", rename_all = "snake_case")]
+pub(crate) enum ShadowProposalLabel {
+    /// The finalized block has the same payload.
+    Same,
+    /// The finalized block has a different payload.
+    Different,
+}
+
+/// Inbound queue of the consensus messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EncodeLabelValue, EncodeLabelSet)]
+#[metrics(label = "queue", rename_all = "snake_case")]
+pub(crate) enum InboundQueueLabel {
+    /// Messages processed by the leader.
+    Leader,
+    /// Messages processed by the replica.
+    Replica,
+}
+
+/// Labels for the views counted per leader.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, EncodeLabelSet)]
+pub(crate) struct LeaderViewLabels {
+    /// Leader of the view.
+    pub(crate) leader: String,
+    /// Reason for which the view has ended.
+    pub(crate) reason: ViewChangeReason,
+}
+
 /// Metrics defined by the consensus module.
 #[derive(Debug, Metrics)]
 #[metrics(prefix = "consensus")]
@@ -56,9 +92,27 @@ pub(crate) struct ConsensusMetrics {
     /// Size of the proposed payload in bytes.
     #[metrics(buckets = PAYLOAD_SIZE_BUCKETS, unit = Unit::Bytes)]
     pub(crate) leader_proposal_payload_size: Histogram<usize>,
+    /// Size of the payload built by the shadow proposer in bytes.
+    #[metrics(buckets = PAYLOAD_SIZE_BUCKETS, unit = Unit::Bytes)]
+    pub(crate) shadow_proposal_payload_size: Histogram<usize>,
+    /// Time it took the shadow proposer to build a payload.
+    #[metrics(buckets = Buckets::LATENCIES, unit = Unit::Seconds)]
+    pub(crate) shadow_proposal_latency: Histogram<Duration>,
+    /// Shadow proposals compared against the finalized blocks.
+    pub(crate) shadow_proposals: Family<ShadowProposalLabel, Counter>,
+    /// Empty blocks proposed after waiting `max_payload_wait` for a non-empty payload.
+    pub(crate) leader_empty_proposals: Counter,
     /// Latency of the commit phase observed by the leader.
     #[metrics(buckets = Buckets::exponential(0.01..=20.0, 1.5), unit = Unit::Seconds)]
     pub(crate) leader_commit_phase_latency: Histogram<Duration>,
+    /// Time spent by the replica in a view.
+    #[metrics(buckets = Buckets::exponential(0.01..=20.0, 1.5), unit = Unit::Seconds)]
+    pub(crate) replica_view_duration: Histogram<Duration>,
+    /// Views left by the replica, by reason.
+    pub(crate) replica_view_changes: Family<ViewChangeReason, Counter>,
+    /// Views left by the replica, by leader and reason.
+    /// The ratio of the `commit_qc` views to all views of a leader is its proposal success rate.
+    pub(crate) replica_leader_views: Family<LeaderViewLabels, Counter>,
     /// Currently set timeout after which replica will proceed to the next view.
     #[metrics(unit = Unit::Seconds)]
     pub(crate) replica_view_timeout: Gauge<Duration>,
@@ -68,8 +122,33 @@ pub(crate) struct ConsensusMetrics {
     /// Latency of processing messages by the leader.
     #[metrics(buckets = Buckets::LATENCIES, unit = Unit::Seconds)]
     pub(crate) leader_processing_latency: Family<ProcessingLatencyLabels, Histogram<Duration>>,
+    /// Number of the consensus messages verified in a single batch.
+    #[metrics(buckets = Buckets::exponential(1.0..=1024.0, 2.0))]
+    pub(crate) verify_batch_size: Histogram<usize>,
+    /// Latency of verifying the signatures of a batch of consensus messages.
+    #[metrics(buckets = Buckets::LATENCIES, unit = Unit::Seconds)]
+    pub(crate) verify_batch_latency: Histogram<Duration>,
+    /// Number of the batches containing an invalid signature or QC,
+    /// which had to be bisected.
+    pub(crate) verify_batch_bisections: Counter,
+    /// Latency of verifying a single proposal, i.e. its signature and the QCs it carries.
+    #[metrics(buckets = Buckets::LATENCIES, unit = Unit::Seconds)]
+    pub(crate) verify_msg_latency: Histogram<Duration>,
     /// Number of the last finalized block observed by the node.
     pub(crate) finalized_block_number: Gauge<u64>,
+    /// Whether the replica is in the catch-up mode (1) or not (0).
+    pub(crate) replica_catching_up: Gauge<u64>,
+    /// Number of blocks between the stored blocks and the highest QC known to the replica.
+    pub(crate) replica_blocks_behind: Gauge<u64>,
+    /// Number of the inbound messages waiting to be processed, sampled whenever a message arrives.
+    /// Compare against the capacity of the queue to see its saturation.
+    pub(crate) inbound_queue_len: Family<InboundQueueLabel, Gauge<usize>>,
+    /// Inbound messages for older views, shed from the full queue to make room for newer ones.
+    pub(crate) inbound_shed: Family<InboundQueueLabel, Counter>,
+    /// Inbound messages dropped, because the queue was full of messages for the same or newer views.
+    pub(crate) inbound_dropped: Family<InboundQueueLabel, Counter>,
+    /// Inbound messages signed by the keys outside of the validator set, rejected without queueing.
+    pub(crate) inbound_rejected: Family<InboundQueueLabel, Counter>,
 }
 
 /// Global instance of [`ConsensusMetrics`].
@@ -0,0 +1,211 @@
+//! Recording and replaying of the consensus message traces.
+//! A node configured with a `Recorder` (see `Config::recorder`) appends every consensus message
+//! it receives and sends to the trace file. The trace survives restarts of the node: the events
+//! of every run are appended to the events of the previous runs. The received messages can be then fed back
+//! into a fresh bft actor with `replay()`, to reproduce a bug deterministically.
+//!
+//! The trace file is a sequence of frames `L ++ t ++ d ++ msg`, where `L` is a little endian
+//! encoding of the frame length (excluding `L`) as u32, `t` is a little endian encoding of the
+//! timestamp of the event (in microseconds since the unix epoch) as i64, `d` is the direction
+//! byte (0 = received, 1 = sent) and `msg` is a protobuf-encoded
+//! `roles.validator.Signed` consensus message.
+use crate::io::InputMessage;
+use anyhow::Context as _;
+use std::{
+    fmt, fs,
+    io::Write as _,
+    path::{Path, PathBuf},
+};
+use zksync_concurrency::{ctx, oneshot, scope, sync, time};
+use zksync_consensus_network::io::ConsensusReq;
+use zksync_consensus_roles::validator;
+
+/// Length of the frame header following `L`.
+const HEADER_SIZE: usize = 9;
+
+/// Direction of a recorded message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Message received by the node.
+    Received,
+    /// Message sent by the node.
+    Sent,
+}
+
+/// Recorded consensus message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    /// Time at which the message was received or sent.
+    pub time: time::Utc,
+    /// Direction of the message.
+    pub direction: Direction,
+    /// The message.
+    pub msg: validator::Signed<validator::ConsensusMsg>,
+}
+
+impl Event {
+    fn encode(&self) -> Vec<u8> {
+        let msg = zksync_protobuf::encode(&self.msg);
+        let micros = (self.time - time::UNIX_EPOCH).whole_microseconds() as i64;
+        let len = (HEADER_SIZE + msg.len()) as u32;
+        let direction = match self.direction {
+            Direction::Received => 0,
+            Direction::Sent => 1,
+        };
+        [
+            &len.to_le_bytes()[..],
+            &micros.to_le_bytes(),
+            &[direction],
+            &msg,
+        ]
+        .concat()
+    }
+
+    fn decode(frame: &[u8]) -> anyhow::Result<Self> {
+        anyhow::ensure!(frame.len() >= HEADER_SIZE, "frame too short");
+        let micros = i64::from_le_bytes(frame[..8].try_into().unwrap());
+        Ok(Self {
+            time: time::UNIX_EPOCH + time::Duration::microseconds(micros),
+            direction: match frame[8] {
+                0 => Direction::Received,
+                1 => Direction::Sent,
+                d => anyhow::bail!("unknown direction {d}"),
+            },
+            msg: zksync_protobuf::decode(&frame[HEADER_SIZE..]).context("msg")?,
+        })
+    }
+}
+
+/// Appends the consensus messages of a node to a trace file.
+/// Meant for debugging: the events are queued without blocking the consensus
+/// and written by `Recorder::run()` on a blocking thread, without fsync.
+pub struct Recorder {
+    path: PathBuf,
+    send: ctx::channel::UnboundedSender<Event>,
+    writer: sync::Mutex<Writer>,
+}
+
+/// State owned by the task writing the trace file.
+struct Writer {
+    file: fs::File,
+    recv: ctx::channel::UnboundedReceiver<Event>,
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Recorder {
+    /// Opens the trace file, creating it if it doesn't exist. The events are appended
+    /// to the ones recorded by the previous runs, so that the trace of a crashed run is kept.
+    /// A truncated frame at the end of the file (a node crashed while recording it) is cut off.
+    pub fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("open({path:?})"))?;
+        let data = fs::read(&path).with_context(|| format!("read({path:?})"))?;
+        let (_, valid) = split_frames(&data);
+        if valid < data.len() {
+            file.set_len(valid as u64).context("set_len()")?;
+        }
+        let (send, recv) = ctx::channel::unbounded();
+        Ok(Self {
+            path,
+            send,
+            writer: sync::Mutex::new(Writer { file, recv }),
+        })
+    }
+
+    /// Queues an event to be appended to the trace.
+    pub(crate) fn record(&self, event: Event) {
+        self.send.send(event);
+    }
+
+    /// Writes the queued events to the trace file, until the context is canceled.
+    /// The events queued before the cancellation are still written.
+    /// Failures are logged rather than returned, so that they never stop the consensus.
+    /// Only one `run()` is expected at a time: the bft actor runs it for its lifetime.
+    pub(crate) async fn run(&self, ctx: &ctx::Ctx) -> ctx::Result<()> {
+        let writer = &mut *self.writer.lock().await;
+        loop {
+            let res = writer.recv.recv(ctx).await;
+            let mut buf = match &res {
+                Ok(event) => event.encode(),
+                Err(_) => vec![],
+            };
+            while let Some(event) = writer.recv.try_recv() {
+                buf.extend(event.encode());
+            }
+            let file = &mut writer.file;
+            if let Err(err) = scope::wait_blocking(|| file.write_all(&buf)).await {
+                tracing::warn!(
+                    "failed to record consensus messages to {:?}: {err}",
+                    self.path
+                );
+            }
+            res?;
+        }
+    }
+}
+
+/// Splits the trace into frames. Returns the frames and the length of the prefix they span:
+/// a truncated frame at the end (a node crashed while recording it) is not included.
+fn split_frames(data: &[u8]) -> (Vec<&[u8]>, usize) {
+    let mut frames = vec![];
+    let mut valid = 0;
+    while let Some(len) = data.get(valid..valid + 4) {
+        let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        let Some(frame) = data.get(valid + 4..valid + 4 + len) else {
+            break;
+        };
+        frames.push(frame);
+        valid += 4 + len;
+    }
+    (frames, valid)
+}
+
+/// Reads the events recorded to the trace file.
+/// A truncated frame at the end of the file (a node crashed while recording it) is ignored.
+pub fn read(path: &Path) -> anyhow::Result<Vec<Event>> {
+    let data = fs::read(path).with_context(|| format!("read({path:?})"))?;
+    let (frames, _) = split_frames(&data);
+    let mut events = vec![];
+    for frame in frames {
+        let event = Event::decode(frame).with_context(|| format!("event {}", events.len()))?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// Feeds the messages received in the `trace` into a bft actor via its input `pipe`,
+/// keeping the original intervals between them. The messages sent in the trace are skipped:
+/// the actor is expected to send them again.
+pub async fn replay(
+    ctx: &ctx::Ctx,
+    trace: &[Event],
+    pipe: &ctx::channel::UnboundedSender<InputMessage>,
+) -> ctx::OrCanceled<()> {
+    let Some(first) = trace.first() else {
+        return Ok(());
+    };
+    let start = ctx.now();
+    for event in trace {
+        if event.direction != Direction::Received {
+            continue;
+        }
+        ctx.sleep_until(start + (event.time - first.time)).await?;
+        pipe.send(InputMessage::Network(ConsensusReq {
+            msg: event.msg.clone(),
+            trace: None,
+            ack: oneshot::channel().0,
+        }));
+    }
+    Ok(())
+}
@@ -2,6 +2,7 @@ use super::StateMachine;
 use tracing::instrument;
 use zksync_concurrency::ctx;
 use zksync_consensus_roles::validator;
+use zksync_consensus_storage as storage;
 
 impl StateMachine {
     /// Tries to build a finalized block from the given CommitQC. We simply search our
@@ -31,19 +32,54 @@ impl StateMachine {
         let Some(payload) = cache.get(&commit_qc.header().payload) else {
             return Ok(());
         };
+        let key_rotation = match &commit_qc.header().key_rotation {
+            Some(hash) => {
+                let Some(cert) = self
+                    .key_rotation_cache
+                    .get(&commit_qc.header().number)
+                    .and_then(|cache| cache.get(hash))
+                else {
+                    return Ok(());
+                };
+                Some(cert.clone())
+            }
+            None => None,
+        };
+        let protocol_upgrade = match &commit_qc.header().protocol_upgrade {
+            Some(hash) => {
+                let Some(qc) = self
+                    .protocol_upgrade_cache
+                    .get(&commit_qc.header().number)
+                    .and_then(|cache| cache.get(hash))
+                else {
+                    return Ok(());
+                };
+                Some(qc.clone())
+            }
+            None => None,
+        };
         let block = validator::FinalBlock {
             payload: payload.clone(),
             justification: commit_qc.clone(),
+            key_rotation,
+            protocol_upgrade,
         };
 
         tracing::info!(
             "Finalized block {}: {:#?}",
             block.header().number,
-            block.header().hash(),
+            self.config.genesis().header_hash(block.header()),
         );
+        let timings = storage::BlockTimings {
+            proposed: self
+                .block_proposal_times
+                .get(&block.header().number)
+                .copied(),
+            certified: Some(ctx.now()),
+        };
         self.config
             .block_store
-            .queue_block(ctx, block.clone())
+            .queue_block_with_timings(ctx, block.clone(), timings)
             .await?;
         // For availability, replica should not proceed until it stores the block persistently.
         self.config
@@ -0,0 +1,64 @@
+//! Catch-up mode of the replica.
+//! A validator which rejoins the consensus far behind the other validators cannot verify
+//! the proposals until it syncs the preceding blocks, so it would only stall the views
+//! in which it is expected to vote or propose. Instead, while catching up, the replica
+//! neither votes nor proposes; it only follows the highest QC until the missing
+//! blocks are fetched by the block syncing. The views still time out as usual, so that
+//! the replica doesn't lag behind the views of the other replicas once it catches up.
+use super::StateMachine;
+use crate::{metrics, ViewChangeReason};
+use zksync_concurrency::{ctx, error::Wrap as _, time};
+
+impl StateMachine {
+    /// How often the replica checks whether it has caught up.
+    pub(crate) const CATCH_UP_POLL_INTERVAL: time::Duration = time::Duration::milliseconds(500);
+
+    /// Whether the replica is in the catch-up mode.
+    pub(crate) fn is_catching_up(&self) -> bool {
+        *self.catching_up.borrow()
+    }
+
+    /// Deadline of the next check whether the replica has caught up:
+    /// after the poll interval, or at the end of the view if it comes sooner.
+    pub(crate) fn catch_up_deadline(&self, ctx: &ctx::Ctx) -> time::Deadline {
+        time::Deadline::Finite(ctx.now() + Self::CATCH_UP_POLL_INTERVAL).min(self.view_deadline)
+    }
+
+    /// Number of blocks between the local block store and the highest QC known to the replica.
+    fn blocks_behind(&self) -> u64 {
+        let Some(qc) = &self.high_qc else {
+            return 0;
+        };
+        let next = self.config.block_store.subscribe().borrow().next();
+        qc.header().number.next().0.saturating_sub(next.0)
+    }
+
+    /// Enters the catch-up mode if the replica is more than `catch_up_threshold` blocks behind
+    /// the highest QC, and leaves it once all the blocks up to the highest QC are stored.
+    /// After leaving the catch-up mode, the replica starts a new view with a fresh timer.
+    pub(crate) async fn check_catch_up(&mut self, ctx: &ctx::Ctx) -> ctx::Result<()> {
+        let Some(threshold) = self.config.catch_up_threshold else {
+            return Ok(());
+        };
+        let behind = self.blocks_behind();
+        metrics::METRICS.replica_blocks_behind.set(behind);
+        if !self.is_catching_up() {
+            if behind > threshold {
+                tracing::info!("{behind} blocks behind the highest QC, entering the catch-up mode");
+                self.catching_up.send_replace(true);
+                metrics::METRICS.replica_catching_up.set(1);
+                self.timeout_deadline = self.catch_up_deadline(ctx);
+            }
+            return Ok(());
+        }
+        if behind > 0 {
+            self.timeout_deadline = self.catch_up_deadline(ctx);
+            return Ok(());
+        }
+        tracing::info!("caught up with the highest QC, leaving the catch-up mode");
+        self.catching_up.send_replace(false);
+        metrics::METRICS.replica_catching_up.set(0);
+        self.end_view(ctx, ViewChangeReason::CatchUp);
+        self.start_new_view(ctx).await.wrap("start_new_view()")
+    }
+}
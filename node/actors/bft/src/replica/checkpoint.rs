@@ -0,0 +1,87 @@
+//! Checkpointing of the replica state.
+//! The replica state is periodically checkpointed to a separate storage, so that a replica
+//! started with a rolled back state (e.g. a database restored from a backup) refuses to run,
+//! rather than voting again in the views in which it has already voted.
+use super::StateMachine;
+use crate::{CheckpointConfig, Config};
+use zksync_concurrency::{ctx, error::Wrap as _, sync};
+use zksync_consensus_roles::validator;
+use zksync_consensus_storage as storage;
+
+impl StateMachine {
+    /// Compares the replica state loaded at startup against the checkpoint of the current epoch.
+    /// Fails if the state is older than the checkpoint, unless `allow_stale_state` is set,
+    /// in which case the state is fast-forwarded to the checkpoint.
+    pub(crate) async fn reconcile_checkpoint(
+        ctx: &ctx::Ctx,
+        config: &Config,
+        mut state: storage::ReplicaState,
+    ) -> ctx::Result<storage::ReplicaState> {
+        let Some(cfg) = &config.checkpoint else {
+            return Ok(state);
+        };
+        let epoch = config.genesis().fork.number;
+        let Some(checkpoint) = cfg
+            .store
+            .checkpoint(ctx, epoch)
+            .await
+            .wrap("checkpoint()")?
+        else {
+            return Ok(state);
+        };
+        if !checkpoint.is_ahead_of(&state) {
+            return Ok(state);
+        }
+        if !cfg.allow_stale_state {
+            return Err(anyhow::format_err!(
+                "replica state (view {:?}) is older than the checkpoint of epoch {epoch:?} \
+                 (view {:?}), running with it could make the validator sign conflicting messages; \
+                 restore the latest replica state or set allow_stale_state to override",
+                state.view,
+                checkpoint.view,
+            )
+            .into());
+        }
+        tracing::warn!(
+            "replica state (view {:?}) is older than the checkpoint of epoch {epoch:?} \
+             (view {:?}), fast-forwarding to the checkpoint",
+            state.view,
+            checkpoint.view,
+        );
+        // Skip the views in which the replica has voted, as far as the checkpoint knows.
+        if checkpoint.view > state.view {
+            state.view = checkpoint.view;
+            state.phase = validator::Phase::Prepare;
+        }
+        let qc_view = |qc: Option<&validator::CommitQC>| qc.map(|qc| qc.view().number);
+        if qc_view(checkpoint.high_qc.as_ref()) > qc_view(state.high_qc.as_ref()) {
+            state.high_qc = checkpoint.high_qc;
+        }
+        Ok(state)
+    }
+
+    /// In a loop, stores the latest persisted replica state as a checkpoint,
+    /// at most once per `cfg.interval`. Storage errors are only logged,
+    /// so that they don't affect the consensus.
+    pub(crate) async fn run_checkpointer(
+        ctx: &ctx::Ctx,
+        cfg: &CheckpointConfig,
+        mut checkpoint: sync::watch::Receiver<Option<storage::ReplicaCheckpoint>>,
+    ) -> ctx::Result<()> {
+        // The replica might have persisted its state before this task started.
+        checkpoint.mark_changed();
+        loop {
+            let Some(checkpoint) = sync::changed(ctx, &mut checkpoint).await?.clone() else {
+                continue;
+            };
+            match cfg.store.set_checkpoint(ctx, &checkpoint).await {
+                Ok(()) => {}
+                Err(ctx::Error::Canceled(err)) => return Err(err.into()),
+                Err(ctx::Error::Internal(err)) => {
+                    tracing::warn!("set_checkpoint(): {err:#}");
+                }
+            }
+            ctx.sleep(cfg.interval).await?;
+        }
+    }
+}
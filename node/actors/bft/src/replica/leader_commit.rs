@@ -1,5 +1,7 @@
 //! Handler of a LeaderCommit message.
 use super::StateMachine;
+use crate::ViewChangeReason;
+use std::ops::RangeInclusive;
 use tracing::instrument;
 use zksync_concurrency::{ctx, error::Wrap};
 use zksync_consensus_roles::validator::{self, ProtocolVersion};
@@ -8,12 +10,12 @@ use zksync_consensus_roles::validator::{self, ProtocolVersion};
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum Error {
     /// Incompatible protocol version.
-    #[error("incompatible protocol version (message version: {message_version:?}, local version: {local_version:?}")]
+    #[error("incompatible protocol version (message version: {message_version:?}, supported versions: {supported_versions:?})")]
     IncompatibleProtocolVersion {
         /// Message version.
         message_version: ProtocolVersion,
-        /// Local version.
-        local_version: ProtocolVersion,
+        /// Versions accepted for this message.
+        supported_versions: RangeInclusive<ProtocolVersion>,
     },
     /// Invalid leader.
     #[error("bad leader: got {got:?}, want {want:?}")]
@@ -69,23 +71,23 @@ impl StateMachine {
         let message = &signed_message.msg;
         let author = &signed_message.key;
 
-        // Check protocol version compatibility.
-        if !crate::PROTOCOL_VERSION.compatible(&message.view().protocol_version) {
-            return Err(Error::IncompatibleProtocolVersion {
+        // Check protocol version compatibility. The commit has to carry the version
+        // which is active at the height of the committed block.
+        self.config
+            .check_protocol_version_at(
+                message.view().protocol_version,
+                message.justification.header().number,
+            )
+            .map_err(|supported_versions| Error::IncompatibleProtocolVersion {
                 message_version: message.view().protocol_version,
-                local_version: crate::PROTOCOL_VERSION,
-            });
-        }
+                supported_versions,
+            })?;
 
         // Check that it comes from the correct leader.
-        let leader = self
-            .config
-            .genesis()
-            .validators
-            .view_leader(message.view().number);
-        if author != &leader {
+        let view = message.view().number;
+        if !self.config.genesis().is_view_leader(author, view) {
             return Err(Error::BadLeader {
-                want: leader,
+                want: self.config.genesis().view_leader(view),
                 got: author.clone(),
             });
         }
@@ -100,11 +102,15 @@ impl StateMachine {
 
         // ----------- Checking the signed part of the message --------------
 
-        // Check the signature on the message.
-        signed_message.verify().map_err(Error::InvalidSignature)?;
-        message
-            .verify(self.config.genesis())
-            .map_err(Error::InvalidMessage)?;
+        // Check the signature and the QCs of the message, in parallel.
+        let genesis = self.config.genesis();
+        let (sig, msg) = self
+            .verifier
+            .verify_with(ctx, &signed_message, || message.verify(&genesis))
+            .await
+            .map_err(ctx::Error::Canceled)?;
+        sig.map_err(Error::InvalidSignature)?;
+        msg.map_err(Error::InvalidMessage)?;
 
         // ----------- All checks finished. Now we process the message. --------------
 
@@ -115,6 +121,7 @@ impl StateMachine {
 
         // Start a new view. But first we skip to the view of this message.
         self.view = message.view().number;
+        self.end_view(ctx, ViewChangeReason::CommitQc);
         self.start_new_view(ctx).await.wrap("start_new_view()")?;
 
         Ok(())
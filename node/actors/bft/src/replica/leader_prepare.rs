@@ -1,7 +1,8 @@
 //! Handler of a LeaderPrepare message.
 use super::StateMachine;
+use std::ops::RangeInclusive;
 use tracing::instrument;
-use zksync_concurrency::{ctx, error::Wrap};
+use zksync_concurrency::{ctx, error::Wrap, scope};
 use zksync_consensus_network::io::{ConsensusInputMessage, Target};
 use zksync_consensus_roles::validator::{self, ProtocolVersion};
 
@@ -9,12 +10,12 @@ use zksync_consensus_roles::validator::{self, ProtocolVersion};
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum Error {
     /// Incompatible protocol version.
-    #[error("incompatible protocol version (message version: {message_version:?}, local version: {local_version:?}")]
+    #[error("incompatible protocol version (message version: {message_version:?}, supported versions: {supported_versions:?})")]
     IncompatibleProtocolVersion {
         /// Message version.
         message_version: ProtocolVersion,
-        /// Local version.
-        local_version: ProtocolVersion,
+        /// Versions accepted for this message.
+        supported_versions: RangeInclusive<ProtocolVersion>,
     },
     /// Invalid leader.
     #[error(
@@ -53,6 +54,12 @@ pub(crate) enum Error {
     /// Invalid payload.
     #[error("invalid payload: {0:#}")]
     ProposalInvalidPayload(#[source] anyhow::Error),
+    /// Key rotation which cannot be committed, given the rotations committed by the preceding blocks.
+    #[error("invalid key rotation: {0:#}")]
+    ProposalInvalidKeyRotation(#[source] anyhow::Error),
+    /// Protocol upgrade which cannot be committed, given the upgrades committed by the preceding blocks.
+    #[error("invalid protocol upgrade: {0:#}")]
+    ProposalInvalidProtocolUpgrade(#[source] anyhow::Error),
     /// Internal error. Unlike other error types, this one isn't supposed to be easily recoverable.
     #[error(transparent)]
     Internal(#[from] ctx::Error),
@@ -85,19 +92,19 @@ impl StateMachine {
         let author = &signed_message.key;
         let view = message.view().number;
 
-        // Check protocol version compatibility.
-        if !crate::PROTOCOL_VERSION.compatible(&message.view().protocol_version) {
-            return Err(Error::IncompatibleProtocolVersion {
+        // Check protocol version compatibility. The proposal has to carry the version
+        // which is active at its height.
+        self.config
+            .check_protocol_version_at(message.view().protocol_version, message.proposal.number)
+            .map_err(|supported_versions| Error::IncompatibleProtocolVersion {
                 message_version: message.view().protocol_version,
-                local_version: crate::PROTOCOL_VERSION,
-            });
-        }
+                supported_versions,
+            })?;
 
         // Check that it comes from the correct leader.
-        let leader = self.config.genesis().validators.view_leader(view);
-        if author != &leader {
+        if !self.config.genesis().is_view_leader(author, view) {
             return Err(Error::InvalidLeader {
-                correct_leader: leader,
+                correct_leader: self.config.genesis().view_leader(view),
                 received_leader: author.clone(),
             });
         }
@@ -112,20 +119,45 @@ impl StateMachine {
 
         // ----------- Checking the message --------------
 
-        signed_message.verify().map_err(Error::InvalidSignature)?;
-        message
-            .verify(self.config.genesis())
-            .map_err(Error::InvalidMessage)?;
+        // The signature and the QCs are verified in parallel.
+        let genesis = self.config.genesis();
+        let (sig, msg) = self
+            .verifier
+            .verify_with(ctx, &signed_message, || message.verify(&genesis))
+            .await
+            .map_err(ctx::Error::Canceled)?;
+        sig.map_err(Error::InvalidSignature)?;
+        msg.map_err(Error::InvalidMessage)?;
         let high_qc = message.justification.high_qc();
 
         // Check that the payload doesn't exceed the maximum size.
         if let Some(payload) = &message.proposal_payload {
-            if payload.0.len() > self.config.max_payload_size {
+            if payload.0.len() > self.config.payload_size_limit() {
                 return Err(Error::ProposalOversizedPayload {
                     payload_size: payload.0.len(),
                 });
             }
+        }
+
+        // A replica which is catching up cannot verify the proposal, so it doesn't vote.
+        // It only learns the highest QC and caches the proposal, which is certified
+        // by the payload hash in the CommitQC, in case it catches up before the block is finalized.
+        if self.is_catching_up() {
+            if let Some(high_qc) = high_qc {
+                self.save_block(ctx, high_qc).await.wrap("save_block()")?;
+            }
+            if let Some(payload) = &message.proposal_payload {
+                self.block_proposal_cache
+                    .entry(message.proposal.number)
+                    .or_default()
+                    .insert(payload.hash(), payload.clone());
+            }
+            self.cache_key_rotation(message);
+            self.cache_protocol_upgrade(message);
+            return Ok(());
+        }
 
+        if let Some(payload) = &message.proposal_payload {
             if let Some(prev) = message.proposal.number.prev() {
                 // Defensively assume that PayloadManager cannot verify proposal until the previous block is stored.
                 self.config
@@ -134,18 +166,45 @@ impl StateMachine {
                     .await
                     .map_err(ctx::Error::Canceled)?;
             }
-            if let Err(err) = self
-                .config
-                .payload_manager
-                .verify(ctx, message.proposal.number, payload)
-                .await
-            {
+            // Verify the messages waiting in the inbound pipe while the payload is being verified,
+            // so that they are processed without verifying their signatures afterwards.
+            let pending: Vec<_> = self
+                .inbound_pipe
+                .inspect(|reqs| reqs.iter().map(|req| req.msg.clone()).collect());
+            self.verifier.clear();
+            let genesis = self.config.genesis();
+            let (verifier, config) = (&self.verifier, &self.config);
+            let res: ctx::OrCanceled<ctx::Result<()>> = scope::run!(ctx, |ctx, s| async {
+                s.spawn(async {
+                    let pending: Vec<_> = pending.iter().collect();
+                    verifier.prefetch(ctx, &genesis, &pending).await
+                });
+                Ok(config
+                    .payload_manager
+                    .verify(ctx, message.proposal.number, payload)
+                    .await)
+            })
+            .await;
+            if let Err(err) = res.map_err(ctx::Error::Canceled).and_then(|res| res) {
                 return Err(match err {
                     err @ ctx::Error::Canceled(_) => Error::Internal(err),
                     ctx::Error::Internal(err) => Error::ProposalInvalidPayload(err),
                 });
             }
         }
+        // The genesis includes the rotations and the upgrades committed by the preceding blocks by now.
+        if let Some(cert) = &message.proposal_key_rotation {
+            self.config
+                .genesis()
+                .check_key_rotation(cert)
+                .map_err(Error::ProposalInvalidKeyRotation)?;
+        }
+        if let Some(qc) = &message.proposal_protocol_upgrade {
+            self.config
+                .genesis()
+                .check_protocol_upgrade(qc, message.proposal.number)
+                .map_err(Error::ProposalInvalidProtocolUpgrade)?;
+        }
 
         // ----------- All checks finished. Now we process the message. --------------
 
@@ -170,21 +229,55 @@ impl StateMachine {
                 .entry(message.proposal.number)
                 .or_default()
                 .insert(payload.hash(), payload.clone());
+            self.block_proposal_times
+                .entry(message.proposal.number)
+                .or_insert_with(|| ctx.now());
         }
+        self.cache_key_rotation(message);
+        self.cache_protocol_upgrade(message);
 
         // Backup our state.
         self.backup_state(ctx).await.wrap("backup_state()")?;
 
+        // An observer verifies the proposal, but doesn't vote for it.
+        if self.config.observer {
+            return Ok(());
+        }
+
         // Send the replica message to the leader.
         let output_message = ConsensusInputMessage {
             message: self
                 .config
-                .secret_key
-                .sign_msg(validator::ConsensusMsg::ReplicaCommit(commit_vote)),
+                .signer
+                .sign_msg(ctx, validator::ConsensusMsg::ReplicaCommit(commit_vote))
+                .await
+                .wrap("sign_msg()")?,
             recipient: Target::Validator(author.clone()),
+            trace: self.trace,
         };
         self.outbound_pipe.send(output_message.into());
 
         Ok(())
     }
+
+    /// Caches the key rotation committed by the proposed block,
+    /// so that the finalized block can be built once the CommitQC is received.
+    fn cache_key_rotation(&mut self, message: &validator::LeaderPrepare) {
+        if let Some(cert) = &message.proposal_key_rotation {
+            self.key_rotation_cache
+                .entry(message.proposal.number)
+                .or_default()
+                .insert(cert.hash(), cert.clone());
+        }
+    }
+
+    /// Caches the protocol upgrade committed by the proposed block, like `cache_key_rotation`.
+    fn cache_protocol_upgrade(&mut self, message: &validator::LeaderPrepare) {
+        if let Some(qc) = &message.proposal_protocol_upgrade {
+            self.protocol_upgrade_cache
+                .entry(message.proposal.number)
+                .or_default()
+                .insert(qc.hash(), qc.clone());
+        }
+    }
 }
@@ -0,0 +1,111 @@
+//! Handler of a LeaderTimeout message.
+use super::StateMachine;
+use crate::ViewChangeReason;
+use std::ops::RangeInclusive;
+use tracing::instrument;
+use zksync_concurrency::{ctx, error::Wrap};
+use zksync_consensus_roles::validator::{self, ProtocolVersion};
+
+/// Errors that can occur when processing a "leader timeout" message.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    /// Incompatible protocol version.
+    #[error("incompatible protocol version (message version: {message_version:?}, supported versions: {supported_versions:?})")]
+    IncompatibleProtocolVersion {
+        /// Message version.
+        message_version: ProtocolVersion,
+        /// Versions accepted for this message.
+        supported_versions: RangeInclusive<ProtocolVersion>,
+    },
+    /// Invalid leader.
+    #[error("bad leader: got {got:?}, want {want:?}")]
+    BadLeader {
+        /// Received leader.
+        got: validator::PublicKey,
+        /// Correct leader.
+        want: validator::PublicKey,
+    },
+    /// Invalid message signature.
+    #[error("invalid signature: {0:#}")]
+    InvalidSignature(validator::Error),
+    /// Invalid message.
+    #[error("invalid message: {0:#}")]
+    InvalidMessage(validator::TimeoutQCVerifyError),
+    /// Internal error. Unlike other error types, this one isn't supposed to be easily recoverable.
+    #[error(transparent)]
+    Internal(#[from] ctx::Error),
+}
+
+impl Wrap for Error {
+    fn with_wrap<C: std::fmt::Display + Send + Sync + 'static, F: FnOnce() -> C>(
+        self,
+        f: F,
+    ) -> Self {
+        match self {
+            Error::Internal(err) => Error::Internal(err.with_wrap(f)),
+            err => err,
+        }
+    }
+}
+
+impl StateMachine {
+    /// Processes a leader timeout message. The TimeoutQC proves that the view has timed out
+    /// for a quorum of replicas, so the replica moves on to the next view without waiting
+    /// for its own timeout. It is a no-op if the replica has already left the view.
+    #[instrument(level = "trace", err)]
+    pub(crate) async fn process_leader_timeout(
+        &mut self,
+        ctx: &ctx::Ctx,
+        signed_message: validator::Signed<validator::LeaderTimeout>,
+    ) -> Result<(), Error> {
+        // ----------- Checking origin of the message --------------
+
+        // Unwrap message.
+        let message = &signed_message.msg;
+        let author = &signed_message.key;
+
+        // Check protocol version compatibility. A timeout is not about a specific block,
+        // so it is checked against the whole supported range.
+        self.config
+            .check_protocol_version(message.view().protocol_version)
+            .map_err(|supported_versions| Error::IncompatibleProtocolVersion {
+                message_version: message.view().protocol_version,
+                supported_versions,
+            })?;
+
+        // Check that it comes from the leader of the next view.
+        let next = message.view().number.next();
+        if !self.config.genesis().is_view_leader(author, next) {
+            return Err(Error::BadLeader {
+                want: self.config.genesis().view_leader(next),
+                got: author.clone(),
+            });
+        }
+
+        // Most replicas have left the view on their own timeout already.
+        if message.view().number < self.view {
+            return Ok(());
+        }
+
+        // ----------- Checking the signed part of the message --------------
+
+        // Check the signature and the QCs of the message, in parallel.
+        let genesis = self.config.genesis();
+        let (sig, msg) = self
+            .verifier
+            .verify_with(ctx, &signed_message, || message.verify(&genesis))
+            .await
+            .map_err(ctx::Error::Canceled)?;
+        sig.map_err(Error::InvalidSignature)?;
+        msg.map_err(Error::InvalidMessage)?;
+
+        // ----------- All checks finished. Now we process the message. --------------
+
+        // Start a new view. But first we skip to the view of this message.
+        self.view = message.view().number;
+        self.end_view(ctx, ViewChangeReason::TimeoutQc);
+        self.start_new_view(ctx).await.wrap("start_new_view()")?;
+
+        Ok(())
+    }
+}
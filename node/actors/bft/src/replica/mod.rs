@@ -3,8 +3,11 @@
 //! node will perform both the replica and leader roles simultaneously.
 
 mod block;
+mod catch_up;
+mod checkpoint;
 pub(crate) mod leader_commit;
 pub(crate) mod leader_prepare;
+pub(crate) mod leader_timeout;
 mod new_view;
 mod state_machine;
 #[cfg(test)]
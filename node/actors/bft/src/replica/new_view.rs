@@ -1,10 +1,36 @@
 use super::StateMachine;
+use crate::{metrics, view_history, ViewChangeReason};
 use tracing::instrument;
-use zksync_concurrency::{ctx, error::Wrap as _};
+use zksync_concurrency::{ctx, error::Wrap as _, metrics::LatencyHistogramExt as _};
+use zksync_consensus_crypto::TextFmt as _;
 use zksync_consensus_network::io::{ConsensusInputMessage, Target};
 use zksync_consensus_roles::validator;
 
 impl StateMachine {
+    /// Records the end of the current view, before the replica moves on to the next one.
+    pub(crate) fn end_view(&self, ctx: &ctx::Ctx, reason: ViewChangeReason) {
+        let duration = ctx.now() - self.view_start;
+        let leader = self.config.genesis().view_leader(self.view);
+        metrics::METRICS
+            .replica_view_duration
+            .observe_latency(duration);
+        metrics::METRICS.replica_view_changes[&reason].inc();
+        metrics::METRICS.replica_leader_views[&metrics::LeaderViewLabels {
+            leader: leader.encode(),
+            reason,
+        }]
+            .inc();
+        self.config
+            .view_history
+            .record(view_history::ViewTransition {
+                view: self.view,
+                leader,
+                reason,
+                ended: self.config.time_source.now_utc(ctx),
+                duration,
+            });
+    }
+
     /// This blocking method is used whenever we start a new view.
     #[instrument(level = "trace", err)]
     pub(crate) async fn start_new_view(&mut self, ctx: &ctx::Ctx) -> ctx::Result<()> {
@@ -12,35 +38,59 @@ impl StateMachine {
 
         // Update the state machine.
         self.view = self.view.next();
+        self.view_start = ctx.now();
         self.phase = validator::Phase::Prepare;
         if let Some(qc) = self.high_qc.as_ref() {
             // Clear the block cache.
             self.block_proposal_cache
                 .retain(|k, _| k > &qc.header().number);
+            self.block_proposal_times
+                .retain(|k, _| k > &qc.header().number);
+            self.key_rotation_cache
+                .retain(|k, _| k > &qc.header().number);
+            self.protocol_upgrade_cache
+                .retain(|k, _| k > &qc.header().number);
         }
 
         // Backup our state.
         self.backup_state(ctx).await.wrap("backup_state()")?;
 
-        // Send the replica message to the next leader.
-        let output_message = ConsensusInputMessage {
-            message: self
-                .config
-                .secret_key
-                .sign_msg(validator::ConsensusMsg::ReplicaPrepare(
-                    validator::ReplicaPrepare {
-                        view: validator::View {
-                            protocol_version: crate::PROTOCOL_VERSION,
-                            fork: self.config.genesis().fork.number,
-                            number: self.view,
-                        },
-                        high_vote: self.high_vote.clone(),
-                        high_qc: self.high_qc.clone(),
-                    },
-                )),
-            recipient: Target::Validator(self.config.genesis().validators.view_leader(self.view)),
-        };
-        self.outbound_pipe.send(output_message.into());
+        // A replica which is catching up doesn't participate in the view,
+        // it only waits for the view to time out.
+        if self.is_catching_up() {
+            self.reset_timer(ctx);
+            self.timeout_deadline = self.catch_up_deadline(ctx);
+            return Ok(());
+        }
+
+        // Send the replica message to the next leader. An observer never signs it.
+        let leader = self.config.genesis().view_leader(self.view);
+        if !self.config.observer {
+            let output_message = ConsensusInputMessage {
+                message: self
+                    .config
+                    .signer
+                    .sign_msg(
+                        ctx,
+                        validator::ConsensusMsg::ReplicaPrepare(validator::ReplicaPrepare {
+                            view: self.current_view(),
+                            high_vote: self.high_vote.clone(),
+                            high_qc: self.high_qc.clone(),
+                        }),
+                    )
+                    .await
+                    .wrap("sign_msg()")?,
+                recipient: Target::Validator(leader.clone()),
+                trace: None,
+            };
+            self.outbound_pipe.send(output_message.into());
+        }
+
+        // Let the shadow proposer build the block that another leader is expected to propose.
+        if self.config.shadow_proposer_enabled() && leader != self.config.signer.public() {
+            self.shadow_block
+                .send_replace(Some(self.next_block_number()));
+        }
 
         // Reset the timer.
         self.reset_timer(ctx);
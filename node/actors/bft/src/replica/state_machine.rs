@@ -1,10 +1,11 @@
-use crate::{metrics, Config, OutputSender};
+use crate::{inbound_priority, metrics, verifier::Verifier, Config, OutputSender};
 use std::{
     collections::{BTreeMap, HashMap},
     sync::Arc,
 };
+use tracing::Instrument as _;
 use zksync_concurrency::{ctx, error::Wrap as _, metrics::LatencyHistogramExt as _, sync, time};
-use zksync_consensus_network::io::ConsensusReq;
+use zksync_consensus_network::{io::ConsensusReq, TraceContext};
 use zksync_consensus_roles::{validator, validator::ConsensusMsg};
 use zksync_consensus_storage as storage;
 
@@ -17,7 +18,7 @@ pub(crate) struct StateMachine {
     /// Pipe through which replica sends network messages.
     pub(super) outbound_pipe: OutputSender,
     /// Pipe through which replica receives network requests.
-    inbound_pipe: sync::prunable_mpsc::Receiver<ConsensusReq>,
+    pub(super) inbound_pipe: sync::prunable_mpsc::Receiver<ConsensusReq>,
     /// The current view number.
     pub(crate) view: validator::ViewNumber,
     /// The current phase.
@@ -29,8 +30,42 @@ pub(crate) struct StateMachine {
     /// A cache of the received block proposals.
     pub(crate) block_proposal_cache:
         BTreeMap<validator::BlockNumber, HashMap<validator::PayloadHash, validator::Payload>>,
+    /// Time at which a proposal for each block in `block_proposal_cache` has been first received.
+    /// Used for the end-to-end latency metrics of the blocks.
+    pub(crate) block_proposal_times: BTreeMap<validator::BlockNumber, time::Instant>,
+    /// A cache of the key rotations committed by the received block proposals.
+    /// Unlike the payloads, the rotations are not backed up: if the replica restarts
+    /// before the block is finalized, the block is fetched from the peers instead.
+    pub(crate) key_rotation_cache: BTreeMap<
+        validator::BlockNumber,
+        HashMap<validator::KeyRotationHash, validator::KeyRotationCert>,
+    >,
+    /// A cache of the protocol upgrades committed by the received block proposals.
+    /// Not backed up, like `key_rotation_cache`.
+    pub(crate) protocol_upgrade_cache: BTreeMap<
+        validator::BlockNumber,
+        HashMap<validator::ProtocolUpgradeHash, validator::ProtocolUpgradeQC>,
+    >,
     /// The deadline to receive an input message.
     pub(crate) timeout_deadline: time::Deadline,
+    /// The deadline of the current view, after which the replica moves on to the next view.
+    /// Equal to `timeout_deadline`, unless the replica is catching up.
+    pub(crate) view_deadline: time::Deadline,
+    /// Time at which the current view has started.
+    pub(crate) view_start: time::Instant,
+    /// Number of the block expected to be proposed by another validator in the current view.
+    /// Consumed by the shadow proposer.
+    pub(crate) shadow_block: sync::watch::Sender<Option<validator::BlockNumber>>,
+    /// Whether the replica is in the catch-up mode, i.e. it is too far behind
+    /// the highest QC to participate in the consensus. Consumed by the proposer.
+    pub(crate) catching_up: sync::watch::Sender<bool>,
+    /// Checkpoint of the last persisted replica state. Consumed by the checkpointer.
+    pub(crate) checkpoint: sync::watch::Sender<Option<storage::ReplicaCheckpoint>>,
+    /// Pool verifying the signatures and the QCs of the proposals.
+    pub(crate) verifier: Verifier,
+    /// Trace context of the message being processed.
+    /// It is propagated to the messages sent in response.
+    pub(crate) trace: Option<TraceContext>,
 }
 
 impl StateMachine {
@@ -46,6 +81,9 @@ impl StateMachine {
         outbound_pipe: OutputSender,
     ) -> ctx::Result<(Self, sync::prunable_mpsc::Sender<ConsensusReq>)> {
         let backup = config.replica_store.state(ctx).await?;
+        let backup = Self::reconcile_checkpoint(ctx, &config, backup)
+            .await
+            .wrap("reconcile_checkpoint()")?;
         let mut block_proposal_cache: BTreeMap<_, HashMap<_, _>> = BTreeMap::new();
         for proposal in backup.proposals {
             block_proposal_cache
@@ -54,8 +92,13 @@ impl StateMachine {
                 .insert(proposal.payload.hash(), proposal.payload);
         }
 
-        let (send, recv) = sync::prunable_mpsc::channel(StateMachine::inbound_pruning_predicate);
+        let (send, recv) = sync::prunable_mpsc::bounded(
+            config.inbound_capacity(),
+            StateMachine::inbound_pruning_predicate,
+            inbound_priority(&config.block_store),
+        );
 
+        let verifier = Verifier::new(config.verifier_threads);
         let mut this = Self {
             config,
             outbound_pipe,
@@ -65,10 +108,21 @@ impl StateMachine {
             high_vote: backup.high_vote,
             high_qc: backup.high_qc,
             block_proposal_cache,
+            block_proposal_times: BTreeMap::new(),
+            key_rotation_cache: BTreeMap::new(),
+            protocol_upgrade_cache: BTreeMap::new(),
             timeout_deadline: time::Deadline::Infinite,
+            view_deadline: time::Deadline::Infinite,
+            view_start: ctx.now(),
+            shadow_block: sync::watch::channel(None).0,
+            catching_up: sync::watch::channel(false).0,
+            checkpoint: sync::watch::channel(None).0,
+            verifier,
+            trace: None,
         };
 
         // We need to start the replica before processing inputs.
+        this.check_catch_up(ctx).await.wrap("check_catch_up()")?;
         this.start_new_view(ctx).await.wrap("start_new_view()")?;
 
         Ok((this, send))
@@ -91,15 +145,22 @@ impl StateMachine {
 
             // Check for timeout.
             let Some(req) = recv.ok() else {
-                self.start_new_view(ctx).await?;
+                self.on_timeout(ctx).await?;
                 continue;
             };
 
             let now = ctx.now();
+            // Continue the trace of the sender.
+            self.trace = req.trace.map(|trace| trace.child(&mut ctx.rng()));
+            let span = self
+                .trace
+                .as_ref()
+                .map_or_else(tracing::Span::none, TraceContext::span);
             let label = match &req.msg.msg {
                 ConsensusMsg::LeaderPrepare(_) => {
                     let res = match self
                         .process_leader_prepare(ctx, req.msg.cast().unwrap())
+                        .instrument(span)
                         .await
                         .wrap("process_leader_prepare()")
                     {
@@ -115,6 +176,7 @@ impl StateMachine {
                 ConsensusMsg::LeaderCommit(_) => {
                     let res = match self
                         .process_leader_commit(ctx, req.msg.cast().unwrap())
+                        .instrument(span)
                         .await
                         .wrap("process_leader_commit()")
                     {
@@ -127,9 +189,27 @@ impl StateMachine {
                     };
                     metrics::ConsensusMsgLabel::LeaderCommit.with_result(&res)
                 }
+                ConsensusMsg::LeaderTimeout(_) => {
+                    let res = match self
+                        .process_leader_timeout(ctx, req.msg.cast().unwrap())
+                        .instrument(span)
+                        .await
+                        .wrap("process_leader_timeout()")
+                    {
+                        Err(super::leader_timeout::Error::Internal(err)) => return Err(err),
+                        Err(err) => {
+                            tracing::warn!("process_leader_timeout(): {err:#}");
+                            Err(())
+                        }
+                        Ok(()) => Ok(()),
+                    };
+                    metrics::ConsensusMsgLabel::LeaderTimeout.with_result(&res)
+                }
                 _ => unreachable!(),
             };
+            self.trace = None;
             metrics::METRICS.replica_processing_latency[&label].observe_latency(ctx.now() - now);
+            self.check_catch_up(ctx).await?;
 
             // Notify network actor that the message has been processed.
             // Ignore sending error.
@@ -137,6 +217,25 @@ impl StateMachine {
         }
     }
 
+    /// Number of the block that the next proposal is expected to have, given `high_qc`.
+    pub(crate) fn next_block_number(&self) -> validator::BlockNumber {
+        match &self.high_qc {
+            Some(qc) => qc.header().number.next(),
+            None => self.config.genesis().fork.first_block,
+        }
+    }
+
+    /// Current view, stamped with the protocol version of the next block:
+    /// the version changes at the activation heights of the protocol upgrades.
+    pub(crate) fn current_view(&self) -> validator::View {
+        let genesis = self.config.genesis();
+        validator::View {
+            protocol_version: genesis.protocol_version_at(self.next_block_number()),
+            fork: genesis.fork.number,
+            number: self.view,
+        }
+    }
+
     /// Backups the replica state to disk.
     pub(crate) async fn backup_state(&self, ctx: &ctx::Ctx) -> ctx::Result<()> {
         let mut proposals = vec![];
@@ -158,6 +257,12 @@ impl StateMachine {
             .set_state(ctx, &backup)
             .await
             .wrap("put_replica_state")?;
+        // The checkpoint is never ahead of the persisted state.
+        self.checkpoint
+            .send_replace(Some(storage::ReplicaCheckpoint::new(
+                self.config.genesis().fork.number,
+                &backup,
+            )));
         Ok(())
     }
 
@@ -169,6 +274,7 @@ impl StateMachine {
         match (&pending_req.msg.msg, &new_req.msg.msg) {
             (ConsensusMsg::LeaderPrepare(_), ConsensusMsg::LeaderPrepare(_)) => true,
             (ConsensusMsg::LeaderCommit(_), ConsensusMsg::LeaderCommit(_)) => true,
+            (ConsensusMsg::LeaderTimeout(_), ConsensusMsg::LeaderTimeout(_)) => true,
             _ => false,
         }
     }
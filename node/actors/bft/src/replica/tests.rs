@@ -1,14 +1,18 @@
-use super::{leader_commit, leader_prepare};
+use super::{leader_commit, leader_prepare, leader_timeout, StateMachine};
 use crate::{
     testonly,
     testonly::ut_harness::{UTHarness, MAX_PAYLOAD_SIZE},
 };
 use assert_matches::assert_matches;
 use rand::Rng;
-use zksync_concurrency::{ctx, scope};
+use zksync_concurrency::{ctx, oneshot, scope, time};
+use zksync_consensus_network::io::ConsensusReq;
 use zksync_consensus_roles::validator::{
     self, CommitQC, Payload, PrepareQC, ReplicaCommit, ReplicaPrepare, ViewNumber,
 };
+use zksync_consensus_storage::{
+    self as storage, testonly::in_memory, CheckpointStore as _, ReplicaCheckpoint,
+};
 
 /// Sanity check of the happy path.
 #[tokio::test]
@@ -58,9 +62,9 @@ async fn leader_prepare_incompatible_protocol_version() {
             .await;
         assert_matches!(
             res,
-            Err(leader_prepare::Error::IncompatibleProtocolVersion { message_version, local_version }) => {
+            Err(leader_prepare::Error::IncompatibleProtocolVersion { message_version, supported_versions }) => {
                 assert_eq!(message_version, incompatible_protocol_version);
-                assert_eq!(local_version, util.protocol_version());
+                assert_eq!(supported_versions, util.protocol_version()..=util.protocol_version());
             }
         );
         Ok(())
@@ -93,6 +97,42 @@ async fn leader_prepare_sanity_yield_replica_commit() {
     .unwrap();
 }
 
+/// The messages waiting in the inbound pipe are verified while the payload is verified.
+#[tokio::test]
+async fn leader_prepare_prefetches_pending_messages() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new(ctx, 1).await;
+        s.spawn_bg(runner.run(ctx));
+
+        let leader_prepare = util.new_leader_prepare(ctx).await;
+        let pending: Vec<_> = (0..3)
+            .map(|_| {
+                util.owner_key()
+                    .sign_msg(validator::ConsensusMsg::ReplicaCommit(rng.gen()))
+            })
+            .collect();
+        for msg in &pending {
+            util.replica_pipe.send(ConsensusReq {
+                msg: msg.clone(),
+                trace: None,
+                ack: oneshot::channel().0,
+            });
+        }
+        util.process_leader_prepare(ctx, util.sign(leader_prepare))
+            .await
+            .unwrap();
+        for msg in &pending {
+            assert!(util.replica.verifier.is_cached(msg));
+        }
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
 #[tokio::test]
 async fn leader_prepare_invalid_leader() {
     zksync_concurrency::testonly::abort_on_panic();
@@ -185,12 +225,14 @@ async fn leader_prepare_invalid_payload() {
                 view: util.replica_view(),
                 proposal: leader_prepare.proposal,
             },
-            util.genesis(),
+            &util.genesis(),
         );
-        justification.add(&util.sign(justification.message.clone()), util.genesis());
+        justification.add(&util.sign(justification.message.clone()), &util.genesis());
         let block = validator::FinalBlock {
             payload: leader_prepare.proposal_payload.clone().unwrap(),
             justification,
+            key_rotation: None,
+            protocol_upgrade: None,
         };
         util.replica
             .config
@@ -417,7 +459,7 @@ async fn leader_prepare_reproposal_without_quorum() {
             replica_prepare.high_vote.as_mut().unwrap().proposal.payload = rng.gen();
             leader_prepare
                 .justification
-                .add(&key.sign_msg(replica_prepare.clone()), util.genesis());
+                .add(&key.sign_msg(replica_prepare.clone()), &util.genesis());
         }
         let res = util
             .process_leader_prepare(ctx, util.sign(leader_prepare))
@@ -530,6 +572,164 @@ async fn leader_commit_sanity_yield_replica_prepare() {
     .unwrap();
 }
 
+#[tokio::test]
+async fn leader_commit_key_rotation() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner, cert) =
+            UTHarness::new_with_key_rotation(ctx, 1, ViewNumber(1000)).await;
+        s.spawn_bg(runner.run(ctx));
+
+        // The rotation is committed by the first block and applied to the genesis.
+        let leader_commit = util.new_leader_commit(ctx).await;
+        let header = *leader_commit.justification.header();
+        assert_eq!(header.key_rotation, Some(cert.hash()));
+        util.process_leader_commit(ctx, util.sign(leader_commit))
+            .await
+            .unwrap();
+        assert!(util.genesis().key_rotations.contains(&cert));
+        let block = util
+            .replica
+            .config
+            .block_store
+            .block(ctx, header.number)
+            .await?
+            .unwrap();
+        assert_eq!(block.key_rotation, Some(cert));
+
+        // The rotation is not proposed again.
+        let leader_commit = util.new_leader_commit(ctx).await;
+        assert_eq!(leader_commit.justification.header().key_rotation, None);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn leader_prepare_invalid_key_rotation() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new(ctx, 1).await;
+        s.spawn_bg(runner.run(ctx));
+
+        let mut leader_prepare = util.new_leader_prepare(ctx).await;
+        let mut cert: validator::KeyRotationCert = rng.gen();
+        cert.rotation.msg.activation = ViewNumber(u64::MAX);
+        leader_prepare.proposal.key_rotation = Some(cert.hash());
+        leader_prepare.proposal_key_rotation = Some(cert);
+        let res = util
+            .process_leader_prepare(ctx, util.sign(leader_prepare))
+            .await;
+        assert_matches!(
+            res,
+            Err(leader_prepare::Error::ProposalInvalidKeyRotation(_))
+        );
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn leader_commit_protocol_upgrade() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner, qc) = UTHarness::new_with_protocol_upgrade(ctx, 1, 10).await;
+        s.spawn_bg(runner.run(ctx));
+
+        // The upgrade is committed by the first block and applied to the genesis.
+        let leader_commit = util.new_leader_commit(ctx).await;
+        let header = *leader_commit.justification.header();
+        assert_eq!(header.protocol_upgrade, Some(qc.hash()));
+        util.process_leader_commit(ctx, util.sign(leader_commit))
+            .await
+            .unwrap();
+        assert!(util.genesis().protocol_upgrades.contains(&qc));
+        assert_eq!(
+            crate::PROTOCOL_VERSION,
+            util.genesis().protocol_version_at(qc.message.activation)
+        );
+        let block = util
+            .replica
+            .config
+            .block_store
+            .block(ctx, header.number)
+            .await?
+            .unwrap();
+        assert_eq!(block.protocol_upgrade, Some(qc));
+
+        // The upgrade is not proposed again.
+        let leader_commit = util.new_leader_commit(ctx).await;
+        assert_eq!(leader_commit.justification.header().protocol_upgrade, None);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn leader_prepare_invalid_protocol_upgrade() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new(ctx, 1).await;
+        s.spawn_bg(runner.run(ctx));
+
+        // An upgrade of a different chain.
+        let mut leader_prepare = util.new_leader_prepare(ctx).await;
+        let mut qc: validator::ProtocolUpgradeQC = rng.gen();
+        qc.message.activation = validator::BlockNumber(u64::MAX);
+        leader_prepare.proposal.protocol_upgrade = Some(qc.hash());
+        leader_prepare.proposal_protocol_upgrade = Some(qc);
+        let res = util
+            .process_leader_prepare(ctx, util.sign(leader_prepare))
+            .await;
+        assert_matches!(
+            res,
+            Err(leader_prepare::Error::ProposalInvalidProtocolUpgrade(_))
+        );
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn leader_prepare_protocol_upgrade_activated_too_early() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new(ctx, 1).await;
+        s.spawn_bg(runner.run(ctx));
+
+        // The upgrade has to activate after the proposed block.
+        let mut leader_prepare = util.new_leader_prepare(ctx).await;
+        let mut qc: validator::ProtocolUpgradeQC = rng.gen();
+        qc.message.activation = leader_prepare.proposal.number;
+        leader_prepare.proposal.protocol_upgrade = Some(qc.hash());
+        leader_prepare.proposal_protocol_upgrade = Some(qc);
+        let res = util
+            .process_leader_prepare(ctx, util.sign(leader_prepare))
+            .await;
+        assert_matches!(
+            res,
+            Err(leader_prepare::Error::InvalidMessage(
+                validator::LeaderPrepareVerifyError::ProposalProtocolUpgradeActivation { .. }
+            ))
+        );
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
 #[tokio::test]
 async fn leader_commit_incompatible_protocol_version() {
     zksync_concurrency::testonly::abort_on_panic();
@@ -544,9 +744,9 @@ async fn leader_commit_incompatible_protocol_version() {
         let res = util.process_leader_commit(ctx, util.sign(leader_commit)).await;
         assert_matches!(
             res,
-            Err(leader_commit::Error::IncompatibleProtocolVersion { message_version, local_version }) => {
+            Err(leader_commit::Error::IncompatibleProtocolVersion { message_version, supported_versions }) => {
                 assert_eq!(message_version, incompatible_protocol_version);
-                assert_eq!(local_version, util.protocol_version());
+                assert_eq!(supported_versions, util.protocol_version()..=util.protocol_version());
             }
         );
         Ok(())
@@ -616,3 +816,392 @@ async fn leader_commit_invalid_commit_qc() {
     .await
     .unwrap();
 }
+
+/// A replica far behind the highest QC doesn't vote until it catches up.
+#[tokio::test]
+async fn catch_up_mode() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new_with_catch_up(ctx, 6, 2).await;
+        s.spawn_bg(runner.run(ctx));
+        util.produce_block(ctx).await;
+        let high_qc = util.replica.high_qc.clone();
+        let leader_prepare = util.new_leader_prepare(ctx).await;
+
+        // Learn about a QC far ahead of the local block store.
+        util.replica.high_qc = Some(util.new_commit_qc(|msg| {
+            msg.proposal.number = validator::BlockNumber(msg.proposal.number.0 + 10)
+        }));
+        util.replica.check_catch_up(ctx).await.unwrap();
+        assert!(util.replica.is_catching_up());
+
+        // The proposal is not voted for.
+        util.replica
+            .process_leader_prepare(ctx, util.sign(leader_prepare))
+            .await
+            .unwrap();
+        assert_eq!(util.replica.phase, validator::Phase::Prepare);
+
+        // Once caught up, the replica starts a new view.
+        let view = util.replica.view;
+        util.replica.high_qc = high_qc;
+        util.replica.check_catch_up(ctx).await.unwrap();
+        assert!(!util.replica.is_catching_up());
+        assert_eq!(util.replica.view, view.next());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+/// A replica in the catch-up mode still advances the view when the view times out,
+/// but without sending a timeout vote.
+#[tokio::test]
+async fn catch_up_mode_view_timeout() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new_with_catch_up(ctx, 6, 2).await;
+        s.spawn_bg(runner.run(ctx));
+        util.produce_block(ctx).await;
+        util.replica.high_qc = Some(util.new_commit_qc(|msg| {
+            msg.proposal.number = validator::BlockNumber(msg.proposal.number.0 + 10)
+        }));
+        util.replica.check_catch_up(ctx).await.unwrap();
+        assert!(util.replica.is_catching_up());
+        assert!(util.replica.timeout_deadline <= util.replica.view_deadline);
+
+        // Polling before the end of the view doesn't change the view.
+        let view = util.replica.view;
+        util.replica.on_timeout(ctx).await.unwrap();
+        assert_eq!(util.replica.view, view);
+
+        // Once the view times out, the replica moves on to the next view.
+        util.clear_outbound();
+        util.replica.view_deadline = time::Deadline::Finite(ctx.now());
+        util.replica.on_timeout(ctx).await.unwrap();
+        assert!(util.replica.is_catching_up());
+        assert_eq!(util.replica.view, view.next());
+        assert!(util.try_recv::<validator::ReplicaTimeout>().is_none());
+        assert!(util.replica.view_deadline > time::Deadline::Finite(ctx.now()));
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+/// Views ended by a CommitQC are recorded in the view history.
+#[tokio::test]
+async fn view_history() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new(ctx, 1).await;
+        s.spawn_bg(runner.run(ctx));
+        let view = util.replica.view;
+        util.produce_block(ctx).await;
+        let leader = util.view_leader(view);
+        let history = util.replica.config.view_history.get();
+        let last = history.last().unwrap();
+        assert_eq!(last.view, view);
+        assert_eq!(last.leader, leader);
+        assert_eq!(last.reason, crate::ViewChangeReason::CommitQc);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+/// An observer verifies the proposals, but doesn't vote for them.
+#[tokio::test]
+async fn observer_doesnt_vote() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new_observer(ctx, 1).await;
+        s.spawn_bg(runner.run(ctx));
+
+        let leader_prepare = util.new_leader_prepare(ctx).await;
+        util.replica
+            .process_leader_prepare(ctx, util.sign(leader_prepare))
+            .await
+            .unwrap();
+        assert_eq!(util.replica.phase, validator::Phase::Commit);
+        assert!(util.try_recv::<ReplicaCommit>().is_none());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn timeout_vote() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new_many(ctx).await;
+        s.spawn_bg(runner.run(ctx));
+
+        util.replica.send_timeout_vote(ctx).await.unwrap();
+        let vote: validator::Signed<validator::ReplicaTimeout> = util.try_recv().unwrap();
+        assert_eq!(vote.msg.view, util.replica_view());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn leader_timeout_sanity_yield_replica_prepare() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new_many(ctx).await;
+        s.spawn_bg(runner.run(ctx));
+
+        let view = util.replica.view;
+        let leader_timeout = validator::LeaderTimeout {
+            justification: util.new_timeout_qc(),
+        };
+        let replica_prepare = util
+            .process_leader_timeout(ctx, util.next_view_leader_key().sign_msg(leader_timeout))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(util.replica.view, view.next());
+        assert_eq!(replica_prepare.msg.view.number, view.next());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn leader_timeout_old() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new_many(ctx).await;
+        s.spawn_bg(runner.run(ctx));
+
+        let leader_timeout = validator::LeaderTimeout {
+            justification: util.new_timeout_qc(),
+        };
+        let leader_timeout = util.next_view_leader_key().sign_msg(leader_timeout);
+        util.produce_block(ctx).await;
+        let view = util.replica.view;
+        // The replica has already left the view.
+        let res = util
+            .process_leader_timeout(ctx, leader_timeout)
+            .await
+            .unwrap();
+        assert!(res.is_none());
+        assert_eq!(util.replica.view, view);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn leader_timeout_bad_leader() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new_many(ctx).await;
+        s.spawn_bg(runner.run(ctx));
+
+        let leader_timeout = validator::LeaderTimeout {
+            justification: util.new_timeout_qc(),
+        };
+        let leader = util.view_leader(util.replica.view.next());
+        let key = util.keys.iter().find(|k| k.public() != leader).unwrap();
+        let res = util
+            .process_leader_timeout(ctx, key.sign_msg(leader_timeout))
+            .await;
+        assert_matches!(res, Err(leader_timeout::Error::BadLeader { .. }));
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn leader_timeout_invalid_qc() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new_many(ctx).await;
+        s.spawn_bg(runner.run(ctx));
+
+        let mut justification = util.new_timeout_qc();
+        justification.signature = ctx.rng().gen();
+        let leader_timeout = validator::LeaderTimeout { justification };
+        let res = util
+            .process_leader_timeout(ctx, util.next_view_leader_key().sign_msg(leader_timeout))
+            .await;
+        assert_matches!(res, Err(leader_timeout::Error::InvalidMessage(_)));
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+fn checkpoint_config(
+    store: &in_memory::CheckpointStore,
+    allow_stale_state: bool,
+) -> crate::CheckpointConfig {
+    crate::CheckpointConfig {
+        store: Box::new(store.clone()),
+        interval: time::Duration::ZERO,
+        allow_stale_state,
+    }
+}
+
+#[tokio::test]
+async fn checkpoint_follows_persisted_state() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let store = in_memory::CheckpointStore::default();
+        let (mut util, runner) =
+            UTHarness::new_with_checkpoint(ctx, 1, checkpoint_config(&store, false)).await;
+        s.spawn_bg(runner.run(ctx));
+
+        util.produce_block(ctx).await;
+        let checkpoint = util.replica.checkpoint.borrow().clone().unwrap();
+        assert_eq!(checkpoint.epoch, util.genesis().fork.number);
+        assert_eq!(checkpoint.view, util.replica.view);
+        assert_eq!(checkpoint.high_qc, util.replica.high_qc);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn stale_replica_state_refused() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let store = in_memory::CheckpointStore::default();
+        let (util, runner) =
+            UTHarness::new_with_checkpoint(ctx, 1, checkpoint_config(&store, false)).await;
+        s.spawn_bg(runner.run(ctx));
+
+        let checkpoint = ReplicaCheckpoint {
+            epoch: util.genesis().fork.number,
+            view: ViewNumber(util.replica.view.0 + 10),
+            high_qc: None,
+        };
+        store.set_checkpoint(ctx, &checkpoint).await.unwrap();
+        let res = StateMachine::reconcile_checkpoint(
+            ctx,
+            &util.replica.config,
+            storage::ReplicaState::default(),
+        )
+        .await;
+        assert!(res.is_err());
+
+        // Checkpoints of other epochs are ignored.
+        let checkpoint = ReplicaCheckpoint {
+            epoch: util.genesis().fork.number.next(),
+            ..checkpoint
+        };
+        store.set_checkpoint(ctx, &checkpoint).await.unwrap();
+        let state = storage::ReplicaState {
+            view: util.replica.view,
+            ..Default::default()
+        };
+        StateMachine::reconcile_checkpoint(ctx, &util.replica.config, state)
+            .await
+            .unwrap();
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn stale_replica_state_override() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let store = in_memory::CheckpointStore::default();
+        let (util, runner) =
+            UTHarness::new_with_checkpoint(ctx, 1, checkpoint_config(&store, true)).await;
+        s.spawn_bg(runner.run(ctx));
+
+        let checkpoint = ReplicaCheckpoint {
+            epoch: util.genesis().fork.number,
+            view: ViewNumber(util.replica.view.0 + 10),
+            high_qc: None,
+        };
+        store.set_checkpoint(ctx, &checkpoint).await.unwrap();
+        let state = StateMachine::reconcile_checkpoint(
+            ctx,
+            &util.replica.config,
+            storage::ReplicaState::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(state.view, checkpoint.view);
+        assert_eq!(state.phase, validator::Phase::Prepare);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn leader_prepare_timeout_qc() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    scope::run!(ctx, |ctx, s| async {
+        let (mut util, runner) = UTHarness::new_many(ctx).await;
+        s.spawn_bg(runner.run(ctx));
+
+        util.set_replica_view(ViewNumber(1));
+        let mut leader_prepare = util.new_leader_prepare(ctx).await;
+        let mut prev_view = leader_prepare.view().clone();
+        prev_view.number = ViewNumber(prev_view.number.0 - 1);
+
+        // A TimeoutQC for the view of the proposal doesn't justify entering it.
+        leader_prepare.timeout_qc = Some(util.new_timeout_qc_for(leader_prepare.view().clone()));
+        let res = util
+            .process_leader_prepare(ctx, util.sign(leader_prepare.clone()))
+            .await;
+        assert_matches!(
+            res,
+            Err(leader_prepare::Error::InvalidMessage(
+                validator::LeaderPrepareVerifyError::TimeoutQCBadView { .. }
+            ))
+        );
+
+        // A TimeoutQC with a bad signature.
+        let mut timeout_qc = util.new_timeout_qc_for(prev_view.clone());
+        timeout_qc.signature = ctx.rng().gen();
+        leader_prepare.timeout_qc = Some(timeout_qc);
+        let res = util
+            .process_leader_prepare(ctx, util.sign(leader_prepare.clone()))
+            .await;
+        assert_matches!(
+            res,
+            Err(leader_prepare::Error::InvalidMessage(
+                validator::LeaderPrepareVerifyError::TimeoutQC(_)
+            ))
+        );
+
+        // A TimeoutQC for the previous view justifies the proposal.
+        leader_prepare.timeout_qc = Some(util.new_timeout_qc_for(prev_view));
+        let replica_commit = util
+            .process_leader_prepare(ctx, util.sign(leader_prepare.clone()))
+            .await
+            .unwrap();
+        assert_eq!(replica_commit.msg.view, *leader_prepare.view());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
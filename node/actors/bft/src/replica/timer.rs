@@ -1,7 +1,8 @@
 use super::StateMachine;
-use crate::metrics;
+use crate::{metrics, ViewChangeReason};
 use tracing::instrument;
-use zksync_concurrency::{ctx, metrics::LatencyGaugeExt as _, time};
+use zksync_concurrency::{ctx, error::Wrap as _, metrics::LatencyGaugeExt as _, time};
+use zksync_consensus_network::io::{ConsensusInputMessage, Target};
 use zksync_consensus_roles::validator;
 
 impl StateMachine {
@@ -19,6 +20,54 @@ impl StateMachine {
         let timeout = Self::BASE_DURATION * 2u32.pow((self.view.0 - final_view.0) as u32);
 
         metrics::METRICS.replica_view_timeout.set_latency(timeout);
-        self.timeout_deadline = time::Deadline::Finite(ctx.now() + timeout);
+        // The monotonic clock is used, so that the NTP offset of `Config::time_source` doesn't affect the timers.
+        self.view_deadline = time::Deadline::Finite(ctx.now() + timeout);
+        self.timeout_deadline = self.view_deadline;
+    }
+
+    /// Whether the deadline of the current view has passed.
+    pub(crate) fn view_timed_out(&self, ctx: &ctx::Ctx) -> bool {
+        time::Deadline::Finite(ctx.now()) >= self.view_deadline
+    }
+
+    /// Handles the expiry of `timeout_deadline`. The view is advanced on timeout even
+    /// while catching up (just without the timeout vote), so that the replica keeps up
+    /// with the views of the other replicas.
+    pub(crate) async fn on_timeout(&mut self, ctx: &ctx::Ctx) -> ctx::Result<()> {
+        if self.is_catching_up() {
+            self.check_catch_up(ctx).await?;
+            if !self.is_catching_up() || !self.view_timed_out(ctx) {
+                return Ok(());
+            }
+        } else {
+            self.send_timeout_vote(ctx).await?;
+        }
+        self.end_view(ctx, ViewChangeReason::Timeout);
+        self.start_new_view(ctx).await
+    }
+
+    /// Sends a timeout vote for the current view to the leader of the next view,
+    /// which aggregates the votes into a TimeoutQC. An observer never signs it.
+    pub(crate) async fn send_timeout_vote(&self, ctx: &ctx::Ctx) -> ctx::Result<()> {
+        if self.config.observer {
+            return Ok(());
+        }
+        let output_message = ConsensusInputMessage {
+            message: self
+                .config
+                .signer
+                .sign_msg(
+                    ctx,
+                    validator::ConsensusMsg::ReplicaTimeout(validator::ReplicaTimeout {
+                        view: self.current_view(),
+                    }),
+                )
+                .await
+                .wrap("sign_msg()")?,
+            recipient: Target::Validator(self.config.genesis().view_leader(self.view.next())),
+            trace: None,
+        };
+        self.outbound_pipe.send(output_message.into());
+        Ok(())
     }
 }
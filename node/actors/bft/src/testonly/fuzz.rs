@@ -47,6 +47,8 @@ impl Fuzz for validator::ConsensusMsg {
             validator::ConsensusMsg::LeaderCommit(msg) => msg.mutate(rng),
             validator::ConsensusMsg::ReplicaPrepare(msg) => msg.mutate(rng),
             validator::ConsensusMsg::ReplicaCommit(msg) => msg.mutate(rng),
+            validator::ConsensusMsg::ReplicaTimeout(msg) => msg.mutate(rng),
+            validator::ConsensusMsg::LeaderTimeout(msg) => msg.mutate(rng),
         }
     }
 }
@@ -73,9 +75,10 @@ impl Fuzz for validator::ReplicaCommit {
 // TODO: why payload is not fuzzed?
 impl Fuzz for validator::LeaderPrepare {
     fn mutate(&mut self, rng: &mut impl Rng) {
-        match rng.gen_range(0..2) {
+        match rng.gen_range(0..3) {
             0 => self.proposal.mutate(rng),
             1 => self.justification.mutate(rng),
+            2 => self.timeout_qc = Some(rng.gen()),
             _ => unreachable!(),
         }
     }
@@ -87,6 +90,18 @@ impl Fuzz for validator::LeaderCommit {
     }
 }
 
+impl Fuzz for validator::ReplicaTimeout {
+    fn mutate(&mut self, rng: &mut impl Rng) {
+        self.view = rng.gen();
+    }
+}
+
+impl Fuzz for validator::LeaderTimeout {
+    fn mutate(&mut self, rng: &mut impl Rng) {
+        self.justification.mutate(rng);
+    }
+}
+
 impl Fuzz for validator::PrepareQC {
     fn mutate(&mut self, rng: &mut impl Rng) {
         // We give them different weights because we want to mutate the signature less often.
@@ -129,6 +144,18 @@ impl Fuzz for validator::CommitQC {
     }
 }
 
+impl Fuzz for validator::TimeoutQC {
+    fn mutate(&mut self, rng: &mut impl Rng) {
+        // We give them different weights because we want to mutate the message more often.
+        match rng.gen_range(0..10) {
+            0..=6 => self.message.mutate(rng),
+            7 | 8 => self.signers.mutate(rng),
+            9 => self.signature = rng.gen(),
+            _ => unreachable!(),
+        }
+    }
+}
+
 impl Fuzz for validator::Signers {
     fn mutate(&mut self, rng: &mut impl Rng) {
         match rng.gen_range(0..5) {
@@ -174,10 +201,12 @@ impl Fuzz for validator::Payload {
 
 impl Fuzz for validator::BlockHeader {
     fn mutate(&mut self, rng: &mut impl Rng) {
-        match rng.gen_range(0..3) {
+        match rng.gen_range(0..5) {
             0 => self.parent = rng.gen(),
             1 => self.number = rng.gen(),
             2 => self.payload = rng.gen(),
+            3 => self.payload_root = rng.gen(),
+            4 => self.key_rotation = rng.gen(),
             _ => unreachable!(),
         }
     }
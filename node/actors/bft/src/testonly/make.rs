@@ -1,6 +1,7 @@
 //! This module contains utilities that are only meant for testing purposes.
 use crate::PayloadManager;
 use rand::Rng as _;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use zksync_concurrency::ctx;
 use zksync_consensus_roles::validator;
 
@@ -27,6 +28,47 @@ impl PayloadManager for RandomPayload {
     ) -> ctx::Result<()> {
         Ok(())
     }
+
+    fn supports_shadow_proposals(&self) -> bool {
+        true
+    }
+}
+
+/// Proposes an empty payload for the first `empty` calls of propose(),
+/// and a random payload of the given size afterwards.
+#[derive(Debug)]
+pub struct DelayedPayload {
+    /// Number of the remaining empty proposals.
+    pub empty: AtomicUsize,
+    /// Size of the non-empty payload.
+    pub size: usize,
+}
+
+#[async_trait::async_trait]
+impl PayloadManager for DelayedPayload {
+    async fn propose(
+        &self,
+        ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<validator::Payload> {
+        if self
+            .empty
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok()
+        {
+            return Ok(validator::Payload(vec![]));
+        }
+        RandomPayload(self.size).propose(ctx, number).await
+    }
+
+    async fn verify(
+        &self,
+        _ctx: &ctx::Ctx,
+        _number: validator::BlockNumber,
+        _payload: &validator::Payload,
+    ) -> ctx::Result<()> {
+        Ok(())
+    }
 }
 
 /// propose() blocks indefinitely.
@@ -29,6 +29,7 @@ impl Distribution<InputMessage> for Standard {
         let (send, _) = oneshot::channel();
         InputMessage::Network(ConsensusReq {
             msg: rng.gen(),
+            trace: None,
             ack: send,
         })
     }
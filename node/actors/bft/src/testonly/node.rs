@@ -3,9 +3,10 @@ use crate::{io, testonly, PayloadManager};
 use anyhow::Context as _;
 use rand::Rng;
 use std::sync::Arc;
-use zksync_concurrency::{ctx, scope};
+use zksync_concurrency::{ctx, scope, sync};
 use zksync_consensus_network as network;
 use zksync_consensus_network::io::ConsensusInputMessage;
+use zksync_consensus_roles::validator;
 use zksync_consensus_storage as storage;
 use zksync_consensus_storage::testonly::in_memory;
 use zksync_consensus_utils::pipe;
@@ -43,6 +44,8 @@ pub(super) struct Node {
     pub(crate) net: network::Config,
     pub(crate) behavior: Behavior,
     pub(crate) block_store: Arc<storage::BlockStore>,
+    /// Protocol upgrade proposed by the node.
+    pub(crate) protocol_upgrade: Option<validator::ProtocolUpgradeQC>,
 }
 
 impl Node {
@@ -65,11 +68,25 @@ impl Node {
             s.spawn(async {
                 let validator_key = self.net.validator_key.clone().unwrap();
                 crate::Config {
-                    secret_key: validator_key.clone(),
+                    signer: validator_key,
                     block_store: self.block_store.clone(),
                     replica_store: Box::new(in_memory::ReplicaStore::default()),
                     payload_manager: self.behavior.payload_manager(),
                     max_payload_size: MAX_PAYLOAD_SIZE,
+                    shadow_proposer: self.behavior == Behavior::Honest,
+                    verifier_threads: 2,
+                    catch_up_threshold: None,
+                    observer: false,
+                    max_payload_wait: None,
+                    checkpoint: None,
+                    time_source: self.net.time_source.clone(),
+                    recorder: None,
+                    key_rotation: None,
+                    protocol_upgrade: self
+                        .protocol_upgrade
+                        .clone()
+                        .map(|qc| sync::watch::channel(Some(Arc::new(qc))).1),
+                    view_history: crate::ViewHistory::default(),
                 }
                 .run(ctx, consensus_actor_pipe)
                 .await
@@ -100,6 +117,7 @@ impl Node {
                             Behavior::Random => ConsensusInputMessage {
                                 message: rng.gen(),
                                 recipient: network::io::Target::Broadcast,
+                                trace: None,
                             },
                             Behavior::Byzantine => {
                                 message.message.mutate(rng);
@@ -19,14 +19,24 @@ pub(crate) struct Test {
     pub(crate) network: Network,
     pub(crate) nodes: Vec<Behavior>,
     pub(crate) blocks_to_finalize: usize,
+    /// Protocol version of the genesis.
+    pub(crate) protocol_version: validator::ProtocolVersion,
+    /// If set, the nodes propose an upgrade to `crate::PROTOCOL_VERSION`,
+    /// activated at the given number of blocks after the first one.
+    pub(crate) protocol_upgrade_at: Option<u64>,
 }
 
 impl Test {
     /// Run a test with the given parameters.
     pub(crate) async fn run(&self, ctx: &ctx::Ctx) -> anyhow::Result<()> {
         let rng = &mut ctx.rng();
-        let setup = validator::testonly::Setup::new(rng, self.nodes.len());
+        let mut setup = validator::testonly::Setup::new(rng, self.nodes.len());
+        setup.set_protocol_version(self.protocol_version);
         let nets: Vec<_> = network::testonly::new_configs(rng, &setup, 1);
+        let first = setup.genesis.fork.first_block;
+        let protocol_upgrade = self.protocol_upgrade_at.map(|n| {
+            setup.protocol_upgrade(crate::PROTOCOL_VERSION, validator::BlockNumber(first.0 + n))
+        });
         let mut nodes = vec![];
         let mut honest = vec![];
         scope::run!(ctx, |ctx, s| async {
@@ -40,13 +50,13 @@ impl Test {
                     net,
                     behavior: self.nodes[i],
                     block_store: store,
+                    protocol_upgrade: protocol_upgrade.clone(),
                 });
             }
             assert!(!honest.is_empty());
             s.spawn_bg(run_nodes(ctx, self.network, &nodes));
 
             // Run the nodes until all honest nodes store enough finalized blocks.
-            let first = setup.genesis.fork.first_block;
             let want_next = validator::BlockNumber(first.0 + self.blocks_to_finalize as u64);
             for store in &honest {
                 sync::wait_for(ctx, &mut store.subscribe(), |state| {
@@ -55,13 +65,24 @@ impl Test {
                 .await?;
             }
 
-            // Check that the stored blocks are consistent.
+            // Check that the stored blocks are consistent
+            // and finalized with the protocol version active at their height.
+            let genesis = honest[0].genesis();
+            if let Some(qc) = &protocol_upgrade {
+                assert!(genesis.protocol_upgrades.contains(qc));
+            }
             for i in 0..self.blocks_to_finalize as u64 + 1 {
                 let i = validator::BlockNumber(i);
                 let want = honest[0].block(ctx, i).await?;
                 for store in &honest[1..] {
                     assert_eq!(want, store.block(ctx, i).await?);
                 }
+                if let Some(block) = want {
+                    assert_eq!(
+                        genesis.protocol_version_at(i),
+                        block.justification.view().protocol_version
+                    );
+                }
             }
             Ok(())
         })
@@ -123,6 +144,7 @@ async fn run_nodes(ctx: &ctx::Ctx, network: Network, specs: &[Node]) -> anyhow::
                                 let msg = || {
                                     io::OutputMessage::Consensus(io::ConsensusReq {
                                         msg: message.message.clone(),
+                                        trace: message.trace,
                                         ack: oneshot::channel().0,
                                     })
                                 };
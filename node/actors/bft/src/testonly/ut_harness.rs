@@ -1,18 +1,18 @@
 use crate::{
     io::OutputMessage,
     leader,
-    leader::{replica_commit, replica_prepare},
+    leader::{replica_commit, replica_prepare, replica_timeout},
     replica,
-    replica::{leader_commit, leader_prepare},
-    testonly, Config, PayloadManager,
+    replica::{leader_commit, leader_prepare, leader_timeout},
+    testonly, CheckpointConfig, Config, PayloadManager,
 };
 use assert_matches::assert_matches;
 use std::{cmp::Ordering, sync::Arc};
-use zksync_concurrency::ctx;
+use zksync_concurrency::{ctx, sync, time};
 use zksync_consensus_network as network;
 use zksync_consensus_roles::validator::{
-    self, CommitQC, LeaderCommit, LeaderPrepare, Phase, PrepareQC, ReplicaCommit, ReplicaPrepare,
-    SecretKey, Signed, ViewNumber,
+    self, CommitQC, LeaderCommit, LeaderPrepare, LeaderTimeout, Phase, PrepareQC, ReplicaCommit,
+    ReplicaPrepare, ReplicaTimeout, SecretKey, Signed, TimeoutQC, ViewNumber,
 };
 use zksync_consensus_storage::{
     testonly::{in_memory, new_store},
@@ -32,6 +32,9 @@ pub(crate) struct UTHarness {
     pub(crate) leader: leader::StateMachine,
     pub(crate) replica: replica::StateMachine,
     pub(crate) keys: Vec<SecretKey>,
+    /// Inbound pipe of the replica; messages sent to it are not processed,
+    /// unless a test does so explicitly.
+    pub(crate) replica_pipe: sync::prunable_mpsc::Sender<network::io::ConsensusReq>,
     pipe: ctx::channel::UnboundedReceiver<OutputMessage>,
 }
 
@@ -54,29 +57,176 @@ impl UTHarness {
         num_validators: usize,
         payload_manager: Box<dyn PayloadManager>,
     ) -> (UTHarness, BlockStoreRunner) {
-        let rng = &mut ctx.rng();
-        let setup = validator::testonly::Setup::new(rng, num_validators);
+        Self::new_with_config(ctx, num_validators, payload_manager, |_| {}).await
+    }
+
+    /// Creates a new `UTHarness` with the catch-up mode enabled.
+    pub(crate) async fn new_with_catch_up(
+        ctx: &ctx::Ctx,
+        num_validators: usize,
+        catch_up_threshold: u64,
+    ) -> (UTHarness, BlockStoreRunner) {
+        Self::new_with_config(
+            ctx,
+            num_validators,
+            Box::new(testonly::RandomPayload(MAX_PAYLOAD_SIZE)),
+            |cfg| cfg.catch_up_threshold = Some(catch_up_threshold),
+        )
+        .await
+    }
+
+    /// Creates a new `UTHarness` running in the observer mode.
+    pub(crate) async fn new_observer(
+        ctx: &ctx::Ctx,
+        num_validators: usize,
+    ) -> (UTHarness, BlockStoreRunner) {
+        Self::new_with_config(
+            ctx,
+            num_validators,
+            Box::new(testonly::RandomPayload(MAX_PAYLOAD_SIZE)),
+            |cfg| cfg.observer = true,
+        )
+        .await
+    }
+
+    /// Creates a new `UTHarness` with the given `max_payload_wait`.
+    pub(crate) async fn new_with_max_payload_wait(
+        ctx: &ctx::Ctx,
+        num_validators: usize,
+        payload_manager: Box<dyn PayloadManager>,
+        max_payload_wait: time::Duration,
+    ) -> (UTHarness, BlockStoreRunner) {
+        Self::new_with_config(ctx, num_validators, payload_manager, |cfg| {
+            cfg.max_payload_wait = Some(max_payload_wait)
+        })
+        .await
+    }
+
+    /// Creates a new `UTHarness` with the genesis of the given protocol version.
+    pub(crate) async fn new_with_protocol_version(
+        ctx: &ctx::Ctx,
+        num_validators: usize,
+        protocol_version: validator::ProtocolVersion,
+    ) -> (UTHarness, BlockStoreRunner) {
+        let mut setup = validator::testonly::Setup::new(&mut ctx.rng(), num_validators);
+        setup.set_protocol_version(protocol_version);
+        Self::new_with_setup(
+            ctx,
+            setup,
+            Box::new(testonly::RandomPayload(MAX_PAYLOAD_SIZE)),
+            |_| {},
+        )
+        .await
+    }
+
+    /// Creates a new `UTHarness` with the given checkpointing config.
+    pub(crate) async fn new_with_checkpoint(
+        ctx: &ctx::Ctx,
+        num_validators: usize,
+        checkpoint: CheckpointConfig,
+    ) -> (UTHarness, BlockStoreRunner) {
+        Self::new_with_config(
+            ctx,
+            num_validators,
+            Box::new(testonly::RandomPayload(MAX_PAYLOAD_SIZE)),
+            |cfg| cfg.checkpoint = Some(checkpoint),
+        )
+        .await
+    }
+
+    /// Creates a new `UTHarness` whose validator proposes to rotate its key,
+    /// with the new key activated at `activation`. Returns the proposed rotation.
+    pub(crate) async fn new_with_key_rotation(
+        ctx: &ctx::Ctx,
+        num_validators: usize,
+        activation: ViewNumber,
+    ) -> (UTHarness, BlockStoreRunner, validator::KeyRotationCert) {
+        let setup = validator::testonly::Setup::new(&mut ctx.rng(), num_validators);
+        let (_, cert) = setup.key_rotation(&mut ctx.rng(), 0, activation, 0);
+        let (util, runner) = Self::new_with_setup(
+            ctx,
+            setup,
+            Box::new(testonly::RandomPayload(MAX_PAYLOAD_SIZE)),
+            |cfg| cfg.key_rotation = Some(cert.clone()),
+        )
+        .await;
+        (util, runner, cert)
+    }
+
+    /// Creates a new `UTHarness` whose validator proposes to upgrade to `crate::PROTOCOL_VERSION`
+    /// at the `activation`-th block after the first one. Returns the proposed upgrade.
+    pub(crate) async fn new_with_protocol_upgrade(
+        ctx: &ctx::Ctx,
+        num_validators: usize,
+        activation: u64,
+    ) -> (UTHarness, BlockStoreRunner, validator::ProtocolUpgradeQC) {
+        let setup = validator::testonly::Setup::new(&mut ctx.rng(), num_validators);
+        let activation = validator::BlockNumber(setup.genesis.fork.first_block.0 + activation);
+        let qc = setup.protocol_upgrade(crate::PROTOCOL_VERSION, activation);
+        let (util, runner) = Self::new_with_setup(
+            ctx,
+            setup,
+            Box::new(testonly::RandomPayload(MAX_PAYLOAD_SIZE)),
+            |cfg| cfg.protocol_upgrade = Some(sync::watch::channel(Some(Arc::new(qc.clone()))).1),
+        )
+        .await;
+        (util, runner, qc)
+    }
+
+    async fn new_with_config(
+        ctx: &ctx::Ctx,
+        num_validators: usize,
+        payload_manager: Box<dyn PayloadManager>,
+        configure: impl FnOnce(&mut Config),
+    ) -> (UTHarness, BlockStoreRunner) {
+        let setup = validator::testonly::Setup::new(&mut ctx.rng(), num_validators);
+        Self::new_with_setup(ctx, setup, payload_manager, configure).await
+    }
+
+    async fn new_with_setup(
+        ctx: &ctx::Ctx,
+        setup: validator::testonly::Setup,
+        payload_manager: Box<dyn PayloadManager>,
+        configure: impl FnOnce(&mut Config),
+    ) -> (UTHarness, BlockStoreRunner) {
         let (block_store, runner) = new_store(ctx, &setup.genesis).await;
         let (send, recv) = ctx::channel::unbounded();
 
-        let cfg = Arc::new(Config {
-            secret_key: setup.keys[0].clone(),
+        let mut cfg = Config {
+            signer: Arc::new(setup.keys[0].clone()),
             block_store: block_store.clone(),
             replica_store: Box::new(in_memory::ReplicaStore::default()),
             payload_manager,
             max_payload_size: MAX_PAYLOAD_SIZE,
-        });
+            shadow_proposer: false,
+            verifier_threads: 2,
+            catch_up_threshold: None,
+            observer: false,
+            max_payload_wait: None,
+            checkpoint: None,
+            time_source: network::TimeSource::default(),
+            recorder: None,
+            key_rotation: None,
+            protocol_upgrade: None,
+            view_history: crate::ViewHistory::default(),
+        };
+        configure(&mut cfg);
+        let observer = cfg.observer;
+        let cfg = Arc::new(cfg);
         let (leader, _) = leader::StateMachine::new(ctx, cfg.clone(), send.clone());
-        let (replica, _) = replica::StateMachine::start(ctx, cfg.clone(), send.clone())
+        let (replica, replica_pipe) = replica::StateMachine::start(ctx, cfg.clone(), send.clone())
             .await
             .unwrap();
         let mut this = UTHarness {
             leader,
             replica,
+            replica_pipe,
             pipe: recv,
             keys: setup.keys.clone(),
         };
-        let _: Signed<ReplicaPrepare> = this.try_recv().unwrap();
+        if !observer {
+            let _: Signed<ReplicaPrepare> = this.try_recv().unwrap();
+        }
         (this, runner)
     }
 
@@ -93,9 +243,8 @@ impl UTHarness {
     pub(crate) async fn produce_block_after_timeout(&mut self, ctx: &ctx::Ctx) {
         let want = ReplicaPrepare {
             view: validator::View {
-                protocol_version: self.protocol_version(),
-                fork: self.genesis().fork.number,
                 number: self.replica.view.next(),
+                ..self.replica.current_view()
             },
             high_qc: self.replica.high_qc.clone(),
             high_vote: self.replica.high_vote.clone(),
@@ -113,20 +262,21 @@ impl UTHarness {
             .unwrap();
     }
 
+    /// Protocol version of the next block of the replica.
     pub(crate) fn protocol_version(&self) -> validator::ProtocolVersion {
-        crate::PROTOCOL_VERSION
+        self.replica.current_view().protocol_version
     }
 
     pub(crate) fn incompatible_protocol_version(&self) -> validator::ProtocolVersion {
-        validator::ProtocolVersion(self.protocol_version().0 + 1)
+        validator::ProtocolVersion(crate::PROTOCOL_VERSION.0 + 1)
     }
 
     pub(crate) fn owner_key(&self) -> &SecretKey {
-        &self.replica.config.secret_key
+        &self.keys[0]
     }
 
     pub(crate) fn sign<V: Variant<validator::Msg>>(&self, msg: V) -> Signed<V> {
-        self.replica.config.secret_key.sign_msg(msg)
+        self.owner_key().sign_msg(msg)
     }
 
     pub(crate) fn set_owner_as_view_leader(&mut self) {
@@ -137,6 +287,15 @@ impl UTHarness {
         self.replica.view = view;
     }
 
+    /// Moves the replica to the closest view followed by a view led by the owner.
+    pub(crate) fn set_owner_as_next_view_leader(&mut self) {
+        let mut view = self.replica.view;
+        while self.view_leader(view.next()) != self.owner_key().public() {
+            view = view.next();
+        }
+        self.replica.view = view;
+    }
+
     pub(crate) fn set_view(&mut self, view: ViewNumber) {
         self.set_replica_view(view);
         self.set_leader_view(view);
@@ -151,11 +310,7 @@ impl UTHarness {
     }
 
     pub(crate) fn replica_view(&self) -> validator::View {
-        validator::View {
-            protocol_version: self.protocol_version(),
-            fork: self.genesis().fork.number,
-            number: self.replica.view,
-        }
+        self.replica.current_view()
     }
 
     pub(crate) fn new_replica_prepare(&mut self) -> ReplicaPrepare {
@@ -167,6 +322,13 @@ impl UTHarness {
         }
     }
 
+    pub(crate) fn new_replica_timeout(&mut self) -> ReplicaTimeout {
+        self.set_owner_as_next_view_leader();
+        ReplicaTimeout {
+            view: self.replica_view(),
+        }
+    }
+
     pub(crate) fn new_current_replica_commit(&self) -> ReplicaCommit {
         ReplicaCommit {
             view: self.replica_view(),
@@ -210,6 +372,15 @@ impl UTHarness {
         Ok(self.try_recv().unwrap())
     }
 
+    pub(crate) async fn process_leader_timeout(
+        &mut self,
+        ctx: &ctx::Ctx,
+        msg: Signed<LeaderTimeout>,
+    ) -> Result<Option<Signed<ReplicaPrepare>>, leader_timeout::Error> {
+        self.replica.process_leader_timeout(ctx, msg).await?;
+        Ok(self.try_recv())
+    }
+
     #[allow(clippy::result_large_err)]
     pub(crate) async fn process_replica_prepare(
         &mut self,
@@ -219,11 +390,12 @@ impl UTHarness {
         let prepare_qc = self.leader.prepare_qc.subscribe();
         self.leader.process_replica_prepare(ctx, msg).await?;
         if prepare_qc.has_changed().unwrap() {
-            let prepare_qc = prepare_qc.borrow().clone().unwrap();
+            let (prepare_qc, timeout_qc) = prepare_qc.borrow().clone().unwrap();
             leader::StateMachine::propose(
                 ctx,
                 &self.leader.config,
                 prepare_qc,
+                timeout_qc,
                 &self.leader.outbound_pipe,
             )
             .await
@@ -256,7 +428,7 @@ impl UTHarness {
         ctx: &ctx::Ctx,
         msg: Signed<ReplicaCommit>,
     ) -> Result<Option<Signed<LeaderCommit>>, replica_commit::Error> {
-        self.leader.process_replica_commit(ctx, msg)?;
+        self.leader.process_replica_commit(ctx, msg).await?;
         Ok(self.try_recv())
     }
 
@@ -268,7 +440,8 @@ impl UTHarness {
         for (i, key) in self.keys.iter().enumerate() {
             let res = self
                 .leader
-                .process_replica_commit(ctx, key.sign_msg(msg.clone()));
+                .process_replica_commit(ctx, key.sign_msg(msg.clone()))
+                .await;
             let want_threshold = self.genesis().validators.threshold();
             match (i + 1).cmp(&want_threshold) {
                 Ordering::Equal => res.unwrap(),
@@ -279,7 +452,21 @@ impl UTHarness {
         self.try_recv().unwrap()
     }
 
-    fn try_recv<V: Variant<validator::Msg>>(&mut self) -> Option<Signed<V>> {
+    pub(crate) async fn process_timeout_vote(
+        &mut self,
+        ctx: &ctx::Ctx,
+        msg: Signed<ReplicaTimeout>,
+    ) -> Result<Option<Signed<LeaderTimeout>>, replica_timeout::Error> {
+        self.leader.process_replica_timeout(ctx, msg).await?;
+        Ok(self.try_recv())
+    }
+
+    /// Drops all the messages sent by the replica and the leader so far.
+    pub(crate) fn clear_outbound(&mut self) {
+        while self.pipe.try_recv().is_some() {}
+    }
+
+    pub(crate) fn try_recv<V: Variant<validator::Msg>>(&mut self) -> Option<Signed<V>> {
         self.pipe.try_recv().map(|message| match message {
             OutputMessage::Network(network::io::ConsensusInputMessage { message, .. }) => {
                 message.cast().unwrap()
@@ -303,20 +490,41 @@ impl UTHarness {
         self.genesis().validators.view_leader(view)
     }
 
-    pub(crate) fn genesis(&self) -> &validator::Genesis {
+    pub(crate) fn genesis(&self) -> Arc<validator::Genesis> {
         self.replica.config.genesis()
     }
 
     pub(crate) fn new_commit_qc(&self, mutate_fn: impl FnOnce(&mut ReplicaCommit)) -> CommitQC {
         let mut msg = self.new_current_replica_commit();
         mutate_fn(&mut msg);
-        let mut qc = CommitQC::new(msg, self.genesis());
+        let mut qc = CommitQC::new(msg, &self.genesis());
         for key in &self.keys {
-            qc.add(&key.sign_msg(qc.message.clone()), self.genesis());
+            qc.add(&key.sign_msg(qc.message.clone()), &self.genesis());
         }
         qc
     }
 
+    /// TimeoutQC for the current view of the replica, signed by all the validators.
+    pub(crate) fn new_timeout_qc(&self) -> TimeoutQC {
+        self.new_timeout_qc_for(self.replica_view())
+    }
+
+    /// TimeoutQC for the given view, signed by all the validators.
+    pub(crate) fn new_timeout_qc_for(&self, view: validator::View) -> TimeoutQC {
+        let msg = ReplicaTimeout { view };
+        let mut qc = TimeoutQC::new(msg, &self.genesis());
+        for key in &self.keys {
+            qc.add(&key.sign_msg(qc.message.clone()), &self.genesis());
+        }
+        qc
+    }
+
+    /// Key of the leader of the view following the current view of the replica.
+    pub(crate) fn next_view_leader_key(&self) -> &SecretKey {
+        let leader = self.view_leader(self.replica.view.next());
+        self.keys.iter().find(|k| k.public() == leader).unwrap()
+    }
+
     pub(crate) fn new_prepare_qc(
         &mut self,
         mutate_fn: impl FnOnce(&mut ReplicaPrepare),
@@ -325,7 +533,7 @@ impl UTHarness {
         mutate_fn(&mut msg);
         let mut qc = PrepareQC::new(msg.view.clone());
         for key in &self.keys {
-            qc.add(&key.sign_msg(msg.clone()), self.genesis());
+            qc.add(&key.sign_msg(msg.clone()), &self.genesis());
         }
         qc
     }
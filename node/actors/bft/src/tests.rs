@@ -1,6 +1,18 @@
-use crate::testonly::{ut_harness::UTHarness, Behavior, Network, Test};
-use zksync_concurrency::{ctx, scope, time};
+use crate::{
+    io, record,
+    testonly::{self, ut_harness::UTHarness, Behavior, Network, Test},
+    Config,
+};
+use rand::Rng as _;
+use std::sync::Arc;
+use zksync_concurrency::{ctx, oneshot, scope, sync, time};
+use zksync_consensus_network::{self as network, io::ConsensusReq};
 use zksync_consensus_roles::validator;
+use zksync_consensus_storage::{
+    self as storage,
+    testonly::{in_memory, new_store},
+};
+use zksync_consensus_utils::pipe;
 
 async fn run_test(behavior: Behavior, network: Network) {
     let _guard = zksync_concurrency::testonly::set_timeout(time::Duration::seconds(20));
@@ -16,12 +28,56 @@ async fn run_test(behavior: Behavior, network: Network) {
         network,
         nodes,
         blocks_to_finalize: 15,
+        protocol_version: validator::ProtocolVersion::EARLIEST,
+        protocol_upgrade_at: None,
     }
     .run(ctx)
     .await
     .unwrap()
 }
 
+/// Testing that the chains of every supported protocol version are finalized,
+/// including the chains hashed in separate domains with keccak256 and with blake2b-256.
+#[tokio::test(flavor = "multi_thread")]
+async fn honest_network_per_protocol_version() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let _guard = zksync_concurrency::testonly::set_timeout(time::Duration::seconds(60));
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    for version in 0..=crate::PROTOCOL_VERSION.0 {
+        Test {
+            network: Network::Real,
+            nodes: vec![Behavior::Honest; 4],
+            blocks_to_finalize: 5,
+            protocol_version: validator::ProtocolVersion(version),
+            protocol_upgrade_at: None,
+        }
+        .run(ctx)
+        .await
+        .unwrap();
+    }
+}
+
+/// Testing that the consensus keeps finalizing blocks across the activation height
+/// of a protocol upgrade, with the views stamped with the version of the block at stake.
+#[tokio::test(flavor = "multi_thread")]
+async fn honest_network_across_protocol_upgrade() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let _guard = zksync_concurrency::testonly::set_timeout(time::Duration::seconds(30));
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    for network in [Network::Real, Network::Mock] {
+        Test {
+            network,
+            nodes: vec![Behavior::Honest; 4],
+            blocks_to_finalize: 8,
+            protocol_version: validator::ProtocolVersion::EARLIEST,
+            protocol_upgrade_at: Some(4),
+        }
+        .run(ctx)
+        .await
+        .unwrap();
+    }
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn honest_mock_network() {
     run_test(Behavior::Honest, Network::Mock).await
@@ -187,8 +243,309 @@ async fn non_proposing_leader() {
         network: Network::Real,
         nodes: vec![Behavior::Honest, Behavior::HonestNotProposing],
         blocks_to_finalize: 10,
+        protocol_version: validator::ProtocolVersion::EARLIEST,
+        protocol_upgrade_at: None,
     }
     .run(ctx)
     .await
     .unwrap()
 }
+
+#[test]
+fn verifier_bisect() {
+    let items: Vec<usize> = (0..64).collect();
+    for invalid in [vec![], vec![17], vec![0, 63], vec![5, 6, 40]] {
+        let calls = std::cell::Cell::new(0);
+        let mut valid = vec![];
+        crate::verifier::bisect(&items, false, &mut valid, &|batch: &[usize]| {
+            calls.set(calls.get() + 1);
+            batch.iter().all(|i| !invalid.contains(i))
+        });
+        let want: Vec<_> = items
+            .iter()
+            .copied()
+            .filter(|i| !invalid.contains(i))
+            .collect();
+        assert_eq!(want, valid);
+        // Every invalid item costs at most 2 batch verifications per level.
+        assert!(calls.get() <= 1 + 2 * invalid.len() * 6);
+    }
+}
+
+#[tokio::test]
+async fn verifier_prefetch() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 1);
+    setup.push_blocks(rng, 1);
+    let key = &setup.keys[0];
+    let verifier = crate::verifier::Verifier::new(3);
+    let valid: Vec<validator::Signed<validator::ConsensusMsg>> = (0..10)
+        .map(|_| key.sign_msg(validator::ConsensusMsg::ReplicaCommit(rng.gen())))
+        .collect();
+    let mut invalid = key.sign_msg(validator::ConsensusMsg::ReplicaCommit(rng.gen()));
+    invalid.msg = validator::ConsensusMsg::ReplicaCommit(rng.gen());
+    // Prepare votes carrying a valid and an invalid high QC.
+    let valid_qc = setup.blocks[0].justification.clone();
+    let invalid_qc: validator::CommitQC = rng.gen();
+    let prepares: Vec<_> = [&valid_qc, &valid_qc, &invalid_qc]
+        .into_iter()
+        .map(|qc| {
+            key.sign_msg(validator::ConsensusMsg::ReplicaPrepare(
+                validator::ReplicaPrepare {
+                    view: validator::View {
+                        protocol_version: setup.genesis.protocol_version,
+                        fork: setup.genesis.fork.number,
+                        number: qc.view().number.next(),
+                    },
+                    high_vote: None,
+                    high_qc: Some(qc.clone()),
+                },
+            ))
+        })
+        .collect();
+    let mut msgs: Vec<_> = valid.iter().chain(&prepares).collect();
+    msgs.push(&invalid);
+    verifier.prefetch(ctx, &setup.genesis, &msgs).await.unwrap();
+    for msg in valid.iter().chain(&prepares) {
+        verifier.verify(msg).unwrap();
+    }
+    assert!(verifier.verify(&invalid).is_err());
+    verifier.verify_high_qc(&setup.genesis, &valid_qc).unwrap();
+    assert!(verifier
+        .verify_high_qc(&setup.genesis, &invalid_qc)
+        .is_err());
+}
+
+#[tokio::test]
+async fn verifier_verify_with() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 1);
+    setup.push_blocks(rng, 1);
+    let verifier = crate::verifier::Verifier::new(2);
+    let valid = setup.keys[0].sign_msg(validator::LeaderCommit {
+        justification: setup.blocks[0].justification.clone(),
+    });
+    let (sig, msg) = verifier
+        .verify_with(ctx, &valid, || valid.msg.verify(&setup.genesis))
+        .await
+        .unwrap();
+    sig.unwrap();
+    msg.unwrap();
+
+    // Invalid signature of a valid QC.
+    let mut invalid = valid.clone();
+    invalid.sig = setup.keys[0]
+        .sign_msg(rng.gen::<validator::ReplicaCommit>())
+        .sig;
+    let (sig, msg) = verifier
+        .verify_with(ctx, &invalid, || invalid.msg.verify(&setup.genesis))
+        .await
+        .unwrap();
+    assert!(sig.is_err());
+    msg.unwrap();
+
+    // Valid signature of an invalid QC.
+    let invalid = setup.keys[0].sign_msg(validator::LeaderCommit {
+        justification: rng.gen(),
+    });
+    let (sig, msg) = verifier
+        .verify_with(ctx, &invalid, || invalid.msg.verify(&setup.genesis))
+        .await
+        .unwrap();
+    sig.unwrap();
+    assert!(msg.is_err());
+}
+
+fn record_test_config(
+    setup: &validator::testonly::Setup,
+    block_store: Arc<storage::BlockStore>,
+    recorder: Option<Arc<record::Recorder>>,
+) -> Config {
+    Config {
+        signer: Arc::new(setup.keys[0].clone()),
+        block_store,
+        replica_store: Box::new(in_memory::ReplicaStore::default()),
+        payload_manager: Box::new(testonly::RandomPayload(1000)),
+        max_payload_size: 1000,
+        shadow_proposer: false,
+        verifier_threads: 2,
+        catch_up_threshold: None,
+        observer: false,
+        max_payload_wait: None,
+        checkpoint: None,
+        time_source: network::TimeSource::default(),
+        recorder,
+        key_rotation: None,
+        protocol_upgrade: None,
+        view_history: crate::ViewHistory::default(),
+    }
+}
+
+#[tokio::test]
+async fn shadow_proposer_opt_in() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let setup = validator::testonly::Setup::new(rng, 1);
+    let (store, _runner) = new_store(ctx, &setup.genesis).await;
+
+    let mut cfg = record_test_config(&setup, store, None);
+    assert!(!cfg.shadow_proposer_enabled());
+    cfg.shadow_proposer = true;
+    assert!(cfg.shadow_proposer_enabled());
+    // Payload managers which don't opt in are never called by the shadow proposer.
+    cfg.payload_manager = Box::new(testonly::PendingPayload);
+    assert!(!cfg.shadow_proposer_enabled());
+}
+
+#[tokio::test]
+async fn inbound_priority_of_far_future_views() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 1);
+    setup.push_blocks(rng, 1);
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let priority = crate::inbound_priority(&store);
+        let mut req = |view| {
+            let mut m: validator::ReplicaTimeout = rng.gen();
+            m.view.number = validator::ViewNumber(view);
+            ConsensusReq {
+                msg: setup.keys[0].sign_msg(validator::ConsensusMsg::ReplicaTimeout(m)),
+                trace: None,
+                ack: oneshot::channel().0,
+            }
+        };
+        let ahead = crate::INBOUND_VIEWS_AHEAD;
+
+        // Messages for the far-future views rank the lowest.
+        assert_eq!(ahead, priority(&req(ahead)));
+        assert_eq!(0, priority(&req(ahead + 1)));
+        assert_eq!(0, priority(&req(u64::MAX)));
+
+        // The window moves with the committed blocks.
+        store.queue_block(ctx, setup.blocks[0].clone()).await?;
+        let committed = setup.blocks[0].justification.view().number.0;
+        assert_eq!(committed + ahead, priority(&req(committed + ahead)));
+        assert_eq!(0, priority(&req(committed + ahead + 1)));
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+/// Waits until the blocks `[first,next)` are stored and returns them.
+async fn wait_for_blocks(
+    ctx: &ctx::Ctx,
+    store: &storage::BlockStore,
+    first: validator::BlockNumber,
+    next: validator::BlockNumber,
+) -> ctx::Result<Vec<validator::FinalBlock>> {
+    sync::wait_for(ctx, &mut store.subscribe(), |s| s.next() >= next).await?;
+    let mut blocks = vec![];
+    for n in (first.0..next.0).map(validator::BlockNumber) {
+        blocks.push(store.block(ctx, n).await?.unwrap());
+    }
+    Ok(blocks)
+}
+
+/// Testing that a fresh actor fed with the messages recorded by a node
+/// finalizes the same blocks.
+#[tokio::test]
+async fn record_and_replay() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let _guard = zksync_concurrency::testonly::set_timeout(time::Duration::seconds(30));
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let setup = validator::testonly::Setup::new(rng, 1);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("trace");
+    let first = setup.genesis.fork.first_block;
+    let next = validator::BlockNumber(first.0 + 5);
+
+    // Single validator sends all the messages to itself.
+    let want = scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let recorder = Arc::new(record::Recorder::open(path.clone())?);
+        let (actor_pipe, pipe) = pipe::new();
+        s.spawn_bg(record_test_config(&setup, store.clone(), Some(recorder)).run(ctx, actor_pipe));
+        s.spawn_bg(async {
+            let mut pipe = pipe;
+            loop {
+                let io::OutputMessage::Network(msg) = pipe.recv(ctx).await?;
+                pipe.send(io::InputMessage::Network(ConsensusReq {
+                    msg: msg.message,
+                    trace: None,
+                    ack: oneshot::channel().0,
+                }));
+            }
+        });
+        Ok(wait_for_blocks(ctx, &store, first, next).await?)
+    })
+    .await
+    .unwrap();
+
+    // The replayed actor is not connected to itself: it receives only the recorded messages.
+    let trace = record::read(&path).unwrap();
+    assert!(trace.iter().any(|e| e.direction == record::Direction::Sent));
+    let got = scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let (actor_pipe, pipe) = pipe::new();
+        s.spawn_bg(record_test_config(&setup, store.clone(), None).run(ctx, actor_pipe));
+        s.spawn_bg(async {
+            let pipe = pipe;
+            record::replay(ctx, &trace, &pipe.send).await?;
+            ctx.canceled().await;
+            Ok(())
+        });
+        Ok(wait_for_blocks(ctx, &store, first, next).await?)
+    })
+    .await
+    .unwrap();
+    assert_eq!(want, got);
+}
+
+/// Testing that the trace keeps the events of the previous runs
+/// and cuts off a frame truncated by a crash.
+#[tokio::test]
+async fn record_appends_across_runs() {
+    zksync_concurrency::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let setup = validator::testonly::Setup::new(rng, 1);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("trace");
+    let events: Vec<_> = (0..4)
+        .map(|i| record::Event {
+            time: time::UNIX_EPOCH + time::Duration::seconds(i),
+            direction: record::Direction::Received,
+            msg: setup.keys[0].sign_msg(validator::ConsensusMsg::ReplicaTimeout(rng.gen())),
+        })
+        .collect();
+
+    // Every run records 2 events. The events queued before the cancellation are still written.
+    for run in events.chunks(2) {
+        let recorder = record::Recorder::open(path.clone()).unwrap();
+        for e in run {
+            recorder.record(e.clone());
+        }
+        let ctx = &ctx.with_timeout(time::Duration::ZERO);
+        assert!(recorder.run(ctx).await.is_err());
+        // Simulate a crash in the middle of writing a frame.
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        std::io::Write::write_all(&mut file, &[7, 0]).unwrap();
+    }
+    assert_eq!(events, record::read(&path).unwrap());
+}
@@ -0,0 +1,258 @@
+//! Pool verifying the signatures and the QCs of the consensus messages in parallel.
+//! The leader receives a vote from every validator in every phase, so verifying the
+//! signatures one by one on the state machine task becomes the bottleneck for large
+//! validator sets. Instead, the pending messages are verified in batches on multiple
+//! blocking tasks and the verified signatures are cached until the state machine
+//! processes the messages. Each task checks its chunk with a single batch verification
+//! and bisects it only if the chunk contains an invalid signature, so that an invalid
+//! signature costs a logarithmic number of extra batch verifications. Signatures of
+//! the schemes which don't support batching are verified one by one instead. The distinct
+//! high QCs carried by the prepare votes are verified on the pool as well, in batches
+//! bisected the same way, so that each of them is verified once per batch.
+//!
+//! The replica receives a single proposal per phase, which is verified with `verify_with()`:
+//! the signature and the QCs carried by the proposal are verified on separate blocking tasks.
+//! While the replica verifies the payload of a proposal, the messages waiting in its inbound
+//! pipe are prefetched.
+use crate::metrics;
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Mutex,
+};
+use zksync_concurrency::{ctx, metrics::LatencyHistogramExt as _, scope};
+use zksync_consensus_roles::validator;
+use zksync_consensus_utils::enum_util::Variant;
+
+/// Verifies `items` with `verify_batch` and bisects the failing batches down to the invalid
+/// items, so that each invalid item costs a logarithmic number of extra batch verifications.
+/// `known_invalid` skips verifying a batch which is already known to fail.
+/// The valid items are appended to `valid`.
+pub(crate) fn bisect<T: Clone>(
+    items: &[T],
+    known_invalid: bool,
+    valid: &mut Vec<T>,
+    verify_batch: &impl Fn(&[T]) -> bool,
+) {
+    if items.is_empty() {
+        return;
+    }
+    if !known_invalid && verify_batch(items) {
+        valid.extend_from_slice(items);
+        return;
+    }
+    if items.len() == 1 {
+        return;
+    }
+    let (left, right) = items.split_at(items.len() / 2);
+    let before = valid.len();
+    bisect(left, false, valid, verify_batch);
+    // If the left half is valid, the invalid items are in the right half.
+    bisect(
+        right,
+        valid.len() - before == left.len(),
+        valid,
+        verify_batch,
+    );
+}
+
+/// Signatures and QCs verified by `Verifier::verify_batch()`.
+#[derive(Debug, Default)]
+pub(crate) struct Verified {
+    /// Valid signatures, together with the signer and the message hash.
+    sigs: HashMap<validator::Signature, (validator::PublicKey, validator::MsgHash)>,
+    /// Valid high QCs of the prepare votes.
+    qcs: BTreeSet<validator::CommitQC>,
+}
+
+/// Signature verification pool.
+#[derive(Debug)]
+pub(crate) struct Verifier {
+    /// Max number of blocking tasks verifying a batch.
+    threads: usize,
+    /// Signatures which have been already verified, together with the signer and the message hash.
+    verified: Mutex<HashMap<validator::Signature, (validator::PublicKey, validator::MsgHash)>>,
+    /// High QCs which have been already verified.
+    qcs: Mutex<BTreeSet<validator::CommitQC>>,
+}
+
+impl Verifier {
+    /// Constructs a pool with `threads` verification tasks.
+    pub(crate) fn new(threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+            verified: Mutex::default(),
+            qcs: Mutex::default(),
+        }
+    }
+
+    /// Verifies the signatures and the high QCs of `msgs` in parallel.
+    /// Valid signatures and QCs are cached, so that the subsequent `verify()` and
+    /// `verify_high_qc()` calls are cheap. Invalid ones are not cached, they will be
+    /// reported by `verify()` and `verify_high_qc()`.
+    pub(crate) async fn prefetch(
+        &self,
+        ctx: &ctx::Ctx,
+        genesis: &validator::Genesis,
+        msgs: &[&validator::Signed<validator::ConsensusMsg>],
+    ) -> ctx::OrCanceled<()> {
+        let verified = self.verify_batch(ctx, genesis, msgs).await?;
+        self.verified.lock().unwrap().extend(verified.sigs);
+        self.qcs.lock().unwrap().extend(verified.qcs);
+        Ok(())
+    }
+
+    /// Verifies the signatures and the high QCs of `msgs` in parallel, without caching them.
+    /// The result can be loaded into the cache later with `load()`, which allows
+    /// verifying the next batch while the previous one is still being processed.
+    pub(crate) async fn verify_batch(
+        &self,
+        ctx: &ctx::Ctx,
+        genesis: &validator::Genesis,
+        msgs: &[&validator::Signed<validator::ConsensusMsg>],
+    ) -> ctx::OrCanceled<Verified> {
+        if msgs.is_empty() {
+            return Ok(Verified::default());
+        }
+        let start = ctx.now();
+        let chunk = msgs.len().div_ceil(self.threads);
+        // Replicas mostly send the same high QC, so every distinct QC is verified once.
+        let qcs: Vec<_> = msgs
+            .iter()
+            .filter_map(|m| match &m.msg {
+                validator::ConsensusMsg::ReplicaPrepare(m) => m.high_qc.as_ref(),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let verified = Mutex::new(Verified::default());
+        let res: ctx::OrCanceled<()> = scope::run!(ctx, |_, s| async {
+            if !qcs.is_empty() {
+                for qcs in qcs.chunks(qcs.len().div_ceil(self.threads)) {
+                    s.spawn_blocking(|| {
+                        let mut valid = vec![];
+                        bisect(qcs, false, &mut valid, &|qcs: &[&validator::CommitQC]| {
+                            validator::CommitQC::verify_batch(genesis, qcs.iter().copied()).is_ok()
+                        });
+                        if valid.len() < qcs.len() {
+                            metrics::METRICS.verify_batch_bisections.inc();
+                        }
+                        verified
+                            .lock()
+                            .unwrap()
+                            .qcs
+                            .extend(valid.into_iter().cloned());
+                        Ok(())
+                    });
+                }
+            }
+            for msgs in msgs.chunks(chunk) {
+                s.spawn_blocking(|| {
+                    // Signatures which don't support batching are verified one by one,
+                    // so that bisecting a failing batch doesn't verify them again.
+                    let (batch, single): (Vec<_>, Vec<_>) = msgs
+                        .iter()
+                        .map(|m| (m.msg.clone().insert().hash(), *m))
+                        .partition(|(_, m)| m.sig.scheme().supports_batching());
+                    let mut valid: Vec<_> = single
+                        .into_iter()
+                        .filter(|(h, m)| m.sig.verify_hash(h, &m.key).is_ok())
+                        .collect();
+                    let want = valid.len() + batch.len();
+                    bisect(&batch, false, &mut valid, &|batch: &[_]| {
+                        validator::Signature::verify_batch(
+                            batch.iter().map(|(h, m)| (h, &m.key, &m.sig)),
+                        )
+                        .is_ok()
+                    });
+                    if valid.len() < want {
+                        metrics::METRICS.verify_batch_bisections.inc();
+                    }
+                    let valid = valid
+                        .into_iter()
+                        .map(|(h, m)| (m.sig.clone(), (m.key.clone(), h)));
+                    verified.lock().unwrap().sigs.extend(valid);
+                    Ok(())
+                });
+            }
+            Ok(())
+        })
+        .await;
+        res?;
+        metrics::METRICS.verify_batch_size.observe(msgs.len());
+        metrics::METRICS
+            .verify_batch_latency
+            .observe_latency(ctx.now() - start);
+        Ok(verified.into_inner().unwrap())
+    }
+
+    /// Replaces the cached signatures and QCs with the result of `verify_batch()`.
+    pub(crate) fn load(&self, verified: Verified) {
+        *self.verified.lock().unwrap() = verified.sigs;
+        *self.qcs.lock().unwrap() = verified.qcs;
+    }
+
+    /// Verifies the signature of the message, using the cached result if available.
+    pub(crate) fn verify<V: Variant<validator::Msg> + Clone>(
+        &self,
+        msg: &validator::Signed<V>,
+    ) -> Result<(), validator::Error> {
+        if let Some((key, hash)) = self.verified.lock().unwrap().remove(&msg.sig) {
+            if key == msg.key && hash == msg.msg.clone().insert().hash() {
+                return Ok(());
+            }
+        }
+        msg.verify()
+    }
+
+    /// Verifies the high QC of a prepare vote, using the cached result if available.
+    /// Unlike the signatures, the QCs stay cached until `clear()`, since they are shared
+    /// by the votes of the batch.
+    pub(crate) fn verify_high_qc(
+        &self,
+        genesis: &validator::Genesis,
+        qc: &validator::CommitQC,
+    ) -> Result<(), validator::CommitQCVerifyError> {
+        if self.qcs.lock().unwrap().contains(qc) {
+            return Ok(());
+        }
+        qc.verify(genesis)
+    }
+
+    /// Verifies the signature of `msg` (using the cached result if available) and runs `verify_msg`
+    /// (which verifies the message itself, including the QCs it carries) on separate blocking tasks in parallel.
+    pub(crate) async fn verify_with<V, E>(
+        &self,
+        ctx: &ctx::Ctx,
+        msg: &validator::Signed<V>,
+        verify_msg: impl Send + FnOnce() -> Result<(), E>,
+    ) -> ctx::OrCanceled<(Result<(), validator::Error>, Result<(), E>)>
+    where
+        V: Variant<validator::Msg> + Clone + Sync,
+        E: 'static + Send,
+    {
+        let start = ctx.now();
+        let res = scope::run!(ctx, |ctx, s| async {
+            let sig = s.spawn_blocking(|| Ok(self.verify(msg)));
+            let msg = s.spawn_blocking(|| Ok(verify_msg()));
+            Ok((sig.join(ctx).await?, msg.join(ctx).await?))
+        })
+        .await;
+        metrics::METRICS
+            .verify_msg_latency
+            .observe_latency(ctx.now() - start);
+        res
+    }
+
+    /// Checks whether the signature of `msg` is cached.
+    #[cfg(test)]
+    pub(crate) fn is_cached<V: Variant<validator::Msg>>(&self, msg: &validator::Signed<V>) -> bool {
+        self.verified.lock().unwrap().contains_key(&msg.sig)
+    }
+
+    /// Drops the cached signatures and QCs which haven't been used.
+    pub(crate) fn clear(&self) {
+        self.verified.lock().unwrap().clear();
+        self.qcs.lock().unwrap().clear();
+    }
+}
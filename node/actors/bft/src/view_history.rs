@@ -0,0 +1,76 @@
+//! History of the recent view transitions of the replica.
+//! Together with the pacemaker metrics, it allows operators to pinpoint
+//! the validators which stall the consensus.
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+use vise::{EncodeLabelSet, EncodeLabelValue};
+use zksync_concurrency::time;
+use zksync_consensus_roles::validator;
+
+/// Max number of the view transitions kept in the history.
+pub const VIEW_HISTORY_SIZE: usize = 256;
+
+/// Reason for which the replica has left a view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EncodeLabelValue, EncodeLabelSet)]
+#[metrics(label = "reason", rename_all = "snake_case")]
+pub enum ViewChangeReason {
+    /// The view has timed out.
+    Timeout,
+    /// A CommitQC for the view has been received.
+    CommitQc,
+    /// A TimeoutQC for the view has been received.
+    TimeoutQc,
+    /// The replica has left the catch-up mode.
+    CatchUp,
+}
+
+impl ViewChangeReason {
+    /// Name of the reason.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Timeout => "timeout",
+            Self::CommitQc => "commit_qc",
+            Self::TimeoutQc => "timeout_qc",
+            Self::CatchUp => "catch_up",
+        }
+    }
+}
+
+/// Transition of the replica from a view to the next one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ViewTransition {
+    /// View which has ended.
+    pub view: validator::ViewNumber,
+    /// Leader of the view.
+    pub leader: validator::PublicKey,
+    /// Reason for which the view has ended.
+    pub reason: ViewChangeReason,
+    /// Time at which the view has ended.
+    pub ended: time::Utc,
+    /// Time the replica has spent in the view.
+    pub duration: time::Duration,
+}
+
+/// History of the view transitions of a replica, oldest first.
+/// Clones share the history, so that it can be read outside of the consensus actor
+/// (e.g. by the RPC server) and survives the restarts of the actor.
+#[derive(Debug, Clone, Default)]
+pub struct ViewHistory(Arc<Mutex<VecDeque<ViewTransition>>>);
+
+impl ViewHistory {
+    /// Appends a transition to the history, dropping the oldest one if the history is full.
+    pub(crate) fn record(&self, transition: ViewTransition) {
+        let mut history = self.0.lock().unwrap();
+        if history.len() == VIEW_HISTORY_SIZE {
+            history.pop_front();
+        }
+        history.push_back(transition);
+    }
+
+    /// Returns the last (at most `VIEW_HISTORY_SIZE`) view transitions, oldest first.
+    pub fn get(&self) -> Vec<ViewTransition> {
+        self.0.lock().unwrap().iter().cloned().collect()
+    }
+}
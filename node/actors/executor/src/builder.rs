@@ -0,0 +1,188 @@
+//! Builder of [`Executor`], which checks the combinations of its parts at build time.
+use crate::{Config, Executor, LogFilter, Validator};
+use std::sync::Arc;
+use zksync_concurrency::sync;
+use zksync_consensus_bft as bft;
+use zksync_consensus_network as network;
+use zksync_consensus_roles::validator;
+use zksync_consensus_storage::{BlockStore, SyncProgressStore};
+
+/// Invalid combination of the parts of an [`Executor`].
+#[derive(Debug, thiserror::Error)]
+pub enum BuildError {
+    /// Validator key is not in the validator committee of the genesis.
+    #[error("validator {0:?} doesn't belong to the consensus")]
+    NotInCommittee(validator::PublicKey),
+    /// Node configured as a sentry is also configured as a validator.
+    #[error("sentry cannot be a validator; the validator runs on its backend")]
+    ValidatorOnSentry,
+    /// Node configured as a sentry backend is not configured as a validator.
+    #[error("sentry backend has to be a validator")]
+    BackendWithoutValidator,
+    /// Config field has an invalid value.
+    #[error("{field}: {reason}")]
+    InvalidConfig {
+        /// Name of the field.
+        field: &'static str,
+        /// Why the value is invalid.
+        reason: &'static str,
+    },
+}
+
+/// Builder of [`Executor`], see [`Executor::builder`].
+#[derive(Debug)]
+pub struct ExecutorBuilder {
+    config: Config,
+    block_store: Arc<BlockStore>,
+    sync_progress_store: Option<Arc<dyn SyncProgressStore>>,
+    validator: Option<Validator>,
+    reload: Option<sync::watch::Receiver<network::ReloadableConfig>>,
+    topics: network::Topics,
+    log_filter: Option<LogFilter>,
+    #[cfg(feature = "grpc")]
+    grpc_addr: Option<std::net::SocketAddr>,
+    #[cfg(feature = "chaos")]
+    chaos: Option<network::chaos::Config>,
+}
+
+impl Executor {
+    /// Starts building an executor of a node with the given config and block store.
+    /// The node is a non-validator, unless [`ExecutorBuilder::validator`] is set.
+    pub fn builder(config: Config, block_store: Arc<BlockStore>) -> ExecutorBuilder {
+        ExecutorBuilder {
+            config,
+            block_store,
+            sync_progress_store: None,
+            validator: None,
+            reload: None,
+            topics: network::Topics::default(),
+            log_filter: None,
+            #[cfg(feature = "grpc")]
+            grpc_addr: None,
+            #[cfg(feature = "chaos")]
+            chaos: None,
+        }
+    }
+}
+
+impl ExecutorBuilder {
+    /// Runs the node as a validator: signs with the validator key, persists the replica
+    /// state in the replica store and proposes the payloads of the payload manager.
+    pub fn validator(mut self, validator: Validator) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+
+    /// Store in which the progress of the block syncing is persisted,
+    /// so that a restarted node resumes catching up right away.
+    pub fn sync_progress_store(mut self, store: Arc<dyn SyncProgressStore>) -> Self {
+        self.sync_progress_store = Some(store);
+        self
+    }
+
+    /// Updates of the reloadable part of the config, applied without restarting the node.
+    /// The initial value is taken from the config, the value in the receiver is ignored
+    /// until it changes.
+    pub fn reload(mut self, reload: sync::watch::Receiver<network::ReloadableConfig>) -> Self {
+        self.reload = Some(reload);
+        self
+    }
+
+    /// Application-defined topics gossiped to the peers, see [`network::Topics::register`].
+    pub fn topics(mut self, topics: network::Topics) -> Self {
+        self.topics = topics;
+        self
+    }
+
+    /// Filter of the node logs, exposed via [`Executor::log_filter`].
+    pub fn log_filter(mut self, log_filter: LogFilter) -> Self {
+        self.log_filter = Some(log_filter);
+        self
+    }
+
+    /// Serves the gRPC stream of the finalized blocks (see [`crate::grpc`]) on `addr`.
+    #[cfg(feature = "grpc")]
+    pub fn grpc(mut self, addr: std::net::SocketAddr) -> Self {
+        self.grpc_addr = Some(addr);
+        self
+    }
+
+    /// Injects the faults into the frames sent by the node.
+    /// NEVER use it in production, see [`network::chaos`].
+    #[cfg(feature = "chaos")]
+    pub fn chaos(mut self, cfg: network::chaos::Config) -> Self {
+        self.chaos = Some(cfg);
+        self
+    }
+
+    /// Checks the combination of the parts and builds the executor.
+    pub fn build(self) -> Result<Executor, BuildError> {
+        let invalid = |field, reason| BuildError::InvalidConfig { field, reason };
+        if self.config.sync_blocks_cross_check_peers == 0 {
+            return Err(invalid(
+                "sync_blocks_cross_check_peers",
+                "has to be positive",
+            ));
+        }
+        if self.config.sync_blocks_sample_chunks > network::io::MAX_PAYLOAD_SAMPLES {
+            return Err(invalid(
+                "sync_blocks_sample_chunks",
+                "exceeds the max number of payload samples",
+            ));
+        }
+        if let Some(quorum) = self.config.sync_blocks_genesis_check_quorum {
+            if quorum == 0 || quorum > self.config.gossip_static_outbound.len() {
+                return Err(invalid(
+                    "sync_blocks_genesis_check_quorum",
+                    "has to be between 1 and the number of the static outbound peers",
+                ));
+            }
+        }
+        if !self.config.ntp_servers.is_empty()
+            && self.config.ntp_servers.len() < network::MIN_NTP_SERVERS
+        {
+            return Err(invalid(
+                "ntp_servers",
+                "has to be empty or list at least 3 servers",
+            ));
+        }
+        if self.config.max_payload_size == 0 {
+            return Err(invalid("max_payload_size", "has to be positive"));
+        }
+        match (&self.config.sentry, &self.validator) {
+            (Some(network::SentryConfig::Sentry { .. }), Some(_)) => {
+                return Err(BuildError::ValidatorOnSentry)
+            }
+            (Some(network::SentryConfig::Backend { .. }), None) => {
+                return Err(BuildError::BackendWithoutValidator)
+            }
+            _ => {}
+        }
+        if let Some(validator) = &self.validator {
+            let key = validator.key.public();
+            if !self.block_store.genesis().accepts_validator_key(&key) {
+                return Err(BuildError::NotInCommittee(key));
+            }
+            if validator.verifier_threads == 0 {
+                return Err(invalid("verifier_threads", "has to be positive"));
+            }
+        }
+        Ok(Executor {
+            config: self.config,
+            block_store: self.block_store,
+            sync_progress_store: self.sync_progress_store,
+            validator: self.validator,
+            fork: None,
+            reload: self.reload,
+            topics: self.topics,
+            log_filter: self.log_filter,
+            #[cfg(feature = "grpc")]
+            grpc_addr: self.grpc_addr,
+            #[cfg(feature = "chaos")]
+            chaos: self.chaos,
+            view_history: bft::ViewHistory::default(),
+            network_monitor: network::Monitor::default(),
+            upgrade_qc: sync::watch::channel(None).0,
+        })
+    }
+}
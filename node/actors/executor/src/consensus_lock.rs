@@ -0,0 +1,217 @@
+//! Local protection against double signing.
+//! A validator signs conflicting consensus messages if two node processes are accidentally
+//! run with the same validator key, or if a restarted node has lost track of what it has
+//! signed before the crash. [`ConsensusLock`] prevents both: it holds an exclusive lock on
+//! a lock file for the lifetime of the process, and persists a signing watermark (the last
+//! signed view of every slashable message kind) *before* each signature is produced.
+use crate::Fatal;
+use anyhow::Context as _;
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use zksync_concurrency::{ctx, scope, sync};
+use zksync_consensus_crypto::{Text, TextFmt};
+use zksync_consensus_roles::validator;
+
+/// Name of the lock file.
+const LOCK_FILE: &str = "LOCK";
+/// Name of the watermark file.
+const WATERMARK_FILE: &str = "last_signed";
+
+/// Last signed message of a given kind.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Signed {
+    fork: validator::ForkNumber,
+    view: validator::ViewNumber,
+    hash: validator::MsgHash,
+}
+
+/// Last signed message of every slashable kind, keyed by `ConsensusMsg::label()`.
+type Watermark = BTreeMap<String, Signed>;
+
+/// Returns the watermark entry for the message, if it is a slashable consensus message.
+/// See `validator::DoubleSignProof`.
+fn watermark_entry(msg: &validator::Msg) -> Option<(&'static str, Signed)> {
+    let validator::Msg::Consensus(cmsg) = msg else {
+        return None;
+    };
+    match cmsg {
+        validator::ConsensusMsg::ReplicaPrepare(_)
+        | validator::ConsensusMsg::ReplicaCommit(_)
+        | validator::ConsensusMsg::LeaderPrepare(_) => {}
+        validator::ConsensusMsg::LeaderCommit(_)
+        | validator::ConsensusMsg::ReplicaTimeout(_)
+        | validator::ConsensusMsg::LeaderTimeout(_) => return None,
+    }
+    let view = cmsg.view();
+    Some((
+        cmsg.label(),
+        Signed {
+            fork: view.fork,
+            view: view.number,
+            hash: msg.hash(),
+        },
+    ))
+}
+
+fn decode_watermark(text: &str) -> anyhow::Result<Watermark> {
+    let mut watermark = Watermark::new();
+    for line in text.lines().filter(|l| !l.is_empty()) {
+        let [kind, fork, view, hash] = line.split(' ').collect::<Vec<_>>()[..] else {
+            anyhow::bail!("malformed line {line:?}");
+        };
+        let signed = Signed {
+            fork: validator::ForkNumber(fork.parse().context("fork")?),
+            view: validator::ViewNumber(view.parse().context("view")?),
+            hash: Text::new(hash).decode().context("hash")?,
+        };
+        watermark.insert(kind.to_string(), signed);
+    }
+    Ok(watermark)
+}
+
+fn encode_watermark(watermark: &Watermark) -> String {
+    let mut text = String::new();
+    for (kind, s) in watermark {
+        text += &format!("{kind} {} {} {}\n", s.fork.0, s.view.0, s.hash.encode());
+    }
+    text
+}
+
+/// Validator signer guarded by a consensus lock directory.
+/// * Only one `ConsensusLock` can be held on a directory at a time, across processes.
+///   The lock is released by the OS when the process exits, even if it crashes.
+/// * A slashable consensus message is signed only if its view is above the watermark of
+///   its kind (or it is exactly the last signed message of its kind).
+///   The watermark is persisted before the signature is produced,
+///   so that it never regresses, even if the process crashes right after signing.
+///
+/// Note that raw hashes (`sign_hash()`) are signed without any checks.
+pub struct ConsensusLock {
+    inner: Arc<dyn validator::ValidatorSigner>,
+    dir: PathBuf,
+    watermark: sync::Mutex<Watermark>,
+    /// Held for the whole lifetime of `ConsensusLock`.
+    _lock: fs::File,
+}
+
+impl fmt::Debug for ConsensusLock {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ConsensusLock")
+            .field("inner", &self.inner)
+            .field("dir", &self.dir)
+            .finish()
+    }
+}
+
+impl ConsensusLock {
+    /// Acquires the consensus lock directory (creating it if missing)
+    /// and loads the signing watermark. Fails if the lock is held by another process.
+    pub async fn acquire(
+        dir: PathBuf,
+        inner: Arc<dyn validator::ValidatorSigner>,
+    ) -> anyhow::Result<Self> {
+        let (lock, watermark) = scope::wait_blocking(|| {
+            fs::create_dir_all(&dir).with_context(|| format!("{dir:?}"))?;
+            let path = dir.join(LOCK_FILE);
+            let lock = fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+                .with_context(|| format!("{path:?}"))?;
+            match lock.try_lock() {
+                Ok(()) => {}
+                Err(fs::TryLockError::WouldBlock) => anyhow::bail!(
+                    "consensus lock {path:?} is held by another process; \
+                     is another node running with the same validator key?"
+                ),
+                Err(fs::TryLockError::Error(err)) => {
+                    return Err(err).with_context(|| format!("lock({path:?})"))
+                }
+            }
+            let path = dir.join(WATERMARK_FILE);
+            let watermark = match fs::read_to_string(&path) {
+                Ok(text) => decode_watermark(&text).with_context(|| format!("{path:?}"))?,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Watermark::new(),
+                Err(err) => return Err(err).with_context(|| format!("read({path:?})")),
+            };
+            anyhow::Ok((lock, watermark))
+        })
+        .await?;
+        Ok(Self {
+            inner,
+            dir,
+            watermark: sync::Mutex::new(watermark),
+            _lock: lock,
+        })
+    }
+
+    /// Atomically replaces the watermark file.
+    fn persist(dir: &Path, watermark: &Watermark) -> anyhow::Result<()> {
+        let path = dir.join(WATERMARK_FILE);
+        let tmp = path.with_extension("tmp");
+        (|| {
+            let mut file = fs::File::create(&tmp)?;
+            file.write_all(encode_watermark(watermark).as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&tmp, &path)?;
+            fs::File::open(dir)?.sync_all()
+        })()
+        .with_context(|| format!("write({path:?})"))
+    }
+}
+
+#[async_trait::async_trait]
+impl validator::ValidatorSigner for ConsensusLock {
+    fn public(&self) -> validator::PublicKey {
+        self.inner.public()
+    }
+
+    async fn sign_hash(
+        &self,
+        ctx: &ctx::Ctx,
+        msg_hash: &validator::MsgHash,
+    ) -> ctx::Result<validator::Signature> {
+        self.inner.sign_hash(ctx, msg_hash).await
+    }
+
+    async fn sign(
+        &self,
+        ctx: &ctx::Ctx,
+        msg: &validator::Msg,
+    ) -> ctx::Result<validator::Signature> {
+        let Some((kind, signed)) = watermark_entry(msg) else {
+            return self.inner.sign(ctx, msg).await;
+        };
+        // The lock is held until the message is signed, so that the concurrent calls
+        // are checked against the up-to-date watermark.
+        let mut watermark = sync::lock(ctx, &self.watermark).await?.into_async();
+        if let Some(last) = watermark.get(kind) {
+            if last == &signed {
+                return self.inner.sign(ctx, msg).await;
+            }
+            if (signed.fork, signed.view) <= (last.fork, last.view) {
+                // Retrying (e.g. by restarting the consensus actor) won't help.
+                return Err(anyhow::Error::new(Fatal(anyhow::format_err!(
+                    "refusing to sign {kind} in view {:?} of fork {:?}: \
+                     already signed a different one in view {:?} of fork {:?}",
+                    signed.view,
+                    signed.fork,
+                    last.view,
+                    last.fork,
+                )))
+                .into());
+            }
+        }
+        let mut new = watermark.clone();
+        new.insert(kind.to_string(), signed);
+        scope::wait_blocking(|| Self::persist(&self.dir, &new)).await?;
+        *watermark = new;
+        self.inner.sign(ctx, msg).await
+    }
+}
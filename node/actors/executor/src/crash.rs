@@ -0,0 +1,141 @@
+//! Crash reports of the panicking actors.
+//! The binaries are built with `panic = "abort"`, so a panic of an actor (or of any task
+//! spawned by it) cannot be caught and recovered from: it aborts the whole process.
+//! Instead, the panic hook installed by the executor turns the panic into a [`CrashReport`],
+//! which is logged and written to the crash directory (if configured) right before the abort.
+//! The panicking task cannot be attributed to an actor, so the report lists the type of the
+//! last message delivered to each of the supervised actors.
+use crate::supervision::Actor;
+use anyhow::Context as _;
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    collections::BTreeMap,
+    fmt, fs, panic,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+/// Crash reporting state of an executor.
+#[derive(Debug, Default)]
+pub(crate) struct Crashes {
+    /// Directory to which the crash reports are written.
+    dir: Option<PathBuf>,
+    /// Type of the last message delivered to each actor, by the actor name.
+    last_messages: Mutex<BTreeMap<&'static str, &'static str>>,
+}
+
+impl Crashes {
+    /// Constructs the state, writing the crash reports to `dir` (if set).
+    pub(crate) fn new(dir: Option<PathBuf>) -> Arc<Self> {
+        Arc::new(Self {
+            dir,
+            last_messages: Mutex::default(),
+        })
+    }
+
+    /// Records the type of the message delivered to the actor.
+    pub(crate) fn delivered(&self, actor: Actor, msg: &'static str) {
+        self.last_messages.lock().unwrap().insert(actor.name(), msg);
+    }
+
+    /// Installs the panic hook reporting the panics. The previously installed hook is still
+    /// called afterwards. The hook doesn't keep the state alive: once it is dropped
+    /// (i.e. the executor has stopped), the hook only calls the previous one.
+    pub(crate) fn install_panic_hook(self: &Arc<Self>) {
+        let this = Arc::downgrade(self);
+        let prev = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if let Some(this) = this.upgrade() {
+                let report = CrashReport {
+                    time: SystemTime::now(),
+                    message: payload_message(info.payload()),
+                    location: info
+                        .location()
+                        .map_or("<unknown>".to_string(), |l| l.to_string()),
+                    thread: std::thread::current()
+                        .name()
+                        .unwrap_or("<unnamed>")
+                        .to_string(),
+                    last_messages: this.last_messages.lock().map_or(vec![], |m| {
+                        m.iter().map(|(actor, msg)| (*actor, *msg)).collect()
+                    }),
+                    backtrace: Backtrace::force_capture().to_string(),
+                };
+                this.report(&report);
+            }
+            prev(info);
+        }));
+    }
+
+    /// Logs the report and writes it to the crash directory.
+    /// The process is about to abort, so the report is written synchronously.
+    fn report(&self, report: &CrashReport) {
+        tracing::error!("panicked:\n{report}");
+        if let Some(dir) = &self.dir {
+            match report.write(dir) {
+                Ok(path) => tracing::error!("crash report written to {path:?}"),
+                Err(err) => tracing::error!("failed to write the crash report: {err:#}"),
+            }
+        }
+    }
+}
+
+/// Message of a panic payload.
+fn payload_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "<non-string panic payload>".to_string()
+    }
+}
+
+/// Report of a panic.
+#[derive(Debug)]
+pub(crate) struct CrashReport {
+    /// Time of the crash.
+    pub(crate) time: SystemTime,
+    /// Panic message.
+    pub(crate) message: String,
+    /// Location of the panic.
+    pub(crate) location: String,
+    /// Thread on which the panic occurred.
+    pub(crate) thread: String,
+    /// Type of the last message delivered to each actor, by the actor name.
+    pub(crate) last_messages: Vec<(&'static str, &'static str)>,
+    /// Backtrace of the panic.
+    pub(crate) backtrace: String,
+}
+
+impl CrashReport {
+    /// Writes the report to a new file in `dir` (creating it if missing).
+    /// Returns the path of the file.
+    pub(crate) fn write(&self, dir: &Path) -> anyhow::Result<PathBuf> {
+        let millis = self
+            .time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let path = dir.join(format!("{millis}.crash"));
+        fs::create_dir_all(dir)
+            .and_then(|()| fs::write(&path, self.to_string()))
+            .with_context(|| format!("write({path:?})"))?;
+        Ok(path)
+    }
+}
+
+impl fmt::Display for CrashReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(fmt, "time: {:?}", self.time)?;
+        writeln!(fmt, "panic: {}", self.message)?;
+        writeln!(fmt, "location: {}", self.location)?;
+        writeln!(fmt, "thread: {}", self.thread)?;
+        for (actor, msg) in &self.last_messages {
+            writeln!(fmt, "last message of {actor}: {msg}")?;
+        }
+        writeln!(fmt, "backtrace:\n{}", self.backtrace)
+    }
+}
@@ -0,0 +1,129 @@
+//! Support for switching the chain to a new fork (regenesis) at a predefined block.
+use anyhow::Context as _;
+use std::sync::Arc;
+use zksync_concurrency::{ctx, error::Wrap as _};
+use zksync_consensus_bft as bft;
+use zksync_consensus_roles::validator;
+use zksync_consensus_storage::{BlockStore, ReplicaState, ReplicaStore};
+
+/// Verifies that `new_genesis` is a valid continuation of the chain stored in `block_store`,
+/// which has to contain the parent of the first block of the new fork.
+/// If `new_genesis.fork.first_parent` is not set, it is filled with the hash of that parent.
+/// Returns the resulting genesis.
+pub async fn fork_genesis(
+    ctx: &ctx::Ctx,
+    block_store: &BlockStore,
+    mut new_genesis: validator::Genesis,
+) -> ctx::Result<validator::Genesis> {
+    let cut_over = new_genesis
+        .fork
+        .first_block
+        .prev()
+        .context("fork cannot start at the first block of the chain")?;
+    let Some(parent) = block_store.block(ctx, cut_over).await.wrap("block()")? else {
+        return Err(anyhow::format_err!("missing parent block {cut_over:?}").into());
+    };
+    let hash = block_store.genesis().header_hash(parent.header());
+    match new_genesis.fork.first_parent {
+        Some(want) if want != hash => {
+            return Err(
+                anyhow::format_err!("fork parent mismatch: got {hash:?}, want {want:?}").into(),
+            )
+        }
+        _ => new_genesis.fork.first_parent = Some(hash),
+    }
+    Ok(new_genesis)
+}
+
+/// Resets the replica state, if it belongs to a different fork than `genesis`.
+/// Consensus messages from the previous fork are meaningless after the fork.
+pub(crate) async fn reset_stale_replica_state(
+    ctx: &ctx::Ctx,
+    genesis: &validator::Genesis,
+    replica_store: &dyn ReplicaStore,
+) -> ctx::Result<()> {
+    let state = replica_store.state(ctx).await.wrap("state()")?;
+    let stale = |view: &validator::View| view.fork != genesis.fork.number;
+    if state.high_vote.as_ref().is_some_and(|v| stale(&v.view))
+        || state
+            .high_qc
+            .as_ref()
+            .is_some_and(|qc| stale(&qc.message.view))
+    {
+        tracing::info!("resetting the replica state of the previous fork");
+        replica_store
+            .set_state(ctx, &ReplicaState::default())
+            .await
+            .wrap("set_state()")?;
+    }
+    Ok(())
+}
+
+/// Payload manager which doesn't propose nor accept blocks after `cut_over`
+/// until the block store is switched to the fork `fork`,
+/// so that the current fork is finalized exactly up to `cut_over`.
+#[derive(Debug)]
+pub(crate) struct ForkPayloadManager {
+    pub(crate) inner: Box<dyn bft::PayloadManager>,
+    pub(crate) block_store: Arc<BlockStore>,
+    pub(crate) fork: validator::ForkNumber,
+    pub(crate) cut_over: validator::BlockNumber,
+}
+
+impl ForkPayloadManager {
+    /// Checks whether the block belongs to the new fork, while the store is still on the current one.
+    fn past_cut_over(&self, number: validator::BlockNumber) -> bool {
+        number > self.cut_over && self.block_store.genesis().fork.number < self.fork
+    }
+}
+
+#[async_trait::async_trait]
+impl bft::PayloadManager for ForkPayloadManager {
+    async fn propose(
+        &self,
+        ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<validator::Payload> {
+        if self.past_cut_over(number) {
+            ctx.canceled().await;
+            return Err(ctx::Canceled.into());
+        }
+        self.inner.propose(ctx, number).await
+    }
+
+    async fn verify(
+        &self,
+        ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+        payload: &validator::Payload,
+    ) -> ctx::Result<()> {
+        if self.past_cut_over(number) {
+            return Err(anyhow::format_err!(
+                "block {number:?} is past the fork cut-over block {:?}",
+                self.cut_over
+            )
+            .into());
+        }
+        self.inner.verify(ctx, number, payload).await
+    }
+
+    fn supports_shadow_proposals(&self) -> bool {
+        self.inner.supports_shadow_proposals()
+    }
+}
+
+/// Waits until the blocks up to the parent of the first block of `new_genesis` are persisted,
+/// then verifies `new_genesis` against the stored chain.
+pub(crate) async fn wait_for_cut_over(
+    ctx: &ctx::Ctx,
+    block_store: &BlockStore,
+    new_genesis: validator::Genesis,
+) -> ctx::Result<validator::Genesis> {
+    let cut_over = new_genesis
+        .fork
+        .first_block
+        .prev()
+        .context("fork cannot start at the first block of the chain")?;
+    block_store.wait_until_persisted(ctx, cut_over).await?;
+    fork_genesis(ctx, block_store, new_genesis).await
+}
@@ -0,0 +1,191 @@
+//! gRPC API streaming the finalized blocks, enabled with the `grpc` feature.
+//! Downstream components (state keepers, explorers) can consume finality as a push stream,
+//! instead of polling the storage. Every stream is fed through a bounded buffer, so that
+//! HTTP/2 flow control of a slow consumer stalls only its own stream. The streams are fed
+//! by the tasks of the server's scope, so they stop together with the server.
+//!
+//! The service is hand-written against the `tonic` primitives (there is no `.proto` for it),
+//! and streams the blocks in the `zksync.roles.validator.FinalBlock` encoding:
+//! ```text
+//! service BlockStream {
+//!   rpc StreamBlocks(StreamBlocksRequest) returns (stream zksync.roles.validator.FinalBlock);
+//! }
+//! ```
+use std::net::SocketAddr;
+use tonic::codegen::{
+    empty_body, http, tokio_stream::wrappers::ReceiverStream, Body, BoxFuture, Context, Poll,
+    Service, StdError,
+};
+use zksync_concurrency::{ctx, scope};
+use zksync_consensus_roles::{proto, validator};
+use zksync_consensus_storage::BlockStoreReader;
+use zksync_protobuf::ProtoFmt as _;
+
+/// Full name of the gRPC service.
+pub const SERVICE_NAME: &str = "zksync.consensus.executor.BlockStream";
+/// Path of the `StreamBlocks` method.
+pub const STREAM_BLOCKS_PATH: &str = "/zksync.consensus.executor.BlockStream/StreamBlocks";
+
+/// Request of the `StreamBlocks` method.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StreamBlocksRequest {
+    /// Number of the first block to stream.
+    /// Defaults to the next block to be finalized.
+    #[prost(uint64, optional, tag = "1")]
+    pub from: Option<u64>,
+}
+
+/// Response stream of the `StreamBlocks` method.
+type BlockStream = ReceiverStream<Result<proto::validator::FinalBlock, tonic::Status>>;
+
+/// Stream of blocks requested by a client, fed by `BlockStreamService::stream_blocks()`.
+struct StreamReq {
+    /// Number of the first block to stream.
+    from: Option<u64>,
+    /// Buffer of the stream.
+    send: tokio::sync::mpsc::Sender<Result<proto::validator::FinalBlock, tonic::Status>>,
+}
+
+/// gRPC service streaming the finalized blocks from the block store.
+#[derive(Debug, Clone)]
+pub struct BlockStreamService {
+    block_store: BlockStoreReader,
+    /// Max number of blocks buffered per stream, before the client reads them.
+    buffer: usize,
+}
+
+impl BlockStreamService {
+    /// Constructs a service streaming blocks from `block_store`,
+    /// buffering up to `buffer` blocks per stream.
+    pub fn new(block_store: BlockStoreReader, buffer: usize) -> Self {
+        Self {
+            block_store,
+            buffer: buffer.max(1),
+        }
+    }
+
+    /// Serves the service on `addr`, until `ctx` is canceled.
+    pub async fn run(self, ctx: &ctx::Ctx, addr: SocketAddr) -> anyhow::Result<()> {
+        let (send, mut recv) = ctx::channel::unbounded();
+        let server = Server {
+            buffer: self.buffer,
+            streams: send,
+        };
+        scope::run!(ctx, |ctx, s| async {
+            s.spawn_bg(async {
+                while let Ok(req) = recv.recv(ctx).await {
+                    s.spawn_bg(self.stream_blocks(ctx, req));
+                }
+                Ok(())
+            });
+            tonic::transport::Server::builder()
+                .add_service(server)
+                .serve_with_shutdown(addr, ctx.canceled_owned())
+                .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Streams the blocks, starting from `req.from`, until the client drops the stream.
+    /// Blocks missing from the store (e.g. pruned) are skipped.
+    async fn stream_blocks(&self, ctx: &ctx::Ctx, req: StreamReq) -> anyhow::Result<()> {
+        let state = self.block_store.state();
+        let mut next = req
+            .from
+            .map_or(state.next(), validator::BlockNumber)
+            .max(state.first);
+        loop {
+            let block = tokio::select! {
+                () = req.send.closed() => return Ok(()),
+                block = async {
+                    self.block_store.wait_until_queued(ctx, next).await?;
+                    self.block_store.block(ctx, next).await
+                } => block,
+            };
+            next = next.next();
+            let res = match block {
+                Ok(None) => continue,
+                Ok(Some(block)) => Ok(block.build()),
+                Err(ctx::Error::Canceled(_)) => return Ok(()),
+                Err(ctx::Error::Internal(err)) => Err(tonic::Status::internal(format!("{err:#}"))),
+            };
+            let failed = res.is_err();
+            if req.send.send(res).await.is_err() || failed {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Service served by the gRPC server, which passes the requested streams
+/// to `BlockStreamService::run()`.
+#[derive(Clone)]
+struct Server {
+    /// Max number of blocks buffered per stream, before the client reads them.
+    buffer: usize,
+    /// Requested streams.
+    streams: ctx::channel::UnboundedSender<StreamReq>,
+}
+
+impl Server {
+    /// Requests a stream of blocks, starting from `from`.
+    fn stream_blocks(&self, from: Option<u64>) -> BlockStream {
+        let (send, recv) = tokio::sync::mpsc::channel(self.buffer);
+        // If the server is stopping, the request is dropped and so is the stream.
+        self.streams.send(StreamReq { from, send });
+        ReceiverStream::new(recv)
+    }
+}
+
+impl tonic::server::NamedService for Server {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+/// Handler of the `StreamBlocks` method.
+struct StreamBlocks(Server);
+
+impl tonic::server::ServerStreamingService<StreamBlocksRequest> for StreamBlocks {
+    type Response = proto::validator::FinalBlock;
+    type ResponseStream = BlockStream;
+    type Future = BoxFuture<tonic::Response<Self::ResponseStream>, tonic::Status>;
+
+    fn call(&mut self, req: tonic::Request<StreamBlocksRequest>) -> Self::Future {
+        let stream = self.0.stream_blocks(req.into_inner().from);
+        Box::pin(async move { Ok(tonic::Response::new(stream)) })
+    }
+}
+
+impl<B> Service<http::Request<B>> for Server
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<tonic::body::BoxBody>;
+    type Error = std::convert::Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        match req.uri().path() {
+            STREAM_BLOCKS_PATH => {
+                let method = StreamBlocks(self.clone());
+                Box::pin(async move {
+                    let mut grpc = tonic::server::Grpc::new(tonic::codec::ProstCodec::default());
+                    Ok(grpc.server_streaming(method, req).await)
+                })
+            }
+            _ => Box::pin(async move {
+                Ok(http::Response::builder()
+                    .status(200)
+                    .header("grpc-status", tonic::Code::Unimplemented as i32)
+                    .header("content-type", "application/grpc")
+                    .body(empty_body())
+                    .unwrap())
+            }),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Ingestion of the finalized blocks obtained outside of the consensus network.
+//! Some nodes can get the blocks from a faster out-of-band channel (e.g. derive them from L1).
+//! Such blocks are verified like the blocks synced over the gossip network and queued
+//! in the same block store, so whichever source delivers a block first wins.
+use anyhow::Context as _;
+use std::sync::Arc;
+use zksync_concurrency::{ctx, error::Wrap as _};
+use zksync_consensus_roles::validator;
+use zksync_consensus_storage::BlockStore;
+
+/// Outcome of ingesting a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ingested {
+    /// The block has been queued to be persisted.
+    Queued,
+    /// The block was not needed: it is already stored (e.g. synced over the gossip network),
+    /// or it precedes the stored blocks.
+    Duplicate,
+    /// The block falls into a gap of the stored blocks (see `BlockStoreState::gaps`).
+    /// The gaps are never filled, so the block has been dropped.
+    Gap,
+}
+
+/// Handle for injecting finalized blocks into the block store of an executor.
+#[derive(Debug, Clone)]
+pub struct BlockIngest {
+    block_store: Arc<BlockStore>,
+}
+
+impl BlockIngest {
+    /// Constructs a handle ingesting blocks into `block_store`.
+    pub fn new(block_store: Arc<BlockStore>) -> Self {
+        Self { block_store }
+    }
+
+    /// Verifies a finalized block and queues it to be persisted.
+    /// Waits until all the preceding blocks are queued (from any source), so the blocks
+    /// are expected to be ingested in order. Fails if the block is invalid, or if a different
+    /// block with the same number is already stored.
+    pub async fn ingest(
+        &self,
+        ctx: &ctx::Ctx,
+        block: validator::FinalBlock,
+    ) -> ctx::Result<Ingested> {
+        block
+            .verify(&self.block_store.genesis())
+            .context("block.verify()")?;
+        let number = block.number();
+        // Checking whether the block is needed and queueing it is atomic, so that a block
+        // queued concurrently by another source is never reported as `Queued`.
+        if self
+            .block_store
+            .try_queue_block(ctx, block.clone())
+            .await
+            .wrap("try_queue_block()")?
+        {
+            return Ok(Ingested::Queued);
+        }
+        // The block precedes the stored range or is within its gaps, or a block with
+        // the same number has been queued first, which has to match the ingested one.
+        let Some(stored) = self
+            .block_store
+            .justification(ctx, number)
+            .await
+            .wrap("justification()")?
+        else {
+            let state = self.block_store.subscribe().borrow().clone();
+            if state.gaps.iter().any(|gap| gap.contains(number)) {
+                return Ok(Ingested::Gap);
+            }
+            return Ok(Ingested::Duplicate);
+        };
+        if stored.header().hash() != block.header().hash() {
+            return Err(anyhow::format_err!(
+                "block {number:?} conflicts with the stored one: got {:?}, want {:?}",
+                block.header().hash(),
+                stored.header().hash()
+            )
+            .into());
+        }
+        Ok(Ingested::Duplicate)
+    }
+}
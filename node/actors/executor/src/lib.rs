@@ -1,38 +1,102 @@
 //! Library files for the executor. We have it separate from the binary so that we can use these files in the tools crate.
-use crate::io::Dispatcher;
+use crate::{
+    io::Dispatcher,
+    supervision::{supervise, Actor},
+};
 use anyhow::Context as _;
 use std::{
     collections::{HashMap, HashSet},
     fmt,
+    path::PathBuf,
     sync::Arc,
 };
-use zksync_concurrency::{ctx, net, scope, time};
+use zksync_concurrency::{ctx, net, scope, sync, time};
 use zksync_consensus_bft as bft;
 use zksync_consensus_network as network;
 use zksync_consensus_roles::{node, validator};
-use zksync_consensus_storage::{BlockStore, ReplicaStore};
+use zksync_consensus_storage::{BlockStore, ReplicaStore, SyncProgressStore};
 use zksync_consensus_sync_blocks as sync_blocks;
 use zksync_consensus_utils::pipe;
 use zksync_protobuf::kB;
 
+mod builder;
+mod consensus_lock;
+mod crash;
+mod fork;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+mod ingest;
 mod io;
+mod log_filter;
+mod role;
+mod supervision;
+mod supervisor;
 #[cfg(test)]
 mod tests;
 
+pub use builder::{BuildError, ExecutorBuilder};
+pub use consensus_lock::ConsensusLock;
+pub use fork::fork_genesis;
+pub use ingest::{BlockIngest, Ingested};
+pub use log_filter::LogFilter;
+pub use network::{
+    PublicAddrDetection, RelayAuth, ReloadableConfig, RpcConfig, Topic, TopicHandle, Topics,
+    Transport,
+};
+pub use role::NodeRole;
+pub use supervision::{Fatal, RestartPolicy};
+pub use supervisor::{Chain, ChainStatus, Supervisor, SupervisorRunner};
+
+/// Timeout of a single request to an NTP server.
+const NTP_TIMEOUT: time::Duration = time::Duration::seconds(5);
+/// Max number of blocks buffered per gRPC stream, before the client reads them.
+#[cfg(feature = "grpc")]
+const GRPC_STREAM_BUFFER: usize = 16;
+
 /// Validator-related part of [`Executor`].
 pub struct Validator {
-    /// Consensus network configuration.
-    pub key: validator::SecretKey,
+    /// Signer with the validator key.
+    pub key: Arc<dyn validator::ValidatorSigner>,
     /// Store for replica state.
     pub replica_store: Box<dyn ReplicaStore>,
     /// Payload manager.
     pub payload_manager: Box<dyn bft::PayloadManager>,
+    /// Whether to build (but not broadcast) payloads when other validators are the leaders.
+    /// See `bft::Config::shadow_proposer`.
+    pub shadow_proposer: bool,
+    /// Number of tasks verifying the signatures and the QCs of the received messages in parallel.
+    pub verifier_threads: usize,
+    /// Max number of blocks the validator may lag behind the highest QC before it stops
+    /// participating in the consensus to catch up. See `bft::Config::catch_up_threshold`.
+    pub catch_up_threshold: Option<u64>,
+    /// Whether to verify the consensus messages without ever voting or proposing.
+    /// See `bft::Config::observer`.
+    pub observer: bool,
+    /// How long the leader waits for a non-empty payload before proposing an empty block.
+    /// See `bft::Config::max_payload_wait`.
+    pub max_payload_wait: Option<time::Duration>,
+    /// Checkpointing of the replica state. See `bft::Config::checkpoint`.
+    pub checkpoint: Option<bft::CheckpointConfig>,
+    /// Directory of the consensus lock, which protects the validator key against
+    /// double signing. See [`ConsensusLock`]. `None` disables the protection.
+    pub consensus_lock: Option<PathBuf>,
+    /// Block at which the validator proposes to activate `bft::PROTOCOL_VERSION`, unless
+    /// the genesis already schedules it. The signed announcement is gossiped to the other nodes,
+    /// which aggregate the announcements of a quorum into a `validator::ProtocolUpgradeQC`.
+    /// `None` doesn't announce anything.
+    pub protocol_upgrade_activation: Option<validator::BlockNumber>,
+    /// Debugging: file to which the consensus messages received and sent by the validator
+    /// are appended, across restarts. See `bft::record`. `None` disables recording.
+    pub consensus_trace: Option<PathBuf>,
+    /// Rotation of the validator key, which the validator proposes to commit to the chain
+    /// when it is the leader. See `bft::Config::key_rotation`.
+    pub key_rotation: Option<validator::KeyRotationCert>,
 }
 
 impl fmt::Debug for Validator {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt.debug_struct("ValidatorExecutor")
-            .field("key", &self.key)
+            .field("key", &self.key.public())
             .finish()
     }
 }
@@ -43,10 +107,16 @@ pub struct Config {
     /// IP:port to listen on, for incoming TCP connections.
     /// Use `0.0.0.0:<port>` to listen on all network interfaces (i.e. on all IPs exposed by this VM).
     pub server_addr: std::net::SocketAddr,
+    /// Transport over which the network connections are established.
+    pub transport: Transport,
     /// Public TCP address that other nodes are expected to connect to.
     /// It is announced over gossip network.
     pub public_addr: std::net::SocketAddr,
+    /// If set, the IP of `public_addr` is detected from the addresses
+    /// observed by the peers (for the nodes behind NAT).
+    pub public_addr_detection: Option<PublicAddrDetection>,
     /// Maximal size of the block payload.
+    /// Overridden by the limit from genesis, if present.
     pub max_payload_size: usize,
 
     /// Key of this node. It uniquely identifies the node.
@@ -60,6 +130,63 @@ pub struct Config {
     /// Outbound connections that the node should actively try to
     /// establish and maintain.
     pub gossip_static_outbound: HashMap<node::PublicKey, std::net::SocketAddr>,
+    /// Limit on the number of outbound connections to the peers discovered
+    /// via the peer exchange, outside of the `static_outbound` set.
+    pub gossip_dynamic_outbound_limit: usize,
+    /// Authentication of the blocks relayed over the gossip network.
+    pub gossip_relay_auth: network::RelayAuth,
+    /// Max rate of the traffic received from a single gossip peer, in bytes per second.
+    /// See `network::GossipConfig::peer_bandwidth_cap`.
+    pub gossip_peer_bandwidth_cap: Option<usize>,
+    /// How long a peer with a different genesis is not redialed after a failed handshake.
+    pub genesis_mismatch_quarantine: time::Duration,
+    /// Number of the most recent views within which the consensus messages
+    /// received from a peer are deduplicated. 0 disables the replay protection.
+    pub consensus_replay_window: u64,
+    /// Max number of hops over which the consensus messages are relayed via the gossip network,
+    /// when the direct connection to a validator is missing. 0 disables relaying.
+    pub consensus_relay_max_hops: u32,
+    /// Max rate of serving blocks to all peers in total, in bytes per second.
+    /// `None` means unlimited.
+    pub serve_blocks_bandwidth: Option<usize>,
+    /// Max rate of serving blocks to a single peer, in bytes per second.
+    /// `None` means unlimited.
+    pub serve_blocks_bandwidth_per_peer: Option<usize>,
+    /// Whether to report the network metrics per peer. See `network::Config::per_peer_metrics`.
+    pub per_peer_metrics: bool,
+    /// Rate limits of the RPCs. See `network::Config::rpc`.
+    pub rpc: RpcConfig,
+    /// Role of the node in the sentry architecture. See `network::SentryConfig`.
+    /// A sentry runs without `Executor::validator`.
+    pub sentry: Option<network::SentryConfig>,
+    /// Policy of restarting the actors (network, consensus, block syncing) after a failure.
+    pub restart_policy: RestartPolicy,
+    /// Directory to which the crash report of a panic is written, right before the panic
+    /// aborts the process. Panics are only logged if not set. See [`crash`].
+    pub crash_dir: Option<PathBuf>,
+    /// NTP servers (`host[:port]`) against which the local clock is checked at startup.
+    /// The median of the measured offsets corrects the timestamps of the node (but never
+    /// the consensus timers). Empty disables the check, otherwise it has to list at least
+    /// `network::MIN_NTP_SERVERS` servers.
+    pub ntp_servers: Vec<String>,
+    /// Max offset of the local clock against the NTP servers,
+    /// beyond which the node refuses to start as a validator.
+    /// It also bounds the correction of the timestamps of the node.
+    pub max_clock_skew: time::Duration,
+    /// Number of peers from which the justification of every synced block is fetched and
+    /// cross-checked before the block is fetched. 1 disables the cross-check.
+    pub sync_blocks_cross_check_peers: usize,
+    /// Number of random chunks of the payload of every synced block which are sampled
+    /// from a peer and verified against the payload root before the block is fetched
+    /// from the peer. 0 disables the sampling.
+    pub sync_blocks_sample_chunks: usize,
+    /// Number of the `gossip_static_outbound` peers which have to confirm the genesis
+    /// of the node before any blocks are synced. `None` disables the check.
+    /// See `sync_blocks::Config::with_genesis_check`.
+    pub sync_blocks_genesis_check_quorum: Option<usize>,
+    /// Role of the node with respect to the history of the chain: which blocks it retains
+    /// and whether it serves them to the peers. See [`NodeRole`].
+    pub role: NodeRole,
 }
 
 impl Config {
@@ -70,56 +197,366 @@ impl Config {
             dynamic_inbound_limit: self.gossip_dynamic_inbound_limit,
             static_inbound: self.gossip_static_inbound.clone(),
             static_outbound: self.gossip_static_outbound.clone(),
+            dynamic_outbound_limit: self.gossip_dynamic_outbound_limit,
+            relay_auth: self.gossip_relay_auth,
+            peer_bandwidth_cap: self.gossip_peer_bandwidth_cap,
+            protocol_version: bft::PROTOCOL_VERSION,
         }
     }
 }
 
+/// Configs of the actors run by the executor.
+struct Actors {
+    network: network::Config,
+    sync_blocks: sync_blocks::Config,
+    consensus: Option<Arc<bft::Config>>,
+    upgrade_vote: Option<Arc<validator::Signed<validator::ProtocolUpgrade>>>,
+}
+
 /// Executor allowing to spin up all actors necessary for a consensus node.
+/// Constructed with [`Executor::builder`].
 #[derive(Debug)]
 pub struct Executor {
     /// General-purpose executor configuration.
-    pub config: Config,
+    pub(crate) config: Config,
     /// Block storage used by the node.
-    pub block_store: Arc<BlockStore>,
+    pub(crate) block_store: Arc<BlockStore>,
+    /// Store in which the progress of the block syncing is persisted,
+    /// so that a restarted node resumes catching up right away. `None` disables it.
+    pub(crate) sync_progress_store: Option<Arc<dyn SyncProgressStore>>,
     /// Validator-specific node data.
-    pub validator: Option<Validator>,
+    pub(crate) validator: Option<Validator>,
+    /// Genesis of the fork scheduled with [`Executor::schedule_fork`].
+    pub(crate) fork: Option<validator::Genesis>,
+    /// Updates of the reloadable part of the config, applied without restarting the node.
+    /// The initial value is taken from `config`, the value in the receiver is ignored
+    /// until it changes.
+    pub(crate) reload: Option<sync::watch::Receiver<ReloadableConfig>>,
+    /// Application-defined topics gossiped to the peers, see [`Topics::register`].
+    pub(crate) topics: Topics,
+    /// Filter of the node logs, adjustable at runtime.
+    pub(crate) log_filter: Option<LogFilter>,
+    /// History of the view transitions of the validator.
+    pub(crate) view_history: bft::ViewHistory,
+    /// Observable state of the network (peer pings and traffic).
+    pub(crate) network_monitor: network::Monitor,
+    /// Certificate of the protocol upgrade aggregated by the network, which the validator
+    /// proposes to commit to the chain. Survives the restarts of the network actor.
+    pub(crate) upgrade_qc: sync::watch::Sender<Option<Arc<validator::ProtocolUpgradeQC>>>,
+    /// Address of the gRPC stream of the finalized blocks.
+    #[cfg(feature = "grpc")]
+    pub(crate) grpc_addr: Option<std::net::SocketAddr>,
+    /// Faults injected into the network of the node.
+    #[cfg(feature = "chaos")]
+    pub(crate) chaos: Option<network::chaos::Config>,
 }
 
 impl Executor {
     /// Extracts a network crate config.
-    fn network_config(&self) -> network::Config {
+    fn network_config(&self, time_source: &network::TimeSource) -> network::Config {
         network::Config {
             server_addr: net::tcp::ListenerAddr::new(self.config.server_addr),
+            transport: self.config.transport.clone(),
             public_addr: self.config.public_addr,
+            public_addr_detection: self.config.public_addr_detection,
             gossip: self.config.gossip(),
             validator_key: self.validator.as_ref().map(|v| v.key.clone()),
+            sentry: self.config.sentry.clone(),
             ping_timeout: Some(time::Duration::seconds(10)),
+            keepalive: Some(network::KeepaliveConfig::default()),
+            genesis_mismatch_quarantine: self.config.genesis_mismatch_quarantine,
+            consensus_replay_window: self.config.consensus_replay_window,
+            consensus_relay_max_hops: self.config.consensus_relay_max_hops,
+            consensus_outbound_queue: network::OutboundQueueConfig::default(),
+            serve_blocks: self.config.role.serves_blocks(),
+            serve_blocks_bandwidth: self.config.serve_blocks_bandwidth,
+            serve_blocks_bandwidth_per_peer: self.config.serve_blocks_bandwidth_per_peer,
+            per_peer_metrics: self.config.per_peer_metrics,
             max_block_size: self.config.max_payload_size.saturating_add(kB),
-            rpc: network::RpcConfig::default(),
+            rpc: self.config.rpc.clone(),
+            topics: self.topics.clone(),
+            reconnect: network::ReconnectConfig::default(),
+            session_ticket_ttl: Some(time::Duration::hours(1)),
+            time_source: time_source.clone(),
+            monitor: self.network_monitor.clone(),
+            #[cfg(feature = "chaos")]
+            chaos: self.chaos.clone(),
         }
     }
 
-    /// Verifies correctness of the Executor.
-    fn verify(&self) -> anyhow::Result<()> {
-        if let Some(validator) = self.validator.as_ref() {
-            if !self
-                .block_store
-                .genesis()
-                .validators
-                .iter()
-                .any(|key| key == &validator.key.public())
-            {
-                anyhow::bail!("this validator doesn't belong to the consensus");
+    /// Measures the offset of the local clock against `Config::ntp_servers`.
+    /// A validator with a clock skewed by more than `Config::max_clock_skew` refuses to start,
+    /// since it would degrade the liveness of the consensus. Other nodes just log a warning.
+    /// The check is skipped (with a warning) if fewer than `network::MIN_NTP_SERVERS` servers respond.
+    async fn check_clock(
+        &self,
+        ctx: &ctx::Ctx,
+        time_source: &network::TimeSource,
+    ) -> anyhow::Result<()> {
+        let offset = match time_source
+            .sync_ntp(
+                ctx,
+                &self.config.ntp_servers,
+                self.config.max_clock_skew,
+                NTP_TIMEOUT,
+            )
+            .await
+        {
+            Ok(offset) => offset,
+            Err(err) => {
+                tracing::warn!("failed to check the local clock: {err:#}");
+                return Ok(());
             }
+        };
+        if offset.abs() <= self.config.max_clock_skew {
+            tracing::info!("local clock is off by {offset}");
+            return Ok(());
         }
+        anyhow::ensure!(
+            self.validator.is_none(),
+            "local clock is off by {offset}, which exceeds the max skew of {}; \
+             fix the time synchronization of the machine",
+            self.config.max_clock_skew
+        );
+        tracing::warn!(
+            "local clock is off by {offset}; timestamps are corrected by up to {}",
+            self.config.max_clock_skew
+        );
+        Ok(())
+    }
+
+    /// Signs the announcement of `Validator::protocol_upgrade_activation`,
+    /// unless the genesis already schedules `bft::PROTOCOL_VERSION`.
+    async fn upgrade_vote(
+        &self,
+        ctx: &ctx::Ctx,
+        validator: &Validator,
+    ) -> anyhow::Result<Option<Arc<validator::Signed<validator::ProtocolUpgrade>>>> {
+        let Some(activation) = validator.protocol_upgrade_activation else {
+            return Ok(None);
+        };
+        let genesis = self.block_store.genesis();
+        if genesis.latest_protocol_version() >= bft::PROTOCOL_VERSION {
+            tracing::info!(
+                "protocol version {:?} is already scheduled, not announcing the upgrade",
+                bft::PROTOCOL_VERSION
+            );
+            return Ok(None);
+        }
+        let upgrade = validator::ProtocolUpgrade {
+            genesis: genesis.hash(),
+            version: bft::PROTOCOL_VERSION,
+            activation,
+        };
+        tracing::info!("announcing {upgrade:?}");
+        Ok(Some(Arc::new(
+            validator
+                .key
+                .sign_msg(ctx, upgrade)
+                .await
+                .context("sign_msg()")?,
+        )))
+    }
+
+    /// Config of the executor.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Block storage used by the node.
+    pub fn block_store(&self) -> &Arc<BlockStore> {
+        &self.block_store
+    }
+
+    /// Filter of the node logs, if provided with [`ExecutorBuilder::log_filter`].
+    /// Allows to change the verbosity of the logs (e.g. of a single peer) without restarting the node.
+    pub fn log_filter(&self) -> Option<&LogFilter> {
+        self.log_filter.as_ref()
+    }
+
+    /// History of the view transitions of the validator.
+    /// Empty for the nodes which are not validators.
+    pub fn view_history(&self) -> &bft::ViewHistory {
+        &self.view_history
+    }
+
+    /// Observable state of the network, e.g. the pings and the traffic of the peers.
+    /// It survives the restarts of the network actor.
+    pub fn network_monitor(&self) -> &network::Monitor {
+        &self.network_monitor
+    }
+
+    /// Handle for injecting the finalized blocks obtained outside of the consensus network
+    /// (e.g. derived from L1) into the block store of this executor.
+    pub fn block_ingest(&self) -> BlockIngest {
+        BlockIngest::new(self.block_store.clone())
+    }
+
+    /// Schedules a fork (regenesis) of the chain starting at block `first_block`.
+    /// The current fork will be finalized up to the parent of `first_block`: validators
+    /// won't propose nor accept any block after it. Once the parent is persisted, the
+    /// `new_genesis` is verified against it (see [`fork_genesis`]), the block store is
+    /// switched to it and the actors are restarted, so that the node re-handshakes its peers
+    /// with the new genesis hash. The application should persist the new genesis
+    /// (available via `block_store().genesis()`), so that the store is reopened with it
+    /// after a restart.
+    /// All validators have to schedule the same fork for the chain to make progress.
+    pub fn schedule_fork(
+        &mut self,
+        first_block: validator::BlockNumber,
+        new_genesis: validator::Genesis,
+    ) -> anyhow::Result<()> {
+        let genesis = self.block_store.genesis();
+        anyhow::ensure!(
+            new_genesis.fork.first_block == first_block,
+            "new genesis starts at {:?}, want {first_block:?}",
+            new_genesis.fork.first_block
+        );
+        anyhow::ensure!(
+            new_genesis.fork.number > genesis.fork.number,
+            "fork number {:?} is not greater than the current one {:?}",
+            new_genesis.fork.number,
+            genesis.fork.number
+        );
+        anyhow::ensure!(
+            first_block > genesis.fork.first_block,
+            "fork has to start after the first block of the current fork {:?}",
+            genesis.fork.first_block
+        );
+        let next = self.block_store.subscribe().borrow().next();
+        anyhow::ensure!(
+            first_block >= next,
+            "blocks up to {:?} are already stored",
+            next.prev()
+        );
+        self.fork = Some(new_genesis);
         Ok(())
     }
 
     /// Runs this executor to completion. This should be spawned on a separate task.
-    pub async fn run(self, ctx: &ctx::Ctx) -> anyhow::Result<()> {
-        self.verify().context("verify()")?;
-        let network_config = self.network_config();
+    /// If a fork has been scheduled, switches to the new fork once the current one
+    /// has been finalized.
+    pub async fn run(mut self, ctx: &ctx::Ctx) -> anyhow::Result<()> {
+        if let Some(validator) = &mut self.validator {
+            if let Some(dir) = validator.consensus_lock.clone() {
+                validator.key = Arc::new(
+                    ConsensusLock::acquire(dir, validator.key.clone())
+                        .await
+                        .context("ConsensusLock::acquire()")?,
+                );
+            }
+        }
+        let time_source = network::TimeSource::default();
+        if !self.config.ntp_servers.is_empty() {
+            self.check_clock(ctx, &time_source)
+                .await
+                .context("check_clock()")?;
+        }
+        let network_config = self.network_config(&time_source);
+        let mut sync_blocks_config = sync_blocks::Config::new()
+            .with_cross_check_peers(self.config.sync_blocks_cross_check_peers)
+            .context("sync_blocks_cross_check_peers")?
+            .with_payload_sampling(self.config.sync_blocks_sample_chunks)
+            .context("sync_blocks_sample_chunks")?;
+        if let Some(store) = &self.sync_progress_store {
+            sync_blocks_config = sync_blocks_config.with_progress_store(store.clone());
+        }
+        if let Some(quorum) = self.config.sync_blocks_genesis_check_quorum {
+            sync_blocks_config = sync_blocks_config
+                .with_genesis_check(
+                    self.config.transport.clone(),
+                    self.config.gossip_static_outbound.clone(),
+                    quorum,
+                )
+                .context("sync_blocks_genesis_check_quorum")?;
+        }
+
+        let (consensus, upgrade_vote) = match self.validator.take() {
+            Some(validator) => {
+                let upgrade_vote = self
+                    .upgrade_vote(ctx, &validator)
+                    .await
+                    .context("upgrade_vote()")?;
+                let cfg = self
+                    .consensus_config(validator, &time_source)
+                    .context("consensus_config()")?;
+                (Some(cfg), upgrade_vote)
+            }
+            None => (None, None),
+        };
+        let actors = Actors {
+            network: network_config,
+            sync_blocks: sync_blocks_config,
+            consensus,
+            upgrade_vote,
+        };
+
+        if let Some(new_genesis) = self.fork.take() {
+            // Run the current fork until the cut-over.
+            let new_genesis = scope::run!(ctx, |ctx, s| async {
+                s.spawn_bg(self.run_actors(ctx, &actors));
+                let new_genesis = fork::wait_for_cut_over(ctx, &self.block_store, new_genesis)
+                    .await
+                    .context("wait_for_cut_over()")?;
+                Ok(new_genesis)
+            })
+            .await?;
+            tracing::info!(
+                "fork cut-over reached, new genesis hash: {:?}",
+                new_genesis.hash()
+            );
+            // The actors are restarted with the new genesis, so that the peers
+            // are re-handshaked and the consensus starts from the first view of the new fork.
+            self.block_store
+                .switch_fork(new_genesis)
+                .context("switch_fork()")?;
+        }
+        self.run_actors(ctx, &actors).await
+    }
+
+    /// Constructs the consensus actor config.
+    fn consensus_config(
+        &self,
+        validator: Validator,
+        time_source: &network::TimeSource,
+    ) -> anyhow::Result<Arc<bft::Config>> {
+        let payload_manager: Box<dyn bft::PayloadManager> = match &self.fork {
+            Some(new_genesis) => Box::new(fork::ForkPayloadManager {
+                inner: validator.payload_manager,
+                block_store: self.block_store.clone(),
+                fork: new_genesis.fork.number,
+                cut_over: new_genesis.fork.first_block.prev().unwrap(),
+            }),
+            None => validator.payload_manager,
+        };
+        let recorder = match &validator.consensus_trace {
+            Some(path) => Some(Arc::new(
+                bft::record::Recorder::open(path.clone()).context("Recorder::open()")?,
+            )),
+            None => None,
+        };
+        Ok(Arc::new(bft::Config {
+            signer: validator.key.clone(),
+            block_store: self.block_store.clone(),
+            replica_store: validator.replica_store,
+            payload_manager,
+            max_payload_size: self.config.max_payload_size,
+            shadow_proposer: validator.shadow_proposer,
+            verifier_threads: validator.verifier_threads,
+            catch_up_threshold: validator.catch_up_threshold,
+            observer: validator.observer,
+            max_payload_wait: validator.max_payload_wait,
+            checkpoint: validator.checkpoint,
+            time_source: time_source.clone(),
+            recorder,
+            key_rotation: validator.key_rotation,
+            protocol_upgrade: Some(self.upgrade_qc.subscribe()),
+            view_history: self.view_history.clone(),
+        }))
+    }
 
+    /// Runs the actors until an error or cancellation.
+    async fn run_actors(&self, ctx: &ctx::Ctx, actors: &Actors) -> anyhow::Result<()> {
         // Generate the communication pipes. We have one for each actor.
         let (consensus_actor_pipe, consensus_dispatcher_pipe) = pipe::new();
         let (sync_blocks_actor_pipe, sync_blocks_dispatcher_pipe) = pipe::new();
@@ -131,38 +568,121 @@ impl Executor {
             network_dispatcher_pipe,
         );
 
+        if let Some(cfg) = &actors.consensus {
+            fork::reset_stale_replica_state(ctx, &self.block_store.genesis(), &*cfg.replica_store)
+                .await
+                .context("reset_stale_replica_state()")?;
+        }
+
+        let crashes = crash::Crashes::new(self.config.crash_dir.clone());
+        crashes.install_panic_hook();
+
         tracing::debug!("Starting actors in separate threads.");
         scope::run!(ctx, |ctx, s| async {
             s.spawn_blocking(|| dispatcher.run(ctx).context("IO Dispatcher stopped"));
+            #[cfg(feature = "grpc")]
+            if let Some(addr) = self.grpc_addr {
+                s.spawn(async move {
+                    grpc::BlockStreamService::new(self.block_store.reader(), GRPC_STREAM_BUFFER)
+                        .run(ctx, addr)
+                        .await
+                        .context("gRPC server stopped")
+                });
+            }
+            s.spawn(async {
+                self.config
+                    .role
+                    .run_pruning(ctx, &self.block_store)
+                    .await
+                    .or_else(|err| match err {
+                        ctx::Error::Canceled(_) => Ok(()),
+                        ctx::Error::Internal(err) => Err(err.context("Pruning blocks stopped")),
+                    })
+            });
             s.spawn(async {
-                let (net, runner) = network::Network::new(
+                let network_config = &actors.network;
+                let block_store = &self.block_store;
+                let reload = &self.reload;
+                let upgrade_vote = &actors.upgrade_vote;
+                let upgrade_qc = &self.upgrade_qc;
+                supervise(
                     ctx,
-                    network_config,
-                    self.block_store.clone(),
+                    Actor::Network,
+                    &self.config.restart_policy,
+                    &crashes,
                     network_actor_pipe,
-                );
-                net.register_metrics();
-                runner.run(ctx).await.context("Network stopped")
+                    move |pipe| async move {
+                        let (net, runner) = network::Network::new(
+                            ctx,
+                            network_config.clone(),
+                            block_store.reader(),
+                            pipe,
+                        );
+                        net.register_metrics();
+                        if let Some(vote) = upgrade_vote {
+                            net.push_upgrade_vote(vote.clone())
+                                .await
+                                .context("push_upgrade_vote()")?;
+                        }
+                        scope::run!(ctx, |ctx, s| async {
+                            // The certificates aggregated by the network are passed to the consensus.
+                            s.spawn_bg(async {
+                                let mut qc = net.subscribe_upgrade_qc();
+                                upgrade_qc.send_replace(qc.borrow_and_update().clone());
+                                while let Ok(new) = sync::changed(ctx, &mut qc).await {
+                                    let new = new.clone();
+                                    upgrade_qc.send_replace(new);
+                                }
+                                Ok(())
+                            });
+                            // A fresh clone of the receiver observes the latest reloaded config,
+                            // so that a restarted network doesn't fall back to `network_config`.
+                            if let Some(mut reload) = reload.clone() {
+                                s.spawn_bg(async {
+                                    while let Ok(cfg) = sync::changed(ctx, &mut reload).await {
+                                        let cfg = cfg.clone();
+                                        net.reload(ctx, cfg).await;
+                                    }
+                                    Ok(())
+                                });
+                            }
+                            runner.run(ctx).await
+                        })
+                        .await
+                    },
+                )
+                .await
+                .context("Network stopped")
             });
-            if let Some(validator) = self.validator {
+            if let Some(cfg) = &actors.consensus {
                 s.spawn(async {
-                    let validator = validator;
-                    bft::Config {
-                        secret_key: validator.key.clone(),
-                        block_store: self.block_store.clone(),
-                        replica_store: validator.replica_store,
-                        payload_manager: validator.payload_manager,
-                        max_payload_size: self.config.max_payload_size,
-                    }
-                    .run(ctx, consensus_actor_pipe)
+                    supervise(
+                        ctx,
+                        Actor::Consensus,
+                        &self.config.restart_policy,
+                        &crashes,
+                        consensus_actor_pipe,
+                        |pipe| cfg.clone().run_shared(ctx, pipe),
+                    )
                     .await
                     .context("Consensus stopped")
                 });
             }
-            sync_blocks::Config::new()
-                .run(ctx, sync_blocks_actor_pipe, self.block_store.clone())
-                .await
-                .context("Syncing blocks stopped")
+            supervise(
+                ctx,
+                Actor::SyncBlocks,
+                &self.config.restart_policy,
+                &crashes,
+                sync_blocks_actor_pipe,
+                |pipe| {
+                    actors
+                        .sync_blocks
+                        .clone()
+                        .run(ctx, pipe, self.block_store.clone())
+                },
+            )
+            .await
+            .context("Syncing blocks stopped")
         })
         .await
     }
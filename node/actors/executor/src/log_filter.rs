@@ -0,0 +1,56 @@
+//! Runtime control of the filter of the node logs.
+use anyhow::Context as _;
+use std::{fmt, sync::Arc};
+use tracing_subscriber::{reload, EnvFilter};
+
+/// Reloadable filter layer, type-erased over the subscriber it is installed in.
+trait Reload: Send + Sync {
+    fn reload(&self, filter: EnvFilter) -> Result<(), reload::Error>;
+    fn current(&self) -> Result<String, reload::Error>;
+}
+
+impl<S: 'static> Reload for reload::Handle<EnvFilter, S> {
+    fn reload(&self, filter: EnvFilter) -> Result<(), reload::Error> {
+        reload::Handle::reload(self, filter)
+    }
+
+    fn current(&self) -> Result<String, reload::Error> {
+        self.with_current(|f| f.to_string())
+    }
+}
+
+/// Handle to the filter of the node logs, which allows to change it without restarting the node.
+/// Connections of the network actor are traced in the `gossip` and `consensus` spans with
+/// the `peer` field, so the filter can target a single peer, e.g.
+/// `info,zksync_consensus_network::rpc[gossip{peer=node:public:ed25519:...}]=trace`.
+#[derive(Clone)]
+pub struct LogFilter(Arc<dyn Reload>);
+
+impl fmt::Debug for LogFilter {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_tuple("LogFilter")
+            .field(&self.get().ok())
+            .finish()
+    }
+}
+
+impl LogFilter {
+    /// Wraps the handle of a reloadable `EnvFilter` layer.
+    pub fn new<S: 'static>(handle: reload::Handle<EnvFilter, S>) -> Self {
+        Self(Arc::new(handle))
+    }
+
+    /// Directives of the current filter.
+    pub fn get(&self) -> anyhow::Result<String> {
+        Ok(self.0.current()?)
+    }
+
+    /// Replaces the filter with the one parsed from `directives`
+    /// (in the `RUST_LOG` syntax). Invalid directives are rejected.
+    pub fn set(&self, directives: &str) -> anyhow::Result<()> {
+        let filter = EnvFilter::try_new(directives).context("invalid filter")?;
+        self.0.reload(filter)?;
+        tracing::info!("log filter set to {directives:?}");
+        Ok(())
+    }
+}
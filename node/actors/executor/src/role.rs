@@ -0,0 +1,66 @@
+//! Role of the node with respect to the history of the chain.
+//! The role determines in one place which blocks the node retains (pruning policy),
+//! whether it serves them to the peers, and hence which `BlockStoreState` it advertises.
+use zksync_concurrency::{ctx, sync, time};
+use zksync_consensus_roles::validator;
+use zksync_consensus_storage::BlockStore;
+
+/// Interval at which the retained block range is re-checked and the older blocks are pruned.
+const PRUNE_INTERVAL: time::Duration = time::Duration::seconds(10);
+
+/// Role of the node with respect to the history of the chain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NodeRole {
+    /// Retains all the blocks and serves them to the peers.
+    #[default]
+    Archive,
+    /// Retains only the last `retention` blocks (at least 1) and serves them to the peers.
+    /// The persistent block store has to support pruning.
+    Pruned {
+        /// Number of the most recent blocks to retain.
+        retention: u64,
+    },
+    /// Retains only the last block and doesn't serve any blocks to the peers.
+    /// The persistent block store has to support pruning.
+    Minimal,
+}
+
+impl NodeRole {
+    /// Number of the most recent blocks retained by the node. `None` means all of them.
+    pub fn retention(&self) -> Option<u64> {
+        match self {
+            Self::Archive => None,
+            Self::Pruned { retention } => Some((*retention).max(1)),
+            Self::Minimal => Some(1),
+        }
+    }
+
+    /// Whether the node serves the blocks (and advertises its `BlockStoreState`) to the peers.
+    pub fn serves_blocks(&self) -> bool {
+        !matches!(self, Self::Minimal)
+    }
+
+    /// Prunes the blocks of `block_store` outside of the retained range, as the chain grows.
+    /// Returns immediately if all the blocks are retained.
+    pub(crate) async fn run_pruning(
+        self,
+        ctx: &ctx::Ctx,
+        block_store: &BlockStore,
+    ) -> ctx::Result<()> {
+        let Some(retention) = self.retention() else {
+            return Ok(());
+        };
+        let mut sub = block_store.subscribe();
+        loop {
+            let state = sync::wait_for(ctx, &mut sub, |state| state.last.is_some())
+                .await?
+                .clone();
+            let last = state.last.as_ref().unwrap().header().number;
+            let first = validator::BlockNumber(last.0.saturating_sub(retention - 1));
+            if first > state.first {
+                block_store.prune(ctx, first).await?;
+            }
+            ctx.sleep(PRUNE_INTERVAL).await?;
+        }
+    }
+}
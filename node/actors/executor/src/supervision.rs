@@ -0,0 +1,235 @@
+//! Supervision of the actors of the executor.
+//! A failing actor (network, consensus or block syncing) is restarted with a backoff,
+//! instead of tearing down the whole executor. The actors communicate with the dispatcher
+//! over pipes, which don't survive the actor: every incarnation of an actor gets a fresh pipe,
+//! which is relayed to the pipe of the dispatcher for as long as the incarnation runs.
+//!
+//! An actor failure is fatal (and stops the executor, as before) if
+//! * the error is marked as [`Fatal`], i.e. restarting the actor wouldn't help
+//!   or would be unsafe (e.g. the consensus lock refusing to sign), or
+//! * the actor has failed more than `RestartPolicy::max_restarts` times within
+//!   `RestartPolicy::window`.
+//!
+//! A panic of an actor is not recovered from: it aborts the process, see [`crate::crash`].
+use crate::crash;
+use std::{collections::VecDeque, fmt, future::Future};
+use vise::{Counter, EncodeLabelSet, EncodeLabelValue, Family, Gauge, Metrics};
+use zksync_concurrency::{ctx, scope, time};
+use zksync_consensus_bft as bft;
+use zksync_consensus_network as network;
+use zksync_consensus_sync_blocks as sync_blocks;
+use zksync_consensus_utils::pipe;
+
+/// Error of an actor which should not be recovered from by restarting the actor.
+#[derive(Debug)]
+pub struct Fatal(pub anyhow::Error);
+
+impl fmt::Display for Fatal {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{:#}", self.0)
+    }
+}
+
+impl std::error::Error for Fatal {}
+
+/// Whether the error of an actor is fatal.
+fn is_fatal(err: &anyhow::Error) -> bool {
+    err.chain().any(|err| err.is::<Fatal>())
+}
+
+/// Policy of restarting the failed actors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Max number of restarts of an actor within `window`.
+    /// The next failure is fatal. 0 disables restarting.
+    pub max_restarts: usize,
+    /// Window over which the restarts are counted.
+    pub window: time::Duration,
+    /// Delay before the first restart within `window`.
+    /// It is doubled on every following restart, up to `max_backoff`.
+    pub initial_backoff: time::Duration,
+    /// Max delay before a restart.
+    pub max_backoff: time::Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: 5,
+            window: time::Duration::minutes(10),
+            initial_backoff: time::Duration::seconds(1),
+            max_backoff: time::Duration::minutes(1),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Delay before the `n`-th restart within the window (counting from 1).
+    fn backoff(&self, n: usize) -> time::Duration {
+        let mut backoff = self.initial_backoff;
+        for _ in 1..n {
+            if backoff >= self.max_backoff {
+                break;
+            }
+            backoff = backoff * 2;
+        }
+        backoff.min(self.max_backoff)
+    }
+}
+
+/// Actor supervised by the executor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EncodeLabelValue, EncodeLabelSet)]
+#[metrics(label = "actor", rename_all = "snake_case")]
+pub(crate) enum Actor {
+    /// Network actor.
+    Network,
+    /// Consensus (bft) actor.
+    Consensus,
+    /// Block syncing actor.
+    SyncBlocks,
+}
+
+impl Actor {
+    /// Name of the actor, as used in the crash reports.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Network => "network",
+            Self::Consensus => "consensus",
+            Self::SyncBlocks => "sync_blocks",
+        }
+    }
+}
+
+/// Message delivered to an actor. The type of the last delivered message
+/// is included in the crash reports.
+pub(crate) trait Message {
+    /// Type of the message.
+    fn label(&self) -> &'static str;
+}
+
+impl Message for network::io::InputMessage {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Consensus(msg) => msg.message.msg.label(),
+            Self::SyncBlocks(msg) => match msg {
+                network::io::SyncBlocksInputMessage::GetBlock { .. } => "GetBlock",
+                network::io::SyncBlocksInputMessage::GetJustification { .. } => "GetJustification",
+                network::io::SyncBlocksInputMessage::SamplePayload { .. } => "SamplePayload",
+                network::io::SyncBlocksInputMessage::Misbehaved { .. } => "Misbehaved",
+            },
+        }
+    }
+}
+
+impl Message for bft::io::InputMessage {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Network(req) => req.msg.msg.label(),
+        }
+    }
+}
+
+impl Message for sync_blocks::io::InputMessage {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Network(network::io::SyncBlocksRequest::UpdatePeerSyncState { .. }) => {
+                "UpdatePeerSyncState"
+            }
+            Self::Network(network::io::SyncBlocksRequest::UpdatePeerRtt { .. }) => "UpdatePeerRtt",
+        }
+    }
+}
+
+/// Runs the actor, restarting it according to `policy` whenever it fails.
+/// `run` starts a new incarnation of the actor with the given pipe.
+/// Returns when an incarnation returns successfully, or fails fatally.
+/// The types of the messages delivered to the actor are recorded in `crashes`.
+pub(crate) async fn supervise<In: Message, Out, F, Fut>(
+    ctx: &ctx::Ctx,
+    actor: Actor,
+    policy: &RestartPolicy,
+    crashes: &crash::Crashes,
+    pipe: pipe::ActorPipe<In, Out>,
+    mut run: F,
+) -> anyhow::Result<()>
+where
+    F: FnMut(pipe::ActorPipe<In, Out>) -> Fut,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
+    let pipe::Pipe { mut recv, send } = pipe;
+    let mut restarts = VecDeque::new();
+    loop {
+        let (actor_pipe, dispatcher_pipe) = pipe::new();
+        let pipe::Pipe {
+            recv: mut actor_recv,
+            send: actor_send,
+        } = dispatcher_pipe;
+        METRICS.up[&actor].set(1);
+        let res = scope::run!(ctx, |ctx, s| async {
+            s.spawn_bg(async {
+                while let Ok(msg) = recv.recv(ctx).await {
+                    crashes.delivered(actor, msg.label());
+                    actor_send.send(msg);
+                }
+                Ok(())
+            });
+            s.spawn_bg(async {
+                while let Ok(msg) = actor_recv.recv(ctx).await {
+                    send.send(msg);
+                }
+                Ok(())
+            });
+            run(actor_pipe).await
+        })
+        .await;
+        METRICS.up[&actor].set(0);
+        // Deliver the messages sent by the actor right before it stopped.
+        while let Some(msg) = actor_recv.try_recv() {
+            send.send(msg);
+        }
+        let Err(err) = res else {
+            return Ok(());
+        };
+        if !ctx.is_active() {
+            return Err(err);
+        }
+        if is_fatal(&err) {
+            METRICS.fatal_errors[&actor].inc();
+            return Err(err);
+        }
+        let now = ctx.now();
+        while restarts.front().is_some_and(|t| now - *t >= policy.window) {
+            restarts.pop_front();
+        }
+        if restarts.len() >= policy.max_restarts {
+            METRICS.fatal_errors[&actor].inc();
+            return Err(err.context(format!(
+                "{actor:?} actor failed {} times within {}",
+                restarts.len() + 1,
+                policy.window
+            )));
+        }
+        restarts.push_back(now);
+        let backoff = policy.backoff(restarts.len());
+        tracing::warn!("{actor:?} actor failed, restarting in {backoff}: {err:#}");
+        METRICS.restarts[&actor].inc();
+        if ctx.sleep(backoff).await.is_err() {
+            return Err(err);
+        }
+    }
+}
+
+/// Health metrics of the supervised actors.
+#[derive(Debug, Metrics)]
+#[metrics(prefix = "zksync_consensus_executor_actor")]
+struct ActorMetrics {
+    /// 1 if the actor is running, 0 if it is waiting for a restart or has stopped.
+    up: Family<Actor, Gauge<u64>>,
+    /// Restarts of the actor after a recoverable failure.
+    restarts: Family<Actor, Counter>,
+    /// Fatal failures of the actor.
+    fatal_errors: Family<Actor, Counter>,
+}
+
+#[vise::register]
+static METRICS: vise::Global<ActorMetrics> = vise::Global::new();
@@ -0,0 +1,308 @@
+//! Supervisor running multiple independent chains in a single process.
+//! Operators of many app-chains can run an executor per chain (each with its own genesis,
+//! storage and ports) on a shared runtime, instead of running a process per chain.
+//! Chains are added and stopped at runtime; a chain failing doesn't affect the other ones.
+//!
+//! Per-chain metrics (`zksync_consensus_executor_chain_*`) are labeled with the chain name,
+//! and cover the state of the block store of every chain.
+//! Metrics of the actors (network, consensus, storage) are process-wide and NOT labeled:
+//! the counters and histograms aggregate over all the chains, the gauges computed on scrape
+//! (e.g. `zksync_consensus_storage_block_store_*`) report only the first chain which registered
+//! them (while it is running), and the gauges set directly by the actors
+//! (e.g. `consensus_replica_blocks_behind`) report whichever chain has set them last.
+//! They are meaningful only for a single-chain process: use the per-chain metrics
+//! to monitor the supervised chains.
+use crate::Executor;
+use anyhow::Context as _;
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+use tracing::Instrument as _;
+use vise::{Collector, Gauge, LabeledFamily, Metrics};
+use zksync_concurrency::{ctx, ctx::channel, oneshot, scope};
+use zksync_consensus_storage::{BlockStore, BlockStoreRunner};
+
+/// Chain to be run by the supervisor.
+pub struct Chain {
+    /// Executor of the chain.
+    pub executor: Executor,
+    /// Runner of the block store of `executor`.
+    pub block_store_runner: BlockStoreRunner,
+}
+
+/// Status of a chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainStatus {
+    /// The chain is running.
+    Running,
+    /// The chain has been stopped, or its executor has returned (e.g. after a fork cut-over).
+    Stopped,
+    /// The chain has failed with the given error.
+    Failed(String),
+}
+
+/// State of a chain managed by the supervisor.
+#[derive(Debug)]
+struct ChainState {
+    /// Distinguishes the chain from the previous chains with the same name.
+    id: u64,
+    /// Status of the chain.
+    status: ChainStatus,
+    /// Address the chain listens on.
+    server_addr: SocketAddr,
+    /// Block store of the chain.
+    block_store: Arc<BlockStore>,
+    /// Dropping the sender stops the chain.
+    stop: Option<oneshot::Sender<()>>,
+}
+
+/// Chain sent to the runner.
+struct Start {
+    /// Name of the chain.
+    name: String,
+    /// See `ChainState::id`.
+    id: u64,
+    /// Chain to run.
+    chain: Chain,
+    /// Disconnected when the chain should be stopped.
+    stop: oneshot::Receiver<()>,
+}
+
+/// Supervisor of the chains run in this process.
+pub struct Supervisor {
+    /// Chains, by name.
+    chains: Mutex<BTreeMap<String, ChainState>>,
+    /// ID of the most recently added chain.
+    next_id: Mutex<u64>,
+    /// Sends the added chains to the runner.
+    send: channel::UnboundedSender<Start>,
+}
+
+/// Runner of the chains added to the [`Supervisor`].
+#[must_use]
+pub struct SupervisorRunner {
+    /// Supervisor of the chains.
+    supervisor: Arc<Supervisor>,
+    /// Receives the chains to run.
+    recv: channel::UnboundedReceiver<Start>,
+}
+
+impl Supervisor {
+    /// Constructs a supervisor without chains.
+    /// The chains are run by the returned runner.
+    pub fn new() -> (Arc<Self>, SupervisorRunner) {
+        let (send, recv) = channel::unbounded();
+        let supervisor = Arc::new(Self {
+            chains: Mutex::default(),
+            next_id: Mutex::default(),
+            send,
+        });
+        let runner = SupervisorRunner {
+            supervisor: supervisor.clone(),
+            recv,
+        };
+        (supervisor, runner)
+    }
+
+    /// Starts running a chain under the given name.
+    /// Fails if a chain with the same name is already running, or if a running chain
+    /// has the same genesis or listens on the same address.
+    pub fn add_chain(&self, name: &str, chain: Chain) -> anyhow::Result<()> {
+        let block_store = chain.executor.block_store.clone();
+        let server_addr = chain.executor.config.server_addr;
+        let mut chains = self.chains.lock().unwrap();
+        for (other_name, other) in chains.iter() {
+            if other.status != ChainStatus::Running {
+                continue;
+            }
+            anyhow::ensure!(other_name != name, "chain {name:?} is already running");
+            anyhow::ensure!(
+                other.block_store.genesis().hash() != block_store.genesis().hash(),
+                "chain {other_name:?} has the same genesis"
+            );
+            anyhow::ensure!(
+                server_addr.port() == 0 || other.server_addr != server_addr,
+                "chain {other_name:?} already listens on {server_addr}"
+            );
+        }
+        let id = {
+            let mut next_id = self.next_id.lock().unwrap();
+            *next_id += 1;
+            *next_id
+        };
+        let (stop_send, stop_recv) = oneshot::channel();
+        chains.insert(
+            name.to_string(),
+            ChainState {
+                id,
+                status: ChainStatus::Running,
+                server_addr,
+                block_store,
+                stop: Some(stop_send),
+            },
+        );
+        self.send.send(Start {
+            name: name.to_string(),
+            id,
+            chain,
+            stop: stop_recv,
+        });
+        Ok(())
+    }
+
+    /// Stops the chain with the given name. The chain stays listed (as stopped),
+    /// until it is removed or replaced by a chain with the same name.
+    pub fn stop_chain(&self, name: &str) -> anyhow::Result<()> {
+        let mut chains = self.chains.lock().unwrap();
+        let chain = chains.get_mut(name).context("unknown chain")?;
+        chain.stop.take();
+        Ok(())
+    }
+
+    /// Stops the chain with the given name (if running) and removes it from the list.
+    pub fn remove_chain(&self, name: &str) -> anyhow::Result<()> {
+        self.chains
+            .lock()
+            .unwrap()
+            .remove(name)
+            .context("unknown chain")?;
+        Ok(())
+    }
+
+    /// Statuses of the chains, by name.
+    pub fn chains(&self) -> BTreeMap<String, ChainStatus> {
+        self.chains
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, chain)| (name.clone(), chain.status.clone()))
+            .collect()
+    }
+
+    /// Block store of the chain with the given name.
+    pub fn block_store(&self, name: &str) -> Option<Arc<BlockStore>> {
+        Some(self.chains.lock().unwrap().get(name)?.block_store.clone())
+    }
+
+    /// Records the outcome of running a chain,
+    /// unless the chain has been removed or replaced in the meantime.
+    fn finished(&self, name: &str, id: u64, res: anyhow::Result<()>) {
+        let mut chains = self.chains.lock().unwrap();
+        let Some(chain) = chains.get_mut(name).filter(|c| c.id == id) else {
+            return;
+        };
+        chain.stop = None;
+        chain.status = match res {
+            Ok(()) => ChainStatus::Stopped,
+            Err(err) => ChainStatus::Failed(format!("{err:#}")),
+        };
+    }
+}
+
+impl SupervisorRunner {
+    /// Runs the chains added to the supervisor, until `ctx` is canceled.
+    /// Failures of the chains are recorded in their statuses, rather than returned.
+    pub async fn run(mut self, ctx: &ctx::Ctx) -> anyhow::Result<()> {
+        ChainMetrics::register(Arc::downgrade(&self.supervisor));
+        scope::run!(ctx, |ctx, s| async {
+            while let Ok(start) = self.recv.recv(ctx).await {
+                let supervisor = self.supervisor.clone();
+                let span = tracing::info_span!("chain", name = %start.name);
+                s.spawn_bg(
+                    async move {
+                        let res = run_chain(ctx, start.chain, start.stop).await;
+                        match &res {
+                            Ok(()) => tracing::info!("chain stopped"),
+                            Err(err) => tracing::warn!("chain failed: {err:#}"),
+                        }
+                        supervisor.finished(&start.name, start.id, res);
+                        Ok(())
+                    }
+                    .instrument(span),
+                );
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// Runs a chain, until it is stopped.
+async fn run_chain(
+    ctx: &ctx::Ctx,
+    chain: Chain,
+    stop: oneshot::Receiver<()>,
+) -> anyhow::Result<()> {
+    let stopped = AtomicBool::new(false);
+    let res = scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(chain.block_store_runner.run(ctx));
+        s.spawn_bg(async {
+            // The sender is never used, the chain is stopped by dropping it.
+            if let Ok(Err(_)) = stop.recv_or_disconnected(ctx).await {
+                stopped.store(true, Ordering::Relaxed);
+                s.cancel();
+            }
+            Ok(())
+        });
+        chain.executor.run(ctx).await
+    })
+    .await;
+    // Errors caused by stopping the chain are expected.
+    match stopped.load(Ordering::Relaxed) || !ctx.is_active() {
+        true => Ok(()),
+        false => res,
+    }
+}
+
+/// Per-chain metrics of the supervised chains.
+#[derive(Debug, Metrics)]
+#[metrics(prefix = "zksync_consensus_executor_chain")]
+struct ChainMetrics {
+    /// Whether the chain is running (1) or not (0).
+    #[metrics(labels = ["chain"])]
+    running: LabeledFamily<String, Gauge<u64>>,
+    /// Number of the first block stored by the chain.
+    #[metrics(labels = ["chain"])]
+    first_block: LabeledFamily<String, Gauge<u64>>,
+    /// Number of the next block to be queued by the chain.
+    #[metrics(labels = ["chain"])]
+    next_block: LabeledFamily<String, Gauge<u64>>,
+    /// Number of the next block to be persisted by the chain.
+    #[metrics(labels = ["chain"])]
+    next_persisted_block: LabeledFamily<String, Gauge<u64>>,
+    /// Number of the blocks queued by the chain, which are not persisted yet.
+    #[metrics(labels = ["chain"])]
+    queue_len: LabeledFamily<String, Gauge<usize>>,
+}
+
+impl ChainMetrics {
+    /// Registers a metrics collector for the chains of the supervisor.
+    fn register(supervisor: Weak<Supervisor>) {
+        #[vise::register]
+        static COLLECTOR: Collector<Option<ChainMetrics>> = Collector::new();
+
+        let register_result = COLLECTOR.before_scrape(move || {
+            supervisor.upgrade().map(|supervisor| {
+                let m = ChainMetrics::default();
+                for (name, chain) in supervisor.chains.lock().unwrap().iter() {
+                    let name = name.clone();
+                    let state = chain.block_store.subscribe().borrow().clone();
+                    m.running[&name].set((chain.status == ChainStatus::Running).into());
+                    m.first_block[&name].set(state.first.0);
+                    m.next_block[&name].set(state.next().0);
+                    m.next_persisted_block[&name].set(chain.block_store.persisted().next().0);
+                    m.queue_len[&name].set(chain.block_store.queue_len());
+                }
+                m
+            })
+        });
+        if register_result.is_err() {
+            tracing::warn!("Failed registering chain metrics collector: already registered");
+        }
+    }
+}
@@ -1,5 +1,7 @@
 //! High-level tests for `Executor`.
 use super::*;
+use crate::supervision::Message;
+use rand::Rng as _;
 use zksync_concurrency::testonly::abort_on_panic;
 use zksync_consensus_bft as bft;
 use zksync_consensus_network::testonly::{new_configs, new_fullnode};
@@ -9,24 +11,63 @@ use zksync_consensus_storage::{
     BlockStore,
 };
 
+fn make_config(cfg: &network::Config) -> Config {
+    Config {
+        server_addr: *cfg.server_addr,
+        transport: cfg.transport.clone(),
+        public_addr: cfg.public_addr,
+        public_addr_detection: cfg.public_addr_detection,
+        max_payload_size: usize::MAX,
+        node_key: cfg.gossip.key.clone(),
+        gossip_dynamic_inbound_limit: cfg.gossip.dynamic_inbound_limit,
+        gossip_static_inbound: cfg.gossip.static_inbound.clone(),
+        gossip_static_outbound: cfg.gossip.static_outbound.clone(),
+        gossip_dynamic_outbound_limit: cfg.gossip.dynamic_outbound_limit,
+        gossip_relay_auth: cfg.gossip.relay_auth,
+        gossip_peer_bandwidth_cap: cfg.gossip.peer_bandwidth_cap,
+        genesis_mismatch_quarantine: cfg.genesis_mismatch_quarantine,
+        consensus_replay_window: cfg.consensus_replay_window,
+        consensus_relay_max_hops: cfg.consensus_relay_max_hops,
+        serve_blocks_bandwidth: cfg.serve_blocks_bandwidth,
+        serve_blocks_bandwidth_per_peer: cfg.serve_blocks_bandwidth_per_peer,
+        per_peer_metrics: cfg.per_peer_metrics,
+        rpc: cfg.rpc.clone(),
+        sentry: None,
+        restart_policy: RestartPolicy::default(),
+        crash_dir: None,
+        ntp_servers: vec![],
+        max_clock_skew: network::MAX_CLOCK_SKEW,
+        sync_blocks_cross_check_peers: 1,
+        sync_blocks_sample_chunks: 0,
+        sync_blocks_genesis_check_quorum: None,
+        role: NodeRole::Archive,
+    }
+}
+
+fn make_validator(key: &Arc<dyn validator::ValidatorSigner>) -> Validator {
+    Validator {
+        key: key.clone(),
+        replica_store: Box::new(in_memory::ReplicaStore::default()),
+        payload_manager: Box::new(bft::testonly::RandomPayload(1000)),
+        shadow_proposer: false,
+        verifier_threads: 2,
+        catch_up_threshold: None,
+        observer: false,
+        max_payload_wait: None,
+        checkpoint: None,
+        consensus_lock: None,
+        protocol_upgrade_activation: None,
+        consensus_trace: None,
+        key_rotation: None,
+    }
+}
+
 fn make_executor(cfg: &network::Config, block_store: Arc<BlockStore>) -> Executor {
-    Executor {
-        config: Config {
-            server_addr: *cfg.server_addr,
-            public_addr: cfg.public_addr,
-            max_payload_size: usize::MAX,
-            node_key: cfg.gossip.key.clone(),
-            gossip_dynamic_inbound_limit: cfg.gossip.dynamic_inbound_limit,
-            gossip_static_inbound: cfg.gossip.static_inbound.clone(),
-            gossip_static_outbound: cfg.gossip.static_outbound.clone(),
-        },
-        block_store,
-        validator: cfg.validator_key.as_ref().map(|key| Validator {
-            key: key.clone(),
-            replica_store: Box::new(in_memory::ReplicaStore::default()),
-            payload_manager: Box::new(bft::testonly::RandomPayload(1000)),
-        }),
+    let mut builder = Executor::builder(make_config(cfg), block_store).topics(cfg.topics.clone());
+    if let Some(key) = &cfg.validator_key {
+        builder = builder.validator(make_validator(key));
     }
+    builder.build().unwrap()
 }
 
 #[tokio::test]
@@ -48,6 +89,46 @@ async fn executing_single_validator() {
     .unwrap();
 }
 
+#[tokio::test]
+async fn upgrading_protocol_version() {
+    abort_on_panic();
+    let ctx = &ctx::root();
+    let rng = &mut ctx.rng();
+
+    let setup = Setup::new(rng, 1);
+    let cfgs = new_configs(rng, &setup, 0);
+    let activation = BlockNumber(setup.genesis.fork.first_block.0 + 100);
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let mut validator = make_validator(cfgs[0].validator_key.as_ref().unwrap());
+        validator.protocol_upgrade_activation = Some(activation);
+        let executor = Executor::builder(make_config(&cfgs[0]), store.clone())
+            .topics(cfgs[0].topics.clone())
+            .validator(validator)
+            .build()
+            .unwrap();
+        s.spawn_bg(executor.run(ctx));
+
+        // The announced upgrade is certified, committed by a block and activated.
+        store.wait_until_persisted(ctx, activation).await?;
+        let genesis = store.genesis();
+        assert_eq!(setup.genesis.hash(), genesis.hash());
+        assert_eq!(
+            bft::PROTOCOL_VERSION,
+            genesis.protocol_version_at(activation)
+        );
+        let block = store.block(ctx, activation).await?.unwrap();
+        assert_eq!(
+            bft::PROTOCOL_VERSION.commits_payload_root(),
+            block.header().payload_root.is_some()
+        );
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
 #[tokio::test]
 async fn executing_validator_and_full_node() {
     abort_on_panic();
@@ -74,3 +155,466 @@ async fn executing_validator_and_full_node() {
     .await
     .unwrap();
 }
+
+#[tokio::test]
+async fn building_invalid_executor() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+
+    let setup = Setup::new(rng, 1);
+    let cfgs = new_configs(rng, &setup, 0);
+    let (store, _) = new_store(ctx, &setup.genesis).await;
+
+    // Validator outside of the committee.
+    let key: Arc<dyn validator::ValidatorSigner> = Arc::new(rng.gen::<validator::SecretKey>());
+    let res = Executor::builder(make_config(&cfgs[0]), store.clone())
+        .validator(make_validator(&key))
+        .build();
+    assert!(matches!(res, Err(BuildError::NotInCommittee(got)) if got == key.public()));
+
+    // Validator with a rotated key is in the committee.
+    let mut rotated = setup.clone();
+    let new_key = rotated.rotate_key(rng, 0, validator::ViewNumber(1), 1);
+    let (rotated_store, _) = new_store(ctx, &rotated.genesis).await;
+    let key: Arc<dyn validator::ValidatorSigner> = Arc::new(new_key);
+    Executor::builder(make_config(&cfgs[0]), rotated_store)
+        .validator(make_validator(&key))
+        .build()
+        .unwrap();
+
+    // Sentry backend without a validator.
+    let mut cfg = make_config(&cfgs[0]);
+    cfg.sentry = Some(network::SentryConfig::Backend {
+        sentry_addr: cfgs[0].public_addr,
+        sentry_key: rng.gen::<node::SecretKey>().public(),
+    });
+    let res = Executor::builder(cfg, store.clone()).build();
+    assert!(matches!(res, Err(BuildError::BackendWithoutValidator)));
+
+    // Invalid config.
+    let mut cfg = make_config(&cfgs[0]);
+    cfg.sync_blocks_cross_check_peers = 0;
+    let res = Executor::builder(cfg, store).build();
+    assert!(matches!(
+        res,
+        Err(BuildError::InvalidConfig {
+            field: "sync_blocks_cross_check_peers",
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn executing_pruned_full_node() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::AffineClock::new(20.0));
+    let rng = &mut ctx.rng();
+
+    let setup = Setup::new(rng, 1);
+    let cfgs = new_configs(rng, &setup, 0);
+    scope::run!(ctx, |ctx, s| async {
+        // Spawn validator.
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        s.spawn_bg(make_executor(&cfgs[0], store).run(ctx));
+
+        // Spawn full node retaining only the last 2 blocks.
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let mut cfg = make_config(&new_fullnode(rng, &cfgs[0]));
+        cfg.role = NodeRole::Pruned { retention: 2 };
+        let executor = Executor::builder(cfg, store.clone()).build().unwrap();
+        s.spawn_bg(executor.run(ctx));
+
+        // Wait for the old blocks to be pruned.
+        sync::wait_for(ctx, &mut store.subscribe(), |state| {
+            state.first >= BlockNumber(5)
+        })
+        .await?;
+        assert_eq!(None, store.block(ctx, BlockNumber(0)).await?);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn executing_scheduled_fork() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::AffineClock::new(20.0));
+    let rng = &mut ctx.rng();
+
+    let setup = Setup::new(rng, 1);
+    let cfgs = new_configs(rng, &setup, 0);
+    let first_block = BlockNumber(setup.genesis.fork.first_block.0 + 5);
+    let mut new_genesis = setup.genesis.clone();
+    new_genesis.fork = validator::Fork {
+        number: setup.genesis.fork.number.next(),
+        first_block,
+        first_parent: None,
+    };
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let mut executor = make_executor(&cfgs[0], store.clone());
+        executor.schedule_fork(first_block, new_genesis.clone())?;
+        s.spawn_bg(executor.run(ctx));
+
+        tracing::info!("Wait for the switch to the new fork.");
+        let cut_over = first_block.prev().unwrap();
+        store.wait_until_persisted(ctx, cut_over).await?;
+        let parent = store.block(ctx, cut_over).await?.unwrap();
+        let mut genesis = store.genesis();
+        while genesis.fork.number != new_genesis.fork.number {
+            ctx.sleep(time::Duration::milliseconds(100)).await?;
+            genesis = store.genesis();
+        }
+        assert_eq!(
+            Some(setup.genesis.header_hash(parent.header())),
+            genesis.fork.first_parent
+        );
+
+        tracing::info!("Expect the new fork to make progress.");
+        store.wait_until_persisted(ctx, first_block).await?;
+        let block = store.block(ctx, first_block).await?.unwrap();
+        assert_eq!(genesis.fork.first_parent, block.header().parent);
+        block.verify(&genesis)?;
+        anyhow::Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn supervising_multiple_chains() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::AffineClock::new(20.0));
+    let rng = &mut ctx.rng();
+
+    let setups: Vec<_> = (0..2).map(|_| Setup::new(rng, 1)).collect();
+    scope::run!(ctx, |ctx, s| async {
+        let (supervisor, runner) = Supervisor::new();
+        s.spawn_bg(runner.run(ctx));
+        for (i, setup) in setups.iter().enumerate() {
+            let cfgs = new_configs(rng, setup, 0);
+            let (store, runner) = new_store(ctx, &setup.genesis).await;
+            let chain = Chain {
+                executor: make_executor(&cfgs[0], store),
+                block_store_runner: runner,
+            };
+            supervisor.add_chain(&format!("chain{i}"), chain)?;
+        }
+        // Chains with the same name are rejected.
+        let cfgs = new_configs(rng, &setups[0], 0);
+        let (store, runner) = new_store(ctx, &setups[0].genesis).await;
+        let chain = Chain {
+            executor: make_executor(&cfgs[0], store),
+            block_store_runner: runner,
+        };
+        assert!(supervisor.add_chain("chain0", chain).is_err());
+
+        // Both chains make progress.
+        for name in ["chain0", "chain1"] {
+            let store = supervisor.block_store(name).unwrap();
+            store.wait_until_persisted(ctx, BlockNumber(3)).await?;
+        }
+
+        // Stopping a chain doesn't affect the other one.
+        supervisor.stop_chain("chain0")?;
+        while supervisor.chains()["chain0"] == ChainStatus::Running {
+            ctx.sleep(time::Duration::milliseconds(100)).await?;
+        }
+        assert_eq!(ChainStatus::Stopped, supervisor.chains()["chain0"]);
+        let store = supervisor.block_store("chain1").unwrap();
+        let next = store.subscribe().borrow().next();
+        store.wait_until_persisted(ctx, next).await?;
+        assert_eq!(ChainStatus::Running, supervisor.chains()["chain1"]);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn ingesting_blocks() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+
+    let mut setup = Setup::new(rng, 3);
+    // Chain with the same genesis, conflicting with `setup`.
+    let mut other = setup.clone();
+    setup.push_blocks(rng, 3);
+    other.push_blocks(rng, 1);
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let ingest = BlockIngest::new(store.clone());
+        assert_eq!(
+            Ingested::Queued,
+            ingest.ingest(ctx, setup.blocks[0].clone()).await?
+        );
+        assert_eq!(
+            Ingested::Duplicate,
+            ingest.ingest(ctx, setup.blocks[0].clone()).await?
+        );
+
+        // Blocks conflicting with the stored ones are rejected.
+        assert!(ingest.ingest(ctx, other.blocks[0].clone()).await.is_err());
+
+        // Invalid blocks are rejected.
+        let mut invalid = setup.blocks[1].clone();
+        invalid.payload = rng.gen();
+        assert!(ingest.ingest(ctx, invalid).await.is_err());
+
+        // Blocks ingested out of order wait for the preceding ones.
+        let task = s.spawn(ingest.ingest(ctx, setup.blocks[2].clone()));
+        assert_eq!(
+            Ingested::Queued,
+            ingest.ingest(ctx, setup.blocks[1].clone()).await?
+        );
+        assert_eq!(Ingested::Queued, task.join(ctx).await?);
+        store
+            .wait_until_persisted(ctx, setup.blocks[2].number())
+            .await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn consensus_lock_excludes_other_holders() {
+    abort_on_panic();
+    let rng = &mut ctx::test_root(&ctx::RealClock).rng();
+    let dir = tempfile::tempdir().unwrap();
+    let key: Arc<dyn validator::ValidatorSigner> = Arc::new(rng.gen::<validator::SecretKey>());
+
+    let lock = ConsensusLock::acquire(dir.path().into(), key.clone())
+        .await
+        .unwrap();
+    assert!(ConsensusLock::acquire(dir.path().into(), key.clone())
+        .await
+        .is_err());
+    drop(lock);
+    ConsensusLock::acquire(dir.path().into(), key)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn consensus_lock_watermark() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let dir = tempfile::tempdir().unwrap();
+    let key: Arc<dyn validator::ValidatorSigner> = Arc::new(rng.gen::<validator::SecretKey>());
+    let mut commit = |view| {
+        let mut msg: validator::ReplicaCommit = rng.gen();
+        msg.view.fork = validator::ForkNumber(0);
+        msg.view.number = validator::ViewNumber(view);
+        validator::ConsensusMsg::ReplicaCommit(msg)
+    };
+    let (first, conflicting, older, newer) = (commit(5), commit(5), commit(4), commit(6));
+
+    let lock: Arc<dyn validator::ValidatorSigner> = Arc::new(
+        ConsensusLock::acquire(dir.path().into(), key.clone())
+            .await
+            .unwrap(),
+    );
+    lock.sign_msg(ctx, first.clone()).await.unwrap();
+    // Signing the same message again is harmless.
+    lock.sign_msg(ctx, first.clone()).await.unwrap();
+    assert!(lock.sign_msg(ctx, conflicting.clone()).await.is_err());
+    assert!(lock.sign_msg(ctx, older.clone()).await.is_err());
+    drop(lock);
+
+    // The watermark survives a restart.
+    let lock: Arc<dyn validator::ValidatorSigner> = Arc::new(
+        ConsensusLock::acquire(dir.path().into(), key)
+            .await
+            .unwrap(),
+    );
+    assert!(lock.sign_msg(ctx, conflicting).await.is_err());
+    assert!(lock.sign_msg(ctx, older).await.is_err());
+    lock.sign_msg(ctx, newer).await.unwrap();
+    // Non-slashable messages are not restricted.
+    let timeout = validator::ReplicaTimeout {
+        view: validator::View {
+            protocol_version: validator::ProtocolVersion::EARLIEST,
+            fork: validator::ForkNumber(0),
+            number: validator::ViewNumber(1),
+        },
+    };
+    lock.sign_msg(ctx, validator::ConsensusMsg::ReplicaTimeout(timeout))
+        .await
+        .unwrap();
+}
+
+#[cfg(feature = "grpc")]
+#[tokio::test]
+async fn streaming_blocks_over_grpc() {
+    use zksync_consensus_roles::proto;
+    use zksync_protobuf::ProtoFmt as _;
+
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+
+    let mut setup = Setup::new(rng, 3);
+    setup.push_blocks(rng, 3);
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        store.queue_block(ctx, setup.blocks[0].clone()).await?;
+        let addr = *net::tcp::testonly::reserve_listener();
+        s.spawn_bg(grpc::BlockStreamService::new(store.reader(), 1).run(ctx, addr));
+
+        let endpoint = tonic::transport::Endpoint::from_shared(format!("http://{addr}"))?;
+        // The server might not be listening yet.
+        let channel = loop {
+            if let Ok(channel) = endpoint.connect().await {
+                break channel;
+            }
+            ctx.sleep(time::Duration::milliseconds(100)).await?;
+        };
+        let mut client = tonic::client::Grpc::new(channel);
+        client
+            .ready()
+            .await
+            .map_err(|err| anyhow::format_err!("{err}"))?;
+        let mut stream =
+            client
+                .server_streaming(
+                    tonic::Request::new(grpc::StreamBlocksRequest { from: Some(0) }),
+                    tonic::codegen::http::uri::PathAndQuery::from_static(grpc::STREAM_BLOCKS_PATH),
+                    tonic::codec::ProstCodec::<
+                        grpc::StreamBlocksRequest,
+                        proto::validator::FinalBlock,
+                    >::default(),
+                )
+                .await?
+                .into_inner();
+
+        // Stored blocks are streamed first, then the newly finalized ones as they arrive.
+        for (i, want) in setup.blocks.iter().enumerate() {
+            if i > 0 {
+                store.queue_block(ctx, want.clone()).await?;
+            }
+            let got = stream.message().await?.context("stream ended")?;
+            assert_eq!(want, &validator::FinalBlock::read(&got)?);
+        }
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn supervising_actors() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let policy = RestartPolicy {
+        max_restarts: 2,
+        window: time::Duration::minutes(10),
+        initial_backoff: time::Duration::milliseconds(10),
+        max_backoff: time::Duration::milliseconds(20),
+    };
+
+    // Every incarnation of the actor echoes a single message and fails.
+    let (actor_pipe, mut dispatcher_pipe) = pipe::new::<u64, u64>();
+    let res = scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(async {
+            for i in 0..3 {
+                dispatcher_pipe.send(i);
+                assert_eq!(i, dispatcher_pipe.recv(ctx).await?);
+            }
+            Ok(())
+        });
+        supervise(
+            ctx,
+            Actor::SyncBlocks,
+            &policy,
+            &crash::Crashes::default(),
+            actor_pipe,
+            |mut pipe| async move {
+                let msg = pipe.recv(ctx).await?;
+                pipe.send(msg);
+                anyhow::bail!("failure {msg}")
+            },
+        )
+        .await
+    })
+    .await;
+    let err = format!("{:#}", res.unwrap_err());
+    assert!(err.contains("failed 3 times"), "{err}");
+
+    // Fatal errors are not recovered from.
+    let (actor_pipe, _dispatcher_pipe) = pipe::new::<u64, u64>();
+    let mut incarnations = 0;
+    let res = supervise(
+        ctx,
+        Actor::Consensus,
+        &policy,
+        &crash::Crashes::default(),
+        actor_pipe,
+        |_| {
+            incarnations += 1;
+            async { Err(Fatal(anyhow::format_err!("fatal")).into()) }
+        },
+    )
+    .await;
+    assert!(res.is_err());
+    assert_eq!(incarnations, 1);
+}
+
+impl Message for u64 {
+    fn label(&self) -> &'static str {
+        "u64"
+    }
+}
+
+#[test]
+fn crash_reports() {
+    // Not calling `abort_on_panic()`, since the test panics on purpose.
+    // Unlike in the binaries, panics unwind in the tests, so the test survives its panic.
+    let crash_dir = tempfile::tempdir().unwrap();
+    let crashes = crash::Crashes::new(Some(crash_dir.path().to_path_buf()));
+    crashes.install_panic_hook();
+    crashes.delivered(Actor::SyncBlocks, 7u64.label());
+    assert!(std::panic::catch_unwind(|| panic!("boom")).is_err());
+    // The hook doesn't report the panics once the state is dropped.
+    drop(crashes);
+
+    // Panics of the concurrently running tests might have been reported as well.
+    let reports: Vec<_> = std::fs::read_dir(crash_dir.path())
+        .unwrap()
+        .map(|e| std::fs::read_to_string(e.unwrap().path()).unwrap())
+        .collect();
+    let report = reports.iter().find(|r| r.contains("panic: boom")).unwrap();
+    assert!(
+        report.contains("last message of sync_blocks: u64"),
+        "{report}"
+    );
+    assert!(report.contains("backtrace:"), "{report}");
+}
+
+#[test]
+fn changing_log_filter() {
+    use tracing_subscriber::{layer::SubscriberExt as _, reload, EnvFilter, Registry};
+    let (layer, handle) = reload::Layer::new(EnvFilter::new("info"));
+    let _subscriber = Registry::default().with(layer);
+    let log_filter = LogFilter::new(handle);
+    assert_eq!(log_filter.get().unwrap(), "info");
+
+    let directives =
+        "info,zksync_consensus_network::rpc[gossip{peer=node:public:ed25519:00}]=trace";
+    log_filter.set(directives).unwrap();
+    let got = log_filter.get().unwrap();
+    assert!(got.contains("zksync_consensus_network::rpc"), "{got}");
+
+    // Invalid directives leave the filter unchanged.
+    assert!(log_filter.set("zksync_consensus_network=loud").is_err());
+    assert_eq!(log_filter.get().unwrap(), got);
+}
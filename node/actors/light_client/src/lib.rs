@@ -0,0 +1,356 @@
+//! # Light client
+//!
+//! Syncs only the headers of the finalized blocks together with their justifications
+//! (`CommitQC`s), without the payloads, over the `get_headers` gossip RPC.
+//! Every header is verified against the validator set of the genesis (including the
+//! key rotations) and against its parent, so that the applications which only need the
+//! finality information (bridges, wallets) can trust the headers streamed by the client
+//! without running a full node. The key rotations and protocol upgrades committed by
+//! the headers are applied to the genesis, so that the client follows them.
+use anyhow::Context as _;
+use std::collections::{HashMap, VecDeque};
+use zksync_concurrency::{ctx, scope, sync, time};
+use zksync_consensus_network as network;
+use zksync_consensus_roles::{node, validator};
+use zksync_consensus_storage::BlockStore;
+use zksync_consensus_utils::pipe;
+
+mod store;
+#[cfg(test)]
+mod tests;
+
+/// Max number of the headers requested from a peer at once.
+const BATCH_SIZE: usize = 100;
+/// Delay before fetching again from a peer which didn't have the next header.
+const RETRY_INTERVAL: time::Duration = time::Duration::seconds(1);
+
+/// Error returned when a header is rejected by the light client.
+#[derive(Debug, thiserror::Error)]
+pub enum HeaderError {
+    /// Header is not the next one.
+    #[error("got block {got}, want {want}")]
+    Number {
+        /// got
+        got: validator::BlockNumber,
+        /// want
+        want: validator::BlockNumber,
+    },
+    /// Header doesn't point to the previous one.
+    #[error("parent hash mismatch")]
+    Parent,
+    /// Justification is invalid.
+    #[error("justification: {0:#}")]
+    Justification(#[source] validator::CommitQCVerifyError),
+    /// Key rotation is missing, doesn't match the header or cannot be applied.
+    #[error("key rotation: {0:#}")]
+    KeyRotation(#[source] anyhow::Error),
+    /// Protocol upgrade is missing, doesn't match the header or cannot be applied.
+    #[error("protocol upgrade: {0:#}")]
+    ProtocolUpgrade(#[source] anyhow::Error),
+}
+
+/// Most recent verified headers, consecutive.
+#[derive(Debug, Default)]
+struct Headers(VecDeque<validator::CommitQC>);
+
+impl Headers {
+    fn last(&self) -> Option<&validator::CommitQC> {
+        self.0.back()
+    }
+}
+
+/// Light client, tracking the finalized headers of the chain.
+#[derive(Debug)]
+pub struct LightClient {
+    /// Genesis of the chain, i.e. the validator set certifying the headers,
+    /// with the key rotations and protocol upgrades committed by the verified headers applied.
+    genesis: sync::watch::Sender<validator::Genesis>,
+    /// Most recent verified headers.
+    headers: sync::watch::Sender<Headers>,
+    /// Max number of the headers kept in `headers`.
+    max_headers: usize,
+}
+
+impl LightClient {
+    /// Constructs a light client of the chain with the given genesis.
+    /// If `trusted` is set, the client starts syncing right after the block it certifies,
+    /// otherwise it starts at the first block of the fork. The genesis has to contain
+    /// the key rotations and protocol upgrades committed up to the trusted block.
+    /// Keeps the `max_headers` (at least 1) most recent headers in memory:
+    /// the subscribers lagging further behind skip the older headers.
+    pub fn new(
+        genesis: validator::Genesis,
+        trusted: Option<validator::CommitQC>,
+        max_headers: usize,
+    ) -> Result<Self, HeaderError> {
+        let mut headers = Headers::default();
+        if let Some(qc) = trusted {
+            qc.verify(&genesis).map_err(HeaderError::Justification)?;
+            headers.0.push_back(qc);
+        }
+        Ok(Self {
+            genesis: sync::watch::channel(genesis).0,
+            headers: sync::watch::channel(headers).0,
+            max_headers: max_headers.max(1),
+        })
+    }
+
+    /// Current genesis of the chain, including the known key rotations and protocol upgrades.
+    pub fn genesis(&self) -> validator::Genesis {
+        self.genesis.borrow().clone()
+    }
+
+    /// Adds a rotation of a validator key to the validator set,
+    /// so that the headers signed with the new key are accepted.
+    pub fn add_key_rotation(&self, cert: validator::KeyRotationCert) -> anyhow::Result<()> {
+        let mut res = Ok(());
+        self.genesis.send_if_modified(|genesis| {
+            let current = genesis.clone();
+            res = genesis.key_rotations.add(&current, cert);
+            res.is_ok()
+        });
+        res
+    }
+
+    /// Justification of the last verified header.
+    pub fn last(&self) -> Option<validator::CommitQC> {
+        self.headers.borrow().last().cloned()
+    }
+
+    /// Number of the next header to verify.
+    fn next(&self) -> validator::BlockNumber {
+        match self.headers.borrow().last() {
+            Some(qc) => qc.header().number.next(),
+            None => self.genesis.borrow().fork.first_block,
+        }
+    }
+
+    /// Verifies the header and appends it to the verified headers.
+    /// It has to be the next header after `last()`. The key rotation and protocol upgrade
+    /// committed by the header are applied to the genesis.
+    pub fn push(&self, header: network::FinalHeader) -> Result<(), HeaderError> {
+        let mut res = Ok(());
+        self.headers.send_if_modified(|headers| {
+            let genesis = match self.verify(headers.last(), &header) {
+                Ok(genesis) => genesis,
+                Err(err) => {
+                    res = Err(err);
+                    return false;
+                }
+            };
+            if let Some(genesis) = genesis {
+                self.genesis.send_replace(genesis);
+            }
+            headers.0.push_back(header.justification);
+            while headers.0.len() > self.max_headers {
+                headers.0.pop_front();
+            }
+            true
+        });
+        res
+    }
+
+    /// Verifies that `header` follows `last`. Returns the genesis with the key rotation
+    /// and the protocol upgrade committed by the header applied, if it commits any.
+    fn verify(
+        &self,
+        last: Option<&validator::CommitQC>,
+        header: &network::FinalHeader,
+    ) -> Result<Option<validator::Genesis>, HeaderError> {
+        let qc = &header.justification;
+        let genesis = self.genesis.borrow();
+        let (want, parent) = match last {
+            Some(last) => (
+                last.header().number.next(),
+                Some(genesis.header_hash(last.header())),
+            ),
+            None => (genesis.fork.first_block, genesis.fork.first_parent),
+        };
+        let number = qc.header().number;
+        if number != want {
+            return Err(HeaderError::Number { got: number, want });
+        }
+        if qc.header().parent != parent {
+            return Err(HeaderError::Parent);
+        }
+        qc.verify(&genesis).map_err(HeaderError::Justification)?;
+        if header.key_rotation.as_ref().map(|cert| cert.hash()) != qc.header().key_rotation {
+            return Err(HeaderError::KeyRotation(anyhow::format_err!(
+                "doesn't match the header"
+            )));
+        }
+        if header
+            .protocol_upgrade
+            .as_ref()
+            .map(|upgrade| upgrade.hash())
+            != qc.header().protocol_upgrade
+        {
+            return Err(HeaderError::ProtocolUpgrade(anyhow::format_err!(
+                "doesn't match the header"
+            )));
+        }
+        let mut updated = None;
+        if let Some(cert) = &header.key_rotation {
+            updated = Some(
+                genesis
+                    .with_key_rotation(cert.clone())
+                    .map_err(HeaderError::KeyRotation)?,
+            );
+        }
+        if let Some(upgrade) = &header.protocol_upgrade {
+            updated = Some(
+                updated
+                    .as_ref()
+                    .unwrap_or(&*genesis)
+                    .with_protocol_upgrade(upgrade.clone(), number)
+                    .map_err(HeaderError::ProtocolUpgrade)?,
+            );
+        }
+        Ok(updated)
+    }
+
+    /// Subscribes to the headers verified after `last()`.
+    pub fn subscribe(&self) -> HeaderStream {
+        HeaderStream {
+            next: self.next(),
+            headers: self.headers.subscribe(),
+        }
+    }
+
+    /// Runs the light client: connects to the gossip network and fetches the headers
+    /// from the peers which have advertised them. Peers which serve invalid headers
+    /// are reported as misbehaving. The light client doesn't serve any blocks.
+    pub async fn run(&self, ctx: &ctx::Ctx, mut cfg: network::Config) -> anyhow::Result<()> {
+        cfg.serve_blocks = false;
+        let result: ctx::Result<()> = scope::run!(ctx, |ctx, s| async {
+            let (block_store, runner) =
+                BlockStore::new(ctx, Box::new(store::NoBlocks(self.genesis()))).await?;
+            s.spawn_bg(async { Ok(runner.run(ctx).await?) });
+            let (actor_pipe, dispatcher_pipe) = pipe::new();
+            let (net, runner) = network::Network::new(ctx, cfg, block_store.reader(), actor_pipe);
+            s.spawn_bg(async { Ok(runner.run(ctx).await.context("network")?) });
+            let pipe::Pipe { mut recv, send } = dispatcher_pipe;
+
+            // Last blocks advertised by the peers.
+            let peers = sync::watch::channel(HashMap::<node::PublicKey, _>::new()).0;
+            s.spawn_bg(async {
+                let mut sub = peers.subscribe();
+                loop {
+                    let next = self.next();
+                    let peer = {
+                        let advertised =
+                            sync::wait_for(ctx, &mut sub, |p| p.values().any(|last| *last >= next))
+                                .await?;
+                        let (peer, _) = advertised.iter().max_by_key(|(_, last)| **last).unwrap();
+                        peer.clone()
+                    };
+                    if let Err(err) = self.fetch(ctx, &net, &peer, next).await {
+                        tracing::info!(%err, ?peer, "fetch()");
+                        // Peer will be considered again once it advertises its blocks again.
+                        peers.send_modify(|p| {
+                            p.remove(&peer);
+                        });
+                        if let FetchError::Misbehaved(_) = err {
+                            send.send(
+                                network::io::SyncBlocksInputMessage::Misbehaved { peer }.into(),
+                            );
+                        }
+                        ctx.sleep(RETRY_INTERVAL).await?;
+                    }
+                }
+            });
+
+            loop {
+                match recv.recv(ctx).await? {
+                    network::io::OutputMessage::SyncBlocks(
+                        network::io::SyncBlocksRequest::UpdatePeerSyncState {
+                            peer,
+                            state,
+                            response,
+                        },
+                    ) => {
+                        if let Some(last) = &state.last {
+                            let last = last.header().number;
+                            peers.send_modify(|p| {
+                                p.insert(peer, last);
+                            });
+                        }
+                        response.send(()).ok();
+                    }
+                    network::io::OutputMessage::SyncBlocks(
+                        network::io::SyncBlocksRequest::UpdatePeerRtt { .. },
+                    ) => {}
+                    // Light client is not a validator, so it doesn't receive consensus messages.
+                    network::io::OutputMessage::Consensus(req) => {
+                        req.ack.send(()).ok();
+                    }
+                }
+            }
+        })
+        .await;
+        result.or_else(|err| match err {
+            ctx::Error::Canceled(_) => Ok(()),
+            ctx::Error::Internal(err) => Err(err),
+        })
+    }
+
+    /// Fetches the headers starting at `next` from `peer` and verifies them.
+    async fn fetch(
+        &self,
+        ctx: &ctx::Ctx,
+        net: &network::Network,
+        peer: &node::PublicKey,
+        next: validator::BlockNumber,
+    ) -> Result<(), FetchError> {
+        let headers = net
+            .get_headers(ctx, peer, next, BATCH_SIZE)
+            .await
+            .map_err(FetchError::Network)?;
+        if headers.is_empty() {
+            return Err(FetchError::Network(anyhow::format_err!(
+                "peer doesn't have block {next}"
+            )));
+        }
+        for header in headers {
+            self.push(header).map_err(FetchError::Misbehaved)?;
+        }
+        Ok(())
+    }
+}
+
+/// Error of fetching the headers from a peer.
+#[derive(Debug, thiserror::Error)]
+enum FetchError {
+    /// Peer is unreachable or doesn't have the headers.
+    #[error(transparent)]
+    Network(anyhow::Error),
+    /// Peer has served an invalid header.
+    #[error("invalid header: {0:#}")]
+    Misbehaved(#[source] HeaderError),
+}
+
+/// Stream of the verified headers, see [`LightClient::subscribe`].
+#[derive(Debug)]
+pub struct HeaderStream {
+    /// Number of the next header to return.
+    next: validator::BlockNumber,
+    headers: sync::watch::Receiver<Headers>,
+}
+
+impl HeaderStream {
+    /// Waits for the next verified header and returns its justification.
+    /// If the stream lags behind by more than `max_headers`, the headers
+    /// no longer kept by the client are skipped.
+    pub async fn next(&mut self, ctx: &ctx::Ctx) -> ctx::OrCanceled<validator::CommitQC> {
+        let next = self.next;
+        let headers = sync::wait_for(ctx, &mut self.headers, |headers| {
+            headers.last().is_some_and(|qc| qc.header().number >= next)
+        })
+        .await?;
+        let first = headers.0.front().unwrap().header().number;
+        let next = next.max(first);
+        let qc = headers.0[(next.0 - first.0) as usize].clone();
+        self.next = next.next();
+        Ok(qc)
+    }
+}
@@ -0,0 +1,40 @@
+//! Block store of the light client, which doesn't store any blocks.
+//! The network actor requires a block store to serve the blocks from;
+//! the light client doesn't serve any (see `Config::serve_blocks`).
+use zksync_concurrency::ctx;
+use zksync_consensus_roles::validator;
+use zksync_consensus_storage::PersistentBlockStore;
+
+/// Persistent block store without blocks.
+#[derive(Debug)]
+pub(crate) struct NoBlocks(pub(crate) validator::Genesis);
+
+#[async_trait::async_trait]
+impl PersistentBlockStore for NoBlocks {
+    async fn genesis(&self, _ctx: &ctx::Ctx) -> ctx::Result<validator::Genesis> {
+        Ok(self.0.clone())
+    }
+
+    async fn last(&self, _ctx: &ctx::Ctx) -> ctx::Result<Option<validator::CommitQC>> {
+        Ok(None)
+    }
+
+    async fn block(
+        &self,
+        _ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<validator::FinalBlock> {
+        Err(
+            anyhow::format_err!("block {number} not available: light client doesn't store blocks")
+                .into(),
+        )
+    }
+
+    async fn store_next_block(
+        &self,
+        _ctx: &ctx::Ctx,
+        _block: &validator::FinalBlock,
+    ) -> ctx::Result<()> {
+        Err(anyhow::format_err!("light client doesn't store blocks").into())
+    }
+}
@@ -0,0 +1,195 @@
+use super::*;
+use assert_matches::assert_matches;
+use rand::Rng;
+use zksync_concurrency::testonly::abort_on_panic;
+use zksync_consensus_network::testonly;
+use zksync_consensus_storage::testonly::new_store;
+
+#[tokio::test]
+async fn syncing_headers() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 2);
+    setup.push_blocks(rng, 5);
+    let cfgs = testonly::new_configs(rng, &setup, 1);
+    let client_cfg = testonly::new_fullnode(rng, &cfgs[0]);
+    let client = LightClient::new(setup.genesis.clone(), None, 3).unwrap();
+    let mut headers = client.subscribe();
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let (_node, runner) = testonly::Instance::new(ctx, cfgs[0].clone(), store.clone());
+        s.spawn_bg(runner.run(ctx));
+        s.spawn_bg(client.run(ctx, client_cfg));
+
+        for block in &setup.blocks[..2] {
+            store.queue_block(ctx, block.clone()).await.unwrap();
+        }
+        for block in &setup.blocks[..2] {
+            assert_eq!(block.justification, headers.next(ctx).await.unwrap());
+        }
+        // The headers of the new blocks are synced as they get finalized.
+        for block in &setup.blocks[2..] {
+            store.queue_block(ctx, block.clone()).await.unwrap();
+            assert_eq!(block.justification, headers.next(ctx).await.unwrap());
+        }
+        assert_eq!(
+            Some(&setup.blocks.last().unwrap().justification),
+            client.last().as_ref()
+        );
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn verifying_headers() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 2);
+    setup.push_blocks(rng, 4);
+    let mut other = validator::testonly::Setup::new(rng, 2);
+    other.push_blocks(rng, 4);
+
+    // Starting from a trusted header.
+    let client = LightClient::new(
+        setup.genesis.clone(),
+        Some(setup.blocks[1].justification.clone()),
+        10,
+    )
+    .unwrap();
+    assert_matches!(
+        client.push(network::FinalHeader::from(&setup.blocks[3])),
+        Err(HeaderError::Number { .. })
+    );
+    // Header signed by a different validator set.
+    assert_matches!(
+        client.push(network::FinalHeader::from(&other.blocks[2])),
+        Err(HeaderError::Number { .. } | HeaderError::Parent | HeaderError::Justification(_))
+    );
+    client
+        .push(network::FinalHeader::from(&setup.blocks[2]))
+        .unwrap();
+    client
+        .push(network::FinalHeader::from(&setup.blocks[3]))
+        .unwrap();
+    assert_eq!(Some(setup.blocks[3].justification.clone()), client.last());
+
+    // Trusted header has to be valid.
+    assert_matches!(
+        LightClient::new(
+            setup.genesis.clone(),
+            Some(other.blocks[0].justification.clone()),
+            10
+        ),
+        Err(HeaderError::Justification(_))
+    );
+}
+
+/// Finalizes the header following `parent`, signed with `keys`: a header which the test setup
+/// cannot produce, since it doesn't sign with the rotated keys.
+fn finalize_next(
+    rng: &mut impl Rng,
+    genesis: &validator::Genesis,
+    keys: &[validator::SecretKey],
+    parent: &validator::CommitQC,
+) -> validator::CommitQC {
+    let number = parent.header().number.next();
+    let version = genesis.protocol_version_at(number);
+    let payload: validator::Payload = rng.gen();
+    let msg = validator::ReplicaCommit {
+        view: validator::View {
+            protocol_version: version,
+            fork: genesis.fork.number,
+            number: parent.view().number.next(),
+        },
+        proposal: validator::BlockHeader {
+            parent: Some(genesis.header_hash(parent.header())),
+            number,
+            payload: payload.hash(),
+            payload_root: version.commits_payload_root().then(|| payload.root()),
+            key_rotation: None,
+            protocol_upgrade: None,
+        },
+    };
+    let mut qc = validator::CommitQC::new(msg.clone(), genesis);
+    for key in keys {
+        qc.add(&key.sign_msg(msg.clone()), genesis);
+    }
+    qc
+}
+
+#[test]
+fn following_key_rotation_and_protocol_upgrade() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 2);
+    setup.push_blocks(rng, 1);
+    // Validator 0 rotates its key at view 3 and the chain upgrades to blake2b-256 hashing
+    // at the 4th block.
+    let (new_key, cert) = setup.key_rotation(rng, 0, validator::ViewNumber(3), 0);
+    setup.push_block_with_key_rotation(rng.gen(), Some(cert.clone()));
+    let activation = validator::BlockNumber(setup.genesis.fork.first_block.0 + 3);
+    let upgrade = setup.protocol_upgrade(validator::ProtocolVersion::BLAKE2_HASHING, activation);
+    setup.push_block_with_protocol_upgrade(rng.gen(), Some(upgrade.clone()));
+
+    // The rotation and the upgrade have to be sent with the headers committing them.
+    let client = LightClient::new(setup.genesis.clone(), None, 10).unwrap();
+    client.push((&setup.blocks[0]).into()).unwrap();
+    let mut header = network::FinalHeader::from(&setup.blocks[1]);
+    header.key_rotation = None;
+    assert_matches!(client.push(header), Err(HeaderError::KeyRotation(_)));
+    client.push((&setup.blocks[1]).into()).unwrap();
+    let mut header = network::FinalHeader::from(&setup.blocks[2]);
+    header.protocol_upgrade = None;
+    assert_matches!(client.push(header), Err(HeaderError::ProtocolUpgrade(_)));
+    client.push((&setup.blocks[2]).into()).unwrap();
+    let genesis = client.genesis();
+    assert!(genesis.key_rotations.contains(&cert));
+    assert!(genesis.protocol_upgrades.contains(&upgrade));
+
+    // The following headers are signed with the rotated key, and linked with
+    // the hashes of the upgraded scheme after the activation.
+    let keys = [new_key, setup.keys[1].clone()];
+    let mut parent = setup.blocks[2].justification.clone();
+    for _ in 0..2 {
+        let qc = finalize_next(rng, &genesis, &keys, &parent);
+        assert!(qc.verify(&setup.genesis).is_err());
+        client
+            .push(network::FinalHeader {
+                justification: qc.clone(),
+                key_rotation: None,
+                protocol_upgrade: None,
+            })
+            .unwrap();
+        parent = qc;
+    }
+    assert_eq!(
+        validator::ProtocolVersion::BLAKE2_HASHING,
+        parent.view().protocol_version
+    );
+    assert_eq!(Some(parent), client.last());
+}
+
+#[tokio::test]
+async fn lagging_subscriber() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 1);
+    setup.push_blocks(rng, 5);
+    let client = LightClient::new(setup.genesis.clone(), None, 2).unwrap();
+    let mut headers = client.subscribe();
+    for block in &setup.blocks {
+        client.push(block.into()).unwrap();
+    }
+    // Only the last 2 headers are kept.
+    for block in &setup.blocks[3..] {
+        assert_eq!(block.justification, headers.next(ctx).await.unwrap());
+    }
+}
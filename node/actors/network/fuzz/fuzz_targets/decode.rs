@@ -0,0 +1,8 @@
+//! Decodes the handshakes and the RPC messages received from a peer.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use zksync_consensus_network::fuzz;
+
+fuzz_target!(|input: fuzz::DecodeInput| {
+    let _ = fuzz::decode_target(&input);
+});
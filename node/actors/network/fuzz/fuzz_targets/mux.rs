@@ -0,0 +1,8 @@
+//! Runs an RPC service over a connection, on which a peer sends arbitrary frames.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use zksync_consensus_network::fuzz;
+
+fuzz_target!(|input: fuzz::MuxInput| {
+    let _ = fuzz::mux_target(&input);
+});
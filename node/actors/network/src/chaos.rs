@@ -0,0 +1,48 @@
+//! Fault injection at the frame layer, enabled with the `chaos` feature.
+//! Delays and drops the frames sent over the real connections of the node,
+//! so that a deployed devnet can be soak-tested under degraded network conditions
+//! without the external tooling (tc/netem). Frames sent concurrently over different
+//! RPC streams get delayed independently, hence reordered.
+//! The faults are configured per network (see `Config::chaos`) and drawn from `ctx.rng()`,
+//! so that they are deterministic in tests.
+//!
+//! NEVER enable it in production: a dropped frame terminates the RPC it belongs to.
+use crate::frame;
+use rand::Rng as _;
+use zksync_concurrency::{ctx, time};
+
+/// Faults to inject.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Config {
+    /// Latency added to every frame.
+    pub latency: time::Duration,
+    /// Max random latency added on top of `latency`, chosen independently for every frame.
+    pub jitter: time::Duration,
+    /// Probability of dropping a frame, in range `[0,1]`.
+    pub drop_rate: f64,
+}
+
+impl Config {
+    /// Delays the frame about to be sent, or drops it.
+    pub(crate) async fn inject(&self, ctx: &ctx::Ctx) -> Result<(), frame::Error> {
+        let (delay, drop) = {
+            let rng = &mut ctx.rng();
+            let jitter = self.jitter.whole_microseconds().max(0) as u64;
+            let jitter = match jitter {
+                0 => time::Duration::ZERO,
+                n => time::Duration::microseconds(rng.gen_range(0..=n) as i64),
+            };
+            (
+                self.latency + jitter,
+                rng.gen_bool(self.drop_rate.clamp(0., 1.)),
+            )
+        };
+        ctx.sleep(delay).await?;
+        if drop {
+            return Err(frame::Error::Io(anyhow::format_err!(
+                "chaos: frame dropped"
+            )));
+        }
+        Ok(())
+    }
+}
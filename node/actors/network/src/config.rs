@@ -1,23 +1,44 @@
 //! Network actor configs.
-use std::collections::{HashMap, HashSet};
+use crate::{Monitor, ReconnectConfig, TimeSource, Topics};
+use rand::Rng;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 use zksync_concurrency::{limiter, net, time};
 use zksync_consensus_roles::{node, validator};
 
-/// How often we should retry to establish a connection to a validator.
-/// TODO(gprusak): once it becomes relevant, choose a more appropriate retry strategy.
-pub(crate) const CONNECT_RETRY: time::Duration = time::Duration::seconds(20);
-
 /// Rate limiting config for RPCs.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct RpcConfig {
     /// Max rate of sending/receiving push_validator_addrs messages.
     pub push_validator_addrs_rate: limiter::Rate,
     /// Max rate of sending/receiving push_block_store_state messages.
     pub push_block_store_state_rate: limiter::Rate,
-    /// Max rate of sending/receiving get_block RPCs.
+    /// Schedule of pushing the block store state changes, within `push_block_store_state_rate`.
+    pub push_block_store_state_schedule: PushSchedule,
+    /// Max rate of sending get_block RPCs.
+    /// They are sent only to the peers which don't support get_block_chunk;
+    /// the rate of serving them is `get_block_server_rate`.
     pub get_block_rate: limiter::Rate,
+    /// Max rate of sending/receiving get_block_chunk RPCs.
+    pub get_block_chunk_rate: limiter::Rate,
     /// Max rate of sending/receiving consensus messages.
     pub consensus_rate: limiter::Rate,
+    /// Max rate of serving push_validator_addrs messages.
+    /// Calls above this rate are refused with a `rate_limited` response.
+    /// Should be higher than `push_validator_addrs_rate` of the honest peers.
+    pub push_validator_addrs_server_rate: limiter::Rate,
+    /// Max rate of serving push_block_store_state messages.
+    /// Calls above this rate are refused with a `rate_limited` response.
+    /// Should be higher than `push_block_store_state_rate` of the honest peers.
+    pub push_block_store_state_server_rate: limiter::Rate,
+    /// Max rate of serving get_block RPCs.
+    /// Calls above this rate are refused with a `rate_limited` response.
+    /// Should be higher than `get_block_rate` of the honest peers.
+    pub get_block_server_rate: limiter::Rate,
 }
 
 impl Default for RpcConfig {
@@ -31,18 +52,130 @@ impl Default for RpcConfig {
                 burst: 2,
                 refresh: time::Duration::milliseconds(500),
             },
+            push_block_store_state_schedule: PushSchedule::default(),
             get_block_rate: limiter::Rate {
                 burst: 10,
                 refresh: time::Duration::milliseconds(100),
             },
+            get_block_chunk_rate: limiter::Rate {
+                burst: 100,
+                refresh: time::Duration::milliseconds(10),
+            },
             consensus_rate: limiter::Rate {
                 burst: 10,
                 refresh: time::Duration::ZERO,
             },
+            // Server rates leave some slack over the client rates above,
+            // to tolerate the network jitter.
+            push_validator_addrs_server_rate: limiter::Rate {
+                burst: 2,
+                refresh: time::Duration::seconds(4),
+            },
+            push_block_store_state_server_rate: limiter::Rate {
+                burst: 4,
+                refresh: time::Duration::milliseconds(400),
+            },
+            get_block_server_rate: limiter::Rate {
+                burst: 20,
+                refresh: time::Duration::milliseconds(80),
+            },
+        }
+    }
+}
+
+/// Adaptive schedule of pushing the `BlockStoreState` to the peers.
+/// A change of the head (the last stored block) is pushed immediately, so that the new
+/// blocks propagate with minimal latency. Other changes (e.g. gaps being filled, or blocks
+/// being pruned) are batched: they are pushed after a delay, which starts at `min_delay`
+/// and doubles with every consecutive push without a head change, up to `max_delay`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushSchedule {
+    /// Delay of the first push without a head change.
+    pub min_delay: time::Duration,
+    /// Cap on the delay of the pushes without a head change.
+    pub max_delay: time::Duration,
+    /// Max random delay added to every delayed push,
+    /// so that the nodes don't push to their peers in lockstep.
+    pub jitter: time::Duration,
+}
+
+impl Default for PushSchedule {
+    fn default() -> Self {
+        Self {
+            min_delay: time::Duration::milliseconds(500),
+            max_delay: time::Duration::seconds(10),
+            jitter: time::Duration::milliseconds(250),
         }
     }
 }
 
+impl PushSchedule {
+    /// Verifies correctness of the config.
+    pub(crate) fn verify(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.min_delay.is_negative(),
+            "min_delay cannot be negative"
+        );
+        anyhow::ensure!(
+            self.max_delay >= self.min_delay,
+            "max_delay has to be at least min_delay"
+        );
+        anyhow::ensure!(!self.jitter.is_negative(), "jitter cannot be negative");
+        Ok(())
+    }
+
+    /// Delay of a push, after `idle` consecutive pushes without a head change.
+    pub(crate) fn delay(&self, rng: &mut impl Rng, idle: u32) -> time::Duration {
+        let d = self
+            .min_delay
+            .saturating_mul(1 << idle.min(30))
+            .min(self.max_delay);
+        let jitter = i64::try_from(self.jitter.whole_nanoseconds()).unwrap_or(i64::MAX);
+        d + time::Duration::nanoseconds(rng.gen_range(0..=jitter))
+    }
+}
+
+/// Authentication of the finalized blocks relayed over the gossip network.
+/// Relayed blocks are always authenticated by their `CommitQC`; relay authentication
+/// additionally lets the consumer verify which node has served the block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RelayAuth {
+    /// Served blocks are not signed.
+    /// Relay signatures attached by the peers are still verified.
+    #[default]
+    Disabled,
+    /// Served blocks are signed with the node key.
+    Sign,
+    /// Served blocks are signed with the node key, and blocks received
+    /// without a relay signature of the serving peer are rejected.
+    Require,
+}
+
+/// Transport over which the network connections are established.
+/// Nodes are addressed by socket addresses regardless of the transport.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Transport {
+    /// TCP connections.
+    #[default]
+    Tcp,
+    /// Unix domain sockets, for the nodes running on the same host.
+    /// Address `ip:port` is served by the socket file `<ip>_<port>.sock`
+    /// in the given directory. Available on unix platforms only.
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// In-memory connections, for the nodes running in the same process.
+    InProcess,
+}
+
+/// Auto-detection of the public IP of the node (for the nodes behind NAT).
+/// The static outbound peers report the address they observe during the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicAddrDetection {
+    /// Number of the peers which have to report the same IP, before it is adopted.
+    /// At least 2 reports are always required, and they have to be a majority of the reports.
+    pub min_reports: usize,
+}
+
 /// Gossip network configuration.
 #[derive(Debug, Clone)]
 pub struct GossipConfig {
@@ -56,6 +189,108 @@ pub struct GossipConfig {
     /// Outbound connections that the node should actively try to
     /// establish and maintain.
     pub static_outbound: HashMap<node::PublicKey, std::net::SocketAddr>,
+    /// Limit on the number of outbound connections to the peers discovered via
+    /// the peer exchange (outside of the `static_outbound` set). These connections
+    /// keep the node connected to the network when the static peers go down.
+    /// 0 disables dialing the discovered peers.
+    pub dynamic_outbound_limit: usize,
+    /// Authentication of the blocks relayed to/from the peers.
+    pub relay_auth: RelayAuth,
+    /// Max rate of the traffic received from a single gossip peer, in bytes per second.
+    /// Peers persistently exceeding it are disconnected. `None` means unlimited.
+    /// Block sync responses don't count towards it: they are requested by this node,
+    /// which throttles them instead.
+    pub peer_bandwidth_cap: Option<usize>,
+    /// Latest protocol version supported by this node, announced to the peers in the handshake.
+    /// Blocks of later versions are neither served to nor requested from a peer.
+    pub protocol_version: validator::ProtocolVersion,
+}
+
+/// Keepalive of the peer connections, independent of the application-level pings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepaliveConfig {
+    /// Interval at which the keepalive frames are sent.
+    pub interval: time::Duration,
+    /// Connection is dropped if nothing has been received from the peer for `timeout`.
+    /// Should be a few times larger than the `interval`.
+    /// Both are negotiated with every peer in the handshake.
+    pub timeout: time::Duration,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            interval: time::Duration::seconds(5),
+            timeout: time::Duration::seconds(20),
+        }
+    }
+}
+
+impl KeepaliveConfig {
+    /// Verifies correctness of the config.
+    pub(crate) fn verify(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.interval.is_positive(), "interval has to be positive");
+        anyhow::ensure!(
+            self.timeout > self.interval,
+            "timeout has to be larger than interval"
+        );
+        Ok(())
+    }
+
+    /// Keepalive of a connection to a peer with keepalive config `peer`.
+    /// Both peers send the KEEPALIVE frames at the lower of the intervals and wait
+    /// for the higher of the timeouts, so that neither drops the other one as dead.
+    pub(crate) fn negotiate(&self, peer: &Self) -> Self {
+        Self {
+            interval: self.interval.min(peer.interval),
+            timeout: self.timeout.max(peer.timeout),
+        }
+    }
+}
+
+/// Outbound queue of the consensus messages to a single validator.
+/// Protects against a slow validator: the messages for it don't pile up indefinitely
+/// and the stale ones are dropped instead of being delivered late.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutboundQueueConfig {
+    /// Max number of the messages queued for a validator. When the queue is full,
+    /// the lowest priority message (the oldest one among equal priorities) is dropped.
+    pub capacity: usize,
+    /// Messages which have been queued for longer than `ttl` are dropped instead of sent.
+    pub ttl: time::Duration,
+}
+
+impl Default for OutboundQueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 32,
+            ttl: time::Duration::seconds(10),
+        }
+    }
+}
+
+/// Role of the node in the sentry architecture, in which the validator key is kept
+/// on a backend node which is not exposed to the network. The backend is connected
+/// only to its sentry node over a private link; the sentry runs the consensus network
+/// with its own node key on behalf of the validator and forwards the consensus messages
+/// between the network and the backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SentryConfig {
+    /// This node is the sentry of the validator with the given key.
+    /// `Config::validator_key` has to be `None`: the network-level proofs of the validator
+    /// identity (handshakes, address announcements, heartbeats) are signed by the backend.
+    Sentry {
+        /// Validator key held by the backend.
+        validator: validator::PublicKey,
+    },
+    /// This node is the backend, holding the validator key.
+    /// Instead of connecting to the other validators, it connects to its sentry.
+    Backend {
+        /// Address of the private link endpoint of the sentry.
+        sentry_addr: std::net::SocketAddr,
+        /// Node key of the sentry.
+        sentry_key: node::PublicKey,
+    },
 }
 
 /// Network actor config.
@@ -63,20 +298,123 @@ pub struct GossipConfig {
 pub struct Config {
     /// TCP socket address to listen for inbound connections at.
     pub server_addr: net::tcp::ListenerAddr,
+    /// Transport over which the connections are established.
+    pub transport: Transport,
     /// Public TCP address that other nodes are expected to connect to.
     /// It is announced over gossip network.
     pub public_addr: std::net::SocketAddr,
+    /// If set, the IP of `public_addr` is replaced with the one observed by the peers.
+    /// The detected address is re-announced whenever it changes.
+    pub public_addr_detection: Option<PublicAddrDetection>,
     /// Gossip network config.
     pub gossip: GossipConfig,
-    /// Private key of the validator.
+    /// Signer with the key of the validator.
     /// None if the node is NOT a validator.
-    pub validator_key: Option<validator::SecretKey>,
+    pub validator_key: Option<Arc<dyn validator::ValidatorSigner>>,
+    /// Role of the node in the sentry architecture.
+    /// `None` if the node is connected to the other validators directly.
+    pub sentry: Option<SentryConfig>,
     /// Maximal size of the proto-encoded `validator::FinalBlock` in bytes.
     pub max_block_size: usize,
     /// If a peer doesn't respond to a ping message within `ping_timeout`,
     /// the connection is dropped.
     /// `None` disables sending ping messages (useful for tests).
     pub ping_timeout: Option<time::Duration>,
+    /// Keepalive of the connections at the transport level, which detects half-open
+    /// connections (NAT timeouts, crashed peers) and drops them from the connection pools.
+    /// `None` disables it.
+    pub keepalive: Option<KeepaliveConfig>,
+    /// How long a peer with a different genesis is not redialed
+    /// after a failed handshake.
+    pub genesis_mismatch_quarantine: time::Duration,
+    /// Number of the most recent views within which the consensus messages
+    /// received from a peer are deduplicated. Messages for older views are dropped.
+    /// 0 disables the replay protection.
+    pub consensus_replay_window: u64,
+    /// Max number of hops over which the consensus messages are relayed via the gossip network,
+    /// when the direct connection to a validator is missing.
+    /// 0 disables relaying.
+    pub consensus_relay_max_hops: u32,
+    /// Outbound queues of the consensus messages to the directly connected validators.
+    pub consensus_outbound_queue: OutboundQueueConfig,
+    /// Whether the node serves the blocks from its block store to the peers,
+    /// and advertises its `BlockStoreState` to them.
+    pub serve_blocks: bool,
+    /// Max rate of serving blocks to all peers in total, in bytes per second.
+    /// Requests exceeding the budget are queued for a while and then shed,
+    /// so that serving blocks doesn't starve the consensus traffic.
+    /// `None` means unlimited.
+    pub serve_blocks_bandwidth: Option<usize>,
+    /// Max rate of serving blocks to a single peer, in bytes per second.
+    /// `None` means unlimited.
+    pub serve_blocks_bandwidth_per_peer: Option<usize>,
+    /// Whether to report the traffic, RPC and consensus message metrics per peer
+    /// (labeled with a prefix of the peer's public key). Makes it possible to pinpoint
+    /// a misbehaving peer, at the cost of the metrics cardinality growing with the network size.
+    pub per_peer_metrics: bool,
     /// Rate limiting config for RPCs.
     pub rpc: RpcConfig,
+    /// Application-defined topics gossiped over the connections.
+    pub topics: Topics,
+    /// Reconnect policy of the connections to the static gossip peers and to the validators.
+    pub reconnect: ReconnectConfig,
+    /// Validity of the session tickets issued to the peers. A peer holding a valid ticket
+    /// is authenticated without verifying its signature, which makes reconnecting after
+    /// a restart cheap. `None` disables issuing and accepting the tickets.
+    pub session_ticket_ttl: Option<time::Duration>,
+    /// Source of the UTC timestamps of the node (pings, heartbeats, addresses).
+    pub time_source: TimeSource,
+    /// State of the network observable from outside (peer pings and traffic, metrics).
+    pub monitor: Monitor,
+    /// Faults injected into the frames sent by the node, see [`crate::chaos`].
+    #[cfg(feature = "chaos")]
+    pub chaos: Option<crate::chaos::Config>,
+}
+
+impl Config {
+    /// Extracts the part of the config which can be changed while the node is running.
+    pub fn reloadable(&self) -> ReloadableConfig {
+        ReloadableConfig {
+            gossip_static_inbound: self.gossip.static_inbound.clone(),
+            gossip_static_outbound: self.gossip.static_outbound.clone(),
+            gossip_dynamic_inbound_limit: self.gossip.dynamic_inbound_limit,
+            serve_blocks_bandwidth: self.serve_blocks_bandwidth,
+            serve_blocks_bandwidth_per_peer: self.serve_blocks_bandwidth_per_peer,
+            rpc: self.rpc.clone(),
+        }
+    }
+
+    /// Validator key on behalf of which this node receives the consensus messages:
+    /// its own validator key, or the key of its backend if this node is a sentry.
+    pub(crate) fn consensus_key(&self) -> Option<validator::PublicKey> {
+        if let Some(SentryConfig::Sentry { validator }) = &self.sentry {
+            return Some(validator.clone());
+        }
+        self.validator_key.as_ref().map(|k| k.public())
+    }
+}
+
+/// Part of the network config which can be changed while the node is running,
+/// see `Network::reload`. The values from `Config` are the initial ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReloadableConfig {
+    /// See `GossipConfig::static_inbound`.
+    /// Inbound connections of the peers removed from the set are kept,
+    /// but count towards `gossip_dynamic_inbound_limit` from now on.
+    pub gossip_static_inbound: HashSet<node::PublicKey>,
+    /// See `GossipConfig::static_outbound`.
+    /// Connections to the removed peers (or to the peers with a changed address) are dropped.
+    pub gossip_static_outbound: HashMap<node::PublicKey, std::net::SocketAddr>,
+    /// See `GossipConfig::dynamic_inbound_limit`.
+    /// Lowering the limit doesn't drop the connections already established.
+    pub gossip_dynamic_inbound_limit: usize,
+    /// See `Config::serve_blocks_bandwidth`.
+    pub serve_blocks_bandwidth: Option<usize>,
+    /// See `Config::serve_blocks_bandwidth_per_peer`.
+    /// Applies to the connections established after the reload.
+    pub serve_blocks_bandwidth_per_peer: Option<usize>,
+    /// See `Config::rpc`. Applies to the connections established after the reload.
+    /// The rate of the consensus clients created when the network starts and
+    /// `push_block_store_state_schedule` are not reloaded.
+    pub rpc: RpcConfig,
 }
@@ -1,11 +1,15 @@
-use crate::{frame, noise, proto::consensus as proto};
+use crate::{
+    frame, metrics, noise,
+    proto::consensus as proto,
+    tickets::{Proof, Tickets},
+};
 use anyhow::Context as _;
 use zksync_concurrency::{ctx, time};
 use zksync_consensus_crypto::ByteFmt;
 use zksync_consensus_roles::{node, validator};
-use zksync_protobuf::{read_required, ProtoFmt};
+use zksync_protobuf::{kB, read_optional, read_required, ProtoFmt};
 
-#[cfg(test)]
+#[cfg(any(test, feature = "fuzzing"))]
 mod testonly;
 #[cfg(test)]
 mod tests;
@@ -22,20 +26,28 @@ pub(crate) struct Handshake {
     /// Hash of the blockchain genesis specification.
     /// Only nodes with the same genesis belong to the same network.
     pub(crate) genesis: validator::GenesisHash,
+    /// Proof of the possession of a session ticket issued by the receiver.
+    /// If valid, the receiver doesn't verify the signature of `session_id`.
+    pub(crate) resume: Option<Proof>,
 }
 
 impl ProtoFmt for Handshake {
     type Proto = proto::Handshake;
+    fn max_size() -> usize {
+        10 * kB
+    }
     fn read(r: &Self::Proto) -> anyhow::Result<Self> {
         Ok(Self {
             session_id: read_required(&r.session_id).context("session_id")?,
             genesis: read_required(&r.genesis).context("genesis")?,
+            resume: read_optional(&r.resume).context("resume")?,
         })
     }
     fn build(&self) -> Self::Proto {
         Self::Proto {
             session_id: Some(self.session_id.build()),
             genesis: Some(self.genesis.build()),
+            resume: self.resume.as_ref().map(ProtoFmt::build),
         }
     }
 }
@@ -43,22 +55,36 @@ impl ProtoFmt for Handshake {
 /// Error returned by handshake logic.
 #[derive(Debug, thiserror::Error)]
 pub(super) enum Error {
-    #[error("genesis mismatch")]
-    GenesisMismatch,
+    #[error("genesis mismatch: peer {peer:?} has {genesis:?}")]
+    GenesisMismatch {
+        peer: validator::PublicKey,
+        genesis: validator::GenesisHash,
+    },
     #[error("session id mismatch")]
     SessionIdMismatch,
     #[error("unexpected peer")]
     PeerMismatch,
     #[error("validator signature {0}")]
     Signature(#[from] validator::Error),
-    #[error("stream {0}")]
-    Stream(#[source] anyhow::Error),
+    #[error("signer {0}")]
+    Signer(#[source] ctx::Error),
+    #[error("stream: {0}")]
+    Stream(#[source] frame::Error),
+}
+
+impl Error {
+    /// Wraps a frame error, counting it in the metrics.
+    fn stream(err: frame::Error) -> Self {
+        Self::Stream(metrics::FrameErrorLayer::Handshake.observe(err))
+    }
 }
 
+/// Performs the handshake on an outbound connection.
 pub(super) async fn outbound(
     ctx: &ctx::Ctx,
-    me: &validator::SecretKey,
+    me: &dyn validator::ValidatorSigner,
     genesis: validator::GenesisHash,
+    tickets: &Tickets<validator::PublicKey>,
     stream: &mut noise::Stream,
     peer: &validator::PublicKey,
 ) -> Result<(), Error> {
@@ -68,17 +94,24 @@ pub(super) async fn outbound(
         ctx,
         stream,
         &Handshake {
-            session_id: me.sign_msg(session_id.clone()),
+            session_id: me
+                .sign_msg(ctx, session_id.clone())
+                .await
+                .map_err(Error::Signer)?,
             genesis,
+            resume: tickets.held(ctx, peer).map(|t| t.prove(&session_id)),
         },
     )
     .await
-    .map_err(Error::Stream)?;
+    .map_err(Error::stream)?;
     let h: Handshake = frame::recv_proto(ctx, stream, Handshake::max_size())
         .await
-        .map_err(Error::Stream)?;
+        .map_err(Error::stream)?;
     if h.genesis != genesis {
-        return Err(Error::GenesisMismatch);
+        return Err(Error::GenesisMismatch {
+            peer: peer.clone(),
+            genesis: h.genesis,
+        });
     }
     if h.session_id.msg != session_id {
         return Err(Error::SessionIdMismatch);
@@ -86,37 +119,54 @@ pub(super) async fn outbound(
     if &h.session_id.key != peer {
         return Err(Error::PeerMismatch);
     }
-    h.session_id.verify()?;
+    if !tickets.verify(ctx, peer, &session_id, h.resume.as_ref()) {
+        h.session_id.verify()?;
+    }
     Ok(())
 }
 
+/// Performs the handshake on an inbound connection.
+/// Returns the key of the peer.
 pub(super) async fn inbound(
     ctx: &ctx::Ctx,
-    me: &validator::SecretKey,
+    me: &dyn validator::ValidatorSigner,
     genesis: validator::GenesisHash,
+    tickets: &Tickets<validator::PublicKey>,
     stream: &mut noise::Stream,
 ) -> Result<validator::PublicKey, Error> {
     let ctx = &ctx.with_timeout(TIMEOUT);
     let session_id = node::SessionId(stream.id().encode());
     let h: Handshake = frame::recv_proto(ctx, stream, Handshake::max_size())
         .await
-        .map_err(Error::Stream)?;
-    if h.genesis != genesis {
-        return Err(Error::GenesisMismatch);
-    }
+        .map_err(Error::stream)?;
     if h.session_id.msg != session_id.clone() {
         return Err(Error::SessionIdMismatch);
     }
-    h.session_id.verify()?;
+    let peer = h.session_id.key.clone();
+    if !tickets.verify(ctx, &peer, &session_id, h.resume.as_ref()) {
+        h.session_id.verify()?;
+    }
+    // The genesis is checked only once the peer is authenticated,
+    // so that the peer can be quarantined.
+    if h.genesis != genesis {
+        return Err(Error::GenesisMismatch {
+            peer,
+            genesis: h.genesis,
+        });
+    }
     frame::send_proto(
         ctx,
         stream,
         &Handshake {
-            session_id: me.sign_msg(session_id.clone()),
+            session_id: me
+                .sign_msg(ctx, session_id.clone())
+                .await
+                .map_err(Error::Signer)?,
             genesis,
+            resume: tickets.held(ctx, &peer).map(|t| t.prove(&session_id)),
         },
     )
     .await
-    .map_err(Error::Stream)?;
-    Ok(h.session_id.key)
+    .map_err(Error::stream)?;
+    Ok(peer)
 }
@@ -16,6 +16,7 @@ impl Distribution<Handshake> for Standard {
         Handshake {
             session_id: key.sign_msg(session_id),
             genesis: rng.gen(),
+            resume: rng.gen(),
         }
     }
 }
@@ -12,11 +12,17 @@ fn test_schema_encode_decode() {
     test_encode_random::<Handshake>(rng);
 }
 
+/// Tickets of a node which neither issues nor accepts them.
+fn no_tickets(rng: &mut impl Rng) -> Tickets<validator::PublicKey> {
+    Tickets::new("consensus", &rng.gen(), None)
+}
+
 #[tokio::test]
 async fn test_session_id_mismatch() {
     abort_on_panic();
     let ctx = &ctx::test_root(&ctx::RealClock);
     let rng = &mut ctx.rng();
+    let tickets = no_tickets(rng);
 
     let key0: validator::SecretKey = rng.gen();
     let key1: validator::SecretKey = rng.gen();
@@ -39,14 +45,14 @@ async fn test_session_id_mismatch() {
         });
         s.spawn(async {
             let mut s4 = s4;
-            match inbound(ctx, &key0, genesis, &mut s4).await {
+            match inbound(ctx, &key0, genesis, &tickets, &mut s4).await {
                 Err(Error::SessionIdMismatch) => Ok(()),
                 res => panic!("unexpected res: {res:?}"),
             }
         });
         s.spawn(async {
             let mut s1 = s1;
-            match outbound(ctx, &key1, genesis, &mut s1, &key0.public()).await {
+            match outbound(ctx, &key1, genesis, &tickets, &mut s1, &key0.public()).await {
                 Err(Error::Stream(..)) => Ok(()),
                 res => panic!("unexpected res: {res:?}"),
             }
@@ -68,12 +74,13 @@ async fn test_session_id_mismatch() {
                 &Handshake {
                     session_id: key1.sign_msg(rng.gen::<node::SessionId>()),
                     genesis,
+                    resume: None,
                 },
             )
             .await?;
             Ok(())
         });
-        match outbound(ctx, &key0, genesis, &mut s1, &key1.public()).await {
+        match outbound(ctx, &key0, genesis, &tickets, &mut s1, &key1.public()).await {
             Err(Error::SessionIdMismatch) => anyhow::Ok(()),
             res => panic!("unexpected res: {res:?}"),
         }
@@ -87,6 +94,7 @@ async fn test_peer_mismatch() {
     abort_on_panic();
     let ctx = &ctx::test_root(&ctx::RealClock);
     let rng = &mut ctx.rng();
+    let tickets = no_tickets(rng);
 
     let key0: validator::SecretKey = rng.gen();
     let key1: validator::SecretKey = rng.gen();
@@ -98,12 +106,15 @@ async fn test_peer_mismatch() {
         let (s0, s1) = noise::testonly::pipe(ctx).await;
         s.spawn(async {
             let mut s0 = s0;
-            assert_eq!(key1.public(), inbound(ctx, &key0, genesis, &mut s0).await?);
+            assert_eq!(
+                key1.public(),
+                inbound(ctx, &key0, genesis, &tickets, &mut s0).await?
+            );
             Ok(())
         });
         s.spawn(async {
             let mut s1 = s1;
-            match outbound(ctx, &key1, genesis, &mut s1, &key2.public()).await {
+            match outbound(ctx, &key1, genesis, &tickets, &mut s1, &key2.public()).await {
                 Err(Error::PeerMismatch) => Ok(()),
                 res => panic!("unexpected res: {res:?}"),
             }
@@ -119,6 +130,7 @@ async fn test_genesis_mismatch() {
     abort_on_panic();
     let ctx = &ctx::test_root(&ctx::RealClock);
     let rng = &mut ctx.rng();
+    let tickets = no_tickets(rng);
 
     let key0: validator::SecretKey = rng.gen();
     let key1: validator::SecretKey = rng.gen();
@@ -128,12 +140,20 @@ async fn test_genesis_mismatch() {
         let (s0, mut s1) = noise::testonly::pipe(ctx).await;
         s.spawn(async {
             let mut s0 = s0;
-            let res = outbound(ctx, &key0, ctx.rng().gen(), &mut s0, &key1.public()).await;
+            let res = outbound(
+                ctx,
+                &key0,
+                ctx.rng().gen(),
+                &tickets,
+                &mut s0,
+                &key1.public(),
+            )
+            .await;
             assert_matches!(res, Err(Error::Stream(_)));
             Ok(())
         });
-        let res = inbound(ctx, &key1, rng.gen(), &mut s1).await;
-        assert_matches!(res, Err(Error::GenesisMismatch));
+        let res = inbound(ctx, &key1, rng.gen(), &tickets, &mut s1).await;
+        assert_matches!(res, Err(Error::GenesisMismatch { peer, .. }) if peer == key0.public());
         anyhow::Ok(())
     })
     .await
@@ -144,8 +164,16 @@ async fn test_genesis_mismatch() {
         let (s0, mut s1) = noise::testonly::pipe(ctx).await;
         s.spawn(async {
             let mut s0 = s0;
-            let res = outbound(ctx, &key0, ctx.rng().gen(), &mut s0, &key1.public()).await;
-            assert_matches!(res, Err(Error::GenesisMismatch));
+            let res = outbound(
+                ctx,
+                &key0,
+                ctx.rng().gen(),
+                &tickets,
+                &mut s0,
+                &key1.public(),
+            )
+            .await;
+            assert_matches!(res, Err(Error::GenesisMismatch { .. }));
             Ok(())
         });
         let session_id = node::SessionId(s1.id().encode());
@@ -158,6 +186,7 @@ async fn test_genesis_mismatch() {
             &Handshake {
                 session_id: key1.sign_msg(session_id),
                 genesis: rng.gen(),
+                resume: None,
             },
         )
         .await
@@ -173,6 +202,7 @@ async fn test_invalid_signature() {
     abort_on_panic();
     let ctx = &ctx::test_root(&ctx::RealClock);
     let rng = &mut ctx.rng();
+    let tickets = no_tickets(rng);
 
     let key0: validator::SecretKey = rng.gen();
     let key1: validator::SecretKey = rng.gen();
@@ -189,7 +219,7 @@ async fn test_invalid_signature() {
             frame::send_proto(ctx, &mut s1, &h).await?;
             Ok(())
         });
-        match outbound(ctx, &key0, genesis, &mut s0, &key1.public()).await {
+        match outbound(ctx, &key0, genesis, &tickets, &mut s0, &key1.public()).await {
             Err(Error::Signature(..)) => anyhow::Ok(()),
             res => panic!("unexpected res: {res:?}"),
         }
@@ -205,11 +235,12 @@ async fn test_invalid_signature() {
             let mut h = Handshake {
                 session_id: key0.sign_msg(node::SessionId(s1.id().encode())),
                 genesis,
+                resume: None,
             };
             h.session_id.key = key1.public();
-            frame::send_proto(ctx, &mut s1, &h).await
+            Ok(frame::send_proto(ctx, &mut s1, &h).await?)
         });
-        match inbound(ctx, &key0, genesis, &mut s0).await {
+        match inbound(ctx, &key0, genesis, &tickets, &mut s0).await {
             Err(Error::Signature(..)) => anyhow::Ok(()),
             res => panic!("unexpected res: {res:?}"),
         }
@@ -217,3 +248,57 @@ async fn test_invalid_signature() {
     .await
     .unwrap();
 }
+
+#[tokio::test]
+async fn test_session_resumption() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+
+    let key0: validator::SecretKey = rng.gen();
+    let key1: validator::SecretKey = rng.gen();
+    let node_key0: node::SecretKey = rng.gen();
+    let genesis: validator::GenesisHash = rng.gen();
+    let ttl = Some(time::Duration::hours(1));
+
+    // Node 1 holds a ticket issued by node 0 before its restart.
+    let ticket = Tickets::new("consensus", &node_key0, ttl)
+        .issue(ctx, &key1.public())
+        .unwrap();
+    let tickets0 = Tickets::new("consensus", &node_key0, ttl);
+    for valid in [true, false] {
+        scope::run!(ctx, |ctx, s| async {
+            let (mut s0, s1) = noise::testonly::pipe(ctx).await;
+            s.spawn_bg(async {
+                let mut s1 = s1;
+                let session_id = node::SessionId(s1.id().encode());
+                let mut resume = ticket.prove(&session_id);
+                if !valid {
+                    resume.mac = ctx.rng().gen();
+                }
+                // Only the ticket can authenticate node 1, the signature is invalid.
+                let mut h = Handshake {
+                    session_id: key1.sign_msg(session_id),
+                    genesis,
+                    resume: Some(resume),
+                };
+                h.session_id.sig = key1.sign_msg(ctx.rng().gen::<node::SessionId>()).sig;
+                frame::send_proto(ctx, &mut s1, &h).await?;
+                if valid {
+                    let _: Handshake =
+                        frame::recv_proto(ctx, &mut s1, Handshake::max_size()).await?;
+                }
+                Ok(())
+            });
+            let res = inbound(ctx, &key0, genesis, &tickets0, &mut s0).await;
+            if valid {
+                assert_eq!(key1.public(), res?);
+            } else {
+                assert_matches!(res, Err(Error::Signature(..)));
+            }
+            anyhow::Ok(())
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -0,0 +1,48 @@
+//! Metrics of the consensus network.
+use std::time::Duration;
+use vise::{
+    Buckets, Counter, EncodeLabelSet, EncodeLabelValue, Family, Histogram, LabeledFamily, Metrics,
+    Unit,
+};
+
+/// Reason for dropping an outbound consensus message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EncodeLabelValue)]
+#[metrics(rename_all = "snake_case")]
+pub(super) enum DropReason {
+    /// Message for a view older than the newest queued one.
+    Stale,
+    /// Message has been queued for longer than the TTL.
+    Expired,
+    /// Queue was full.
+    Overflow,
+}
+
+/// Labels of the consensus messages received from a validator.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, EncodeLabelSet)]
+pub(super) struct ValidatorMsgLabels {
+    pub(super) validator: String,
+    pub(super) msg: &'static str,
+}
+
+#[derive(Debug, Metrics)]
+#[metrics(prefix = "network_consensus")]
+pub(super) struct ConsensusMetrics {
+    /// Latency of sending a broadcasted message to a validator,
+    /// from the moment the connection was ready until the message was acknowledged.
+    /// Validators are labelled with `metrics::key_label`, which bounds the cardinality.
+    #[metrics(unit = Unit::Seconds, buckets = Buckets::LATENCIES, labels = ["validator"])]
+    pub(super) broadcast_send_latency: LabeledFamily<String, Histogram<Duration>>,
+    /// Latency of sending a message addressed to a single validator (see `Network::send`),
+    /// from the moment the connection was ready until the message was acknowledged.
+    #[metrics(unit = Unit::Seconds, buckets = Buckets::LATENCIES, labels = ["validator"])]
+    pub(super) send_latency: LabeledFamily<String, Histogram<Duration>>,
+    /// Consensus messages received, by the signing validator and the message type.
+    /// Reported only if `Config::per_peer_metrics` is set.
+    pub(super) received_msgs: Family<ValidatorMsgLabels, Counter>,
+    /// Outbound consensus messages dropped without being sent, by the reason.
+    #[metrics(labels = ["reason"])]
+    pub(super) outbound_dropped: LabeledFamily<DropReason, Counter>,
+}
+
+#[vise::register]
+pub(super) static METRICS: vise::Global<ConsensusMetrics> = vise::Global::new();
@@ -1,16 +1,26 @@
 //! Consensus network is a full graph of connections between all validators.
 //! BFT consensus messages are exchanged over this network.
-use crate::{config, gossip, io, noise, pool::PoolWatch, preface, rpc};
+use crate::{
+    dump, gossip, io, metrics::key_label, noise, pool::PoolWatch, preface, quarantine::Quarantine,
+    rpc, tickets::Tickets, OutboundPeer, SentryConfig, TraceContext,
+};
 use anyhow::Context as _;
+use metrics::{ValidatorMsgLabels, METRICS};
+use replay::ReplayWindow;
 use std::{
     collections::{HashMap, HashSet},
     sync::Arc,
 };
-use zksync_concurrency::{ctx, oneshot, scope, sync, time};
+use zksync_concurrency::{ctx, metrics::LatencyHistogramExt as _, oneshot, scope, sync, time};
+use zksync_consensus_crypto::TextFmt as _;
 use zksync_consensus_roles::validator;
 use zksync_protobuf::kB;
 
-mod handshake;
+pub(crate) mod handshake;
+mod metrics;
+mod outbound;
+pub(crate) mod replay;
+pub(crate) mod sentry;
 #[cfg(test)]
 mod tests;
 
@@ -20,26 +30,63 @@ const RESP_MAX_SIZE: usize = kB;
 /// we do this periodically, so that the network can observe if validator
 /// is down.
 const ADDRESS_ANNOUNCER_INTERVAL: time::Duration = time::Duration::minutes(10);
+/// Frequency at which the validator sends heartbeats to the other validators.
+const HEARTBEAT_INTERVAL: time::Duration = time::Duration::seconds(30);
+/// Validator is considered online if we have received a heartbeat from it within this time.
+const HEARTBEAT_TTL: time::Duration = time::Duration::seconds(90);
+/// Timeout of sending a heartbeat to a single validator.
+const HEARTBEAT_CALL_TIMEOUT: time::Duration = time::Duration::seconds(10);
+/// Heartbeats with a timestamp further than this from the local time are rejected.
+/// Covers the delivery time and the clock skew between the validators.
+const HEARTBEAT_MAX_SKEW: time::Duration = time::Duration::seconds(20);
+/// Delay before retrying to sign a message with the validator key after a failure.
+const SIGN_RETRY: time::Duration = time::Duration::seconds(5);
 
 /// Consensus network state.
 pub(crate) struct Network {
     /// Gossip network state to bootstrap consensus network from.
     pub(crate) gossip: Arc<gossip::Network>,
-    /// This validator's secret key.
-    pub(crate) key: validator::SecretKey,
+    /// Signer with this validator's key.
+    /// On a sentry, the signing requests are forwarded to the backend.
+    pub(crate) key: Arc<dyn validator::ValidatorSigner>,
+    /// Backend of this sentry. `None` if this node is not a sentry.
+    pub(crate) backend: Option<Arc<sentry::Backend>>,
+    /// Client relaying the consensus messages of this backend to its sentry.
+    /// `None` if this node is not a sentry backend.
+    pub(crate) sentry: Option<rpc::Client<rpc::sentry_relay::Rpc>>,
     /// Set of the currently open inbound connections.
     pub(crate) inbound: PoolWatch<validator::PublicKey>,
     /// Set of the currently open outbound connections.
     pub(crate) outbound: PoolWatch<validator::PublicKey>,
     /// RPC clients for all validators.
     pub(crate) clients: HashMap<validator::PublicKey, rpc::Client<rpc::consensus::Rpc>>,
+    /// Outbound queues of the consensus messages for all validators,
+    /// drained by `run_outbound_queue()`.
+    outbound_queues: HashMap<validator::PublicKey, outbound::Queue>,
+    /// Heartbeat RPC clients for all validators.
+    pub(crate) heartbeat_clients: HashMap<validator::PublicKey, rpc::Client<rpc::heartbeat::Rpc>>,
+    /// Time at which the last heartbeat has been received from each validator.
+    /// Entries older than `HEARTBEAT_TTL` are pruned periodically.
+    pub(crate) heartbeats: sync::watch::Sender<HashMap<validator::PublicKey, time::Instant>>,
+    /// Validators with a different genesis.
+    pub(crate) quarantine: Quarantine<validator::PublicKey>,
+    /// Session tickets issued to and by this validator.
+    pub(crate) tickets: Tickets<validator::PublicKey>,
+}
+
+/// Consensus server for a single inbound connection.
+struct ConsensusServer<'a> {
+    /// Consensus network state.
+    net: &'a Network,
+    /// Messages recently received over the connection.
+    replay: ReplayWindow,
 }
 
 #[async_trait::async_trait]
-impl rpc::Handler<rpc::consensus::Rpc> for &Network {
+impl rpc::Handler<rpc::consensus::Rpc> for ConsensusServer<'_> {
     /// Here we bound the buffering of incoming consensus messages.
     fn max_req_size(&self) -> usize {
-        self.gossip.cfg.max_block_size.saturating_add(kB)
+        self.net.gossip.max_block_size().saturating_add(kB)
     }
 
     async fn handle(
@@ -47,11 +94,36 @@ impl rpc::Handler<rpc::consensus::Rpc> for &Network {
         ctx: &ctx::Ctx,
         req: rpc::consensus::Req,
     ) -> anyhow::Result<rpc::consensus::Resp> {
+        let genesis = self.net.gossip.genesis();
+        anyhow::ensure!(
+            genesis.accepts_validator_key(&req.msg.key),
+            "message signed by a non-validator"
+        );
+        if let Err(replay) = self.replay.check(&req.msg) {
+            tracing::debug!("dropping consensus message: {replay:?}");
+            return Ok(rpc::consensus::Resp);
+        }
+        // Only the verified messages are recorded in the replay window.
+        req.msg.verify().context("verify()")?;
+        if let Err(replay) = self.replay.insert(&req.msg) {
+            tracing::debug!("dropping consensus message: {replay:?}");
+            return Ok(rpc::consensus::Resp);
+        }
+        if self.net.gossip.cfg.per_peer_metrics {
+            METRICS.received_msgs[&ValidatorMsgLabels {
+                validator: key_label(&req.msg.key),
+                msg: req.msg.msg.label(),
+            }]
+                .inc();
+        }
+        self.net.gossip.high_qc.observe(&genesis, &req.msg);
         let (send, recv) = oneshot::channel();
-        self.gossip
+        self.net
+            .gossip
             .sender
             .send(io::OutputMessage::Consensus(io::ConsensusReq {
-                msg: req.0,
+                msg: req.msg,
+                trace: req.trace,
                 ack: send,
             }));
         recv.recv_or_disconnected(ctx).await??;
@@ -59,13 +131,70 @@ impl rpc::Handler<rpc::consensus::Rpc> for &Network {
     }
 }
 
+/// Heartbeat server for a single inbound connection.
+struct HeartbeatServer<'a> {
+    /// Consensus network state.
+    net: &'a Network,
+    /// Validator on the other end of the connection.
+    peer: &'a validator::PublicKey,
+}
+
+#[async_trait::async_trait]
+impl rpc::Handler<rpc::heartbeat::Rpc> for HeartbeatServer<'_> {
+    fn max_req_size(&self) -> usize {
+        kB
+    }
+
+    async fn handle(&self, ctx: &ctx::Ctx, req: rpc::heartbeat::Req) -> anyhow::Result<()> {
+        let heartbeat = req.0;
+        anyhow::ensure!(
+            &heartbeat.key == self.peer,
+            "heartbeat signed by a different key"
+        );
+        anyhow::ensure!(
+            heartbeat.msg.genesis == self.net.gossip.genesis().hash(),
+            "genesis mismatch"
+        );
+        let skew = heartbeat.msg.timestamp - self.net.gossip.cfg.time_source.now_utc(ctx);
+        anyhow::ensure!(
+            skew.abs() <= HEARTBEAT_MAX_SKEW,
+            "heartbeat timestamp is off by {skew}"
+        );
+        heartbeat.verify().context("verify()")?;
+        let now = ctx.now();
+        self.net.heartbeats.send_modify(|heartbeats| {
+            heartbeats.insert(heartbeat.key, now);
+        });
+        Ok(())
+    }
+}
+
 impl Network {
     /// Constructs a new consensus network state.
     pub(crate) fn new(ctx: &ctx::Ctx, gossip: Arc<gossip::Network>) -> Option<Arc<Self>> {
-        let key = gossip.cfg.validator_key.clone()?;
-        let validators: HashSet<_> = gossip.genesis().validators.iter().cloned().collect();
+        let (key, backend, sentry): (Arc<dyn validator::ValidatorSigner>, _, _) =
+            match &gossip.cfg.sentry {
+                Some(SentryConfig::Sentry { validator }) => {
+                    let backend = Arc::new(sentry::Backend::new(
+                        ctx,
+                        validator.clone(),
+                        gossip.cfg.rpc.consensus_rate,
+                    ));
+                    (backend.clone(), Some(backend), None)
+                }
+                Some(SentryConfig::Backend { .. }) => (
+                    gossip.cfg.validator_key.clone()?,
+                    None,
+                    Some(rpc::Client::new(ctx, gossip.cfg.rpc.consensus_rate)),
+                ),
+                None => (gossip.cfg.validator_key.clone()?, None, None),
+            };
+        // Rotated keys are accepted as well, so that validators can switch to them.
+        let validators: HashSet<_> = gossip.genesis().validator_keys().cloned().collect();
         Some(Arc::new(Self {
             key,
+            backend,
+            sentry,
             inbound: PoolWatch::new(validators.clone(), 0),
             outbound: PoolWatch::new(validators.clone(), 0),
             clients: validators
@@ -77,75 +206,239 @@ impl Network {
                     )
                 })
                 .collect(),
+            outbound_queues: validators
+                .iter()
+                .map(|peer| {
+                    (
+                        peer.clone(),
+                        outbound::Queue::new(gossip.cfg.consensus_outbound_queue),
+                    )
+                })
+                .collect(),
+            heartbeat_clients: validators
+                .iter()
+                .map(|peer| (peer.clone(), rpc::Client::new(ctx, rpc::heartbeat::RATE)))
+                .collect(),
+            heartbeats: sync::watch::channel(HashMap::new()).0,
+            quarantine: Quarantine::new(gossip.cfg.genesis_mismatch_quarantine),
+            // Derived from the node key, since the validator key might not be available locally.
+            tickets: Tickets::new(
+                "consensus",
+                &gossip.cfg.gossip.key,
+                gossip.cfg.session_ticket_ttl,
+            ),
             gossip,
         }))
     }
 
     /// Sends a message to all validators.
+    /// The message is queued in the outbound queues of the directly connected validators.
+    /// If some validators are not directly connected, the message is also relayed
+    /// over the gossip network (if enabled).
     pub(crate) async fn broadcast(
         &self,
         ctx: &ctx::Ctx,
         msg: validator::Signed<validator::ConsensusMsg>,
+        trace: Option<TraceContext>,
     ) -> anyhow::Result<()> {
-        let req = rpc::consensus::Req(msg);
-        scope::run!(ctx, |ctx, s| async {
-            for (peer, client) in &self.clients {
-                s.spawn(async {
-                    if let Err(err) = client.call(ctx, &req, RESP_MAX_SIZE).await {
-                        tracing::info!("send({:?},<ConsensusMsg>): {err:#}", &*peer);
-                    }
-                    Ok(())
-                });
-            }
-            Ok(())
-        })
-        .await
+        if let Some(relay) = &self.sentry {
+            return self.relay_to_sentry(ctx, relay, msg, None, trace).await;
+        }
+        let own_key = self.key.public();
+        let outbound = self.outbound.subscribe().borrow().current().clone();
+        let relay = self.gossip.cfg.consensus_relay_max_hops > 0
+            && self
+                .clients
+                .keys()
+                .any(|peer| peer != &own_key && !outbound.contains(peer));
+        // The request is encoded once for all the validators.
+        let req = Arc::new(rpc::EncodedReq::new(rpc::consensus::Req { msg, trace }));
+        for queue in self.outbound_queues.values() {
+            queue.push(ctx, req.clone(), outbound::Delivery::Broadcast);
+        }
+        if relay {
+            self.gossip
+                .relay_consensus(ctx, req.req().msg.clone(), None, trace)
+                .await?;
+        }
+        Ok(())
     }
 
     /// Sends a message to the given validator.
+    /// If the validator is not directly connected and relaying is enabled,
+    /// the message is relayed over the gossip network instead.
     pub(crate) async fn send(
         &self,
         ctx: &ctx::Ctx,
         key: &validator::PublicKey,
         msg: validator::Signed<validator::ConsensusMsg>,
+        trace: Option<TraceContext>,
     ) -> anyhow::Result<()> {
-        let client = self.clients.get(key).context("not an active validator")?;
-        client
-            .call(ctx, &rpc::consensus::Req(msg), RESP_MAX_SIZE)
-            .await?;
+        let queue = self
+            .outbound_queues
+            .get(key)
+            .context("not an active validator")?;
+        if let Some(relay) = &self.sentry {
+            return self
+                .relay_to_sentry(ctx, relay, msg, Some(key.clone()), trace)
+                .await;
+        }
+        if self.gossip.cfg.consensus_relay_max_hops > 0
+            && key != &self.key.public()
+            && !self.outbound.subscribe().borrow().current().contains(key)
+        {
+            return self
+                .gossip
+                .relay_consensus(ctx, msg, Some(key.clone()), trace)
+                .await;
+        }
+        let req = rpc::consensus::Req { msg, trace };
+        queue.push(
+            ctx,
+            Arc::new(rpc::EncodedReq::new(req)),
+            outbound::Delivery::Send,
+        );
         Ok(())
     }
 
+    /// Sends the messages from the outbound queue of the given validator,
+    /// as long as the connection is ready to accept them.
+    pub(crate) async fn run_outbound_queue(&self, ctx: &ctx::Ctx, peer: &validator::PublicKey) {
+        let (Some(client), Some(queue)) = (self.clients.get(peer), self.outbound_queues.get(peer))
+        else {
+            return;
+        };
+        let ttl = self.gossip.cfg.consensus_outbound_queue.ttl;
+        let _: ctx::OrCanceled<()> = scope::run!(ctx, |ctx, s| async {
+            loop {
+                // The call is reserved before the message is taken from the queue,
+                // so that the messages wait in the queue (where the stale ones get dropped)
+                // while the validator is not ready to accept them.
+                let call = client.reserve(ctx).await?;
+                let (req, delivery) = queue.pop(ctx).await?;
+                s.spawn(async move {
+                    let ctx = &ctx.with_timeout(ttl);
+                    let start = ctx.now();
+                    let latency = match delivery {
+                        outbound::Delivery::Broadcast => &METRICS.broadcast_send_latency,
+                        outbound::Delivery::Send => &METRICS.send_latency,
+                    };
+                    match call.call_encoded(ctx, &req, RESP_MAX_SIZE).await {
+                        Ok(_) => latency[&key_label(&*peer)].observe_latency(ctx.now() - start),
+                        Err(err) => tracing::info!("send({:?},<ConsensusMsg>): {err:#}", &*peer),
+                    }
+                    Ok(())
+                });
+            }
+        })
+        .await;
+    }
+
     /// Performs handshake of an inbound stream.
     /// Closes the stream if there is another inbound stream opened from the same validator.
+    #[tracing::instrument(level = "info", name = "consensus", skip_all, fields(peer))]
     pub(crate) async fn run_inbound_stream(
         &self,
         ctx: &ctx::Ctx,
         mut stream: noise::Stream,
     ) -> anyhow::Result<()> {
-        let peer =
-            handshake::inbound(ctx, &self.key, self.gossip.genesis().hash(), &mut stream).await?;
+        let res = handshake::inbound(
+            ctx,
+            &self.key,
+            self.gossip.genesis().hash(),
+            &self.tickets,
+            &mut stream,
+        )
+        .await;
+        if let Err(handshake::Error::GenesisMismatch { peer, genesis }) = &res {
+            self.quarantine.insert(ctx, peer.clone(), *genesis);
+        }
+        let peer = res?;
+        tracing::Span::current().record("peer", tracing::field::debug(&peer));
         self.inbound.insert(peer.clone()).await?;
+        let metrics_peer = self.gossip.cfg.per_peer_metrics.then(|| key_label(&peer));
+        if let Some(label) = &metrics_peer {
+            stream.set_metrics_peer(label.clone(), ctx.now_utc());
+        }
         let res = scope::run!(ctx, |ctx, s| async {
             let mut service = rpc::Service::new()
-                .add_server(rpc::ping::Server, rpc::ping::RATE)
-                .add_server(self, self.gossip.cfg.rpc.consensus_rate);
+                .keepalive(self.gossip.cfg.keepalive)
+                .dump(dump::Dumper::new(
+                    peer.encode(),
+                    self.gossip.cfg.monitor.wire_dumps().clone(),
+                ))
+                .metrics_peer(metrics_peer)
+                .add_server(
+                    rpc::ping::Server(&self.gossip.cfg.time_source),
+                    rpc::ping::RATE,
+                )
+                .add_server(
+                    ConsensusServer {
+                        net: self,
+                        replay: ReplayWindow::new(self.gossip.cfg.consensus_replay_window),
+                    },
+                    self.gossip.rpc_cfg().consensus_rate,
+                )
+                .add_server(
+                    HeartbeatServer {
+                        net: self,
+                        peer: &peer,
+                    },
+                    rpc::heartbeat::RATE,
+                );
+            // Issue session tickets to the peer.
+            let push_session_ticket_client = rpc::Client::<rpc::push_session_ticket::Rpc>::new(
+                ctx,
+                rpc::push_session_ticket::RATE,
+            );
+            service = service.add_client(&push_session_ticket_client).add_server(
+                rpc::push_session_ticket::Server {
+                    tickets: &self.tickets,
+                    peer: &peer,
+                },
+                rpc::push_session_ticket::RATE,
+            );
+            s.spawn_bg(async {
+                let client = push_session_ticket_client;
+                let _ = client.push_loop(ctx, &self.tickets, &peer).await;
+                Ok(())
+            });
             if let Some(ping_timeout) = &self.gossip.cfg.ping_timeout {
                 let ping_client = rpc::Client::<rpc::ping::Rpc>::new(ctx, rpc::ping::RATE);
                 service = service.add_client(&ping_client);
                 s.spawn(async {
                     let ping_client = ping_client;
-                    ping_client.ping_loop(ctx, *ping_timeout).await
+                    ping_client
+                        .ping_loop(ctx, &self.gossip.cfg.time_source, *ping_timeout, |_| {})
+                        .await
                 });
             }
+            #[cfg(any(test, feature = "chaos"))]
+            let service = service.filter(
+                self.gossip
+                    .partition
+                    .filter(crate::partition::Peer::Validator(peer.clone())),
+            );
+            #[cfg(feature = "chaos")]
+            let service = service.chaos(self.gossip.cfg.chaos.clone());
             service.run(ctx, stream).await?;
             Ok(())
         })
         .await;
         self.inbound.remove(&peer).await;
+        if let Some(penalty) = res
+            .as_ref()
+            .err()
+            .and_then(gossip::address_book::misbehavior_penalty)
+        {
+            self.gossip
+                .reconnect
+                .penalize(&OutboundPeer::Consensus(peer.clone()), penalty);
+        }
         res
     }
 
+    #[tracing::instrument(level = "info", name = "consensus", skip_all, fields(peer = ?peer))]
     async fn run_outbound_stream(
         &self,
         ctx: &ctx::Ctx,
@@ -153,28 +446,94 @@ impl Network {
         addr: std::net::SocketAddr,
     ) -> anyhow::Result<()> {
         let client = self.clients.get(peer).context("not an active validator")?;
-        let mut stream = preface::connect(ctx, addr, preface::Endpoint::ConsensusNet).await?;
-        handshake::outbound(
+        let heartbeat_client = self
+            .heartbeat_clients
+            .get(peer)
+            .context("not an active validator")?;
+        if let Some(q) = self.quarantine.get(ctx, peer) {
+            anyhow::bail!(
+                "peer quarantined until {:?} (genesis {:?})",
+                q.until,
+                q.genesis
+            );
+        }
+        let mut stream = preface::connect(
+            ctx,
+            &self.gossip.cfg.transport,
+            addr,
+            preface::Endpoint::ConsensusNet,
+        )
+        .await?;
+        let res = handshake::outbound(
             ctx,
             &self.key,
             self.gossip.genesis().hash(),
+            &self.tickets,
             &mut stream,
             peer,
         )
-        .await?;
+        .await;
+        if let Err(handshake::Error::GenesisMismatch { genesis, .. }) = &res {
+            self.quarantine.insert(ctx, peer.clone(), *genesis);
+        }
+        res?;
         self.outbound.insert(peer.clone()).await?;
+        self.gossip
+            .reconnect
+            .connected(ctx, &OutboundPeer::Consensus(peer.clone()));
+        let metrics_peer = self.gossip.cfg.per_peer_metrics.then(|| key_label(peer));
+        if let Some(label) = &metrics_peer {
+            stream.set_metrics_peer(label.clone(), ctx.now_utc());
+        }
         let res = scope::run!(ctx, |ctx, s| async {
             let mut service = rpc::Service::new()
-                .add_server(rpc::ping::Server, rpc::ping::RATE)
-                .add_client(client);
+                .keepalive(self.gossip.cfg.keepalive)
+                .dump(dump::Dumper::new(
+                    peer.encode(),
+                    self.gossip.cfg.monitor.wire_dumps().clone(),
+                ))
+                .metrics_peer(metrics_peer)
+                .add_server(
+                    rpc::ping::Server(&self.gossip.cfg.time_source),
+                    rpc::ping::RATE,
+                )
+                .add_client(client)
+                .add_client(heartbeat_client);
+            // Issue session tickets to the peer.
+            let push_session_ticket_client = rpc::Client::<rpc::push_session_ticket::Rpc>::new(
+                ctx,
+                rpc::push_session_ticket::RATE,
+            );
+            service = service.add_client(&push_session_ticket_client).add_server(
+                rpc::push_session_ticket::Server {
+                    tickets: &self.tickets,
+                    peer,
+                },
+                rpc::push_session_ticket::RATE,
+            );
+            s.spawn_bg(async {
+                let client = push_session_ticket_client;
+                let _ = client.push_loop(ctx, &self.tickets, peer).await;
+                Ok(())
+            });
             if let Some(ping_timeout) = &self.gossip.cfg.ping_timeout {
                 let ping_client = rpc::Client::<rpc::ping::Rpc>::new(ctx, rpc::ping::RATE);
                 service = service.add_client(&ping_client);
                 s.spawn(async {
                     let ping_client = ping_client;
-                    ping_client.ping_loop(ctx, *ping_timeout).await
+                    ping_client
+                        .ping_loop(ctx, &self.gossip.cfg.time_source, *ping_timeout, |_| {})
+                        .await
                 });
             }
+            #[cfg(any(test, feature = "chaos"))]
+            let service = service.filter(
+                self.gossip
+                    .partition
+                    .filter(crate::partition::Peer::Validator(peer.clone())),
+            );
+            #[cfg(feature = "chaos")]
+            let service = service.chaos(self.gossip.cfg.chaos.clone());
             service.run(ctx, stream).await?;
             Ok(())
         })
@@ -184,55 +543,149 @@ impl Network {
     }
 
     /// Maintains a connection to the given validator.
-    /// If connection breaks, it tries to reconnect periodically.
+    /// If connection breaks, it is reestablished according to the reconnect policy.
+    /// The connection is dropped (and reestablished) when the address of the validator changes.
     pub(crate) async fn maintain_connection(&self, ctx: &ctx::Ctx, peer: &validator::PublicKey) {
         let addrs = &mut self.gossip.validator_addrs.subscribe();
-        let mut addr = None;
-        while ctx.is_active() {
-            // Wait for a new address, or retry with the old one after timeout.
-            if let Ok(new) =
-                sync::wait_for(&ctx.with_timeout(config::CONNECT_RETRY), addrs, |addrs| {
-                    addrs.get(peer).map(|x| x.msg.addr) != addr
+        let target = OutboundPeer::Consensus(peer.clone());
+        let _: ctx::OrCanceled<()> = async {
+            loop {
+                let addr = sync::wait_for(ctx, addrs, |addrs| addrs.get(peer).is_some())
+                    .await?
+                    .get(peer)
+                    .unwrap()
+                    .msg
+                    .addr;
+                scope::run!(ctx, |ctx, s| async {
+                    s.spawn_bg(async {
+                        let _ = self
+                            .gossip
+                            .reconnect
+                            .maintain(ctx, &target, || self.run_outbound_stream(ctx, peer, addr))
+                            .await;
+                        Ok(())
+                    });
+                    sync::wait_for(ctx, addrs, |addrs| {
+                        addrs.get(peer).map(|x| x.msg.addr) != Some(addr)
+                    })
+                    .await?;
+                    Ok(())
                 })
-                .await
-            {
-                addr = new.get(peer).map(|x| x.msg.addr);
-            }
-            let Some(addr) = addr else { continue };
-            if let Err(err) = self.run_outbound_stream(ctx, peer, addr).await {
-                tracing::info!("run_outbound_stream({peer:?},{addr}): {err:#}");
+                .await?;
             }
         }
+        .await;
     }
 
     /// Periodically announces this validator's public IP over gossip network,
     /// so that other validators can discover and connect to this validator.
+    /// The address is re-announced as soon as the detected public address changes.
     pub(crate) async fn run_address_announcer(&self, ctx: &ctx::Ctx) {
-        let my_addr = self.gossip.cfg.public_addr;
+        let mut my_addr_sub = self.gossip.public_addr.subscribe();
         let mut sub = self.gossip.validator_addrs.subscribe();
         while ctx.is_active() {
-            let ctx = &ctx.with_timeout(ADDRESS_ANNOUNCER_INTERVAL);
-            let _ = sync::wait_for(ctx, &mut sub, |got| {
-                got.get(&self.key.public()).map(|x| &x.msg.addr) != Some(&my_addr)
-            })
+            let my_addr = *my_addr_sub.borrow_and_update();
+            // Wait until the announced address is outdated, or the interval passes.
+            // A change of the public address interrupts the wait.
+            let _: ctx::OrCanceled<()> = scope::run!(
+                &ctx.with_timeout(ADDRESS_ANNOUNCER_INTERVAL),
+                |ctx, s| async {
+                    s.spawn_bg::<()>(async {
+                        sync::changed(ctx, &mut my_addr_sub).await?;
+                        Err(ctx::Canceled)
+                    });
+                    sync::wait_for(ctx, &mut sub, |got| {
+                        got.get(&self.key.public()).map(|x| &x.msg.addr) != Some(&my_addr)
+                    })
+                    .await?;
+                    Ok(())
+                }
+            )
             .await;
+            let my_addr = *my_addr_sub.borrow();
             let next_version = sub
                 .borrow()
                 .get(&self.key.public())
                 .map(|x| x.msg.version + 1)
                 .unwrap_or(0);
+            let addr = validator::NetAddress {
+                addr: my_addr,
+                version: next_version,
+                timestamp: self.gossip.cfg.time_source.now_utc(ctx),
+            };
+            let addr = match self.key.sign_msg(ctx, addr).await {
+                Ok(addr) => addr,
+                Err(err) => {
+                    tracing::warn!("sign_msg(<NetAddress>): {err:#}");
+                    let _ = ctx.sleep(SIGN_RETRY).await;
+                    continue;
+                }
+            };
             self.gossip
                 .validator_addrs
-                .update(
-                    &self.gossip.genesis().validators,
-                    &[Arc::new(self.key.sign_msg(validator::NetAddress {
-                        addr: my_addr,
-                        version: next_version,
-                        timestamp: ctx.now_utc(),
-                    }))],
-                )
+                .update(&self.gossip.genesis(), &[Arc::new(addr)])
                 .await
                 .unwrap();
         }
     }
+
+    /// Periodically sends a signed heartbeat to all the other validators
+    /// and prunes the expired heartbeats received from them.
+    /// The heartbeats are sent in the background, so that the unreachable
+    /// validators don't delay the next round.
+    pub(crate) async fn run_heartbeats(&self, ctx: &ctx::Ctx) {
+        let genesis = self.gossip.genesis().hash();
+        let me = self.key.public();
+        let _: ctx::OrCanceled<()> = scope::run!(ctx, |ctx, s| async {
+            while ctx.is_active() {
+                let heartbeat = validator::Heartbeat {
+                    genesis,
+                    timestamp: self.gossip.cfg.time_source.now_utc(ctx),
+                };
+                let req = match self.key.sign_msg(ctx, heartbeat).await {
+                    Ok(heartbeat) => Arc::new(rpc::heartbeat::Req(heartbeat)),
+                    Err(err) => {
+                        tracing::warn!("sign_msg(<Heartbeat>): {err:#}");
+                        ctx.sleep(SIGN_RETRY).await?;
+                        continue;
+                    }
+                };
+                for (peer, client) in &self.heartbeat_clients {
+                    if peer == &me {
+                        continue;
+                    }
+                    let req = req.clone();
+                    s.spawn(async move {
+                        let ctx = &ctx.with_timeout(HEARTBEAT_CALL_TIMEOUT);
+                        if let Err(err) = client.call(ctx, &req, kB).await {
+                            tracing::debug!("heartbeat({:?}): {err:#}", &*peer);
+                        }
+                        Ok(())
+                    });
+                }
+                let now = ctx.now();
+                self.heartbeats.send_if_modified(|heartbeats| {
+                    let before = heartbeats.len();
+                    heartbeats.retain(|_, t| now - *t <= HEARTBEAT_TTL);
+                    heartbeats.len() != before
+                });
+                ctx.sleep(HEARTBEAT_INTERVAL).await?;
+            }
+            Ok(())
+        })
+        .await;
+    }
+
+    /// Estimated number of online validators (including this one),
+    /// i.e. validators from which we have received a heartbeat recently.
+    /// Since all validators currently have equal weight, it is also
+    /// a good estimate of the online stake.
+    pub(crate) fn online_validators(&self) -> usize {
+        let me = self
+            .gossip
+            .genesis()
+            .validators
+            .contains(&self.key.public());
+        self.heartbeats.borrow().len() + usize::from(me)
+    }
 }
@@ -0,0 +1,172 @@
+//! Outbound queues of the consensus messages, one per validator.
+//! A slow validator doesn't make the messages for it pile up in memory:
+//! * the messages for the views older than the newest queued one are stale and dropped,
+//!   except for the high priority ones, which let the validator finish the older views,
+//! * the messages which have been queued for longer than the TTL are dropped instead of sent,
+//! * when the queue is full, the lowest priority message is dropped.
+//!
+//! The messages are sent in the order of priority, and in the FIFO order within a priority.
+use super::metrics::{DropReason, METRICS};
+use crate::{rpc, OutboundQueueConfig};
+use std::sync::Arc;
+use zksync_concurrency::{ctx, sync, time};
+use zksync_consensus_roles::validator;
+
+/// Priority of an outbound consensus message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Priority {
+    /// Proposal requests.
+    Low,
+    /// Proposals and the votes for them.
+    Normal,
+    /// Messages which finish the view: commit QCs and timeouts.
+    High,
+}
+
+impl Priority {
+    /// Priority of the given message.
+    pub(crate) fn of(msg: &validator::ConsensusMsg) -> Self {
+        use validator::ConsensusMsg as M;
+        match msg {
+            M::ReplicaPrepare(_) => Self::Low,
+            M::LeaderPrepare(_) | M::ReplicaCommit(_) => Self::Normal,
+            M::LeaderCommit(_) | M::ReplicaTimeout(_) | M::LeaderTimeout(_) => Self::High,
+        }
+    }
+}
+
+/// Encoded consensus message.
+/// Shared by the queues of all the validators that it is broadcasted to.
+pub(crate) type Msg = Arc<rpc::EncodedReq<rpc::consensus::Rpc>>;
+
+/// Whether a message is broadcasted to all the validators or sent to a single one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Delivery {
+    /// See `Network::broadcast`.
+    Broadcast,
+    /// See `Network::send`.
+    Send,
+}
+
+struct Entry {
+    msg: Msg,
+    delivery: Delivery,
+    view: validator::ViewNumber,
+    priority: Priority,
+    /// Entry is dropped instead of sent after this time.
+    deadline: time::Instant,
+}
+
+impl Entry {
+    /// Whether the entry is stale, given the newest view of the queued messages.
+    fn is_stale(&self, view: validator::ViewNumber) -> bool {
+        self.view < view && self.priority < Priority::High
+    }
+}
+
+/// Queued messages.
+#[derive(Default)]
+struct Inner {
+    /// Queued messages, in the FIFO order.
+    entries: Vec<Entry>,
+    /// Newest view of the queued messages.
+    view: Option<validator::ViewNumber>,
+}
+
+impl Inner {
+    /// Drops the expired entries.
+    fn prune(&mut self, now: time::Instant) {
+        let before = self.entries.len();
+        self.entries.retain(|e| now < e.deadline);
+        METRICS.outbound_dropped[&DropReason::Expired].inc_by((before - self.entries.len()) as u64);
+    }
+
+    /// Removes and returns the entry with the highest priority, the oldest one among
+    /// the entries with equal priorities.
+    fn take(&mut self) -> Option<Entry> {
+        // `max_by_key` returns the last maximal element, so the order is reversed.
+        let (i, _) = self
+            .entries
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|(_, e)| e.priority)?;
+        Some(self.entries.remove(i))
+    }
+}
+
+/// Outbound queue of the consensus messages to a single validator.
+pub(crate) struct Queue {
+    cfg: OutboundQueueConfig,
+    inner: sync::watch::Sender<Inner>,
+}
+
+impl Queue {
+    /// Constructs an empty queue.
+    pub(crate) fn new(cfg: OutboundQueueConfig) -> Self {
+        Self {
+            cfg,
+            inner: sync::watch::channel(Inner::default()).0,
+        }
+    }
+
+    /// Queues a message. The message is dropped right away if it is stale.
+    /// A message for a newer view drops the queued stale messages.
+    pub(crate) fn push(&self, ctx: &ctx::Ctx, msg: Msg, delivery: Delivery) {
+        let entry = Entry {
+            view: msg.req().msg.msg.view().number,
+            priority: Priority::of(&msg.req().msg.msg),
+            deadline: ctx.now() + self.cfg.ttl,
+            delivery,
+            msg,
+        };
+        self.inner.send_if_modified(|inner| {
+            match inner.view {
+                Some(view) if entry.is_stale(view) => {
+                    METRICS.outbound_dropped[&DropReason::Stale].inc();
+                    return false;
+                }
+                Some(view) if entry.view <= view => {}
+                _ => {
+                    let before = inner.entries.len();
+                    inner.entries.retain(|e| !e.is_stale(entry.view));
+                    METRICS.outbound_dropped[&DropReason::Stale]
+                        .inc_by((before - inner.entries.len()) as u64);
+                    inner.view = Some(entry.view);
+                }
+            }
+            inner.entries.push(entry);
+            if inner.entries.len() > self.cfg.capacity.max(1) {
+                // Drop the lowest priority entry, the oldest one among the equal priorities.
+                let (i, _) = inner
+                    .entries
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, e)| e.priority)
+                    .unwrap();
+                inner.entries.remove(i);
+                METRICS.outbound_dropped[&DropReason::Overflow].inc();
+            }
+            true
+        });
+    }
+
+    /// Waits for the next message to send.
+    pub(crate) async fn pop(&self, ctx: &ctx::Ctx) -> ctx::OrCanceled<(Msg, Delivery)> {
+        let sub = &mut self.inner.subscribe();
+        loop {
+            sync::wait_for(ctx, sub, |inner| !inner.entries.is_empty()).await?;
+            let now = ctx.now();
+            let mut entry = None;
+            // Removing entries doesn't need to wake up anyone.
+            self.inner.send_if_modified(|inner| {
+                inner.prune(now);
+                entry = inner.take();
+                false
+            });
+            if let Some(entry) = entry {
+                return Ok((entry.msg, entry.delivery));
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Protection against replayed consensus messages.
+//! Messages received from a peer are deduplicated within a window of the most recent views,
+//! and messages for views older than the window are dropped, so that the bft actor
+//! doesn't process the duplicates. Only verified messages are recorded in the window,
+//! so that a forged message (e.g. for a far-future view) can't move the window
+//! and get the honest messages dropped.
+use std::{collections::BTreeMap, sync::Mutex};
+use vise::{Counter, EncodeLabelSet, EncodeLabelValue, Family, Metrics};
+use zksync_consensus_roles::validator;
+
+/// Max number of distinct messages remembered per view.
+/// An honest validator sends just a few messages per view,
+/// so this bounds the memory used by the window without affecting honest peers.
+const MAX_MSGS_PER_VIEW: usize = 8;
+
+/// Reason for dropping a consensus message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EncodeLabelValue, EncodeLabelSet)]
+#[metrics(label = "reason", rename_all = "snake_case")]
+pub(crate) enum Replay {
+    /// The message has been already received.
+    Duplicate,
+    /// The message is for a view older than the window.
+    Stale,
+}
+
+/// Window of the consensus messages recently received from a single peer.
+pub(crate) struct ReplayWindow {
+    /// Number of the most recent views covered by the window.
+    size: u64,
+    /// Hashes of the received messages, per view.
+    seen: Mutex<BTreeMap<validator::ViewNumber, Vec<validator::MsgHash>>>,
+}
+
+impl ReplayWindow {
+    /// Constructs a window covering `size` most recent views.
+    /// `size == 0` disables the replay protection.
+    pub(crate) fn new(size: u64) -> Self {
+        Self {
+            size,
+            seen: Mutex::default(),
+        }
+    }
+
+    /// Checks whether the message should be dropped, without recording it.
+    /// Used to drop the replayed messages before verifying them.
+    pub(crate) fn check(
+        &self,
+        msg: &validator::Signed<validator::ConsensusMsg>,
+    ) -> Result<(), Replay> {
+        let hash = validator::Msg::Consensus(msg.msg.clone()).hash();
+        let res = Self::check_inner(self.size, &self.seen.lock().unwrap(), msg, &hash);
+        if let Err(replay) = res {
+            METRICS.dropped_msgs[&replay].inc();
+        }
+        res
+    }
+
+    /// Records the message. Returns an error if the message should be dropped.
+    /// The message has to be verified (both the signature and the signer) by the caller.
+    pub(crate) fn insert(
+        &self,
+        msg: &validator::Signed<validator::ConsensusMsg>,
+    ) -> Result<(), Replay> {
+        let res = self.insert_inner(msg);
+        if let Err(replay) = res {
+            METRICS.dropped_msgs[&replay].inc();
+        }
+        res
+    }
+
+    fn check_inner(
+        size: u64,
+        seen: &BTreeMap<validator::ViewNumber, Vec<validator::MsgHash>>,
+        msg: &validator::Signed<validator::ConsensusMsg>,
+        hash: &validator::MsgHash,
+    ) -> Result<(), Replay> {
+        if size == 0 {
+            return Ok(());
+        }
+        let view = msg.msg.view().number;
+        if let Some(high) = seen.last_key_value().map(|(v, _)| *v) {
+            if view.0.saturating_add(size) <= high.0 {
+                return Err(Replay::Stale);
+            }
+        }
+        if seen.get(&view).is_some_and(|hashes| hashes.contains(hash)) {
+            return Err(Replay::Duplicate);
+        }
+        Ok(())
+    }
+
+    fn insert_inner(&self, msg: &validator::Signed<validator::ConsensusMsg>) -> Result<(), Replay> {
+        if self.size == 0 {
+            return Ok(());
+        }
+        let hash = validator::Msg::Consensus(msg.msg.clone()).hash();
+        let mut seen = self.seen.lock().unwrap();
+        Self::check_inner(self.size, &seen, msg, &hash)?;
+        let hashes = seen.entry(msg.msg.view().number).or_default();
+        if hashes.len() < MAX_MSGS_PER_VIEW {
+            hashes.push(hash);
+        }
+        // Prune the views which have fallen out of the window.
+        let high = *seen.last_key_value().unwrap().0;
+        let min = validator::ViewNumber(high.0.saturating_sub(self.size) + 1);
+        *seen = seen.split_off(&min);
+        Ok(())
+    }
+}
+
+/// Metrics of the replay protection.
+#[derive(Debug, Metrics)]
+#[metrics(prefix = "network_consensus_replay")]
+struct ReplayMetrics {
+    /// Consensus messages dropped before reaching the bft actor.
+    dropped_msgs: Family<Replay, Counter>,
+}
+
+#[vise::register]
+static METRICS: vise::Global<ReplayMetrics> = vise::Global::new();
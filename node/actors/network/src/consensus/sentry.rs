@@ -0,0 +1,359 @@
+//! Sentry architecture, see `SentryConfig`.
+//! The backend node holds the validator key and runs the consensus actor, but it is connected
+//! only to its sentry, over a private link. The sentry dials and accepts the consensus
+//! connections of the other validators with its own node key, and relies on the backend for
+//! the network-level proofs of the validator identity: it asks the backend to sign the session
+//! IDs, the address announcements and the heartbeats (`rpc::sentry_sign`). The consensus
+//! messages are forwarded between the network and the backend in both directions.
+use super::{handshake, ConsensusServer, Network, ReplayWindow, RESP_MAX_SIZE};
+use crate::{frame, io, noise, preface, rpc, OutboundPeer, TraceContext};
+use anyhow::Context as _;
+use std::fmt;
+use zksync_concurrency::{ctx, limiter, scope, sync, time};
+use zksync_consensus_crypto::ByteFmt;
+use zksync_consensus_roles::{node, validator};
+use zksync_protobuf::{kB, ProtoFmt as _};
+
+/// Timeout on performing the handshake of the sentry link.
+const HANDSHAKE_TIMEOUT: time::Duration = time::Duration::seconds(5);
+
+/// Backend of a sentry, as seen by the sentry.
+/// Acts as the validator signer of the sentry, forwarding the signing requests to the backend.
+pub(crate) struct Backend {
+    /// Validator key held by the backend.
+    pub(crate) key: validator::PublicKey,
+    /// Client of the signing requests.
+    sign: rpc::Client<rpc::sentry_sign::Rpc>,
+    /// Client forwarding the received consensus messages to the backend.
+    consensus: rpc::Client<rpc::consensus::Rpc>,
+    /// Whether the backend is currently connected.
+    pub(crate) connected: sync::watch::Sender<bool>,
+}
+
+impl fmt::Debug for Backend {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Backend").field("key", &self.key).finish()
+    }
+}
+
+impl Backend {
+    /// Constructs the backend state of a sentry.
+    pub(crate) fn new(ctx: &ctx::Ctx, key: validator::PublicKey, rate: limiter::Rate) -> Self {
+        Self {
+            key,
+            sign: rpc::Client::new(ctx, rpc::sentry_sign::RATE),
+            consensus: rpc::Client::new(ctx, rate),
+            connected: sync::watch::channel(false).0,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl validator::ValidatorSigner for Backend {
+    fn public(&self) -> validator::PublicKey {
+        self.key.clone()
+    }
+
+    async fn sign_hash(
+        &self,
+        _ctx: &ctx::Ctx,
+        _msg_hash: &validator::MsgHash,
+    ) -> ctx::Result<validator::Signature> {
+        Err(anyhow::format_err!("backend signs only the messages it can inspect").into())
+    }
+
+    async fn sign(
+        &self,
+        ctx: &ctx::Ctx,
+        msg: &validator::Msg,
+    ) -> ctx::Result<validator::Signature> {
+        let resp = self
+            .sign
+            .call(ctx, &rpc::sentry_sign::Req(msg.clone()), kB)
+            .await?;
+        resp.0
+            .verify_msg(msg, &self.key)
+            .context("backend returned an invalid signature")?;
+        Ok(resp.0)
+    }
+}
+
+/// Server of the signing requests of the sentry, run by the backend.
+struct SignServer<'a> {
+    key: &'a dyn validator::ValidatorSigner,
+}
+
+#[async_trait::async_trait]
+impl rpc::Handler<rpc::sentry_sign::Rpc> for SignServer<'_> {
+    fn max_req_size(&self) -> usize {
+        kB
+    }
+
+    async fn handle(
+        &self,
+        ctx: &ctx::Ctx,
+        req: rpc::sentry_sign::Req,
+    ) -> anyhow::Result<rpc::sentry_sign::Resp> {
+        // The validator key signs the consensus messages only for the local consensus actor.
+        match &req.0 {
+            validator::Msg::SessionId(_)
+            | validator::Msg::NetAddress(_)
+            | validator::Msg::Heartbeat(_) => {}
+            validator::Msg::Consensus(_)
+            | validator::Msg::KeyRotation(_)
+            | validator::Msg::ProtocolUpgrade(_) => {
+                anyhow::bail!("sentry is not allowed to request this signature")
+            }
+        }
+        let sig = self.key.sign(ctx, &req.0).await.context("sign()")?;
+        Ok(rpc::sentry_sign::Resp(sig))
+    }
+}
+
+/// Server of the consensus messages sent by the backend, run by the sentry.
+struct RelayServer<'a> {
+    net: &'a Network,
+    backend: &'a Backend,
+}
+
+#[async_trait::async_trait]
+impl rpc::Handler<rpc::sentry_relay::Rpc> for RelayServer<'_> {
+    fn max_req_size(&self) -> usize {
+        self.net.gossip.max_block_size().saturating_add(kB)
+    }
+
+    async fn handle(&self, ctx: &ctx::Ctx, req: rpc::sentry_relay::Req) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            req.msg.key == self.backend.key,
+            "message signed by a different key"
+        );
+        self.net
+            .gossip
+            .high_qc
+            .observe(&self.net.gossip.genesis(), &req.msg);
+        match &req.recipient {
+            Some(key) => self.net.send(ctx, key, req.msg, req.trace).await,
+            None => self.net.broadcast(ctx, req.msg, req.trace).await,
+        }
+    }
+}
+
+impl Network {
+    /// Sends a consensus message of the backend to its sentry, which delivers it.
+    pub(crate) async fn relay_to_sentry(
+        &self,
+        ctx: &ctx::Ctx,
+        relay: &rpc::Client<rpc::sentry_relay::Rpc>,
+        msg: validator::Signed<validator::ConsensusMsg>,
+        recipient: Option<validator::PublicKey>,
+        trace: Option<TraceContext>,
+    ) -> anyhow::Result<()> {
+        let req = rpc::sentry_relay::Req {
+            msg,
+            recipient,
+            trace,
+        };
+        relay.call(ctx, &req, kB).await?;
+        Ok(())
+    }
+
+    /// Forwards a consensus message received by the sentry to its backend.
+    /// The message is acknowledged once the backend has processed it.
+    pub(crate) async fn forward_to_backend(&self, ctx: &ctx::Ctx, req: io::ConsensusReq) {
+        let Some(backend) = &self.backend else {
+            return;
+        };
+        let fwd = rpc::consensus::Req {
+            msg: req.msg,
+            trace: req.trace,
+        };
+        if let Err(err) = backend.consensus.call(ctx, &fwd, RESP_MAX_SIZE).await {
+            tracing::info!("forward_to_backend(): {err:#}");
+            return;
+        }
+        let _ = req.ack.send(());
+    }
+
+    /// Serves the private link of the backend, on the sentry.
+    pub(crate) async fn run_backend_stream(
+        &self,
+        ctx: &ctx::Ctx,
+        mut stream: noise::Stream,
+    ) -> anyhow::Result<()> {
+        let backend = self.backend.as_ref().context("not a sentry")?;
+        sentry_handshake(
+            ctx,
+            &self.gossip.cfg.gossip.key,
+            self.gossip.genesis().hash(),
+            &mut stream,
+            &backend.key,
+        )
+        .await
+        .context("handshake")?;
+        let connected = backend
+            .connected
+            .send_if_modified(|connected| !std::mem::replace(connected, true));
+        anyhow::ensure!(connected, "backend is already connected");
+        let res = scope::run!(ctx, |ctx, s| async {
+            let mut service = rpc::Service::new()
+                .keepalive(self.gossip.cfg.keepalive)
+                .add_server(
+                    rpc::ping::Server(&self.gossip.cfg.time_source),
+                    rpc::ping::RATE,
+                )
+                .add_client(&backend.sign)
+                .add_client(&backend.consensus)
+                .add_server(
+                    RelayServer { net: self, backend },
+                    self.gossip.rpc_cfg().consensus_rate,
+                );
+            if let Some(ping_timeout) = &self.gossip.cfg.ping_timeout {
+                let ping_client = rpc::Client::<rpc::ping::Rpc>::new(ctx, rpc::ping::RATE);
+                service = service.add_client(&ping_client);
+                s.spawn(async {
+                    let ping_client = ping_client;
+                    ping_client
+                        .ping_loop(ctx, &self.gossip.cfg.time_source, *ping_timeout, |_| {})
+                        .await
+                });
+            }
+            #[cfg(feature = "chaos")]
+            let service = service.chaos(self.gossip.cfg.chaos.clone());
+            service.run(ctx, stream).await?;
+            Ok(())
+        })
+        .await;
+        backend.connected.send_replace(false);
+        res
+    }
+
+    /// Establishes the private link to the sentry, on the backend.
+    async fn run_sentry_link(
+        &self,
+        ctx: &ctx::Ctx,
+        relay: &rpc::Client<rpc::sentry_relay::Rpc>,
+        addr: std::net::SocketAddr,
+        sentry: &node::PublicKey,
+    ) -> anyhow::Result<()> {
+        let mut stream = preface::connect(
+            ctx,
+            &self.gossip.cfg.transport,
+            addr,
+            preface::Endpoint::SentryLink,
+        )
+        .await?;
+        backend_handshake(
+            ctx,
+            &*self.key,
+            self.gossip.genesis().hash(),
+            &mut stream,
+            sentry,
+        )
+        .await
+        .context("handshake")?;
+        self.gossip
+            .reconnect
+            .connected(ctx, &OutboundPeer::Sentry(sentry.clone()));
+        scope::run!(ctx, |ctx, s| async {
+            let mut service = rpc::Service::new()
+                .keepalive(self.gossip.cfg.keepalive)
+                .add_server(
+                    rpc::ping::Server(&self.gossip.cfg.time_source),
+                    rpc::ping::RATE,
+                )
+                .add_client(relay)
+                .add_server(
+                    ConsensusServer {
+                        net: self,
+                        replay: ReplayWindow::new(self.gossip.cfg.consensus_replay_window),
+                    },
+                    self.gossip.rpc_cfg().consensus_rate,
+                )
+                .add_server(SignServer { key: &*self.key }, rpc::sentry_sign::RATE);
+            if let Some(ping_timeout) = &self.gossip.cfg.ping_timeout {
+                let ping_client = rpc::Client::<rpc::ping::Rpc>::new(ctx, rpc::ping::RATE);
+                service = service.add_client(&ping_client);
+                s.spawn(async {
+                    let ping_client = ping_client;
+                    ping_client
+                        .ping_loop(ctx, &self.gossip.cfg.time_source, *ping_timeout, |_| {})
+                        .await
+                });
+            }
+            #[cfg(feature = "chaos")]
+            let service = service.chaos(self.gossip.cfg.chaos.clone());
+            service.run(ctx, stream).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Maintains the private link to the sentry, on the backend.
+    /// If the link breaks, it is reestablished according to the reconnect policy.
+    pub(crate) async fn maintain_sentry_link(
+        &self,
+        ctx: &ctx::Ctx,
+        addr: std::net::SocketAddr,
+        sentry: &node::PublicKey,
+    ) {
+        let Some(relay) = &self.sentry else {
+            return;
+        };
+        let target = OutboundPeer::Sentry(sentry.clone());
+        let _ = self
+            .gossip
+            .reconnect
+            .maintain(ctx, &target, || {
+                self.run_sentry_link(ctx, relay, addr, sentry)
+            })
+            .await;
+    }
+}
+
+/// Handshake of the backend on the sentry link: the backend authenticates with
+/// the validator key, the sentry with its node key.
+async fn backend_handshake(
+    ctx: &ctx::Ctx,
+    me: &dyn validator::ValidatorSigner,
+    genesis: validator::GenesisHash,
+    stream: &mut noise::Stream,
+    sentry: &node::PublicKey,
+) -> anyhow::Result<()> {
+    let ctx = &ctx.with_timeout(HANDSHAKE_TIMEOUT);
+    let session_id = node::SessionId(stream.id().encode());
+    frame::send_proto(
+        ctx,
+        stream,
+        &handshake::Handshake {
+            session_id: me.sign_msg(ctx, session_id.clone()).await?,
+            genesis,
+            resume: None,
+        },
+    )
+    .await?;
+    let h: node::Signed<node::SessionId> = frame::recv_proto(ctx, stream, kB).await?;
+    anyhow::ensure!(h.msg == session_id, "session id mismatch");
+    anyhow::ensure!(&h.key == sentry, "unexpected sentry");
+    h.verify().context("verify()")?;
+    Ok(())
+}
+
+/// Handshake of the sentry on the sentry link, see `backend_handshake()`.
+async fn sentry_handshake(
+    ctx: &ctx::Ctx,
+    me: &node::SecretKey,
+    genesis: validator::GenesisHash,
+    stream: &mut noise::Stream,
+    backend: &validator::PublicKey,
+) -> anyhow::Result<()> {
+    let ctx = &ctx.with_timeout(HANDSHAKE_TIMEOUT);
+    let session_id = node::SessionId(stream.id().encode());
+    let h: handshake::Handshake =
+        frame::recv_proto(ctx, stream, handshake::Handshake::max_size()).await?;
+    anyhow::ensure!(h.genesis == genesis, "genesis mismatch");
+    anyhow::ensure!(h.session_id.msg == session_id, "session id mismatch");
+    anyhow::ensure!(&h.session_id.key == backend, "unexpected backend");
+    h.session_id.verify().context("verify()")?;
+    frame::send_proto(ctx, stream, &me.sign_msg(session_id)).await?;
+    Ok(())
+}
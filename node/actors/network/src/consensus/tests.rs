@@ -1,9 +1,9 @@
 use super::*;
-use crate::{io, metrics, preface, rpc, testonly};
+use crate::{io, metrics, preface, rpc, testonly, transport};
 use assert_matches::assert_matches;
 use rand::Rng;
 use tracing::Instrument as _;
-use zksync_concurrency::{ctx, net, scope, testonly::abort_on_panic};
+use zksync_concurrency::{ctx, scope, sync, testonly::abort_on_panic};
 use zksync_consensus_roles::validator;
 use zksync_consensus_storage::testonly::new_store;
 
@@ -37,6 +37,7 @@ async fn test_one_connection_per_validator() {
         tracing::info!("Impersonate node 1, and try to establish additional connection to node 0. It should close automatically after the handshake.");
         let mut stream = preface::connect(
             ctx,
+            &nodes[0].cfg().transport,
             *nodes[0].cfg().server_addr,
             preface::Endpoint::ConsensusNet,
         )
@@ -46,6 +47,7 @@ async fn test_one_connection_per_validator() {
             ctx,
             &nodes[1].cfg().validator_key.clone().unwrap(),
             setup.genesis.hash(),
+            &Tickets::new("consensus", &nodes[1].cfg().gossip.key, None),
             &mut stream,
             &nodes[0].cfg().validator_key.as_ref().unwrap().public(),
         )
@@ -72,7 +74,8 @@ async fn test_genesis_mismatch() {
     let cfgs = testonly::new_configs(rng, &setup, /*gossip_peers=*/ 0);
 
     scope::run!(ctx, |ctx, s| async {
-        let mut listener = cfgs[1].server_addr.bind().context("server_addr.bind()")?;
+        let mut listener = transport::bind(&cfgs[1].transport, &cfgs[1].server_addr)
+            .context("server_addr.bind()")?;
 
         tracing::info!("Start one node, we will simulate the other one.");
         let (store, runner) = new_store(ctx, &setup.genesis).await;
@@ -85,7 +88,7 @@ async fn test_genesis_mismatch() {
             .gossip
             .validator_addrs
             .update(
-                &setup.genesis.validators,
+                &setup.genesis,
                 &[Arc::new(setup.keys[1].sign_msg(validator::NetAddress {
                     addr: cfgs[1].public_addr,
                     version: 0,
@@ -104,18 +107,24 @@ async fn test_genesis_mismatch() {
             .context("preface::accept()")?;
         assert_eq!(endpoint, preface::Endpoint::ConsensusNet);
         tracing::info!("Expect the handshake to fail");
-        let res = handshake::inbound(ctx, &setup.keys[1], rng.gen(), &mut stream).await;
-        assert_matches!(res, Err(handshake::Error::GenesisMismatch));
+        let tickets = Tickets::new("consensus", &cfgs[1].gossip.key, None);
+        let res = handshake::inbound(ctx, &setup.keys[1], rng.gen(), &tickets, &mut stream).await;
+        assert_matches!(res, Err(handshake::Error::GenesisMismatch { .. }));
 
         tracing::info!("Try to connect to a node with a mismatching genesis.");
-        let mut stream =
-            preface::connect(ctx, cfgs[0].public_addr, preface::Endpoint::ConsensusNet)
-                .await
-                .context("preface::connect")?;
+        let mut stream = preface::connect(
+            ctx,
+            &cfgs[0].transport,
+            cfgs[0].public_addr,
+            preface::Endpoint::ConsensusNet,
+        )
+        .await
+        .context("preface::connect")?;
         let res = handshake::outbound(
             ctx,
             &setup.keys[1],
             rng.gen(),
+            &tickets,
             &mut stream,
             &setup.keys[0].public(),
         )
@@ -130,6 +139,57 @@ async fn test_genesis_mismatch() {
     .unwrap();
 }
 
+#[tokio::test]
+async fn test_inbound_genesis_mismatch_quarantine() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let setup = validator::testonly::Setup::new(rng, 2);
+    let cfgs = testonly::new_configs(rng, &setup, /*gossip_peers=*/ 0);
+
+    scope::run!(ctx, |ctx, s| async {
+        tracing::info!("Start one node, we will simulate the other one.");
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let (node, runner) = testonly::Instance::new(ctx, cfgs[0].clone(), store.clone());
+        s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node")));
+
+        tracing::info!("Connect to the node with a mismatching genesis.");
+        let mut stream = preface::connect(
+            ctx,
+            &cfgs[1].transport,
+            cfgs[0].public_addr,
+            preface::Endpoint::ConsensusNet,
+        )
+        .await
+        .context("preface::connect")?;
+        let genesis: validator::GenesisHash = rng.gen();
+        let tickets = Tickets::new("consensus", &cfgs[1].gossip.key, None);
+        let res = handshake::outbound(
+            ctx,
+            &setup.keys[1],
+            genesis,
+            &tickets,
+            &mut stream,
+            &setup.keys[0].public(),
+        )
+        .await;
+        assert_matches!(res, Err(handshake::Error::Stream(_)));
+
+        tracing::info!("Expect the peer to be quarantined.");
+        let consensus = node.net.consensus.as_ref().unwrap();
+        let entry = consensus
+            .quarantine
+            .get(ctx, &setup.keys[1].public())
+            .context("peer not quarantined")?;
+        assert_eq!(genesis, entry.genesis);
+        assert!(entry.until > ctx.now());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_address_change() {
     abort_on_panic();
@@ -166,7 +226,7 @@ async fn test_address_change() {
         // node[0] is expected to connect to its gossip peers.
         // Then it should broadcast its new address and the consensus network
         // should get reconstructed.
-        cfgs[0].server_addr = net::tcp::testonly::reserve_listener();
+        cfgs[0].server_addr = testonly::reserve_virtual_listener();
         cfgs[0].public_addr = *cfgs[0].server_addr;
         let (node0, runner) = testonly::Instance::new(ctx, cfgs[0].clone(), store.clone());
         s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node0")));
@@ -217,12 +277,15 @@ async fn test_transmission() {
             n.wait_for_consensus_connections().await;
         }
         for _ in 0..10 {
-            let want: validator::Signed<validator::ConsensusMsg> = rng.gen();
+            // Only the messages signed by validators are accepted.
+            let want = setup.keys[0].sign_msg(rng.gen::<validator::ConsensusMsg>());
+            let trace = Some(TraceContext::new_root(rng));
             let in_message = io::ConsensusInputMessage {
                 message: want.clone(),
                 recipient: io::Target::Validator(
                     nodes[1].cfg().validator_key.as_ref().unwrap().public(),
                 ),
+                trace,
             };
             nodes[0].pipe.send(in_message.into());
 
@@ -234,7 +297,350 @@ async fn test_transmission() {
                 break got;
             };
             assert_eq!(want, got.msg);
+            assert_eq!(trace, got.trace);
+        }
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_sentry() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+
+    let setup = validator::testonly::Setup::new(rng, 2);
+    let mut cfgs = testonly::new_configs(rng, &setup, 1);
+    // Move the validator key of node 1 to a backend behind it.
+    let key = cfgs[1].validator_key.take().unwrap();
+    cfgs[1].sentry = Some(SentryConfig::Sentry {
+        validator: key.public(),
+    });
+    let mut backend_cfg = testonly::new_fullnode(rng, &cfgs[1]);
+    backend_cfg.validator_key = Some(key.clone());
+    backend_cfg.sentry = Some(SentryConfig::Backend {
+        sentry_addr: *cfgs[1].server_addr,
+        sentry_key: cfgs[1].gossip.key.public(),
+    });
+    cfgs.push(backend_cfg);
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let mut nodes: Vec<_> = cfgs
+            .iter()
+            .enumerate()
+            .map(|(i, cfg)| {
+                let (node, runner) = testonly::Instance::new(ctx, cfg.clone(), store.clone());
+                s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node", i)));
+                node
+            })
+            .collect();
+
+        tracing::info!("waiting for the sentry to connect to the validator");
+        nodes[0].wait_for_consensus_connections().await;
+        nodes[1].wait_for_consensus_connections().await;
+
+        tracing::info!("validator -> backend");
+        let want = setup.keys[0].sign_msg(rng.gen::<validator::ConsensusMsg>());
+        nodes[0].pipe.send(
+            io::ConsensusInputMessage {
+                message: want.clone(),
+                recipient: io::Target::Validator(key.public()),
+                trace: None,
+            }
+            .into(),
+        );
+        let got = loop {
+            if let io::OutputMessage::Consensus(got) = nodes[2].pipe.recv(ctx).await? {
+                break got;
+            }
+        };
+        assert_eq!(want, got.msg);
+        let _ = got.ack.send(());
+
+        tracing::info!("backend -> validator");
+        let want: validator::Signed<validator::ConsensusMsg> = key
+            .sign_msg(ctx, rng.gen::<validator::ConsensusMsg>())
+            .await?;
+        nodes[2].pipe.send(
+            io::ConsensusInputMessage {
+                message: want.clone(),
+                recipient: io::Target::Broadcast,
+                trace: None,
+            }
+            .into(),
+        );
+        let got = loop {
+            if let io::OutputMessage::Consensus(got) = nodes[0].pipe.recv(ctx).await? {
+                break got;
+            }
+        };
+        assert_eq!(want, got.msg);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_heartbeats() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let setup = validator::testonly::Setup::new(rng, 3);
+    let cfgs = testonly::new_configs(rng, &setup, 1);
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let nodes: Vec<_> = cfgs
+            .iter()
+            .enumerate()
+            .map(|(i, cfg)| {
+                let (node, runner) = testonly::Instance::new(ctx, cfg.clone(), store.clone());
+                s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node", i)));
+                node
+            })
+            .collect();
+
+        tracing::info!("waiting for heartbeats from all the other validators");
+        for node in &nodes {
+            let consensus = node.net.consensus.as_ref().unwrap();
+            sync::wait_for(ctx, &mut consensus.heartbeats.subscribe(), |got| {
+                got.len() == nodes.len() - 1
+            })
+            .await?;
+            assert_eq!(nodes.len(), consensus.online_validators());
+        }
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_rotated_key() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 2);
+    let new_key = setup.rotate_key(rng, 1, validator::ViewNumber(0), 0);
+    let mut cfgs = testonly::new_configs(rng, &setup, 1);
+    cfgs[1].validator_key = Some(Arc::new(new_key.clone()));
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let nodes: Vec<_> = cfgs
+            .iter()
+            .enumerate()
+            .map(|(i, cfg)| {
+                let (node, runner) = testonly::Instance::new(ctx, cfg.clone(), store.clone());
+                s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node", i)));
+                node
+            })
+            .collect();
+
+        tracing::info!("waiting for the connections with the rotated key");
+        let consensus = nodes[0].net.consensus.as_ref().unwrap();
+        let want = new_key.public();
+        consensus
+            .inbound
+            .subscribe()
+            .wait_for(|got| got.current().contains(&want))
+            .await
+            .unwrap();
+        consensus
+            .outbound
+            .subscribe()
+            .wait_for(|got| got.current().contains(&want))
+            .await
+            .unwrap();
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[test]
+fn test_replay_window() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let key: validator::SecretKey = rng.gen();
+    let msg = |rng: &mut rand::rngs::StdRng, view| {
+        let mut msg: validator::ReplicaPrepare = rng.gen();
+        msg.view.number = validator::ViewNumber(view);
+        key.sign_msg(validator::ConsensusMsg::ReplicaPrepare(msg))
+    };
+    let window = replay::ReplayWindow::new(5);
+    let a = msg(rng, 10);
+    assert_eq!(Ok(()), window.insert(&a));
+    assert_eq!(Err(replay::Replay::Duplicate), window.insert(&a));
+    // Different messages for the same view are accepted.
+    assert_eq!(Ok(()), window.insert(&msg(rng, 10)));
+    // Older views within the window are accepted.
+    assert_eq!(Ok(()), window.insert(&msg(rng, 6)));
+    // Moving the window makes the older views stale.
+    assert_eq!(Ok(()), window.insert(&msg(rng, 15)));
+    assert_eq!(Err(replay::Replay::Stale), window.insert(&a));
+    assert_eq!(Err(replay::Replay::Stale), window.insert(&msg(rng, 6)));
+
+    // Checking a far-future message doesn't move the window.
+    let b = msg(rng, 100);
+    assert_eq!(Ok(()), window.check(&b));
+    assert_eq!(Ok(()), window.check(&b));
+    assert_eq!(Ok(()), window.insert(&msg(rng, 11)));
+    // Inserting it does.
+    assert_eq!(Ok(()), window.insert(&b));
+    assert_eq!(Err(replay::Replay::Duplicate), window.check(&b));
+    assert_eq!(Err(replay::Replay::Stale), window.check(&msg(rng, 11)));
+
+    // Window of size 0 accepts everything.
+    let window = replay::ReplayWindow::new(0);
+    assert_eq!(Ok(()), window.insert(&a));
+    assert_eq!(Ok(()), window.insert(&a));
+}
+
+#[tokio::test]
+async fn test_outbound_queue() {
+    abort_on_panic();
+    let clock = ctx::ManualClock::new();
+    let ctx = &ctx::test_root(&clock);
+    let rng = &mut ctx.rng();
+    let key: validator::SecretKey = rng.gen();
+    let mut msg = |view, priority| {
+        let view = validator::ViewNumber(view);
+        let msg = match priority {
+            outbound::Priority::Low => {
+                let mut m: validator::ReplicaPrepare = rng.gen();
+                m.view.number = view;
+                validator::ConsensusMsg::ReplicaPrepare(m)
+            }
+            outbound::Priority::Normal => {
+                let mut m: validator::ReplicaCommit = rng.gen();
+                m.view.number = view;
+                validator::ConsensusMsg::ReplicaCommit(m)
+            }
+            outbound::Priority::High => {
+                let mut m: validator::ReplicaTimeout = rng.gen();
+                m.view.number = view;
+                validator::ConsensusMsg::ReplicaTimeout(m)
+            }
+        };
+        let req = rpc::consensus::Req {
+            msg: key.sign_msg(msg),
+            trace: None,
+        };
+        Arc::new(rpc::EncodedReq::new(req))
+    };
+    let queue = outbound::Queue::new(crate::OutboundQueueConfig {
+        capacity: 3,
+        ttl: time::Duration::seconds(10),
+    });
+    let pop = || async { queue.pop(ctx).await.unwrap().0.req().clone() };
+    use outbound::Priority as P;
+
+    // Messages are sent in the order of priority.
+    let (low, normal, high) = (msg(10, P::Low), msg(10, P::Normal), msg(10, P::High));
+    for m in [&low, &normal, &high] {
+        queue.push(ctx, m.clone(), outbound::Delivery::Broadcast);
+    }
+    for m in [&high, &normal, &low] {
+        assert_eq!(m.req(), &pop().await);
+    }
+
+    // Messages for the older views are dropped, except for the high priority ones.
+    let (stale, kept) = (msg(10, P::Normal), msg(10, P::High));
+    let (fresh, late) = (msg(11, P::Low), msg(10, P::High));
+    queue.push(ctx, stale.clone(), outbound::Delivery::Broadcast);
+    queue.push(ctx, kept.clone(), outbound::Delivery::Broadcast);
+    queue.push(ctx, fresh.clone(), outbound::Delivery::Send);
+    queue.push(ctx, msg(10, P::Normal), outbound::Delivery::Broadcast);
+    queue.push(ctx, late.clone(), outbound::Delivery::Broadcast);
+    for m in [&kept, &late] {
+        assert_eq!(m.req(), &pop().await);
+    }
+    let (got, delivery) = queue.pop(ctx).await.unwrap();
+    assert_eq!(fresh.req(), got.req());
+    assert_eq!(outbound::Delivery::Send, delivery);
+
+    // When the queue is full, the oldest lowest priority message is dropped.
+    let msgs: Vec<_> = [P::Low, P::Low, P::Normal, P::Normal]
+        .into_iter()
+        .map(|p| msg(12, p))
+        .collect();
+    for m in &msgs {
+        queue.push(ctx, m.clone(), outbound::Delivery::Broadcast);
+    }
+    for m in [&msgs[2], &msgs[3], &msgs[1]] {
+        assert_eq!(m.req(), &pop().await);
+    }
+
+    // Expired messages are dropped.
+    queue.push(ctx, msg(12, P::High), outbound::Delivery::Broadcast);
+    clock.advance(time::Duration::seconds(11));
+    let last = msg(12, P::Low);
+    queue.push(ctx, last.clone(), outbound::Delivery::Broadcast);
+    assert_eq!(last.req(), &pop().await);
+}
+
+/// Test that consensus messages don't cross a partition,
+/// and that the held back messages are delivered once it is healed.
+#[tokio::test]
+async fn test_partition() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+
+    let setup = validator::testonly::Setup::new(rng, 3);
+    let cfgs = testonly::new_configs(rng, &setup, 1);
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let mut nodes: Vec<_> = cfgs
+            .iter()
+            .map(|cfg| {
+                let (node, runner) = testonly::Instance::new(ctx, cfg.clone(), store.clone());
+                s.spawn_bg(runner.run(ctx));
+                node
+            })
+            .collect();
+        for n in &nodes {
+            n.wait_for_consensus_connections().await;
+        }
+
+        testonly::Instance::partition(&[&[&nodes[0], &nodes[1]], &[&nodes[2]]]);
+        let want = setup.keys[0].sign_msg(rng.gen::<validator::ConsensusMsg>());
+        nodes[0].pipe.send(
+            io::ConsensusInputMessage {
+                message: want.clone(),
+                recipient: io::Target::Broadcast,
+                trace: None,
+            }
+            .into(),
+        );
+        async fn recv(
+            ctx: &ctx::Ctx,
+            node: &mut testonly::Instance,
+        ) -> ctx::OrCanceled<validator::Signed<validator::ConsensusMsg>> {
+            loop {
+                if let io::OutputMessage::Consensus(got) = node.pipe.recv(ctx).await? {
+                    return Ok(got.msg);
+                }
+            }
+        }
+        assert_eq!(want, recv(ctx, &mut nodes[1]).await?);
+        let timeout = &ctx.with_timeout(time::Duration::seconds(1));
+        assert!(recv(timeout, &mut nodes[2]).await.is_err());
+
+        for n in &nodes {
+            n.heal();
         }
+        assert_eq!(want, recv(ctx, &mut nodes[2]).await?);
         Ok(())
     })
     .await
@@ -0,0 +1,357 @@
+//! Wire dumps of the connections with a single peer, for debugging protocol incompatibilities.
+//! A dump is started for a peer (see `Monitor::start_dump`) and lasts until the given deadline.
+//! Meanwhile, every RPC message exchanged with the peer is decoded and appended to the dump file
+//! as a line of JSON (in the proto JSON mapping). Bytes fields longer than `MAX_BYTES_LEN`
+//! (payloads, topic messages, etc.) are replaced with their keccak256 hash, so that the dumps
+//! don't leak the contents of the blocks. The dump file is rotated once it reaches `MAX_FILE_SIZE`.
+//!
+//! The connections only queue the decoded messages: they are encoded and written to the files
+//! by a background task of the network (`Dumps::run`). The messages are dropped while
+//! the queue is full, so that a slow disk never stalls the connections.
+use anyhow::Context as _;
+use prost_reflect::{DynamicMessage, ReflectMessage as _, Value};
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+use zksync_concurrency::{ctx, scope, sync, time};
+use zksync_consensus_crypto::keccak256::Keccak256;
+use zksync_protobuf::ProtoFmt;
+
+/// Bytes fields longer than that are replaced with their hash.
+const MAX_BYTES_LEN: usize = 32;
+/// Size of the dump file above which it is rotated.
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+/// Number of the rotated dump files kept per peer.
+const MAX_ROTATED_FILES: usize = 3;
+/// Maximal number of the simultaneously active dumps, which bounds the disk usage.
+pub const MAX_DUMPS: usize = 8;
+/// Number of the queued messages above which the dumped messages are dropped.
+const QUEUE_SIZE: usize = 1024;
+
+/// Direction of a dumped message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    /// Message received from the peer.
+    Received,
+    /// Message sent to the peer.
+    Sent,
+}
+
+/// Request to the task writing the dump files.
+enum Command {
+    /// Starts (or restarts) the dump of a peer, truncating its file.
+    Start { peer: String, path: PathBuf },
+    /// Stops the dump of a peer, closing its file.
+    Stop { peer: String },
+    /// Appends a message to the dump of a peer.
+    Msg {
+        peer: Arc<str>,
+        time: time::Utc,
+        direction: Direction,
+        method: String,
+        msg: DynamicMessage,
+    },
+}
+
+/// Open dump file of a peer.
+struct File {
+    path: PathBuf,
+    file: fs::File,
+    size: u64,
+}
+
+impl File {
+    /// Creates the dump file, truncating the existing one.
+    fn create(path: PathBuf) -> anyhow::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("create_dir_all({dir:?})"))?;
+        }
+        let file = fs::File::create(&path).with_context(|| format!("create({path:?})"))?;
+        Ok(Self {
+            path,
+            file,
+            size: 0,
+        })
+    }
+
+    /// Appends a line to the dump file, rotating it if it is too large.
+    fn write(&mut self, line: &[u8]) -> anyhow::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > MAX_FILE_SIZE {
+            for i in (1..MAX_ROTATED_FILES).rev() {
+                let _ = fs::rename(rotated(&self.path, i), rotated(&self.path, i + 1));
+            }
+            fs::rename(&self.path, rotated(&self.path, 1)).context("rename()")?;
+            self.file = fs::File::create(&self.path).context("create()")?;
+            self.size = 0;
+        }
+        self.file.write_all(line).context("write_all()")?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Path of the `i`-th rotated dump file.
+fn rotated(path: &Path, i: usize) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{i}"));
+    path.into()
+}
+
+/// Encodes a dumped message as a line of JSON.
+fn encode_line(
+    time: time::Utc,
+    direction: Direction,
+    method: &str,
+    mut msg: DynamicMessage,
+) -> Vec<u8> {
+    redact(&mut msg);
+    let mut line = serde_json::to_vec(&serde_json::json!({
+        "time_us": (time - time::UNIX_EPOCH).whole_microseconds() as i64,
+        "direction": match direction {
+            Direction::Received => "received",
+            Direction::Sent => "sent",
+        },
+        "method": method,
+        "msg": msg,
+    }))
+    .unwrap();
+    line.push(b'\n');
+    line
+}
+
+/// Active dump of a peer, as reported by `Monitor::dumps()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpInfo {
+    /// Peer key, as encoded with `TextFmt`.
+    pub peer: String,
+    /// Path of the dump file.
+    pub path: PathBuf,
+    /// Time until which the messages are dumped.
+    pub until: time::Utc,
+}
+
+/// Wire dumps of a network. Clones share the dumps.
+#[derive(Clone)]
+pub(crate) struct Dumps(Arc<Inner>);
+
+struct Inner {
+    /// Active dumps, by peer.
+    dumps: Mutex<BTreeMap<String, DumpInfo>>,
+    /// Whether `dumps` may be non-empty, so that the connections don't take
+    /// the `dumps` lock when no dump is active.
+    active: AtomicBool,
+    /// Queue of the requests to the writer task.
+    send: ctx::channel::Sender<Command>,
+    recv: sync::Mutex<ctx::channel::Receiver<Command>>,
+}
+
+impl Default for Dumps {
+    fn default() -> Self {
+        let (send, recv) = ctx::channel::bounded(QUEUE_SIZE);
+        Self(Arc::new(Inner {
+            dumps: Mutex::default(),
+            active: AtomicBool::new(false),
+            send,
+            recv: sync::Mutex::new(recv),
+        }))
+    }
+}
+
+impl fmt::Debug for Dumps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dumps").finish_non_exhaustive()
+    }
+}
+
+impl Dumps {
+    /// Starts dumping the messages exchanged with `peer` (its node or validator key,
+    /// as encoded with `TextFmt`) to a file in `dir`, until `until`.
+    /// Restarting a dump of the same peer truncates its file. Returns the path of the file.
+    pub(crate) async fn start(
+        &self,
+        ctx: &ctx::Ctx,
+        peer: &str,
+        dir: &Path,
+        until: time::Utc,
+    ) -> ctx::Result<PathBuf> {
+        let path = dir.join(format!("{}.dump", peer.replace(':', "_")));
+        {
+            let mut dumps = self.0.dumps.lock().unwrap();
+            dumps.retain(|_, d| d.until > ctx.now_utc());
+            if !dumps.contains_key(peer) && dumps.len() >= MAX_DUMPS {
+                return Err(anyhow::format_err!("too many active dumps").into());
+            }
+            dumps.insert(
+                peer.to_string(),
+                DumpInfo {
+                    peer: peer.to_string(),
+                    path: path.clone(),
+                    until,
+                },
+            );
+            self.0.active.store(true, Ordering::Relaxed);
+        }
+        // The messages queued before the file is created are dropped by the writer.
+        let start = Command::Start {
+            peer: peer.to_string(),
+            path: path.clone(),
+        };
+        self.0.send.send(ctx, start).await?;
+        tracing::info!("dumping the messages of peer {peer} to {path:?} until {until:?}");
+        Ok(path)
+    }
+
+    /// Stops dumping the messages exchanged with `peer`. Returns false if there was no such dump.
+    pub(crate) async fn stop(&self, ctx: &ctx::Ctx, peer: &str) -> ctx::OrCanceled<bool> {
+        let stopped = {
+            let mut dumps = self.0.dumps.lock().unwrap();
+            let stopped = dumps.remove(peer).is_some();
+            self.0.active.store(!dumps.is_empty(), Ordering::Relaxed);
+            stopped
+        };
+        let stop = Command::Stop {
+            peer: peer.to_string(),
+        };
+        self.0.send.send(ctx, stop).await?;
+        Ok(stopped)
+    }
+
+    /// Returns the dumps which are active at `now`.
+    pub(crate) fn list(&self, now: time::Utc) -> Vec<DumpInfo> {
+        let mut dumps = self.0.dumps.lock().unwrap();
+        dumps.retain(|_, d| d.until > now);
+        self.0.active.store(!dumps.is_empty(), Ordering::Relaxed);
+        dumps.values().cloned().collect()
+    }
+
+    /// Writes the dumped messages to the files, until the context is canceled.
+    /// Failures are logged rather than returned, so that they never affect the network.
+    pub(crate) async fn run(&self, ctx: &ctx::Ctx) {
+        let Ok(mut recv) = sync::lock(ctx, &self.0.recv)
+            .await
+            .map(|recv| recv.into_async())
+        else {
+            return;
+        };
+        let mut files = BTreeMap::<String, File>::new();
+        while let Ok(cmd) = recv.recv(ctx).await {
+            match cmd {
+                Command::Start { peer, path } => {
+                    match scope::wait_blocking(|| File::create(path)).await {
+                        Ok(file) => {
+                            files.insert(peer, file);
+                        }
+                        Err(err) => {
+                            tracing::warn!("failed to start the dump of peer {peer}: {err:#}")
+                        }
+                    }
+                }
+                Command::Stop { peer } => {
+                    files.remove(&peer);
+                }
+                Command::Msg {
+                    peer,
+                    time,
+                    direction,
+                    method,
+                    msg,
+                } => {
+                    let Some(file) = files.get_mut(&*peer) else {
+                        continue;
+                    };
+                    let res = scope::wait_blocking(|| {
+                        file.write(&encode_line(time, direction, &method, msg))
+                    })
+                    .await;
+                    if let Err(err) = res {
+                        tracing::warn!("failed to dump a message of peer {peer}: {err:#}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Replaces the long bytes fields of the message with their hashes.
+fn redact(msg: &mut DynamicMessage) {
+    for (_, value) in msg.fields_mut() {
+        redact_value(value);
+    }
+}
+
+fn redact_value(value: &mut Value) {
+    match value {
+        Value::Bytes(bytes) if bytes.len() > MAX_BYTES_LEN => {
+            *bytes = Keccak256::new(&bytes[..]).as_bytes().to_vec().into();
+        }
+        Value::Message(msg) => redact(msg),
+        Value::List(values) => values.iter_mut().for_each(redact_value),
+        Value::Map(values) => values.values_mut().for_each(redact_value),
+        _ => {}
+    }
+}
+
+/// Dumps the messages exchanged over a connection with a peer, while its dump is active.
+#[derive(Debug, Clone)]
+pub(crate) struct Dumper {
+    peer: Arc<str>,
+    dumps: Dumps,
+}
+
+impl Dumper {
+    /// Dumper of the connection with `peer`.
+    pub(crate) fn new(peer: String, dumps: Dumps) -> Self {
+        Self {
+            peer: peer.into(),
+            dumps,
+        }
+    }
+
+    /// Queues a message of RPC `method` to be dumped, if the dump of the peer is active.
+    /// The message is dropped if the queue is full, so that it never stalls the connection.
+    pub(crate) fn dump<T: ProtoFmt>(
+        &self,
+        now: time::Utc,
+        direction: Direction,
+        method: &str,
+        msg: &T,
+    ) {
+        let inner = &self.dumps.0;
+        if !inner.active.load(Ordering::Relaxed) {
+            return;
+        }
+        {
+            let mut dumps = inner.dumps.lock().unwrap();
+            let Some(dump) = dumps.get(&*self.peer) else {
+                return;
+            };
+            if dump.until <= now {
+                dumps.remove(&*self.peer);
+                inner.active.store(!dumps.is_empty(), Ordering::Relaxed);
+                tracing::info!("dump of peer {} finished", self.peer);
+                // If the queue is full, the file is closed when the dump is restarted.
+                let _ = inner.send.try_send(Command::Stop {
+                    peer: self.peer.to_string(),
+                });
+                return;
+            }
+        }
+        let cmd = Command::Msg {
+            peer: self.peer.clone(),
+            time: now,
+            direction,
+            method: method.to_string(),
+            msg: msg.build().transcode_to_dynamic(),
+        };
+        if inner.send.try_send(cmd).is_err() {
+            tracing::debug!("dump queue full, dropping a message of peer {}", self.peer);
+        }
+    }
+}
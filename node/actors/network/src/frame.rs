@@ -1,32 +1,146 @@
 //! Simple frame encoding format (length ++ value) for protobuf messages,
 //! since protobuf messages do not have delimiters.
+//!
+//! The send paths don't copy the encoded messages into intermediate buffers:
+//! an encoded message is moved into `Bytes` and split into the mux frames
+//! (or written together with its length prefix) without copying.
+//! The messages sent over a connection are encoded with an `Encoder`,
+//! which reuses its buffers across the messages.
 use crate::{mux, noise::bytes};
+use ::bytes::{Buf as _, BufMut as _, Bytes, BytesMut};
+use vise::EncodeLabelValue;
 use zksync_concurrency::{ctx, io};
 
+/// Error of sending or receiving a frame.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    /// Context has been canceled, most likely due to a timeout.
+    #[error("timeout")]
+    Timeout,
+    /// Received frame is not a valid message.
+    #[error("decode: {0:#}")]
+    Decode(#[source] anyhow::Error),
+    /// Received frame exceeds the size limit.
+    #[error("frame too large: max = {max}B, got {got}B")]
+    FrameTooLarge { max: usize, got: usize },
+    /// Underlying transport failed.
+    #[error("io: {0:#}")]
+    Io(#[source] anyhow::Error),
+    /// Peer didn't follow the protocol (e.g. closed the stream in the middle of a frame).
+    #[error("protocol violation: {0}")]
+    ProtocolViolation(&'static str),
+    /// Peer refused to serve the request, because of the rate limit.
+    #[error("rate limited")]
+    RateLimited,
+}
+
+/// Class of a frame error, used as a metric label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EncodeLabelValue)]
+#[metrics(rename_all = "snake_case")]
+pub(crate) enum ErrorClass {
+    Timeout,
+    Decode,
+    FrameTooLarge,
+    Io,
+    ProtocolViolation,
+    RateLimited,
+}
+
+impl Error {
+    /// Class of the error.
+    pub(crate) fn class(&self) -> ErrorClass {
+        match self {
+            Self::Timeout => ErrorClass::Timeout,
+            Self::Decode(_) => ErrorClass::Decode,
+            Self::FrameTooLarge { .. } => ErrorClass::FrameTooLarge,
+            Self::Io(_) => ErrorClass::Io,
+            Self::ProtocolViolation(_) => ErrorClass::ProtocolViolation,
+            Self::RateLimited => ErrorClass::RateLimited,
+        }
+    }
+
+    /// Classifies an error of a mux stream operation.
+    fn mux(err: anyhow::Error) -> Self {
+        match err.is::<ctx::Canceled>() {
+            true => Self::Timeout,
+            false => Self::Io(err),
+        }
+    }
+}
+
+impl From<ctx::Canceled> for Error {
+    fn from(_: ctx::Canceled) -> Self {
+        Self::Timeout
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err.into())
+    }
+}
+
+/// Buffers for encoding the messages sent over a connection, reused across the messages.
+/// An encoded message is split off `buf` without copying, and `buf` reclaims
+/// the allocation once the message has been sent and dropped.
+#[derive(Debug, Default)]
+pub(crate) struct Encoder {
+    /// Intermediate (non-canonical) encoding of the message.
+    scratch: Vec<u8>,
+    /// Encoded messages.
+    buf: BytesMut,
+}
+
+impl Encoder {
+    /// Encodes a proto message.
+    pub(crate) fn encode<T: zksync_protobuf::ProtoFmt>(&mut self, msg: &T) -> Bytes {
+        zksync_protobuf::encode_to(msg, &mut self.scratch, (&mut self.buf).writer());
+        self.buf.split().freeze()
+    }
+}
+
+/// Max number of bytes of a frame allocated before they are received.
+const CHUNK_SIZE: usize = 64 * zksync_protobuf::kB;
+
+/// Frame length reserved for the "rate limited" response, which has no payload.
+/// Such a large frame would never be accepted anyway.
+const RATE_LIMITED: u32 = u32::MAX;
+
 /// Reads a raw frame of bytes from the stream and interprets it as proto.
 /// A `frame : [u8]` is encoded as `L ++ frame`, where `L` is
 /// a little endian encoding of `frame.len() as u32`.
+/// Returns `Error::RateLimited` if the peer sent the "rate limited" response instead.
 /// Returns the decoded proto and the size of the received message in bytes.
 pub(crate) async fn mux_recv_proto<T: zksync_protobuf::ProtoFmt>(
     ctx: &ctx::Ctx,
     stream: &mut mux::ReadStream,
     max_size: usize,
-) -> anyhow::Result<(T, usize)> {
+) -> Result<(T, usize), Error> {
     let mut msg_size = bytes::Buffer::new(4);
-    stream.read_exact(ctx, &mut msg_size).await?;
+    stream
+        .read_exact(ctx, &mut msg_size)
+        .await
+        .map_err(Error::mux)?;
     if msg_size.capacity() != 0 {
-        anyhow::bail!("end of stream");
+        return Err(Error::ProtocolViolation("end of stream"));
     }
-    let msg_size = u32::from_le_bytes(msg_size.prefix()) as usize;
+    let msg_size = u32::from_le_bytes(msg_size.prefix());
+    if msg_size == RATE_LIMITED {
+        return Err(Error::RateLimited);
+    }
+    let msg_size = msg_size as usize;
     if msg_size > max_size {
-        anyhow::bail!("message too large: max = {}B, got {msg_size}B", max_size);
+        return Err(Error::FrameTooLarge {
+            max: max_size,
+            got: msg_size,
+        });
     }
     let mut msg = bytes::Buffer::new(msg_size);
-    stream.read_exact(ctx, &mut msg).await?;
+    stream.read_exact(ctx, &mut msg).await.map_err(Error::mux)?;
     if msg.len() < msg_size {
-        anyhow::bail!("end of stream");
+        return Err(Error::ProtocolViolation("end of stream"));
     }
-    let msg = zksync_protobuf::decode(msg.as_slice())?;
+    let msg = zksync_protobuf::decode(msg.as_slice()).map_err(Error::Decode)?;
     Ok((msg, msg_size))
 }
 
@@ -36,14 +150,48 @@ pub(crate) async fn mux_recv_proto<T: zksync_protobuf::ProtoFmt>(
 pub(crate) async fn mux_send_proto<T: zksync_protobuf::ProtoFmt>(
     ctx: &ctx::Ctx,
     stream: &mut mux::WriteStream,
+    encoder: &mut Encoder,
     msg: &T,
-) -> anyhow::Result<usize> {
-    let msg = zksync_protobuf::encode(msg);
+) -> Result<usize, Error> {
+    mux_send_encoded(ctx, stream, encoder.encode(msg)).await
+}
+
+/// Sends an already encoded proto as a raw frame of bytes to the stream.
+/// Useful for sending the same message to multiple streams:
+/// cloning `Bytes` doesn't copy the message.
+/// It doesn't flush the stream.
+/// Returns the size of the sent proto in bytes.
+pub(crate) async fn mux_send_encoded(
+    ctx: &ctx::Ctx,
+    stream: &mut mux::WriteStream,
+    msg: Bytes,
+) -> Result<usize, Error> {
+    let msg_size = u32::try_from(msg.len())
+        .ok()
+        .filter(|n| *n != RATE_LIMITED)
+        .ok_or(Error::FrameTooLarge {
+            max: RATE_LIMITED as usize - 1,
+            got: msg.len(),
+        })?;
+    stream
+        .write_all(ctx, &u32::to_le_bytes(msg_size))
+        .await
+        .map_err(Error::mux)?;
+    let msg_len = msg.len();
+    stream.write_bytes(ctx, msg).await.map_err(Error::mux)?;
+    Ok(msg_len)
+}
+
+/// Sends the "rate limited" response to the stream, in place of a proto frame.
+/// It doesn't flush the stream.
+pub(crate) async fn mux_send_rate_limited(
+    ctx: &ctx::Ctx,
+    stream: &mut mux::WriteStream,
+) -> Result<(), Error> {
     stream
-        .write_all(ctx, &u32::to_le_bytes(msg.len().try_into()?))
-        .await?;
-    stream.write_all(ctx, &msg).await?;
-    Ok(msg.len())
+        .write_all(ctx, &u32::to_le_bytes(RATE_LIMITED))
+        .await
+        .map_err(Error::mux)
 }
 
 /// Reads a raw frame of bytes from the stream and interprets it as proto.
@@ -53,17 +201,25 @@ pub(crate) async fn recv_proto<T: zksync_protobuf::ProtoFmt, S: io::AsyncRead +
     ctx: &ctx::Ctx,
     stream: &mut S,
     max_size: usize,
-) -> anyhow::Result<T> {
+) -> Result<T, Error> {
     let mut msg_size = [0u8; 4];
     io::read_exact(ctx, stream, &mut msg_size).await??;
-    let msg_size = u32::from_le_bytes(msg_size);
-    anyhow::ensure!(
-        msg_size as usize <= max_size,
-        "message too large: max = {max_size}, got {msg_size}",
-    );
-    let mut msg = vec![0u8; msg_size as usize];
-    io::read_exact(ctx, stream, &mut msg[..]).await??;
-    zksync_protobuf::decode(&msg)
+    let msg_size = u32::from_le_bytes(msg_size) as usize;
+    if msg_size > max_size {
+        return Err(Error::FrameTooLarge {
+            max: max_size,
+            got: msg_size,
+        });
+    }
+    // The buffer grows as the frame arrives, so that a peer announcing
+    // a large frame can't make us allocate it upfront.
+    let mut msg = Vec::with_capacity(msg_size.min(CHUNK_SIZE));
+    while msg.len() < msg_size {
+        let start = msg.len();
+        msg.resize(start + (msg_size - start).min(CHUNK_SIZE), 0);
+        io::read_exact(ctx, stream, &mut msg[start..]).await??;
+    }
+    zksync_protobuf::decode(&msg).map_err(Error::Decode)
 }
 
 /// Sends a proto serialized to a raw frame of bytes to the stream.
@@ -71,10 +227,15 @@ pub(crate) async fn send_proto<T: zksync_protobuf::ProtoFmt, S: io::AsyncWrite +
     ctx: &ctx::Ctx,
     stream: &mut S,
     msg: &T,
-) -> anyhow::Result<()> {
+) -> Result<(), Error> {
     let msg = zksync_protobuf::encode(msg);
-    io::write_all(ctx, stream, &u32::to_le_bytes(msg.len().try_into()?)).await??;
-    io::write_all(ctx, stream, &msg).await??;
+    let msg_size = u32::try_from(msg.len()).map_err(|_| Error::FrameTooLarge {
+        max: u32::MAX as usize,
+        got: msg.len(),
+    })?;
+    let msg_size = u32::to_le_bytes(msg_size);
+    let mut buf = msg_size.as_slice().chain(msg.as_slice());
+    io::write_all_buf(ctx, stream, &mut buf).await??;
     io::flush(ctx, stream).await??;
     Ok(())
 }
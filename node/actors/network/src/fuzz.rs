@@ -0,0 +1,287 @@
+//! Entry points of the fuzz targets (see `fuzz/` in the crate directory).
+//! They feed the hostile input to the code decoding the data received from the peers:
+//! the frames, the handshakes, the multiplexer and the RPC messages.
+//! Malformed input is expected to be rejected, but it should never panic,
+//! hang, or make the node allocate more memory than the size limit of the message.
+use crate::{consensus, frame, gossip, mux, preface, rpc, TimeSource};
+use rand::{
+    distributions::{Distribution, Standard},
+    Rng, SeedableRng as _,
+};
+use std::collections::HashMap;
+use zksync_concurrency::{ctx, io, scope, time};
+use zksync_consensus_roles::validator;
+use zksync_protobuf::{kB, ProtoFmt, MB};
+
+/// Size limit of the RPC messages in the `decode` target.
+/// In production it is set per RPC by the handlers and the clients.
+const MAX_RPC_MSG_SIZE: usize = 10 * MB;
+
+/// Timeout of processing a single input.
+const TIMEOUT: time::Duration = time::Duration::seconds(10);
+
+/// Corruption applied to an encoded message.
+#[derive(Debug, arbitrary::Arbitrary)]
+pub enum Mutation {
+    /// XORs the byte at `pos` (modulo the message length) with `mask`.
+    Flip {
+        /// Position of the byte.
+        pos: u16,
+        /// Bits to flip.
+        mask: u8,
+    },
+    /// Inserts `byte` at `pos` (modulo the message length + 1).
+    Insert {
+        /// Position of the byte.
+        pos: u16,
+        /// Inserted byte.
+        byte: u8,
+    },
+    /// Truncates the message to the given length.
+    Truncate(u16),
+}
+
+/// Encoded message, as sent by the peer.
+#[derive(Debug, arbitrary::Arbitrary)]
+pub enum Data {
+    /// Arbitrary bytes.
+    Raw(Vec<u8>),
+    /// Valid message generated from `seed` and then corrupted with `mutations`.
+    /// It lets the fuzzer reach the validation logic beyond the proto parser.
+    Sampled {
+        /// Seed of the message generator.
+        seed: u64,
+        /// Corruptions applied to the encoded message.
+        mutations: Vec<Mutation>,
+    },
+}
+
+impl Data {
+    /// Encoded message of type `T`.
+    fn encode<T: ProtoFmt>(&self) -> Vec<u8>
+    where
+        Standard: Distribution<T>,
+    {
+        let (seed, mutations) = match self {
+            Self::Raw(bytes) => return bytes.clone(),
+            Self::Sampled { seed, mutations } => (*seed, mutations),
+        };
+        let rng = &mut rand::rngs::StdRng::seed_from_u64(seed);
+        let mut bytes = zksync_protobuf::encode(&rng.gen::<T>());
+        for m in mutations {
+            match *m {
+                Mutation::Flip { pos, mask } => {
+                    if !bytes.is_empty() {
+                        let i = usize::from(pos) % bytes.len();
+                        bytes[i] ^= mask;
+                    }
+                }
+                Mutation::Insert { pos, byte } => {
+                    let i = usize::from(pos) % (bytes.len() + 1);
+                    bytes.insert(i, byte);
+                }
+                Mutation::Truncate(len) => bytes.truncate(len.into()),
+            }
+        }
+        bytes
+    }
+}
+
+impl Distribution<mux::Handshake> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> mux::Handshake {
+        let mut max_streams = || -> HashMap<_, _> {
+            let n = rng.gen_range(0..5);
+            (0..n).map(|_| (rng.gen_range(0..20), rng.gen())).collect()
+        };
+        mux::Handshake {
+            accept_max_streams: max_streams(),
+            connect_max_streams: max_streams(),
+            keepalive: rng.gen::<bool>().then(|| {
+                let interval = time::Duration::milliseconds(rng.gen_range(1..1000));
+                crate::KeepaliveConfig {
+                    interval,
+                    timeout: interval + time::Duration::milliseconds(rng.gen_range(1..1000)),
+                }
+            }),
+        }
+    }
+}
+
+impl Distribution<preface::Endpoint> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> preface::Endpoint {
+        match rng.gen_range(0..4) {
+            0 => preface::Endpoint::ConsensusNet,
+            1 => preface::Endpoint::GossipNet,
+            2 => preface::Endpoint::GenesisNet,
+            _ => preface::Endpoint::SentryLink,
+        }
+    }
+}
+
+/// Decodes a message of type `T`, received in a frame with the given size limit.
+async fn decode<T: ProtoFmt>(ctx: &ctx::Ctx, data: &Data, max_size: usize) -> anyhow::Result<()>
+where
+    Standard: Distribution<T>,
+{
+    let msg = data.encode::<T>();
+    let msg_size = u32::try_from(msg.len()).unwrap_or(u32::MAX);
+    let mut stream = msg_size.to_le_bytes().to_vec();
+    stream.extend(msg);
+    let _: T = frame::recv_proto(ctx, &mut stream.as_slice(), max_size).await?;
+    Ok(())
+}
+
+/// Defines `Message`, listing the types of the messages received from the peers,
+/// together with their size limits.
+macro_rules! messages {
+    ($($name:ident($ty:ty, $max_size:expr),)*) => {
+        /// Type of a message received from a peer.
+        #[derive(Debug, Clone, Copy, arbitrary::Arbitrary)]
+        pub enum Message {
+            $(#[doc = concat!("`", stringify!($ty), "`.")] $name,)*
+        }
+
+        impl Message {
+            /// Decodes `data` as a message of this type.
+            async fn decode(self, ctx: &ctx::Ctx, data: &Data) -> anyhow::Result<()> {
+                match self {
+                    $(Self::$name => decode::<$ty>(ctx, data, $max_size).await,)*
+                }
+            }
+        }
+    };
+}
+
+messages! {
+    PrefaceEndpoint(preface::Endpoint, preface::Endpoint::max_size()),
+    MuxHandshake(mux::Handshake, mux::Handshake::max_size()),
+    GossipHandshake(gossip::handshake::Handshake, gossip::handshake::Handshake::max_size()),
+    ConsensusHandshake(consensus::handshake::Handshake, consensus::handshake::Handshake::max_size()),
+    ConsensusReq(rpc::consensus::Req, MAX_RPC_MSG_SIZE),
+    ConsensusResp(rpc::consensus::Resp, MAX_RPC_MSG_SIZE),
+    RelayConsensusReq(rpc::relay_consensus::Req, MAX_RPC_MSG_SIZE),
+    SentryRelayReq(rpc::sentry_relay::Req, MAX_RPC_MSG_SIZE),
+    SentrySignReq(rpc::sentry_sign::Req, MAX_RPC_MSG_SIZE),
+    SentrySignResp(rpc::sentry_sign::Resp, MAX_RPC_MSG_SIZE),
+    PushValidatorAddrsReq(rpc::push_validator_addrs::Req, MAX_RPC_MSG_SIZE),
+    PushBlockStoreStateReq(rpc::push_block_store_state::Req, MAX_RPC_MSG_SIZE),
+    GetBlockReq(rpc::get_block::Req, MAX_RPC_MSG_SIZE),
+    GetBlockResp(rpc::get_block::Resp, MAX_RPC_MSG_SIZE),
+    GetBlockChunkReq(rpc::get_block_chunk::Req, MAX_RPC_MSG_SIZE),
+    GetBlockChunkResp(rpc::get_block_chunk::Resp, MAX_RPC_MSG_SIZE),
+    GetHeadersReq(rpc::get_headers::Req, MAX_RPC_MSG_SIZE),
+    GetHeadersResp(rpc::get_headers::Resp, MAX_RPC_MSG_SIZE),
+    SamplePayloadReq(rpc::sample_payload::Req, MAX_RPC_MSG_SIZE),
+    SamplePayloadResp(rpc::sample_payload::Resp, MAX_RPC_MSG_SIZE),
+    PexReq(rpc::pex::Req, MAX_RPC_MSG_SIZE),
+    PushHighQcReq(rpc::push_high_qc::Req, MAX_RPC_MSG_SIZE),
+    PushBatchVotesReq(rpc::push_batch_votes::Req, MAX_RPC_MSG_SIZE),
+    PushUpgradeVotesReq(rpc::push_upgrade_votes::Req, MAX_RPC_MSG_SIZE),
+    PushTopicReq(rpc::push_topic::Req, MAX_RPC_MSG_SIZE),
+    PushSessionTicketReq(rpc::push_session_ticket::Req, MAX_RPC_MSG_SIZE),
+    GetGenesisResp(rpc::get_genesis::Resp, MAX_RPC_MSG_SIZE),
+    HeartbeatReq(rpc::heartbeat::Req, MAX_RPC_MSG_SIZE),
+    PingResp(rpc::ping::Resp, MAX_RPC_MSG_SIZE),
+}
+
+/// Input of the `decode` fuzz target.
+#[derive(Debug, arbitrary::Arbitrary)]
+pub struct DecodeInput {
+    /// Expected type of the message.
+    pub msg: Message,
+    /// Received message.
+    pub data: Data,
+}
+
+/// Runs `fut` to completion on a fresh single-threaded runtime.
+fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(fut)
+}
+
+/// `decode` fuzz target: decodes a message received in a frame.
+/// Returns the decoding error, if any.
+pub fn decode_target(input: &DecodeInput) -> anyhow::Result<()> {
+    block_on(async {
+        let ctx = &ctx::root().with_timeout(TIMEOUT);
+        input.msg.decode(ctx, &input.data).await
+    })
+}
+
+/// Multiplexer frame sent by the peer.
+#[derive(Debug, arbitrary::Arbitrary)]
+pub struct MuxFrame {
+    /// Header of the frame (frame kind, stream kind and stream id).
+    pub header: u16,
+    /// Payload, sent only if the header denotes a DATA frame.
+    pub data: Vec<u8>,
+}
+
+/// Input of the `mux` fuzz target: the data sent by the peer over a connection.
+#[derive(Debug, arbitrary::Arbitrary)]
+pub struct MuxInput {
+    /// Multiplexer handshake sent by the peer. A valid handshake is generated from the seed,
+    /// so that the fuzzer reaches the frame processing.
+    pub handshake: Data,
+    /// Frames sent by the peer after the handshake.
+    pub frames: Vec<MuxFrame>,
+}
+
+impl MuxInput {
+    /// Bytes sent by the peer.
+    fn encode(&self) -> Vec<u8> {
+        let handshake = self.handshake.encode::<mux::Handshake>();
+        let handshake_size = u32::try_from(handshake.len()).unwrap_or(u32::MAX);
+        let mut bytes = handshake_size.to_le_bytes().to_vec();
+        bytes.extend(handshake);
+        for f in &self.frames {
+            bytes.extend(f.header.to_le_bytes());
+            // Only the DATA frames (kind bits `01`) have a payload.
+            if f.header >> 14 == 0b01 {
+                let len = f.data.len().min(u16::MAX.into());
+                bytes.extend((len as u16).to_le_bytes());
+                bytes.extend(&f.data[..len]);
+            }
+        }
+        bytes
+    }
+}
+
+/// `mux` fuzz target: runs an RPC service (with `ping` and `get_genesis` servers)
+/// over a connection, on which the peer sends the input.
+/// Returns the error of the service, if any.
+pub fn mux_target(input: &MuxInput) -> anyhow::Result<()> {
+    let genesis: validator::Genesis = rand::rngs::StdRng::seed_from_u64(0).gen();
+    let bytes = input.encode();
+    block_on(async {
+        let ctx = &ctx::root().with_timeout(TIMEOUT);
+        let (peer, stream) = tokio::io::duplex(64 * kB);
+        let time = TimeSource::default();
+        let service = rpc::Service::new()
+            .add_server(rpc::ping::Server(&time), rpc::ping::RATE)
+            .add_server(rpc::get_genesis::Server(&genesis), rpc::get_genesis::RATE);
+        scope::run!(ctx, |ctx, s| async {
+            s.spawn_bg(async {
+                // Sends the input, then drains the responses until the connection is closed.
+                let _ = async {
+                    let (mut read, mut write) = tokio::io::split(peer);
+                    io::write_all(ctx, &mut write, &bytes).await??;
+                    io::shutdown(ctx, &mut write).await??;
+                    let mut buf = vec![0; kB];
+                    while io::read(ctx, &mut read, &mut buf).await?? > 0 {}
+                    anyhow::Ok(())
+                }
+                .await;
+                Ok(())
+            });
+            match service.run(ctx, stream).await {
+                Ok(()) | Err(mux::RunError::Closed | mux::RunError::Canceled(_)) => Ok(()),
+                Err(err) => Err(err.into()),
+            }
+        })
+        .await
+    })
+}
@@ -0,0 +1,181 @@
+//! Address book of the nodes discovered via the peer exchange.
+use crate::frame;
+use rand::{seq::IteratorRandom as _, Rng};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+use zksync_consensus_roles::node;
+
+/// Max number of the addresses kept in the book.
+const CAPACITY: usize = 1000;
+/// Max number of the addresses in the book learned from a single peer.
+/// Signatures only prove that an address belongs to its node, so without the cap
+/// a single peer could fill the book with the addresses of the nodes it controls.
+pub(crate) const MAX_ENTRIES_PER_PEER: usize = 32;
+/// Score of a newly discovered address.
+const INITIAL_SCORE: i32 = 0;
+/// Max score of an address.
+const MAX_SCORE: i32 = 10;
+/// Addresses with a lower score are removed from the book.
+const MIN_SCORE: i32 = -3;
+
+/// Score penalty for serving invalid data (e.g. a block with a bad justification).
+pub(crate) const MISBEHAVIOR_PENALTY: i32 = 4;
+
+/// Score penalty for a failed connection, depending on the cause of the failure.
+/// Timeouts and transport failures are likely to be network issues, while malformed
+/// or oversized frames indicate a misbehaving peer, which is dropped from the book
+/// much faster.
+pub(crate) fn penalty(err: &anyhow::Error) -> i32 {
+    misbehavior_penalty(err).unwrap_or(1)
+}
+
+/// Score penalty for a connection which failed because the peer has misbehaved,
+/// `None` if the failure may be a network issue.
+/// Unlike [`penalty`], it is applicable to the inbound connections, which are
+/// closed by the peers at will.
+pub(crate) fn misbehavior_penalty(err: &anyhow::Error) -> Option<i32> {
+    use frame::ErrorClass as C;
+    let err = err.chain().find_map(|e| e.downcast_ref::<frame::Error>())?;
+    match err.class() {
+        C::Timeout | C::Io | C::RateLimited => None,
+        C::ProtocolViolation => Some(2),
+        C::Decode | C::FrameTooLarge => Some(MISBEHAVIOR_PENALTY),
+    }
+}
+
+/// Entry of the address book.
+struct Entry {
+    /// Address signed by the node.
+    addr: Arc<node::Signed<node::NodeAddr>>,
+    /// Peer from which the address has been learned.
+    source: node::PublicKey,
+    /// Incremented on every successful connection, decremented on every failed one.
+    score: i32,
+}
+
+/// Address book of the nodes discovered via the peer exchange.
+/// Each address is signed by the node it belongs to, so peers cannot
+/// advertise fake addresses of other nodes.
+#[derive(Default)]
+pub(crate) struct AddressBook(Mutex<HashMap<node::PublicKey, Entry>>);
+
+impl AddressBook {
+    /// Inserts the addresses received from the peer `source`, skipping the address of `own_key`.
+    /// Addresses older than the ones already in the book are ignored, and so are the new
+    /// addresses once `source` has contributed `MAX_ENTRIES_PER_PEER` entries.
+    /// Returns an error if any of the signatures is invalid, in which case nothing is inserted.
+    pub(crate) fn update(
+        &self,
+        own_key: &node::PublicKey,
+        source: &node::PublicKey,
+        addrs: &[Arc<node::Signed<node::NodeAddr>>],
+    ) -> anyhow::Result<()> {
+        let mut done = HashSet::new();
+        for addr in addrs {
+            anyhow::ensure!(done.insert(&addr.key), "duplicate entry");
+        }
+        node::Signed::verify_batch(addrs.iter().map(|a| &**a))?;
+        let mut book = self.0.lock().unwrap();
+        let mut from_source = book.values().filter(|e| &e.source == source).count();
+        for addr in addrs {
+            if &addr.key == own_key {
+                continue;
+            }
+            if let Some(entry) = book.get_mut(&addr.key) {
+                if addr.msg.timestamp > entry.addr.msg.timestamp {
+                    entry.addr = addr.clone();
+                }
+                continue;
+            }
+            if from_source >= MAX_ENTRIES_PER_PEER {
+                continue;
+            }
+            if book.len() >= CAPACITY {
+                // Evict the lowest scored entry, unless it scores better than a new one.
+                let (worst, score) = book
+                    .iter()
+                    .map(|(k, e)| (k.clone(), e.score))
+                    .min_by_key(|(_, score)| *score)
+                    .unwrap();
+                if score >= INITIAL_SCORE {
+                    continue;
+                }
+                book.remove(&worst);
+            }
+            book.insert(
+                addr.key.clone(),
+                Entry {
+                    addr: addr.clone(),
+                    source: source.clone(),
+                    score: INITIAL_SCORE,
+                },
+            );
+            from_source += 1;
+        }
+        Ok(())
+    }
+
+    /// Random sample of at most `n` addresses from the book.
+    pub(crate) fn sample(
+        &self,
+        rng: &mut impl Rng,
+        n: usize,
+    ) -> Vec<Arc<node::Signed<node::NodeAddr>>> {
+        let book = self.0.lock().unwrap();
+        book.values()
+            .filter(|e| e.score >= INITIAL_SCORE)
+            .map(|e| e.addr.clone())
+            .choose_multiple(rng, n)
+    }
+
+    /// Best scored address, among the nodes satisfying `filter`.
+    /// Ties are broken randomly.
+    pub(crate) fn best(
+        &self,
+        rng: &mut impl Rng,
+        filter: impl Fn(&node::PublicKey) -> bool,
+    ) -> Option<Arc<node::Signed<node::NodeAddr>>> {
+        let book = self.0.lock().unwrap();
+        let best = book
+            .values()
+            .filter(|e| filter(&e.addr.key))
+            .max_by_key(|e| e.score)?
+            .score;
+        book.values()
+            .filter(|e| e.score == best && filter(&e.addr.key))
+            .map(|e| e.addr.clone())
+            .choose(rng)
+    }
+
+    /// Records a successful connection to the node.
+    pub(crate) fn connected(&self, key: &node::PublicKey) {
+        if let Some(e) = self.0.lock().unwrap().get_mut(key) {
+            e.score = (e.score + 1).min(MAX_SCORE);
+        }
+    }
+
+    /// Records a failed connection to the node.
+    /// Nodes which keep failing are removed from the book.
+    pub(crate) fn failed(&self, key: &node::PublicKey) {
+        self.penalize(key, 1);
+    }
+
+    /// Decreases the score of the node by `penalty`.
+    /// Nodes with a score below `MIN_SCORE` are removed from the book.
+    pub(crate) fn penalize(&self, key: &node::PublicKey, penalty: i32) {
+        let mut book = self.0.lock().unwrap();
+        if let Some(e) = book.get_mut(key) {
+            e.score -= penalty;
+            if e.score < MIN_SCORE {
+                book.remove(key);
+            }
+        }
+    }
+
+    /// Number of the addresses in the book.
+    pub(crate) fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+}
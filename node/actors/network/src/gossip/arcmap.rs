@@ -1,5 +1,5 @@
 //! Multimap of pointers indexed by `node::PublicKey`.
-//! Used to maintain a collection GetBlock rpc clients.
+//! Used to maintain collections of rpc clients (GetBlockChunk, RelayConsensus).
 //! TODO(gprusak): consider upgrading PoolWatch instead.
 use std::{
     collections::HashMap,
@@ -22,6 +22,16 @@ impl<T> ArcMap<T> {
         self.0.lock().unwrap().get(key)?.first().cloned()
     }
 
+    /// Fetches any pointer for each key.
+    pub(crate) fn all(&self) -> Vec<(node::PublicKey, Arc<T>)> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(k, v)| Some((k.clone(), v.first()?.clone())))
+            .collect()
+    }
+
     /// Insert a pointer.
     pub(crate) fn insert(&self, key: node::PublicKey, p: Arc<T>) {
         self.0.lock().unwrap().entry(key).or_default().push(p);
@@ -0,0 +1,94 @@
+//! Outbound bandwidth budget for serving blocks to the peers.
+//! A node with a long history may be asked for blocks by many syncing peers at once.
+//! The budget bounds the bandwidth spent on serving them (in total and per peer),
+//! so that the node doesn't starve its own consensus traffic.
+use std::time::Duration;
+use vise::{Buckets, Counter, Histogram, Metrics, Unit};
+use zksync_concurrency::{ctx, limiter, metrics::LatencyHistogramExt as _, time};
+use zksync_protobuf::kB;
+
+/// Max time a request waits for the budget, before it is shed.
+const MAX_QUEUE_DELAY: time::Duration = time::Duration::seconds(5);
+
+/// Bandwidth budget. The underlying limiter counts in kB units.
+pub(crate) struct Budget(Option<(limiter::Limiter, usize)>);
+
+impl Budget {
+    /// Constructs a budget of `bytes_per_sec`. `None` means unlimited.
+    pub(crate) fn new(ctx: &ctx::Ctx, bytes_per_sec: Option<usize>) -> Self {
+        Self(bytes_per_sec.map(|bytes_per_sec| {
+            let burst = (bytes_per_sec / kB).max(1);
+            let rate = limiter::Rate {
+                burst,
+                refresh: time::Duration::SECOND / u32::try_from(burst).unwrap_or(u32::MAX),
+            };
+            (limiter::Limiter::new(ctx, rate), burst)
+        }))
+    }
+
+    /// Waits until the budget allows sending `bytes`, then consumes them.
+    /// Messages larger than the burst are charged over multiple refills. Only the wait
+    /// for the first refill is bounded by `max_delay`: it is the time the request spends
+    /// in the queue, while the subsequent refills just pace the sending.
+    async fn consume(
+        &self,
+        ctx: &ctx::Ctx,
+        bytes: usize,
+        max_delay: time::Duration,
+    ) -> ctx::OrCanceled<()> {
+        let Some((limiter, burst)) = &self.0 else {
+            return Ok(());
+        };
+        let mut permits = bytes.div_ceil(kB).max(1);
+        let first = permits.min(*burst);
+        limiter.acquire(&ctx.with_timeout(max_delay), first).await?;
+        permits -= first;
+        while permits > 0 {
+            let n = permits.min(*burst);
+            limiter.acquire(ctx, n).await?;
+            permits -= n;
+        }
+        Ok(())
+    }
+}
+
+/// Consumes `bytes` from both the `peer` budget and the `global` budget.
+/// The per-peer budget is consumed first, so that a single peer cannot occupy
+/// the global queue. Fails (and the request should be shed) if waiting for either budget
+/// to start refilling takes longer than `MAX_QUEUE_DELAY`.
+pub(crate) async fn consume(
+    ctx: &ctx::Ctx,
+    peer: &Budget,
+    global: &Budget,
+    bytes: usize,
+) -> anyhow::Result<()> {
+    let start = ctx.now();
+    let res = async {
+        peer.consume(ctx, bytes, MAX_QUEUE_DELAY).await?;
+        global.consume(ctx, bytes, MAX_QUEUE_DELAY).await
+    }
+    .await;
+    METRICS.queue_latency.observe_latency(ctx.now() - start);
+    if res.is_err() {
+        METRICS.shed_requests.inc();
+        anyhow::bail!("bandwidth budget exhausted");
+    }
+    METRICS.served_bytes.inc_by(bytes as u64);
+    Ok(())
+}
+
+/// Metrics of serving blocks to the peers.
+#[derive(Debug, Metrics)]
+#[metrics(prefix = "network_gossip_serve_blocks")]
+struct ServeBlocksMetrics {
+    /// Bytes of the payloads served to the peers.
+    served_bytes: Counter,
+    /// Time the requests have waited for the bandwidth budget.
+    #[metrics(unit = Unit::Seconds, buckets = Buckets::LATENCIES)]
+    queue_latency: Histogram<Duration>,
+    /// Requests rejected because the bandwidth budget was exhausted.
+    shed_requests: Counter,
+}
+
+#[vise::register]
+static METRICS: vise::Global<ServeBlocksMetrics> = vise::Global::new();
@@ -0,0 +1,200 @@
+//! Votes of the attesters for the batches of the finalized blocks.
+//! Every attester signs the batches (as their last blocks get finalized) and the votes are
+//! gossiped to all the nodes, so that any node can aggregate them into a batch certificate.
+//! The votes of each attester are kept for the `WINDOW` most recent batches it has signed,
+//! so that voting on a newer batch doesn't withdraw the vote on a batch still gathering
+//! a quorum. Votes for the certified batches (and the batches below them) are dropped.
+use crate::watch::Watch;
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Arc,
+};
+use zksync_concurrency::sync;
+use zksync_consensus_roles::{attester, validator};
+
+/// Max number of batches for which the votes of a single attester are kept.
+/// When an attester signs more batches, its votes for the lowest ones are dropped.
+pub(crate) const WINDOW: usize = 16;
+
+/// Votes of a single attester, by batch number.
+type AttesterVotes = im::OrdMap<attester::BatchNumber, Arc<attester::Signed<attester::Batch>>>;
+
+/// Votes of the attesters for the batches which are not certified yet.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub(crate) struct BatchVotes {
+    /// Votes of each attester, for at most `WINDOW` batches.
+    pub(super) votes: im::HashMap<attester::PublicKey, AttesterVotes>,
+    /// Highest certified batch. Votes for it and for the lower batches are not kept.
+    pub(super) certified: Option<attester::BatchNumber>,
+}
+
+impl BatchVotes {
+    /// Returns the votes of `self` which are not in `b`.
+    pub(super) fn get_newer(&self, b: &Self) -> Vec<Arc<attester::Signed<attester::Batch>>> {
+        let mut newer = vec![];
+        for (k, votes) in &self.votes {
+            let old = b.votes.get(k);
+            for (n, v) in votes {
+                if old.is_some_and(|old| old.contains_key(n)) {
+                    continue;
+                }
+                newer.push(v.clone());
+            }
+        }
+        newer
+    }
+
+    /// Updates the votes with entries from `data`.
+    /// It exits as soon as an invalid entry is found.
+    /// `self` might get modified even if an error is returned
+    /// (all entries verified so far are added).
+    /// Returns true iff some new entry was added.
+    pub(super) fn update(
+        &mut self,
+        genesis: &validator::Genesis,
+        data: &[Arc<attester::Signed<attester::Batch>>],
+    ) -> anyhow::Result<bool> {
+        let Some(attesters) = &genesis.attesters else {
+            return Ok(false);
+        };
+        let genesis_hash = genesis.hash();
+        let mut changed = false;
+        let mut done = HashSet::new();
+        for d in data {
+            // Disallow multiple entries for the same key and batch:
+            // verifying signatures is expensive.
+            anyhow::ensure!(
+                done.insert((&d.key, d.msg.number)),
+                "duplicate entry for {:?}",
+                d.key
+            );
+            if !attesters.contains(&d.key) {
+                continue;
+            }
+            anyhow::ensure!(d.msg.genesis == genesis_hash, "genesis mismatch");
+            if self.certified.is_some_and(|c| d.msg.number <= c) {
+                continue;
+            }
+            let mut votes = self.votes.get(&d.key).cloned().unwrap_or_default();
+            if votes.contains_key(&d.msg.number) {
+                continue;
+            }
+            // The window of the attester is full and the vote is below all of it.
+            if votes.len() >= WINDOW && votes.keys().next().is_some_and(|n| d.msg.number < *n) {
+                continue;
+            }
+            d.verify()?;
+            votes.insert(d.msg.number, d.clone());
+            if votes.len() > WINDOW {
+                let lowest = *votes.keys().next().unwrap();
+                votes.remove(&lowest);
+            }
+            self.votes.insert(d.key.clone(), votes);
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    /// Marks the batch `certified` as certified,
+    /// dropping the votes for it and for the lower batches.
+    pub(super) fn prune(&mut self, certified: attester::BatchNumber) {
+        self.certified = Some(certified);
+        self.votes = self
+            .votes
+            .iter()
+            .filter_map(|(k, votes)| {
+                let (_, above) = votes.split(&certified);
+                (!above.is_empty()).then(|| (k.clone(), above))
+            })
+            .collect();
+    }
+
+    /// Aggregates the votes into a certificate of the highest batch
+    /// which has been signed by enough attesters.
+    pub(super) fn certificate(&self, committee: &attester::Committee) -> Option<attester::BatchQC> {
+        let mut by_batch: BTreeMap<attester::BatchNumber, Vec<&attester::Signed<attester::Batch>>> =
+            BTreeMap::new();
+        for v in self.votes.values().flat_map(|votes| votes.values()) {
+            by_batch.entry(v.msg.number).or_default().push(v);
+        }
+        for votes in by_batch.values().rev() {
+            // Honest attesters sign the same batch, but the votes are grouped
+            // by the whole message, in case some attester signed a different one.
+            for v in votes {
+                let mut qc = attester::BatchQC::new(v.msg.clone(), committee);
+                for v in votes {
+                    qc.add(v, committee);
+                }
+                if qc.signers.count() >= committee.threshold() {
+                    return Some(qc);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Watch wrapper of BatchVotes, which supports subscribing to the votes and
+/// to the certificates aggregated from them.
+pub(crate) struct BatchVotesWatch {
+    /// Votes of the attesters for the batches which are not certified yet.
+    votes: Watch<BatchVotes>,
+    /// Certificate of the highest batch signed by enough attesters.
+    qc: sync::watch::Sender<Option<Arc<attester::BatchQC>>>,
+}
+
+impl Default for BatchVotesWatch {
+    fn default() -> Self {
+        Self {
+            votes: Watch::new(BatchVotes::default()),
+            qc: sync::watch::channel(None).0,
+        }
+    }
+}
+
+impl BatchVotesWatch {
+    /// Subscribes to BatchVotes updates.
+    pub(crate) fn subscribe(&self) -> sync::watch::Receiver<BatchVotes> {
+        self.votes.subscribe()
+    }
+
+    /// Subscribes to the certificate of the highest batch.
+    pub(crate) fn subscribe_qc(&self) -> sync::watch::Receiver<Option<Arc<attester::BatchQC>>> {
+        self.qc.subscribe()
+    }
+
+    /// Inserts data to BatchVotes.
+    /// Subscribers are notified iff at least 1 new entry has
+    /// been inserted. Returns an error iff an invalid
+    /// entry in `data` has been found. The provider of the
+    /// invalid entry should be banned.
+    pub(crate) async fn update(
+        &self,
+        genesis: &validator::Genesis,
+        data: &[Arc<attester::Signed<attester::Batch>>],
+    ) -> anyhow::Result<()> {
+        let this = self.votes.lock().await;
+        let mut votes = this.borrow().clone();
+        if votes.update(genesis, data)? {
+            if let Some(qc) = genesis
+                .attesters
+                .as_ref()
+                .and_then(|c| votes.certificate(c))
+            {
+                votes.prune(qc.message.number);
+                self.qc.send_if_modified(|old| {
+                    if old
+                        .as_ref()
+                        .is_some_and(|old| old.message.number >= qc.message.number)
+                    {
+                        return false;
+                    }
+                    *old = Some(Arc::new(qc));
+                    true
+                });
+            }
+            this.send(votes).ok().unwrap();
+        }
+        Ok(())
+    }
+}
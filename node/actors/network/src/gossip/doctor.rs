@@ -0,0 +1,117 @@
+//! Connectivity diagnostics of the gossip network peers.
+//! Allows operators to debug connectivity without digging through the node logs.
+use super::handshake;
+use crate::{preface, rpc, tickets::Tickets, GossipConfig, TimeSource, Transport};
+use zksync_concurrency::{ctx, scope, time};
+use zksync_consensus_roles::{node, validator};
+
+/// Number of pings sent to a peer to measure the round trip time.
+const PINGS: usize = 3;
+/// Timeout of a single ping.
+const PING_TIMEOUT: time::Duration = time::Duration::seconds(5);
+
+/// Reason why a peer is not reachable.
+#[derive(Debug, thiserror::Error)]
+pub enum ProbeError {
+    /// Failed to establish an encrypted connection.
+    #[error("connect: {0:#}")]
+    Connect(#[source] anyhow::Error),
+    /// Peer belongs to a chain with a different genesis.
+    #[error("genesis mismatch: peer has {0:?}")]
+    GenesisMismatch(validator::GenesisHash),
+    /// Peer has a different node key than expected.
+    #[error("unexpected peer key")]
+    PeerMismatch,
+    /// Handshake failed. Note that the peer closes the connection
+    /// during the handshake if it has a different genesis.
+    #[error("handshake: {0:#}")]
+    Handshake(#[source] anyhow::Error),
+    /// Peer didn't respond to pings.
+    #[error("ping: {0:#}")]
+    Ping(#[source] anyhow::Error),
+}
+
+/// Diagnostic report of a gossip network peer.
+#[derive(Debug, Default)]
+pub struct PeerReport {
+    /// Time to establish an encrypted connection.
+    pub connect_latency: Option<time::Duration>,
+    /// Time to perform the gossip handshake.
+    pub handshake_latency: Option<time::Duration>,
+    /// Round trip times of the pings.
+    pub ping_rtts: Vec<time::Duration>,
+    /// Estimated offset of the peer's UTC clock relative to ours,
+    /// if the peer reports its time.
+    pub clock_skew: Option<time::Duration>,
+    /// The first error encountered, if any.
+    pub error: Option<ProbeError>,
+}
+
+/// Dials the peer, performs the gossip handshake (which verifies the genesis compatibility)
+/// and measures the latency with a few pings.
+pub async fn probe_peer(
+    ctx: &ctx::Ctx,
+    transport: &Transport,
+    cfg: &GossipConfig,
+    genesis: validator::GenesisHash,
+    peer: &node::PublicKey,
+    addr: std::net::SocketAddr,
+) -> PeerReport {
+    let mut report = PeerReport::default();
+    if let Err(err) = probe(ctx, transport, cfg, genesis, peer, addr, &mut report).await {
+        report.error = Some(err);
+    }
+    report
+}
+
+async fn probe(
+    ctx: &ctx::Ctx,
+    transport: &Transport,
+    cfg: &GossipConfig,
+    genesis: validator::GenesisHash,
+    peer: &node::PublicKey,
+    addr: std::net::SocketAddr,
+    report: &mut PeerReport,
+) -> Result<(), ProbeError> {
+    let start = ctx.now();
+    let mut stream = preface::connect(ctx, transport, addr, preface::Endpoint::GossipNet)
+        .await
+        .map_err(ProbeError::Connect)?;
+    report.connect_latency = Some(ctx.now() - start);
+
+    let start = ctx.now();
+    // Tickets are not used, so that the probe measures the full handshake.
+    let tickets = Tickets::new("gossip", &cfg.key, None);
+    handshake::outbound(ctx, cfg, genesis, &tickets, &mut stream, peer)
+        .await
+        .map_err(|err| match err {
+            handshake::Error::GenesisMismatch { genesis, .. } => {
+                ProbeError::GenesisMismatch(genesis)
+            }
+            handshake::Error::PeerMismatch => ProbeError::PeerMismatch,
+            err => ProbeError::Handshake(err.into()),
+        })?;
+    report.handshake_latency = Some(ctx.now() - start);
+
+    // The skew is measured against the local clock.
+    let time = &TimeSource::default();
+    let ping_client = rpc::Client::<rpc::ping::Rpc>::new(ctx, rpc::ping::RATE);
+    let service = rpc::Service::new()
+        .add_client(&ping_client)
+        .add_server(rpc::ping::Server(time), rpc::ping::RATE);
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(async {
+            // The connection is dropped once the pings are done.
+            let _ = service.run(ctx, stream).await;
+            Ok(())
+        });
+        for _ in 0..PINGS {
+            let sample = ping_client.ping(ctx, time, PING_TIMEOUT).await?;
+            report.ping_rtts.push(sample.rtt);
+            report.clock_skew = sample.clock_skew;
+        }
+        Ok(())
+    })
+    .await
+    .map_err(ProbeError::Ping)
+}
@@ -0,0 +1,136 @@
+//! Fetching the genesis of the peers.
+//! Every node serves its genesis on a dedicated endpoint, without a handshake, so that:
+//! * a node which failed a handshake with a genesis mismatch can log the peer's genesis,
+//! * a fresh node configured with only the peers can bootstrap its genesis,
+//! * a node can confirm its genesis with the peers before syncing blocks from them.
+//! The peers authenticate with their node keys, so that a single node can't pose as many.
+use super::Network;
+use crate::{mux, noise, preface, rpc, Transport};
+use std::collections::HashMap;
+use zksync_concurrency::{ctx, scope, time};
+use zksync_consensus_crypto::ByteFmt as _;
+use zksync_consensus_roles::{node, validator};
+
+/// Timeout of fetching the genesis from a single peer.
+const TIMEOUT: time::Duration = time::Duration::seconds(10);
+
+/// Fetches the genesis of the peer `peer` at `addr`.
+/// Fails if the peer doesn't authenticate with its node key.
+pub async fn fetch_genesis(
+    ctx: &ctx::Ctx,
+    transport: &Transport,
+    peer: &node::PublicKey,
+    addr: std::net::SocketAddr,
+) -> anyhow::Result<validator::Genesis> {
+    let ctx = &ctx.with_timeout(TIMEOUT);
+    let stream = preface::connect(ctx, transport, addr, preface::Endpoint::GenesisNet).await?;
+    let session_id = node::SessionId(stream.id().encode());
+    let client = rpc::Client::<rpc::get_genesis::Rpc>::new(ctx, rpc::get_genesis::RATE);
+    let service = rpc::Service::new().add_client(&client);
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(async {
+            // The connection is dropped once the genesis is fetched.
+            let _ = service.run(ctx, stream).await;
+            Ok(())
+        });
+        client.get_genesis(ctx, &session_id, peer).await
+    })
+    .await
+}
+
+/// Bootstraps the genesis from the `peers` (node keys and their addresses).
+/// The genesis is accepted only if at least `quorum` of the peers have reported
+/// the same genesis hash and no other genesis hash has been reported by that many peers.
+/// Every peer has to authenticate with its node key, so it is counted once,
+/// no matter how many addresses it is reachable at. Still, the `peers` should be
+/// diverse enough that `quorum` of them can't be controlled by a single party.
+pub async fn bootstrap_genesis(
+    ctx: &ctx::Ctx,
+    transport: &Transport,
+    peers: &HashMap<node::PublicKey, std::net::SocketAddr>,
+    quorum: usize,
+) -> anyhow::Result<validator::Genesis> {
+    anyhow::ensure!(quorum > 0, "quorum has to be positive");
+    anyhow::ensure!(
+        peers.len() >= quorum,
+        "{} peers are not enough for quorum {quorum}",
+        peers.len()
+    );
+    let peers: Vec<_> = peers.iter().collect();
+    let results = scope::run!(ctx, |ctx, s| async {
+        let tasks: Vec<_> = peers
+            .iter()
+            .map(|(peer, addr)| {
+                s.spawn(async move { Ok(fetch_genesis(ctx, transport, peer, **addr).await) })
+            })
+            .collect();
+        let mut results = vec![];
+        for t in tasks {
+            results.push(t.join(ctx).await?);
+        }
+        ctx::OrCanceled::Ok(results)
+    })
+    .await?;
+    let mut votes: HashMap<validator::GenesisHash, (usize, validator::Genesis)> = HashMap::new();
+    for ((peer, addr), res) in peers.iter().zip(results) {
+        match res {
+            Ok(genesis) => {
+                tracing::info!("peer {peer:?} at {addr} has genesis {:?}", genesis.hash());
+                votes.entry(genesis.hash()).or_insert((0, genesis)).0 += 1;
+            }
+            Err(err) => tracing::info!("fetch_genesis({peer:?}, {addr}): {err:#}"),
+        }
+    }
+    let mut agreed: Vec<_> = votes.into_values().filter(|(n, _)| *n >= quorum).collect();
+    match agreed.len() {
+        1 => Ok(agreed.pop().unwrap().1),
+        0 => anyhow::bail!("no genesis has been reported by {quorum} peers"),
+        _ => anyhow::bail!("conflicting genesis reported by {quorum} peers"),
+    }
+}
+
+impl Network {
+    /// Serves the genesis of this node on an inbound `GenesisNet` stream.
+    pub(crate) async fn serve_genesis(
+        &self,
+        ctx: &ctx::Ctx,
+        stream: noise::Stream,
+    ) -> anyhow::Result<()> {
+        let ctx = &ctx.with_timeout(TIMEOUT);
+        let genesis = self.genesis();
+        let session_id = node::SessionId(stream.id().encode());
+        let server = rpc::get_genesis::Server {
+            genesis: &genesis,
+            session_id: self.cfg.gossip.key.sign_msg(session_id),
+        };
+        let service = rpc::Service::new().add_server(server, rpc::get_genesis::RATE);
+        match service.run(ctx, stream).await {
+            Ok(()) | Err(mux::RunError::Closed | mux::RunError::Canceled(_)) => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Fetches and logs the genesis of a peer, which turned out to have a different genesis.
+    /// It is a diagnostic only: failures are ignored.
+    pub(crate) async fn log_peer_genesis(
+        &self,
+        ctx: &ctx::Ctx,
+        peer: &node::PublicKey,
+        genesis_hash: &validator::GenesisHash,
+        addr: std::net::SocketAddr,
+    ) {
+        match fetch_genesis(ctx, &self.cfg.transport, peer, addr).await {
+            Ok(genesis) => tracing::warn!(
+                "peer at {addr} has a different genesis {genesis_hash:?}: fork {:?}, {} validators, \
+                 attesters: {:?}; our genesis {:?}: fork {:?}, {} validators",
+                genesis.fork,
+                genesis.validators.len(),
+                genesis.attesters.as_ref().map(|a| a.len()),
+                self.genesis().hash(),
+                self.genesis().fork,
+                self.genesis().validators.len(),
+            ),
+            Err(err) => tracing::info!("fetch_genesis({addr}): {err:#}"),
+        }
+    }
+}
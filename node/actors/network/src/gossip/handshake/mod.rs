@@ -1,11 +1,16 @@
-use crate::{frame, noise, proto::gossip as proto, GossipConfig};
+use crate::{
+    frame, metrics, noise,
+    proto::gossip as proto,
+    tickets::{Proof, Tickets},
+    GossipConfig,
+};
 use anyhow::Context as _;
 use zksync_concurrency::{ctx, time};
 use zksync_consensus_crypto::ByteFmt;
 use zksync_consensus_roles::{node, validator};
-use zksync_protobuf::{read_required, required, ProtoFmt};
+use zksync_protobuf::{kB, read_optional, read_required, required, ProtoFmt};
 
-#[cfg(test)]
+#[cfg(any(test, feature = "fuzzing"))]
 mod testonly;
 #[cfg(test)]
 mod tests;
@@ -25,15 +30,30 @@ pub(crate) struct Handshake {
     /// Information whether the peer treats this connection as static.
     /// It is informational only, it doesn't affect the logic of the node.
     pub(crate) is_static: bool,
+    /// Address of the peer, as observed by the node accepting the connection.
+    /// Lets the nodes behind NAT detect their public address.
+    pub(crate) observed_addr: Option<std::net::SocketAddr>,
+    /// Proof of the possession of a session ticket issued by the receiver.
+    /// If valid, the receiver doesn't verify the signature of `session_id`.
+    pub(crate) resume: Option<Proof>,
+    /// Latest protocol version supported by the peer.
+    /// Blocks of later versions are not exchanged with it.
+    pub(crate) protocol_version: validator::ProtocolVersion,
 }
 
 impl ProtoFmt for Handshake {
     type Proto = proto::Handshake;
+    fn max_size() -> usize {
+        10 * kB
+    }
     fn read(r: &Self::Proto) -> anyhow::Result<Self> {
         Ok(Self {
             session_id: read_required(&r.session_id).context("session_id")?,
             genesis: read_required(&r.genesis).context("genesis")?,
             is_static: *required(&r.is_static).context("is_static")?,
+            observed_addr: read_optional(&r.observed_addr).context("observed_addr")?,
+            resume: read_optional(&r.resume).context("resume")?,
+            protocol_version: validator::ProtocolVersion(r.protocol_version.unwrap_or(0)),
         })
     }
     fn build(&self) -> Self::Proto {
@@ -41,6 +61,10 @@ impl ProtoFmt for Handshake {
             session_id: Some(self.session_id.build()),
             genesis: Some(self.genesis.build()),
             is_static: Some(self.is_static),
+            observed_addr: self.observed_addr.as_ref().map(ProtoFmt::build),
+            resume: self.resume.as_ref().map(ProtoFmt::build),
+            protocol_version: (self.protocol_version != validator::ProtocolVersion::EARLIEST)
+                .then_some(self.protocol_version.0),
         }
     }
 }
@@ -48,25 +72,48 @@ impl ProtoFmt for Handshake {
 /// Error returned by gossip handshake logic.
 #[derive(Debug, thiserror::Error)]
 pub(super) enum Error {
-    #[error("genesis mismatch")]
-    GenesisMismatch,
+    #[error("genesis mismatch: peer {peer:?} has {genesis:?}")]
+    GenesisMismatch {
+        peer: node::PublicKey,
+        genesis: validator::GenesisHash,
+    },
     #[error("session id mismatch")]
     SessionIdMismatch,
     #[error("unexpected peer")]
     PeerMismatch,
     #[error(transparent)]
     Signature(#[from] node::InvalidSignatureError),
-    #[error(transparent)]
-    Stream(anyhow::Error),
+    #[error("stream: {0}")]
+    Stream(#[source] frame::Error),
+}
+
+impl Error {
+    /// Wraps a frame error, counting it in the metrics.
+    fn stream(err: frame::Error) -> Self {
+        Self::Stream(metrics::FrameErrorLayer::Handshake.observe(err))
+    }
 }
 
+/// Outcome of a successful handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Accepted {
+    /// Key of the peer.
+    pub(super) peer: node::PublicKey,
+    /// Address of this node, as observed by the peer (if reported).
+    pub(super) observed_addr: Option<std::net::SocketAddr>,
+    /// Latest protocol version supported by the peer.
+    pub(super) protocol_version: validator::ProtocolVersion,
+}
+
+/// Performs the handshake on an outbound connection.
 pub(super) async fn outbound(
     ctx: &ctx::Ctx,
     cfg: &GossipConfig,
     genesis: validator::GenesisHash,
+    tickets: &Tickets<node::PublicKey>,
     stream: &mut noise::Stream,
     peer: &node::PublicKey,
-) -> Result<(), Error> {
+) -> Result<Accepted, Error> {
     let ctx = &ctx.with_timeout(TIMEOUT);
     let session_id = node::SessionId(stream.id().encode());
     frame::send_proto(
@@ -76,15 +123,21 @@ pub(super) async fn outbound(
             session_id: cfg.key.sign_msg(session_id.clone()),
             genesis,
             is_static: cfg.static_outbound.contains_key(peer),
+            observed_addr: None,
+            resume: tickets.held(ctx, peer).map(|t| t.prove(&session_id)),
+            protocol_version: cfg.protocol_version,
         },
     )
     .await
-    .map_err(Error::Stream)?;
+    .map_err(Error::stream)?;
     let h: Handshake = frame::recv_proto(ctx, stream, Handshake::max_size())
         .await
-        .map_err(Error::Stream)?;
+        .map_err(Error::stream)?;
     if h.genesis != genesis {
-        return Err(Error::GenesisMismatch);
+        return Err(Error::GenesisMismatch {
+            peer: peer.clone(),
+            genesis: h.genesis,
+        });
     }
     if h.session_id.msg != session_id {
         return Err(Error::SessionIdMismatch);
@@ -92,38 +145,61 @@ pub(super) async fn outbound(
     if &h.session_id.key != peer {
         return Err(Error::PeerMismatch);
     }
-    h.session_id.verify()?;
-    Ok(())
+    if !tickets.verify(ctx, peer, &session_id, h.resume.as_ref()) {
+        h.session_id.verify()?;
+    }
+    Ok(Accepted {
+        peer: peer.clone(),
+        observed_addr: h.observed_addr,
+        protocol_version: h.protocol_version,
+    })
 }
 
+/// Performs the handshake on an inbound connection.
 pub(super) async fn inbound(
     ctx: &ctx::Ctx,
     cfg: &GossipConfig,
     genesis: validator::GenesisHash,
+    tickets: &Tickets<node::PublicKey>,
     stream: &mut noise::Stream,
-) -> Result<node::PublicKey, Error> {
+) -> Result<Accepted, Error> {
     let ctx = &ctx.with_timeout(TIMEOUT);
     let session_id = node::SessionId(stream.id().encode());
     let h: Handshake = frame::recv_proto(ctx, stream, Handshake::max_size())
         .await
-        .map_err(Error::Stream)?;
+        .map_err(Error::stream)?;
     if h.session_id.msg != session_id {
         return Err(Error::SessionIdMismatch);
     }
+    let peer = h.session_id.key.clone();
+    if !tickets.verify(ctx, &peer, &session_id, h.resume.as_ref()) {
+        h.session_id.verify()?;
+    }
+    // The genesis is checked only once the peer is authenticated,
+    // so that the peer can be quarantined.
     if h.genesis != genesis {
-        return Err(Error::GenesisMismatch);
+        return Err(Error::GenesisMismatch {
+            peer,
+            genesis: h.genesis,
+        });
     }
-    h.session_id.verify()?;
     frame::send_proto(
         ctx,
         stream,
         &Handshake {
             session_id: cfg.key.sign_msg(session_id.clone()),
             genesis,
-            is_static: cfg.static_inbound.contains(&h.session_id.key),
+            is_static: cfg.static_inbound.contains(&peer),
+            observed_addr: stream.peer_addr(),
+            resume: tickets.held(ctx, &peer).map(|t| t.prove(&session_id)),
+            protocol_version: cfg.protocol_version,
         },
     )
     .await
-    .map_err(Error::Stream)?;
-    Ok(h.session_id.key)
+    .map_err(Error::stream)?;
+    Ok(Accepted {
+        peer,
+        observed_addr: None,
+        protocol_version: h.protocol_version,
+    })
 }
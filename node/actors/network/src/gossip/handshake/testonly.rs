@@ -7,7 +7,7 @@ use rand::{
     distributions::{Distribution, Standard},
     Rng,
 };
-use zksync_consensus_roles::node;
+use zksync_consensus_roles::{node, validator};
 
 impl Distribution<Handshake> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Handshake {
@@ -17,6 +17,12 @@ impl Distribution<Handshake> for Standard {
             session_id: key.sign_msg(session_id),
             genesis: rng.gen(),
             is_static: rng.gen(),
+            observed_addr: Some(std::net::SocketAddr::new(
+                std::net::Ipv4Addr::from(rng.gen::<[u8; 4]>()).into(),
+                rng.gen(),
+            )),
+            resume: rng.gen(),
+            protocol_version: validator::ProtocolVersion(rng.gen()),
         }
     }
 }
@@ -1,5 +1,5 @@
 use super::*;
-use crate::{frame, noise, testonly, GossipConfig};
+use crate::{frame, noise, testonly, GossipConfig, RelayAuth};
 use assert_matches::assert_matches;
 use rand::Rng;
 use std::collections::{HashMap, HashSet};
@@ -19,14 +19,24 @@ fn make_cfg<R: Rng>(rng: &mut R) -> GossipConfig {
         dynamic_inbound_limit: 0,
         static_inbound: HashSet::default(),
         static_outbound: HashMap::default(),
+        dynamic_outbound_limit: 0,
+        relay_auth: RelayAuth::default(),
+        peer_bandwidth_cap: None,
+        protocol_version: validator::ProtocolVersion::EARLIEST,
     }
 }
 
+/// Tickets of a node which neither issues nor accepts them.
+fn no_tickets(rng: &mut impl Rng) -> Tickets<node::PublicKey> {
+    Tickets::new("gossip", &rng.gen(), None)
+}
+
 #[tokio::test]
 async fn test_session_id_mismatch() {
     abort_on_panic();
     let ctx = &ctx::test_root(&ctx::RealClock);
     let rng = &mut ctx.rng();
+    let tickets = no_tickets(rng);
 
     let cfg0 = make_cfg(rng);
     let cfg1 = make_cfg(rng);
@@ -48,14 +58,14 @@ async fn test_session_id_mismatch() {
         });
         s.spawn(async {
             let mut s4 = s4;
-            match inbound(ctx, &cfg0, genesis, &mut s4).await {
+            match inbound(ctx, &cfg0, genesis, &tickets, &mut s4).await {
                 Err(Error::SessionIdMismatch) => Ok(()),
                 res => panic!("unexpected res: {res:?}"),
             }
         });
         s.spawn(async {
             let mut s1 = s1;
-            match outbound(ctx, &cfg1, genesis, &mut s1, &cfg0.key.public()).await {
+            match outbound(ctx, &cfg1, genesis, &tickets, &mut s1, &cfg0.key.public()).await {
                 Err(Error::Stream(..)) => Ok(()),
                 res => panic!("unexpected res: {res:?}"),
             }
@@ -78,12 +88,15 @@ async fn test_session_id_mismatch() {
                     session_id: cfg1.key.sign_msg(rng.gen::<node::SessionId>()),
                     genesis,
                     is_static: false,
+                    observed_addr: None,
+                    resume: None,
+                    protocol_version: validator::ProtocolVersion::EARLIEST,
                 },
             )
             .await?;
             Ok(())
         });
-        match outbound(ctx, &cfg0, genesis, &mut s1, &cfg1.key.public()).await {
+        match outbound(ctx, &cfg0, genesis, &tickets, &mut s1, &cfg1.key.public()).await {
             Err(Error::SessionIdMismatch) => anyhow::Ok(()),
             res => panic!("unexpected res: {res:?}"),
         }
@@ -97,6 +110,7 @@ async fn test_peer_mismatch() {
     abort_on_panic();
     let ctx = &ctx::test_root(&ctx::RealClock);
     let rng = &mut ctx.rng();
+    let tickets = no_tickets(rng);
 
     let cfg0 = make_cfg(rng);
     let cfg1 = make_cfg(rng);
@@ -110,13 +124,13 @@ async fn test_peer_mismatch() {
             let mut s0 = s0;
             assert_eq!(
                 cfg1.key.public(),
-                inbound(ctx, &cfg0, genesis, &mut s0).await?
+                inbound(ctx, &cfg0, genesis, &tickets, &mut s0).await?.peer
             );
             Ok(())
         });
         s.spawn(async {
             let mut s1 = s1;
-            match outbound(ctx, &cfg1, genesis, &mut s1, &cfg2.key.public()).await {
+            match outbound(ctx, &cfg1, genesis, &tickets, &mut s1, &cfg2.key.public()).await {
                 Err(Error::PeerMismatch) => Ok(()),
                 res => panic!("unexpected res: {res:?}"),
             }
@@ -132,6 +146,7 @@ async fn test_genesis_mismatch() {
     abort_on_panic();
     let ctx = &ctx::test_root(&ctx::RealClock);
     let rng = &mut ctx.rng();
+    let tickets = no_tickets(rng);
 
     let cfg0 = make_cfg(rng);
     let cfg1 = make_cfg(rng);
@@ -141,12 +156,20 @@ async fn test_genesis_mismatch() {
         let (s0, mut s1) = noise::testonly::pipe(ctx).await;
         s.spawn(async {
             let mut s0 = s0;
-            let res = outbound(ctx, &cfg0, ctx.rng().gen(), &mut s0, &cfg1.key.public()).await;
+            let res = outbound(
+                ctx,
+                &cfg0,
+                ctx.rng().gen(),
+                &tickets,
+                &mut s0,
+                &cfg1.key.public(),
+            )
+            .await;
             assert_matches!(res, Err(Error::Stream(_)));
             Ok(())
         });
-        let res = inbound(ctx, &cfg1, rng.gen(), &mut s1).await;
-        assert_matches!(res, Err(Error::GenesisMismatch));
+        let res = inbound(ctx, &cfg1, rng.gen(), &tickets, &mut s1).await;
+        assert_matches!(res, Err(Error::GenesisMismatch { peer, .. }) if peer == cfg0.key.public());
         anyhow::Ok(())
     })
     .await
@@ -157,8 +180,16 @@ async fn test_genesis_mismatch() {
         let (s0, mut s1) = noise::testonly::pipe(ctx).await;
         s.spawn(async {
             let mut s0 = s0;
-            let res = outbound(ctx, &cfg0, ctx.rng().gen(), &mut s0, &cfg1.key.public()).await;
-            assert_matches!(res, Err(Error::GenesisMismatch));
+            let res = outbound(
+                ctx,
+                &cfg0,
+                ctx.rng().gen(),
+                &tickets,
+                &mut s0,
+                &cfg1.key.public(),
+            )
+            .await;
+            assert_matches!(res, Err(Error::GenesisMismatch { .. }));
             Ok(())
         });
         let session_id = node::SessionId(s1.id().encode());
@@ -172,6 +203,9 @@ async fn test_genesis_mismatch() {
                 session_id: cfg1.key.sign_msg(session_id),
                 genesis: rng.gen(),
                 is_static: false,
+                observed_addr: None,
+                resume: None,
+                protocol_version: validator::ProtocolVersion::EARLIEST,
             },
         )
         .await
@@ -187,6 +221,7 @@ async fn test_invalid_signature() {
     abort_on_panic();
     let ctx = &ctx::test_root(&ctx::RealClock);
     let rng = &mut ctx.rng();
+    let tickets = no_tickets(rng);
 
     let cfg0 = make_cfg(rng);
     let cfg1 = make_cfg(rng);
@@ -203,7 +238,7 @@ async fn test_invalid_signature() {
             frame::send_proto(ctx, &mut s1, &h).await?;
             Ok(())
         });
-        match outbound(ctx, &cfg0, genesis, &mut s0, &cfg1.key.public()).await {
+        match outbound(ctx, &cfg0, genesis, &tickets, &mut s0, &cfg1.key.public()).await {
             Err(Error::Signature(..)) => anyhow::Ok(()),
             res => panic!("unexpected res: {res:?}"),
         }
@@ -220,11 +255,14 @@ async fn test_invalid_signature() {
                 session_id: cfg0.key.sign_msg(node::SessionId(s1.id().encode())),
                 genesis,
                 is_static: true,
+                observed_addr: None,
+                resume: None,
+                protocol_version: validator::ProtocolVersion::EARLIEST,
             };
             h.session_id.key = cfg1.key.public();
-            frame::send_proto(ctx, &mut s1, &h).await
+            Ok(frame::send_proto(ctx, &mut s1, &h).await?)
         });
-        match inbound(ctx, &cfg0, genesis, &mut s0).await {
+        match inbound(ctx, &cfg0, genesis, &tickets, &mut s0).await {
             Err(Error::Signature(..)) => anyhow::Ok(()),
             res => panic!("unexpected res: {res:?}"),
         }
@@ -232,3 +270,127 @@ async fn test_invalid_signature() {
     .await
     .unwrap();
 }
+
+#[tokio::test]
+async fn test_protocol_version() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let tickets = no_tickets(rng);
+
+    let mut cfg0 = make_cfg(rng);
+    cfg0.protocol_version = validator::ProtocolVersion::PAYLOAD_ROOT;
+    let cfg1 = make_cfg(rng);
+    let genesis: validator::GenesisHash = rng.gen();
+
+    // Both ends learn the version supported by the other one.
+    scope::run!(ctx, |ctx, s| async {
+        let (s0, mut s1) = noise::testonly::pipe(ctx).await;
+        s.spawn(async {
+            let mut s0 = s0;
+            let got = inbound(ctx, &cfg0, genesis, &tickets, &mut s0).await?;
+            assert_eq!(cfg1.protocol_version, got.protocol_version);
+            Ok(())
+        });
+        let got = outbound(ctx, &cfg1, genesis, &tickets, &mut s1, &cfg0.key.public()).await?;
+        assert_eq!(cfg0.protocol_version, got.protocol_version);
+        anyhow::Ok(())
+    })
+    .await
+    .unwrap();
+
+    // The peers which don't announce a version support only the earliest one.
+    let mut h: Handshake = rng.gen();
+    h.protocol_version = cfg0.protocol_version;
+    let mut proto = h.build();
+    proto.protocol_version = None;
+    assert_eq!(
+        validator::ProtocolVersion::EARLIEST,
+        Handshake::read(&proto).unwrap().protocol_version
+    );
+}
+
+#[tokio::test]
+async fn test_session_resumption() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+
+    let cfg0 = make_cfg(rng);
+    let cfg1 = make_cfg(rng);
+    let genesis: validator::GenesisHash = rng.gen();
+    let ttl = Some(time::Duration::hours(1));
+
+    tracing::info!("full handshake, neither node holds a ticket yet");
+    let tickets0 = Tickets::new("gossip", &cfg0.key, ttl);
+    let tickets1 = Tickets::new("gossip", &cfg1.key, ttl);
+    scope::run!(ctx, |ctx, s| async {
+        let (s0, mut s1) = noise::testonly::pipe(ctx).await;
+        s.spawn(async {
+            let mut s0 = s0;
+            inbound(ctx, &cfg0, genesis, &tickets0, &mut s0).await?;
+            Ok(())
+        });
+        outbound(ctx, &cfg1, genesis, &tickets1, &mut s1, &cfg0.key.public()).await?;
+        anyhow::Ok(())
+    })
+    .await
+    .unwrap();
+    // Normally the ticket is pushed over the established connection.
+    let ticket = tickets0.issue(ctx, &cfg1.key.public()).unwrap();
+    tickets1.insert(&cfg0.key.public(), ticket);
+
+    tracing::info!("node 0 restarts and still accepts the ticket instead of the signature");
+    let tickets0 = Tickets::new("gossip", &cfg0.key, ttl);
+    for valid in [true, false] {
+        scope::run!(ctx, |ctx, s| async {
+            let (mut s0, s1) = noise::testonly::pipe(ctx).await;
+            s.spawn_bg(async {
+                let mut s1 = s1;
+                let session_id = node::SessionId(s1.id().encode());
+                let mut resume = tickets1
+                    .held(ctx, &cfg0.key.public())
+                    .unwrap()
+                    .prove(&session_id);
+                if !valid {
+                    resume.mac = ctx.rng().gen();
+                }
+                // Only the ticket can authenticate node 1, the signature is invalid.
+                let mut h = Handshake {
+                    session_id: cfg1.key.sign_msg(session_id),
+                    genesis,
+                    is_static: false,
+                    observed_addr: None,
+                    resume: Some(resume),
+                    protocol_version: validator::ProtocolVersion::EARLIEST,
+                };
+                h.session_id.sig = cfg1.key.sign_msg(ctx.rng().gen::<node::SessionId>()).sig;
+                frame::send_proto(ctx, &mut s1, &h).await?;
+                if valid {
+                    let _: Handshake =
+                        frame::recv_proto(ctx, &mut s1, Handshake::max_size()).await?;
+                }
+                Ok(())
+            });
+            let res = inbound(ctx, &cfg0, genesis, &tickets0, &mut s0).await;
+            if valid {
+                assert_eq!(cfg1.key.public(), res?.peer);
+            } else {
+                assert_matches!(res, Err(Error::Signature(..)));
+            }
+            anyhow::Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    tracing::info!("a ticket issued to another node is not accepted");
+    let ticket = tickets0.issue(ctx, &cfg0.key.public()).unwrap();
+    let session_id: node::SessionId = rng.gen();
+    assert!(!tickets0.verify(
+        ctx,
+        &cfg1.key.public(),
+        &session_id,
+        Some(&ticket.prove(&session_id))
+    ));
+}
@@ -0,0 +1,72 @@
+//! Highest CommitQC known to the node.
+//! A replica which was partitioned away would otherwise time out through every view it has
+//! missed. Instead, the LeaderCommit carrying the highest CommitQC is gossiped to all the nodes,
+//! so that the replica can jump directly to the current view of the network.
+use std::sync::Arc;
+use zksync_concurrency::sync;
+use zksync_consensus_roles::validator;
+
+/// LeaderCommit with the highest view known to the node.
+pub(crate) type HighQc = Option<Arc<validator::Signed<validator::LeaderCommit>>>;
+
+/// Watch of the highest known LeaderCommit.
+pub(crate) struct HighQcWatch(sync::watch::Sender<HighQc>);
+
+impl Default for HighQcWatch {
+    fn default() -> Self {
+        Self(sync::watch::channel(None).0)
+    }
+}
+
+impl HighQcWatch {
+    /// Subscribes to the updates of the highest LeaderCommit.
+    pub(crate) fn subscribe(&self) -> sync::watch::Receiver<HighQc> {
+        self.0.subscribe()
+    }
+
+    /// Replaces the highest LeaderCommit with `msg`, if `msg` is for a higher view.
+    /// Returns an error if `msg` is invalid, `false` if it is not newer than the current one.
+    pub(crate) fn update(
+        &self,
+        genesis: &validator::Genesis,
+        msg: &Arc<validator::Signed<validator::LeaderCommit>>,
+    ) -> anyhow::Result<bool> {
+        let view = msg.msg.view().number;
+        if let Some(old) = &*self.0.borrow() {
+            if old.msg.view().number >= view {
+                return Ok(false);
+            }
+        }
+        anyhow::ensure!(
+            genesis.is_view_leader(&msg.key, view),
+            "not signed by the leader of {view:?}"
+        );
+        msg.verify()?;
+        msg.msg.verify(genesis)?;
+        Ok(self.0.send_if_modified(|old| {
+            if old
+                .as_ref()
+                .is_some_and(|old| old.msg.view().number >= view)
+            {
+                return false;
+            }
+            *old = Some(msg.clone());
+            true
+        }))
+    }
+
+    /// Updates the highest LeaderCommit from a consensus message passing through the node.
+    /// Other consensus messages are ignored.
+    pub(crate) fn observe(
+        &self,
+        genesis: &validator::Genesis,
+        msg: &validator::Signed<validator::ConsensusMsg>,
+    ) {
+        let Ok(msg) = msg.clone().cast::<validator::LeaderCommit>() else {
+            return;
+        };
+        if let Err(err) = self.update(genesis, &Arc::new(msg)) {
+            tracing::debug!("high_qc.update(): {err:#}");
+        }
+    }
+}
@@ -16,29 +16,51 @@ use crate::{
     gossip::{ArcMap, ValidatorAddrsWatch},
     io,
     pool::PoolWatch,
-    rpc, Config,
+    quarantine::Quarantine,
+    reconnect::Reconnector,
+    rpc,
+    tickets::Tickets,
+    watch::Watch,
+    Config, GossipConfig, RelayAuth, ReloadableConfig, RpcConfig,
 };
 use anyhow::Context as _;
-use std::sync::{atomic::AtomicUsize, Arc};
+use std::{
+    collections::BTreeMap,
+    sync::{atomic::AtomicUsize, Arc, Mutex},
+};
 
+pub(crate) mod address_book;
 mod arcmap;
-mod handshake;
+mod bandwidth;
+mod batch_votes;
+pub mod doctor;
+pub mod genesis;
+pub(crate) mod handshake;
+mod high_qc;
+mod payload_cache;
+mod public_addr;
+mod relay;
 mod runner;
 #[cfg(test)]
 mod tests;
+pub mod topics;
+mod upgrade_votes;
 mod validator_addrs;
 
 pub(crate) use arcmap::*;
 pub(crate) use validator_addrs::*;
-use zksync_concurrency::{ctx, ctx::channel};
+use zksync_concurrency::{ctx, ctx::channel, limiter, scope};
 use zksync_consensus_roles::{node, validator};
-use zksync_consensus_storage::BlockStore;
-use zksync_protobuf::kB;
+use zksync_consensus_storage::BlockStoreReader;
+use zksync_protobuf::{kB, MB};
 
 /// Gossip network state.
 pub(crate) struct Network {
     /// Gossip network configuration.
+    /// The reloadable part of it is superseded by `reloadable`.
     pub(crate) cfg: Config,
+    /// Current reloadable part of the configuration.
+    pub(crate) reloadable: Watch<ReloadableConfig>,
     /// Currently open inbound connections.
     pub(crate) inbound: PoolWatch<node::PublicKey>,
     /// Currently open outbound connections.
@@ -46,11 +68,53 @@ pub(crate) struct Network {
     /// Current state of knowledge about validators' endpoints.
     pub(crate) validator_addrs: ValidatorAddrsWatch,
     /// Block store to serve `get_block` requests from.
-    pub(crate) block_store: Arc<BlockStore>,
+    pub(crate) block_store: BlockStoreReader,
+    /// Latest protocol versions supported by the currently active peers,
+    /// as announced in the handshake.
+    pub(crate) peer_versions: ArcMap<validator::ProtocolVersion>,
     /// Clients for `get_block` requests for each currently active peer.
+    /// Used for the peers which don't support `get_block_chunk`.
     pub(crate) get_block_clients: ArcMap<rpc::Client<rpc::get_block::Rpc>>,
+    /// Clients for `get_block_chunk` requests for each currently active peer.
+    pub(crate) get_block_chunk_clients: ArcMap<rpc::Client<rpc::get_block_chunk::Rpc>>,
+    /// Clients for `get_headers` requests for each currently active peer.
+    pub(crate) get_headers_clients: ArcMap<rpc::Client<rpc::get_headers::Rpc>>,
+    /// Clients for `sample_payload` requests for each currently active peer.
+    pub(crate) sample_payload_clients: ArcMap<rpc::Client<rpc::sample_payload::Rpc>>,
+    /// Payloads sampled by the peers recently, to serve `sample_payload` requests from.
+    pub(crate) sampled_payloads: payload_cache::PayloadCache,
+    /// Clients for `relay_consensus` requests for each currently active peer.
+    pub(crate) relay_clients: ArcMap<rpc::Client<rpc::relay_consensus::Rpc>>,
+    /// Replay windows of the consensus messages relayed over the gossip network.
+    pub(crate) relay_replay: relay::RelayReplay,
+    /// Rate limit of forwarding the relayed consensus messages.
+    pub(crate) relay_limiter: limiter::Limiter,
     /// Output pipe of the network actor.
     pub(crate) sender: channel::UnboundedSender<io::OutputMessage>,
+    /// Peers with a different genesis.
+    pub(crate) quarantine: Quarantine<node::PublicKey>,
+    /// Node addresses discovered via the peer exchange.
+    pub(crate) address_book: address_book::AddressBook,
+    /// LeaderCommit with the highest CommitQC known to this node.
+    pub(crate) high_qc: high_qc::HighQcWatch,
+    /// Latest votes of the attesters and the batch certificate aggregated from them.
+    pub(crate) batch_votes: batch_votes::BatchVotesWatch,
+    /// Latest protocol upgrades announced by the validators
+    /// and the upgrade certificate aggregated from them.
+    pub(crate) upgrade_votes: upgrade_votes::UpgradeVotesWatch,
+    /// Reconnect manager of the static outbound connections
+    /// (and of the consensus connections, see `consensus::Network`).
+    pub(crate) reconnect: Reconnector,
+    /// Session tickets issued to and by this node.
+    pub(crate) tickets: Tickets<node::PublicKey>,
+    /// Public address of this node (configured or detected).
+    pub(crate) public_addr: public_addr::PublicAddr,
+    /// Bandwidth budget for serving blocks, shared by all peers.
+    /// Replaced when `serve_blocks_bandwidth` is reloaded.
+    serve_budget: Mutex<Arc<bandwidth::Budget>>,
+    /// TESTONLY: peers this node is partitioned away from.
+    #[cfg(any(test, feature = "chaos"))]
+    pub(crate) partition: crate::partition::PartitionWatch,
     /// TESTONLY: how many time push_validator_addrs rpc was called by the peers.
     pub(crate) push_validator_addrs_calls: AtomicUsize,
 }
@@ -58,8 +122,9 @@ pub(crate) struct Network {
 impl Network {
     /// Constructs a new State.
     pub(crate) fn new(
+        ctx: &ctx::Ctx,
         cfg: Config,
-        block_store: Arc<BlockStore>,
+        block_store: BlockStoreReader,
         sender: channel::UnboundedSender<io::OutputMessage>,
     ) -> Arc<Self> {
         Arc::new(Self {
@@ -68,37 +133,363 @@ impl Network {
                 cfg.gossip.static_inbound.clone(),
                 cfg.gossip.dynamic_inbound_limit,
             ),
-            outbound: PoolWatch::new(cfg.gossip.static_outbound.keys().cloned().collect(), 0),
+            outbound: PoolWatch::new(
+                cfg.gossip.static_outbound.keys().cloned().collect(),
+                cfg.gossip.dynamic_outbound_limit,
+            ),
             validator_addrs: ValidatorAddrsWatch::default(),
             block_store,
+            peer_versions: ArcMap::default(),
             get_block_clients: ArcMap::default(),
+            get_block_chunk_clients: ArcMap::default(),
+            get_headers_clients: ArcMap::default(),
+            sample_payload_clients: ArcMap::default(),
+            sampled_payloads: payload_cache::PayloadCache::default(),
+            relay_clients: ArcMap::default(),
+            relay_replay: relay::RelayReplay::new(cfg.consensus_replay_window),
+            relay_limiter: limiter::Limiter::new(ctx, relay::FORWARD_RATE),
+            quarantine: Quarantine::new(cfg.genesis_mismatch_quarantine),
+            address_book: address_book::AddressBook::default(),
+            high_qc: high_qc::HighQcWatch::default(),
+            batch_votes: batch_votes::BatchVotesWatch::default(),
+            upgrade_votes: upgrade_votes::UpgradeVotesWatch::default(),
+            reconnect: Reconnector::new(cfg.reconnect.clone()),
+            tickets: Tickets::new("gossip", &cfg.gossip.key, cfg.session_ticket_ttl),
+            public_addr: public_addr::PublicAddr::new(cfg.public_addr, cfg.public_addr_detection),
+            serve_budget: Mutex::new(Arc::new(bandwidth::Budget::new(
+                ctx,
+                cfg.serve_blocks_bandwidth,
+            ))),
+            reloadable: Watch::new(cfg.reloadable()),
             cfg,
+            #[cfg(any(test, feature = "chaos"))]
+            partition: crate::partition::PartitionWatch::default(),
             push_validator_addrs_calls: 0.into(),
         })
     }
 
+    /// Applies the new reloadable part of the configuration.
+    pub(crate) async fn reload(&self, ctx: &ctx::Ctx, cfg: ReloadableConfig) {
+        let reloadable = self.reloadable.lock().await;
+        if *reloadable.borrow() == cfg {
+            return;
+        }
+        self.inbound
+            .update(
+                cfg.gossip_static_inbound.clone(),
+                cfg.gossip_dynamic_inbound_limit,
+            )
+            .await;
+        self.outbound
+            .update(
+                cfg.gossip_static_outbound.keys().cloned().collect(),
+                self.cfg.gossip.dynamic_outbound_limit,
+            )
+            .await;
+        if reloadable.borrow().serve_blocks_bandwidth != cfg.serve_blocks_bandwidth {
+            *self.serve_budget.lock().unwrap() =
+                Arc::new(bandwidth::Budget::new(ctx, cfg.serve_blocks_bandwidth));
+        }
+        tracing::info!("gossip config reloaded: {cfg:?}");
+        reloadable.send_replace(cfg);
+    }
+
+    /// Gossip config, with the reloadable part up to date.
+    pub(crate) fn gossip_cfg(&self) -> GossipConfig {
+        let r = self.reloadable.subscribe().borrow().clone();
+        GossipConfig {
+            static_inbound: r.gossip_static_inbound,
+            static_outbound: r.gossip_static_outbound,
+            dynamic_inbound_limit: r.gossip_dynamic_inbound_limit,
+            ..self.cfg.gossip.clone()
+        }
+    }
+
+    /// Rate limits of the RPCs, up to date with the reloads.
+    pub(crate) fn rpc_cfg(&self) -> RpcConfig {
+        self.reloadable.subscribe().borrow().rpc.clone()
+    }
+
+    /// Bandwidth budget for serving blocks, shared by all peers.
+    pub(crate) fn serve_budget(&self) -> Arc<bandwidth::Budget> {
+        self.serve_budget.lock().unwrap().clone()
+    }
+
     /// Genesis.
-    pub(crate) fn genesis(&self) -> &validator::Genesis {
+    pub(crate) fn genesis(&self) -> Arc<validator::Genesis> {
         self.block_store.genesis()
     }
 
-    /// Sends a GetBlock RPC to the given peer.
+    /// Maximal size of the proto-encoded `validator::FinalBlock` in bytes.
+    /// If genesis limits the payload size, the limit is derived from genesis,
+    /// so that all nodes accept the same blocks.
+    pub(crate) fn max_block_size(&self) -> usize {
+        match self.genesis().max_payload_size {
+            Some(max) => max.saturating_add(kB),
+            None => self.cfg.max_block_size,
+        }
+    }
+
+    /// Whether the block `number` has a protocol version not later than `version`.
+    /// Blocks are exchanged only with the peers which support their version.
+    pub(crate) fn supports_block(
+        &self,
+        version: validator::ProtocolVersion,
+        number: validator::BlockNumber,
+    ) -> bool {
+        self.genesis().protocol_version_at(number) <= version
+    }
+
+    /// Whether the given peer supports the protocol version of the block `number`.
+    /// Peers which didn't announce a version support only the earliest one.
+    fn peer_supports_block(&self, peer: &node::PublicKey, number: validator::BlockNumber) -> bool {
+        let version = self
+            .peer_versions
+            .get_any(peer)
+            .map_or(validator::ProtocolVersion::EARLIEST, |v| *v);
+        self.supports_block(version, number)
+    }
+
+    /// Fetches the justification of a block from the given peer, without the payload.
+    /// Peers which don't support GetBlockChunk send the whole block instead.
+    /// The justification is NOT verified.
+    pub(crate) async fn get_justification(
+        &self,
+        ctx: &ctx::Ctx,
+        recipient: &node::PublicKey,
+        number: validator::BlockNumber,
+    ) -> anyhow::Result<Option<validator::CommitQC>> {
+        if !self.peer_supports_block(recipient, number) {
+            return Ok(None);
+        }
+        let client = self
+            .get_block_chunk_clients
+            .get_any(recipient)
+            .context("recipient is unreachable")?;
+        if !client.supported(ctx).await? {
+            let block = self.get_whole_block(ctx, recipient, number).await?;
+            return Ok(block.map(|(block, _)| block.justification));
+        }
+        let max_resp_size = rpc::get_block_chunk::MAX_CHUNK_SIZE.saturating_add(kB);
+        let req = rpc::get_block_chunk::Req {
+            number,
+            offset: 0,
+            justification_only: true,
+        };
+        let Some(chunk) = client.call(ctx, &req, max_resp_size).await?.0 else {
+            return Ok(None);
+        };
+        Ok(Some(chunk.justification.context("missing justification")?))
+    }
+
+    /// Fetches the headers of up to `count` consecutive blocks starting at `first`
+    /// from the given peer, without the payloads. The headers are NOT verified,
+    /// except that they are for the requested blocks.
+    pub(crate) async fn get_headers(
+        &self,
+        ctx: &ctx::Ctx,
+        recipient: &node::PublicKey,
+        first: validator::BlockNumber,
+        count: usize,
+    ) -> anyhow::Result<Vec<rpc::get_headers::FinalHeader>> {
+        let client = self
+            .get_headers_clients
+            .get_any(recipient)
+            .context("recipient is unreachable")?;
+        let req = rpc::get_headers::Req { first, count };
+        let headers = client.call(ctx, &req, MB).await?.0;
+        anyhow::ensure!(headers.len() <= count, "too many headers");
+        let mut want = first;
+        for header in &headers {
+            let got = header.justification.header().number;
+            anyhow::ensure!(got == want, "got block {got:?}, want {want:?}");
+            want = want.next();
+        }
+        Ok(headers)
+    }
+
+    /// Samples the payload of a block from the given peer, see `rpc::sample_payload`.
+    /// The samples are NOT verified, except that there is one per requested sample.
+    pub(crate) async fn sample_payload(
+        &self,
+        ctx: &ctx::Ctx,
+        recipient: &node::PublicKey,
+        number: validator::BlockNumber,
+        samples: Vec<u64>,
+    ) -> anyhow::Result<Option<Vec<io::PayloadSample>>> {
+        anyhow::ensure!(
+            samples.len() <= rpc::sample_payload::MAX_SAMPLES,
+            "too many samples"
+        );
+        let client = self
+            .sample_payload_clients
+            .get_any(recipient)
+            .context("recipient is unreachable")?;
+        let want = samples.len();
+        let req = rpc::sample_payload::Req { number, samples };
+        let Some(samples) = client.call(ctx, &req, MB).await?.0 else {
+            return Ok(None);
+        };
+        anyhow::ensure!(
+            samples.0.len() == want,
+            "got {} samples, want {want}",
+            samples.0.len()
+        );
+        Ok(Some(samples.0))
+    }
+
+    /// Fetches a block from the given peer, using GetBlockChunk RPCs.
+    /// The payload is fetched in chunks, so that blocks larger than the max RPC message size
+    /// can be synced. Peers which don't support GetBlockChunk are asked for the whole block
+    /// with a GetBlock RPC instead. Blocks of protocol versions not supported by the peer
+    /// are not requested.
     pub(crate) async fn get_block(
         &self,
         ctx: &ctx::Ctx,
         recipient: &node::PublicKey,
         number: validator::BlockNumber,
     ) -> anyhow::Result<Option<validator::FinalBlock>> {
-        Ok(self
+        if !self.peer_supports_block(recipient, number) {
+            return Ok(None);
+        }
+        let client = self
+            .get_block_chunk_clients
+            .get_any(recipient)
+            .context("recipient is unreachable")?;
+        let res = match client.supported(ctx).await? {
+            true => self.get_block_chunks(ctx, &client, number).await?,
+            false => self.get_whole_block(ctx, recipient, number).await?,
+        };
+        let Some((block, relay_sig)) = res else {
+            return Ok(None);
+        };
+        let Some(sig) = relay_sig else {
+            anyhow::ensure!(
+                self.cfg.gossip.relay_auth != RelayAuth::Require,
+                "missing relay signature"
+            );
+            return Ok(Some(block));
+        };
+        // Only the relay path is authenticated here, the block itself
+        // is verified by the consumer.
+        let relayed = node::Signed {
+            msg: node::RelayedBlock {
+                genesis: self.genesis().hash(),
+                block,
+            },
+            key: recipient.clone(),
+            sig,
+        };
+        relayed.verify().context("relay signature")?;
+        Ok(Some(relayed.msg.block))
+    }
+
+    /// Fetches a block with a single GetBlock RPC, together with the relay signature.
+    async fn get_whole_block(
+        &self,
+        ctx: &ctx::Ctx,
+        recipient: &node::PublicKey,
+        number: validator::BlockNumber,
+    ) -> anyhow::Result<Option<(validator::FinalBlock, Option<node::Signature>)>> {
+        let resp = self
             .get_block_clients
             .get_any(recipient)
             .context("recipient is unreachable")?
             .call(
                 ctx,
                 &rpc::get_block::Req(number),
-                self.cfg.max_block_size.saturating_add(kB),
+                self.max_block_size().saturating_add(kB),
             )
-            .await?
-            .0)
+            .await?;
+        let Some(block) = resp.block else {
+            return Ok(None);
+        };
+        anyhow::ensure!(
+            block.number() == number,
+            "got block {:?}, want {number:?}",
+            block.number()
+        );
+        Ok(Some((block, resp.relay_sig)))
+    }
+
+    /// Fetches a block with GetBlockChunk RPCs, together with the relay signature.
+    /// Every chunk but the last one has `MAX_CHUNK_SIZE` bytes, so the offsets of all the chunks
+    /// are known once the first chunk arrives. The remaining chunks are then fetched concurrently
+    /// and indexed by their offset, and the payload is verified against the justification.
+    async fn get_block_chunks(
+        &self,
+        ctx: &ctx::Ctx,
+        client: &rpc::Client<rpc::get_block_chunk::Rpc>,
+        number: validator::BlockNumber,
+    ) -> anyhow::Result<Option<(validator::FinalBlock, Option<node::Signature>)>> {
+        use rpc::get_block_chunk::MAX_CHUNK_SIZE;
+        let max_resp_size = MAX_CHUNK_SIZE.saturating_add(kB);
+        let req = rpc::get_block_chunk::Req {
+            number,
+            offset: 0,
+            justification_only: false,
+        };
+        let Some(first) = client.call(ctx, &req, max_resp_size).await?.0 else {
+            return Ok(None);
+        };
+        let justification = first.justification.context("missing justification")?;
+        anyhow::ensure!(
+            justification.header().number == number,
+            "got block {:?}, want {number:?}",
+            justification.header().number
+        );
+        let payload_size = first.payload_size;
+        anyhow::ensure!(
+            payload_size <= self.max_block_size(),
+            "payload too large: got {payload_size}B, max {}B",
+            self.max_block_size()
+        );
+        let chunk_len = |offset: usize| MAX_CHUNK_SIZE.min(payload_size - offset);
+        anyhow::ensure!(first.data.len() == chunk_len(0), "bad chunk size");
+        let chunks = Mutex::new(BTreeMap::from([(0, first.data)]));
+        let res: anyhow::Result<()> = scope::run!(ctx, |ctx, s| async {
+            for offset in (MAX_CHUNK_SIZE..payload_size).step_by(MAX_CHUNK_SIZE) {
+                let chunks = &chunks;
+                s.spawn(async move {
+                    let req = rpc::get_block_chunk::Req {
+                        number,
+                        offset,
+                        justification_only: false,
+                    };
+                    let chunk = client
+                        .call(ctx, &req, max_resp_size)
+                        .await?
+                        .0
+                        .context("block is no longer available")?;
+                    anyhow::ensure!(
+                        chunk.payload_size == payload_size,
+                        "payload size has changed"
+                    );
+                    anyhow::ensure!(chunk.data.len() == chunk_len(offset), "bad chunk size");
+                    chunks.lock().unwrap().insert(offset, chunk.data);
+                    Ok(())
+                });
+            }
+            Ok(())
+        })
+        .await;
+        res?;
+        let mut hasher = validator::PayloadHasher::default();
+        let mut payload = Vec::with_capacity(payload_size);
+        for chunk in chunks.into_inner().unwrap().into_values() {
+            hasher.update(&chunk);
+            payload.extend_from_slice(&chunk);
+        }
+        anyhow::ensure!(
+            hasher.finalize() == justification.header().payload,
+            "payload hash mismatch"
+        );
+        let block = validator::FinalBlock {
+            payload: validator::Payload(payload),
+            justification,
+            key_rotation: first.key_rotation,
+            protocol_upgrade: first.protocol_upgrade,
+        };
+        Ok(Some((block, first.relay_sig)))
     }
 }
@@ -0,0 +1,52 @@
+//! Cache of the payloads sampled by the peers, together with their Merkle trees.
+//! The peers sample a few random chunks of a block at a time, while the proofs of the chunks
+//! are computed from the Merkle tree over the whole payload. Without the cache, every
+//! `sample_payload` request would read the whole block and hash the whole payload again.
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+use zksync_consensus_roles::validator;
+
+/// Max number of the cached payloads.
+pub(crate) const CAPACITY: usize = 16;
+
+/// Payload with its Merkle tree.
+#[derive(Debug)]
+pub(crate) struct SampledPayload {
+    /// Payload of the block.
+    pub(crate) payload: validator::Payload,
+    /// Merkle tree over the chunks of the payload.
+    pub(crate) tree: validator::PayloadTree,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    payloads: HashMap<validator::BlockNumber, Arc<SampledPayload>>,
+    /// Block numbers of the cached payloads, in the insertion order.
+    order: VecDeque<validator::BlockNumber>,
+}
+
+/// Cache of at most `CAPACITY` payloads, evicting the least recently inserted one.
+#[derive(Debug, Default)]
+pub(crate) struct PayloadCache(Mutex<Inner>);
+
+impl PayloadCache {
+    /// Cached payload of the block.
+    pub(crate) fn get(&self, number: validator::BlockNumber) -> Option<Arc<SampledPayload>> {
+        self.0.lock().unwrap().payloads.get(&number).cloned()
+    }
+
+    /// Inserts the payload of the block, evicting the oldest entry if the cache is full.
+    pub(crate) fn insert(&self, number: validator::BlockNumber, payload: Arc<SampledPayload>) {
+        let mut this = self.0.lock().unwrap();
+        if this.payloads.insert(number, payload).is_some() {
+            return;
+        }
+        this.order.push_back(number);
+        if this.order.len() > CAPACITY {
+            let oldest = this.order.pop_front().unwrap();
+            this.payloads.remove(&oldest);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Detection of the public address of the node.
+//! Nodes behind NAT don't know their public IP. Instead, the peers that the node
+//! dials report the remote address they observe during the handshake, and once
+//! enough of them agree, the reported IP replaces the configured one.
+//! Only the static outbound peers report the address, and at least `MIN_REPORTS` of them,
+//! forming a majority of the reports, have to agree, so that a single peer cannot
+//! hijack the advertised address.
+use crate::config::PublicAddrDetection;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+};
+use zksync_concurrency::sync;
+use zksync_consensus_roles::node;
+
+/// Min number of the agreeing reports, regardless of the configured `min_reports`.
+const MIN_REPORTS: usize = 2;
+
+/// Public address of the node.
+pub(crate) struct PublicAddr {
+    /// Configured public address. Its port is kept when the IP is detected.
+    configured: SocketAddr,
+    /// Detection config. `None` if the detection is disabled.
+    detection: Option<PublicAddrDetection>,
+    /// IPs of this node, as observed by the peers.
+    reports: Mutex<HashMap<node::PublicKey, IpAddr>>,
+    /// Current public address.
+    current: sync::watch::Sender<SocketAddr>,
+}
+
+impl PublicAddr {
+    /// Constructs a new public address, starting with the `configured` one.
+    pub(crate) fn new(configured: SocketAddr, detection: Option<PublicAddrDetection>) -> Self {
+        Self {
+            configured,
+            detection,
+            reports: Mutex::default(),
+            current: sync::watch::channel(configured).0,
+        }
+    }
+
+    /// Current public address.
+    pub(crate) fn get(&self) -> SocketAddr {
+        *self.current.borrow()
+    }
+
+    /// Subscribes to the public address changes.
+    pub(crate) fn subscribe(&self) -> sync::watch::Receiver<SocketAddr> {
+        self.current.subscribe()
+    }
+
+    /// Records the IP of this node observed by the `peer`.
+    /// Updates the public address, if at least `min_reports` (and `MIN_REPORTS`) peers
+    /// agree on a new IP, and they are a strict majority of the reporting peers.
+    pub(crate) fn report(&self, peer: &node::PublicKey, ip: IpAddr) {
+        let Some(detection) = &self.detection else {
+            return;
+        };
+        let mut reports = self.reports.lock().unwrap();
+        reports.insert(peer.clone(), ip);
+        let mut counts = HashMap::<IpAddr, usize>::new();
+        for ip in reports.values() {
+            *counts.entry(*ip).or_default() += 1;
+        }
+        // On a tie, the current IP is preferred.
+        let current = self.get().ip();
+        let Some((ip, count)) = counts
+            .into_iter()
+            .max_by_key(|(ip, count)| (*count, *ip == current))
+        else {
+            return;
+        };
+        if count < detection.min_reports.max(MIN_REPORTS) || 2 * count <= reports.len() {
+            return;
+        }
+        let addr = SocketAddr::new(ip, self.configured.port());
+        self.current.send_if_modified(|current| {
+            if *current == addr {
+                return false;
+            }
+            tracing::info!("public address changed: {current} -> {addr}");
+            *current = addr;
+            true
+        });
+    }
+
+    /// Drops the report of the `peer`, after the connection to it has been closed.
+    pub(crate) fn forget(&self, peer: &node::PublicKey) {
+        self.reports.lock().unwrap().remove(peer);
+    }
+}
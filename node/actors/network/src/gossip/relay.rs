@@ -0,0 +1,239 @@
+//! Relaying of the consensus messages over the gossip network.
+//! Consensus network requires a direct connection between every pair of validators.
+//! When some of these connections are broken (misconfigured firewall, network partition),
+//! the messages are flooded over the gossip network instead, with a limited number of hops,
+//! so that the committee stays live under partial connectivity. The relayed messages pass
+//! through per-signer replay windows and the forwarding is rate limited,
+//! so that a flood is not amplified across the mesh.
+use super::Network;
+use crate::{
+    consensus::replay::{Replay, ReplayWindow},
+    io, rpc,
+};
+use anyhow::Context as _;
+use std::{collections::HashMap, sync::Mutex};
+use vise::{Counter, Metrics};
+use zksync_concurrency::{ctx, limiter, oneshot, scope, time};
+use zksync_consensus_roles::{node, validator};
+use zksync_protobuf::kB;
+
+/// Size of the replay windows of the relayed messages (in views),
+/// used if the replay protection is disabled in the config.
+/// Relayed messages are always deduplicated, otherwise they would circulate
+/// until they run out of hops.
+const RELAY_REPLAY_WINDOW: u64 = 16;
+/// Timeout of forwarding a message to a single peer.
+/// Relaying is best effort, so a slow peer shouldn't block the others.
+const FORWARD_TIMEOUT: time::Duration = time::Duration::seconds(5);
+/// Max rate of forwarding the messages relayed by the peers, in total.
+/// Messages above it are still delivered to this node, but not forwarded,
+/// so that a flood of messages is not amplified across the mesh.
+pub(crate) const FORWARD_RATE: limiter::Rate = limiter::Rate {
+    burst: 100,
+    refresh: time::Duration::milliseconds(10),
+};
+/// Max number of the messages relayed by a single peer, waiting to be forwarded.
+/// Messages above it are not forwarded.
+pub(super) const FORWARD_QUEUE_SIZE: usize = 100;
+
+/// Replay windows of the relayed messages, one per signer.
+/// Messages are deduplicated by hash within the recent views, and the messages
+/// for older views are dropped. Signers are verified to be validators before their
+/// messages are recorded, so the number of the windows is bounded by the committee size.
+pub(crate) struct RelayReplay {
+    size: u64,
+    windows: Mutex<HashMap<validator::PublicKey, ReplayWindow>>,
+}
+
+impl RelayReplay {
+    /// Constructs the replay windows covering `size` most recent views.
+    pub(crate) fn new(size: u64) -> Self {
+        Self {
+            size: if size == 0 { RELAY_REPLAY_WINDOW } else { size },
+            windows: Mutex::default(),
+        }
+    }
+
+    /// Checks whether the message should be dropped, without recording it.
+    fn check(&self, msg: &validator::Signed<validator::ConsensusMsg>) -> Result<(), Replay> {
+        match self.windows.lock().unwrap().get(&msg.key) {
+            Some(window) => window.check(msg),
+            None => Ok(()),
+        }
+    }
+
+    /// Records the message. Returns an error if the message should be dropped.
+    /// The message has to be verified (both the signature and the signer) by the caller.
+    pub(crate) fn insert(
+        &self,
+        msg: &validator::Signed<validator::ConsensusMsg>,
+    ) -> Result<(), Replay> {
+        self.windows
+            .lock()
+            .unwrap()
+            .entry(msg.key.clone())
+            .or_insert_with(|| ReplayWindow::new(self.size))
+            .insert(msg)
+    }
+}
+
+/// Server of the relayed consensus messages for a single gossip connection.
+/// The requests are acknowledged once the message is verified and recorded,
+/// the forwarding happens in the background (see `Network::forward_relayed`),
+/// so that the relaying peer is not blocked by the slow peers of this node.
+pub(super) struct RelayConsensusServer<'a> {
+    pub(super) net: &'a Network,
+    /// Messages to forward.
+    pub(super) forward: ctx::channel::Sender<rpc::relay_consensus::Req>,
+}
+
+#[async_trait::async_trait]
+impl rpc::Handler<rpc::relay_consensus::Rpc> for RelayConsensusServer<'_> {
+    fn max_req_size(&self) -> usize {
+        self.net.max_block_size().saturating_add(kB)
+    }
+
+    async fn handle(&self, ctx: &ctx::Ctx, req: rpc::relay_consensus::Req) -> anyhow::Result<()> {
+        let net = self.net;
+        if net.cfg.consensus_relay_max_hops == 0 || net.relay_replay.check(&req.msg).is_err() {
+            return Ok(());
+        }
+        // Messages are verified before being recorded, so that a forged copy
+        // doesn't suppress relaying of the genuine one.
+        anyhow::ensure!(
+            net.genesis().validator_keys().any(|k| k == &req.msg.key),
+            "message signed by a non-validator"
+        );
+        req.msg.verify().context("verify()")?;
+        if net.relay_replay.insert(&req.msg).is_err() {
+            return Ok(());
+        }
+        METRICS.received.inc();
+        let own_key = net.cfg.consensus_key();
+        let deliver = own_key.is_some() && (req.recipient.is_none() || req.recipient == own_key);
+        let forward = req.recipient.is_none() || req.recipient != own_key;
+        let hops = req.hops.min(net.cfg.consensus_relay_max_hops);
+        if forward && hops > 0 {
+            if net.relay_limiter.try_acquire(ctx, 1).is_none() {
+                METRICS.forward_rate_limited.inc();
+            } else if self
+                .forward
+                .try_send(rpc::relay_consensus::Req {
+                    hops: hops - 1,
+                    ..req.clone()
+                })
+                .is_err()
+            {
+                METRICS.forward_queue_full.inc();
+            }
+        }
+        if deliver {
+            net.high_qc.observe(&net.genesis(), &req.msg);
+            let (send, recv) = oneshot::channel();
+            net.sender
+                .send(io::OutputMessage::Consensus(io::ConsensusReq {
+                    msg: req.msg,
+                    trace: req.trace,
+                    ack: send,
+                }));
+            recv.recv_or_disconnected(ctx).await??;
+        }
+        Ok(())
+    }
+}
+
+impl Network {
+    /// Relays a consensus message originating at this node over the gossip network.
+    /// `recipient` is the validator the message is addressed to (`None` for broadcast).
+    /// Noop if relaying is disabled.
+    pub(crate) async fn relay_consensus(
+        &self,
+        ctx: &ctx::Ctx,
+        msg: validator::Signed<validator::ConsensusMsg>,
+        recipient: Option<validator::PublicKey>,
+        trace: Option<crate::TraceContext>,
+    ) -> anyhow::Result<()> {
+        let hops = self.cfg.consensus_relay_max_hops;
+        if hops == 0 {
+            return Ok(());
+        }
+        // Echoes of the message coming back from the peers are ignored.
+        let _ = self.relay_replay.insert(&msg);
+        let req = rpc::relay_consensus::Req {
+            msg,
+            recipient,
+            hops: hops - 1,
+            trace,
+        };
+        self.forward_consensus(ctx, &req, None).await
+    }
+
+    /// Forwards the messages relayed by `peer`, queued by its `RelayConsensusServer`.
+    pub(super) async fn forward_relayed(
+        &self,
+        ctx: &ctx::Ctx,
+        peer: &node::PublicKey,
+        mut queue: ctx::channel::Receiver<rpc::relay_consensus::Req>,
+    ) -> ctx::OrCanceled<()> {
+        scope::run!(ctx, |ctx, s| async {
+            loop {
+                let req = queue.recv(ctx).await?;
+                s.spawn(async move {
+                    let _ = self.forward_consensus(ctx, &req, Some(peer)).await;
+                    Ok(())
+                });
+            }
+        })
+        .await
+    }
+
+    /// Forwards a relayed message to all the gossip peers except `from`.
+    /// Failures are only logged, since relaying is best effort.
+    async fn forward_consensus(
+        &self,
+        ctx: &ctx::Ctx,
+        req: &rpc::relay_consensus::Req,
+        from: Option<&node::PublicKey>,
+    ) -> anyhow::Result<()> {
+        let clients = self.relay_clients.all();
+        scope::run!(ctx, |ctx, s| async {
+            for (peer, client) in &clients {
+                if Some(peer) == from {
+                    continue;
+                }
+                s.spawn(async {
+                    let ctx = &ctx.with_timeout(FORWARD_TIMEOUT);
+                    match client.call(ctx, req, kB).await {
+                        Ok(()) => METRICS.forwarded.inc(),
+                        Err(err) => {
+                            tracing::debug!("relay_consensus({peer:?}): {err:#}");
+                            METRICS.forward_failures.inc();
+                        }
+                    }
+                    Ok(())
+                });
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// Metrics of the relayed consensus messages.
+#[derive(Debug, Metrics)]
+#[metrics(prefix = "network_gossip_relay")]
+struct RelayMetrics {
+    /// Distinct valid messages received from the peers.
+    received: Counter,
+    /// Messages successfully forwarded to the peers.
+    forwarded: Counter,
+    /// Messages which failed to be forwarded to the peers.
+    forward_failures: Counter,
+    /// Messages which were not forwarded, because of `FORWARD_RATE`.
+    forward_rate_limited: Counter,
+    /// Messages which were not forwarded, because of `FORWARD_QUEUE_SIZE`.
+    forward_queue_full: Counter,
+}
+
+#[vise::register]
+static METRICS: vise::Global<RelayMetrics> = vise::Global::new();
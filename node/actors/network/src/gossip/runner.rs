@@ -1,12 +1,24 @@
-use super::{handshake, Network, ValidatorAddrs};
-use crate::{io, noise, preface, rpc};
+use super::{
+    address_book, bandwidth, batch_votes::BatchVotes, handshake, payload_cache::SampledPayload,
+    relay, topics, upgrade_votes::UpgradeVotes, Network, ValidatorAddrs,
+};
+use crate::{dump, io, metrics, noise, preface, rpc, OutboundPeer, RelayAuth};
+use anyhow::Context as _;
 use async_trait::async_trait;
-use std::sync::{atomic::Ordering, Arc};
-use zksync_concurrency::{ctx, oneshot, scope, sync};
-use zksync_consensus_roles::node;
-use zksync_consensus_storage::BlockStore;
+use std::{
+    collections::HashSet,
+    sync::{atomic::Ordering, Arc, Mutex},
+};
+use zksync_concurrency::{ctx, oneshot, scope, sync, time};
+use zksync_consensus_crypto::TextFmt as _;
+use zksync_consensus_roles::{node, validator};
 use zksync_protobuf::kB;
 
+/// Max number of the addresses sent to a peer in a single peer exchange.
+const PEX_SAMPLE_SIZE: usize = 16;
+/// Interval between the attempts to dial a discovered peer.
+const DIAL_INTERVAL: time::Duration = time::Duration::seconds(1);
+
 struct PushValidatorAddrsServer<'a>(&'a Network);
 
 #[async_trait]
@@ -24,12 +36,93 @@ impl rpc::Handler<rpc::push_validator_addrs::Rpc> for PushValidatorAddrsServer<'
             .fetch_add(1, Ordering::SeqCst);
         self.0
             .validator_addrs
-            .update(&self.0.genesis().validators, &req.0[..])
+            .update(&self.0.genesis(), &req.0[..])
             .await?;
         Ok(())
     }
 }
 
+struct PushBatchVotesServer<'a>(&'a Network);
+
+#[async_trait]
+impl rpc::Handler<rpc::push_batch_votes::Rpc> for PushBatchVotesServer<'_> {
+    fn max_req_size(&self) -> usize {
+        100 * kB
+    }
+    async fn handle(&self, _ctx: &ctx::Ctx, req: rpc::push_batch_votes::Req) -> anyhow::Result<()> {
+        self.0
+            .batch_votes
+            .update(&self.0.genesis(), &req.0[..])
+            .await
+    }
+}
+
+struct PushUpgradeVotesServer<'a>(&'a Network);
+
+#[async_trait]
+impl rpc::Handler<rpc::push_upgrade_votes::Rpc> for PushUpgradeVotesServer<'_> {
+    fn max_req_size(&self) -> usize {
+        100 * kB
+    }
+    async fn handle(
+        &self,
+        _ctx: &ctx::Ctx,
+        req: rpc::push_upgrade_votes::Req,
+    ) -> anyhow::Result<()> {
+        self.0
+            .upgrade_votes
+            .update(&self.0.genesis(), &req.0[..])
+            .await
+    }
+}
+
+struct PexServer<'a> {
+    net: &'a Network,
+    /// Peer sending the addresses.
+    peer: &'a node::PublicKey,
+}
+
+#[async_trait]
+impl rpc::Handler<rpc::pex::Rpc> for PexServer<'_> {
+    fn max_req_size(&self) -> usize {
+        100 * kB
+    }
+    async fn handle(&self, _ctx: &ctx::Ctx, req: rpc::pex::Req) -> anyhow::Result<()> {
+        self.net
+            .address_book
+            .update(&self.net.cfg.gossip.key.public(), self.peer, &req.0)
+    }
+}
+
+struct PushHighQcServer<'a>(&'a Network);
+
+#[async_trait]
+impl rpc::Handler<rpc::push_high_qc::Rpc> for PushHighQcServer<'_> {
+    fn max_req_size(&self) -> usize {
+        10 * kB
+    }
+    async fn handle(&self, ctx: &ctx::Ctx, req: rpc::push_high_qc::Req) -> anyhow::Result<()> {
+        if !self.0.high_qc.update(&self.0.genesis(), &req.0)? {
+            return Ok(());
+        }
+        // Validators pass the newer LeaderCommit to the consensus, so that
+        // the replica can skip directly to the view of the CommitQC.
+        if self.0.cfg.consensus_key().is_none() {
+            return Ok(());
+        }
+        let (send, recv) = oneshot::channel();
+        self.0
+            .sender
+            .send(io::OutputMessage::Consensus(io::ConsensusReq {
+                msg: req.0.as_ref().clone().cast().unwrap(),
+                trace: None,
+                ack: send,
+            }));
+        recv.recv_or_disconnected(ctx).await??;
+        Ok(())
+    }
+}
+
 #[derive(Clone, Copy)]
 struct PushBlockStoreStateServer<'a> {
     peer: &'a node::PublicKey,
@@ -58,8 +151,16 @@ impl rpc::Handler<rpc::push_block_store_state::Rpc> for PushBlockStoreStateServe
     }
 }
 
+struct GetBlockServer<'a> {
+    net: &'a Network,
+    /// Bandwidth budget for serving blocks to the peer.
+    budget: &'a bandwidth::Budget,
+    /// Latest protocol version supported by the peer.
+    peer_version: validator::ProtocolVersion,
+}
+
 #[async_trait]
-impl rpc::Handler<rpc::get_block::Rpc> for &BlockStore {
+impl rpc::Handler<rpc::get_block::Rpc> for GetBlockServer<'_> {
     fn max_req_size(&self) -> usize {
         kB
     }
@@ -68,69 +169,511 @@ impl rpc::Handler<rpc::get_block::Rpc> for &BlockStore {
         ctx: &ctx::Ctx,
         req: rpc::get_block::Req,
     ) -> anyhow::Result<rpc::get_block::Resp> {
-        Ok(rpc::get_block::Resp(self.block(ctx, req.0).await?))
+        if !self.net.cfg.serve_blocks || !self.net.supports_block(self.peer_version, req.0) {
+            return Ok(rpc::get_block::Resp {
+                block: None,
+                relay_sig: None,
+            });
+        }
+        let block = self.net.block_store.block(ctx, req.0).await?;
+        if let Some(block) = &block {
+            bandwidth::consume(
+                ctx,
+                self.budget,
+                &self.net.serve_budget(),
+                block.payload.0.len(),
+            )
+            .await?;
+        }
+        let relay_sig = block.as_ref().and_then(|b| self.net.relay_sig(b));
+        Ok(rpc::get_block::Resp { block, relay_sig })
+    }
+}
+
+struct GetBlockChunkServer<'a> {
+    net: &'a Network,
+    /// Bandwidth budget for serving blocks to the peer.
+    budget: &'a bandwidth::Budget,
+    /// Latest protocol version supported by the peer.
+    peer_version: validator::ProtocolVersion,
+}
+
+#[async_trait]
+impl rpc::Handler<rpc::get_block_chunk::Rpc> for GetBlockChunkServer<'_> {
+    fn max_req_size(&self) -> usize {
+        kB
+    }
+    async fn handle(
+        &self,
+        ctx: &ctx::Ctx,
+        req: rpc::get_block_chunk::Req,
+    ) -> anyhow::Result<rpc::get_block_chunk::Resp> {
+        if !self.net.cfg.serve_blocks || !self.net.supports_block(self.peer_version, req.number) {
+            return Ok(rpc::get_block_chunk::Resp(None));
+        }
+        let Some(block) = self.net.block_store.block(ctx, req.number).await? else {
+            return Ok(rpc::get_block_chunk::Resp(None));
+        };
+        let payload_size = block.payload.0.len();
+        anyhow::ensure!(req.offset <= payload_size, "offset out of range");
+        let end = match req.justification_only {
+            true => req.offset,
+            false => payload_size.min(
+                req.offset
+                    .saturating_add(rpc::get_block_chunk::MAX_CHUNK_SIZE),
+            ),
+        };
+        bandwidth::consume(ctx, self.budget, &self.net.serve_budget(), end - req.offset).await?;
+        let data = block.payload.0[req.offset..end].to_vec();
+        // Justification, relay signature, key rotation and protocol upgrade
+        // are sent with the first chunk only.
+        let (justification, relay_sig, key_rotation, protocol_upgrade) = match req.offset {
+            0 => (
+                Some(block.justification.clone()),
+                self.net.relay_sig(&block),
+                block.key_rotation.clone(),
+                block.protocol_upgrade.clone(),
+            ),
+            _ => (None, None, None, None),
+        };
+        Ok(rpc::get_block_chunk::Resp(Some(
+            rpc::get_block_chunk::Chunk {
+                justification,
+                relay_sig,
+                payload_size,
+                data,
+                key_rotation,
+                protocol_upgrade,
+            },
+        )))
+    }
+}
+
+/// Serves the headers to a peer supporting the given protocol version.
+struct GetHeadersServer<'a>(&'a Network, validator::ProtocolVersion);
+
+#[async_trait]
+impl rpc::Handler<rpc::get_headers::Rpc> for GetHeadersServer<'_> {
+    fn max_req_size(&self) -> usize {
+        kB
+    }
+    async fn handle(
+        &self,
+        ctx: &ctx::Ctx,
+        req: rpc::get_headers::Req,
+    ) -> anyhow::Result<rpc::get_headers::Resp> {
+        let mut headers = vec![];
+        if !self.0.cfg.serve_blocks {
+            return Ok(rpc::get_headers::Resp(headers));
+        }
+        let mut number = req.first;
+        while headers.len() < req.count.min(rpc::get_headers::MAX_HEADERS)
+            && self.0.supports_block(self.1, number)
+        {
+            let Some(qc) = self.0.block_store.justification(ctx, number).await? else {
+                break;
+            };
+            // The committed key rotations and protocol upgrades are kept in the genesis.
+            let genesis = self.0.block_store.genesis();
+            let header = qc.header();
+            let key_rotation =
+                match &header.key_rotation {
+                    Some(hash) => {
+                        Some(genesis.key_rotations.find(hash).cloned().with_context(|| {
+                            format!("key rotation of block {number} is missing")
+                        })?)
+                    }
+                    None => None,
+                };
+            let protocol_upgrade = match &header.protocol_upgrade {
+                Some(hash) => Some(
+                    genesis
+                        .protocol_upgrades
+                        .find(hash)
+                        .cloned()
+                        .with_context(|| {
+                            format!("protocol upgrade of block {number} is missing")
+                        })?,
+                ),
+                None => None,
+            };
+            headers.push(rpc::get_headers::FinalHeader {
+                justification: qc,
+                key_rotation,
+                protocol_upgrade,
+            });
+            number = number.next();
+        }
+        Ok(rpc::get_headers::Resp(headers))
+    }
+}
+
+struct SamplePayloadServer<'a> {
+    net: &'a Network,
+    /// Bandwidth budget for serving blocks to the peer.
+    budget: &'a bandwidth::Budget,
+}
+
+#[async_trait]
+impl rpc::Handler<rpc::sample_payload::Rpc> for SamplePayloadServer<'_> {
+    fn max_req_size(&self) -> usize {
+        kB
+    }
+    async fn handle(
+        &self,
+        ctx: &ctx::Ctx,
+        req: rpc::sample_payload::Req,
+    ) -> anyhow::Result<rpc::sample_payload::Resp> {
+        anyhow::ensure!(
+            req.samples.len() <= rpc::sample_payload::MAX_SAMPLES,
+            "too many samples"
+        );
+        if !self.net.cfg.serve_blocks {
+            return Ok(rpc::sample_payload::Resp(None));
+        }
+        let sampled = match self.net.sampled_payloads.get(req.number) {
+            Some(sampled) => sampled,
+            None => {
+                let Some(block) = self.net.block_store.block(ctx, req.number).await? else {
+                    return Ok(rpc::sample_payload::Resp(None));
+                };
+                // Samples are verifiable only against the payload root.
+                if block.header().payload_root.is_none() {
+                    return Ok(rpc::sample_payload::Resp(None));
+                }
+                // Reading the block and hashing the whole payload is charged as serving
+                // the whole payload, so that the samples don't amplify the cost of a request.
+                let size = block.payload.0.len();
+                bandwidth::consume(ctx, self.budget, &self.net.serve_budget(), size).await?;
+                let sampled = Arc::new(SampledPayload {
+                    tree: block.payload.tree(),
+                    payload: block.payload,
+                });
+                self.net
+                    .sampled_payloads
+                    .insert(req.number, sampled.clone());
+                sampled
+            }
+        };
+        let chunks = sampled.payload.chunks();
+        let indices: Vec<_> = req
+            .samples
+            .iter()
+            .map(|s| (s % chunks.len() as u64) as usize)
+            .collect();
+        let size = indices.iter().map(|i| chunks[*i].len()).sum();
+        bandwidth::consume(ctx, self.budget, &self.net.serve_budget(), size).await?;
+        let proofs = sampled.tree.chunk_proofs(&indices).unwrap();
+        Ok(rpc::sample_payload::Resp(Some(
+            rpc::sample_payload::Samples(
+                indices
+                    .iter()
+                    .zip(proofs)
+                    .map(|(i, proof)| io::PayloadSample {
+                        chunk: chunks[*i].to_vec(),
+                        proof,
+                    })
+                    .collect(),
+            ),
+        )))
+    }
+}
+
+impl Network {
+    /// Signature over the served block, if relay authentication is enabled.
+    fn relay_sig(&self, block: &validator::FinalBlock) -> Option<node::Signature> {
+        match self.cfg.gossip.relay_auth {
+            RelayAuth::Sign | RelayAuth::Require => Some(
+                self.cfg
+                    .gossip
+                    .key
+                    .sign_msg(node::RelayedBlock {
+                        genesis: self.genesis().hash(),
+                        block: block.clone(),
+                    })
+                    .sig,
+            ),
+            RelayAuth::Disabled => None,
+        }
     }
 }
 
 impl Network {
     /// Manages lifecycle of a single connection.
+    /// `protocol_version` is the latest protocol version supported by the peer.
     async fn run_stream(
         &self,
         ctx: &ctx::Ctx,
         peer: &node::PublicKey,
-        stream: noise::Stream,
+        protocol_version: validator::ProtocolVersion,
+        mut stream: noise::Stream,
     ) -> anyhow::Result<()> {
-        let push_validator_addrs_client = rpc::Client::<rpc::push_validator_addrs::Rpc>::new(
-            ctx,
-            self.cfg.rpc.push_validator_addrs_rate,
-        );
+        let metrics_peer = self.cfg.per_peer_metrics.then(|| metrics::key_label(peer));
+        if let Some(label) = &metrics_peer {
+            stream.set_metrics_peer(label.clone(), ctx.now_utc());
+        }
+        let rpc = &self.rpc_cfg();
+        let push_validator_addrs_client =
+            rpc::Client::<rpc::push_validator_addrs::Rpc>::new(ctx, rpc.push_validator_addrs_rate);
         let push_validator_addrs_server = PushValidatorAddrsServer(self);
         let push_block_store_state_client = rpc::Client::<rpc::push_block_store_state::Rpc>::new(
             ctx,
-            self.cfg.rpc.push_block_store_state_rate,
+            rpc.push_block_store_state_rate,
         );
         let push_block_store_state_server = PushBlockStoreStateServer { peer, net: self };
+        let push_batch_votes_client =
+            rpc::Client::<rpc::push_batch_votes::Rpc>::new(ctx, rpc::push_batch_votes::RATE);
+        let push_upgrade_votes_client =
+            rpc::Client::<rpc::push_upgrade_votes::Rpc>::new(ctx, rpc::push_upgrade_votes::RATE);
+        let pex_client = rpc::Client::<rpc::pex::Rpc>::new(ctx, rpc::pex::RATE);
+        let push_high_qc_client =
+            rpc::Client::<rpc::push_high_qc::Rpc>::new(ctx, rpc::push_high_qc::RATE);
+        let push_session_ticket_client =
+            rpc::Client::<rpc::push_session_ticket::Rpc>::new(ctx, rpc::push_session_ticket::RATE);
+        let serve_budget = bandwidth::Budget::new(
+            ctx,
+            self.reloadable
+                .subscribe()
+                .borrow()
+                .serve_blocks_bandwidth_per_peer,
+        );
 
+        let peer_version = Arc::new(protocol_version);
+        self.peer_versions
+            .insert(peer.clone(), peer_version.clone());
         let get_block_client = Arc::new(rpc::Client::<rpc::get_block::Rpc>::new(
             ctx,
-            self.cfg.rpc.get_block_rate,
+            rpc.get_block_rate,
         ));
         self.get_block_clients
             .insert(peer.clone(), get_block_client.clone());
+        let get_block_chunk_client = Arc::new(rpc::Client::<rpc::get_block_chunk::Rpc>::new(
+            ctx,
+            rpc.get_block_chunk_rate,
+        ));
+        self.get_block_chunk_clients
+            .insert(peer.clone(), get_block_chunk_client.clone());
+        let get_headers_client = Arc::new(rpc::Client::<rpc::get_headers::Rpc>::new(
+            ctx,
+            rpc::get_headers::RATE,
+        ));
+        self.get_headers_clients
+            .insert(peer.clone(), get_headers_client.clone());
+        let sample_payload_client = Arc::new(rpc::Client::<rpc::sample_payload::Rpc>::new(
+            ctx,
+            rpc::sample_payload::RATE,
+        ));
+        self.sample_payload_clients
+            .insert(peer.clone(), sample_payload_client.clone());
+        let relay_client = Arc::new(rpc::Client::<rpc::relay_consensus::Rpc>::new(
+            ctx,
+            rpc.consensus_rate,
+        ));
+        self.relay_clients
+            .insert(peer.clone(), relay_client.clone());
+        let (relay_forward, relay_queue) = ctx::channel::bounded(relay::FORWARD_QUEUE_SIZE);
+        let push_topic_client = Arc::new(rpc::Client::<rpc::push_topic::Rpc>::new(
+            ctx,
+            rpc::push_topic::RATE,
+        ));
+        self.cfg
+            .topics
+            .clients()
+            .insert(peer.clone(), push_topic_client.clone());
 
         let res = scope::run!(ctx, |ctx, s| async {
             let mut service = rpc::Service::new()
+                .keepalive(self.cfg.keepalive)
+                .dump(dump::Dumper::new(
+                    peer.encode(),
+                    self.cfg.monitor.wire_dumps().clone(),
+                ))
+                .metrics_peer(metrics_peer)
                 .add_client(&push_validator_addrs_client)
-                .add_server(
+                .add_limited_server(
                     push_validator_addrs_server,
-                    self.cfg.rpc.push_validator_addrs_rate,
+                    rpc.push_validator_addrs_server_rate,
                 )
                 .add_client(&push_block_store_state_client)
-                .add_server(
+                .add_limited_server(
                     push_block_store_state_server,
-                    self.cfg.rpc.push_block_store_state_rate,
+                    rpc.push_block_store_state_server_rate,
                 )
+                // `get_block` is still used for the peers which don't support `get_block_chunk`.
                 .add_client(&get_block_client)
-                .add_server(&*self.block_store, self.cfg.rpc.get_block_rate)
-                .add_server(rpc::ping::Server, rpc::ping::RATE);
+                .add_limited_server(
+                    GetBlockServer {
+                        net: self,
+                        budget: &serve_budget,
+                        peer_version: protocol_version,
+                    },
+                    rpc.get_block_server_rate,
+                )
+                .add_client(&get_block_chunk_client)
+                .add_server(
+                    GetBlockChunkServer {
+                        net: self,
+                        budget: &serve_budget,
+                        peer_version: protocol_version,
+                    },
+                    rpc.get_block_chunk_rate,
+                )
+                .add_client(&get_headers_client)
+                .add_server(
+                    GetHeadersServer(self, protocol_version),
+                    rpc::get_headers::RATE,
+                )
+                .add_client(&sample_payload_client)
+                .add_server(
+                    SamplePayloadServer {
+                        net: self,
+                        budget: &serve_budget,
+                    },
+                    rpc::sample_payload::RATE,
+                )
+                .add_client(&push_batch_votes_client)
+                .add_server(PushBatchVotesServer(self), rpc::push_batch_votes::RATE)
+                .add_client(&push_upgrade_votes_client)
+                .add_server(PushUpgradeVotesServer(self), rpc::push_upgrade_votes::RATE)
+                .add_client(&pex_client)
+                .add_server(PexServer { net: self, peer }, rpc::pex::RATE)
+                .add_client(&push_high_qc_client)
+                .add_server(PushHighQcServer(self), rpc::push_high_qc::RATE)
+                .add_client(&relay_client)
+                .add_limited_server(
+                    relay::RelayConsensusServer {
+                        net: self,
+                        forward: relay_forward,
+                    },
+                    rpc::relay_consensus::RATE,
+                )
+                .add_client(&push_topic_client)
+                .add_server(
+                    topics::PushTopicServer {
+                        topics: &self.cfg.topics,
+                        peer,
+                        limiters: Mutex::default(),
+                    },
+                    rpc::push_topic::RATE,
+                )
+                .add_client(&push_session_ticket_client)
+                .add_server(
+                    rpc::push_session_ticket::Server {
+                        tickets: &self.tickets,
+                        peer,
+                    },
+                    rpc::push_session_ticket::RATE,
+                )
+                .add_server(rpc::ping::Server(&self.cfg.time_source), rpc::ping::RATE);
+            let traffic = service.traffic().clone();
+            let _registered = self.cfg.monitor.traffic().register(peer, &traffic);
+
+            // Disconnect the peer if it exceeds its bandwidth cap.
+            if let Some(cap) = self.cfg.gossip.peer_bandwidth_cap {
+                s.spawn(async { traffic.enforce_cap(ctx, cap).await });
+            }
+
+            // Forward the consensus messages relayed by the peer.
+            s.spawn_bg(async {
+                let _ = self.forward_relayed(ctx, peer, relay_queue).await;
+                Ok(())
+            });
+
+            // Issue session tickets to the peer.
+            s.spawn_bg(async {
+                let _ = push_session_ticket_client
+                    .push_loop(ctx, &self.tickets, peer)
+                    .await;
+                Ok(())
+            });
 
             if let Some(ping_timeout) = &self.cfg.ping_timeout {
                 let ping_client = rpc::Client::<rpc::ping::Rpc>::new(ctx, rpc::ping::RATE);
                 service = service.add_client(&ping_client);
                 s.spawn(async {
                     let ping_client = ping_client;
-                    ping_client.ping_loop(ctx, *ping_timeout).await
+                    ping_client
+                        .ping_loop(ctx, &self.cfg.time_source, *ping_timeout, |sample| {
+                            self.cfg.monitor.pings().record(
+                                peer,
+                                sample,
+                                self.cfg.time_source.now_utc(ctx),
+                            );
+                            self.sender.send(
+                                io::SyncBlocksRequest::UpdatePeerRtt {
+                                    peer: peer.clone(),
+                                    rtt: sample.rtt,
+                                }
+                                .into(),
+                            );
+                        })
+                        .await
                 });
             }
 
+            // Exchange the known node addresses with the peer.
+            s.spawn::<()>(async {
+                loop {
+                    let mut addrs = self.address_book.sample(&mut ctx.rng(), PEX_SAMPLE_SIZE);
+                    addrs.push(Arc::new(self.cfg.gossip.key.sign_msg(node::NodeAddr {
+                        addr: self.public_addr.get(),
+                        timestamp: self.cfg.time_source.now_utc(ctx),
+                    })));
+                    pex_client.call(ctx, &rpc::pex::Req(addrs), kB).await?;
+                    ctx.sleep(rpc::pex::INTERVAL).await?;
+                }
+            });
+
             // Push block store state updates to peer.
+            // A node which doesn't serve blocks doesn't advertise any.
+            // Head changes are pushed immediately, other changes are batched,
+            // see `PushSchedule`.
+            if self.cfg.serve_blocks {
+                s.spawn::<()>(async {
+                    let schedule = &self.cfg.rpc.push_block_store_state_schedule;
+                    let mut sub = self.block_store.subscribe();
+                    sub.mark_changed();
+                    let mut head = None;
+                    let mut idle = 0;
+                    loop {
+                        let head_changed = sync::changed(ctx, &mut sub)
+                            .await?
+                            .last
+                            .as_ref()
+                            .map(|qc| qc.header().number)
+                            != head;
+                        if head_changed {
+                            idle = 0;
+                        } else {
+                            ctx.sleep(schedule.delay(&mut ctx.rng(), idle)).await?;
+                            idle += 1;
+                        }
+                        // Push the most recent state, including the changes made while waiting.
+                        let state = sub.borrow_and_update().clone();
+                        let new_head = state.last.as_ref().map(|qc| qc.header().number);
+                        let req = rpc::push_block_store_state::Req(state);
+                        match push_block_store_state_client.call(ctx, &req, kB).await {
+                            // Retry with the most recent state.
+                            Err(err) if rpc::is_rate_limited(&err) => sub.mark_changed(),
+                            res => {
+                                res?;
+                                head = new_head;
+                            }
+                        }
+                    }
+                });
+            }
+
+            // Push the highest known CommitQC to peer.
             s.spawn::<()>(async {
-                let mut sub = self.block_store.subscribe();
+                let mut sub = self.high_qc.subscribe();
                 sub.mark_changed();
                 loop {
-                    let state = sync::changed(ctx, &mut sub).await?.clone();
-                    let req = rpc::push_block_store_state::Req(state);
-                    push_block_store_state_client.call(ctx, &req, kB).await?;
+                    let Some(msg) = sync::changed(ctx, &mut sub).await?.clone() else {
+                        continue;
+                    };
+                    let req = rpc::push_high_qc::Req(msg);
+                    push_high_qc_client.call(ctx, &req, kB).await?;
                 }
             });
 
@@ -145,58 +688,299 @@ impl Network {
                     if diff.is_empty() {
                         continue;
                     }
-                    old = new;
                     let req = rpc::push_validator_addrs::Req(diff);
-                    push_validator_addrs_client.call(ctx, &req, kB).await?;
+                    match push_validator_addrs_client.call(ctx, &req, kB).await {
+                        // Retry with the most recent diff.
+                        Err(err) if rpc::is_rate_limited(&err) => sub.mark_changed(),
+                        res => {
+                            res?;
+                            old = new;
+                        }
+                    }
                 }
             });
 
+            s.spawn::<()>(async {
+                // Push batch votes updates to peer.
+                let mut old = BatchVotes::default();
+                let mut sub = self.batch_votes.subscribe();
+                sub.mark_changed();
+                loop {
+                    let new = sync::changed(ctx, &mut sub).await?.clone();
+                    let diff = new.get_newer(&old);
+                    if diff.is_empty() {
+                        continue;
+                    }
+                    let req = rpc::push_batch_votes::Req(diff);
+                    match push_batch_votes_client.call(ctx, &req, kB).await {
+                        // Retry with the most recent diff.
+                        Err(err) if rpc::is_rate_limited(&err) => sub.mark_changed(),
+                        res => {
+                            res?;
+                            old = new;
+                        }
+                    }
+                }
+            });
+
+            s.spawn::<()>(async {
+                // Push protocol upgrade announcements to peer.
+                let mut old = UpgradeVotes::default();
+                let mut sub = self.upgrade_votes.subscribe();
+                sub.mark_changed();
+                loop {
+                    let new = sync::changed(ctx, &mut sub).await?.clone();
+                    let diff = new.get_newer(&old);
+                    if diff.is_empty() {
+                        continue;
+                    }
+                    let req = rpc::push_upgrade_votes::Req(diff);
+                    match push_upgrade_votes_client.call(ctx, &req, kB).await {
+                        // Retry with the most recent diff.
+                        Err(err) if rpc::is_rate_limited(&err) => sub.mark_changed(),
+                        res => {
+                            res?;
+                            old = new;
+                        }
+                    }
+                }
+            });
+
+            #[cfg(any(test, feature = "chaos"))]
+            let service = service.filter(
+                self.partition
+                    .filter(crate::partition::Peer::Node(peer.clone())),
+            );
+            #[cfg(feature = "chaos")]
+            let service = service.chaos(self.cfg.chaos.clone());
             service.run(ctx, stream).await?;
             Ok(())
         })
         .await;
 
+        self.peer_versions.remove(peer.clone(), peer_version);
         self.get_block_clients
             .remove(peer.clone(), get_block_client);
+        self.get_block_chunk_clients
+            .remove(peer.clone(), get_block_chunk_client);
+        self.get_headers_clients
+            .remove(peer.clone(), get_headers_client);
+        self.sample_payload_clients
+            .remove(peer.clone(), sample_payload_client);
+        self.relay_clients.remove(peer.clone(), relay_client);
+        self.cfg
+            .topics
+            .clients()
+            .remove(peer.clone(), push_topic_client);
+        self.cfg.monitor.pings().remove(peer);
         res
     }
 
     /// Handles an inbound stream.
     /// Closes the stream if there is another inbound stream opened from the same peer.
+    #[tracing::instrument(level = "info", name = "gossip", skip_all, fields(peer))]
     pub(crate) async fn run_inbound_stream(
         &self,
         ctx: &ctx::Ctx,
         mut stream: noise::Stream,
     ) -> anyhow::Result<()> {
-        let peer =
-            handshake::inbound(ctx, &self.cfg.gossip, self.genesis().hash(), &mut stream).await?;
+        let res = handshake::inbound(
+            ctx,
+            &self.gossip_cfg(),
+            self.genesis().hash(),
+            &self.tickets,
+            &mut stream,
+        )
+        .await;
+        if let Err(handshake::Error::GenesisMismatch { peer, genesis }) = &res {
+            self.quarantine.insert(ctx, peer.clone(), *genesis);
+        }
+        let accepted = res?;
+        let peer = accepted.peer;
         tracing::Span::current().record("peer", tracing::field::debug(&peer));
         self.inbound.insert(peer.clone()).await?;
-        let res = self.run_stream(ctx, &peer, stream).await;
+        let res = self
+            .run_stream(ctx, &peer, accepted.protocol_version, stream)
+            .await;
         self.inbound.remove(&peer).await;
+        if let Some(penalty) = res
+            .as_ref()
+            .err()
+            .and_then(address_book::misbehavior_penalty)
+        {
+            self.address_book.penalize(&peer, penalty);
+        }
         res
     }
 
     /// Connects to a peer and handles the resulting stream.
+    #[tracing::instrument(level = "info", name = "gossip", skip_all, fields(peer = ?peer))]
     pub(crate) async fn run_outbound_stream(
         &self,
         ctx: &ctx::Ctx,
         peer: &node::PublicKey,
         addr: std::net::SocketAddr,
     ) -> anyhow::Result<()> {
-        let mut stream = preface::connect(ctx, addr, preface::Endpoint::GossipNet).await?;
-        handshake::outbound(
+        if let Some(q) = self.quarantine.get(ctx, peer) {
+            anyhow::bail!(
+                "peer quarantined until {:?} (genesis {:?})",
+                q.until,
+                q.genesis
+            );
+        }
+        let mut stream =
+            preface::connect(ctx, &self.cfg.transport, addr, preface::Endpoint::GossipNet).await?;
+        let res = handshake::outbound(
             ctx,
-            &self.cfg.gossip,
+            &self.gossip_cfg(),
             self.genesis().hash(),
+            &self.tickets,
             &mut stream,
             peer,
         )
-        .await?;
+        .await;
+        if let Err(handshake::Error::GenesisMismatch { genesis, .. }) = &res {
+            self.quarantine.insert(ctx, peer.clone(), *genesis);
+            self.log_peer_genesis(ctx, peer, genesis, addr).await;
+        }
+        let accepted = res?;
 
         self.outbound.insert(peer.clone()).await?;
-        let res = self.run_stream(ctx, peer, stream).await;
+        self.reconnect
+            .connected(ctx, &OutboundPeer::Gossip(peer.clone()));
+        self.address_book.connected(peer);
+        // Only the static peers are trusted to report the address: the dynamic ones
+        // are learned from the address book, so an attacker could control many of them.
+        let is_static = self
+            .reloadable
+            .subscribe()
+            .borrow()
+            .gossip_static_outbound
+            .contains_key(peer);
+        if let (Some(addr), true) = (accepted.observed_addr, is_static) {
+            self.public_addr.report(peer, addr.ip());
+        }
+        let res = self
+            .run_stream(ctx, peer, accepted.protocol_version, stream)
+            .await;
+        self.public_addr.forget(peer);
         self.outbound.remove(peer).await;
         res
     }
+
+    /// Maintains the connections to the `gossip_static_outbound` peers.
+    /// Connections are established and dropped as the peers are added to
+    /// and removed from the config.
+    pub(crate) async fn run_static_outbound(&self, ctx: &ctx::Ctx) {
+        let _: ctx::OrCanceled<()> = scope::run!(ctx, |ctx, s| async {
+            let mut sub = self.reloadable.subscribe();
+            let mut spawned = HashSet::new();
+            loop {
+                let peers: Vec<_> = sub
+                    .borrow_and_update()
+                    .gossip_static_outbound
+                    .keys()
+                    .cloned()
+                    .collect();
+                for peer in peers {
+                    if spawned.insert(peer.clone()) {
+                        s.spawn(async move { self.maintain_static_outbound(ctx, &peer).await });
+                    }
+                }
+                sync::changed(ctx, &mut sub).await?;
+            }
+        })
+        .await;
+    }
+
+    /// Maintains the connection to the static outbound `peer`, while it is in the config.
+    async fn maintain_static_outbound(
+        &self,
+        ctx: &ctx::Ctx,
+        peer: &node::PublicKey,
+    ) -> ctx::OrCanceled<()> {
+        let mut sub = self.reloadable.subscribe();
+        let target = OutboundPeer::Gossip(peer.clone());
+        loop {
+            let addr = *sync::wait_for(ctx, &mut sub, |c| {
+                c.gossip_static_outbound.contains_key(peer)
+            })
+            .await?
+            .gossip_static_outbound
+            .get(peer)
+            .unwrap();
+            // The connection is dropped as soon as the address of the peer changes.
+            scope::run!(ctx, |ctx, s| async {
+                s.spawn_bg(async {
+                    let _ = self
+                        .reconnect
+                        .maintain(ctx, &target, || self.run_outbound_stream(ctx, peer, addr))
+                        .await;
+                    Ok(())
+                });
+                sync::wait_for(ctx, &mut sub, |c| {
+                    c.gossip_static_outbound.get(peer) != Some(&addr)
+                })
+                .await?;
+                Ok(())
+            })
+            .await?;
+            if !sub.borrow().gossip_static_outbound.contains_key(peer) {
+                self.reconnect.remove(&target);
+            }
+        }
+    }
+
+    /// Maintains up to `dynamic_outbound_limit` outbound connections to the peers
+    /// from the address book, preferring the peers with the best connection record.
+    pub(crate) async fn run_dialer(&self, ctx: &ctx::Ctx) {
+        let own_key = self.cfg.gossip.key.public();
+        let dialing = Mutex::new(HashSet::new());
+        let _: ctx::OrCanceled<()> = scope::run!(ctx, |ctx, s| async {
+            for _ in 0..self.cfg.gossip.dynamic_outbound_limit {
+                s.spawn::<()>(async {
+                    loop {
+                        let static_outbound = self
+                            .reloadable
+                            .subscribe()
+                            .borrow()
+                            .gossip_static_outbound
+                            .clone();
+                        let outbound = self.outbound.subscribe().borrow().current().clone();
+                        let inbound = self.inbound.subscribe().borrow().current().clone();
+                        let addr = {
+                            let mut dialing = dialing.lock().unwrap();
+                            let addr = self.address_book.best(&mut ctx.rng(), |key| {
+                                key != &own_key
+                                    && !static_outbound.contains_key(key)
+                                    && !outbound.contains(key)
+                                    && !inbound.contains(key)
+                                    && !dialing.contains(key)
+                            });
+                            if let Some(addr) = &addr {
+                                dialing.insert(addr.key.clone());
+                            }
+                            addr
+                        };
+                        let Some(addr) = addr else {
+                            ctx.sleep(DIAL_INTERVAL).await?;
+                            continue;
+                        };
+                        let res = self
+                            .run_outbound_stream(ctx, &addr.key, addr.msg.addr)
+                            .await;
+                        dialing.lock().unwrap().remove(&addr.key);
+                        if let Err(err) = res {
+                            tracing::info!("gossip.run_outbound_stream({:?}): {err:#}", addr.key);
+                            self.address_book
+                                .penalize(&addr.key, address_book::penalty(&err));
+                            ctx.sleep(DIAL_INTERVAL).await?;
+                        }
+                    }
+                });
+            }
+            Ok(())
+        })
+        .await;
+    }
 }
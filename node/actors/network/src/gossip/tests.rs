@@ -1,5 +1,5 @@
 use super::*;
-use crate::{io, metrics, preface, rpc, testonly};
+use crate::{frame, io, metrics, preface, rpc, testonly, transport, PublicAddrDetection};
 use assert_matches::assert_matches;
 use pretty_assertions::assert_eq;
 use rand::Rng;
@@ -10,12 +10,17 @@ use std::{
 use test_casing::{test_casing, Product};
 use tracing::Instrument as _;
 use zksync_concurrency::{
-    ctx, oneshot, scope, sync,
+    ctx, limiter, oneshot, scope, sync,
     testonly::{abort_on_panic, set_timeout},
     time,
 };
-use zksync_consensus_roles::validator::{self, BlockNumber, FinalBlock};
+use zksync_consensus_crypto::ByteFmt as _;
+use zksync_consensus_roles::{
+    attester,
+    validator::{self, BlockNumber, FinalBlock},
+};
 use zksync_consensus_storage::testonly::new_store;
+use zksync_protobuf::{kB, ProtoFmt as _};
 
 #[tokio::test]
 async fn test_one_connection_per_node() {
@@ -46,15 +51,23 @@ async fn test_one_connection_per_node() {
         let (peer, addr) = cfgs[0].gossip.static_outbound.iter().next().unwrap();
         let mut stream = preface::connect(
             ctx,
+            &cfgs[0].transport,
             *addr,
             preface::Endpoint::GossipNet,
         )
         .await
         .context("preface::connect")?;
 
-        handshake::outbound(ctx, &cfgs[0].gossip, setup.genesis.hash(), &mut stream, peer)
-            .await
-            .context("handshake::outbound")?;
+        handshake::outbound(
+            ctx,
+            &cfgs[0].gossip,
+            setup.genesis.hash(),
+            &Tickets::new("gossip", &cfgs[0].gossip.key, None),
+            &mut stream,
+            peer,
+        )
+        .await
+        .context("handshake::outbound")?;
         tracing::info!("The connection is expected to be closed automatically by peer.");
         // The multiplexer runner should exit gracefully.
         let _ = rpc::Service::new().run(ctx, stream).await;
@@ -68,6 +81,76 @@ async fn test_one_connection_per_node() {
     .unwrap();
 }
 
+#[tokio::test]
+async fn test_reload_static_outbound() {
+    abort_on_panic();
+    let _guard = set_timeout(time::Duration::seconds(20));
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let setup = validator::testonly::Setup::new(rng, 2);
+    let cfgs = testonly::new_configs(rng, &setup, 0);
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let nodes: Vec<_> = cfgs
+            .iter()
+            .enumerate()
+            .map(|(i, cfg)| {
+                let (node, runner) = testonly::Instance::new(ctx, cfg.clone(), store.clone());
+                s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node", i)));
+                node
+            })
+            .collect();
+        let peer = cfgs[1].gossip.key.public();
+        let mut reloadable = cfgs[0].reloadable();
+
+        tracing::info!("Add a static peer.");
+        reloadable
+            .gossip_static_outbound
+            .insert(peer.clone(), *cfgs[1].server_addr);
+        nodes[0].state().reload(ctx, reloadable.clone()).await;
+        sync::wait_for(ctx, &mut nodes[0].net.gossip.outbound.subscribe(), |got| {
+            got.current().contains(&peer)
+        })
+        .await?;
+
+        tracing::info!("Remove the static peer.");
+        reloadable.gossip_static_outbound.clear();
+        nodes[0].state().reload(ctx, reloadable).await;
+        nodes[0].wait_for_gossip_disconnect(ctx, &peer).await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_reload_rpc_rates() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let setup = validator::testonly::Setup::new(rng, 1);
+    let cfgs = testonly::new_configs(rng, &setup, 0);
+    let mut reloadable = cfgs[0].reloadable();
+    reloadable.rpc.get_block_server_rate = limiter::Rate {
+        burst: rng.gen_range(1..10),
+        refresh: time::Duration::milliseconds(rng.gen_range(1..1000)),
+    };
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let (node, runner) = testonly::Instance::new(ctx, cfgs[0].clone(), store.clone());
+        s.spawn_bg(runner.run(ctx));
+        node.state().reload(ctx, reloadable.clone()).await;
+        assert_eq!(reloadable.rpc, node.net.gossip.rpc_cfg());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
 fn mk_addr<R: Rng>(rng: &mut R) -> std::net::SocketAddr {
     std::net::SocketAddr::new(std::net::IpAddr::from(rng.gen::<[u8; 16]>()), rng.gen())
 }
@@ -143,7 +226,16 @@ async fn test_validator_addrs() {
     let rng = &mut ctx::test_root(&ctx::RealClock).rng();
 
     let keys: Vec<validator::SecretKey> = (0..8).map(|_| rng.gen()).collect();
-    let validators = validator::ValidatorSet::new(keys.iter().map(|k| k.public())).unwrap();
+    let genesis = validator::Genesis {
+        validators: validator::ValidatorSet::new(keys.iter().map(|k| k.public())).unwrap(),
+        fork: rng.gen(),
+        max_payload_size: None,
+        key_rotations: validator::KeyRotations::default(),
+        attesters: None,
+        protocol_version: validator::ProtocolVersion::EARLIEST,
+        protocol_upgrades: validator::ProtocolUpgrades::default(),
+        signature_scheme: validator::SignatureScheme::Bn254,
+    };
     let va = ValidatorAddrsWatch::default();
     let mut sub = va.subscribe();
 
@@ -152,7 +244,7 @@ async fn test_validator_addrs() {
     for k in &keys[0..6] {
         want.insert(random_netaddr(rng, k));
     }
-    va.update(&validators, &want.as_vec()).await.unwrap();
+    va.update(&genesis, &want.as_vec()).await.unwrap();
     assert_eq!(want.0, sub.borrow_and_update().0);
 
     // Update values.
@@ -193,7 +285,7 @@ async fn test_validator_addrs() {
         // no entry at all for keys[7]
         k8v1.clone(),
     ];
-    va.update(&validators, &update).await.unwrap();
+    va.update(&genesis, &update).await.unwrap();
     assert_eq!(want.0, sub.borrow_and_update().0);
 
     // Invalid signature.
@@ -204,11 +296,11 @@ async fn test_validator_addrs() {
         mk_timestamp(rng),
     );
     k0v3.key = keys[0].public();
-    assert!(va.update(&validators, &[Arc::new(k0v3)]).await.is_err());
+    assert!(va.update(&genesis, &[Arc::new(k0v3)]).await.is_err());
     assert_eq!(want.0, sub.borrow_and_update().0);
 
     // Duplicate entry in the update.
-    assert!(va.update(&validators, &[k8v1.clone(), k8v1]).await.is_err());
+    assert!(va.update(&genesis, &[k8v1.clone(), k8v1]).await.is_err());
     assert_eq!(want.0, sub.borrow_and_update().0);
 }
 
@@ -260,6 +352,394 @@ async fn test_validator_addrs_propagation() {
     .unwrap();
 }
 
+/// The highest CommitQC is propagated over the gossip network
+/// and passed to the consensus of the validators.
+#[tokio::test]
+async fn test_high_qc_propagation() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::AffineClock::new(40.));
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 5);
+    setup.push_blocks(rng, 1);
+    let cfgs = testonly::new_configs(rng, &setup, 1);
+    let qc = setup.blocks[0].justification.clone();
+    let leader = setup.genesis.view_leader(qc.view().number);
+    let key = setup.keys.iter().find(|k| k.public() == leader).unwrap();
+    let want = Arc::new(key.sign_msg(validator::LeaderCommit { justification: qc }));
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let mut nodes: Vec<_> = cfgs
+            .iter()
+            .enumerate()
+            .map(|(i, cfg)| {
+                let (node, runner) = testonly::Instance::new(ctx, cfg.clone(), store.clone());
+                s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node", i)));
+                node
+            })
+            .collect();
+        assert!(nodes[0]
+            .net
+            .gossip
+            .high_qc
+            .update(&setup.genesis, &want)
+            .unwrap());
+        for (i, node) in nodes.iter().enumerate() {
+            tracing::info!("awaiting for node[{i}] to learn the high QC");
+            let sub = &mut node.net.gossip.high_qc.subscribe();
+            sync::wait_for(ctx, sub, |got| got.as_ref() == Some(&want)).await?;
+        }
+        loop {
+            match nodes[1].pipe.recv.recv(ctx).await? {
+                io::OutputMessage::Consensus(req) => {
+                    let got: validator::Signed<validator::LeaderCommit> = req.msg.cast().unwrap();
+                    assert_eq!(want.as_ref(), &got);
+                    req.ack.send(()).ok();
+                    break;
+                }
+                io::OutputMessage::SyncBlocks(io::SyncBlocksRequest::UpdatePeerSyncState {
+                    response,
+                    ..
+                }) => {
+                    response.send(()).ok();
+                }
+                io::OutputMessage::SyncBlocks(_) => {}
+            }
+        }
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+/// Votes of the attesters should be gossiped to all the nodes,
+/// so that every node aggregates them into a batch certificate.
+#[tokio::test]
+async fn test_batch_votes_propagation() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::AffineClock::new(40.));
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 5);
+    let attester_keys = setup.add_attesters(rng, 5);
+    setup.push_blocks(rng, 1);
+    let cfgs = testonly::new_configs(rng, &setup, 1);
+    let batch = attester::Batch::new(
+        &setup.genesis,
+        attester::BatchNumber(0),
+        setup.blocks[0].header(),
+    );
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let nodes: Vec<_> = cfgs
+            .iter()
+            .enumerate()
+            .map(|(i, cfg)| {
+                let (node, runner) = testonly::Instance::new(ctx, cfg.clone(), store.clone());
+                s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node", i)));
+                node
+            })
+            .collect();
+        // Votes of an attester outside of the committee are ignored.
+        let outsider: attester::SecretKey = rng.gen();
+        nodes[0]
+            .net
+            .push_batch_vote(Arc::new(outsider.sign_msg(batch.clone())))
+            .await?;
+        assert!(nodes[0]
+            .net
+            .gossip
+            .batch_votes
+            .subscribe()
+            .borrow()
+            .votes
+            .is_empty());
+        for (node, key) in nodes.iter().zip(&attester_keys) {
+            node.net
+                .push_batch_vote(Arc::new(key.sign_msg(batch.clone())))
+                .await?;
+        }
+        for (i, node) in nodes.iter().enumerate() {
+            tracing::info!("awaiting for node[{i}] to aggregate the batch certificate");
+            let sub = &mut node.net.subscribe_batch_qc();
+            let qc = sync::wait_for(ctx, sub, |qc| qc.is_some())
+                .await?
+                .clone()
+                .unwrap();
+            assert_eq!(batch, qc.message);
+            qc.verify(&setup.genesis).unwrap();
+        }
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+/// A vote on a newer batch shouldn't withdraw the vote of the attester on a batch
+/// which is still gathering a quorum.
+#[tokio::test]
+async fn test_batch_votes_window() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 1);
+    let keys = setup.add_attesters(rng, 5);
+    setup.push_blocks(rng, 2);
+    let batches: Vec<_> = (0..2)
+        .map(|i| {
+            attester::Batch::new(
+                &setup.genesis,
+                attester::BatchNumber(i),
+                setup.blocks[i as usize].header(),
+            )
+        })
+        .collect();
+    let threshold = setup.genesis.attesters.as_ref().unwrap().threshold();
+    let vote =
+        |key: &attester::SecretKey, batch: &attester::Batch| Arc::new(key.sign_msg(batch.clone()));
+
+    let watch = batch_votes::BatchVotesWatch::default();
+    // All but one of the attesters needed for a quorum vote on both batches.
+    for key in &keys[..threshold - 1] {
+        watch
+            .update(
+                &setup.genesis,
+                &[vote(key, &batches[0]), vote(key, &batches[1])],
+            )
+            .await
+            .unwrap();
+    }
+    assert!(watch.subscribe_qc().borrow().is_none());
+    // The missing vote completes the quorum of the lower batch.
+    watch
+        .update(&setup.genesis, &[vote(&keys[threshold - 1], &batches[0])])
+        .await
+        .unwrap();
+    let qc = watch.subscribe_qc().borrow().clone().unwrap();
+    assert_eq!(batches[0], qc.message);
+    qc.verify(&setup.genesis).unwrap();
+    // Only the votes for the batch above the certified one are kept.
+    let votes = watch.subscribe().borrow().clone();
+    assert_eq!(Some(attester::BatchNumber(0)), votes.certified);
+    assert_eq!(
+        threshold - 1,
+        votes.get_newer(&batch_votes::BatchVotes::default()).len()
+    );
+}
+
+/// Protocol upgrades announced by the validators should be gossiped to all the nodes,
+/// so that every node aggregates them into an upgrade certificate.
+#[tokio::test]
+async fn test_upgrade_votes_propagation() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::AffineClock::new(40.));
+    let rng = &mut ctx.rng();
+    let setup = validator::testonly::Setup::new(rng, 4);
+    let cfgs = testonly::new_configs(rng, &setup, 1);
+    let upgrade = validator::ProtocolUpgrade {
+        genesis: setup.genesis.hash(),
+        version: validator::ProtocolVersion::PAYLOAD_ROOT,
+        activation: validator::BlockNumber(setup.genesis.fork.first_block.0 + 10),
+    };
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let nodes: Vec<_> = cfgs
+            .iter()
+            .enumerate()
+            .map(|(i, cfg)| {
+                let (node, runner) = testonly::Instance::new(ctx, cfg.clone(), store.clone());
+                s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node", i)));
+                node
+            })
+            .collect();
+        // Announcements of a key outside of the validator set are ignored.
+        let outsider: validator::SecretKey = rng.gen();
+        nodes[0]
+            .net
+            .push_upgrade_vote(Arc::new(outsider.sign_msg(upgrade.clone())))
+            .await?;
+        assert!(nodes[0]
+            .net
+            .gossip
+            .upgrade_votes
+            .subscribe()
+            .borrow()
+            .0
+            .is_empty());
+        for (node, key) in nodes.iter().zip(&setup.keys) {
+            node.net
+                .push_upgrade_vote(Arc::new(key.sign_msg(upgrade.clone())))
+                .await?;
+        }
+        for (i, node) in nodes.iter().enumerate() {
+            tracing::info!("awaiting for node[{i}] to aggregate the upgrade certificate");
+            let sub = &mut node.net.subscribe_upgrade_qc();
+            let qc = sync::wait_for(ctx, sub, |qc| qc.is_some())
+                .await?
+                .clone()
+                .unwrap();
+            assert_eq!(upgrade, qc.message);
+            qc.verify(&setup.genesis).unwrap();
+        }
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+/// Messages of an application-defined topic should be pushed to the peers,
+/// and only the valid ones should be delivered.
+#[tokio::test]
+async fn test_topics() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::AffineClock::new(20.));
+    let rng = &mut ctx.rng();
+    let setup = validator::testonly::Setup::new(rng, 3);
+    let cfgs = testonly::new_configs(rng, &setup, 2);
+    let topic = crate::Topic {
+        name: "headers".to_string(),
+        max_msg_size: kB,
+        rate: limiter::Rate {
+            burst: 10,
+            refresh: time::Duration::ZERO,
+        },
+        queue_size: 10,
+    };
+    let mut invalid: validator::BlockHeader = rng.gen();
+    invalid.number = BlockNumber(0);
+    let mut want: validator::BlockHeader = rng.gen();
+    want.number = BlockNumber(1);
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let mut nodes = vec![];
+        let mut handles = vec![];
+        for (i, cfg) in cfgs.iter().enumerate() {
+            let handle = cfg
+                .topics
+                .register(topic.clone(), |_, h: &validator::BlockHeader| {
+                    anyhow::ensure!(h.number > BlockNumber(0), "invalid header");
+                    Ok(())
+                })
+                .unwrap();
+            // Names are unique within the registry.
+            assert!(cfg
+                .topics
+                .register(topic.clone(), |_, _: &validator::BlockHeader| Ok(()))
+                .is_err());
+            handles.push(handle);
+            let (node, runner) = testonly::Instance::new(ctx, cfg.clone(), store.clone());
+            s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node", i)));
+            nodes.push(node);
+        }
+        let (sender, receivers) = handles.split_first_mut().unwrap();
+        s.spawn_bg(async {
+            // Keep publishing, until all the peers get connected.
+            loop {
+                sender.publish(ctx, &invalid).await?;
+                sender.publish(ctx, &want).await?;
+                ctx.sleep(time::Duration::seconds(1)).await?;
+            }
+        });
+        let want_peer = cfgs[0].gossip.key.public();
+        for (i, r) in receivers.iter_mut().enumerate() {
+            tracing::info!("awaiting for node[{}] to receive the message", i + 1);
+            let (peer, got) = r.recv(ctx).await?;
+            assert_eq!(want_peer, peer);
+            assert_eq!(want, got);
+        }
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[test]
+fn test_relay_replay() {
+    use crate::consensus::replay::Replay;
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let keys: [validator::SecretKey; 2] = rng.gen();
+    let msg = |rng: &mut rand::rngs::StdRng, key: &validator::SecretKey, view| {
+        let mut msg: validator::ReplicaPrepare = rng.gen();
+        msg.view.number = validator::ViewNumber(view);
+        key.sign_msg(validator::ConsensusMsg::ReplicaPrepare(msg))
+    };
+    // Relayed messages are deduplicated even if the replay protection is disabled.
+    let replay = relay::RelayReplay::new(0);
+    let a = msg(rng, &keys[0], 10);
+    assert_eq!(Ok(()), replay.insert(&a));
+    assert_eq!(Err(Replay::Duplicate), replay.insert(&a));
+    // Windows are kept per signer, so one signer can't move the window of another.
+    assert_eq!(Ok(()), replay.insert(&msg(rng, &keys[1], 1000)));
+    assert_eq!(Ok(()), replay.insert(&msg(rng, &keys[0], 11)));
+    // Messages for the views older than the window are not relayed.
+    assert_eq!(Ok(()), replay.insert(&msg(rng, &keys[0], 100)));
+    assert_eq!(Err(Replay::Stale), replay.insert(&msg(rng, &keys[0], 12)));
+}
+
+/// Consensus message should be relayed over the gossip network to a validator
+/// which is not a direct gossip peer of the sender.
+#[tokio::test]
+async fn test_consensus_relay() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::AffineClock::new(20.));
+    let rng = &mut ctx.rng();
+    let setup = validator::testonly::Setup::new(rng, 4);
+    // Nodes form a ring, so node 2 is 2 hops away from node 0.
+    let mut cfgs = testonly::new_configs(rng, &setup, 1);
+    for cfg in &mut cfgs {
+        cfg.consensus_relay_max_hops = 2;
+    }
+    let want = setup.keys[0].sign_msg(rng.gen::<validator::ConsensusMsg>());
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let mut nodes: Vec<_> = cfgs
+            .iter()
+            .enumerate()
+            .map(|(i, cfg)| {
+                let (node, runner) = testonly::Instance::new(ctx, cfg.clone(), store.clone());
+                s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node", i)));
+                node
+            })
+            .collect();
+        for node in &nodes {
+            node.wait_for_gossip_connections().await;
+        }
+        nodes[0]
+            .net
+            .gossip
+            .relay_consensus(ctx, want.clone(), Some(setup.keys[2].public()), None)
+            .await?;
+        loop {
+            match nodes[2].pipe.recv.recv(ctx).await? {
+                io::OutputMessage::Consensus(req) => {
+                    assert_eq!(want, req.msg);
+                    req.ack.send(()).ok();
+                    break;
+                }
+                io::OutputMessage::SyncBlocks(io::SyncBlocksRequest::UpdatePeerSyncState {
+                    response,
+                    ..
+                }) => {
+                    response.send(()).ok();
+                }
+                io::OutputMessage::SyncBlocks(_) => {}
+            }
+        }
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
 #[tokio::test]
 async fn test_genesis_mismatch() {
     abort_on_panic();
@@ -269,7 +749,8 @@ async fn test_genesis_mismatch() {
     let cfgs = testonly::new_configs(rng, &setup, 1);
 
     scope::run!(ctx, |ctx, s| async {
-        let mut listener = cfgs[1].server_addr.bind().context("server_addr.bind()")?;
+        let mut listener = transport::bind(&cfgs[1].transport, &cfgs[1].server_addr)
+            .context("server_addr.bind()")?;
 
         tracing::info!("Start one node, we will simulate the other one.");
         let (store, runner) = new_store(ctx, &setup.genesis).await;
@@ -286,17 +767,24 @@ async fn test_genesis_mismatch() {
             .context("preface::accept()")?;
         assert_eq!(endpoint, preface::Endpoint::GossipNet);
         tracing::info!("Expect the handshake to fail");
-        let res = handshake::inbound(ctx, &cfgs[1].gossip, rng.gen(), &mut stream).await;
-        assert_matches!(res, Err(handshake::Error::GenesisMismatch));
+        let tickets = Tickets::new("gossip", &cfgs[1].gossip.key, None);
+        let res = handshake::inbound(ctx, &cfgs[1].gossip, rng.gen(), &tickets, &mut stream).await;
+        assert_matches!(res, Err(handshake::Error::GenesisMismatch { .. }));
 
         tracing::info!("Try to connect to a node with a mismatching genesis.");
-        let mut stream = preface::connect(ctx, cfgs[0].public_addr, preface::Endpoint::GossipNet)
-            .await
-            .context("preface::connect")?;
+        let mut stream = preface::connect(
+            ctx,
+            &cfgs[1].transport,
+            cfgs[0].public_addr,
+            preface::Endpoint::GossipNet,
+        )
+        .await
+        .context("preface::connect")?;
         let res = handshake::outbound(
             ctx,
             &cfgs[1].gossip,
             rng.gen(),
+            &tickets,
             &mut stream,
             &cfgs[0].gossip.key.public(),
         )
@@ -311,37 +799,287 @@ async fn test_genesis_mismatch() {
     .unwrap();
 }
 
-const EXCHANGED_STATE_COUNT: usize = 5;
-const NETWORK_CONNECTIVITY_CASES: [(usize, usize); 5] = [(2, 1), (3, 2), (5, 3), (10, 4), (10, 7)];
-
-/// Tests block syncing with global network synchronization (a next block becoming available
-/// to all nodes only after all nodes have received previous `SyncState` updates from peers).
-#[test_casing(5, NETWORK_CONNECTIVITY_CASES)]
-#[tokio::test(flavor = "multi_thread")]
-#[tracing::instrument(level = "trace")]
-async fn syncing_blocks(node_count: usize, gossip_peers: usize) {
+#[tokio::test]
+async fn test_genesis_mismatch_quarantine() {
     abort_on_panic();
-    let _guard = set_timeout(time::Duration::seconds(5));
-
-    let ctx = &ctx::test_root(&ctx::AffineClock::new(20.0));
+    let ctx = &ctx::test_root(&ctx::RealClock);
     let rng = &mut ctx.rng();
-    let mut setup = validator::testonly::Setup::new(rng, node_count);
-    setup.push_blocks(rng, EXCHANGED_STATE_COUNT);
-    let cfgs = testonly::new_configs(rng, &setup, gossip_peers);
+    let setup = validator::testonly::Setup::new(rng, 2);
+    let cfgs = testonly::new_configs(rng, &setup, 1);
+
+    scope::run!(ctx, |ctx, s| async {
+        let mut listener = transport::bind(&cfgs[1].transport, &cfgs[1].server_addr)
+            .context("server_addr.bind()")?;
+
+        tracing::info!("Start one node, we will simulate the other one.");
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let (node, runner) = testonly::Instance::new(ctx, cfgs[0].clone(), store.clone());
+        s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node")));
+
+        tracing::info!("Respond to the handshake with a mismatching genesis.");
+        let stream = metrics::MeteredStream::listen(ctx, &mut listener)
+            .await?
+            .context("listen()")?;
+        let (mut stream, endpoint) = preface::accept(ctx, stream)
+            .await
+            .context("preface::accept()")?;
+        assert_eq!(endpoint, preface::Endpoint::GossipNet);
+        let _: handshake::Handshake =
+            frame::recv_proto(ctx, &mut stream, handshake::Handshake::max_size()).await?;
+        let genesis: validator::GenesisHash = rng.gen();
+        let session_id = node::SessionId(stream.id().encode());
+        frame::send_proto(
+            ctx,
+            &mut stream,
+            &handshake::Handshake {
+                session_id: cfgs[1].gossip.key.sign_msg(session_id),
+                genesis,
+                is_static: false,
+                observed_addr: None,
+                resume: None,
+                protocol_version: validator::ProtocolVersion::EARLIEST,
+            },
+        )
+        .await?;
+
+        tracing::info!("Expect the peer to be quarantined.");
+        let peer = cfgs[1].gossip.key.public();
+        let entry = loop {
+            if let Some(entry) = node.net.gossip.quarantine.get(ctx, &peer) {
+                break entry;
+            }
+            ctx.sleep(time::Duration::milliseconds(10)).await?;
+        };
+        assert_eq!(genesis, entry.genesis);
+        assert!(entry.until > ctx.now());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_inbound_genesis_mismatch_quarantine() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let setup = validator::testonly::Setup::new(rng, 2);
+    let cfgs = testonly::new_configs(rng, &setup, 0);
+
+    scope::run!(ctx, |ctx, s| async {
+        tracing::info!("Start one node, we will simulate the other one.");
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let (node, runner) = testonly::Instance::new(ctx, cfgs[0].clone(), store.clone());
+        s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node")));
+
+        tracing::info!("Connect to the node with a mismatching genesis.");
+        let mut stream = preface::connect(
+            ctx,
+            &cfgs[1].transport,
+            cfgs[0].public_addr,
+            preface::Endpoint::GossipNet,
+        )
+        .await
+        .context("preface::connect")?;
+        let genesis: validator::GenesisHash = rng.gen();
+        let tickets = Tickets::new("gossip", &cfgs[1].gossip.key, None);
+        let res = handshake::outbound(
+            ctx,
+            &cfgs[1].gossip,
+            genesis,
+            &tickets,
+            &mut stream,
+            &cfgs[0].gossip.key.public(),
+        )
+        .await;
+        assert_matches!(res, Err(handshake::Error::Stream(_)));
+
+        tracing::info!("Expect the peer to be quarantined.");
+        let peer = cfgs[1].gossip.key.public();
+        let entry = node
+            .net
+            .gossip
+            .quarantine
+            .get(ctx, &peer)
+            .context("peer not quarantined")?;
+        assert_eq!(genesis, entry.genesis);
+        assert!(entry.until > ctx.now());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_probe_peer() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let setup = validator::testonly::Setup::new(rng, 1);
+    let cfgs = testonly::new_configs(rng, &setup, 0);
+    let doctor_cfg = testonly::new_fullnode(rng, &cfgs[0]).gossip;
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let (_node, runner) = testonly::Instance::new(ctx, cfgs[0].clone(), store.clone());
+        s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node")));
+
+        let peer = cfgs[0].gossip.key.public();
+        let addr = cfgs[0].public_addr;
+        tracing::info!("Healthy peer.");
+        let report = doctor::probe_peer(
+            ctx,
+            &cfgs[0].transport,
+            &doctor_cfg,
+            setup.genesis.hash(),
+            &peer,
+            addr,
+        )
+        .await;
+        assert!(report.error.is_none(), "{:?}", report.error);
+        assert!(report.connect_latency.is_some());
+        assert!(report.handshake_latency.is_some());
+        assert_eq!(3, report.ping_rtts.len());
+        assert!(report.clock_skew.unwrap().abs() < crate::MAX_CLOCK_SKEW);
+
+        tracing::info!("Peer with a different genesis.");
+        let report =
+            doctor::probe_peer(ctx, &cfgs[0].transport, &doctor_cfg, rng.gen(), &peer, addr).await;
+        assert_matches!(report.error, Some(doctor::ProbeError::Handshake(_)));
+
+        tracing::info!("Peer with an unexpected key.");
+        let peer = rng.gen::<node::SecretKey>().public();
+        let report = doctor::probe_peer(
+            ctx,
+            &cfgs[0].transport,
+            &doctor_cfg,
+            setup.genesis.hash(),
+            &peer,
+            addr,
+        )
+        .await;
+        assert_matches!(report.error, Some(doctor::ProbeError::PeerMismatch));
+
+        tracing::info!("Unreachable peer.");
+        let addr = *testonly::reserve_virtual_listener();
+        let report = doctor::probe_peer(
+            ctx,
+            &cfgs[0].transport,
+            &doctor_cfg,
+            setup.genesis.hash(),
+            &peer,
+            addr,
+        )
+        .await;
+        assert_matches!(report.error, Some(doctor::ProbeError::Connect(_)));
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_bootstrap_genesis() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let setup = validator::testonly::Setup::new(rng, 3);
+    let cfgs = testonly::new_configs(rng, &setup, 0);
+    let other = validator::testonly::Setup::new(rng, 1);
+    let other_cfg = testonly::new_configs(rng, &other, 0).pop().unwrap();
+    let fake_key = rng.gen::<node::SecretKey>().public();
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        for (i, cfg) in cfgs.iter().enumerate() {
+            let (_node, runner) = testonly::Instance::new(ctx, cfg.clone(), store.clone());
+            s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node", i)));
+        }
+        let (store, runner) = new_store(ctx, &other.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let (_node, runner) = testonly::Instance::new(ctx, other_cfg.clone(), store);
+        s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("other")));
+
+        let transport = &cfgs[0].transport;
+        let other_key = other_cfg.gossip.key.public();
+        tracing::info!("Fetch the genesis of a peer with a different genesis.");
+        let got = genesis::fetch_genesis(ctx, transport, &other_key, other_cfg.public_addr).await?;
+        assert_eq!(other.genesis, got);
+
+        tracing::info!("A peer with an unexpected key is not trusted.");
+        let key = cfgs[0].gossip.key.public();
+        assert!(
+            genesis::fetch_genesis(ctx, transport, &key, other_cfg.public_addr)
+                .await
+                .is_err()
+        );
+
+        let mut peers: HashMap<_, _> = cfgs
+            .iter()
+            .map(|cfg| (cfg.gossip.key.public(), cfg.public_addr))
+            .collect();
+        peers.insert(other_key, other_cfg.public_addr);
+        tracing::info!("Quorum agrees on the genesis.");
+        let got = genesis::bootstrap_genesis(ctx, transport, &peers, 3).await?;
+        assert_eq!(setup.genesis, got);
+
+        tracing::info!("Not enough peers agree on the genesis.");
+        assert!(genesis::bootstrap_genesis(ctx, transport, &peers, 4)
+            .await
+            .is_err());
+
+        tracing::info!("A peer listed under the key of another peer is not counted.");
+        let mut fake = peers.clone();
+        fake.insert(fake_key.clone(), cfgs[0].public_addr);
+        assert!(genesis::bootstrap_genesis(ctx, transport, &fake, 4)
+            .await
+            .is_err());
+
+        tracing::info!("Conflicting genesis reach the quorum.");
+        assert!(genesis::bootstrap_genesis(ctx, transport, &peers, 1)
+            .await
+            .is_err());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+const EXCHANGED_STATE_COUNT: usize = 5;
+const NETWORK_CONNECTIVITY_CASES: [(usize, usize); 5] = [(2, 1), (3, 2), (5, 3), (10, 4), (10, 7)];
+
+/// Tests block syncing with global network synchronization (a next block becoming available
+/// to all nodes only after all nodes have received previous `SyncState` updates from peers).
+#[test_casing(5, NETWORK_CONNECTIVITY_CASES)]
+#[tokio::test(flavor = "multi_thread")]
+#[tracing::instrument(level = "trace")]
+async fn syncing_blocks(node_count: usize, gossip_peers: usize) {
+    abort_on_panic();
+    let _guard = set_timeout(time::Duration::seconds(5));
+
+    let ctx = &ctx::test_root(&ctx::AffineClock::new(20.0));
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, node_count);
+    setup.push_blocks(rng, EXCHANGED_STATE_COUNT);
+    let cfgs = testonly::new_configs(rng, &setup, gossip_peers);
     scope::run!(ctx, |ctx, s| async {
         let mut nodes = vec![];
+        let mut stores = vec![];
         for (i, cfg) in cfgs.into_iter().enumerate() {
             let (store, runner) = new_store(ctx, &setup.genesis).await;
             s.spawn_bg(runner.run(ctx));
-            let (node, runner) = testonly::Instance::new(ctx, cfg, store);
+            let (node, runner) = testonly::Instance::new(ctx, cfg, store.clone());
             s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node", i)));
             nodes.push(node);
+            stores.push(store);
         }
         for block in &setup.blocks {
-            for node in &nodes {
-                node.net
-                    .gossip
-                    .block_store
+            for store in &stores {
+                store
                     .queue_block(ctx, block.clone())
                     .await
                     .context("queue_block()")?;
@@ -515,6 +1253,409 @@ async fn getting_blocks_from_peers(node_count: usize, gossip_peers: usize) {
     .unwrap();
 }
 
+/// Blocks fetched by a node requiring relay authentication
+/// should be accepted only from the peers signing them.
+#[tokio::test]
+async fn getting_blocks_with_relay_auth() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 3);
+    setup.push_blocks(rng, 1);
+    let mut cfgs = testonly::new_configs(rng, &setup, 1);
+    cfgs[0].gossip.relay_auth = crate::RelayAuth::Require;
+    cfgs[1].gossip.relay_auth = crate::RelayAuth::Sign;
+    cfgs[2].gossip.relay_auth = crate::RelayAuth::Disabled;
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        store
+            .queue_block(ctx, setup.blocks[0].clone())
+            .await
+            .unwrap();
+        let nodes: Vec<_> = cfgs
+            .into_iter()
+            .enumerate()
+            .map(|(i, cfg)| {
+                let (node, runner) = testonly::Instance::new(ctx, cfg, store.clone());
+                s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node", i)));
+                node
+            })
+            .collect();
+        nodes[0].wait_for_gossip_connections().await;
+
+        for (peer, want_ok) in [(&nodes[1], true), (&nodes[2], false)] {
+            let (response, response_receiver) = oneshot::channel();
+            nodes[0].pipe.send(
+                io::SyncBlocksInputMessage::GetBlock {
+                    recipient: peer.net.gossip.cfg.gossip.key.public(),
+                    number: setup.blocks[0].number(),
+                    response,
+                }
+                .into(),
+            );
+            match response_receiver.recv(ctx).await? {
+                Ok(block) => {
+                    assert!(want_ok);
+                    assert_eq!(block, setup.blocks[0]);
+                }
+                Err(err) => {
+                    assert!(!want_ok);
+                    assert_matches!(err, io::GetBlockError::Internal(_));
+                }
+            }
+        }
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+/// Blocks with payloads spanning multiple chunks should be fetched in full.
+#[tokio::test]
+async fn getting_large_blocks() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 2);
+    let payload_size = 3 * rpc::get_block_chunk::MAX_CHUNK_SIZE + 17;
+    setup.push_block(validator::Payload(
+        (0..payload_size).map(|_| rng.gen()).collect(),
+    ));
+    let mut cfgs = testonly::new_configs(rng, &setup, 1);
+    cfgs[1].gossip.relay_auth = crate::RelayAuth::Sign;
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        store
+            .queue_block(ctx, setup.blocks[0].clone())
+            .await
+            .unwrap();
+        let nodes: Vec<_> = cfgs
+            .into_iter()
+            .enumerate()
+            .map(|(i, cfg)| {
+                let (node, runner) = testonly::Instance::new(ctx, cfg, store.clone());
+                s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node", i)));
+                node
+            })
+            .collect();
+        nodes[0].wait_for_gossip_connections().await;
+
+        let block = nodes[0]
+            .net
+            .gossip
+            .get_block(
+                ctx,
+                &nodes[1].net.gossip.cfg.gossip.key.public(),
+                setup.blocks[0].number(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(Some(&setup.blocks[0]), block.as_ref());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+/// `get_block` server of a peer which doesn't support `get_block_chunk`.
+struct LegacyGetBlockServer(FinalBlock);
+
+#[async_trait::async_trait]
+impl rpc::Handler<rpc::get_block::Rpc> for LegacyGetBlockServer {
+    fn max_req_size(&self) -> usize {
+        kB
+    }
+    async fn handle(
+        &self,
+        _ctx: &ctx::Ctx,
+        req: rpc::get_block::Req,
+    ) -> anyhow::Result<rpc::get_block::Resp> {
+        Ok(rpc::get_block::Resp {
+            block: (req.0 == self.0.number()).then(|| self.0.clone()),
+            relay_sig: None,
+        })
+    }
+}
+
+/// Peers which don't support `get_block_chunk` should be asked for the whole block instead.
+#[tokio::test]
+async fn getting_blocks_from_legacy_peers() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 2);
+    setup.push_blocks(rng, 1);
+    let cfgs = testonly::new_configs(rng, &setup, 1);
+
+    scope::run!(ctx, |ctx, s| async {
+        let mut listener = transport::bind(&cfgs[1].transport, &cfgs[1].server_addr)
+            .context("server_addr.bind()")?;
+
+        tracing::info!("Start one node, we will simulate the other one.");
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let (node, runner) = testonly::Instance::new(ctx, cfgs[0].clone(), store.clone());
+        s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node")));
+
+        tracing::info!("Accept the connection and serve get_block only.");
+        let stream = metrics::MeteredStream::listen(ctx, &mut listener)
+            .await?
+            .context("listen()")?;
+        let (mut stream, endpoint) = preface::accept(ctx, stream)
+            .await
+            .context("preface::accept()")?;
+        assert_eq!(endpoint, preface::Endpoint::GossipNet);
+        let tickets = Tickets::new("gossip", &cfgs[1].gossip.key, None);
+        handshake::inbound(
+            ctx,
+            &cfgs[1].gossip,
+            setup.genesis.hash(),
+            &tickets,
+            &mut stream,
+        )
+        .await
+        .context("handshake::inbound()")?;
+        s.spawn_bg(async {
+            let _ = rpc::Service::new()
+                .add_server(
+                    LegacyGetBlockServer(setup.blocks[0].clone()),
+                    limiter::Rate {
+                        burst: 10,
+                        refresh: time::Duration::ZERO,
+                    },
+                )
+                .run(ctx, stream)
+                .await;
+            Ok(())
+        });
+        node.wait_for_gossip_connections().await;
+
+        let peer = cfgs[1].gossip.key.public();
+        let block = node
+            .net
+            .gossip
+            .get_block(ctx, &peer, setup.blocks[0].number())
+            .await
+            .unwrap();
+        assert_eq!(Some(&setup.blocks[0]), block.as_ref());
+        let justification = node
+            .net
+            .gossip
+            .get_justification(ctx, &peer, setup.blocks[0].number())
+            .await
+            .unwrap();
+        assert_eq!(Some(&setup.blocks[0].justification), justification.as_ref());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn getting_headers() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 2);
+    setup.push_blocks(rng, 2);
+    // The committed key rotation and protocol upgrade are sent with the headers.
+    let (_, cert) = setup.key_rotation(rng, 0, validator::ViewNumber(100), 0);
+    setup.push_block_with_key_rotation(rng.gen(), Some(cert));
+    let activation = BlockNumber(setup.genesis.fork.first_block.0 + 100);
+    let qc = setup.protocol_upgrade(validator::ProtocolVersion::PAYLOAD_ROOT, activation);
+    setup.push_block_with_protocol_upgrade(rng.gen(), Some(qc));
+    setup.push_blocks(rng, 1);
+    let cfgs = testonly::new_configs(rng, &setup, 1);
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        for block in &setup.blocks {
+            store.queue_block(ctx, block.clone()).await.unwrap();
+        }
+        let nodes: Vec<_> = cfgs
+            .into_iter()
+            .enumerate()
+            .map(|(i, cfg)| {
+                let (node, runner) = testonly::Instance::new(ctx, cfg, store.clone());
+                s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node", i)));
+                node
+            })
+            .collect();
+        nodes[0].wait_for_gossip_connections().await;
+        let peer = &nodes[1].net.gossip.cfg.gossip.key.public();
+        let want: Vec<_> = setup.blocks[1..]
+            .iter()
+            .map(crate::FinalHeader::from)
+            .collect();
+
+        // Only the available blocks are returned.
+        let got = nodes[0]
+            .net
+            .get_headers(ctx, peer, setup.blocks[1].number(), 10)
+            .await
+            .unwrap();
+        assert_eq!(want, got);
+        // At most `count` blocks are returned.
+        let got = nodes[0]
+            .net
+            .get_headers(ctx, peer, setup.blocks[1].number(), 2)
+            .await
+            .unwrap();
+        assert_eq!(want[..2], got);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn blocks_exchanged_by_peer_protocol_version() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 2);
+    setup.set_protocol_version(validator::ProtocolVersion::PAYLOAD_ROOT);
+    setup.push_blocks(rng, 2);
+    let mut cfgs = testonly::new_configs(rng, &setup, 1);
+    // Node 1 doesn't support the genesis protocol version.
+    cfgs[1].gossip.protocol_version = validator::ProtocolVersion::EARLIEST;
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        for block in &setup.blocks {
+            store.queue_block(ctx, block.clone()).await.unwrap();
+        }
+        let nodes: Vec<_> = cfgs
+            .into_iter()
+            .enumerate()
+            .map(|(i, cfg)| {
+                let (node, runner) = testonly::Instance::new(ctx, cfg, store.clone());
+                s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node", i)));
+                node
+            })
+            .collect();
+        nodes[0].wait_for_gossip_connections().await;
+        nodes[1].wait_for_gossip_connections().await;
+        let keys: Vec<_> = nodes
+            .iter()
+            .map(|n| n.net.gossip.cfg.gossip.key.public())
+            .collect();
+        let number = setup.blocks[0].number();
+
+        // The versions announced in the handshake are known to the peers.
+        let got = nodes[0].net.gossip.peer_versions.get_any(&keys[1]).unwrap();
+        assert_eq!(validator::ProtocolVersion::EARLIEST, *got);
+        let got = nodes[1].net.gossip.peer_versions.get_any(&keys[0]).unwrap();
+        assert_eq!(testonly::PROTOCOL_VERSION, *got);
+
+        // Node 0 doesn't request the block from node 1.
+        let got = nodes[0].net.gossip.get_block(ctx, &keys[1], number).await?;
+        assert_eq!(None, got);
+        let got = nodes[0]
+            .net
+            .gossip
+            .get_justification(ctx, &keys[1], number)
+            .await?;
+        assert_eq!(None, got);
+        // Node 0 doesn't serve the block to node 1.
+        let got = nodes[1].net.gossip.get_block(ctx, &keys[0], number).await?;
+        assert_eq!(None, got);
+        let got = nodes[1]
+            .net
+            .gossip
+            .get_headers(ctx, &keys[0], number, 2)
+            .await?;
+        assert!(got.is_empty());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn sampling_payload() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 2);
+    setup.set_protocol_version(validator::ProtocolVersion::PAYLOAD_ROOT);
+    setup.push_block(validator::Payload(
+        (0..20 * kB).map(|_| rng.gen()).collect(),
+    ));
+    let cfgs = testonly::new_configs(rng, &setup, 1);
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        for block in &setup.blocks {
+            store.queue_block(ctx, block.clone()).await.unwrap();
+        }
+        let nodes: Vec<_> = cfgs
+            .into_iter()
+            .enumerate()
+            .map(|(i, cfg)| {
+                let (node, runner) = testonly::Instance::new(ctx, cfg, store.clone());
+                s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node", i)));
+                node
+            })
+            .collect();
+        nodes[0].wait_for_gossip_connections().await;
+        let peer = &nodes[1].net.gossip.cfg.gossip.key.public();
+
+        let block = &setup.blocks[0];
+        let root = block.header().payload_root.unwrap();
+        let samples: Vec<u64> = (0..10).map(|_| rng.gen()).collect();
+        let got = nodes[0]
+            .net
+            .gossip
+            .sample_payload(ctx, peer, block.number(), samples.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        let count = validator::payload_chunk_count(block.payload.0.len());
+        for (sample, got) in samples.iter().zip(got) {
+            assert_eq!((sample % count as u64) as usize, got.proof.index);
+            got.proof.verify(&root, &got.chunk).unwrap();
+        }
+        // The payload tree is cached by the server and the following samples are served from it.
+        let cached = nodes[1]
+            .net
+            .gossip
+            .sampled_payloads
+            .get(block.number())
+            .unwrap();
+        assert_eq!(root, cached.tree.root());
+        let samples: Vec<u64> = (0..10).map(|_| rng.gen()).collect();
+        let got = nodes[0]
+            .net
+            .gossip
+            .sample_payload(ctx, peer, block.number(), samples.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        for got in got {
+            got.proof.verify(&root, &got.chunk).unwrap();
+        }
+        // Missing block cannot be sampled.
+        let got = nodes[0]
+            .net
+            .gossip
+            .sample_payload(ctx, peer, block.number().next(), samples)
+            .await
+            .unwrap();
+        assert_eq!(None, got);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
 /// When validator node is restarted, it should immediately override
 /// the AccountData that is present in the network from the previous run.
 #[tokio::test]
@@ -534,6 +1675,7 @@ async fn validator_node_restart() {
     // Set the rpc refresh time to 0, so that any updates are immediately propagated.
     for cfg in &mut cfgs {
         cfg.rpc.push_validator_addrs_rate.refresh = time::Duration::ZERO;
+        cfg.rpc.push_validator_addrs_server_rate.refresh = time::Duration::ZERO;
     }
     let (store, store_runner) = new_store(ctx, &setup.genesis).await;
     let (node1, node1_runner) = testonly::Instance::new(ctx, cfgs[1].clone(), store.clone());
@@ -662,3 +1804,230 @@ async fn rate_limiting() {
         assert!((1..=2).contains(&got), "got {got} want 1 or 2");
     }
 }
+
+/// Serving blocks should be throttled by the bandwidth budget.
+#[tokio::test]
+async fn test_serve_budget() {
+    abort_on_panic();
+    let clock = &ctx::ManualClock::new();
+    clock.set_advance_on_sleep();
+    let ctx = &ctx::test_root(clock);
+    let peer = bandwidth::Budget::new(ctx, Some(10 * kB));
+    let unlimited = bandwidth::Budget::new(ctx, None);
+
+    // The burst is available immediately.
+    let now = ctx.now();
+    bandwidth::consume(ctx, &peer, &unlimited, 10 * kB)
+        .await
+        .unwrap();
+    assert_eq!(now, ctx.now());
+
+    // Further requests wait until the budget refreshes.
+    bandwidth::consume(ctx, &peer, &unlimited, 5 * kB)
+        .await
+        .unwrap();
+    assert_eq!(now + time::Duration::milliseconds(500), ctx.now());
+
+    // Requests larger than the burst are charged over multiple refills.
+    let now = ctx.now();
+    bandwidth::consume(ctx, &peer, &unlimited, 25 * kB)
+        .await
+        .unwrap();
+    assert_eq!(now + time::Duration::milliseconds(2500), ctx.now());
+
+    // The global budget applies as well.
+    let global = bandwidth::Budget::new(ctx, Some(kB));
+    let now = ctx.now();
+    bandwidth::consume(ctx, &unlimited, &global, kB)
+        .await
+        .unwrap();
+    bandwidth::consume(ctx, &unlimited, &global, kB)
+        .await
+        .unwrap();
+    assert_eq!(now + time::Duration::seconds(1), ctx.now());
+}
+
+#[test]
+fn test_address_book() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let book = address_book::AddressBook::default();
+    let own_key: node::SecretKey = rng.gen();
+    let source = rng.gen::<node::SecretKey>().public();
+    let keys: Vec<node::SecretKey> = (0..5).map(|_| rng.gen()).collect();
+    let mk = |key: &node::SecretKey, timestamp: time::Utc| {
+        Arc::new(key.sign_msg(node::NodeAddr {
+            addr: mk_addr(&mut ctx.rng()),
+            timestamp,
+        }))
+    };
+    let t0 = ctx.now_utc();
+
+    // Own address is not stored.
+    let addrs: Vec<_> = keys.iter().map(|k| mk(k, t0)).collect();
+    book.update(
+        &own_key.public(),
+        &source,
+        &[addrs.clone(), vec![mk(&own_key, t0)]].concat(),
+    )
+    .unwrap();
+    assert_eq!(keys.len(), book.len());
+
+    // Duplicates and invalid signatures are rejected.
+    assert!(book
+        .update(
+            &own_key.public(),
+            &source,
+            &[addrs[0].clone(), addrs[0].clone()]
+        )
+        .is_err());
+    let mut invalid = (*mk(&keys[0], t0)).clone();
+    invalid.key = keys[1].public();
+    let valid = mk(&rng.gen(), t0);
+    assert!(book
+        .update(&own_key.public(), &source, &[valid, Arc::new(invalid)])
+        .is_err());
+    assert_eq!(keys.len(), book.len());
+
+    // Newer addresses replace the older ones.
+    let newer = mk(&keys[0], t0 + time::Duration::seconds(1));
+    book.update(&own_key.public(), &source, &[newer.clone()])
+        .unwrap();
+    book.update(&own_key.public(), &source, &[addrs[0].clone()])
+        .unwrap();
+    let got = book.best(rng, |k| k == &keys[0].public()).unwrap();
+    assert_eq!(newer.msg, got.msg);
+
+    // The best scored node is preferred.
+    book.connected(&keys[1].public());
+    let got = book.best(rng, |_| true).unwrap();
+    assert_eq!(keys[1].public(), got.key);
+
+    // Nodes which keep failing are removed.
+    for _ in 0..4 {
+        book.failed(&keys[2].public());
+    }
+    assert_eq!(keys.len() - 1, book.len());
+    assert!(book.best(rng, |k| k == &keys[2].public()).is_none());
+
+    // A single peer contributes a limited number of addresses.
+    let other = rng.gen::<node::SecretKey>().public();
+    let many: Vec<_> = (0..rpc::pex::MAX_ADDRS)
+        .map(|_| mk(&rng.gen(), t0))
+        .collect();
+    book.update(&own_key.public(), &other, &many).unwrap();
+    let many: Vec<_> = (0..rpc::pex::MAX_ADDRS)
+        .map(|_| mk(&rng.gen(), t0))
+        .collect();
+    book.update(&own_key.public(), &other, &many).unwrap();
+    assert_eq!(
+        keys.len() - 1 + address_book::MAX_ENTRIES_PER_PEER,
+        book.len()
+    );
+}
+
+#[test]
+fn test_failure_penalty() {
+    use address_book::penalty;
+    let decode = || frame::Error::Decode(anyhow::format_err!("invalid"));
+    let timeout = || frame::Error::Timeout;
+    // Errors are classified, even if wrapped.
+    let handshake = anyhow::Error::from(handshake::Error::Stream(decode()));
+    assert!(penalty(&handshake.context("run_outbound_stream()")) > 1);
+    let handshake = anyhow::Error::from(handshake::Error::Stream(timeout()));
+    assert_eq!(1, penalty(&handshake));
+    // Peers sending invalid frames are penalized more than the ones timing out.
+    assert!(penalty(&decode().into()) > penalty(&timeout().into()));
+    assert_eq!(1, penalty(&anyhow::format_err!("unclassified")));
+    // Only the misbehavior is penalized on the inbound connections.
+    use address_book::misbehavior_penalty;
+    assert_eq!(
+        Some(penalty(&decode().into())),
+        misbehavior_penalty(&decode().into())
+    );
+    assert_eq!(None, misbehavior_penalty(&timeout().into()));
+    assert_eq!(
+        None,
+        misbehavior_penalty(&anyhow::format_err!("unclassified"))
+    );
+}
+
+#[test]
+fn test_public_addr_detection() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let configured: std::net::SocketAddr = "10.0.0.1:3054".parse().unwrap();
+    let detected: std::net::IpAddr = "1.2.3.4".parse().unwrap();
+    let other: std::net::IpAddr = "5.6.7.8".parse().unwrap();
+    let peers: Vec<node::PublicKey> = (0..4)
+        .map(|_| rng.gen::<node::SecretKey>().public())
+        .collect();
+
+    // Detection disabled.
+    let addr = public_addr::PublicAddr::new(configured, None);
+    for peer in &peers {
+        addr.report(peer, detected);
+    }
+    assert_eq!(configured, addr.get());
+
+    // A single peer cannot set the address, even if `min_reports` allows it.
+    let addr =
+        public_addr::PublicAddr::new(configured, Some(PublicAddrDetection { min_reports: 1 }));
+    addr.report(&peers[0], detected);
+    assert_eq!(configured, addr.get());
+
+    // Agreeing peers have to be a majority of the reporting peers.
+    addr.report(&peers[1], other);
+    addr.report(&peers[2], "9.9.9.9".parse().unwrap());
+    addr.report(&peers[3], detected);
+    assert_eq!(configured, addr.get());
+
+    // Detection enabled.
+    let addr =
+        public_addr::PublicAddr::new(configured, Some(PublicAddrDetection { min_reports: 2 }));
+    let mut sub = addr.subscribe();
+    addr.report(&peers[0], detected);
+    addr.report(&peers[1], other);
+    assert_eq!(configured, addr.get());
+    addr.report(&peers[2], detected);
+    let want = std::net::SocketAddr::new(detected, configured.port());
+    assert_eq!(want, addr.get());
+    assert!(sub.has_changed().unwrap());
+    assert_eq!(want, *sub.borrow_and_update());
+
+    // Closing the connections doesn't revert the detected address.
+    addr.forget(&peers[2]);
+    assert_eq!(want, addr.get());
+    assert!(!sub.has_changed().unwrap());
+
+    // The address is updated, once the majority of the peers reports a new one.
+    addr.report(&peers[2], other);
+    assert_eq!(
+        std::net::SocketAddr::new(other, configured.port()),
+        addr.get()
+    );
+    assert!(sub.has_changed().unwrap());
+}
+
+#[test]
+fn payload_cache_eviction() {
+    let rng = &mut ctx::test_root(&ctx::RealClock).rng();
+    let cache = payload_cache::PayloadCache::default();
+    let payload = |rng: &mut rand::rngs::StdRng| {
+        let payload: validator::Payload = rng.gen();
+        Arc::new(payload_cache::SampledPayload {
+            tree: payload.tree(),
+            payload,
+        })
+    };
+    for i in 0..payload_cache::CAPACITY as u64 {
+        cache.insert(BlockNumber(i), payload(rng));
+    }
+    // Reinserting a cached payload doesn't evict anything.
+    cache.insert(BlockNumber(0), payload(rng));
+    assert!(cache.get(BlockNumber(0)).is_some());
+    // The least recently inserted payload is evicted.
+    cache.insert(BlockNumber(payload_cache::CAPACITY as u64), payload(rng));
+    assert!(cache.get(BlockNumber(0)).is_none());
+    assert!(cache.get(BlockNumber(1)).is_some());
+}
@@ -0,0 +1,260 @@
+//! Application-defined gossip topics.
+//! The embedder of the network actor can declare extra topics, the messages of which are pushed
+//! to the gossip peers over the same connections as the built-in RPCs. Every topic has its own
+//! message type, max message size, rate limit and validation callback.
+//! Messages are pushed to the direct peers only: an application which needs its messages
+//! flooded through the whole network can republish the messages it receives.
+use crate::{gossip::ArcMap, rpc};
+use anyhow::Context as _;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::{Arc, Mutex},
+};
+use vise::{Counter, LabeledFamily, Metrics};
+use zksync_concurrency::{ctx, ctx::channel, limiter, scope, time};
+use zksync_consensus_roles::node;
+use zksync_protobuf::{kB, ProtoFmt};
+
+/// Timeout of pushing a message to a single peer.
+/// Pushing is best effort, so a slow peer shouldn't block the others.
+const PUSH_TIMEOUT: time::Duration = time::Duration::seconds(5);
+
+/// Config of a topic.
+#[derive(Debug, Clone)]
+pub struct Topic {
+    /// Name of the topic, unique within the registry.
+    /// Messages of the topics unknown to the receiver are ignored.
+    pub name: String,
+    /// Max size of the proto-encoded message in bytes.
+    pub max_msg_size: usize,
+    /// Max rate of the messages received from a single peer.
+    /// Messages above it are dropped.
+    pub rate: limiter::Rate,
+    /// Capacity of the queue of the received messages.
+    /// Messages received while the queue is full are dropped.
+    pub queue_size: usize,
+}
+
+/// Callback decoding, validating and enqueueing a received message.
+type Deliver = dyn Fn(&node::PublicKey, &[u8]) -> anyhow::Result<()> + Send + Sync;
+
+/// Registered topic.
+struct TopicState {
+    /// Config of the topic.
+    topic: Topic,
+    /// Delivers the received messages to the `TopicHandle`.
+    deliver: Box<Deliver>,
+}
+
+/// Shared state of the registry.
+#[derive(Default)]
+struct Registry {
+    /// Registered topics, by name.
+    topics: Mutex<BTreeMap<String, Arc<TopicState>>>,
+    /// Clients for `push_topic` requests for each currently active peer.
+    clients: ArcMap<rpc::Client<rpc::push_topic::Rpc>>,
+}
+
+/// Registry of the application-defined topics.
+/// Clones share the registry, so topics can be registered (and unregistered)
+/// while the network actor is running.
+#[derive(Clone, Default)]
+pub struct Topics(Arc<Registry>);
+
+impl fmt::Debug for Topics {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_list()
+            .entries(self.0.topics.lock().unwrap().keys())
+            .finish()
+    }
+}
+
+impl Topics {
+    /// Registers a topic with messages of type `T`.
+    /// Every received message is passed to `validate` together with the peer which pushed it.
+    /// Invalid messages are dropped, the valid ones are delivered via the returned handle.
+    /// The topic stays registered until the handle is dropped.
+    pub fn register<T: ProtoFmt + Send + 'static>(
+        &self,
+        topic: Topic,
+        validate: impl Fn(&node::PublicKey, &T) -> anyhow::Result<()> + Send + Sync + 'static,
+    ) -> anyhow::Result<TopicHandle<T>> {
+        anyhow::ensure!(topic.queue_size > 0, "queue_size has to be positive");
+        let mut topics = self.0.topics.lock().unwrap();
+        anyhow::ensure!(
+            !topics.contains_key(&topic.name),
+            "topic {:?} is already registered",
+            topic.name
+        );
+        let (send, recv) = channel::bounded(topic.queue_size);
+        let name = topic.name.clone();
+        let deliver = move |peer: &node::PublicKey, payload: &[u8]| {
+            let msg: T = zksync_protobuf::decode(payload).context("decode()")?;
+            validate(peer, &msg).context("validate()")?;
+            match send.try_send((peer.clone(), msg)) {
+                Ok(()) => METRICS.received[&name].inc(),
+                Err(_) => METRICS.dropped[&name].inc(),
+            }
+            Ok(())
+        };
+        topics.insert(
+            topic.name.clone(),
+            Arc::new(TopicState {
+                topic: topic.clone(),
+                deliver: Box::new(deliver),
+            }),
+        );
+        Ok(TopicHandle {
+            topic,
+            registry: self.0.clone(),
+            recv,
+        })
+    }
+
+    /// Looks up a registered topic.
+    fn get(&self, name: &str) -> Option<Arc<TopicState>> {
+        self.0.topics.lock().unwrap().get(name).cloned()
+    }
+
+    /// Max message size over all the registered topics.
+    fn max_msg_size(&self) -> usize {
+        let topics = self.0.topics.lock().unwrap();
+        topics
+            .values()
+            .map(|t| t.topic.max_msg_size)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Clients of the connected peers, to which the messages are pushed.
+    pub(super) fn clients(&self) -> &ArcMap<rpc::Client<rpc::push_topic::Rpc>> {
+        &self.0.clients
+    }
+}
+
+/// Handle of a registered topic, used to publish and receive its messages.
+pub struct TopicHandle<T> {
+    /// Config of the topic.
+    topic: Topic,
+    /// Registry the topic is registered in.
+    registry: Arc<Registry>,
+    /// Validated messages received from the peers.
+    recv: channel::Receiver<(node::PublicKey, T)>,
+}
+
+impl<T> fmt::Debug for TopicHandle<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("TopicHandle")
+            .field("topic", &self.topic)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Drop for TopicHandle<T> {
+    fn drop(&mut self) {
+        self.registry
+            .topics
+            .lock()
+            .unwrap()
+            .remove(&self.topic.name);
+    }
+}
+
+impl<T: ProtoFmt> TopicHandle<T> {
+    /// Pushes the message to all the gossip peers.
+    /// Pushing is best effort: failures to push to the individual peers are only logged.
+    pub async fn publish(&self, ctx: &ctx::Ctx, msg: &T) -> anyhow::Result<()> {
+        let req = rpc::push_topic::Req {
+            topic: self.topic.name.clone(),
+            payload: zksync_protobuf::encode(msg),
+        };
+        anyhow::ensure!(
+            req.payload.len() <= self.topic.max_msg_size,
+            "message too large: {}B, max {}B",
+            req.payload.len(),
+            self.topic.max_msg_size
+        );
+        let req = rpc::EncodedReq::new(req);
+        let clients = self.registry.clients.all();
+        scope::run!(ctx, |ctx, s| async {
+            for (peer, client) in &clients {
+                s.spawn(async {
+                    let ctx = &ctx.with_timeout(PUSH_TIMEOUT);
+                    if let Err(err) = client.call_encoded(ctx, &req, kB).await {
+                        tracing::debug!("push_topic({peer:?}): {err:#}");
+                    }
+                    Ok(())
+                });
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Receives the next valid message, together with the peer which pushed it.
+    pub async fn recv(&mut self, ctx: &ctx::Ctx) -> ctx::OrCanceled<(node::PublicKey, T)> {
+        self.recv.recv(ctx).await
+    }
+}
+
+/// Server of the topic messages for a single gossip connection.
+pub(super) struct PushTopicServer<'a> {
+    /// Registry of the topics.
+    pub(super) topics: &'a Topics,
+    /// Peer on the other end of the connection.
+    pub(super) peer: &'a node::PublicKey,
+    /// Rate limiters of the messages received from the peer, by topic.
+    pub(super) limiters: Mutex<HashMap<String, limiter::Limiter>>,
+}
+
+#[async_trait::async_trait]
+impl rpc::Handler<rpc::push_topic::Rpc> for PushTopicServer<'_> {
+    fn max_req_size(&self) -> usize {
+        self.topics.max_msg_size().saturating_add(kB)
+    }
+
+    async fn handle(&self, ctx: &ctx::Ctx, req: rpc::push_topic::Req) -> anyhow::Result<()> {
+        // Peers may run applications with a different set of topics.
+        let Some(state) = self.topics.get(&req.topic) else {
+            return Ok(());
+        };
+        let topic = &state.topic;
+        anyhow::ensure!(
+            req.payload.len() <= topic.max_msg_size,
+            "{}: message too large",
+            topic.name
+        );
+        let allowed = self
+            .limiters
+            .lock()
+            .unwrap()
+            .entry(topic.name.clone())
+            .or_insert_with(|| limiter::Limiter::new(ctx, topic.rate))
+            .try_acquire(ctx, 1)
+            .is_some();
+        if !allowed {
+            METRICS.rate_limited[&topic.name].inc();
+            return Ok(());
+        }
+        (state.deliver)(self.peer, &req.payload).with_context(|| topic.name.clone())
+    }
+}
+
+/// Metrics of the application-defined topics.
+#[derive(Debug, Metrics)]
+#[metrics(prefix = "network_gossip_topics")]
+struct TopicMetrics {
+    /// Valid messages received from the peers.
+    #[metrics(labels = ["topic"])]
+    received: LabeledFamily<String, Counter>,
+    /// Valid messages dropped, because the queue of the topic was full.
+    #[metrics(labels = ["topic"])]
+    dropped: LabeledFamily<String, Counter>,
+    /// Messages dropped, because the peer exceeded the rate limit of the topic.
+    #[metrics(labels = ["topic"])]
+    rate_limited: LabeledFamily<String, Counter>,
+}
+
+#[vise::register]
+static METRICS: vise::Global<TopicMetrics> = vise::Global::new();
@@ -0,0 +1,167 @@
+//! Announcements of the validators that they support the next protocol version.
+//! Every validator signs the upgrade it is ready for and the announcements are gossiped to all
+//! the nodes, so that any node can aggregate them into a `ProtocolUpgradeQC`, which the leaders
+//! then commit to the chain to activate the new version at the announced block.
+//! Only the latest announcement of each validator is kept.
+use crate::watch::Watch;
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Arc,
+};
+use zksync_concurrency::sync;
+use zksync_consensus_roles::validator;
+
+/// Orders the announcements of a single validator: the later announcement
+/// is for a higher version, or for the same version with a later activation.
+fn order(u: &validator::ProtocolUpgrade) -> (validator::ProtocolVersion, validator::BlockNumber) {
+    (u.version, u.activation)
+}
+
+/// Mapping from validator::PublicKey to the latest upgrade announced by the validator.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub(crate) struct UpgradeVotes(
+    pub(super) im::HashMap<validator::PublicKey, Arc<validator::Signed<validator::ProtocolUpgrade>>>,
+);
+
+impl UpgradeVotes {
+    /// Returns the votes of `self` which are newer than the votes in `b`.
+    pub(super) fn get_newer(
+        &self,
+        b: &Self,
+    ) -> Vec<Arc<validator::Signed<validator::ProtocolUpgrade>>> {
+        let mut newer = vec![];
+        for (k, v) in &self.0 {
+            if let Some(bv) = b.0.get(k) {
+                if order(&v.msg) <= order(&bv.msg) {
+                    continue;
+                }
+            }
+            newer.push(v.clone());
+        }
+        newer
+    }
+
+    /// Updates the votes with entries from `data`.
+    /// It exits as soon as an invalid entry is found.
+    /// `self` might get modified even if an error is returned
+    /// (all entries verified so far are added).
+    /// Returns true iff some new entry was added.
+    pub(super) fn update(
+        &mut self,
+        genesis: &validator::Genesis,
+        data: &[Arc<validator::Signed<validator::ProtocolUpgrade>>],
+    ) -> anyhow::Result<bool> {
+        let genesis_hash = genesis.hash();
+        let latest = genesis.latest_protocol_version();
+        let mut changed = false;
+        let mut done = HashSet::new();
+        for d in data {
+            // Disallow multiple entries for the same key:
+            // verifying signatures is expensive.
+            anyhow::ensure!(done.insert(&d.key), "duplicate entry for {:?}", d.key);
+            if !genesis.accepts_validator_key(&d.key) {
+                continue;
+            }
+            anyhow::ensure!(d.msg.genesis == genesis_hash, "genesis mismatch");
+            // Upgrades which are already scheduled are not interesting anymore.
+            if d.msg.version <= latest {
+                continue;
+            }
+            if let Some(x) = self.0.get(&d.key) {
+                if order(&d.msg) <= order(&x.msg) {
+                    continue;
+                }
+            }
+            d.verify()?;
+            self.0.insert(d.key.clone(), d.clone());
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    /// Aggregates the votes into a certificate of the highest version
+    /// which has been announced by enough validators.
+    pub(super) fn certificate(
+        &self,
+        genesis: &validator::Genesis,
+    ) -> Option<validator::ProtocolUpgradeQC> {
+        let mut by_upgrade: BTreeMap<_, Vec<&validator::Signed<validator::ProtocolUpgrade>>> =
+            BTreeMap::new();
+        for v in self.0.values() {
+            by_upgrade.entry(order(&v.msg)).or_default().push(v);
+        }
+        // Validators have to agree on both the version and the activation block.
+        for votes in by_upgrade.values().rev() {
+            let mut qc = validator::ProtocolUpgradeQC::new(votes[0].msg.clone(), genesis);
+            for v in votes {
+                qc.add(v, genesis);
+            }
+            if qc.signers.count() >= genesis.validators.threshold() {
+                return Some(qc);
+            }
+        }
+        None
+    }
+}
+
+/// Watch wrapper of UpgradeVotes, which supports subscribing to the votes and
+/// to the certificates aggregated from them.
+pub(crate) struct UpgradeVotesWatch {
+    /// Latest votes of the validators.
+    votes: Watch<UpgradeVotes>,
+    /// Certificate of the highest version announced by enough validators.
+    qc: sync::watch::Sender<Option<Arc<validator::ProtocolUpgradeQC>>>,
+}
+
+impl Default for UpgradeVotesWatch {
+    fn default() -> Self {
+        Self {
+            votes: Watch::new(UpgradeVotes::default()),
+            qc: sync::watch::channel(None).0,
+        }
+    }
+}
+
+impl UpgradeVotesWatch {
+    /// Subscribes to UpgradeVotes updates.
+    pub(crate) fn subscribe(&self) -> sync::watch::Receiver<UpgradeVotes> {
+        self.votes.subscribe()
+    }
+
+    /// Subscribes to the certificate of the highest version.
+    pub(crate) fn subscribe_qc(
+        &self,
+    ) -> sync::watch::Receiver<Option<Arc<validator::ProtocolUpgradeQC>>> {
+        self.qc.subscribe()
+    }
+
+    /// Inserts data to UpgradeVotes.
+    /// Subscribers are notified iff at least 1 new entry has
+    /// been inserted. Returns an error iff an invalid
+    /// entry in `data` has been found. The provider of the
+    /// invalid entry should be banned.
+    pub(crate) async fn update(
+        &self,
+        genesis: &validator::Genesis,
+        data: &[Arc<validator::Signed<validator::ProtocolUpgrade>>],
+    ) -> anyhow::Result<()> {
+        let this = self.votes.lock().await;
+        let mut votes = this.borrow().clone();
+        if votes.update(genesis, data)? {
+            if let Some(qc) = votes.certificate(genesis) {
+                self.qc.send_if_modified(|old| {
+                    if old
+                        .as_ref()
+                        .is_some_and(|old| order(&old.message) >= order(&qc.message))
+                    {
+                        return false;
+                    }
+                    *old = Some(Arc::new(qc));
+                    true
+                });
+            }
+            this.send(votes).ok().unwrap();
+        }
+        Ok(())
+    }
+}
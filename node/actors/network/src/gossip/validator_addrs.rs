@@ -41,7 +41,7 @@ impl ValidatorAddrs {
     /// Returns true iff some new entry was added.
     pub(super) fn update(
         &mut self,
-        validators: &validator::ValidatorSet,
+        genesis: &validator::Genesis,
         data: &[Arc<validator::Signed<validator::NetAddress>>],
     ) -> anyhow::Result<bool> {
         let mut changed = false;
@@ -55,7 +55,7 @@ impl ValidatorAddrs {
                 anyhow::bail!("duplicate entry for {:?}", d.key);
             }
             done.insert(d.key.clone());
-            if !validators.contains(&d.key) {
+            if !genesis.accepts_validator_key(&d.key) {
                 // We just skip the entries we are not interested in.
                 // For now the set of validators is static, so we could treat this as an error,
                 // however we eventually want the validator set to be dynamic.
@@ -97,12 +97,12 @@ impl ValidatorAddrsWatch {
     /// invalid entry should be banned.
     pub(crate) async fn update(
         &self,
-        validators: &validator::ValidatorSet,
+        genesis: &validator::Genesis,
         data: &[Arc<validator::Signed<validator::NetAddress>>],
     ) -> anyhow::Result<()> {
         let this = self.0.lock().await;
         let mut validator_addrs = this.borrow().clone();
-        if validator_addrs.update(validators, data)? {
+        if validator_addrs.update(genesis, data)? {
             this.send(validator_addrs).ok().unwrap();
         }
         Ok(())
@@ -1,5 +1,6 @@
 #![allow(missing_docs)]
-use zksync_concurrency::oneshot;
+use crate::TraceContext;
+use zksync_concurrency::{oneshot, time};
 use zksync_consensus_roles::{node, validator};
 use zksync_consensus_storage::BlockStoreState;
 
@@ -17,6 +18,8 @@ pub enum InputMessage {
 pub struct ConsensusInputMessage {
     pub message: validator::Signed<validator::ConsensusMsg>,
     pub recipient: Target,
+    /// Trace context of the span which produced the message.
+    pub trace: Option<TraceContext>,
 }
 
 impl From<ConsensusInputMessage> for InputMessage {
@@ -34,6 +37,38 @@ pub enum SyncBlocksInputMessage {
         number: validator::BlockNumber,
         response: oneshot::Sender<Result<validator::FinalBlock, GetBlockError>>,
     },
+    /// Request to get the justification of a block from a specific peer, without the payload.
+    /// The justification is not verified.
+    GetJustification {
+        recipient: node::PublicKey,
+        number: validator::BlockNumber,
+        response: oneshot::Sender<Result<validator::CommitQC, GetBlockError>>,
+    },
+    /// Request to sample the payload of a block from a specific peer, see
+    /// `validator::PayloadRoot`. The peer sends the chunk with index
+    /// `sample % (number of chunks)` for every sample, at most [`MAX_PAYLOAD_SAMPLES`].
+    /// The samples are not verified.
+    SamplePayload {
+        recipient: node::PublicKey,
+        number: validator::BlockNumber,
+        samples: Vec<u64>,
+        response: oneshot::Sender<Result<Vec<PayloadSample>, GetBlockError>>,
+    },
+    /// Reports a peer which has served invalid data.
+    /// The peer is penalized in the address book.
+    Misbehaved { peer: node::PublicKey },
+}
+
+/// Max number of the samples in a [`SyncBlocksInputMessage::SamplePayload`] request.
+pub const MAX_PAYLOAD_SAMPLES: usize = 16;
+
+/// Chunk of a block payload with its proof against the payload root in the block header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayloadSample {
+    /// Data of the chunk.
+    pub chunk: Vec<u8>,
+    /// Proof of the chunk.
+    pub proof: validator::PayloadChunkProof,
 }
 
 impl From<SyncBlocksInputMessage> for InputMessage {
@@ -47,6 +82,8 @@ impl From<SyncBlocksInputMessage> for InputMessage {
 pub struct ConsensusReq {
     /// Payload.
     pub msg: validator::Signed<validator::ConsensusMsg>,
+    /// Trace context of the span which sent the message.
+    pub trace: Option<TraceContext>,
     /// Channel that should be used to notify network actor that
     /// processing of this message has been completed.
     /// Used for rate limiting.
@@ -77,6 +114,14 @@ pub enum SyncBlocksRequest {
         // TODO: return an error in case of invalid `SyncState`?
         response: oneshot::Sender<()>,
     },
+    /// Notifies about a new round trip time measurement of a peer.
+    /// Peers with lower RTT are preferred for fetching blocks.
+    UpdatePeerRtt {
+        /// Peer that has been pinged.
+        peer: node::PublicKey,
+        /// Round trip time of the ping.
+        rtt: time::Duration,
+    },
 }
 
 /// All the messages that the Network actor sends to other actors.
@@ -1,29 +1,55 @@
 //! Network actor maintaining a pool of outbound and inbound connections to other nodes.
 use anyhow::Context as _;
 use std::sync::Arc;
-use zksync_concurrency::{ctx, ctx::channel, scope, time};
-use zksync_consensus_storage::BlockStore;
+use zksync_concurrency::{ctx, ctx::channel, scope, sync, time};
+use zksync_consensus_roles::{attester, node, validator};
+use zksync_consensus_storage::BlockStoreReader;
 use zksync_consensus_utils::pipe::ActorPipe;
 
+#[cfg(feature = "chaos")]
+pub mod chaos;
 mod config;
 pub mod consensus;
+mod dump;
 mod frame;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
 pub mod gossip;
 pub mod io;
 mod metrics;
+mod monitor;
 mod mux;
 mod noise;
+#[cfg(any(test, feature = "chaos"))]
+mod partition;
+mod pings;
 mod pool;
 mod preface;
 pub mod proto;
+mod quarantine;
+mod reconnect;
 mod rpc;
 mod state;
 pub mod testonly;
 #[cfg(test)]
 mod tests;
+mod tickets;
+mod time_source;
+mod trace;
+mod traffic;
+mod transport;
 mod watch;
 
 pub use config::*;
+pub use dump::{DumpInfo, MAX_DUMPS};
+pub use gossip::topics::{Topic, TopicHandle, Topics};
+pub use monitor::Monitor;
+pub use pings::{PeerPing, MAX_CLOCK_SKEW};
+pub use reconnect::{ConnHistories, ConnHistory, ConnState, OutboundPeer, ReconnectConfig};
+pub use rpc::get_headers::FinalHeader;
+pub use time_source::{TimeSource, MIN_NTP_SERVERS};
+pub use trace::TraceContext;
+pub use traffic::{PeerTraffic, TrafficStats};
 
 /// State of the network actor observable outside of the actor.
 pub struct Network {
@@ -40,6 +66,12 @@ pub struct Runner {
     net: Arc<Network>,
     /// Receiver of the messages from the dispatcher.
     receiver: channel::UnboundedReceiver<io::InputMessage>,
+    /// On a sentry: receiver of the messages from the network, which are either forwarded to
+    /// the backend (consensus messages) or to the dispatcher (everything else).
+    forward: Option<(
+        channel::UnboundedReceiver<io::OutputMessage>,
+        channel::UnboundedSender<io::OutputMessage>,
+    )>,
 }
 
 impl Network {
@@ -48,10 +80,17 @@ impl Network {
     pub fn new(
         ctx: &ctx::Ctx,
         cfg: Config,
-        block_store: Arc<BlockStore>,
+        block_store: BlockStoreReader,
         pipe: ActorPipe<io::InputMessage, io::OutputMessage>,
     ) -> (Arc<Self>, Runner) {
-        let gossip = gossip::Network::new(cfg, block_store, pipe.send);
+        let (sender, forward) = match &cfg.sentry {
+            Some(SentryConfig::Sentry { .. }) => {
+                let (send, recv) = channel::unbounded();
+                (send, Some((recv, pipe.send)))
+            }
+            _ => (pipe.send, None),
+        };
+        let gossip = gossip::Network::new(ctx, cfg, block_store, sender);
         let consensus = consensus::Network::new(ctx, gossip.clone());
         let net = Arc::new(Self { gossip, consensus });
         (
@@ -59,13 +98,79 @@ impl Network {
             Runner {
                 net,
                 receiver: pipe.recv,
+                forward,
             },
         )
     }
 
-    /// Registers metrics for this state.
+    /// Applies the new reloadable part of the config, without restarting the network.
+    pub async fn reload(&self, ctx: &ctx::Ctx, cfg: ReloadableConfig) {
+        self.gossip.reload(ctx, cfg).await;
+    }
+
+    /// Gossips the vote of this node's attester for a batch.
+    /// Votes of the attesters outside of the genesis committee are ignored.
+    pub async fn push_batch_vote(
+        &self,
+        vote: Arc<attester::Signed<attester::Batch>>,
+    ) -> anyhow::Result<()> {
+        self.gossip
+            .batch_votes
+            .update(&self.gossip.genesis(), &[vote])
+            .await
+    }
+
+    /// Subscribes to the certificate of the highest batch signed by enough attesters.
+    pub fn subscribe_batch_qc(&self) -> sync::watch::Receiver<Option<Arc<attester::BatchQC>>> {
+        self.gossip.batch_votes.subscribe_qc()
+    }
+
+    /// Gossips the announcement of this node's validator that it supports a protocol upgrade.
+    /// Announcements of the keys outside of the genesis validator set are ignored.
+    pub async fn push_upgrade_vote(
+        &self,
+        vote: Arc<validator::Signed<validator::ProtocolUpgrade>>,
+    ) -> anyhow::Result<()> {
+        self.gossip
+            .upgrade_votes
+            .update(&self.gossip.genesis(), &[vote])
+            .await
+    }
+
+    /// Subscribes to the certificate of the highest protocol upgrade
+    /// announced by enough validators. The validators propose to commit
+    /// the certificate to the chain, see `bft::Config::protocol_upgrade`.
+    pub fn subscribe_upgrade_qc(
+        &self,
+    ) -> sync::watch::Receiver<Option<Arc<validator::ProtocolUpgradeQC>>> {
+        self.gossip.upgrade_votes.subscribe_qc()
+    }
+
+    /// Subscribes to the recent states of the persistent outbound connections
+    /// (to the static gossip peers and to the validators).
+    pub fn subscribe_connections(&self) -> sync::watch::Receiver<ConnHistories> {
+        self.gossip.reconnect.subscribe()
+    }
+
+    /// Fetches the headers of up to `count` consecutive blocks starting at `first`
+    /// from the given peer, without the payloads. Used by the light clients.
+    /// The headers are NOT verified.
+    pub async fn get_headers(
+        &self,
+        ctx: &ctx::Ctx,
+        peer: &node::PublicKey,
+        first: validator::BlockNumber,
+        count: usize,
+    ) -> anyhow::Result<Vec<FinalHeader>> {
+        self.gossip.get_headers(ctx, peer, first, count).await
+    }
+
+    /// Registers metrics for this state. A restarted network replaces the previous one
+    /// in `Config::monitor`, so that the metrics keep reporting the running network.
     pub fn register_metrics(self: &Arc<Self>) {
-        metrics::NetworkGauges::register(Arc::downgrade(self));
+        let monitor = &self.gossip.cfg.monitor;
+        monitor.set_network(Arc::downgrade(self));
+        metrics::NetworkGauges::register(monitor.downgrade());
     }
 
     /// Handles a dispatcher message.
@@ -82,12 +187,21 @@ impl Network {
         match message {
             io::InputMessage::Consensus(message) => {
                 let consensus = self.consensus.as_ref().context("not a validator node")?;
+                self.gossip
+                    .high_qc
+                    .observe(&self.gossip.genesis(), &message.message);
                 let ctx = &ctx.with_timeout(CONSENSUS_MSG_TIMEOUT);
                 match message.recipient {
                     io::Target::Validator(key) => {
-                        consensus.send(ctx, &key, message.message).await?
+                        consensus
+                            .send(ctx, &key, message.message, message.trace)
+                            .await?
+                    }
+                    io::Target::Broadcast => {
+                        consensus
+                            .broadcast(ctx, message.message, message.trace)
+                            .await?
                     }
-                    io::Target::Broadcast => consensus.broadcast(ctx, message.message).await?,
                 }
             }
             io::InputMessage::SyncBlocks(io::SyncBlocksInputMessage::GetBlock {
@@ -102,6 +216,42 @@ impl Network {
                     Err(err) => Err(io::GetBlockError::Internal(err)),
                 });
             }
+            io::InputMessage::SyncBlocks(io::SyncBlocksInputMessage::GetJustification {
+                recipient,
+                number,
+                response,
+            }) => {
+                let ctx = &ctx.with_timeout(GET_BLOCK_TIMEOUT);
+                let res = self.gossip.get_justification(ctx, &recipient, number).await;
+                let _ = response.send(match res {
+                    Ok(Some(justification)) => Ok(justification),
+                    Ok(None) => Err(io::GetBlockError::NotAvailable),
+                    Err(err) => Err(io::GetBlockError::Internal(err)),
+                });
+            }
+            io::InputMessage::SyncBlocks(io::SyncBlocksInputMessage::SamplePayload {
+                recipient,
+                number,
+                samples,
+                response,
+            }) => {
+                let ctx = &ctx.with_timeout(GET_BLOCK_TIMEOUT);
+                let res = self
+                    .gossip
+                    .sample_payload(ctx, &recipient, number, samples)
+                    .await;
+                let _ = response.send(match res {
+                    Ok(Some(samples)) => Ok(samples),
+                    Ok(None) => Err(io::GetBlockError::NotAvailable),
+                    Err(err) => Err(io::GetBlockError::Internal(err)),
+                });
+            }
+            io::InputMessage::SyncBlocks(io::SyncBlocksInputMessage::Misbehaved { peer }) => {
+                tracing::info!("peer {peer:?} has served invalid data");
+                self.gossip
+                    .address_book
+                    .penalize(&peer, gossip::address_book::MISBEHAVIOR_PENALTY);
+            }
         }
         Ok(())
     }
@@ -110,13 +260,39 @@ impl Network {
 impl Runner {
     /// Runs the network actor.
     pub async fn run(mut self, ctx: &ctx::Ctx) -> anyhow::Result<()> {
-        let mut listener = self
-            .net
+        let mut listener = transport::bind(
+            &self.net.gossip.cfg.transport,
+            &self.net.gossip.cfg.server_addr,
+        )
+        .context("server_addr.bind()")?;
+        self.net
+            .gossip
+            .cfg
+            .reconnect
+            .verify()
+            .context("reconnect")?;
+        self.net
             .gossip
             .cfg
-            .server_addr
-            .bind()
-            .context("server_addr.bind()")?;
+            .rpc
+            .push_block_store_state_schedule
+            .verify()
+            .context("push_block_store_state_schedule")?;
+        if let Some(ttl) = self.net.gossip.cfg.session_ticket_ttl {
+            anyhow::ensure!(ttl.is_positive(), "session_ticket_ttl has to be positive");
+        }
+        match &self.net.gossip.cfg.sentry {
+            Some(SentryConfig::Sentry { .. }) => anyhow::ensure!(
+                self.net.gossip.cfg.validator_key.is_none(),
+                "sentry cannot hold a validator key"
+            ),
+            Some(SentryConfig::Backend { .. }) => anyhow::ensure!(
+                self.net.gossip.cfg.validator_key.is_some(),
+                "sentry backend requires a validator key"
+            ),
+            None => {}
+        }
+        let forward = self.forward.take();
 
         scope::run!(ctx, |ctx, s| async {
             // Handle incoming messages.
@@ -133,37 +309,81 @@ impl Runner {
                 Ok(())
             });
 
-            // Maintain static gossip connections.
-            for (peer, addr) in &self.net.gossip.cfg.gossip.static_outbound {
-                s.spawn(async {
-                    loop {
-                        let run_result =
-                            self.net.gossip.run_outbound_stream(ctx, peer, *addr).await;
-                        if let Err(err) = run_result {
-                            tracing::info!("gossip.run_outbound_stream(): {err:#}");
-                        }
-                        if let Err(ctx::Canceled) = ctx.sleep(CONNECT_RETRY).await {
-                            return Ok(());
+            // Forward the consensus messages received by the sentry to the backend.
+            if let Some((mut recv, send)) = forward {
+                let c = self
+                    .net
+                    .consensus
+                    .as_ref()
+                    .context("sentry without a backend")?;
+                s.spawn(async move {
+                    while let Ok(message) = recv.recv(ctx).await {
+                        match message {
+                            io::OutputMessage::Consensus(req) => {
+                                s.spawn(async move {
+                                    c.forward_to_backend(ctx, req).await;
+                                    Ok(())
+                                });
+                            }
+                            message => send.send(message),
                         }
                     }
+                    Ok(())
                 });
             }
 
+            // Write the wire dumps.
+            s.spawn(async {
+                self.net.gossip.cfg.monitor.wire_dumps().run(ctx).await;
+                Ok(())
+            });
+
+            // Maintain static gossip connections.
+            s.spawn(async {
+                self.net.gossip.run_static_outbound(ctx).await;
+                Ok(())
+            });
+
+            // Maintain connections to the discovered peers.
+            s.spawn(async {
+                self.net.gossip.run_dialer(ctx).await;
+                Ok(())
+            });
+
             if let Some(c) = &self.net.consensus {
-                // If we are active validator ...
-                if c.gossip.genesis().validators.contains(&c.key.public()) {
+                if let Some(SentryConfig::Backend {
+                    sentry_addr,
+                    sentry_key,
+                }) = &c.gossip.cfg.sentry
+                {
+                    // Backend is connected only to its sentry.
+                    s.spawn(async {
+                        c.maintain_sentry_link(ctx, *sentry_addr, sentry_key).await;
+                        Ok(())
+                    });
+                } else if c.gossip.genesis().accepts_validator_key(&c.key.public()) {
+                    // If we are active validator ...
                     // Maintain outbound connections.
                     for peer in c.clients.keys() {
                         s.spawn(async {
                             c.maintain_connection(ctx, peer).await;
                             Ok(())
                         });
+                        s.spawn(async {
+                            c.run_outbound_queue(ctx, peer).await;
+                            Ok(())
+                        });
                     }
                     // Announce IP periodically.
                     s.spawn(async {
                         c.run_address_announcer(ctx).await;
                         Ok(())
                     });
+                    // Send heartbeats periodically.
+                    s.spawn(async {
+                        c.run_heartbeats(ctx).await;
+                        Ok(())
+                    });
                 }
             }
 
@@ -190,6 +410,20 @@ impl Runner {
                                     .await
                                     .context("gossip.run_inbound_stream()")?;
                             }
+                            preface::Endpoint::SentryLink => {
+                                if let Some(c) = &self.net.consensus {
+                                    c.run_backend_stream(ctx, stream)
+                                        .await
+                                        .context("consensus.run_backend_stream()")?;
+                                }
+                            }
+                            preface::Endpoint::GenesisNet => {
+                                self.net
+                                    .gossip
+                                    .serve_genesis(ctx, stream)
+                                    .await
+                                    .context("gossip.serve_genesis()")?;
+                            }
                         }
                         anyhow::Ok(())
                     }
@@ -1,57 +1,111 @@
 //! General-purpose network metrics.
 
-use crate::Network;
+use crate::{frame, monitor::WeakMonitor, transport, Transport};
 use std::{
+    collections::BTreeSet,
     net::SocketAddr,
     pin::Pin,
-    sync::Weak,
+    sync::Mutex,
     task::{ready, Context, Poll},
 };
 use vise::{
-    Collector, Counter, EncodeLabelSet, EncodeLabelValue, Family, Gauge, GaugeGuard, Metrics, Unit,
+    Collector, Counter, EncodeLabelSet, EncodeLabelValue, Family, Gauge, GaugeGuard, LabeledFamily,
+    Metrics, Unit,
 };
-use zksync_concurrency::{ctx, io, net};
+use zksync_concurrency::{ctx, io, time};
+use zksync_consensus_crypto::TextFmt;
 
-/// Metered TCP stream.
+/// Max number of the distinct peers (and validators) labelled in the metrics.
+/// Metrics are never unregistered, so without the bound a node accepting many
+/// dynamic peers (or going through many validator key rotations) would export
+/// an ever growing number of time series.
+const MAX_KEY_LABELS: usize = 256;
+/// Label under which the metrics of the peers above `MAX_KEY_LABELS` are aggregated.
+pub(crate) const OTHER_KEYS_LABEL: &str = "other";
+
+/// Label of a peer (or validator) in the per-peer metrics: its public key
+/// truncated to the first 16 hex digits, which keeps the label readable.
+/// Once `MAX_KEY_LABELS` distinct keys have been labelled, the new keys
+/// get the `OTHER_KEYS_LABEL` label.
+pub(crate) fn key_label(key: &impl TextFmt) -> String {
+    /// Labels handed out so far. The metrics they are used in are process-global too.
+    static LABELS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+    let key = key.encode();
+    let label = match key.rsplit_once(':') {
+        Some((prefix, hex)) => format!("{prefix}:{}", &hex[..hex.len().min(16)]),
+        None => key,
+    };
+    let mut labels = LABELS.lock().unwrap();
+    if labels.len() < MAX_KEY_LABELS {
+        labels.insert(label.clone());
+    }
+    if labels.contains(&label) {
+        label
+    } else {
+        OTHER_KEYS_LABEL.to_string()
+    }
+}
+
+/// Metered transport stream.
 #[pin_project::pin_project]
 pub(crate) struct MeteredStream {
     #[pin]
-    stream: net::tcp::Stream,
+    stream: transport::Stream,
     _active: GaugeGuard,
+    /// Label of the peer, if the traffic is reported per peer.
+    peer: Option<String>,
+    _peer_active: Option<GaugeGuard<usize>>,
 }
 
 impl MeteredStream {
-    /// Opens a TCP connection to a remote host and returns a metered stream.
+    /// Opens a connection to a remote host and returns a metered stream.
     pub(crate) async fn connect(
         ctx: &ctx::Ctx,
+        transport: &Transport,
         addr: SocketAddr,
     ) -> ctx::OrCanceled<io::Result<Self>> {
-        let io_result = net::tcp::connect(ctx, addr).await?;
+        let io_result = transport::connect(ctx, transport, addr).await?;
         Ok(io_result.map(|stream| Self::new(stream, Direction::Outbound)))
     }
 
     /// Accepts an inbound connection and returns a metered stream.
     pub(crate) async fn listen(
         ctx: &ctx::Ctx,
-        listener: &mut net::tcp::Listener,
+        listener: &mut transport::Listener,
     ) -> ctx::OrCanceled<io::Result<Self>> {
-        let io_result = net::tcp::accept(ctx, listener).await?;
+        let io_result = transport::accept(ctx, listener).await?;
         Ok(io_result.map(|stream| Self::new(stream, Direction::Inbound)))
     }
 
     #[cfg(test)]
-    pub(crate) async fn test_pipe(ctx: &ctx::Ctx) -> (Self, Self) {
-        let (outbound_stream, inbound_stream) = net::tcp::testonly::pipe(ctx).await;
+    pub(crate) fn test_pipe() -> (Self, Self) {
+        let (outbound_stream, inbound_stream) = transport::testonly::pipe();
         let outbound_stream = Self::new(outbound_stream, Direction::Outbound);
         let inbound_stream = Self::new(inbound_stream, Direction::Inbound);
         (outbound_stream, inbound_stream)
     }
 
-    fn new(stream: net::tcp::Stream, direction: Direction) -> Self {
+    /// Address of the remote end of the stream.
+    pub(crate) fn peer_addr(&self) -> Option<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    /// Starts reporting the traffic of the stream per peer,
+    /// once the peer has been authenticated.
+    pub(crate) fn set_peer(&mut self, peer: String, now: time::Utc) {
+        let connected_at = (now - time::UNIX_EPOCH).whole_seconds();
+        PEER_METRICS.connected_at[&peer].set(connected_at.try_into().unwrap_or(0));
+        self._peer_active = Some(PEER_METRICS.connections[&peer].inc_guard(1));
+        self.peer = Some(peer);
+    }
+
+    fn new(stream: transport::Stream, direction: Direction) -> Self {
         TCP_METRICS.established[&direction].inc();
         Self {
             stream,
             _active: TCP_METRICS.active[&direction].inc_guard(1),
+            peer: None,
+            _peer_active: None,
         }
     }
 }
@@ -68,6 +122,9 @@ impl io::AsyncRead for MeteredStream {
         let res = this.stream.poll_read(cx, buf);
         let after = buf.remaining();
         TCP_METRICS.received.inc_by((before - after) as u64);
+        if let Some(peer) = this.peer {
+            PEER_METRICS.received[&*peer].inc_by((before - after) as u64);
+        }
         res
     }
 }
@@ -78,6 +135,9 @@ impl io::AsyncWrite for MeteredStream {
         let this = self.project();
         let res = ready!(this.stream.poll_write(cx, buf))?;
         TCP_METRICS.sent.inc_by(res as u64);
+        if let Some(peer) = this.peer {
+            PEER_METRICS.sent[&*peer].inc_by(res as u64);
+        }
         Poll::Ready(Ok(res))
     }
 
@@ -122,6 +182,77 @@ struct TcpMetrics {
 #[vise::register]
 static TCP_METRICS: vise::Global<TcpMetrics> = vise::Global::new();
 
+/// Labels of the RPCs served to a peer.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, EncodeLabelSet)]
+pub(crate) struct PeerRpcLabels {
+    pub(crate) peer: String,
+    pub(crate) method: &'static str,
+}
+
+/// Metrics reported per peer, if enabled with `Config::per_peer_metrics`.
+#[derive(Debug, Metrics)]
+#[metrics(prefix = "network_peer")]
+pub(crate) struct PeerMetrics {
+    /// Bytes sent to the peer.
+    #[metrics(unit = Unit::Bytes, labels = ["peer"])]
+    sent: LabeledFamily<String, Counter>,
+    /// Bytes received from the peer.
+    #[metrics(unit = Unit::Bytes, labels = ["peer"])]
+    received: LabeledFamily<String, Counter>,
+    /// Number of the currently active connections with the peer.
+    #[metrics(labels = ["peer"])]
+    connections: LabeledFamily<String, Gauge<usize>>,
+    /// UNIX timestamp (in seconds) of the latest connection established with the peer.
+    /// While `connections` is positive, `time() - connected_at` is the connection uptime.
+    #[metrics(labels = ["peer"])]
+    connected_at: LabeledFamily<String, Gauge<u64>>,
+    /// RPCs served to the peer, by method.
+    pub(crate) rpc_calls: Family<PeerRpcLabels, Counter>,
+}
+
+/// Per-peer metrics instance.
+#[vise::register]
+pub(crate) static PEER_METRICS: vise::Global<PeerMetrics> = vise::Global::new();
+
+/// Protocol layer at which a frame error has occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EncodeLabelValue)]
+#[metrics(rename_all = "snake_case")]
+pub(crate) enum FrameErrorLayer {
+    /// Handshake of a gossip or consensus connection.
+    Handshake,
+    /// RPC call over an established connection.
+    Rpc,
+}
+
+impl FrameErrorLayer {
+    /// Counts the error in the metrics and passes it through.
+    pub(crate) fn observe(self, err: frame::Error) -> frame::Error {
+        ERROR_METRICS.frame_errors[&FrameErrorLabels {
+            layer: self,
+            class: err.class(),
+        }]
+            .inc();
+        err
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EncodeLabelSet)]
+struct FrameErrorLabels {
+    layer: FrameErrorLayer,
+    class: frame::ErrorClass,
+}
+
+/// Metrics of the failures of the peer connections.
+#[derive(Debug, Metrics)]
+#[metrics(prefix = "network")]
+struct ErrorMetrics {
+    /// Errors of sending or receiving frames, by the protocol layer and the error class.
+    frame_errors: Family<FrameErrorLabels, Counter>,
+}
+
+#[vise::register]
+static ERROR_METRICS: vise::Global<ErrorMetrics> = vise::Global::new();
+
 /// General-purpose network metrics exposed via a collector.
 #[derive(Debug, Metrics)]
 #[metrics(prefix = "network")]
@@ -134,33 +265,48 @@ pub(crate) struct NetworkGauges {
     consensus_inbound_connections: Gauge<usize>,
     /// Number of active outbound consensus connections.
     consensus_outbound_connections: Gauge<usize>,
+    /// Number of validators in the committee.
+    consensus_validators: Gauge<usize>,
+    /// Estimated number of online validators, based on the received heartbeats.
+    /// Compare with `consensus_validators` to tell a stalled leader from an offline quorum.
+    consensus_online_validators: Gauge<usize>,
+    /// Number of the node addresses in the address book.
+    gossip_address_book_entries: Gauge<usize>,
 }
 
 impl NetworkGauges {
-    /// Registers a metrics collector for the specified state.
-    pub(crate) fn register(state_ref: Weak<Network>) {
+    /// Registers a metrics collector reporting the network currently running under `monitor`.
+    /// Only the first registration in the process succeeds, so with multiple networks
+    /// per process (e.g. multiple executors) the gauges report the first one.
+    pub(crate) fn register(monitor: WeakMonitor) {
         #[vise::register]
         static COLLECTOR: Collector<Option<NetworkGauges>> = Collector::new();
 
         let register_result = COLLECTOR.before_scrape(move || {
-            state_ref.upgrade().map(|state| {
+            monitor.upgrade().and_then(|m| m.network()).map(|state| {
                 let gauges = NetworkGauges::default();
                 let len = state.gossip.inbound.subscribe().borrow().current().len();
                 gauges.gossip_inbound_connections.set(len);
                 let len = state.gossip.outbound.subscribe().borrow().current().len();
                 gauges.gossip_outbound_connections.set(len);
+                let len = state.gossip.address_book.len();
+                gauges.gossip_address_book_entries.set(len);
                 if let Some(consensus_state) = &state.consensus {
                     let len = consensus_state.inbound.subscribe().borrow().current().len();
                     gauges.consensus_inbound_connections.set(len);
                     let subscriber = consensus_state.outbound.subscribe();
                     let len = subscriber.borrow().current().len();
                     gauges.consensus_outbound_connections.set(len);
+                    let len = state.gossip.genesis().validators.len();
+                    gauges.consensus_validators.set(len);
+                    let len = consensus_state.online_validators();
+                    gauges.consensus_online_validators.set(len);
                 }
                 gauges
             })
         });
         if register_result.is_err() {
-            tracing::warn!("Failed registering network metrics collector: already registered");
+            tracing::debug!("Network metrics collector is already registered");
         }
     }
 }
@@ -0,0 +1,100 @@
+//! State of the network observable from outside of the network actor
+//! (e.g. by the RPC server of the node), which survives the restarts of the actor.
+use crate::{dump, pings, traffic, DumpInfo, Network, PeerPing, PeerTraffic};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+};
+use zksync_concurrency::{ctx, time};
+
+/// Observable state of the network: ping measurements and traffic of the peers,
+/// the wire dumps and the network reported in the metrics.
+/// Clones share the state, so that the owner of the config (e.g. the executor)
+/// keeps observing the network after it is restarted.
+#[derive(Debug, Clone, Default)]
+pub struct Monitor(Arc<Inner>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Latest ping measurements of the peers.
+    pings: pings::Pings,
+    /// Traffic of the gossip connections.
+    traffic: traffic::Registry,
+    /// Wire dumps of the connections.
+    dumps: dump::Dumps,
+    /// Currently running network.
+    network: Mutex<Weak<Network>>,
+}
+
+impl Monitor {
+    /// Latest ping measurements of the currently connected gossip peers, ordered by the peer key.
+    pub fn peer_pings(&self) -> Vec<PeerPing> {
+        self.0.pings.get()
+    }
+
+    /// Traffic of the currently open gossip connections, ordered by the peer key.
+    pub fn peer_traffic(&self) -> Vec<PeerTraffic> {
+        self.0.traffic.get()
+    }
+
+    /// Starts dumping the messages exchanged with `peer` (its node or validator key,
+    /// as encoded with `TextFmt`) to a file in `dir`, until `until`.
+    /// Restarting a dump of the same peer truncates its file. Returns the path of the file.
+    /// Fails if `MAX_DUMPS` dumps are already active.
+    pub async fn start_dump(
+        &self,
+        ctx: &ctx::Ctx,
+        peer: &str,
+        dir: &Path,
+        until: time::Utc,
+    ) -> ctx::Result<PathBuf> {
+        self.0.dumps.start(ctx, peer, dir, until).await
+    }
+
+    /// Stops dumping the messages exchanged with `peer`. Returns false if there was no such dump.
+    pub async fn stop_dump(&self, ctx: &ctx::Ctx, peer: &str) -> ctx::OrCanceled<bool> {
+        self.0.dumps.stop(ctx, peer).await
+    }
+
+    /// Returns the dumps which are active at `now`.
+    pub fn dumps(&self, now: time::Utc) -> Vec<DumpInfo> {
+        self.0.dumps.list(now)
+    }
+
+    pub(crate) fn wire_dumps(&self) -> &dump::Dumps {
+        &self.0.dumps
+    }
+
+    pub(crate) fn pings(&self) -> &pings::Pings {
+        &self.0.pings
+    }
+
+    pub(crate) fn traffic(&self) -> &traffic::Registry {
+        &self.0.traffic
+    }
+
+    /// Replaces the currently running network.
+    pub(crate) fn set_network(&self, network: Weak<Network>) {
+        *self.0.network.lock().unwrap() = network;
+    }
+
+    /// Currently running network, if any.
+    pub(crate) fn network(&self) -> Option<Arc<Network>> {
+        self.0.network.lock().unwrap().upgrade()
+    }
+
+    /// Weak reference to the monitor.
+    pub(crate) fn downgrade(&self) -> WeakMonitor {
+        WeakMonitor(Arc::downgrade(&self.0))
+    }
+}
+
+/// Weak reference to a [`Monitor`], which doesn't keep its state alive.
+pub(crate) struct WeakMonitor(Weak<Inner>);
+
+impl WeakMonitor {
+    /// Returns the monitor, unless its state has been dropped.
+    pub(crate) fn upgrade(&self) -> Option<Monitor> {
+        self.0.upgrade().map(Monitor)
+    }
+}
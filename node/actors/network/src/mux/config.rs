@@ -1,4 +1,5 @@
 use super::StreamId;
+use anyhow::Context as _;
 use zksync_concurrency::sync;
 
 /// Maximal frame size.
@@ -44,6 +45,11 @@ pub(crate) struct Config {
     /// (mux protocol requires to write full frames, but we can close the
     /// transient stream after every frame).
     pub(crate) write_frame_size: u64,
+
+    /// Keepalive of the transport stream.
+    /// If set and the peer supports it, KEEPALIVE frames are sent every `interval`
+    /// and the connection is dropped if nothing is received for `timeout`.
+    pub(crate) keepalive: Option<crate::KeepaliveConfig>,
 }
 
 impl Config {
@@ -58,6 +64,9 @@ impl Config {
         if self.read_frame_count > MAX_READ_FRAME_COUNT {
             anyhow::bail!("read_frame_count > {MAX_READ_FRAME_COUNT}");
         }
+        if let Some(keepalive) = &self.keepalive {
+            keepalive.verify().context("keepalive")?;
+        }
         Ok(())
     }
 }
@@ -2,13 +2,17 @@ use super::CapabilityId;
 use crate::proto::mux as proto;
 use anyhow::Context as _;
 use std::collections::HashMap;
-use zksync_protobuf::required;
+use zksync_protobuf::{required, ProtoFmt};
 
-pub(super) struct Handshake {
+/// Multiplexer handshake, exchanging the configs of the reusable streams.
+pub(crate) struct Handshake {
     /// Maximal supported number of the accept streams per capability.
-    pub(super) accept_max_streams: HashMap<CapabilityId, u32>,
+    pub(crate) accept_max_streams: HashMap<CapabilityId, u32>,
     /// Maximal supported number of the connect streams per capability.
-    pub(super) connect_max_streams: HashMap<CapabilityId, u32>,
+    pub(crate) connect_max_streams: HashMap<CapabilityId, u32>,
+    /// Keepalive config of the sender, if it sends KEEPALIVE frames.
+    /// Peers which don't support keepalive don't set it.
+    pub(crate) keepalive: Option<crate::KeepaliveConfig>,
 }
 
 fn read_max_streams(
@@ -37,7 +41,25 @@ fn build_capabilities(
         .collect()
 }
 
-impl zksync_protobuf::ProtoFmt for Handshake {
+/// Reads the keepalive config of the peer.
+/// Peers announcing keepalive without the config are treated as not supporting it,
+/// since it is unknown how often they send the KEEPALIVE frames.
+fn read_keepalive(r: &proto::Handshake) -> anyhow::Result<Option<crate::KeepaliveConfig>> {
+    if !r.keepalive.unwrap_or(false) {
+        return Ok(None);
+    }
+    let (Some(interval), Some(timeout)) = (&r.keepalive_interval, &r.keepalive_timeout) else {
+        return Ok(None);
+    };
+    let cfg = crate::KeepaliveConfig {
+        interval: ProtoFmt::read(interval).context("interval")?,
+        timeout: ProtoFmt::read(timeout).context("timeout")?,
+    };
+    cfg.verify()?;
+    Ok(Some(cfg))
+}
+
+impl ProtoFmt for Handshake {
     type Proto = proto::Handshake;
 
     fn max_size() -> usize {
@@ -48,6 +70,7 @@ impl zksync_protobuf::ProtoFmt for Handshake {
         Ok(Self {
             accept_max_streams: read_max_streams(&r.accept).context("accept")?,
             connect_max_streams: read_max_streams(&r.connect).context("connect")?,
+            keepalive: read_keepalive(r).context("keepalive")?,
         })
     }
 
@@ -55,6 +78,9 @@ impl zksync_protobuf::ProtoFmt for Handshake {
         Self::Proto {
             accept: build_capabilities(&self.accept_max_streams),
             connect: build_capabilities(&self.connect_max_streams),
+            keepalive: Some(self.keepalive.is_some()),
+            keepalive_interval: self.keepalive.as_ref().map(|k| k.interval.build()),
+            keepalive_timeout: self.keepalive.as_ref().map(|k| k.timeout.build()),
         }
     }
 }
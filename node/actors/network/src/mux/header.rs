@@ -12,6 +12,9 @@ impl FrameKind {
     pub(super) const OPEN: Self = Self(0b0000000000000000);
     pub(super) const DATA: Self = Self(0b0100000000000000);
     pub(super) const CLOSE: Self = Self(0b1000000000000000);
+    /// Frame which doesn't belong to any stream, sent to keep the connection alive.
+    /// Its stream kind and stream id are ignored.
+    pub(super) const KEEPALIVE: Self = Self(0b1100000000000000);
 }
 
 impl StreamKind {
@@ -50,11 +50,15 @@
 //! Communication on each reusable stream is independent, except for the shared capacity of the
 //! read buffers (TODO(gprusak): make the read buffers also independent).
 //!
-//! There are 3 kinds of frames exchanged over the transport stream:
+//! There are 4 kinds of frames exchanged over the transport stream:
 //! * OPEN frame: used to establish a new transient stream
 //! * DATA frame: used to send the actual data over the transient stream
 //! * CLOSE frame: used to indicate end of the transient stream (i.e. no more DATA frames will be
 //!   sent).
+//! * KEEPALIVE frame: doesn't belong to any stream, sent periodically if both peers
+//!   announced keepalive support in the handshake, at the interval negotiated there.
+//!   It allows to detect half-open connections (NAT timeouts, crashed peers)
+//!   even when no transient streams are active.
 //!
 //! Multipexer protocol:
 //! 1. peer A and B exchange their multiplexer configs.
@@ -77,10 +81,11 @@
 //! therefore stopping the peer from sending any DATA frames.
 //! This can be used to implement a rate limiting strategy that
 //! both sides of the connection can enforce.
-use crate::{frame, noise::bytes};
+use crate::{dump, frame, noise::bytes, traffic::Traffic};
+use ::bytes::Buf as _;
 use anyhow::Context as _;
 use std::{collections::BTreeMap, sync::Arc};
-use zksync_concurrency::{ctx, ctx::channel, io, scope, sync};
+use zksync_concurrency::{ctx, ctx::channel, io, scope, sync, time};
 use zksync_protobuf::ProtoFmt as _;
 
 mod config;
@@ -92,7 +97,7 @@ mod tests;
 mod transient_stream;
 
 pub(crate) use config::*;
-use handshake::Handshake;
+pub(crate) use handshake::Handshake;
 use header::{FrameKind, Header, StreamId, StreamKind};
 pub(crate) use reusable_stream::*;
 pub(crate) use transient_stream::*;
@@ -107,6 +112,16 @@ pub(crate) struct Mux {
     pub(crate) accept: BTreeMap<CapabilityId, Arc<StreamQueue>>,
     /// StreamQueues of "connect" streams per capability.
     pub(crate) connect: BTreeMap<CapabilityId, Arc<StreamQueue>>,
+    /// Traffic of the connection, per capability.
+    pub(crate) traffic: Arc<Traffic>,
+    /// Filter of the sent frames, used to simulate network partitions.
+    #[cfg(any(test, feature = "chaos"))]
+    pub(crate) filter: Option<crate::partition::Filter>,
+    /// Dumper of the messages exchanged over the connection.
+    pub(crate) dump: Option<dump::Dumper>,
+    /// Faults injected into the messages sent over the connection.
+    #[cfg(feature = "chaos")]
+    pub(crate) chaos: Option<crate::chaos::Config>,
 }
 
 fn saturating_sum(iter: impl Iterator<Item = u32>) -> u32 {
@@ -127,6 +142,7 @@ impl Mux {
                 .iter()
                 .map(|(id, q)| (*id, q.max_streams))
                 .collect(),
+            keepalive: self.cfg.keepalive,
         }
     }
 
@@ -155,6 +171,8 @@ pub(crate) enum RunError {
     Closed,
     #[error("protocol: {0:#}")]
     Protocol(anyhow::Error),
+    #[error("peer is unresponsive")]
+    DeadPeer,
     #[error(transparent)]
     IO(#[from] io::Error),
 }
@@ -168,7 +186,7 @@ impl Mux {
         handshake: &Handshake,
         write_send: &channel::Sender<WriteCommand>,
         flush: &Arc<sync::Notify>,
-    ) -> Vec<channel::UnboundedSender<Frame>> {
+    ) -> Vec<(CapabilityId, channel::UnboundedSender<Frame>)> {
         let mut streams = vec![];
         let (queues, peer) = match stream_kind {
             StreamKind::ACCEPT => (&self.accept, &handshake.connect_max_streams),
@@ -177,10 +195,11 @@ impl Mux {
         };
         for (cap, queue) in queues {
             let max_streams = std::cmp::min(queue.max_streams, *peer.get(cap).unwrap_or(&0));
+            queue.streams.send_replace(Some(max_streams));
             for _ in 0..max_streams {
                 let (read_send, read_recv) = channel::unbounded();
                 let stream_id = StreamId::new(streams.len() as u16);
-                streams.push(read_send);
+                streams.push((*cap, read_send));
                 let stream = ReusableStream {
                     read: ReadReusableStream::new(read_recv),
                     write: WriteReusableStream::new(
@@ -191,6 +210,9 @@ impl Mux {
                         flush.clone(),
                     ),
                     stream_queue: queue.clone(),
+                    dump: self.dump.clone(),
+                    #[cfg(feature = "chaos")]
+                    chaos: self.chaos.clone(),
                 };
                 scope.spawn_bg(stream.run(ctx));
             }
@@ -202,15 +224,28 @@ impl Mux {
         &self,
         ctx: &ctx::Ctx,
         mut read: impl io::AsyncRead + Send + Unpin,
-        accept_streams: Vec<channel::UnboundedSender<Frame>>,
-        connect_streams: Vec<channel::UnboundedSender<Frame>>,
+        accept_streams: Vec<(CapabilityId, channel::UnboundedSender<Frame>)>,
+        connect_streams: Vec<(CapabilityId, channel::UnboundedSender<Frame>)>,
+        dead_peer_timeout: Option<time::Duration>,
     ) -> Result<(), RunError> {
         let count_sem = Arc::new(sync::Semaphore::new(self.cfg.read_frame_count as usize));
         let size_sem = Arc::new(sync::Semaphore::new(self.cfg.read_buffer_size as usize));
         loop {
             let mut header = [0u8, 2];
-            io::read_exact(ctx, &mut read, &mut header).await??;
+            match dead_peer_timeout {
+                None => io::read_exact(ctx, &mut read, &mut header).await??,
+                Some(timeout) => {
+                    match io::read_exact(&ctx.with_timeout(timeout), &mut read, &mut header).await {
+                        Ok(res) => res?,
+                        Err(_) if ctx.is_active() => return Err(RunError::DeadPeer),
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+            };
             let header = Header::from(header);
+            if header.frame_kind() == FrameKind::KEEPALIVE {
+                continue;
+            }
             // If the frame was sent by the inbound end of the stream, then it should be
             // handled by the outbound end, and vice versa.
             let streams = match header.stream_kind() {
@@ -218,7 +253,7 @@ impl Mux {
                 StreamKind::CONNECT => &accept_streams,
                 _ => unreachable!("bad StreamKind"),
             };
-            let stream = streams
+            let (cap, stream) = streams
                 .get(header.stream_id().0 as usize)
                 .with_context(|| format!("bad stream id {:?}", header.stream_id()))
                 .map_err(RunError::Protocol)?;
@@ -239,6 +274,7 @@ impl Mux {
                     let mut length = [0u8, 2];
                     io::read_exact(ctx, &mut read, &mut length).await??;
                     let mut length = u16::from_le_bytes(length) as usize;
+                    self.traffic.received(*cap, length);
                     // Split into frames of `read_frame_size` size.
                     while length > 0 {
                         let size = std::cmp::min(length, self.cfg.read_frame_size as usize);
@@ -282,7 +318,12 @@ impl Mux {
             frame::recv_proto(ctx, &mut read, Handshake::max_size()).await
         })
         .await
-        .map_err(RunError::Protocol)?;
+        .map_err(|err| RunError::Protocol(err.into()))?;
+        // Keepalive is used only if both peers support it.
+        let keepalive = match (&self.cfg.keepalive, &handshake.keepalive) {
+            (Some(cfg), Some(peer)) => Some(cfg.negotiate(peer)),
+            _ => None,
+        };
 
         let (write_send, write_recv) = channel::bounded(1);
         let flush = Arc::new(sync::Notify::new());
@@ -293,20 +334,43 @@ impl Mux {
             let connect_streams = self
                 .spawn_streams(ctx, s, StreamKind::CONNECT, &handshake, &write_send, &flush)
                 .await;
+            let accept_caps: Vec<_> = accept_streams.iter().map(|(cap, _)| *cap).collect();
+            let connect_caps: Vec<_> = connect_streams.iter().map(|(cap, _)| *cap).collect();
 
             s.spawn_bg::<()>(async {
                 let mut write = write;
                 let mut write_recv = write_recv;
+                #[cfg(any(test, feature = "chaos"))]
+                let mut filter = self.filter.clone();
                 loop {
-                    match write_recv.recv(ctx).await? {
+                    let cmd = write_recv.recv(ctx).await?;
+                    #[cfg(any(test, feature = "chaos"))]
+                    if let (Some(filter), WriteCommand::Frame { .. }) = (&mut filter, &cmd) {
+                        filter.wait(ctx).await?;
+                    }
+                    match cmd {
                         WriteCommand::Flush => io::flush(ctx, &mut write).await??,
-                        WriteCommand::Frame(frame) => {
-                            io::write_all(ctx, &mut write, &frame.header.raw()).await??;
-                            if let Some(data) = frame.data {
-                                let length = (data.len() as u16).to_le_bytes();
-                                io::write_all(ctx, &mut write, &length).await??;
-                                io::write_all(ctx, &mut write, data.as_slice()).await??;
+                        WriteCommand::Frame { header, data: None } => {
+                            io::write_all(ctx, &mut write, &header.raw()).await??;
+                        }
+                        WriteCommand::Frame {
+                            header,
+                            data: Some(data),
+                        } => {
+                            let caps = match header.stream_kind() {
+                                StreamKind::ACCEPT => &accept_caps,
+                                _ => &connect_caps,
+                            };
+                            if let Some(cap) = caps.get(header.stream_id().0 as usize) {
+                                self.traffic.sent(*cap, data.len());
                             }
+                            let [h0, h1] = header.raw();
+                            let [l0, l1] = (data.len() as u16).to_le_bytes();
+                            // Header, length and data are written at once,
+                            // without copying the data into an intermediate buffer.
+                            let prefix = [h0, h1, l0, l1];
+                            let mut buf = prefix.as_slice().chain(data);
+                            io::write_all_buf(ctx, &mut write, &mut buf).await??;
                         }
                     }
                 }
@@ -320,8 +384,28 @@ impl Mux {
                 }
             });
 
-            self.process_inbound_frames(ctx, read, accept_streams, connect_streams)
-                .await
+            if let Some(keepalive) = &keepalive {
+                s.spawn_bg::<()>(async {
+                    let header = Header::new(FrameKind::KEEPALIVE, StreamKind::ACCEPT, StreamId(0));
+                    loop {
+                        ctx.sleep(keepalive.interval).await?;
+                        write_send
+                            .send(ctx, WriteCommand::Frame { header, data: None })
+                            .await?;
+                        write_send.send(ctx, WriteCommand::Flush).await?;
+                    }
+                });
+            }
+
+            let dead_peer_timeout = keepalive.map(|k| k.timeout);
+            self.process_inbound_frames(
+                ctx,
+                read,
+                accept_streams,
+                connect_streams,
+                dead_peer_timeout,
+            )
+            .await
         })
         .await;
 
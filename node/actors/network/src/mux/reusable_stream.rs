@@ -2,7 +2,8 @@
 use super::{
     Config, FrameKind, Header, ReadStream, RunError, Stream, StreamId, StreamKind, WriteStream,
 };
-use crate::noise::bytes;
+use crate::{dump, noise::bytes};
+use ::bytes::{Bytes, BytesMut};
 use std::sync::Arc;
 use zksync_concurrency::{ctx, ctx::channel, oneshot, scope, sync};
 
@@ -15,7 +16,7 @@ pub(super) struct ReadPermit {
     pub(super) _size: sync::OwnedSemaphorePermit,
 }
 
-/// Mux protocol frame.
+/// Received mux protocol frame.
 #[derive(Debug)]
 pub(super) struct Frame {
     /// Frame header.
@@ -23,7 +24,7 @@ pub(super) struct Frame {
     /// Frame data. Present iff `header.frame_kind() == FrameKind::DATA`.
     /// If present, it is always nonempty.
     pub(super) data: Option<bytes::Buffer>,
-    /// Frame permit. Present iff the frame is for reading.
+    /// Frame permit.
     /// NOTE: we rely here on the order of dropping the fields:
     /// data should be dropped BEFORE permit. It doesn't matter much, but
     /// if it wasn't the case, in a very pathological situation we might
@@ -34,7 +35,13 @@ pub(super) struct Frame {
 /// Commands send to a task which owns the write half of the stream.
 #[derive(Debug)]
 pub(super) enum WriteCommand {
-    Frame(Frame),
+    /// Frame to send. `data` is present iff `header.frame_kind() == FrameKind::DATA`.
+    /// If present, it is nonempty and at most `Config::write_frame_size` long.
+    /// `Bytes` allow sending slices of the encoded messages without copying them.
+    Frame {
+        header: Header,
+        data: Option<Bytes>,
+    },
     Flush,
 }
 
@@ -66,6 +73,9 @@ impl ReservedStream {
 /// `queue.pop()` before the OPEN message is sent to the peer.
 pub(crate) struct StreamQueue {
     pub(super) max_streams: u32,
+    /// Number of the reusable streams agreed with the peer in the handshake,
+    /// `None` until the handshake is completed.
+    pub(super) streams: sync::watch::Sender<Option<u32>>,
     send: channel::UnboundedSender<ReservedStream>,
     recv: sync::Mutex<channel::UnboundedReceiver<ReservedStream>>,
 }
@@ -78,11 +88,20 @@ impl StreamQueue {
         let (send, recv) = channel::unbounded();
         Arc::new(Self {
             max_streams,
+            streams: sync::watch::channel(None).0,
             send,
             recv: sync::Mutex::new(recv),
         })
     }
 
+    /// Waits for the multiplexer handshake and returns whether any stream has been agreed
+    /// for the queue, i.e. whether the peer supports the capability at all.
+    /// Streams of an unsupported capability can never be reserved.
+    pub(crate) async fn supported(&self, ctx: &ctx::Ctx) -> ctx::OrCanceled<bool> {
+        let streams = *sync::wait_for(ctx, &mut self.streams.subscribe(), |s| s.is_some()).await?;
+        Ok(streams.unwrap() > 0)
+    }
+
     /// Reserves a transient stream from the queue to open later.
     /// Reservations can be placed in a common capacity pool.
     pub(crate) async fn reserve(&self, ctx: &ctx::Ctx) -> ctx::OrCanceled<ReservedStream> {
@@ -137,8 +156,9 @@ pub(super) struct WriteReusableStream {
     pub(crate) stream_id: StreamId,
     /// Kind of the reusable stream.
     pub(crate) stream_kind: StreamKind,
-    /// Cached data frame to send.
-    pub(crate) buffer: bytes::Buffer,
+    /// Data of the next frame to send, shorter than `cfg.write_frame_size`.
+    /// The allocation is reused for the consecutive frames, once the sent ones are dropped.
+    pub(crate) buffer: BytesMut,
     /// Channel used to schedule the frames for sending.
     pub(super) write_send: channel::Sender<WriteCommand>,
     /// A notification scheduling the stream flush.
@@ -177,32 +197,40 @@ impl WriteReusableStream {
         Self {
             stream_id,
             stream_kind,
-            buffer: bytes::Buffer::new(cfg.write_frame_size as usize),
+            buffer: BytesMut::with_capacity(cfg.write_frame_size as usize),
             write_send,
             flush,
             cfg,
         }
     }
 
+    /// Max size of the data of a frame.
+    pub(super) fn frame_size(&self) -> usize {
+        self.cfg.write_frame_size as usize
+    }
+
+    /// Sends the buffered data (if any) as a DATA frame.
     pub(super) async fn send_data(&mut self, ctx: &ctx::Ctx) -> Result<(), RunError> {
-        if self.buffer.len() == 0 {
+        if self.buffer.is_empty() {
             return Ok(());
         }
+        let data = self.buffer.split().freeze();
+        self.send_frame(ctx, data).await
+    }
+
+    /// Sends `data` as a DATA frame, without copying it.
+    /// `data` has to be nonempty and at most `frame_size()` long.
+    pub(super) async fn send_frame(&mut self, ctx: &ctx::Ctx, data: Bytes) -> Result<(), RunError> {
+        debug_assert!(!data.is_empty() && data.len() <= self.frame_size());
         let slot = self
             .write_send
             .reserve_or_disconnected(ctx)
             .await?
             .map_err(|_| RunError::Closed)?;
-        let header = Header::new(FrameKind::DATA, self.stream_kind, self.stream_id);
-        let frame = Frame {
-            header,
-            data: Some(std::mem::replace(
-                &mut self.buffer,
-                bytes::Buffer::new(self.cfg.write_frame_size as usize),
-            )),
-            _permit: None,
-        };
-        slot.send(WriteCommand::Frame(frame));
+        slot.send(WriteCommand::Frame {
+            header: Header::new(FrameKind::DATA, self.stream_kind, self.stream_id),
+            data: Some(data),
+        });
         Ok(())
     }
 
@@ -210,13 +238,8 @@ impl WriteReusableStream {
     pub(super) async fn send_close(&mut self, ctx: &ctx::Ctx) -> Result<(), RunError> {
         self.send_data(ctx).await?;
         let header = Header::new(FrameKind::CLOSE, self.stream_kind, self.stream_id);
-        let frame = Frame {
-            header,
-            data: None,
-            _permit: None,
-        };
         self.write_send
-            .send(ctx, WriteCommand::Frame(frame))
+            .send(ctx, WriteCommand::Frame { header, data: None })
             .await?;
         self.flush.notify_one();
         Ok(())
@@ -224,13 +247,8 @@ impl WriteReusableStream {
 
     pub(super) async fn send_open(&mut self, ctx: &ctx::Ctx) -> Result<(), RunError> {
         let header = Header::new(FrameKind::OPEN, self.stream_kind, self.stream_id);
-        let frame = Frame {
-            header,
-            data: None,
-            _permit: None,
-        };
         self.write_send
-            .send(ctx, WriteCommand::Frame(frame))
+            .send(ctx, WriteCommand::Frame { header, data: None })
             .await?;
         self.flush.notify_one();
         Ok(())
@@ -245,6 +263,11 @@ pub(super) struct ReusableStream {
     pub(super) write: WriteReusableStream,
     /// A queue through which fresh transient streams for the given capability will be requested.
     pub(super) stream_queue: Arc<StreamQueue>,
+    /// Dumper of the messages sent over the transient streams.
+    pub(super) dump: Option<dump::Dumper>,
+    /// Faults injected into the messages sent over the transient streams.
+    #[cfg(feature = "chaos")]
+    pub(super) chaos: Option<crate::chaos::Config>,
 }
 
 impl ReusableStream {
@@ -294,6 +317,9 @@ impl ReusableStream {
                 let _ = reservation.send(Stream {
                     read: ReadStream(read_lock),
                     write: WriteStream(write_lock),
+                    dump: self.dump.clone(),
+                    #[cfg(feature = "chaos")]
+                    chaos: self.chaos.clone(),
                 });
             }
         })
@@ -8,7 +8,7 @@ use std::{
         Arc,
     },
 };
-use zksync_concurrency::{ctx, scope, testonly::abort_on_panic};
+use zksync_concurrency::{ctx, scope, testonly::abort_on_panic, time};
 use zksync_protobuf::ProtoFmt as _;
 
 mod proto;
@@ -38,11 +38,17 @@ fn test_mux_verify() {
         read_frame_size: 100,
         read_frame_count: 10,
         write_frame_size: 100,
+        keepalive: None,
     });
     assert!(mux::Mux {
         cfg: cfg.clone(),
         accept: [].into(),
         connect: [].into(),
+        traffic: Arc::default(),
+        filter: None,
+        dump: None,
+        #[cfg(feature = "chaos")]
+        chaos: None,
     }
     .verify()
     .is_ok());
@@ -55,6 +61,11 @@ fn test_mux_verify() {
         cfg: cfg.clone(),
         accept: queues.clone(),
         connect: [].into(),
+        traffic: Arc::default(),
+        filter: None,
+        dump: None,
+        #[cfg(feature = "chaos")]
+        chaos: None,
     }
     .verify()
     .is_err());
@@ -62,6 +73,11 @@ fn test_mux_verify() {
         cfg,
         accept: [].into(),
         connect: queues.clone(),
+        traffic: Arc::default(),
+        filter: None,
+        dump: None,
+        #[cfg(feature = "chaos")]
+        chaos: None,
     }
     .verify()
     .is_err());
@@ -121,6 +137,7 @@ async fn run_server(
             assert!(count.fetch_add(1, Ordering::SeqCst) < queue.max_streams as usize);
             s.spawn(async {
                 let mut stream = stream;
+                let mut encoder = frame::Encoder::default();
                 while let Ok((req, _)) =
                     frame::mux_recv_proto::<Req>(ctx, &mut stream.read, Req::max_size()).await
                 {
@@ -128,7 +145,7 @@ async fn run_server(
                         output: rpc_handler(req.0),
                         capability_id: cap,
                     };
-                    frame::mux_send_proto(ctx, &mut stream.write, &resp)
+                    frame::mux_send_proto(ctx, &mut stream.write, &mut encoder, &resp)
                         .await
                         .unwrap();
                     stream.write.flush(ctx).await.unwrap();
@@ -157,11 +174,13 @@ async fn run_client(
             s.spawn(async {
                 let rng = &mut ctx.rng();
                 let mut stream = stream;
+                let mut encoder = frame::Encoder::default();
                 let rpcs = rng.gen_range(3..10);
                 for _ in 0..rpcs {
                     let mut req = vec![0u8; rng.gen_range(1000..2000)];
                     rng.fill(&mut req[..]);
-                    frame::mux_send_proto(ctx, &mut stream.write, &Req(req.clone())).await?;
+                    let req_msg = Req(req.clone());
+                    frame::mux_send_proto(ctx, &mut stream.write, &mut encoder, &req_msg).await?;
                     stream.write.flush(ctx).await?;
                     let (resp, _) =
                         frame::mux_recv_proto::<Resp>(ctx, &mut stream.read, Resp::max_size())
@@ -208,6 +227,7 @@ fn mux_with_noise() {
                 read_frame_size: 100,
                 read_frame_count: 7,
                 write_frame_size: 150,
+                keepalive: None,
             }),
             accept: (0..caps)
                 .map(|c| (c, mux::StreamQueue::new(rng.gen_range(1..5))))
@@ -215,6 +235,11 @@ fn mux_with_noise() {
             connect: (0..caps)
                 .map(|c| (c, mux::StreamQueue::new(rng.gen_range(1..5))))
                 .collect(),
+            traffic: Arc::default(),
+            filter: None,
+            dump: None,
+            #[cfg(feature = "chaos")]
+            chaos: None,
         };
         let mux2 = mux::Mux {
             cfg: Arc::new(mux::Config {
@@ -222,6 +247,7 @@ fn mux_with_noise() {
                 read_frame_size: 80,
                 read_frame_count: 10,
                 write_frame_size: 79,
+                keepalive: None,
             }),
             accept: (0..caps)
                 .map(|c| (c, mux::StreamQueue::new(rng.gen_range(1..5))))
@@ -229,6 +255,11 @@ fn mux_with_noise() {
             connect: (0..caps)
                 .map(|c| (c, mux::StreamQueue::new(rng.gen_range(1..5))))
                 .collect(),
+            traffic: Arc::default(),
+            filter: None,
+            dump: None,
+            #[cfg(feature = "chaos")]
+            chaos: None,
         };
 
         // Different buffer size and frame count.
@@ -292,6 +323,7 @@ async fn test_transport_closed() {
         read_frame_size: 100,
         read_frame_count: 10,
         write_frame_size: 100,
+        keepalive: None,
     });
     scope::run!(ctx, |ctx, s| async {
         let streams = s
@@ -304,6 +336,11 @@ async fn test_transport_closed() {
                             cfg: cfg.clone(),
                             accept: BTreeMap::default(),
                             connect: BTreeMap::default(),
+                            traffic: Arc::default(),
+                            filter: None,
+                            dump: None,
+                            #[cfg(feature = "chaos")]
+                            chaos: None,
                         };
                         let q = mux::StreamQueue::new(1);
                         mux.connect.insert(cap, q.clone());
@@ -317,6 +354,11 @@ async fn test_transport_closed() {
                             cfg: cfg.clone(),
                             accept: BTreeMap::default(),
                             connect: BTreeMap::default(),
+                            traffic: Arc::default(),
+                            filter: None,
+                            dump: None,
+                            #[cfg(feature = "chaos")]
+                            chaos: None,
                         };
                         let q = mux::StreamQueue::new(1);
                         mux.accept.insert(cap, q.clone());
@@ -350,3 +392,182 @@ async fn test_transport_closed() {
     .await
     .unwrap();
 }
+
+#[tokio::test]
+async fn test_write_bytes() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let cfg = Arc::new(mux::Config {
+        read_buffer_size: 1000,
+        read_frame_size: 100,
+        read_frame_count: 10,
+        write_frame_size: 100,
+        keepalive: None,
+    });
+    // Chunks of various sizes, including ones not aligned to the frames.
+    let chunks: Vec<Vec<u8>> = (0..30)
+        .map(|_| (0..rng.gen_range(0..350)).map(|_| rng.gen()).collect())
+        .collect();
+    let want = chunks.concat();
+    let (s1, s2) = noise::testonly::pipe(ctx).await;
+    scope::run!(ctx, |ctx, s| async {
+        let cap = 0;
+        let mut accept = mux::Mux {
+            cfg: cfg.clone(),
+            accept: BTreeMap::default(),
+            connect: BTreeMap::default(),
+            traffic: Arc::default(),
+            filter: None,
+            dump: None,
+            #[cfg(feature = "chaos")]
+            chaos: None,
+        };
+        let accept_queue = mux::StreamQueue::new(1);
+        accept.accept.insert(cap, accept_queue.clone());
+        s.spawn_bg(async { expected(accept.run(ctx, s1).await).context("[accept] mux.run()") });
+        let mut connect = mux::Mux {
+            cfg: cfg.clone(),
+            accept: BTreeMap::default(),
+            connect: BTreeMap::default(),
+            traffic: Arc::default(),
+            filter: None,
+            dump: None,
+            #[cfg(feature = "chaos")]
+            chaos: None,
+        };
+        let connect_queue = mux::StreamQueue::new(1);
+        connect.connect.insert(cap, connect_queue.clone());
+        s.spawn_bg(async { expected(connect.run(ctx, s2).await).context("[connect] mux.run()") });
+
+        let mut stream = connect_queue.open(ctx).await?;
+        // Written data has to be received in order, regardless of whether it was copied
+        // into the frames (`write_all`) or sent as slices of `Bytes` (`write_bytes`).
+        for (i, chunk) in chunks.iter().enumerate() {
+            match i % 2 {
+                0 => stream.write.write_all(ctx, chunk).await?,
+                _ => stream.write.write_bytes(ctx, chunk.clone().into()).await?,
+            }
+        }
+        drop(stream.write);
+        let mut stream = accept_queue.open(ctx).await?;
+        let mut got = bytes::Buffer::new(want.len() + 1);
+        stream.read.read_exact(ctx, &mut got).await?;
+        assert_eq!(want, got.as_slice());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+fn keepalive_cfg() -> Arc<mux::Config> {
+    Arc::new(mux::Config {
+        read_buffer_size: 1000,
+        read_frame_size: 100,
+        read_frame_count: 10,
+        write_frame_size: 100,
+        keepalive: Some(crate::KeepaliveConfig {
+            interval: time::Duration::milliseconds(20),
+            timeout: time::Duration::milliseconds(200),
+        }),
+    })
+}
+
+#[tokio::test]
+async fn test_keepalive_idle_connection() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let (s1, s2) = noise::testonly::pipe(ctx).await;
+    scope::run!(ctx, |ctx, s| async {
+        for stream in [s1, s2] {
+            let mux = mux::Mux {
+                cfg: keepalive_cfg(),
+                accept: BTreeMap::default(),
+                connect: BTreeMap::default(),
+                traffic: Arc::default(),
+                filter: None,
+                dump: None,
+                #[cfg(feature = "chaos")]
+                chaos: None,
+            };
+            s.spawn_bg(async { expected(mux.run(ctx, stream).await).context("mux.run()") });
+        }
+        // Connection without any streams should stay alive for many timeouts.
+        ctx.sleep(time::Duration::seconds(1)).await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+/// Peer sending the KEEPALIVE frames less often than our timeout should not be dropped.
+#[tokio::test]
+async fn test_keepalive_negotiation() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let (s1, s2) = noise::testonly::pipe(ctx).await;
+    let mut slow = (*keepalive_cfg()).clone();
+    slow.keepalive = Some(crate::KeepaliveConfig {
+        interval: time::Duration::milliseconds(500),
+        timeout: time::Duration::seconds(2),
+    });
+    scope::run!(ctx, |ctx, s| async {
+        for (cfg, stream) in [(keepalive_cfg(), s1), (Arc::new(slow), s2)] {
+            let mux = mux::Mux {
+                cfg,
+                accept: BTreeMap::default(),
+                connect: BTreeMap::default(),
+                traffic: Arc::default(),
+                filter: None,
+                dump: None,
+                #[cfg(feature = "chaos")]
+                chaos: None,
+            };
+            s.spawn_bg(async { expected(mux.run(ctx, stream).await).context("mux.run()") });
+        }
+        ctx.sleep(time::Duration::seconds(1)).await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_keepalive_dead_peer() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let (s1, mut s2) = noise::testonly::pipe(ctx).await;
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(async {
+            // Peer completes the handshake, announcing keepalive support,
+            // but never sends anything afterwards.
+            let h = mux::Handshake {
+                accept_max_streams: [].into(),
+                connect_max_streams: [].into(),
+                keepalive: Some(crate::KeepaliveConfig::default()),
+            };
+            frame::send_proto(ctx, &mut s2, &h).await?;
+            let _: mux::Handshake =
+                frame::recv_proto(ctx, &mut s2, mux::Handshake::max_size()).await?;
+            ctx.canceled().await;
+            Ok(())
+        });
+        let mux = mux::Mux {
+            cfg: keepalive_cfg(),
+            accept: BTreeMap::default(),
+            connect: BTreeMap::default(),
+            traffic: Arc::default(),
+            filter: None,
+            dump: None,
+            #[cfg(feature = "chaos")]
+            chaos: None,
+        };
+        assert!(matches!(
+            mux.run(ctx, s1).await,
+            Err(mux::RunError::DeadPeer)
+        ));
+        anyhow::Ok(())
+    })
+    .await
+    .unwrap();
+}
@@ -8,7 +8,8 @@
 //! It might get adjusted later for better buffer management
 //! or convenience of use.
 use super::{FrameKind, ReadReusableStream, WriteReusableStream};
-use crate::noise::bytes;
+use crate::{dump, noise::bytes};
+use ::bytes::{Buf as _, Bytes};
 use zksync_concurrency::{ctx, sync};
 
 /// Read half of the transient stream.
@@ -70,17 +71,39 @@ impl WriteStream {
     /// Writes `buf` to the stream.
     /// On success, all the data has been written to the stream.
     /// On error, part of the data may have been written to the stream.
-    pub(crate) async fn write_all(&mut self, ctx: &ctx::Ctx, buf: &[u8]) -> anyhow::Result<()> {
-        let mut offset = 0;
-        while offset < buf.len() {
-            if self.0.buffer.capacity() == 0 {
+    pub(crate) async fn write_all(&mut self, ctx: &ctx::Ctx, mut buf: &[u8]) -> anyhow::Result<()> {
+        let frame_size = self.0.frame_size();
+        while !buf.is_empty() {
+            let n = buf.len().min(frame_size - self.0.buffer.len());
+            self.0.buffer.extend_from_slice(&buf[..n]);
+            buf.advance(n);
+            if self.0.buffer.len() == frame_size {
                 self.0.send_data(ctx).await?;
             }
-            offset += self.0.buffer.push(&buf[offset..]);
         }
         Ok(())
     }
 
+    /// Writes `buf` to the stream, like `write_all()`.
+    /// The full frames are sent as slices of `buf`, without copying the data:
+    /// only the beginning of `buf` (topping up the already buffered frame)
+    /// and the tail shorter than a frame are copied.
+    pub(crate) async fn write_bytes(
+        &mut self,
+        ctx: &ctx::Ctx,
+        mut buf: Bytes,
+    ) -> anyhow::Result<()> {
+        let frame_size = self.0.frame_size();
+        if !self.0.buffer.is_empty() {
+            let n = buf.len().min(frame_size - self.0.buffer.len());
+            self.write_all(ctx, &buf.split_to(n)).await?;
+        }
+        while buf.len() >= frame_size {
+            self.0.send_frame(ctx, buf.split_to(frame_size)).await?;
+        }
+        self.write_all(ctx, &buf).await
+    }
+
     /// Notifies the transport stream to flush the stream.
     // Remove once we have a use case in prod code for this.
     #[allow(dead_code)]
@@ -101,4 +124,9 @@ pub(crate) struct Stream {
     pub(crate) read: ReadStream,
     /// Write half of the transient stream.
     pub(crate) write: WriteStream,
+    /// Dumper of the messages sent over the stream, if the connection is dumped.
+    pub(crate) dump: Option<dump::Dumper>,
+    /// Faults injected into the messages sent over the stream.
+    #[cfg(feature = "chaos")]
+    pub(crate) chaos: Option<crate::chaos::Config>,
 }
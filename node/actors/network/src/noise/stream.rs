@@ -8,6 +8,7 @@ use std::{
 use zksync_concurrency::{
     ctx, io,
     io::{AsyncRead as _, AsyncWrite as _},
+    time,
 };
 use zksync_consensus_crypto::{keccak256::Keccak256, ByteFmt};
 
@@ -87,6 +88,18 @@ pub(crate) struct Stream<S = MeteredStream> {
     write_buf: Box<Buffer>,
 }
 
+impl Stream {
+    /// Address of the remote end of the underlying TCP stream.
+    pub(crate) fn peer_addr(&self) -> Option<std::net::SocketAddr> {
+        self.inner.peer_addr()
+    }
+
+    /// Starts reporting the traffic of the underlying TCP stream per peer.
+    pub(crate) fn set_metrics_peer(&mut self, peer: String, now: time::Utc) {
+        self.inner.set_peer(peer, now);
+    }
+}
+
 impl<S> Stream<S>
 where
     S: io::AsyncRead + io::AsyncWrite + Unpin,
@@ -3,7 +3,7 @@ use zksync_concurrency::{ctx, scope};
 
 pub(crate) async fn pipe(ctx: &ctx::Ctx) -> (noise::Stream, noise::Stream) {
     scope::run!(ctx, |ctx, s| async {
-        let (outbound_stream, inbound_stream) = metrics::MeteredStream::test_pipe(ctx).await;
+        let (outbound_stream, inbound_stream) = metrics::MeteredStream::test_pipe();
         let outbound_task =
             s.spawn(async { noise::Stream::client_handshake(ctx, outbound_stream).await });
         let inbound_task =
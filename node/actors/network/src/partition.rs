@@ -0,0 +1,61 @@
+//! Network partitions simulated at the frame layer.
+//! Frames sent to a peer which is partitioned away from the node are held back
+//! (the connection stays open, as a TCP connection over a broken route would),
+//! until the partition is healed. Used by tests, see `testonly::Instance::partition`.
+//! Compiled only for tests and with the `chaos` feature, so that production nodes never
+//! filter the sent frames.
+use std::collections::HashSet;
+use zksync_concurrency::{ctx, sync};
+use zksync_consensus_roles::{node, validator};
+
+/// Peer at the other end of a connection.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Peer {
+    /// Peer of a gossip connection.
+    Node(node::PublicKey),
+    /// Peer of a consensus connection.
+    Validator(validator::PublicKey),
+}
+
+/// Set of peers the node is partitioned away from.
+pub(crate) type Partition = HashSet<Peer>;
+
+/// Partition of the node. Empty unless set by a test.
+pub(crate) struct PartitionWatch(sync::watch::Sender<Partition>);
+
+impl Default for PartitionWatch {
+    fn default() -> Self {
+        Self(sync::watch::channel(Partition::default()).0)
+    }
+}
+
+impl PartitionWatch {
+    /// Replaces the partition.
+    pub(crate) fn set(&self, partition: Partition) {
+        self.0.send_replace(partition);
+    }
+
+    /// Constructs a filter of the frames sent to `peer`.
+    pub(crate) fn filter(&self, peer: Peer) -> Filter {
+        Filter {
+            partition: self.0.subscribe(),
+            peer,
+        }
+    }
+}
+
+/// Filter of the frames sent over a single connection.
+#[derive(Debug, Clone)]
+pub(crate) struct Filter {
+    partition: sync::watch::Receiver<Partition>,
+    peer: Peer,
+}
+
+impl Filter {
+    /// Waits until frames can be sent to the peer.
+    pub(crate) async fn wait(&mut self, ctx: &ctx::Ctx) -> ctx::OrCanceled<()> {
+        let peer = &self.peer;
+        sync::wait_for(ctx, &mut self.partition, |p| !p.contains(peer)).await?;
+        Ok(())
+    }
+}
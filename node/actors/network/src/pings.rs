@@ -0,0 +1,82 @@
+//! Round trip times and clock skews of the peers, measured with the ping RPC.
+//! Large clock skew is a frequent misconfiguration of the nodes, which is otherwise
+//! invisible until timestamps (e.g. of the node addresses) start getting rejected.
+use crate::rpc::ping::Sample;
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+use vise::{Buckets, Histogram, Metrics, Unit};
+use zksync_concurrency::{metrics::LatencyHistogramExt as _, time};
+use zksync_consensus_roles::node;
+
+/// Clock skew above which a warning is logged.
+pub const MAX_CLOCK_SKEW: time::Duration = time::Duration::seconds(5);
+
+/// Latest ping measurement of a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerPing {
+    /// Key of the peer.
+    pub key: node::PublicKey,
+    /// Round trip time of the latest ping.
+    pub rtt: time::Duration,
+    /// Estimated offset of the peer's UTC clock relative to ours
+    /// (positive if the peer's clock is ahead).
+    /// `None` if the peer doesn't report its time.
+    pub clock_skew: Option<time::Duration>,
+    /// Time of the measurement.
+    pub measured: time::Utc,
+}
+
+/// Latest ping measurements of the currently connected peers, by the peer key.
+#[derive(Debug, Default)]
+pub(crate) struct Pings(Mutex<BTreeMap<node::PublicKey, PeerPing>>);
+
+impl Pings {
+    /// Records a ping measurement of a peer.
+    pub(crate) fn record(&self, peer: &node::PublicKey, sample: Sample, now: time::Utc) {
+        METRICS.rtt.observe_latency(sample.rtt);
+        if let Some(skew) = sample.clock_skew {
+            METRICS.clock_skew.observe_latency(skew.abs());
+        }
+        let prev = self.0.lock().unwrap().insert(
+            peer.clone(),
+            PeerPing {
+                key: peer.clone(),
+                rtt: sample.rtt,
+                clock_skew: sample.clock_skew,
+                measured: now,
+            },
+        );
+        // Warn only once the skew exceeds the threshold, rather than on every ping.
+        let skewed = |s: Option<time::Duration>| s.map_or(false, |s| s.abs() > MAX_CLOCK_SKEW);
+        if skewed(sample.clock_skew) && !skewed(prev.and_then(|p| p.clock_skew)) {
+            tracing::warn!(
+                "clock of peer {peer:?} is off by {} (ours or theirs is misconfigured)",
+                sample.clock_skew.unwrap()
+            );
+        }
+    }
+
+    /// Removes the measurements of a disconnected peer.
+    pub(crate) fn remove(&self, peer: &node::PublicKey) {
+        self.0.lock().unwrap().remove(peer);
+    }
+
+    /// Latest ping measurements, ordered by the peer key.
+    pub(crate) fn get(&self) -> Vec<PeerPing> {
+        self.0.lock().unwrap().values().cloned().collect()
+    }
+}
+
+/// Metrics of the ping measurements.
+#[derive(Debug, Metrics)]
+#[metrics(prefix = "network_ping")]
+struct PingMetrics {
+    /// Round trip times of the pings.
+    #[metrics(unit = Unit::Seconds, buckets = Buckets::LATENCIES)]
+    rtt: Histogram<Duration>,
+    /// Absolute clock skews of the peers.
+    #[metrics(unit = Unit::Seconds, buckets = Buckets::LATENCIES)]
+    clock_skew: Histogram<Duration>,
+}
+
+#[vise::register]
+static METRICS: vise::Global<PingMetrics> = vise::Global::new();
@@ -17,7 +17,6 @@ pub(crate) struct Pool<T> {
 
 impl<T> Pool<T> {
     /// Returns a reference to the underlying set.
-    #[allow(dead_code)]
     pub(crate) fn current(&self) -> &HashSet<T> {
         &self.current
     }
@@ -60,6 +59,16 @@ impl<T: std::hash::Hash + PartialEq + Eq> PoolWatch<T> {
             .await
     }
 
+    /// Replaces the set of allowed elements and the limit on the other elements.
+    /// Elements already in the set are kept, even if they exceed the new limit.
+    pub(crate) async fn update(&self, allowed: HashSet<T>, extra_limit: usize) {
+        self.0.lock().await.send_modify(|pool| {
+            pool.extra_count = pool.current.iter().filter(|v| !allowed.contains(v)).count();
+            pool.allowed = allowed;
+            pool.extra_limit = extra_limit;
+        });
+    }
+
     /// Removes an element from the set.
     pub(crate) async fn remove(&self, v: &T) {
         self.0.lock().await.send_if_modified(|pool| {
@@ -74,7 +83,6 @@ impl<T: std::hash::Hash + PartialEq + Eq> PoolWatch<T> {
     }
 
     /// Subscribes to the set changes.
-    #[allow(dead_code)]
     pub(crate) fn subscribe(&self) -> sync::watch::Receiver<Pool<T>> {
         self.0.subscribe()
     }
@@ -7,7 +7,7 @@
 //!
 //! Hence, the preface protocol is used to enable encryption
 //! and multiplex between multiple endpoints available on the same TCP port.
-use crate::{frame, metrics, noise, proto::preface as proto};
+use crate::{frame, metrics, noise, proto::preface as proto, Transport};
 use zksync_concurrency::{ctx, time};
 use zksync_protobuf::{required, ProtoFmt};
 
@@ -28,6 +28,10 @@ pub(crate) enum Endpoint {
     ConsensusNet,
     /// Gossip network endpoint.
     GossipNet,
+    /// Endpoint serving the genesis of the node, without a handshake.
+    GenesisNet,
+    /// Private link between a sentry and its backend validator node.
+    SentryLink,
 }
 
 impl ProtoFmt for Encryption {
@@ -60,6 +64,8 @@ impl ProtoFmt for Endpoint {
         Ok(match required(&r.t)? {
             T::ConsensusNet(..) => Self::ConsensusNet,
             T::GossipNet(..) => Self::GossipNet,
+            T::GenesisNet(..) => Self::GenesisNet,
+            T::SentryLink(..) => Self::SentryLink,
         })
     }
     fn build(&self) -> Self::Proto {
@@ -67,6 +73,8 @@ impl ProtoFmt for Endpoint {
         let t = match self {
             Self::ConsensusNet => T::ConsensusNet(proto::endpoint::ConsensusNet {}),
             Self::GossipNet => T::GossipNet(proto::endpoint::GossipNet {}),
+            Self::GenesisNet => T::GenesisNet(proto::endpoint::GenesisNet {}),
+            Self::SentryLink => T::SentryLink(proto::endpoint::SentryLink {}),
         };
         Self::Proto { t: Some(t) }
     }
@@ -75,11 +83,12 @@ impl ProtoFmt for Endpoint {
 /// Connects to the given TCP address and performs client-side preface protocol.
 pub(crate) async fn connect(
     ctx: &ctx::Ctx,
+    transport: &Transport,
     addr: std::net::SocketAddr,
     endpoint: Endpoint,
 ) -> anyhow::Result<noise::Stream> {
     let ctx = &ctx.with_timeout(TIMEOUT);
-    let mut stream = metrics::MeteredStream::connect(ctx, addr).await??;
+    let mut stream = metrics::MeteredStream::connect(ctx, transport, addr).await??;
     frame::send_proto(ctx, &mut stream, &Encryption::NoiseNN).await?;
     let mut stream = noise::Stream::client_handshake(ctx, stream).await?;
     frame::send_proto(ctx, &mut stream, &endpoint).await?;
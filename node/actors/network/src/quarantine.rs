@@ -0,0 +1,101 @@
+//! Quarantine of the peers which have failed the handshake due to a genesis mismatch.
+//! Instead of redialing such peers at the regular rate, they are quarantined for
+//! a configured time. Mismatches are counted per foreign genesis, so that operators
+//! can detect a large-scale misconfiguration or an unintended network split.
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    sync::Mutex,
+};
+use vise::{Counter, LabeledFamily, Metrics};
+use zksync_concurrency::{ctx, time};
+use zksync_consensus_crypto::TextFmt as _;
+use zksync_consensus_roles::validator;
+
+/// Max number of distinct foreign genesis hashes reported as metric labels.
+/// Further genesis hashes are reported as "other", so that peers cannot
+/// blow up the metrics cardinality.
+const MAX_GENESIS_LABELS: usize = 16;
+/// Max number of quarantined peers. Inbound peers can generate keys at will,
+/// so the quarantine is bounded. Further peers are just disconnected.
+const MAX_PEERS: usize = 1024;
+
+/// Quarantined peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Entry {
+    /// Genesis that the peer has announced.
+    pub(crate) genesis: validator::GenesisHash,
+    /// Time until which the peer won't be redialed.
+    pub(crate) until: time::Instant,
+}
+
+struct Inner<K> {
+    peers: HashMap<K, Entry>,
+    labels: HashSet<validator::GenesisHash>,
+}
+
+/// Peers which have a different genesis than this node.
+pub(crate) struct Quarantine<K> {
+    duration: time::Duration,
+    inner: Mutex<Inner<K>>,
+}
+
+impl<K: Hash + Eq> Quarantine<K> {
+    /// Constructs a new quarantine, which keeps the peers for `duration`.
+    pub(crate) fn new(duration: time::Duration) -> Self {
+        Self {
+            duration,
+            inner: Mutex::new(Inner {
+                peers: HashMap::new(),
+                labels: HashSet::new(),
+            }),
+        }
+    }
+
+    /// Counts a genesis mismatch in the metrics.
+    fn observe(&self, genesis: validator::GenesisHash) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.labels.len() < MAX_GENESIS_LABELS {
+            inner.labels.insert(genesis);
+        }
+        let label = match inner.labels.contains(&genesis) {
+            true => genesis.encode(),
+            false => "other".to_string(),
+        };
+        METRICS.genesis_mismatch[&label].inc();
+    }
+
+    /// Records a genesis mismatch with a peer and quarantines it.
+    pub(crate) fn insert(&self, ctx: &ctx::Ctx, peer: K, genesis: validator::GenesisHash) {
+        self.observe(genesis);
+        tracing::warn!("peer has a different genesis {genesis:?}, quarantining it");
+        let until = ctx.now() + self.duration;
+        let mut inner = self.inner.lock().unwrap();
+        inner.peers.retain(|_, e| e.until > ctx.now());
+        if inner.peers.len() < MAX_PEERS || inner.peers.contains_key(&peer) {
+            inner.peers.insert(peer, Entry { genesis, until });
+        }
+    }
+
+    /// Returns the quarantine entry of the peer, if the peer is still quarantined.
+    pub(crate) fn get(&self, ctx: &ctx::Ctx, peer: &K) -> Option<Entry> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .peers
+            .get(peer)
+            .filter(|e| e.until > ctx.now())
+            .cloned()
+    }
+}
+
+/// Metrics of the quarantine.
+#[derive(Debug, Metrics)]
+#[metrics(prefix = "network_quarantine")]
+struct QuarantineMetrics {
+    /// Handshakes which have failed due to a genesis mismatch, per foreign genesis.
+    #[metrics(labels = ["genesis"])]
+    genesis_mismatch: LabeledFamily<String, Counter>,
+}
+
+#[vise::register]
+static METRICS: vise::Global<QuarantineMetrics> = vise::Global::new();
@@ -0,0 +1,274 @@
+//! Reconnect manager of the outbound connections which the node keeps up persistently:
+//! the connections to the static gossip peers and the consensus connections to the validators.
+//! Failed connection attempts (and dropped connections) are retried after a jittered
+//! exponential backoff. The recent connection states of every peer are exposed via a watch.
+//! Failures caused by a misbehaving peer (see `address_book::penalty`) count as several
+//! failures, so that the backoff grows faster.
+use crate::{gossip::address_book, metrics::key_label};
+use rand::Rng;
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+};
+use vise::{Counter, LabeledFamily, Metrics};
+use zksync_concurrency::{ctx, sync, time};
+use zksync_consensus_roles::{node, validator};
+
+/// Max number of the recent states remembered per peer.
+const HISTORY_LEN: usize = 16;
+
+/// Reconnect policy of the persistent outbound connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconnectConfig {
+    /// Backoff after the first failed attempt. It doubles with every consecutive failure.
+    pub initial_backoff: time::Duration,
+    /// Cap on the backoff.
+    pub max_backoff: time::Duration,
+    /// Max number of the consecutive failed attempts, after which the peer is not dialed
+    /// until its address changes. `None` means retrying indefinitely.
+    pub max_retries: Option<u32>,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff: time::Duration::seconds(1),
+            max_backoff: time::Duration::seconds(20),
+            max_retries: None,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// Verifies correctness of the config.
+    pub(crate) fn verify(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.initial_backoff.is_positive(),
+            "initial_backoff has to be positive"
+        );
+        anyhow::ensure!(
+            self.max_backoff >= self.initial_backoff,
+            "max_backoff has to be at least initial_backoff"
+        );
+        anyhow::ensure!(
+            self.max_retries != Some(0),
+            "max_retries has to be positive"
+        );
+        Ok(())
+    }
+
+    /// Backoff after `failures` consecutive failed attempts.
+    /// It is picked uniformly from `[b/2,b]`, where `b` is the exponential backoff,
+    /// so that the peers disconnected at the same time don't redial in lockstep.
+    pub(crate) fn backoff(&self, rng: &mut impl Rng, failures: u32) -> time::Duration {
+        let shift = failures.saturating_sub(1).min(30);
+        let b = self
+            .initial_backoff
+            .saturating_mul(1 << shift)
+            .min(self.max_backoff);
+        let jitter = i64::try_from((b - b / 2).whole_nanoseconds()).unwrap_or(i64::MAX);
+        b / 2 + time::Duration::nanoseconds(rng.gen_range(0..=jitter))
+    }
+}
+
+/// Peer to which the node keeps up a persistent outbound connection.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum OutboundPeer {
+    /// Static gossip peer.
+    Gossip(node::PublicKey),
+    /// Validator, connected via the consensus network.
+    Consensus(validator::PublicKey),
+    /// Sentry of this validator, connected via the private sentry link.
+    Sentry(node::PublicKey),
+}
+
+impl OutboundPeer {
+    /// Label of the peer in the metrics.
+    fn label(&self) -> String {
+        match self {
+            Self::Gossip(key) => key_label(key),
+            Self::Consensus(key) => key_label(key),
+            Self::Sentry(key) => key_label(key),
+        }
+    }
+}
+
+/// State of a persistent outbound connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnState {
+    /// Connection is being established.
+    Connecting,
+    /// Connection has been established.
+    Connected,
+    /// Connection attempt has failed or the connection has been closed.
+    /// Connection will be reattempted after `delay`.
+    Backoff {
+        /// Number of the consecutive failed attempts.
+        failures: u32,
+        /// Delay until the next attempt.
+        delay: time::Duration,
+        /// Reason of the failure.
+        error: String,
+    },
+    /// `max_retries` consecutive attempts have failed.
+    /// The peer won't be dialed until its address changes.
+    GivenUp {
+        /// Reason of the last failure.
+        error: String,
+    },
+}
+
+/// Recent states of the connection to a peer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnHistory {
+    /// Recent states together with the time they were entered, oldest first.
+    pub states: VecDeque<(time::Utc, ConnState)>,
+    /// Number of the consecutive failed attempts.
+    pub failures: u32,
+}
+
+impl ConnHistory {
+    /// Current state of the connection.
+    pub fn current(&self) -> Option<&ConnState> {
+        self.states.back().map(|(_, s)| s)
+    }
+
+    /// Appends a state, forgetting the oldest one if the history is full.
+    fn push(&mut self, now: time::Utc, state: ConnState) {
+        if self.states.len() == HISTORY_LEN {
+            self.states.pop_front();
+        }
+        self.states.push_back((now, state));
+    }
+}
+
+/// Connection histories of the persistent outbound peers.
+pub type ConnHistories = HashMap<OutboundPeer, ConnHistory>;
+
+/// Reconnect manager.
+pub(crate) struct Reconnector {
+    /// Reconnect policy.
+    cfg: ReconnectConfig,
+    /// Connection histories of the managed peers.
+    peers: sync::watch::Sender<ConnHistories>,
+}
+
+impl Reconnector {
+    /// Constructs a new reconnect manager.
+    pub(crate) fn new(cfg: ReconnectConfig) -> Self {
+        Self {
+            cfg,
+            peers: sync::watch::channel(ConnHistories::default()).0,
+        }
+    }
+
+    /// Subscribes to the connection histories.
+    pub(crate) fn subscribe(&self) -> sync::watch::Receiver<ConnHistories> {
+        self.peers.subscribe()
+    }
+
+    /// Records that the connection to `peer` has been established.
+    /// Noop if the peer is not managed (for example a dynamically dialed gossip peer).
+    pub(crate) fn connected(&self, ctx: &ctx::Ctx, peer: &OutboundPeer) {
+        self.peers.send_if_modified(|peers| {
+            let Some(h) = peers.get_mut(peer) else {
+                return false;
+            };
+            h.failures = 0;
+            h.push(ctx.now_utc(), ConnState::Connected);
+            true
+        });
+    }
+
+    /// Records that `peer` has misbehaved on another connection (e.g. an inbound one),
+    /// which counts as `penalty` failed attempts to connect to it.
+    /// Noop if the peer is not managed.
+    pub(crate) fn penalize(&self, peer: &OutboundPeer, penalty: i32) {
+        self.peers.send_if_modified(|peers| {
+            let Some(h) = peers.get_mut(peer) else {
+                return false;
+            };
+            h.failures = h.failures.saturating_add(penalty.unsigned_abs());
+            true
+        });
+    }
+
+    /// Stops tracking the peer.
+    pub(crate) fn remove(&self, peer: &OutboundPeer) {
+        self.peers
+            .send_if_modified(|peers| peers.remove(peer).is_some());
+    }
+
+    /// Keeps up the connection to `peer`: calls `connect` (which is expected to run the
+    /// whole connection) again and again, with a backoff between the attempts.
+    /// After `max_retries` consecutive failures it waits until `ctx` is canceled.
+    pub(crate) async fn maintain<F: Future<Output = anyhow::Result<()>>>(
+        &self,
+        ctx: &ctx::Ctx,
+        peer: &OutboundPeer,
+        mut connect: impl FnMut() -> F,
+    ) -> ctx::OrCanceled<()> {
+        let label = peer.label();
+        // Failures recorded before the address of the peer changed don't count.
+        self.peers.send_modify(|peers| {
+            peers.entry(peer.clone()).or_default().failures = 0;
+        });
+        loop {
+            self.peers.send_modify(|peers| {
+                peers
+                    .entry(peer.clone())
+                    .or_default()
+                    .push(ctx.now_utc(), ConnState::Connecting);
+            });
+            let res = connect().await;
+            if !ctx.is_active() {
+                return Err(ctx::Canceled);
+            }
+            let (error, penalty) = match res {
+                Ok(()) => ("connection closed".to_string(), 1),
+                Err(err) => (format!("{err:#}"), address_book::penalty(&err)),
+            };
+            tracing::info!("connection to {peer:?}: {error}");
+            let mut failures = 0;
+            self.peers.send_modify(|peers| {
+                let h = peers.entry(peer.clone()).or_default();
+                h.failures = h.failures.saturating_add(penalty.unsigned_abs());
+                failures = h.failures;
+            });
+            if self.cfg.max_retries.is_some_and(|max| failures >= max) {
+                tracing::warn!("giving up on {peer:?} after {failures} failed attempts");
+                self.peers.send_modify(|peers| {
+                    let h = peers.entry(peer.clone()).or_default();
+                    h.push(ctx.now_utc(), ConnState::GivenUp { error });
+                });
+                return ctx.wait(std::future::pending()).await;
+            }
+            let delay = self.cfg.backoff(&mut ctx.rng(), failures);
+            self.peers.send_modify(|peers| {
+                let h = peers.entry(peer.clone()).or_default();
+                h.push(
+                    ctx.now_utc(),
+                    ConnState::Backoff {
+                        failures,
+                        delay,
+                        error,
+                    },
+                );
+            });
+            ctx.sleep(delay).await?;
+            METRICS.attempts[&label].inc();
+        }
+    }
+}
+
+/// Metrics of the persistent outbound connections.
+#[derive(Debug, Metrics)]
+#[metrics(prefix = "network_reconnect")]
+struct ReconnectMetrics {
+    /// Attempts to re-establish a persistent outbound connection, per peer.
+    #[metrics(labels = ["peer"])]
+    attempts: LabeledFamily<String, Counter>,
+}
+
+#[vise::register]
+static METRICS: vise::Global<ReconnectMetrics> = vise::Global::new();
@@ -1,7 +1,8 @@
 //! Defines RPC for passing consensus messages.
-use crate::{mux, proto::consensus as proto};
+use crate::{mux, proto::consensus as proto, TraceContext};
+use anyhow::Context as _;
 use zksync_consensus_roles::validator;
-use zksync_protobuf::{read_required, ProtoFmt};
+use zksync_protobuf::{read_optional, read_required, ProtoFmt};
 
 /// Consensus RPC.
 pub(crate) struct Rpc;
@@ -14,13 +15,18 @@ impl super::Rpc for Rpc {
     type Resp = Resp;
 
     fn submethod(req: &Self::Req) -> &'static str {
-        req.0.msg.label()
+        req.msg.msg.label()
     }
 }
 
 /// Signed consensus message that the receiving peer should process.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub(crate) struct Req(pub(crate) validator::Signed<validator::ConsensusMsg>);
+pub(crate) struct Req {
+    /// Consensus message.
+    pub(crate) msg: validator::Signed<validator::ConsensusMsg>,
+    /// Trace context of the span which sent the message.
+    pub(crate) trace: Option<TraceContext>,
+}
 
 /// Confirmation that the consensus message has been processed.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -30,12 +36,16 @@ impl ProtoFmt for Req {
     type Proto = proto::ConsensusReq;
 
     fn read(r: &Self::Proto) -> anyhow::Result<Self> {
-        read_required(&r.msg).map(Self)
+        Ok(Self {
+            msg: read_required(&r.msg).context("msg")?,
+            trace: read_optional(&r.trace).context("trace")?,
+        })
     }
 
     fn build(&self) -> Self::Proto {
         Self::Proto {
-            msg: Some(self.0.build()),
+            msg: Some(self.msg.build()),
+            trace: self.trace.as_ref().map(ProtoFmt::build),
         }
     }
 }
@@ -1,7 +1,10 @@
 //! RPC for fetching a block from peer.
 use crate::{mux, proto::gossip as proto};
 use anyhow::Context;
-use zksync_consensus_roles::validator::{BlockNumber, FinalBlock};
+use zksync_consensus_roles::{
+    node,
+    validator::{BlockNumber, FinalBlock},
+};
 use zksync_protobuf::{read_optional, ProtoFmt};
 
 /// `get_block` RPC.
@@ -13,6 +16,7 @@ impl super::Rpc for Rpc {
     const CAPABILITY_ID: mux::CapabilityId = 4;
     const INFLIGHT: u32 = 5;
     const METHOD: &'static str = "get_block";
+    const CAPPED: bool = false;
 
     type Req = Req;
     type Resp = Resp;
@@ -40,18 +44,27 @@ impl ProtoFmt for Req {
 
 /// Response to a [`GetBlockRequest`] containing a block or a reason it cannot be retrieved.
 #[derive(Debug, PartialEq)]
-pub(crate) struct Resp(pub(crate) Option<FinalBlock>);
+pub(crate) struct Resp {
+    /// Requested block, `None` if not available.
+    pub(crate) block: Option<FinalBlock>,
+    /// Signature of the server over `node::RelayedBlock` containing `block`.
+    pub(crate) relay_sig: Option<node::Signature>,
+}
 
 impl ProtoFmt for Resp {
     type Proto = proto::GetBlockResponse;
 
     fn read(r: &Self::Proto) -> anyhow::Result<Self> {
-        Ok(Self(read_optional(&r.block).context("block")?))
+        Ok(Self {
+            block: read_optional(&r.block).context("block")?,
+            relay_sig: read_optional(&r.relay_sig).context("relay_sig")?,
+        })
     }
 
     fn build(&self) -> Self::Proto {
         Self::Proto {
-            block: self.0.as_ref().map(ProtoFmt::build),
+            block: self.block.as_ref().map(ProtoFmt::build),
+            relay_sig: self.relay_sig.as_ref().map(ProtoFmt::build),
         }
     }
 }
@@ -0,0 +1,124 @@
+//! RPC for fetching a block from peer in chunks.
+//! Unlike `get_block`, it allows fetching blocks larger than the max RPC message size,
+//! without buffering the whole encoded block before decoding it.
+use crate::{mux, proto::gossip as proto};
+use anyhow::Context;
+use zksync_consensus_roles::{
+    node,
+    validator::{BlockNumber, CommitQC, KeyRotationCert, ProtocolUpgradeQC},
+};
+use zksync_protobuf::{kB, read_optional, required, ProtoFmt};
+
+/// Max size of the payload data sent in a single chunk.
+pub(crate) const MAX_CHUNK_SIZE: usize = 256 * kB;
+
+/// `get_block_chunk` RPC.
+#[derive(Debug)]
+pub(crate) struct Rpc;
+
+impl super::Rpc for Rpc {
+    const CAPABILITY_ID: mux::CapabilityId = 6;
+    const INFLIGHT: u32 = 5;
+    const METHOD: &'static str = "get_block_chunk";
+    const CAPPED: bool = false;
+
+    type Req = Req;
+    type Resp = Resp;
+}
+
+/// Asks the server to send a chunk of a block payload.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Req {
+    /// Number of the block.
+    pub(crate) number: BlockNumber,
+    /// Offset of the chunk within the payload.
+    pub(crate) offset: usize,
+    /// Asks for the justification only, without the payload data.
+    pub(crate) justification_only: bool,
+}
+
+impl ProtoFmt for Req {
+    type Proto = proto::GetBlockChunkRequest;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            number: BlockNumber(*required(&r.number).context("number")?),
+            offset: (*required(&r.offset).context("offset")?)
+                .try_into()
+                .context("offset")?,
+            justification_only: r.justification_only.unwrap_or(false),
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            number: Some(self.number.0),
+            offset: Some(self.offset.try_into().unwrap()),
+            justification_only: Some(self.justification_only),
+        }
+    }
+}
+
+/// Chunk of a block.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Chunk {
+    /// Justification of the block, sent together with the first chunk only.
+    pub(crate) justification: Option<CommitQC>,
+    /// Signature of the server over `node::RelayedBlock` containing the block.
+    /// Sent together with the first chunk only.
+    pub(crate) relay_sig: Option<node::Signature>,
+    /// Size of the whole payload.
+    pub(crate) payload_size: usize,
+    /// Part of the payload starting at the requested offset.
+    pub(crate) data: Vec<u8>,
+    /// Key rotation committed by the block, sent together with the first chunk only.
+    pub(crate) key_rotation: Option<KeyRotationCert>,
+    /// Protocol upgrade committed by the block, sent together with the first chunk only.
+    pub(crate) protocol_upgrade: Option<ProtocolUpgradeQC>,
+}
+
+impl ProtoFmt for Chunk {
+    type Proto = proto::BlockChunk;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            justification: read_optional(&r.justification).context("justification")?,
+            relay_sig: read_optional(&r.relay_sig).context("relay_sig")?,
+            payload_size: (*required(&r.payload_size).context("payload_size")?)
+                .try_into()
+                .context("payload_size")?,
+            data: required(&r.data).context("data")?.clone(),
+            key_rotation: read_optional(&r.key_rotation).context("key_rotation")?,
+            protocol_upgrade: read_optional(&r.protocol_upgrade).context("protocol_upgrade")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            justification: self.justification.as_ref().map(ProtoFmt::build),
+            relay_sig: self.relay_sig.as_ref().map(ProtoFmt::build),
+            payload_size: Some(self.payload_size.try_into().unwrap()),
+            data: Some(self.data.clone()),
+            key_rotation: self.key_rotation.as_ref().map(ProtoFmt::build),
+            protocol_upgrade: self.protocol_upgrade.as_ref().map(ProtoFmt::build),
+        }
+    }
+}
+
+/// Response to a [`Req`] containing a chunk of the block, `None` if the block is not available.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Resp(pub(crate) Option<Chunk>);
+
+impl ProtoFmt for Resp {
+    type Proto = proto::GetBlockChunkResponse;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self(read_optional(&r.chunk).context("chunk")?))
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            chunk: self.0.as_ref().map(ProtoFmt::build),
+        }
+    }
+}
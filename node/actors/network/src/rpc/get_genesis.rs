@@ -0,0 +1,98 @@
+//! RPC for fetching the genesis of a peer.
+//! It is served on a dedicated endpoint without a handshake, so that it is available
+//! to the nodes with a different (or not yet known) genesis. Instead, the server
+//! authenticates itself by signing the session ID with its node key.
+use crate::{mux, proto::gossip as proto};
+use anyhow::Context as _;
+use zksync_concurrency::{ctx, limiter, time};
+use zksync_consensus_roles::{node, validator};
+use zksync_protobuf::{kB, read_required, ProtoFmt, MB};
+
+/// `get_genesis` RPC.
+#[derive(Debug)]
+pub(crate) struct Rpc;
+
+impl super::Rpc for Rpc {
+    const CAPABILITY_ID: mux::CapabilityId = 13;
+    const INFLIGHT: u32 = 1;
+    const METHOD: &'static str = "get_genesis";
+
+    type Req = ();
+    type Resp = Resp;
+}
+
+/// Hardcoded rate supported by the server.
+/// A single call per connection is expected.
+pub(crate) const RATE: limiter::Rate = limiter::Rate {
+    burst: 1,
+    refresh: time::Duration::seconds(1),
+};
+
+/// Max size of the response. Genesis contains the whole validator and attester committees.
+const MAX_RESP_SIZE: usize = 10 * MB;
+
+/// Genesis of the server.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Resp {
+    /// Genesis of the server.
+    pub(crate) genesis: validator::Genesis,
+    /// Session ID signed with the node key of the server.
+    pub(crate) session_id: node::Signed<node::SessionId>,
+}
+
+impl ProtoFmt for Resp {
+    type Proto = proto::GetGenesisResponse;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            genesis: read_required(&r.genesis).context("genesis")?,
+            session_id: read_required(&r.session_id).context("session_id")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            genesis: Some(self.genesis.build()),
+            session_id: Some(self.session_id.build()),
+        }
+    }
+}
+
+/// Server responding with the genesis of this node.
+pub(crate) struct Server<'a> {
+    /// Genesis of this node.
+    pub(crate) genesis: &'a validator::Genesis,
+    /// ID of the session, signed with the node key of this node.
+    pub(crate) session_id: node::Signed<node::SessionId>,
+}
+
+#[async_trait::async_trait]
+impl super::Handler<Rpc> for Server<'_> {
+    fn max_req_size(&self) -> usize {
+        kB
+    }
+
+    async fn handle(&self, _ctx: &ctx::Ctx, _req: ()) -> anyhow::Result<Resp> {
+        Ok(Resp {
+            genesis: self.genesis.clone(),
+            session_id: self.session_id.clone(),
+        })
+    }
+}
+
+impl super::Client<Rpc> {
+    /// Fetches the genesis of the peer `peer`, over the session `session_id`.
+    /// Fails if the response is not signed by `peer` for this session.
+    pub(crate) async fn get_genesis(
+        &self,
+        ctx: &ctx::Ctx,
+        session_id: &node::SessionId,
+        peer: &node::PublicKey,
+    ) -> anyhow::Result<validator::Genesis> {
+        let resp = self.call(ctx, &(), MAX_RESP_SIZE).await?;
+        anyhow::ensure!(&resp.session_id.msg == session_id, "session ID mismatch");
+        anyhow::ensure!(&resp.session_id.key == peer, "peer key mismatch");
+        resp.session_id.verify().context("session_id")?;
+        Ok(resp.genesis)
+    }
+}
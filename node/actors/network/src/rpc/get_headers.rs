@@ -0,0 +1,130 @@
+//! RPC for fetching a range of block headers together with their justifications,
+//! without the payloads. Used by the light clients, which only track the finality
+//! of the blocks: a `CommitQC` certifies the header, which commits to the payload.
+//! The key rotations and protocol upgrades committed by the headers are sent along,
+//! so that the light clients can follow the changes of the validator set and of the protocol.
+use crate::{mux, proto::gossip as proto};
+use anyhow::Context as _;
+use zksync_concurrency::{limiter, time};
+use zksync_consensus_roles::validator::{
+    BlockNumber, CommitQC, FinalBlock, KeyRotationCert, ProtocolUpgradeQC,
+};
+use zksync_protobuf::{read_optional, read_required, required, ProtoFmt};
+
+/// `get_headers` RPC.
+#[derive(Debug)]
+pub(crate) struct Rpc;
+
+impl super::Rpc for Rpc {
+    const CAPABILITY_ID: mux::CapabilityId = 16;
+    const INFLIGHT: u32 = 1;
+    const METHOD: &'static str = "get_headers";
+    const CAPPED: bool = false;
+
+    type Req = Req;
+    type Resp = Resp;
+}
+
+/// Max number of the headers sent in a single response.
+pub(crate) const MAX_HEADERS: usize = 100;
+
+/// Hardcoded rate supported by the server.
+pub(crate) const RATE: limiter::Rate = limiter::Rate {
+    burst: 10,
+    refresh: time::Duration::milliseconds(100),
+};
+
+/// Asks the server to send the justifications of the consecutive blocks
+/// starting at `first`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Req {
+    /// Number of the first block.
+    pub(crate) first: BlockNumber,
+    /// Max number of the blocks. The server sends at most `MAX_HEADERS` of them.
+    pub(crate) count: usize,
+}
+
+impl ProtoFmt for Req {
+    type Proto = proto::GetHeadersRequest;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            first: BlockNumber(*required(&r.first).context("first")?),
+            count: (*required(&r.count).context("count")?)
+                .try_into()
+                .context("count")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            first: Some(self.first.0),
+            count: Some(self.count.try_into().unwrap()),
+        }
+    }
+}
+
+/// Header of a finalized block, i.e. a finalized block without the payload.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FinalHeader {
+    /// Justification of the block, certifying the header.
+    pub justification: CommitQC,
+    /// Key rotation committed by the block. Should match `header.key_rotation` hash.
+    pub key_rotation: Option<KeyRotationCert>,
+    /// Protocol upgrade committed by the block. Should match `header.protocol_upgrade` hash.
+    pub protocol_upgrade: Option<ProtocolUpgradeQC>,
+}
+
+impl From<&FinalBlock> for FinalHeader {
+    fn from(block: &FinalBlock) -> Self {
+        Self {
+            justification: block.justification.clone(),
+            key_rotation: block.key_rotation.clone(),
+            protocol_upgrade: block.protocol_upgrade.clone(),
+        }
+    }
+}
+
+impl ProtoFmt for FinalHeader {
+    type Proto = proto::FinalHeader;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            justification: read_required(&r.justification).context("justification")?,
+            key_rotation: read_optional(&r.key_rotation).context("key_rotation")?,
+            protocol_upgrade: read_optional(&r.protocol_upgrade).context("protocol_upgrade")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            justification: Some(self.justification.build()),
+            key_rotation: self.key_rotation.as_ref().map(ProtoFmt::build),
+            protocol_upgrade: self.protocol_upgrade.as_ref().map(ProtoFmt::build),
+        }
+    }
+}
+
+/// Headers of the consecutive blocks starting at the requested one.
+/// Contains only a prefix of the requested range if the server doesn't have
+/// the remaining blocks (empty if it doesn't have the first one).
+#[derive(Debug, PartialEq)]
+pub(crate) struct Resp(pub(crate) Vec<FinalHeader>);
+
+impl ProtoFmt for Resp {
+    type Proto = proto::GetHeadersResponse;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        let mut headers = vec![];
+        for (i, header) in r.headers.iter().enumerate() {
+            headers.push(ProtoFmt::read(header).with_context(|| format!("headers[{i}]"))?);
+        }
+        Ok(Self(headers))
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            headers: self.0.iter().map(ProtoFmt::build).collect(),
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! RPC for exchanging liveness heartbeats between validators.
+use crate::{mux, proto::consensus as proto};
+use zksync_concurrency::{limiter, time};
+use zksync_consensus_roles::validator;
+use zksync_protobuf::{read_required, ProtoFmt};
+
+/// Heartbeat RPC.
+pub(crate) struct Rpc;
+
+impl super::Rpc for Rpc {
+    const CAPABILITY_ID: mux::CapabilityId = 5;
+    const INFLIGHT: u32 = 1;
+    const METHOD: &'static str = "heartbeat";
+    type Req = Req;
+    type Resp = ();
+}
+
+/// Hardcoded expected rate supported by the server.
+/// Heartbeats are low frequency, so it is way above what honest peers send.
+pub(crate) const RATE: limiter::Rate = limiter::Rate {
+    burst: 2,
+    refresh: time::Duration::seconds(5),
+};
+
+/// Signed heartbeat of the sending validator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Req(pub(crate) validator::Signed<validator::Heartbeat>);
+
+impl ProtoFmt for Req {
+    type Proto = proto::HeartbeatReq;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        read_required(&r.heartbeat).map(Self)
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            heartbeat: Some(self.0.build()),
+        }
+    }
+}
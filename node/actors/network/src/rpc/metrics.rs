@@ -3,8 +3,8 @@
 use super::Rpc;
 use std::{any::Any, time::Duration};
 use vise::{
-    Buckets, EncodeLabelSet, EncodeLabelValue, Family, Gauge, Histogram, LabeledFamily, Metrics,
-    Unit,
+    Buckets, Counter, EncodeLabelSet, EncodeLabelValue, Family, Gauge, Histogram, LabeledFamily,
+    Metrics, Unit,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EncodeLabelValue)]
@@ -102,6 +102,9 @@ pub(super) struct RpcMetrics {
     /// Time that client waits for the server to prepare a stream for an RPC call.
     #[metrics(unit = Unit::Seconds, buckets = Buckets::LATENCIES, labels = ["method"])]
     pub(super) call_reserve_latency: LabeledFamily<&'static str, Histogram<Duration>>,
+    /// Number of requests refused by the server-side rate limit.
+    #[metrics(labels = ["method"])]
+    pub(super) rate_limited: LabeledFamily<&'static str, Counter>,
 }
 
 #[vise::register]
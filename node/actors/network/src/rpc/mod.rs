@@ -16,18 +16,39 @@
 //! at the same time (max 1 client + server per CapabilityId).
 
 use self::metrics::{CallLatencyType, CallType, RPC_METRICS};
-use crate::{frame, mux};
+use crate::{
+    dump, frame,
+    metrics::{FrameErrorLayer, PeerRpcLabels, PEER_METRICS},
+    mux, traffic,
+};
 use anyhow::Context as _;
-use std::{collections::BTreeMap, sync::Arc};
-use zksync_concurrency::{ctx, io, limiter, metrics::LatencyHistogramExt as _, scope};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+use zksync_concurrency::{ctx, io, limiter, metrics::LatencyHistogramExt as _, scope, time};
 
 pub(crate) mod consensus;
 pub(crate) mod get_block;
+pub(crate) mod get_block_chunk;
+pub(crate) mod get_genesis;
+pub(crate) mod get_headers;
+pub(crate) mod heartbeat;
 mod metrics;
+pub(crate) mod pex;
 pub(crate) mod ping;
+pub(crate) mod push_batch_votes;
 pub(crate) mod push_block_store_state;
+pub(crate) mod push_high_qc;
+pub(crate) mod push_session_ticket;
+pub(crate) mod push_topic;
+pub(crate) mod push_upgrade_votes;
 pub(crate) mod push_validator_addrs;
-#[cfg(test)]
+pub(crate) mod relay_consensus;
+pub(crate) mod sample_payload;
+pub(crate) mod sentry_relay;
+pub(crate) mod sentry_sign;
+#[cfg(any(test, feature = "fuzzing"))]
 pub(crate) mod testonly;
 #[cfg(test)]
 mod tests;
@@ -37,6 +58,7 @@ const MUX_CONFIG: mux::Config = mux::Config {
     read_frame_size: 16 * zksync_protobuf::kB as u64,
     read_frame_count: 100,
     write_frame_size: 16 * zksync_protobuf::kB as u64,
+    keepalive: None,
 };
 
 /// Trait for defining an RPC.
@@ -54,6 +76,10 @@ pub(crate) trait Rpc: Sync + Send + 'static {
     const INFLIGHT: u32;
     /// Name of the RPC, used in prometheus metrics.
     const METHOD: &'static str;
+    /// Whether the traffic of the RPC counts towards `GossipConfig::peer_bandwidth_cap`.
+    /// Block sync RPCs are exempt: their responses are requested by this node,
+    /// which throttles the transfer by bounding the calls in flight.
+    const CAPPED: bool = true;
     /// Type of the request message.
     type Req: zksync_protobuf::ProtoFmt + Send + Sync;
     /// Type of the response message.
@@ -75,9 +101,31 @@ pub(crate) trait Rpc: Sync + Send + 'static {
 pub(crate) struct ReservedCall<'a, R: Rpc> {
     stream: mux::ReservedStream,
     permit: limiter::Permit<'a>,
+    /// Encoder of the client's requests.
+    encoder: &'a Mutex<frame::Encoder>,
     _rpc: std::marker::PhantomData<R>,
 }
 
+/// Request encoded once, so that it can be sent to multiple servers
+/// without encoding it again for every call.
+pub(crate) struct EncodedReq<R: Rpc> {
+    req: R::Req,
+    bytes: bytes::Bytes,
+}
+
+impl<R: Rpc> EncodedReq<R> {
+    /// Encodes the request.
+    pub(crate) fn new(req: R::Req) -> Self {
+        let bytes = zksync_protobuf::encode(&req).into();
+        Self { req, bytes }
+    }
+
+    /// The request.
+    pub(crate) fn req(&self) -> &R::Req {
+        &self.req
+    }
+}
+
 impl<'a, R: Rpc> ReservedCall<'a, R> {
     /// Performs the call.
     pub(crate) async fn call(
@@ -85,6 +133,29 @@ impl<'a, R: Rpc> ReservedCall<'a, R> {
         ctx: &ctx::Ctx,
         req: &R::Req,
         max_resp_size: usize,
+    ) -> anyhow::Result<R::Resp> {
+        let bytes = self.encoder.lock().unwrap().encode(req);
+        self.call_raw(ctx, req, bytes, max_resp_size).await
+    }
+
+    /// Performs the call with an already encoded request.
+    pub(crate) async fn call_encoded(
+        self,
+        ctx: &ctx::Ctx,
+        req: &EncodedReq<R>,
+        max_resp_size: usize,
+    ) -> anyhow::Result<R::Resp> {
+        self.call_raw(ctx, &req.req, req.bytes.clone(), max_resp_size)
+            .await
+    }
+
+    /// Performs the call, sending `bytes`, which is the encoding of `req`.
+    async fn call_raw(
+        self,
+        ctx: &ctx::Ctx,
+        req: &R::Req,
+        bytes: bytes::Bytes,
+        max_resp_size: usize,
     ) -> anyhow::Result<R::Resp> {
         let send_time = ctx.now();
         let mut stream = self.stream.open(ctx).await??;
@@ -92,14 +163,24 @@ impl<'a, R: Rpc> ReservedCall<'a, R> {
         let res = async {
             let metric_labels = CallType::Client.to_labels::<R>(req);
             let _guard = RPC_METRICS.inflight[&metric_labels].inc_guard(1);
-            let msg_size = frame::mux_send_proto(ctx, &mut stream.write, req)
-                .await
-                .context("mux_send_proto(req)")?;
+            #[cfg(feature = "chaos")]
+            if let Some(chaos) = &stream.chaos {
+                chaos.inject(ctx).await?;
+            }
+            let msg_size = frame::mux_send_encoded(ctx, &mut stream.write, bytes).await?;
             RPC_METRICS.message_size[&CallType::ReqSent.to_labels::<R>(req)].observe(msg_size);
+            if let Some(dump) = &stream.dump {
+                dump.dump(ctx.now_utc(), dump::Direction::Sent, R::METHOD, req);
+            }
             drop(stream.write);
-            frame::mux_recv_proto(ctx, &mut stream.read, max_resp_size).await
+            let res = frame::mux_recv_proto::<R::Resp>(ctx, &mut stream.read, max_resp_size).await;
+            if let (Some(dump), Ok((resp, _))) = (&stream.dump, &res) {
+                dump.dump(ctx.now_utc(), dump::Direction::Received, R::METHOD, resp);
+            }
+            res
         }
-        .await;
+        .await
+        .map_err(|err| anyhow::Error::from(FrameErrorLayer::Rpc.observe(err)));
 
         let now = ctx.now();
         let metric_labels = CallLatencyType::ClientSendRecv.to_labels::<R>(req, &res);
@@ -110,10 +191,26 @@ impl<'a, R: Rpc> ReservedCall<'a, R> {
     }
 }
 
+/// Checks whether the call has been refused by the server's rate limit.
+/// Such calls can be retried later, without dropping the connection.
+pub(crate) fn is_rate_limited(err: &ctx::Error) -> bool {
+    let ctx::Error::Internal(err) = err else {
+        return false;
+    };
+    err.chain().any(|e| {
+        matches!(
+            e.downcast_ref::<frame::Error>(),
+            Some(frame::Error::RateLimited)
+        )
+    })
+}
+
 /// RPC client used to issue the calls to the server.
 pub(crate) struct Client<R: Rpc> {
     limiter: limiter::Limiter,
     queue: Arc<mux::StreamQueue>,
+    /// Buffers for encoding the requests, reused across the calls.
+    encoder: Mutex<frame::Encoder>,
     _rpc: std::marker::PhantomData<R>,
 }
 
@@ -123,10 +220,16 @@ impl<R: Rpc> Client<R> {
         Client {
             limiter: limiter::Limiter::new(ctx, rate),
             queue: mux::StreamQueue::new(R::INFLIGHT),
+            encoder: Mutex::default(),
             _rpc: std::marker::PhantomData,
         }
     }
 
+    /// Waits for the connection handshake and returns whether the peer serves this RPC.
+    pub(crate) async fn supported(&self, ctx: &ctx::Ctx) -> ctx::OrCanceled<bool> {
+        self.queue.supported(ctx).await
+    }
+
     /// Reserves an RPC.
     pub(crate) async fn reserve<'a>(
         &'a self,
@@ -139,6 +242,7 @@ impl<R: Rpc> Client<R> {
         Ok(ReservedCall {
             stream,
             permit,
+            encoder: &self.encoder,
             _rpc: std::marker::PhantomData,
         })
     }
@@ -156,6 +260,20 @@ impl<R: Rpc> Client<R> {
             .call(ctx, req, max_resp_size)
             .await?)
     }
+
+    /// Performs an RPC with an already encoded request.
+    pub(crate) async fn call_encoded(
+        &self,
+        ctx: &ctx::Ctx,
+        req: &EncodedReq<R>,
+        max_resp_size: usize,
+    ) -> ctx::Result<R::Resp> {
+        Ok(self
+            .reserve(ctx)
+            .await?
+            .call_encoded(ctx, req, max_resp_size)
+            .await?)
+    }
 }
 
 /// Trait for defining RPC server implementations.
@@ -173,35 +291,67 @@ struct Server<R: Rpc, H: Handler<R>> {
     handler: H,
     queue: Arc<mux::StreamQueue>,
     rate: limiter::Rate,
+    /// Per-request rate limit. Requests above it get the "rate limited" response.
+    limit: Option<limiter::Rate>,
     _rpc: std::marker::PhantomData<R>,
 }
 
 #[async_trait::async_trait]
 trait ServerTrait: Sync + Send {
-    async fn serve(&self, ctx: &ctx::Ctx) -> ctx::OrCanceled<()>;
+    async fn serve(&self, ctx: &ctx::Ctx, peer: Option<&str>) -> ctx::OrCanceled<()>;
 }
 
 #[async_trait::async_trait]
 impl<R: Rpc, H: Handler<R>> ServerTrait for Server<R, H> {
     /// Serves the incoming RPCs, respecting the rate limit and
     /// max inflight limit.
-    async fn serve(&self, ctx: &ctx::Ctx) -> ctx::OrCanceled<()> {
+    async fn serve(&self, ctx: &ctx::Ctx, peer: Option<&str>) -> ctx::OrCanceled<()> {
         let limiter = limiter::Limiter::new(ctx, self.rate);
+        let limit = self.limit.map(|rate| limiter::Limiter::new(ctx, rate));
         scope::run!(ctx, |ctx, s| async {
             for _ in 0..R::INFLIGHT {
                 s.spawn::<()>(async {
+                    let mut encoder = frame::Encoder::default();
                     loop {
                         let permit = limiter.acquire(ctx, 1).await?;
                         let mut stream = self.queue.open(ctx).await?;
                         drop(permit);
                         let res = async {
                             let recv_time = ctx.now();
+                            if let Some(peer) = peer {
+                                PEER_METRICS.rpc_calls[&PeerRpcLabels {
+                                    peer: peer.to_string(),
+                                    method: R::METHOD,
+                                }]
+                                    .inc();
+                            }
+                            // The limit is checked before the request is received, so that
+                            // the refused requests are neither buffered nor decoded.
+                            // The remaining frames of the request are discarded by the mux.
+                            if let Some(limit) = &limit {
+                                if limit.try_acquire(ctx, 1).is_none() {
+                                    RPC_METRICS.rate_limited[&R::METHOD].inc();
+                                    frame::mux_send_rate_limited(ctx, &mut stream.write)
+                                        .await
+                                        .map_err(|err| FrameErrorLayer::Rpc.observe(err))?;
+                                    return anyhow::Ok(());
+                                }
+                            }
                             let (req, msg_size) = frame::mux_recv_proto::<R::Req>(
                                 ctx,
                                 &mut stream.read,
                                 self.handler.max_req_size(),
                             )
-                            .await?;
+                            .await
+                            .map_err(|err| FrameErrorLayer::Rpc.observe(err))?;
+                            if let Some(dump) = &stream.dump {
+                                dump.dump(
+                                    ctx.now_utc(),
+                                    dump::Direction::Received,
+                                    R::METHOD,
+                                    &req,
+                                );
+                            }
 
                             let size_labels = CallType::ReqRecv.to_labels::<R>(&req);
                             let resp_size_labels = CallType::RespSent.to_labels::<R>(&req);
@@ -219,7 +369,20 @@ impl<R: Rpc, H: Handler<R>> ServerTrait for Server<R, H> {
                             RPC_METRICS.latency[&server_process_labels]
                                 .observe_latency(ctx.now() - process_time);
 
-                            let res = frame::mux_send_proto(ctx, &mut stream.write, &res?).await;
+                            let resp = res?;
+                            if let Some(dump) = &stream.dump {
+                                dump.dump(ctx.now_utc(), dump::Direction::Sent, R::METHOD, &resp);
+                            }
+                            let res = async {
+                                #[cfg(feature = "chaos")]
+                                if let Some(chaos) = &stream.chaos {
+                                    chaos.inject(ctx).await?;
+                                }
+                                frame::mux_send_proto(ctx, &mut stream.write, &mut encoder, &resp)
+                                    .await
+                            }
+                            .await
+                            .map_err(|err| anyhow::Error::from(FrameErrorLayer::Rpc.observe(err)));
                             recv_send_labels.set_result(&res);
                             RPC_METRICS.latency[&recv_send_labels]
                                 .observe_latency(ctx.now() - recv_time);
@@ -245,6 +408,8 @@ impl<R: Rpc, H: Handler<R>> ServerTrait for Server<R, H> {
 pub(crate) struct Service<'a> {
     mux: mux::Mux,
     servers: Vec<Box<dyn 'a + ServerTrait>>,
+    /// Label of the peer, if the served RPCs are reported per peer.
+    metrics_peer: Option<String>,
 }
 
 impl<'a> Service<'a> {
@@ -255,11 +420,55 @@ impl<'a> Service<'a> {
                 cfg: Arc::new(MUX_CONFIG.clone()),
                 accept: BTreeMap::default(),
                 connect: BTreeMap::default(),
+                traffic: Arc::default(),
+                #[cfg(any(test, feature = "chaos"))]
+                filter: None,
+                dump: None,
+                #[cfg(feature = "chaos")]
+                chaos: None,
             },
             servers: vec![],
+            metrics_peer: None,
         }
     }
 
+    /// Reports the RPCs served over the connection per peer, if `peer` is set.
+    pub(crate) fn metrics_peer(mut self, peer: Option<String>) -> Self {
+        self.metrics_peer = peer;
+        self
+    }
+
+    /// Traffic of the connection, per RPC.
+    pub(crate) fn traffic(&self) -> &Arc<traffic::Traffic> {
+        &self.mux.traffic
+    }
+
+    /// Sets the filter of the frames sent over the connection.
+    #[cfg(any(test, feature = "chaos"))]
+    pub(crate) fn filter(mut self, filter: crate::partition::Filter) -> Self {
+        self.mux.filter = Some(filter);
+        self
+    }
+
+    /// Injects the faults into the messages sent over the connection.
+    #[cfg(feature = "chaos")]
+    pub(crate) fn chaos(mut self, cfg: Option<crate::chaos::Config>) -> Self {
+        self.mux.chaos = cfg;
+        self
+    }
+
+    /// Dumps the messages exchanged over the connection, while the dump of the peer is active.
+    pub(crate) fn dump(mut self, dumper: dump::Dumper) -> Self {
+        self.mux.dump = Some(dumper);
+        self
+    }
+
+    /// Sets the keepalive of the connection.
+    pub(crate) fn keepalive(mut self, cfg: Option<crate::KeepaliveConfig>) -> Self {
+        Arc::make_mut(&mut self.mux.cfg).keepalive = cfg;
+        self
+    }
+
     /// Adds a client to the RPC service.
     pub(crate) fn add_client<R: Rpc>(mut self, client: &Client<R>) -> Self {
         if self
@@ -273,14 +482,45 @@ impl<'a> Service<'a> {
                 R::CAPABILITY_ID
             );
         }
+        self.mux
+            .traffic
+            .register(R::CAPABILITY_ID, R::METHOD, R::CAPPED);
         self
     }
 
     /// Adds a server to the RPC service.
+    /// Calls above `rate` are delayed, until the server accepts them.
     pub(crate) fn add_server<R: Rpc>(
+        self,
+        handler: impl Handler<R> + 'a,
+        rate: limiter::Rate,
+    ) -> Self {
+        self.add_server_with_limit(handler, rate, None)
+    }
+
+    /// Adds a server to the RPC service.
+    /// Calls above `limit` are accepted, but refused with the "rate limited" response
+    /// (without receiving the request),
+    /// so that the peer sending too many requests is throttled rather than disconnected
+    /// or allowed to queue up requests.
+    pub(crate) fn add_limited_server<R: Rpc>(
+        self,
+        handler: impl Handler<R> + 'a,
+        limit: limiter::Rate,
+    ) -> Self {
+        // Concurrent calls are still bounded by `R::INFLIGHT`.
+        let rate = limiter::Rate {
+            burst: 1,
+            refresh: time::Duration::ZERO,
+        };
+        self.add_server_with_limit(handler, rate, Some(limit))
+    }
+
+    fn add_server_with_limit<R: Rpc>(
         mut self,
         handler: impl Handler<R> + 'a,
         rate: limiter::Rate,
+        limit: Option<limiter::Rate>,
     ) -> Self {
         let queue = mux::StreamQueue::new(R::INFLIGHT);
         if self
@@ -294,10 +534,14 @@ impl<'a> Service<'a> {
                 R::CAPABILITY_ID
             );
         }
+        self.mux
+            .traffic
+            .register(R::CAPABILITY_ID, R::METHOD, R::CAPPED);
         self.servers.push(Box::new(Server {
             handler,
             queue,
             rate,
+            limit,
             _rpc: std::marker::PhantomData,
         }));
         self
@@ -311,7 +555,7 @@ impl<'a> Service<'a> {
     ) -> Result<(), mux::RunError> {
         scope::run!(ctx, |ctx, s| async {
             for server in &self.servers {
-                s.spawn(async { Ok(server.serve(ctx).await?) });
+                s.spawn(async { Ok(server.serve(ctx, self.metrics_peer.as_deref()).await?) });
             }
             self.mux.run(ctx, transport).await
         })
@@ -0,0 +1,58 @@
+//! Peer exchange RPC: peers periodically push to each other a sample of the known
+//! node addresses, so that the nodes can discover new peers.
+use crate::{mux, proto::gossip as proto};
+use anyhow::Context as _;
+use std::sync::Arc;
+use zksync_concurrency::{limiter, time};
+use zksync_consensus_roles::node;
+use zksync_protobuf::ProtoFmt;
+
+/// Pex RPC.
+pub(crate) struct Rpc;
+
+impl super::Rpc for Rpc {
+    const CAPABILITY_ID: mux::CapabilityId = 7;
+    const INFLIGHT: u32 = 1;
+    const METHOD: &'static str = "pex";
+
+    type Req = Req;
+    type Resp = ();
+}
+
+/// Max number of the addresses in a single request.
+pub(crate) const MAX_ADDRS: usize = 32;
+
+/// Interval at which the peers exchange the addresses.
+pub(crate) const INTERVAL: time::Duration = time::Duration::seconds(10);
+
+/// Hardcoded rate supported by the server.
+pub(crate) const RATE: limiter::Rate = limiter::Rate {
+    burst: 2,
+    refresh: time::Duration::seconds(5),
+};
+
+/// Sample of the node addresses known to the sender, including its own address.
+/// Every address is signed by the node it belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Req(pub(crate) Vec<Arc<node::Signed<node::NodeAddr>>>);
+
+impl ProtoFmt for Req {
+    type Proto = proto::Pex;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        anyhow::ensure!(r.node_addrs.len() <= MAX_ADDRS, "too many addresses");
+        let mut addrs = vec![];
+        for (i, e) in r.node_addrs.iter().enumerate() {
+            addrs.push(Arc::new(
+                ProtoFmt::read(e).with_context(|| format!("node_addrs[{i}]"))?,
+            ));
+        }
+        Ok(Self(addrs))
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            node_addrs: self.0.iter().map(|a| ProtoFmt::build(a.as_ref())).collect(),
+        }
+    }
+}
@@ -1,9 +1,9 @@
 //! Defines an RPC for sending ping messages.
-use crate::{mux, proto::ping as proto};
+use crate::{mux, proto::ping as proto, TimeSource};
 use anyhow::Context as _;
 use rand::Rng;
 use zksync_concurrency::{ctx, limiter, time};
-use zksync_protobuf::{kB, required, ProtoFmt};
+use zksync_protobuf::{kB, read_optional, required, ProtoFmt};
 
 /// Ping RPC.
 pub(crate) struct Rpc;
@@ -25,37 +25,71 @@ pub(crate) const RATE: limiter::Rate = limiter::Rate {
 };
 
 /// Canonical Ping server implementation,
-/// which responds with data from the request.
-pub(crate) struct Server;
+/// which responds with data from the request and its current UTC time.
+pub(crate) struct Server<'a>(pub(crate) &'a TimeSource);
 
 #[async_trait::async_trait]
-impl super::Handler<Rpc> for Server {
+impl super::Handler<Rpc> for Server<'_> {
     fn max_req_size(&self) -> usize {
         kB
     }
-    async fn handle(&self, _ctx: &ctx::Ctx, req: Req) -> anyhow::Result<Resp> {
-        Ok(Resp(req.0))
+    async fn handle(&self, ctx: &ctx::Ctx, req: Req) -> anyhow::Result<Resp> {
+        Ok(Resp {
+            data: req.0,
+            timestamp: Some(self.0.now_utc(ctx)),
+        })
     }
 }
 
+/// Measurement obtained from a single ping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Sample {
+    /// Round trip time of the ping.
+    pub(crate) rtt: time::Duration,
+    /// Estimated offset of the peer's UTC clock relative to ours
+    /// (positive if the peer's clock is ahead), assuming symmetric latency.
+    /// `None` if the peer doesn't report its time.
+    pub(crate) clock_skew: Option<time::Duration>,
+}
+
 impl super::Client<Rpc> {
-    /// Sends a ping every `timeout`.
+    /// Sends a single ping and measures the round trip.
+    /// The clock skew of the peer is measured against `time`.
+    /// Returns an error if the request fails or exceeds `timeout`.
+    pub(crate) async fn ping(
+        &self,
+        ctx: &ctx::Ctx,
+        time: &TimeSource,
+        timeout: time::Duration,
+    ) -> anyhow::Result<Sample> {
+        let req = Req(ctx.rng().gen());
+        let (start, start_utc) = (ctx.now(), time.now_utc(ctx));
+        let resp = self
+            .call(&ctx.with_timeout(timeout), &req, kB)
+            .await
+            .context("ping")?;
+        if req.0 != resp.data {
+            anyhow::bail!("bad ping response");
+        }
+        let rtt = ctx.now() - start;
+        Ok(Sample {
+            rtt,
+            clock_skew: resp.timestamp.map(|t| t - (start_utc + rtt / 2)),
+        })
+    }
+
+    /// Sends a ping every `timeout`, passing the measurements to `observe`.
     /// Returns an error if any single ping request fails or
     /// exceeds `timeout`.
     pub(crate) async fn ping_loop(
         &self,
         ctx: &ctx::Ctx,
+        time: &TimeSource,
         timeout: time::Duration,
+        mut observe: impl FnMut(Sample) + Send,
     ) -> anyhow::Result<()> {
         loop {
-            let req = Req(ctx.rng().gen());
-            let resp = self
-                .call(&ctx.with_timeout(timeout), &req, kB)
-                .await
-                .context("ping")?;
-            if req.0 != resp.0 {
-                anyhow::bail!("bad ping response");
-            }
+            observe(self.ping(ctx, time, timeout).await?);
             if let Err(ctx::Canceled) = ctx.sleep(timeout).await {
                 return Ok(());
             }
@@ -70,7 +104,12 @@ pub(crate) struct Req(pub(crate) [u8; 32]);
 /// Ping response, should contain the same data
 /// as the request.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub(crate) struct Resp(pub(crate) [u8; 32]);
+pub(crate) struct Resp {
+    /// Data from the request.
+    pub(crate) data: [u8; 32],
+    /// UTC time of the server. Older nodes don't set it.
+    pub(crate) timestamp: Option<time::Utc>,
+}
 
 impl ProtoFmt for Req {
     type Proto = proto::PingReq;
@@ -87,11 +126,15 @@ impl ProtoFmt for Req {
 impl ProtoFmt for Resp {
     type Proto = proto::PingResp;
     fn read(r: &Self::Proto) -> anyhow::Result<Self> {
-        Ok(Self(required(&r.data)?[..].try_into()?))
+        Ok(Self {
+            data: required(&r.data)?[..].try_into()?,
+            timestamp: read_optional(&r.timestamp).context("timestamp")?,
+        })
     }
     fn build(&self) -> Self::Proto {
         Self::Proto {
-            data: Some(self.0.into()),
+            data: Some(self.data.into()),
+            timestamp: self.timestamp.as_ref().map(|t| t.build()),
         }
     }
 }
@@ -2,7 +2,7 @@
 use crate::{mux, proto::gossip as proto};
 use anyhow::Context;
 use zksync_consensus_roles::validator;
-use zksync_consensus_storage::BlockStoreState;
+use zksync_consensus_storage::{BlockRange, BlockStoreState};
 use zksync_protobuf::{read_optional, required, ProtoFmt};
 
 /// PushBlockStoreState RPC.
@@ -26,16 +26,38 @@ impl ProtoFmt for Req {
     type Proto = proto::PushBlockStoreState;
 
     fn read(message: &Self::Proto) -> anyhow::Result<Self> {
-        Ok(Self(BlockStoreState {
+        let state = BlockStoreState {
             first: validator::BlockNumber(*required(&message.first).context("first")?),
             last: read_optional(&message.last).context("last")?,
-        }))
+            gaps: message
+                .gaps
+                .iter()
+                .map(|gap| {
+                    Ok(BlockRange {
+                        first: validator::BlockNumber(*required(&gap.first).context("first")?),
+                        last: validator::BlockNumber(*required(&gap.last).context("last")?),
+                    })
+                })
+                .collect::<anyhow::Result<_>>()
+                .context("gaps")?,
+        };
+        state.verify_gaps().context("gaps")?;
+        Ok(Self(state))
     }
 
     fn build(&self) -> Self::Proto {
         Self::Proto {
             first: Some(self.0.first.0),
             last: self.0.last.as_ref().map(|x| x.build()),
+            gaps: self
+                .0
+                .gaps
+                .iter()
+                .map(|gap| proto::BlockRange {
+                    first: Some(gap.first.0),
+                    last: Some(gap.last.0),
+                })
+                .collect(),
         }
     }
 }
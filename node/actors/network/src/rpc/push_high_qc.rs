@@ -0,0 +1,50 @@
+//! RPC for notifying peer about the highest CommitQC known to us.
+//! The CommitQC is pushed together with the LeaderCommit carrying it,
+//! so that it can be processed by the consensus like a regular message.
+use crate::{mux, proto::gossip as proto};
+use anyhow::Context as _;
+use std::sync::Arc;
+use zksync_concurrency::{limiter, time};
+use zksync_consensus_roles::validator;
+use zksync_protobuf::{read_required, ProtoFmt};
+
+/// PushHighQC RPC.
+#[derive(Debug)]
+pub(crate) struct Rpc;
+
+impl super::Rpc for Rpc {
+    const CAPABILITY_ID: mux::CapabilityId = 8;
+    const INFLIGHT: u32 = 1;
+    const METHOD: &'static str = "push_high_qc";
+
+    type Req = Req;
+    type Resp = ();
+}
+
+/// Hardcoded rate supported by the server.
+/// Intermediate updates are skipped by the client, so it is enough to push
+/// a few times per second, even if views are shorter.
+pub(crate) const RATE: limiter::Rate = limiter::Rate {
+    burst: 2,
+    refresh: time::Duration::milliseconds(250),
+};
+
+/// LeaderCommit carrying the highest CommitQC known to the sender.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Req(pub(crate) Arc<validator::Signed<validator::LeaderCommit>>);
+
+impl ProtoFmt for Req {
+    type Proto = proto::PushHighQc;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self(Arc::new(
+            read_required(&r.leader_commit).context("leader_commit")?,
+        )))
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            leader_commit: Some(self.0.build()),
+        }
+    }
+}
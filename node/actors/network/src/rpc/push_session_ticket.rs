@@ -0,0 +1,94 @@
+//! RPC for issuing session tickets to the peers.
+use crate::{
+    mux,
+    proto::tickets as proto,
+    tickets::{Ticket, Tickets},
+};
+use anyhow::Context as _;
+use std::hash::Hash;
+use zksync_concurrency::{ctx, limiter, time};
+use zksync_consensus_crypto::ByteFmt;
+use zksync_protobuf::{kB, read_required, ProtoFmt};
+
+/// PushSessionTicket RPC.
+#[derive(Debug)]
+pub(crate) struct Rpc;
+
+impl super::Rpc for Rpc {
+    const CAPABILITY_ID: mux::CapabilityId = 12;
+    const INFLIGHT: u32 = 1;
+    const METHOD: &'static str = "push_session_ticket";
+
+    type Req = Req;
+    type Resp = ();
+}
+
+/// Hardcoded rate supported by the server.
+/// Tickets are refreshed way less often, it just allows for reconnects.
+pub(crate) const RATE: limiter::Rate = limiter::Rate {
+    burst: 2,
+    refresh: time::Duration::seconds(10),
+};
+
+/// Ticket issued to the receiver.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Req(pub(crate) Ticket);
+
+impl ProtoFmt for Req {
+    type Proto = proto::PushSessionTicket;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self(read_required(&r.ticket).context("ticket")?))
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            ticket: Some(self.0.build()),
+        }
+    }
+}
+
+/// Server storing the tickets issued to this node by the peer.
+pub(crate) struct Server<'a, K> {
+    /// Tickets of this node.
+    pub(crate) tickets: &'a Tickets<K>,
+    /// Peer on the other end of the connection.
+    pub(crate) peer: &'a K,
+}
+
+#[async_trait::async_trait]
+impl<K: ByteFmt + Clone + Eq + Hash + Send + Sync> super::Handler<Rpc> for Server<'_, K> {
+    fn max_req_size(&self) -> usize {
+        kB
+    }
+
+    async fn handle(&self, _ctx: &ctx::Ctx, req: Req) -> anyhow::Result<()> {
+        self.tickets.insert(self.peer, req.0);
+        Ok(())
+    }
+}
+
+impl super::Client<Rpc> {
+    /// Issues a ticket to `peer` and keeps refreshing it, until `ctx` is canceled.
+    /// Returns immediately if issuing tickets is disabled.
+    /// Failures are only logged: the peer can always fall back to a full handshake.
+    pub(crate) async fn push_loop<K: ByteFmt + Clone + Eq + Hash>(
+        &self,
+        ctx: &ctx::Ctx,
+        tickets: &Tickets<K>,
+        peer: &K,
+    ) -> ctx::OrCanceled<()> {
+        let Some(ttl) = tickets.ttl() else {
+            return Ok(());
+        };
+        loop {
+            if let Some(ticket) = tickets.issue(ctx, peer) {
+                if let Err(err) = self.call(ctx, &Req(ticket), kB).await {
+                    tracing::debug!("push_session_ticket(): {err:#}");
+                }
+            }
+            // Refresh the ticket well before it expires.
+            ctx.sleep(ttl / 2).await?;
+        }
+    }
+}
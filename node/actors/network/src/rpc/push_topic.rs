@@ -0,0 +1,53 @@
+//! RPC for pushing the messages of the application-defined topics to the peer.
+//! All the topics share the same RPC, the messages are dispatched by the topic name.
+use crate::{mux, proto::gossip as proto};
+use anyhow::Context as _;
+use zksync_concurrency::{limiter, time};
+use zksync_protobuf::{required, ProtoFmt};
+
+/// PushTopic RPC.
+#[derive(Debug)]
+pub(crate) struct Rpc;
+
+impl super::Rpc for Rpc {
+    const CAPABILITY_ID: mux::CapabilityId = 11;
+    const INFLIGHT: u32 = 3;
+    const METHOD: &'static str = "push_topic";
+
+    type Req = Req;
+    type Resp = ();
+}
+
+/// Hardcoded rate supported by the server, shared by all the topics.
+/// The rates of the individual topics are enforced by the topic registry.
+pub(crate) const RATE: limiter::Rate = limiter::Rate {
+    burst: 100,
+    refresh: time::Duration::milliseconds(10),
+};
+
+/// Message of a topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Req {
+    /// Name of the topic.
+    pub(crate) topic: String,
+    /// Proto-encoded message.
+    pub(crate) payload: Vec<u8>,
+}
+
+impl ProtoFmt for Req {
+    type Proto = proto::PushTopic;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            topic: required(&r.topic).context("topic")?.clone(),
+            payload: required(&r.payload).context("payload")?.clone(),
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            topic: Some(self.topic.clone()),
+            payload: Some(self.payload.clone()),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! RPC for gossiping the announcements of the validators that they support the next protocol version.
+use crate::{mux, proto::gossip as proto};
+use anyhow::Context as _;
+use std::sync::Arc;
+use zksync_concurrency::{limiter, time};
+use zksync_consensus_roles::validator;
+use zksync_protobuf::ProtoFmt;
+
+/// PushUpgradeVotes RPC.
+#[derive(Debug)]
+pub(crate) struct Rpc;
+
+impl super::Rpc for Rpc {
+    const CAPABILITY_ID: mux::CapabilityId = 18;
+    const INFLIGHT: u32 = 1;
+    const METHOD: &'static str = "push_upgrade_votes";
+
+    type Req = Req;
+    type Resp = ();
+}
+
+/// Hardcoded rate supported by the server.
+/// Upgrades are announced very rarely.
+pub(crate) const RATE: limiter::Rate = limiter::Rate {
+    burst: 2,
+    refresh: time::Duration::seconds(1),
+};
+
+/// Votes that the sender has learned about since the previous call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Req(pub(crate) Vec<Arc<validator::Signed<validator::ProtocolUpgrade>>>);
+
+impl ProtoFmt for Req {
+    type Proto = proto::PushUpgradeVotes;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        let mut votes = vec![];
+        for (i, e) in r.votes.iter().enumerate() {
+            votes.push(Arc::new(
+                ProtoFmt::read(e).with_context(|| format!("votes[{i}]"))?,
+            ));
+        }
+        Ok(Self(votes))
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            votes: self.0.iter().map(|a| ProtoFmt::build(a.as_ref())).collect(),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! RPC for relaying consensus messages over the gossip network.
+//! Used when the direct consensus connection to the recipient is broken,
+//! so that the committee stays live without a full mesh of connections.
+use crate::{mux, proto::consensus as proto, TraceContext};
+use anyhow::Context as _;
+use zksync_concurrency::{limiter, time};
+use zksync_consensus_roles::validator;
+use zksync_protobuf::{read_optional, read_required, required, ProtoFmt};
+
+/// RelayConsensus RPC.
+pub(crate) struct Rpc;
+
+impl super::Rpc for Rpc {
+    const CAPABILITY_ID: mux::CapabilityId = 9;
+    const INFLIGHT: u32 = 3;
+    const METHOD: &'static str = "relay_consensus";
+    type Req = Req;
+    type Resp = ();
+
+    fn submethod(req: &Self::Req) -> &'static str {
+        req.msg.msg.label()
+    }
+}
+
+/// Max rate of the relayed messages accepted from a single peer.
+/// Calls above it are refused with the "rate limited" response.
+pub(crate) const RATE: limiter::Rate = limiter::Rate {
+    burst: 100,
+    refresh: time::Duration::milliseconds(10),
+};
+
+/// Consensus message to be delivered to a validator via the gossip network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Req {
+    /// Consensus message.
+    pub(crate) msg: validator::Signed<validator::ConsensusMsg>,
+    /// Validator which the message is addressed to.
+    /// `None` if the message is broadcasted to all validators.
+    pub(crate) recipient: Option<validator::PublicKey>,
+    /// Number of times the message may still be forwarded.
+    pub(crate) hops: u32,
+    /// Trace context of the span which sent the message.
+    pub(crate) trace: Option<TraceContext>,
+}
+
+impl ProtoFmt for Req {
+    type Proto = proto::RelayConsensusReq;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            msg: read_required(&r.msg).context("msg")?,
+            recipient: read_optional(&r.recipient).context("recipient")?,
+            hops: *required(&r.hops).context("hops")?,
+            trace: read_optional(&r.trace).context("trace")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            msg: Some(self.msg.build()),
+            recipient: self.recipient.as_ref().map(ProtoFmt::build),
+            hops: Some(self.hops),
+            trace: self.trace.as_ref().map(ProtoFmt::build),
+        }
+    }
+}
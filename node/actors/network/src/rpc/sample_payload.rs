@@ -0,0 +1,120 @@
+//! RPC for sampling the availability of a block payload: the server sends the requested
+//! chunks of the payload together with their Merkle proofs against the payload root
+//! in the block header (see `validator::PayloadRoot`). It allows a node to check that
+//! the payload is available before downloading it in full.
+use crate::{io::PayloadSample, mux, proto::gossip as proto};
+use anyhow::Context as _;
+use zksync_concurrency::{limiter, time};
+use zksync_consensus_roles::validator::BlockNumber;
+use zksync_protobuf::{read_optional, read_required, required, ProtoFmt};
+
+/// `sample_payload` RPC.
+#[derive(Debug)]
+pub(crate) struct Rpc;
+
+impl super::Rpc for Rpc {
+    const CAPABILITY_ID: mux::CapabilityId = 17;
+    const INFLIGHT: u32 = 5;
+    const METHOD: &'static str = "sample_payload";
+    const CAPPED: bool = false;
+
+    type Req = Req;
+    type Resp = Resp;
+}
+
+/// Max number of the samples in a single request.
+pub(crate) const MAX_SAMPLES: usize = crate::io::MAX_PAYLOAD_SAMPLES;
+
+/// Rate limit of the `sample_payload` RPC.
+pub(crate) const RATE: limiter::Rate = limiter::Rate {
+    burst: 20,
+    refresh: time::Duration::milliseconds(50),
+};
+
+/// Asks the server to send the chunks of a block payload.
+/// The index of the chunk sent for a sample is the sample modulo the number of chunks,
+/// so that the client can pick the chunks at random without knowing the payload size.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Req {
+    /// Number of the block.
+    pub(crate) number: BlockNumber,
+    /// Samples, at most `MAX_SAMPLES`.
+    pub(crate) samples: Vec<u64>,
+}
+
+impl ProtoFmt for Req {
+    type Proto = proto::SamplePayloadRequest;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            number: BlockNumber(*required(&r.number).context("number")?),
+            samples: r.samples.clone(),
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            number: Some(self.number.0),
+            samples: self.samples.clone(),
+        }
+    }
+}
+
+impl ProtoFmt for PayloadSample {
+    type Proto = proto::PayloadSample;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            chunk: required(&r.chunk).context("chunk")?.clone(),
+            proof: read_required(&r.proof).context("proof")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            chunk: Some(self.chunk.clone()),
+            proof: Some(self.proof.build()),
+        }
+    }
+}
+
+/// Samples of the payload, in the order of the requested samples.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Samples(pub(crate) Vec<PayloadSample>);
+
+impl ProtoFmt for Samples {
+    type Proto = proto::PayloadSamples;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        let mut samples = vec![];
+        for (i, sample) in r.samples.iter().enumerate() {
+            samples.push(ProtoFmt::read(sample).with_context(|| format!("samples[{i}]"))?);
+        }
+        Ok(Self(samples))
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            samples: self.0.iter().map(ProtoFmt::build).collect(),
+        }
+    }
+}
+
+/// Response to a [`Req`], `None` if the block is not available
+/// or its header doesn't commit to the payload root.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Resp(pub(crate) Option<Samples>);
+
+impl ProtoFmt for Resp {
+    type Proto = proto::SamplePayloadResponse;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self(read_optional(&r.samples).context("samples")?))
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            samples: self.0.as_ref().map(ProtoFmt::build),
+        }
+    }
+}
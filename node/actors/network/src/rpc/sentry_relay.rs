@@ -0,0 +1,53 @@
+//! RPC over which a backend validator node hands its consensus messages
+//! to its sentry, which delivers them to the other validators.
+use crate::{mux, proto::consensus as proto, TraceContext};
+use anyhow::Context as _;
+use zksync_consensus_roles::validator;
+use zksync_protobuf::{read_optional, read_required, ProtoFmt};
+
+/// SentryRelay RPC.
+pub(crate) struct Rpc;
+
+impl super::Rpc for Rpc {
+    const CAPABILITY_ID: mux::CapabilityId = 15;
+    const INFLIGHT: u32 = 3;
+    const METHOD: &'static str = "sentry_relay";
+    type Req = Req;
+    type Resp = ();
+
+    fn submethod(req: &Self::Req) -> &'static str {
+        req.msg.msg.label()
+    }
+}
+
+/// Consensus message to be delivered by the sentry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Req {
+    /// Consensus message.
+    pub(crate) msg: validator::Signed<validator::ConsensusMsg>,
+    /// Validator which the message is addressed to.
+    /// `None` if the message is broadcasted to all validators.
+    pub(crate) recipient: Option<validator::PublicKey>,
+    /// Trace context of the span which sent the message.
+    pub(crate) trace: Option<TraceContext>,
+}
+
+impl ProtoFmt for Req {
+    type Proto = proto::SentryRelayReq;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            msg: read_required(&r.msg).context("msg")?,
+            recipient: read_optional(&r.recipient).context("recipient")?,
+            trace: read_optional(&r.trace).context("trace")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            msg: Some(self.msg.build()),
+            recipient: self.recipient.as_ref().map(ProtoFmt::build),
+            trace: self.trace.as_ref().map(ProtoFmt::build),
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! RPC over which a sentry asks its backend to sign the network-level messages
+//! (session IDs, address announcements, heartbeats) with the validator key.
+use crate::{mux, proto::consensus as proto};
+use anyhow::Context as _;
+use zksync_concurrency::{limiter, time};
+use zksync_consensus_roles::validator;
+use zksync_protobuf::{read_required, ProtoFmt};
+
+/// SentrySign RPC.
+pub(crate) struct Rpc;
+
+impl super::Rpc for Rpc {
+    const CAPABILITY_ID: mux::CapabilityId = 14;
+    const INFLIGHT: u32 = 10;
+    const METHOD: &'static str = "sentry_sign";
+    type Req = Req;
+    type Resp = Resp;
+}
+
+/// Expected rate of the signing requests: one per handshake of the sentry,
+/// plus the periodic address announcements and heartbeats.
+pub(crate) const RATE: limiter::Rate = limiter::Rate {
+    burst: 20,
+    refresh: time::Duration::milliseconds(100),
+};
+
+/// Message to be signed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Req(pub(crate) validator::Msg);
+
+/// Signature of the message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Resp(pub(crate) validator::Signature);
+
+impl ProtoFmt for Req {
+    type Proto = proto::SentrySignReq;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self(read_required(&r.msg).context("msg")?))
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            msg: Some(self.0.build()),
+        }
+    }
+}
+
+impl ProtoFmt for Resp {
+    type Proto = proto::SentrySignResp;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self(read_required(&r.sig).context("sig")?))
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            sig: Some(self.0.build()),
+        }
+    }
+}
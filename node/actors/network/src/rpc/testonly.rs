@@ -2,18 +2,61 @@
 //! Implementations of Distribution are supposed to generate realistic data,
 //! but in fact they are "best-effort realistic" - they might need an upgrade,
 //! if tests require stricter properties of the generated data.
-use crate::rpc;
+use crate::{io, rpc, tickets, TraceContext};
 use rand::{
     distributions::{Distribution, Standard},
     Rng,
 };
 use std::sync::Arc;
-use zksync_consensus_roles::validator;
-use zksync_consensus_storage::BlockStoreState;
+use zksync_concurrency::time;
+use zksync_consensus_roles::{node, validator};
+use zksync_consensus_storage::{BlockRange, BlockStoreState};
 
 impl Distribution<rpc::consensus::Req> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::consensus::Req {
-        rpc::consensus::Req(rng.gen())
+        rpc::consensus::Req {
+            msg: rng.gen(),
+            trace: rng.gen(),
+        }
+    }
+}
+
+impl Distribution<rpc::relay_consensus::Req> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::relay_consensus::Req {
+        rpc::relay_consensus::Req {
+            msg: rng.gen(),
+            recipient: rng.gen(),
+            hops: rng.gen(),
+            trace: rng.gen(),
+        }
+    }
+}
+
+impl Distribution<rpc::sentry_relay::Req> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::sentry_relay::Req {
+        rpc::sentry_relay::Req {
+            msg: rng.gen(),
+            recipient: rng.gen(),
+            trace: rng.gen(),
+        }
+    }
+}
+
+impl Distribution<rpc::sentry_sign::Req> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::sentry_sign::Req {
+        rpc::sentry_sign::Req(rng.gen())
+    }
+}
+
+impl Distribution<rpc::sentry_sign::Resp> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::sentry_sign::Resp {
+        rpc::sentry_sign::Resp(rng.gen())
+    }
+}
+
+impl Distribution<TraceContext> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> TraceContext {
+        TraceContext::new_root(rng)
     }
 }
 
@@ -40,10 +83,21 @@ impl Distribution<rpc::push_validator_addrs::Req> for Standard {
 
 impl Distribution<rpc::push_block_store_state::Req> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::push_block_store_state::Req {
-        rpc::push_block_store_state::Req(BlockStoreState {
-            first: rng.gen(),
-            last: rng.gen(),
-        })
+        let first: validator::BlockNumber = rng.gen();
+        let last: Option<validator::CommitQC> = rng.gen();
+        // Sample a gap, if there is space for it in `(first, last)`.
+        let mut gaps = vec![];
+        if let Some(last) = last.as_ref().map(|qc| qc.header().number) {
+            if first.0 + 1 < last.0 {
+                let a = rng.gen_range(first.0 + 1..last.0);
+                let b = rng.gen_range(a..last.0);
+                gaps.push(BlockRange {
+                    first: validator::BlockNumber(a),
+                    last: validator::BlockNumber(b),
+                });
+            }
+        }
+        rpc::push_block_store_state::Req(BlockStoreState { first, last, gaps })
     }
 }
 
@@ -55,6 +109,179 @@ impl Distribution<rpc::get_block::Req> for Standard {
 
 impl Distribution<rpc::get_block::Resp> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::get_block::Resp {
-        rpc::get_block::Resp(Some(rng.gen()))
+        rpc::get_block::Resp {
+            block: Some(rng.gen()),
+            relay_sig: Some(rng.gen()),
+        }
+    }
+}
+
+impl Distribution<rpc::get_block_chunk::Req> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::get_block_chunk::Req {
+        rpc::get_block_chunk::Req {
+            number: rng.gen(),
+            offset: rng.gen(),
+            justification_only: rng.gen(),
+        }
+    }
+}
+
+impl Distribution<rpc::get_block_chunk::Resp> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::get_block_chunk::Resp {
+        let n = rng.gen_range(0..100);
+        rpc::get_block_chunk::Resp(Some(rpc::get_block_chunk::Chunk {
+            justification: Some(rng.gen()),
+            relay_sig: Some(rng.gen()),
+            payload_size: rng.gen(),
+            data: (0..n).map(|_| rng.gen()).collect(),
+            key_rotation: Some(rng.gen()),
+            protocol_upgrade: Some(rng.gen()),
+        }))
+    }
+}
+
+impl Distribution<rpc::get_headers::Req> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::get_headers::Req {
+        rpc::get_headers::Req {
+            first: rng.gen(),
+            count: rng.gen(),
+        }
+    }
+}
+
+impl Distribution<rpc::get_headers::FinalHeader> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::get_headers::FinalHeader {
+        rpc::get_headers::FinalHeader {
+            justification: rng.gen(),
+            key_rotation: Some(rng.gen()),
+            protocol_upgrade: Some(rng.gen()),
+        }
+    }
+}
+
+impl Distribution<rpc::get_headers::Resp> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::get_headers::Resp {
+        let n = rng.gen_range(0..5);
+        rpc::get_headers::Resp((0..n).map(|_| rng.gen()).collect())
+    }
+}
+
+impl Distribution<rpc::sample_payload::Req> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::sample_payload::Req {
+        let n = rng.gen_range(0..rpc::sample_payload::MAX_SAMPLES);
+        rpc::sample_payload::Req {
+            number: rng.gen(),
+            samples: (0..n).map(|_| rng.gen()).collect(),
+        }
+    }
+}
+
+impl Distribution<rpc::sample_payload::Resp> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::sample_payload::Resp {
+        let n = rng.gen_range(0..5);
+        rpc::sample_payload::Resp(Some(rpc::sample_payload::Samples(
+            (0..n)
+                .map(|_| io::PayloadSample {
+                    chunk: (0..rng.gen_range(0..100)).map(|_| rng.gen()).collect(),
+                    proof: rng.gen(),
+                })
+                .collect(),
+        )))
+    }
+}
+
+impl Distribution<rpc::pex::Req> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::pex::Req {
+        let n = rng.gen_range(5..10);
+        rpc::pex::Req(
+            (0..n)
+                .map(|_| {
+                    let key: node::SecretKey = rng.gen();
+                    let addr: node::NodeAddr = rng.gen();
+                    Arc::new(key.sign_msg(addr))
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Distribution<rpc::push_high_qc::Req> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::push_high_qc::Req {
+        rpc::push_high_qc::Req(Arc::new(rng.gen()))
+    }
+}
+
+impl Distribution<rpc::push_batch_votes::Req> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::push_batch_votes::Req {
+        let n = rng.gen_range(5..10);
+        rpc::push_batch_votes::Req((0..n).map(|_| Arc::new(rng.gen())).collect())
+    }
+}
+
+impl Distribution<rpc::push_upgrade_votes::Req> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::push_upgrade_votes::Req {
+        let n = rng.gen_range(5..10);
+        rpc::push_upgrade_votes::Req((0..n).map(|_| Arc::new(rng.gen())).collect())
+    }
+}
+
+impl Distribution<rpc::push_topic::Req> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::push_topic::Req {
+        let n = rng.gen_range(5..10);
+        rpc::push_topic::Req {
+            topic: format!("topic{}", rng.gen::<u8>()),
+            payload: (0..n).map(|_| rng.gen()).collect(),
+        }
+    }
+}
+
+impl Distribution<tickets::Ticket> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> tickets::Ticket {
+        tickets::Ticket {
+            expires: time::UNIX_EPOCH + time::Duration::seconds(rng.gen_range(0..1000000000)),
+            secret: rng.gen(),
+        }
+    }
+}
+
+impl Distribution<tickets::Proof> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> tickets::Proof {
+        tickets::Proof {
+            expires: time::UNIX_EPOCH + time::Duration::seconds(rng.gen_range(0..1000000000)),
+            mac: rng.gen(),
+        }
+    }
+}
+
+impl Distribution<rpc::push_session_ticket::Req> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::push_session_ticket::Req {
+        rpc::push_session_ticket::Req(rng.gen())
+    }
+}
+
+impl Distribution<rpc::get_genesis::Resp> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::get_genesis::Resp {
+        let key: node::SecretKey = rng.gen();
+        rpc::get_genesis::Resp {
+            genesis: rng.gen(),
+            session_id: key.sign_msg(rng.gen::<node::SessionId>()),
+        }
+    }
+}
+
+impl Distribution<rpc::heartbeat::Req> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::heartbeat::Req {
+        rpc::heartbeat::Req(rng.gen())
+    }
+}
+
+impl Distribution<rpc::ping::Resp> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> rpc::ping::Resp {
+        rpc::ping::Resp {
+            data: rng.gen(),
+            timestamp: rng
+                .gen::<bool>()
+                .then(|| time::UNIX_EPOCH + time::Duration::seconds(rng.gen_range(0..1000000000))),
+        }
     }
 }
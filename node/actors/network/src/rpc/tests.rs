@@ -1,5 +1,5 @@
 use super::*;
-use crate::noise;
+use crate::{noise, TimeSource};
 use rand::Rng as _;
 use std::{
     collections::HashSet,
@@ -16,7 +16,21 @@ fn test_capability_rpc_correspondence() {
         push_validator_addrs::Rpc::CAPABILITY_ID,
         push_block_store_state::Rpc::CAPABILITY_ID,
         get_block::Rpc::CAPABILITY_ID,
+        get_block_chunk::Rpc::CAPABILITY_ID,
         ping::Rpc::CAPABILITY_ID,
+        heartbeat::Rpc::CAPABILITY_ID,
+        pex::Rpc::CAPABILITY_ID,
+        push_high_qc::Rpc::CAPABILITY_ID,
+        relay_consensus::Rpc::CAPABILITY_ID,
+        push_batch_votes::Rpc::CAPABILITY_ID,
+        push_topic::Rpc::CAPABILITY_ID,
+        push_session_ticket::Rpc::CAPABILITY_ID,
+        get_genesis::Rpc::CAPABILITY_ID,
+        sentry_sign::Rpc::CAPABILITY_ID,
+        sentry_relay::Rpc::CAPABILITY_ID,
+        get_headers::Rpc::CAPABILITY_ID,
+        sample_payload::Rpc::CAPABILITY_ID,
+        push_upgrade_votes::Rpc::CAPABILITY_ID,
     ];
     assert_eq!(ids.len(), HashSet::from(ids).len());
 }
@@ -30,6 +44,25 @@ fn test_schema_encode_decode() {
     test_encode_random::<push_block_store_state::Req>(rng);
     test_encode_random::<get_block::Req>(rng);
     test_encode_random::<get_block::Resp>(rng);
+    test_encode_random::<get_block_chunk::Req>(rng);
+    test_encode_random::<get_block_chunk::Resp>(rng);
+    test_encode_random::<heartbeat::Req>(rng);
+    test_encode_random::<ping::Resp>(rng);
+    test_encode_random::<pex::Req>(rng);
+    test_encode_random::<push_high_qc::Req>(rng);
+    test_encode_random::<relay_consensus::Req>(rng);
+    test_encode_random::<push_batch_votes::Req>(rng);
+    test_encode_random::<push_upgrade_votes::Req>(rng);
+    test_encode_random::<push_topic::Req>(rng);
+    test_encode_random::<push_session_ticket::Req>(rng);
+    test_encode_random::<get_genesis::Resp>(rng);
+    test_encode_random::<sentry_sign::Req>(rng);
+    test_encode_random::<sentry_sign::Resp>(rng);
+    test_encode_random::<sentry_relay::Req>(rng);
+    test_encode_random::<get_headers::Req>(rng);
+    test_encode_random::<get_headers::Resp>(rng);
+    test_encode_random::<sample_payload::Req>(rng);
+    test_encode_random::<sample_payload::Resp>(rng);
 }
 
 fn expected(res: Result<(), mux::RunError>) -> Result<(), mux::RunError> {
@@ -50,7 +83,7 @@ async fn test_ping() {
         s.spawn_bg(async {
             expected(
                 Service::new()
-                    .add_server(ping::Server, ping::RATE)
+                    .add_server(ping::Server(&TimeSource::default()), ping::RATE)
                     .run(ctx, s1)
                     .await,
             )
@@ -62,13 +95,13 @@ async fn test_ping() {
         for _ in 0..ping::RATE.burst {
             let req = ping::Req(ctx.rng().gen());
             let resp = client.call(ctx, &req, kB).await?;
-            assert_eq!(req.0, resp.0);
+            assert_eq!(req.0, resp.data);
         }
         let now = ctx.now();
         clock.set_advance_on_sleep();
         let req = ping::Req(ctx.rng().gen());
         let resp = client.call(ctx, &req, kB).await?;
-        assert_eq!(req.0, resp.0);
+        assert_eq!(req.0, resp.data);
         assert!(ctx.now() >= now + ping::RATE.refresh);
         Ok(())
     })
@@ -76,6 +109,161 @@ async fn test_ping() {
     .unwrap();
 }
 
+#[tokio::test]
+async fn test_traffic() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let (s1, s2) = noise::testonly::pipe(ctx).await;
+    let client = Client::<ping::Rpc>::new(ctx, ping::RATE);
+    let time = TimeSource::default();
+    let server = Service::new().add_server(ping::Server(&time), ping::RATE);
+    let server_traffic = server.traffic().clone();
+    let client_service = Service::new().add_client(&client);
+    let client_traffic = client_service.traffic().clone();
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(async { expected(server.run(ctx, s1).await).context("server") });
+        s.spawn_bg(async { expected(client_service.run(ctx, s2).await).context("client") });
+        for _ in 0..3 {
+            let req = ping::Req(ctx.rng().gen());
+            client.call(ctx, &req, kB).await?;
+        }
+        let got = client_traffic.by_class();
+        let want = server_traffic.by_class();
+        let got = got[ping::Rpc::METHOD];
+        let want = want[ping::Rpc::METHOD];
+        assert!(got.sent > 0);
+        assert!(got.received > 0);
+        assert_eq!(got.sent, want.received);
+        assert_eq!(got.received, want.sent);
+        assert_eq!(got, client_traffic.total());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+/// Block sync traffic doesn't count towards the bandwidth cap.
+#[test]
+fn test_capped_traffic() {
+    let t = traffic::Traffic::default();
+    t.register(
+        ping::Rpc::CAPABILITY_ID,
+        ping::Rpc::METHOD,
+        ping::Rpc::CAPPED,
+    );
+    t.register(
+        get_block::Rpc::CAPABILITY_ID,
+        get_block::Rpc::METHOD,
+        get_block::Rpc::CAPPED,
+    );
+    t.received(ping::Rpc::CAPABILITY_ID, 10);
+    t.received(get_block::Rpc::CAPABILITY_ID, 1000);
+    assert_eq!(10, t.capped_received());
+    assert_eq!(1010, t.total().received);
+}
+
+#[tokio::test]
+async fn test_rate_limited_server() {
+    abort_on_panic();
+    let clock = ctx::ManualClock::new();
+    let ctx = &ctx::test_root(&clock);
+    let (s1, s2) = noise::testonly::pipe(ctx).await;
+    let limit = limiter::Rate {
+        burst: 2,
+        refresh: time::Duration::seconds(1),
+    };
+    // Client doesn't limit itself.
+    let client = Client::<ping::Rpc>::new(ctx, RATE);
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(async {
+            expected(
+                Service::new()
+                    .add_limited_server(ping::Server(&TimeSource::default()), limit)
+                    .run(ctx, s1)
+                    .await,
+            )
+            .context("server")
+        });
+        s.spawn_bg(async {
+            expected(Service::new().add_client(&client).run(ctx, s2).await).context("client")
+        });
+        for _ in 0..limit.burst {
+            let req = ping::Req(ctx.rng().gen());
+            let resp = client.call(ctx, &req, kB).await?;
+            assert_eq!(req.0, resp.data);
+        }
+        // Calls above the limit are refused, but the connection stays alive.
+        for _ in 0..3 {
+            let req = ping::Req(ctx.rng().gen());
+            let err = client.call(ctx, &req, kB).await.unwrap_err();
+            assert!(is_rate_limited(&err), "{err:#}");
+        }
+        clock.advance(limit.refresh);
+        let req = ping::Req(ctx.rng().gen());
+        let resp = client.call(ctx, &req, kB).await?;
+        assert_eq!(req.0, resp.data);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+/// Server which doesn't accept any request.
+struct RejectingServer;
+
+#[async_trait::async_trait]
+impl Handler<ping::Rpc> for RejectingServer {
+    fn max_req_size(&self) -> usize {
+        0
+    }
+    async fn handle(&self, _ctx: &ctx::Ctx, _req: ping::Req) -> anyhow::Result<ping::Resp> {
+        unreachable!("request should have been rejected")
+    }
+}
+
+/// Calls above the limit should be refused before the request is received.
+#[tokio::test]
+async fn test_rate_limited_before_recv() {
+    abort_on_panic();
+    let clock = ctx::ManualClock::new();
+    let ctx = &ctx::test_root(&clock);
+    let (s1, s2) = noise::testonly::pipe(ctx).await;
+    let limit = limiter::Rate {
+        burst: 1,
+        refresh: time::Duration::seconds(1),
+    };
+    let client = Client::<ping::Rpc>::new(ctx, RATE);
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(async {
+            expected(
+                Service::new()
+                    .add_limited_server(RejectingServer, limit)
+                    .run(ctx, s1)
+                    .await,
+            )
+            .context("server")
+        });
+        s.spawn_bg(async {
+            expected(Service::new().add_client(&client).run(ctx, s2).await).context("client")
+        });
+        // The call within the limit is received and rejected as too large.
+        let err = client
+            .call(ctx, &ping::Req(ctx.rng().gen()), kB)
+            .await
+            .unwrap_err();
+        assert!(!is_rate_limited(&err), "{err:#}");
+        // The call above the limit is refused without checking the request.
+        let err = client
+            .call(ctx, &ping::Req(ctx.rng().gen()), kB)
+            .await
+            .unwrap_err();
+        assert!(is_rate_limited(&err), "{err:#}");
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
 struct PingServer {
     clock: ctx::ManualClock,
     pings: AtomicU64,
@@ -95,7 +283,10 @@ impl Handler<ping::Rpc> for PingServer {
             ctx.canceled().await;
             Err(ctx::Canceled.into())
         } else {
-            Ok(ping::Resp(req.0))
+            Ok(ping::Resp {
+                data: req.0,
+                timestamp: None,
+            })
         }
     }
 }
@@ -136,7 +327,10 @@ async fn test_ping_loop() {
             expected(Service::new().add_client(&client).run(ctx, s2).await).context("client")
         });
         let now = ctx.now();
-        assert!(client.ping_loop(ctx, PING_TIMEOUT).await.is_err());
+        assert!(client
+            .ping_loop(ctx, &TimeSource::default(), PING_TIMEOUT, |_| {})
+            .await
+            .is_err());
         let got = ctx.now() - now;
         // PING_COUNT will succeed and the next with time out.
         let want = (PING_COUNT + 1) as u32 * PING_TIMEOUT;
@@ -1,16 +1,25 @@
 //! Testonly utilities.
 #![allow(dead_code)]
-use crate::{Config, GossipConfig, Network, RpcConfig, Runner};
+use crate::{
+    Config, GossipConfig, Monitor, Network, OutboundQueueConfig, ReconnectConfig, RelayAuth,
+    RpcConfig, Runner, TimeSource, Topics, Transport,
+};
 use rand::Rng;
 use std::{
     collections::{HashMap, HashSet},
     sync::Arc,
 };
-use zksync_concurrency::{ctx, ctx::channel, io, net, scope, sync};
+use zksync_concurrency::{ctx, ctx::channel, io, scope, sync, time};
 use zksync_consensus_roles::{node, validator};
 use zksync_consensus_storage::BlockStore;
 use zksync_consensus_utils::pipe;
 
+/// Protocol version announced by the test nodes,
+/// so that they exchange the blocks of every version.
+pub const PROTOCOL_VERSION: validator::ProtocolVersion = validator::ProtocolVersion(u32::MAX);
+
+pub use crate::transport::testonly::reserve_virtual_listener;
+
 /// Synchronously forwards data from one stream to another.
 pub(crate) async fn forward(
     ctx: &ctx::Ctx,
@@ -54,22 +63,45 @@ pub fn new_configs(
     gossip_peers: usize,
 ) -> Vec<Config> {
     let configs = setup.keys.iter().map(|key| {
-        let addr = net::tcp::testonly::reserve_listener();
+        let addr = reserve_virtual_listener();
         Config {
             server_addr: addr,
+            transport: Transport::InProcess,
             public_addr: *addr,
+            public_addr_detection: None,
             // Pings are disabled in tests by default to avoid dropping connections
             // due to timeouts.
             ping_timeout: None,
-            validator_key: Some(key.clone()),
+            keepalive: None,
+            genesis_mismatch_quarantine: time::Duration::minutes(10),
+            consensus_replay_window: 16,
+            consensus_relay_max_hops: 0,
+            consensus_outbound_queue: OutboundQueueConfig::default(),
+            serve_blocks: true,
+            serve_blocks_bandwidth: None,
+            serve_blocks_bandwidth_per_peer: None,
+            per_peer_metrics: false,
+            validator_key: Some(Arc::new(key.clone())),
+            sentry: None,
             gossip: GossipConfig {
                 key: rng.gen(),
                 dynamic_inbound_limit: usize::MAX,
+                dynamic_outbound_limit: 0,
                 static_inbound: HashSet::default(),
                 static_outbound: HashMap::default(),
+                relay_auth: RelayAuth::default(),
+                peer_bandwidth_cap: None,
+                protocol_version: PROTOCOL_VERSION,
             },
             max_block_size: usize::MAX,
             rpc: RpcConfig::default(),
+            topics: Topics::default(),
+            reconnect: ReconnectConfig::default(),
+            session_ticket_ttl: Some(time::Duration::hours(1)),
+            time_source: TimeSource::default(),
+            monitor: Monitor::default(),
+            #[cfg(feature = "chaos")]
+            chaos: None,
         }
     });
     let mut cfgs: Vec<_> = configs.collect();
@@ -89,22 +121,45 @@ pub fn new_configs(
 /// Constructs a config for a non-validator node, which will
 /// establish a gossip connection to `peer`.
 pub fn new_fullnode(rng: &mut impl Rng, peer: &Config) -> Config {
-    let addr = net::tcp::testonly::reserve_listener();
+    let addr = reserve_virtual_listener();
     Config {
         server_addr: addr,
+        transport: Transport::InProcess,
         public_addr: *addr,
+        public_addr_detection: None,
         // Pings are disabled in tests by default to avoid dropping connections
         // due to timeouts.
         ping_timeout: None,
+        keepalive: None,
+        genesis_mismatch_quarantine: time::Duration::minutes(10),
+        consensus_replay_window: 16,
+        consensus_relay_max_hops: 0,
+        consensus_outbound_queue: OutboundQueueConfig::default(),
+        serve_blocks: true,
+        serve_blocks_bandwidth: None,
+        serve_blocks_bandwidth_per_peer: None,
+        per_peer_metrics: false,
         validator_key: None,
+        sentry: None,
         gossip: GossipConfig {
             key: rng.gen(),
             dynamic_inbound_limit: usize::MAX,
+            dynamic_outbound_limit: 0,
             static_inbound: HashSet::default(),
             static_outbound: [(peer.gossip.key.public(), peer.public_addr)].into(),
+            relay_auth: RelayAuth::default(),
+            peer_bandwidth_cap: None,
+            protocol_version: PROTOCOL_VERSION,
         },
         max_block_size: usize::MAX,
         rpc: RpcConfig::default(),
+        topics: Topics::default(),
+        reconnect: ReconnectConfig::default(),
+        session_ticket_ttl: Some(time::Duration::hours(1)),
+        time_source: TimeSource::default(),
+        monitor: Monitor::default(),
+        #[cfg(feature = "chaos")]
+        chaos: None,
     }
 }
 
@@ -136,7 +191,7 @@ impl Instance {
         block_store: Arc<BlockStore>,
     ) -> (Self, InstanceRunner) {
         let (actor_pipe, dispatcher_pipe) = pipe::new();
-        let (net, runner) = Network::new(ctx, cfg, block_store, actor_pipe);
+        let (net, runner) = Network::new(ctx, cfg, block_store.reader(), actor_pipe);
         let (terminate_send, terminate_recv) = channel::bounded(1);
         (
             Self {
@@ -171,7 +226,7 @@ impl Instance {
     }
 
     /// Genesis.
-    pub fn genesis(&self) -> &validator::Genesis {
+    pub fn genesis(&self) -> Arc<validator::Genesis> {
         self.net.gossip.genesis()
     }
 
@@ -180,6 +235,46 @@ impl Instance {
         &self.net.gossip.cfg
     }
 
+    /// Partitions the network between the given groups of instances:
+    /// frames sent between instances of different groups are held back until the partition
+    /// is healed. Connections are kept open, so the RPCs in flight just stall,
+    /// as they would over a broken route.
+    #[cfg(any(test, feature = "chaos"))]
+    pub fn partition(groups: &[&[&Instance]]) {
+        for (i, group) in groups.iter().enumerate() {
+            let others: crate::partition::Partition = groups
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .flat_map(|(_, g)| g.iter().flat_map(|n| n.partition_peers()))
+                .collect();
+            for n in *group {
+                n.net.gossip.partition.set(others.clone());
+            }
+        }
+    }
+
+    /// Heals the partition of this instance, so that the frames held back
+    /// are sent to the peers. Has to be called for every partitioned instance.
+    #[cfg(any(test, feature = "chaos"))]
+    pub fn heal(&self) {
+        self.net
+            .gossip
+            .partition
+            .set(crate::partition::Partition::default());
+    }
+
+    /// Identities of this instance, as seen by its peers.
+    #[cfg(any(test, feature = "chaos"))]
+    fn partition_peers(&self) -> Vec<crate::partition::Peer> {
+        use crate::partition::Peer;
+        let mut peers = vec![Peer::Node(self.cfg().gossip.key.public())];
+        if let Some(key) = &self.cfg().validator_key {
+            peers.push(Peer::Validator(key.public()));
+        }
+        peers
+    }
+
     /// Wait for static outbound gossip connections to be established.
     pub async fn wait_for_gossip_connections(&self) {
         let want: HashSet<_> = self.cfg().gossip.static_outbound.keys().cloned().collect();
@@ -274,7 +369,7 @@ pub async fn instant_network(
         node.net
             .gossip
             .validator_addrs
-            .update(&node.genesis().validators, &addrs)
+            .update(&node.genesis(), &addrs)
             .await
             .unwrap();
     }
@@ -1,7 +1,12 @@
-use crate::testonly;
+use crate::{
+    dump, metrics, rpc, testonly, transport, ConnState, OutboundPeer, PushSchedule,
+    ReconnectConfig, Transport,
+};
+use rand::Rng as _;
 use tracing::Instrument as _;
-use zksync_concurrency::{ctx, scope, testonly::abort_on_panic};
-use zksync_consensus_roles::validator;
+use zksync_concurrency::{ctx, io, net, scope, sync, testonly::abort_on_panic, time};
+use zksync_consensus_crypto::TextFmt as _;
+use zksync_consensus_roles::{node, validator};
 use zksync_consensus_storage::testonly::new_store;
 
 /// Test that metrics are correctly defined
@@ -12,7 +17,10 @@ async fn test_metrics() {
     let ctx = &mut ctx::test_root(&ctx::RealClock);
     let rng = &mut ctx.rng();
     let setup = validator::testonly::Setup::new(rng, 3);
-    let cfgs = testonly::new_configs(rng, &setup, 1);
+    let mut cfgs = testonly::new_configs(rng, &setup, 1);
+    for cfg in &mut cfgs {
+        cfg.per_peer_metrics = true;
+    }
     scope::run!(ctx, |ctx, s| async {
         let (store, runner) = new_store(ctx, &setup.genesis).await;
         s.spawn_bg(runner.run(ctx));
@@ -32,8 +40,376 @@ async fn test_metrics() {
         let mut encoded_metrics = String::new();
         registry.encode(&mut encoded_metrics, vise::Format::OpenMetricsForPrometheus)?;
         tracing::info!("stats =\n{encoded_metrics}");
+        assert!(encoded_metrics.contains("network_peer_received_bytes"));
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Test that the observable state of a network is not shared with the other networks
+/// running in the same process.
+#[tokio::test]
+async fn test_monitor_per_network() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let setup = validator::testonly::Setup::new(rng, 2);
+    let cfgs = testonly::new_configs(rng, &setup, 1);
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let nodes: Vec<_> = cfgs
+            .into_iter()
+            .enumerate()
+            .map(|(i, cfg)| {
+                let (node, runner) = testonly::Instance::new(ctx, cfg, store.clone());
+                s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node", i)));
+                node
+            })
+            .collect();
+        testonly::instant_network(ctx, nodes.iter()).await?;
+        for (i, node) in nodes.iter().enumerate() {
+            let want = vec![nodes[1 - i].cfg().gossip.key.public()];
+            loop {
+                let got: Vec<_> = node
+                    .cfg()
+                    .monitor
+                    .peer_traffic()
+                    .into_iter()
+                    .map(|t| t.key)
+                    .collect();
+                if got == want {
+                    break;
+                }
+                ctx.sleep(time::Duration::milliseconds(10)).await?;
+            }
+        }
         Ok(())
     })
     .await
     .unwrap()
 }
+
+/// Checks that `transport` delivers data and reports the bound addresses correctly.
+async fn check_transport(ctx: &ctx::Ctx, transport: &Transport, addr: net::tcp::ListenerAddr) {
+    // Nothing is listening yet.
+    let res = transport::connect(ctx, transport, *addr).await.unwrap();
+    assert!(res.is_err());
+
+    let mut listener = transport::bind(transport, &addr).unwrap();
+    let res = transport::bind(transport, &addr);
+    assert_eq!(io::ErrorKind::AddrInUse, res.err().unwrap().kind());
+
+    let mut outbound = transport::connect(ctx, transport, *addr)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut inbound = transport::accept(ctx, &mut listener)
+        .await
+        .unwrap()
+        .unwrap();
+    let want = b"hello";
+    io::write_all(ctx, &mut outbound, want)
+        .await
+        .unwrap()
+        .unwrap();
+    io::flush(ctx, &mut outbound).await.unwrap().unwrap();
+    let mut got = [0; 5];
+    io::read_exact(ctx, &mut inbound, &mut got)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(want, &got);
+
+    // Address can be reused once the listener is dropped.
+    drop(listener);
+    transport::bind(transport, &addr).unwrap();
+}
+
+/// Test that the virtual transport behaves like TCP without binding any OS ports.
+#[tokio::test]
+async fn test_virtual_transport() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let addr = testonly::reserve_virtual_listener();
+    let res = transport::connect(ctx, &Transport::InProcess, *addr)
+        .await
+        .unwrap();
+    assert_eq!(io::ErrorKind::ConnectionRefused, res.err().unwrap().kind());
+    check_transport(ctx, &Transport::InProcess, addr).await;
+}
+
+#[tokio::test]
+async fn test_in_process_transport() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    // Any address is served in-memory.
+    let addr = net::tcp::ListenerAddr::new("127.0.0.1:3054".parse().unwrap());
+    check_transport(ctx, &Transport::InProcess, addr).await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_unix_transport() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let dir = tempfile::tempdir().unwrap();
+    let transport = Transport::Unix(dir.path().to_path_buf());
+    let addr = net::tcp::ListenerAddr::new("127.0.0.1:3054".parse().unwrap());
+    check_transport(ctx, &transport, addr).await;
+}
+
+#[test]
+fn test_reconnect_backoff() {
+    let rng = &mut ctx::test_root(&ctx::RealClock).rng();
+    let cfg = ReconnectConfig::default();
+    for (failures, want) in [(1, 1), (2, 2), (3, 4), (4, 8), (100, 20)] {
+        let want = time::Duration::seconds(want).min(cfg.max_backoff);
+        for _ in 0..10 {
+            let got = cfg.backoff(rng, failures);
+            assert!(
+                want / 2 <= got && got <= want,
+                "failures = {failures}, got {got}"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_key_label_cardinality() {
+    let rng = &mut ctx::test_root(&ctx::RealClock).rng();
+    let first = rng.gen::<node::SecretKey>().public();
+    let label = metrics::key_label(&first);
+    let labels: std::collections::HashSet<_> = (0..1000)
+        .map(|_| metrics::key_label(&rng.gen::<node::SecretKey>().public()))
+        .collect();
+    // Labels are bounded, the keys above the bound are aggregated.
+    assert!(labels.len() < 1000);
+    assert!(labels.contains(metrics::OTHER_KEYS_LABEL));
+    // Keys labelled before keep their labels.
+    assert_eq!(label, metrics::key_label(&first));
+}
+
+#[test]
+fn test_push_schedule_delay() {
+    let rng = &mut ctx::test_root(&ctx::RealClock).rng();
+    let schedule = PushSchedule::default();
+    for (idle, want) in [(0, 500), (1, 1000), (2, 2000), (4, 8000), (100, 10000)] {
+        let want = time::Duration::milliseconds(want);
+        for _ in 0..10 {
+            let got = schedule.delay(rng, idle);
+            assert!(
+                want <= got && got <= want + schedule.jitter,
+                "idle = {idle}, got {got}"
+            );
+        }
+    }
+}
+
+/// Failed connection attempts to a static peer should be recorded in the connection history,
+/// and the connection should be established once the peer comes up.
+#[tokio::test]
+async fn test_reconnect_history() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::AffineClock::new(20.));
+    let rng = &mut ctx.rng();
+    let setup = validator::testonly::Setup::new(rng, 2);
+    let cfgs = testonly::new_configs(rng, &setup, 1);
+    let peer = OutboundPeer::Gossip(cfgs[1].gossip.key.public());
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let (node0, runner) = testonly::Instance::new(ctx, cfgs[0].clone(), store.clone());
+        s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node0")));
+        let mut sub = node0.state().subscribe_connections();
+
+        tracing::info!("waiting for the failed attempts");
+        sync::wait_for(ctx, &mut sub, |h| {
+            h.get(&peer).is_some_and(|h| h.failures >= 2)
+        })
+        .await?;
+        let h = sub.borrow().get(&peer).unwrap().clone();
+        assert!(h
+            .states
+            .iter()
+            .any(|(_, s)| matches!(s, ConnState::Backoff { failures: 1, .. })));
+
+        tracing::info!("starting the peer");
+        let (_node1, runner) = testonly::Instance::new(ctx, cfgs[1].clone(), store.clone());
+        s.spawn_bg(runner.run(ctx).instrument(tracing::info_span!("node1")));
+        let h = sync::wait_for(ctx, &mut sub, |h| {
+            h.get(&peer)
+                .is_some_and(|h| h.current() == Some(&ConnState::Connected))
+        })
+        .await?;
+        assert_eq!(0, h.get(&peer).unwrap().failures);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+/// Fake NTP server with a clock `offset` ahead of the local clock, answering a single request.
+fn fake_ntp_server(offset: time::Duration) -> std::net::SocketAddr {
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    std::thread::spawn(move || {
+        let mut req = [0; 48];
+        let (_, peer) = socket.recv_from(&mut req).unwrap();
+        let now = ctx::RealClock.now_utc() + offset - time::UNIX_EPOCH;
+        let secs = (now.whole_seconds() + 2_208_988_800) as u32;
+        let frac = ((u64::from(now.subsec_nanoseconds() as u32) << 32) / 1_000_000_000) as u32;
+        let mut resp = [0; 48];
+        // No leap indicator, version 4, server mode; stratum 1.
+        resp[0] = 0x24;
+        resp[1] = 1;
+        for ts in [&mut resp[32..40], &mut resp[40..48]] {
+            ts[0..4].copy_from_slice(&secs.to_be_bytes());
+            ts[4..8].copy_from_slice(&frac.to_be_bytes());
+        }
+        socket.send_to(&resp, peer).unwrap();
+    });
+    addr
+}
+
+#[tokio::test]
+async fn test_time_source_ntp() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let offset = time::Duration::minutes(1);
+    let max_correction = time::Duration::minutes(2);
+    let timeout = time::Duration::seconds(1);
+    let time_source = crate::TimeSource::default();
+    let servers = [
+        fake_ntp_server(offset).to_string(),
+        fake_ntp_server(offset).to_string(),
+        // A spoofed response is outvoted by the median.
+        fake_ntp_server(time::Duration::hours(10)).to_string(),
+        // Unreachable servers are skipped.
+        "127.0.0.1:1".to_string(),
+    ];
+    let got = time_source
+        .sync_ntp(ctx, &servers, max_correction, timeout)
+        .await
+        .unwrap();
+    assert!((got - offset).abs() < time::Duration::seconds(1), "{got}");
+    assert_eq!(got, time_source.offset());
+    let skew = time_source.now_utc(ctx) - ctx.now_utc();
+    assert!((skew - offset).abs() < time::Duration::seconds(1), "{skew}");
+
+    // The applied correction is bounded.
+    let time_source = crate::TimeSource::default();
+    let servers: Vec<_> = (0..crate::MIN_NTP_SERVERS)
+        .map(|_| fake_ntp_server(time::Duration::hours(10)).to_string())
+        .collect();
+    let got = time_source
+        .sync_ntp(ctx, &servers, max_correction, timeout)
+        .await
+        .unwrap();
+    assert!(got > max_correction);
+    assert_eq!(max_correction, time_source.offset());
+
+    // Too few servers responded.
+    let time_source = crate::TimeSource::default();
+    let servers = [
+        fake_ntp_server(offset).to_string(),
+        "127.0.0.1:1".to_string(),
+    ];
+    assert!(time_source
+        .sync_ntp(ctx, &servers, max_correction, timeout)
+        .await
+        .is_err());
+    assert_eq!(time::Duration::ZERO, time_source.offset());
+}
+
+#[tokio::test]
+async fn test_dump_redacts_payloads() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let dir = tempfile::tempdir().unwrap();
+    let peer = rng.gen::<node::SecretKey>().public().encode();
+    let monitor = crate::Monitor::default();
+    let lines = scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(async {
+            monitor.wire_dumps().run(ctx).await;
+            Ok(())
+        });
+        let now = ctx.now_utc();
+        let until = now + time::Duration::minutes(1);
+        let path = monitor.start_dump(ctx, &peer, dir.path(), until).await?;
+        assert_eq!(monitor.dumps(now).len(), 1);
+
+        let dumper = dump::Dumper::new(peer.clone(), monitor.wire_dumps().clone());
+        let req = rpc::push_topic::Req {
+            topic: "test".to_string(),
+            payload: vec![7; 1000],
+        };
+        dumper.dump(now, dump::Direction::Sent, "push_topic", &req);
+        // Messages are not dumped once the dump expires.
+        dumper.dump(
+            now + time::Duration::minutes(2),
+            dump::Direction::Sent,
+            "push_topic",
+            &req,
+        );
+        assert!(monitor.dumps(now).is_empty());
+
+        // The number of the active dumps is bounded.
+        for _ in 0..crate::MAX_DUMPS {
+            let peer = rng.gen::<node::SecretKey>().public().encode();
+            monitor.start_dump(ctx, &peer, dir.path(), until).await?;
+        }
+        assert!(monitor
+            .start_dump(ctx, &peer, dir.path(), until)
+            .await
+            .is_err());
+
+        // The messages are written in the background.
+        loop {
+            let data = std::fs::read_to_string(&path).unwrap_or_default();
+            if data.ends_with('\n') {
+                break Ok(data);
+            }
+            ctx.sleep(time::Duration::milliseconds(10)).await?;
+        }
+    })
+    .await
+    .unwrap();
+    let lines: Vec<serde_json::Value> = lines
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["method"], "push_topic");
+    assert_eq!(lines[0]["direction"], "sent");
+    assert_eq!(lines[0]["msg"]["topic"], "test");
+    // The payload is replaced with its 32-byte hash (base64 encoded).
+    assert_eq!(lines[0]["msg"]["payload"].as_str().unwrap().len(), 44);
+}
+
+/// The injected faults are drawn from the rng of the context,
+/// so they are reproducible in tests.
+#[cfg(feature = "chaos")]
+#[tokio::test]
+async fn test_chaos_deterministic() {
+    abort_on_panic();
+    let cfg = crate::chaos::Config {
+        latency: time::Duration::ZERO,
+        jitter: time::Duration::ZERO,
+        drop_rate: 0.5,
+    };
+    let mut drops = vec![];
+    for _ in 0..2 {
+        let ctx = &ctx::test_root(&ctx::ManualClock::new());
+        let mut dropped = vec![];
+        for _ in 0..100 {
+            dropped.push(cfg.inject(ctx).await.is_err());
+        }
+        drops.push(dropped);
+    }
+    assert_eq!(drops[0], drops[1]);
+    assert!(drops[0].iter().any(|d| *d));
+    assert!(drops[0].iter().any(|d| !*d));
+}
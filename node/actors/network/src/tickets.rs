@@ -0,0 +1,207 @@
+//! Session tickets, which let the nodes resume their sessions without verifying signatures.
+//!
+//! Every node issues a ticket to each of its peers right after the connection is established,
+//! and then refreshes it periodically, so that the peers always hold a valid one.
+//! A ticket is a secret derived from the node key of the issuer, the public key of the holder
+//! and the expiration time, so the issuer doesn't need to store the tickets it has issued and
+//! recognizes them even after a restart. During the handshake the holder proves the possession
+//! of the ticket with a MAC of the session ID, and the issuer skips verifying the signature of
+//! the session ID. The session ID is still signed, so that the peers which don't hold a ticket
+//! of the node (e.g. because they have restarted) can authenticate it as usual.
+//!
+//! This way a validator restarting while its tickets are valid doesn't have to verify the
+//! signatures of all its peers at once: each peer verifies the signature of the restarted node
+//! instead, which spreads the cost over the whole network.
+use crate::proto::tickets as proto;
+use anyhow::Context as _;
+use std::{collections::HashMap, hash::Hash, sync::Mutex};
+use vise::{Counter, Metrics};
+use zksync_concurrency::{ctx, time};
+use zksync_consensus_crypto::{
+    keccak256::{Hasher, Keccak256},
+    ByteFmt,
+};
+use zksync_consensus_roles::node;
+use zksync_protobuf::{read_required, required, ProtoFmt};
+
+/// Ticket issued by a node to its peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Ticket {
+    /// Time after which the issuer doesn't accept the ticket.
+    pub(crate) expires: time::Utc,
+    /// Secret known only to the issuer and the holder.
+    pub(crate) secret: Keccak256,
+}
+
+/// Proof of the possession of a ticket, bound to a session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Proof {
+    /// Expiration time of the ticket.
+    pub(crate) expires: time::Utc,
+    /// MAC of the session ID, keyed with the secret of the ticket.
+    pub(crate) mac: Keccak256,
+}
+
+/// MAC of the session ID.
+/// Keccak256 is not susceptible to length extension, so prefixing the key is enough.
+fn mac(secret: &Keccak256, session_id: &node::SessionId) -> Keccak256 {
+    let mut h = Hasher::default();
+    h.update(secret.as_bytes());
+    h.update(&session_id.0);
+    h.finalize()
+}
+
+impl Ticket {
+    /// Proves the possession of the ticket in the session with the given ID.
+    pub(crate) fn prove(&self, session_id: &node::SessionId) -> Proof {
+        Proof {
+            expires: self.expires,
+            mac: mac(&self.secret, session_id),
+        }
+    }
+}
+
+impl ProtoFmt for Ticket {
+    type Proto = proto::SessionTicket;
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            expires: read_required(&r.expires).context("expires")?,
+            secret: ByteFmt::decode(required(&r.secret).context("secret")?)?,
+        })
+    }
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            expires: Some(self.expires.build()),
+            secret: Some(self.secret.encode()),
+        }
+    }
+}
+
+impl ProtoFmt for Proof {
+    type Proto = proto::TicketProof;
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            expires: read_required(&r.expires).context("expires")?,
+            mac: ByteFmt::decode(required(&r.mac).context("mac")?)?,
+        })
+    }
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            expires: Some(self.expires.build()),
+            mac: Some(self.mac.encode()),
+        }
+    }
+}
+
+/// Session tickets of a node on one of its networks:
+/// the tickets it issues to its peers and the tickets the peers have issued to it.
+/// `K` is the type of the keys identifying the peers on the network.
+pub(crate) struct Tickets<K> {
+    /// Key from which the secrets of the issued tickets are derived.
+    key: Keccak256,
+    /// Validity of the issued tickets. `None` if the node doesn't issue tickets.
+    ttl: Option<time::Duration>,
+    /// Tickets issued to this node by its peers.
+    held: Mutex<HashMap<K, Ticket>>,
+}
+
+impl<K: ByteFmt + Clone + Eq + Hash> Tickets<K> {
+    /// Constructs the tickets of a node.
+    /// The key of the tickets is derived from the node key, so that it survives restarts.
+    /// `domain` separates the tickets of the different networks of the node.
+    pub(crate) fn new(
+        domain: &str,
+        node_key: &node::SecretKey,
+        ttl: Option<time::Duration>,
+    ) -> Self {
+        let mut h = Hasher::default();
+        h.update(b"zksync_network_session_ticket:");
+        h.update(domain.as_bytes());
+        h.update(&[0]);
+        h.update(&node_key.encode());
+        Self {
+            key: h.finalize(),
+            ttl,
+            held: Mutex::default(),
+        }
+    }
+
+    /// Validity of the issued tickets.
+    pub(crate) fn ttl(&self) -> Option<time::Duration> {
+        self.ttl
+    }
+
+    /// Secret of the ticket issued to `holder`, expiring at `expires`.
+    fn secret(&self, holder: &K, expires: time::Utc) -> Keccak256 {
+        let expires = (expires - time::UNIX_EPOCH).whole_nanoseconds();
+        let mut h = Hasher::default();
+        h.update(self.key.as_bytes());
+        h.update(&expires.to_le_bytes());
+        h.update(&holder.encode());
+        h.finalize()
+    }
+
+    /// Issues a ticket to `holder`. Returns `None` if issuing tickets is disabled.
+    pub(crate) fn issue(&self, ctx: &ctx::Ctx, holder: &K) -> Option<Ticket> {
+        let expires = ctx.now_utc() + self.ttl?;
+        METRICS.issued.inc();
+        Some(Ticket {
+            expires,
+            secret: self.secret(holder, expires),
+        })
+    }
+
+    /// Checks that `proof` proves the possession of a valid ticket,
+    /// issued by this node to `holder`, in the session with the given ID.
+    /// If it does, the signature of the session ID doesn't need to be verified.
+    pub(crate) fn verify(
+        &self,
+        ctx: &ctx::Ctx,
+        holder: &K,
+        session_id: &node::SessionId,
+        proof: Option<&Proof>,
+    ) -> bool {
+        let (Some(ttl), Some(proof)) = (self.ttl, proof) else {
+            return false;
+        };
+        let now = ctx.now_utc();
+        // Tickets valid for longer than `ttl` have been issued with a different config.
+        let ok = now < proof.expires
+            && proof.expires <= now + ttl
+            && proof.mac == mac(&self.secret(holder, proof.expires), session_id);
+        match ok {
+            true => METRICS.resumed.inc(),
+            false => METRICS.rejected.inc(),
+        };
+        ok
+    }
+
+    /// Ticket issued to this node by `peer`, if it is still valid.
+    pub(crate) fn held(&self, ctx: &ctx::Ctx, peer: &K) -> Option<Ticket> {
+        let now = ctx.now_utc();
+        let mut held = self.held.lock().unwrap();
+        held.retain(|_, t| t.expires > now);
+        held.get(peer).cloned()
+    }
+
+    /// Stores a ticket issued to this node by `peer`, replacing the previous one.
+    pub(crate) fn insert(&self, peer: &K, ticket: Ticket) {
+        self.held.lock().unwrap().insert(peer.clone(), ticket);
+    }
+}
+
+/// Metrics of the session tickets.
+#[derive(Debug, Metrics)]
+#[metrics(prefix = "network_session_tickets")]
+struct TicketMetrics {
+    /// Tickets issued to the peers.
+    issued: Counter,
+    /// Handshakes in which the peer proved the possession of a valid ticket,
+    /// so that verifying its signature has been skipped.
+    resumed: Counter,
+    /// Handshakes in which the peer presented an invalid or expired ticket.
+    rejected: Counter,
+}
+
+#[vise::register]
+static METRICS: vise::Global<TicketMetrics> = vise::Global::new();
@@ -0,0 +1,146 @@
+//! Source of the time of the node, tolerating a skew of the local clock.
+//! The UTC timestamps (of the pings, heartbeats, node addresses) are taken from the local
+//! clock, corrected by the offset measured against the NTP servers at startup.
+//! A large skew of the local clock otherwise goes unnoticed, while it silently degrades
+//! the liveness: e.g. the heartbeats and addresses of the node look stale to its peers.
+//!
+//! The SNTP responses are not authenticated, so they can be spoofed. Therefore the offset
+//! is the median over several servers, the applied correction is bounded, and the offset
+//! never affects the timeouts (in particular the consensus timers): they are measured with
+//! the monotonic clock of the context.
+use anyhow::Context as _;
+use std::{
+    net::{ToSocketAddrs as _, UdpSocket},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use vise::{Gauge, Metrics, Unit};
+use zksync_concurrency::{ctx, metrics::LatencyGaugeExt as _, scope, time};
+
+/// Default port of the NTP servers.
+const NTP_PORT: u16 = 123;
+/// Seconds between the NTP epoch (1900) and the UNIX epoch (1970).
+const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+/// Size of an (S)NTP packet without extensions.
+const NTP_PACKET_SIZE: usize = 48;
+/// Min number of the NTP servers which have to respond for the offset to be measured.
+pub const MIN_NTP_SERVERS: usize = 3;
+
+/// Source of the time of the node.
+/// Clones share the offset of the clock.
+#[derive(Debug, Clone, Default)]
+pub struct TimeSource(Arc<Mutex<time::Duration>>);
+
+impl TimeSource {
+    /// Current UTC time, i.e. the local clock corrected by `offset()`.
+    pub fn now_utc(&self, ctx: &ctx::Ctx) -> time::Utc {
+        ctx.now_utc() + self.offset()
+    }
+
+    /// Offset of the reference clock relative to the local clock
+    /// (positive if the local clock is behind).
+    pub fn offset(&self) -> time::Duration {
+        *self.0.lock().unwrap()
+    }
+
+    /// Sets the offset of the reference clock relative to the local clock.
+    pub fn set_offset(&self, offset: time::Duration) {
+        METRICS.clock_offset.set_latency(offset.abs());
+        *self.0.lock().unwrap() = offset;
+    }
+
+    /// Measures the offset of the local clock against the NTP `servers` (`host[:port]`)
+    /// and sets it, clamped to `max_correction`. The median of the successful measurements
+    /// is used, so that a minority of misbehaving (or spoofed) servers doesn't skew the result.
+    /// Returns the measured (not clamped) offset, or an error if fewer than
+    /// `MIN_NTP_SERVERS` servers responded.
+    pub async fn sync_ntp(
+        &self,
+        ctx: &ctx::Ctx,
+        servers: &[String],
+        max_correction: time::Duration,
+        timeout: time::Duration,
+    ) -> anyhow::Result<time::Duration> {
+        let mut offsets = vec![];
+        for server in servers {
+            match ntp_offset(ctx, server, timeout).await {
+                Ok(offset) => offsets.push(offset),
+                Err(err) => tracing::warn!("ntp_offset({server}): {err:#}"),
+            }
+        }
+        anyhow::ensure!(
+            offsets.len() >= MIN_NTP_SERVERS,
+            "only {} NTP servers responded, want at least {MIN_NTP_SERVERS}",
+            offsets.len()
+        );
+        offsets.sort();
+        let offset = offsets[offsets.len() / 2];
+        self.set_offset(offset.clamp(-max_correction.abs(), max_correction.abs()));
+        Ok(offset)
+    }
+}
+
+/// Converts an NTP timestamp to UTC.
+fn ntp_to_utc(buf: &[u8]) -> time::Utc {
+    let secs = i64::from(u32::from_be_bytes(buf[0..4].try_into().unwrap()));
+    let frac = u64::from(u32::from_be_bytes(buf[4..8].try_into().unwrap()));
+    let nanos = (frac * 1_000_000_000) >> 32;
+    time::UNIX_EPOCH + time::Duration::new(secs - NTP_UNIX_OFFSET, nanos as i32)
+}
+
+/// Measures the offset of the local clock against an NTP server with a single SNTP request.
+/// Positive if the local clock is behind the server.
+async fn ntp_offset(
+    ctx: &ctx::Ctx,
+    server: &str,
+    timeout: time::Duration,
+) -> anyhow::Result<time::Duration> {
+    let socket = scope::wait_blocking(|| {
+        let addr = match server.to_socket_addrs() {
+            Ok(mut addrs) => addrs.next(),
+            Err(_) => (server, NTP_PORT).to_socket_addrs()?.next(),
+        }
+        .context("no address")?;
+        let socket = UdpSocket::bind(if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })?;
+        socket.connect(addr)?;
+        socket.set_read_timeout(Some(timeout.try_into()?))?;
+        anyhow::Ok(socket)
+    })
+    .await?;
+    let (sent, start) = (ctx.now_utc(), ctx.now());
+    let resp = scope::wait_blocking(|| {
+        let mut req = [0; NTP_PACKET_SIZE];
+        // No leap indicator, version 4, client mode.
+        req[0] = 0x23;
+        socket.send(&req)?;
+        let mut resp = [0; NTP_PACKET_SIZE];
+        anyhow::ensure!(
+            socket.recv(&mut resp)? == NTP_PACKET_SIZE,
+            "truncated response"
+        );
+        Ok(resp)
+    })
+    .await
+    .context("request")?;
+    let received = sent + (ctx.now() - start);
+    anyhow::ensure!(resp[0] & 0b111 == 4, "not a server response");
+    anyhow::ensure!(resp[1] != 0, "kiss-of-death response");
+    let (server_received, server_sent) = (ntp_to_utc(&resp[32..40]), ntp_to_utc(&resp[40..48]));
+    Ok(((server_received - sent) + (server_sent - received)) / 2)
+}
+
+/// Metrics of the time source.
+#[derive(Debug, Metrics)]
+#[metrics(prefix = "network_time")]
+struct TimeMetrics {
+    /// Absolute offset of the local clock against the NTP servers.
+    #[metrics(unit = Unit::Seconds)]
+    clock_offset: Gauge<Duration>,
+}
+
+#[vise::register]
+static METRICS: vise::Global<TimeMetrics> = vise::Global::new();
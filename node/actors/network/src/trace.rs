@@ -0,0 +1,73 @@
+//! Trace context propagated along the consensus messages, so that the processing
+//! of a single proposal (from the leader proposal to the finalization) can be
+//! correlated across nodes as one distributed trace.
+//! The format follows the W3C Trace Context (<https://www.w3.org/TR/trace-context/>),
+//! which is what OpenTelemetry uses for propagation.
+use crate::proto::consensus as proto;
+use anyhow::Context as _;
+use rand::Rng;
+use std::fmt;
+use zksync_protobuf::{required, ProtoFmt};
+
+/// Identifiers of a trace and of the span within it, which caused the message to be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    /// Id of the trace, shared by all spans of the trace.
+    pub trace_id: u128,
+    /// Id of the span within the trace.
+    pub span_id: u64,
+}
+
+impl TraceContext {
+    /// Starts a new trace.
+    pub fn new_root(rng: &mut impl Rng) -> Self {
+        Self {
+            trace_id: rng.gen(),
+            span_id: rng.gen(),
+        }
+    }
+
+    /// Creates a new span within the same trace.
+    pub fn child(&self, rng: &mut impl Rng) -> Self {
+        Self {
+            trace_id: self.trace_id,
+            span_id: rng.gen(),
+        }
+    }
+
+    /// `tracing` span carrying the trace context.
+    pub fn span(&self) -> tracing::Span {
+        tracing::info_span!(
+            "trace",
+            trace_id = %format_args!("{:032x}", self.trace_id),
+            span_id = %format_args!("{:016x}", self.span_id),
+        )
+    }
+}
+
+impl fmt::Display for TraceContext {
+    /// Formats the context as a W3C `traceparent` header value.
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "00-{:032x}-{:016x}-01", self.trace_id, self.span_id)
+    }
+}
+
+impl ProtoFmt for TraceContext {
+    type Proto = proto::TraceContext;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            trace_id: u128::from_be_bytes(
+                required(&r.trace_id)?[..].try_into().context("trace_id")?,
+            ),
+            span_id: u64::from_be_bytes(required(&r.span_id)?[..].try_into().context("span_id")?),
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            trace_id: Some(self.trace_id.to_be_bytes().to_vec()),
+            span_id: Some(self.span_id.to_be_bytes().to_vec()),
+        }
+    }
+}
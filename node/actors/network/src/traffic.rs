@@ -0,0 +1,234 @@
+//! Accounting of the traffic of the peer connections, per stream class.
+//! Every RPC has its own mux capability, so the stream class is identified by the RPC method.
+//! The traffic of the gossip connections is exposed via `Monitor::peer_traffic()`,
+//! and checked against `GossipConfig::peer_bandwidth_cap`, except for the block sync RPCs
+//! (see `Rpc::CAPPED`).
+use crate::mux::CapabilityId;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+use vise::{Counter, EncodeLabelSet, EncodeLabelValue, Family, Metrics, Unit};
+use zksync_concurrency::{ctx, time};
+use zksync_consensus_roles::node;
+
+/// Window over which the traffic received from a peer is compared against its cap.
+const CAP_WINDOW: time::Duration = time::Duration::seconds(10);
+/// Number of consecutive windows in which a peer has to exceed its cap to get disconnected.
+/// A single burst (e.g. of blocks requested by us) is not a violation.
+const CAP_STRIKES: usize = 3;
+
+/// Bytes transferred over a connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrafficStats {
+    /// Bytes sent to the peer.
+    pub sent: u64,
+    /// Bytes received from the peer.
+    pub received: u64,
+}
+
+/// Traffic of a single stream class.
+#[derive(Debug)]
+struct ClassTraffic {
+    class: &'static str,
+    /// Whether the traffic counts towards the bandwidth cap of the peer.
+    capped: bool,
+    stats: TrafficStats,
+}
+
+/// Traffic of a single connection, per stream class.
+/// Only the payload of the mux data frames is counted.
+#[derive(Debug, Default)]
+pub(crate) struct Traffic(Mutex<BTreeMap<CapabilityId, ClassTraffic>>);
+
+impl Traffic {
+    /// Registers the stream class of a capability.
+    /// Traffic of the unregistered capabilities is not counted.
+    pub(crate) fn register(&self, cap: CapabilityId, class: &'static str, capped: bool) {
+        self.0.lock().unwrap().insert(
+            cap,
+            ClassTraffic {
+                class,
+                capped,
+                stats: TrafficStats::default(),
+            },
+        );
+    }
+
+    /// Records bytes sent over a stream of the capability.
+    pub(crate) fn sent(&self, cap: CapabilityId, bytes: usize) {
+        if let Some(t) = self.0.lock().unwrap().get_mut(&cap) {
+            t.stats.sent += bytes as u64;
+            METRICS.bytes[&TrafficLabels {
+                class: t.class,
+                direction: Direction::Sent,
+            }]
+                .inc_by(bytes as u64);
+        }
+    }
+
+    /// Records bytes received over a stream of the capability.
+    pub(crate) fn received(&self, cap: CapabilityId, bytes: usize) {
+        if let Some(t) = self.0.lock().unwrap().get_mut(&cap) {
+            t.stats.received += bytes as u64;
+            METRICS.bytes[&TrafficLabels {
+                class: t.class,
+                direction: Direction::Received,
+            }]
+                .inc_by(bytes as u64);
+        }
+    }
+
+    /// Traffic per stream class.
+    pub(crate) fn by_class(&self) -> BTreeMap<&'static str, TrafficStats> {
+        self.0
+            .lock()
+            .unwrap()
+            .values()
+            .map(|t| (t.class, t.stats))
+            .collect()
+    }
+
+    /// Total traffic of the connection.
+    pub(crate) fn total(&self) -> TrafficStats {
+        let mut total = TrafficStats::default();
+        for t in self.0.lock().unwrap().values() {
+            total.sent += t.stats.sent;
+            total.received += t.stats.received;
+        }
+        total
+    }
+
+    /// Bytes received over the streams which count towards the bandwidth cap.
+    pub(crate) fn capped_received(&self) -> u64 {
+        self.0
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|t| t.capped)
+            .map(|t| t.stats.received)
+            .sum()
+    }
+
+    /// Returns an error once the peer has exceeded `cap` (in bytes per second)
+    /// of the received capped traffic for `CAP_STRIKES` consecutive windows.
+    /// Block sync transfers are not capped, so that a peer serving the blocks requested
+    /// by this node is not disconnected in the middle of the sync.
+    /// Returns `Ok` when `ctx` is canceled.
+    pub(crate) async fn enforce_cap(&self, ctx: &ctx::Ctx, cap: usize) -> anyhow::Result<()> {
+        let allowance = (cap as u64).saturating_mul(CAP_WINDOW.whole_seconds() as u64);
+        let mut prev = self.capped_received();
+        let mut strikes = 0;
+        while ctx.sleep(CAP_WINDOW).await.is_ok() {
+            let received = self.capped_received();
+            strikes = if received - prev > allowance {
+                strikes + 1
+            } else {
+                0
+            };
+            prev = received;
+            if strikes >= CAP_STRIKES {
+                METRICS.cap_disconnects.inc();
+                anyhow::bail!("peer exceeded the bandwidth cap of {cap}B/s for {strikes} windows");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Traffic of a gossip connection with a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerTraffic {
+    /// Key of the peer.
+    pub key: node::PublicKey,
+    /// Total traffic of the connection.
+    pub total: TrafficStats,
+    /// Traffic per stream class (RPC method).
+    pub by_class: BTreeMap<&'static str, TrafficStats>,
+}
+
+/// Traffic of the currently open gossip connections, by the peer key.
+#[derive(Debug, Default)]
+pub(crate) struct Registry(Mutex<BTreeMap<node::PublicKey, Arc<Traffic>>>);
+
+/// Registration of the traffic of a connection, see `Registry::register()`.
+pub(crate) struct Registered<'a> {
+    registry: &'a Registry,
+    peer: node::PublicKey,
+    traffic: Arc<Traffic>,
+}
+
+impl Drop for Registered<'_> {
+    /// Removes the traffic of the closed connection,
+    /// unless it has been replaced by a newer connection with the same peer already.
+    fn drop(&mut self) {
+        let mut peers = self.registry.0.lock().unwrap();
+        if peers
+            .get(&self.peer)
+            .is_some_and(|t| Arc::ptr_eq(t, &self.traffic))
+        {
+            peers.remove(&self.peer);
+        }
+    }
+}
+
+impl Registry {
+    /// Registers the traffic of a connection with the peer,
+    /// until the returned guard is dropped.
+    pub(crate) fn register(
+        &self,
+        peer: &node::PublicKey,
+        traffic: &Arc<Traffic>,
+    ) -> Registered<'_> {
+        self.0.lock().unwrap().insert(peer.clone(), traffic.clone());
+        Registered {
+            registry: self,
+            peer: peer.clone(),
+            traffic: traffic.clone(),
+        }
+    }
+
+    /// Traffic of the open connections, ordered by the peer key.
+    pub(crate) fn get(&self) -> Vec<PeerTraffic> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, traffic)| PeerTraffic {
+                key: key.clone(),
+                total: traffic.total(),
+                by_class: traffic.by_class(),
+            })
+            .collect()
+    }
+}
+
+/// Direction of the traffic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EncodeLabelValue)]
+#[metrics(rename_all = "snake_case")]
+enum Direction {
+    /// Traffic sent to the peers.
+    Sent,
+    /// Traffic received from the peers.
+    Received,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EncodeLabelSet)]
+struct TrafficLabels {
+    class: &'static str,
+    direction: Direction,
+}
+
+/// Metrics of the traffic of the peer connections.
+#[derive(Debug, Metrics)]
+#[metrics(prefix = "network_traffic")]
+struct TrafficMetrics {
+    /// Bytes transferred over all the connections, by the stream class and direction.
+    #[metrics(unit = Unit::Bytes)]
+    bytes: Family<TrafficLabels, Counter>,
+    /// Peers disconnected for exceeding `GossipConfig::peer_bandwidth_cap`.
+    cap_disconnects: Counter,
+}
+
+#[vise::register]
+static METRICS: vise::Global<TrafficMetrics> = vise::Global::new();
@@ -0,0 +1,236 @@
+//! Transport over which the network connections are established.
+//!
+//! Connections go over the transport selected in the config (TCP by default).
+//! `Transport::InProcess` is served by a virtual transport: listeners and streams
+//! are in-memory, so that tests (see `testonly::reserve_virtual_listener`) can run
+//! without binding any OS ports, massively in parallel and in sandboxed environments.
+use crate::Transport;
+use once_cell::sync::Lazy;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+};
+use tokio::sync::mpsc;
+use zksync_concurrency::{ctx, io, net};
+
+/// Capacity of the in-memory buffer of a virtual stream (in each direction).
+const VIRTUAL_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Virtual listeners, which are currently bound.
+static VIRTUAL_LISTENERS: Lazy<
+    Mutex<HashMap<SocketAddr, mpsc::UnboundedSender<tokio::io::DuplexStream>>>,
+> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Path of the unix socket serving `addr`.
+#[cfg(unix)]
+fn unix_socket_path(dir: &Path, addr: &SocketAddr) -> PathBuf {
+    dir.join(format!("{}_{}.sock", addr.ip(), addr.port()))
+}
+
+/// Stream of the transport.
+#[pin_project::pin_project(project = StreamProj)]
+pub(crate) enum Stream {
+    /// TCP stream.
+    Tcp(#[pin] net::tcp::Stream),
+    /// Unix domain socket stream.
+    #[cfg(unix)]
+    Unix(#[pin] tokio::net::UnixStream),
+    /// In-memory stream.
+    Virtual(#[pin] tokio::io::DuplexStream),
+}
+
+impl io::AsyncRead for Stream {
+    #[inline(always)]
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.project() {
+            StreamProj::Tcp(s) => s.poll_read(cx, buf),
+            #[cfg(unix)]
+            StreamProj::Unix(s) => s.poll_read(cx, buf),
+            StreamProj::Virtual(s) => s.poll_read(cx, buf),
+        }
+    }
+}
+
+impl io::AsyncWrite for Stream {
+    #[inline(always)]
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.project() {
+            StreamProj::Tcp(s) => s.poll_write(cx, buf),
+            #[cfg(unix)]
+            StreamProj::Unix(s) => s.poll_write(cx, buf),
+            StreamProj::Virtual(s) => s.poll_write(cx, buf),
+        }
+    }
+
+    #[inline(always)]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match self.project() {
+            StreamProj::Tcp(s) => s.poll_flush(cx),
+            #[cfg(unix)]
+            StreamProj::Unix(s) => s.poll_flush(cx),
+            StreamProj::Virtual(s) => s.poll_flush(cx),
+        }
+    }
+
+    #[inline(always)]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match self.project() {
+            StreamProj::Tcp(s) => s.poll_shutdown(cx),
+            #[cfg(unix)]
+            StreamProj::Unix(s) => s.poll_shutdown(cx),
+            StreamProj::Virtual(s) => s.poll_shutdown(cx),
+        }
+    }
+}
+
+impl Stream {
+    /// Address of the remote end of the stream.
+    /// `None` for the virtual streams.
+    pub(crate) fn peer_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Tcp(s) => s.peer_addr().ok(),
+            #[cfg(unix)]
+            Self::Unix(_) => None,
+            Self::Virtual(_) => None,
+        }
+    }
+}
+
+/// Listener of the transport.
+pub(crate) enum Listener {
+    /// TCP listener.
+    Tcp(net::tcp::Listener),
+    /// Unix domain socket listener.
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+    /// In-memory listener.
+    Virtual(mpsc::UnboundedReceiver<tokio::io::DuplexStream>),
+}
+
+/// Binds a listener to the given address.
+pub(crate) fn bind(transport: &Transport, addr: &net::tcp::ListenerAddr) -> io::Result<Listener> {
+    match transport {
+        Transport::Tcp => return Ok(Listener::Tcp(addr.bind()?)),
+        #[cfg(unix)]
+        Transport::Unix(dir) => return bind_unix(&unix_socket_path(dir, addr)),
+        Transport::InProcess => {}
+    }
+    let mut listeners = VIRTUAL_LISTENERS.lock().unwrap();
+    // A listener of a terminated node might still be registered.
+    if listeners
+        .get(&**addr)
+        .map_or(false, |send| !send.is_closed())
+    {
+        return Err(io::ErrorKind::AddrInUse.into());
+    }
+    let (send, recv) = mpsc::unbounded_channel();
+    listeners.insert(**addr, send);
+    Ok(Listener::Virtual(recv))
+}
+
+/// Binds a unix socket listener.
+/// A socket file left by a terminated node is removed.
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> io::Result<Listener> {
+    if path.exists() {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(io::ErrorKind::AddrInUse.into());
+        }
+        std::fs::remove_file(path)?;
+    }
+    Ok(Listener::Unix(tokio::net::UnixListener::bind(path)?))
+}
+
+/// Accepts an INBOUND listener connection.
+pub(crate) async fn accept(
+    ctx: &ctx::Ctx,
+    listener: &mut Listener,
+) -> ctx::OrCanceled<io::Result<Stream>> {
+    Ok(match listener {
+        Listener::Tcp(listener) => net::tcp::accept(ctx, listener).await?.map(Stream::Tcp),
+        #[cfg(unix)]
+        Listener::Unix(listener) => ctx
+            .wait(listener.accept())
+            .await?
+            .map(|(stream, _)| Stream::Unix(stream)),
+        Listener::Virtual(recv) => ctx
+            .wait(recv.recv())
+            .await?
+            .map(Stream::Virtual)
+            .ok_or_else(|| io::ErrorKind::NotConnected.into()),
+    })
+}
+
+/// Opens a connection to a remote host.
+pub(crate) async fn connect(
+    ctx: &ctx::Ctx,
+    transport: &Transport,
+    addr: SocketAddr,
+) -> ctx::OrCanceled<io::Result<Stream>> {
+    match transport {
+        Transport::Tcp => return Ok(net::tcp::connect(ctx, addr).await?.map(Stream::Tcp)),
+        #[cfg(unix)]
+        Transport::Unix(dir) => {
+            return Ok(ctx
+                .wait(tokio::net::UnixStream::connect(unix_socket_path(
+                    dir, &addr,
+                )))
+                .await?
+                .map(Stream::Unix))
+        }
+        Transport::InProcess => {}
+    }
+    let (local, remote) = tokio::io::duplex(VIRTUAL_BUFFER_SIZE);
+    let listeners = VIRTUAL_LISTENERS.lock().unwrap();
+    Ok(match listeners.get(&addr).map(|send| send.send(remote)) {
+        Some(Ok(())) => Ok(Stream::Virtual(local)),
+        _ => Err(io::ErrorKind::ConnectionRefused.into()),
+    })
+}
+
+/// Test-only transport utilities.
+pub(crate) mod testonly {
+    use super::*;
+    use std::{
+        net::Ipv6Addr,
+        sync::atomic::{AtomicU64, Ordering},
+    };
+
+    /// First segment of the reserved addresses, from the discard-only IPv6 prefix
+    /// `100::/64` (RFC 6666), so that they never collide with a real address.
+    const VIRTUAL_PREFIX: u16 = 0x100;
+
+    /// Reserves a unique address of the virtual transport (`Transport::InProcess`).
+    /// Unlike `net::tcp::testonly::reserve_listener()`, it doesn't use any OS resources.
+    pub fn reserve_virtual_listener() -> net::tcp::ListenerAddr {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let ip = Ipv6Addr::new(
+            VIRTUAL_PREFIX,
+            0,
+            0,
+            0,
+            (n >> 48) as u16,
+            (n >> 32) as u16,
+            (n >> 16) as u16,
+            n as u16,
+        );
+        net::tcp::ListenerAddr::new(SocketAddr::new(ip.into(), 1))
+    }
+
+    /// Establishes a virtual connection.
+    #[cfg(test)]
+    pub(crate) fn pipe() -> (Stream, Stream) {
+        let (outbound, inbound) = tokio::io::duplex(VIRTUAL_BUFFER_SIZE);
+        (Stream::Virtual(outbound), Stream::Virtual(inbound))
+    }
+}
@@ -1,8 +1,55 @@
 //! Configuration for the `SyncBlocks` actor.
-use zksync_concurrency::time;
+use std::{collections::HashMap, sync::Arc};
+use zksync_concurrency::{ctx, time};
+use zksync_consensus_network::{gossip::genesis, io::MAX_PAYLOAD_SAMPLES, Transport};
+use zksync_consensus_roles::{node, validator};
+use zksync_consensus_storage::SyncProgressStore;
+
+/// Interval between the attempts to confirm the genesis with the peers.
+const GENESIS_CHECK_RETRY_INTERVAL: time::Duration = time::Duration::seconds(10);
+
+/// Confirmation of the genesis of the node by its peers, before any blocks are fetched.
+#[derive(Debug, Clone)]
+pub(crate) struct GenesisCheck {
+    /// Transport over which the peers are reached.
+    pub(crate) transport: Transport,
+    /// Node keys and addresses of the peers.
+    pub(crate) peers: HashMap<node::PublicKey, std::net::SocketAddr>,
+    /// Number of the peers which have to report the genesis of the node.
+    pub(crate) quorum: usize,
+}
+
+impl GenesisCheck {
+    /// Waits until a quorum of the peers confirms `genesis`, retrying while the peers
+    /// are unreachable. Fails if a quorum of the peers reports a different genesis.
+    pub(crate) async fn run(
+        &self,
+        ctx: &ctx::Ctx,
+        genesis: &validator::Genesis,
+    ) -> ctx::Result<()> {
+        loop {
+            match genesis::bootstrap_genesis(ctx, &self.transport, &self.peers, self.quorum).await {
+                Ok(got) if got.hash() == genesis.hash() => {
+                    tracing::info!("genesis confirmed by {} peers", self.quorum);
+                    return Ok(());
+                }
+                Ok(got) => {
+                    return Err(anyhow::format_err!(
+                        "{} peers have a different genesis {:?}",
+                        self.quorum,
+                        got.hash()
+                    )
+                    .into())
+                }
+                Err(err) => tracing::warn!("failed to confirm the genesis: {err:#}"),
+            }
+            ctx.sleep(GENESIS_CHECK_RETRY_INTERVAL).await?;
+        }
+    }
+}
 
 /// Configuration for the `SyncBlocks` actor.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Config {
     /// Maximum number of blocks to attempt to get concurrently from all peers in total.
     pub(crate) max_concurrent_blocks: usize,
@@ -11,6 +58,21 @@ pub struct Config {
     /// Interval between re-checking peers to get a specific block if no peers currently should have
     /// the block.
     pub(crate) sleep_interval_for_get_block: time::Duration,
+    /// Number of peers from which the justification of a block is fetched and cross-checked
+    /// before the payload is fetched from one of them. 1 disables the cross-check.
+    pub(crate) cross_check_peers: usize,
+    /// Number of random chunks of the payload sampled from a peer before the block
+    /// is fetched. 0 disables the sampling.
+    pub(crate) sample_chunks: usize,
+    /// Number of blocks ahead of the stored ones which are sampled,
+    /// while their download is deferred.
+    pub(crate) sample_ahead: usize,
+    /// Store in which the sync progress is persisted, so that the fetching is resumed
+    /// right after a restart. `None` disables the persistence.
+    pub(crate) progress_store: Option<Arc<dyn SyncProgressStore>>,
+    /// Confirmation of the genesis by the peers, awaited before any blocks are fetched.
+    /// `None` disables the check.
+    pub(crate) genesis_check: Option<GenesisCheck>,
 }
 
 impl Default for Config {
@@ -26,6 +88,11 @@ impl Config {
             max_concurrent_blocks: 20,
             max_concurrent_blocks_per_peer: 5,
             sleep_interval_for_get_block: time::Duration::seconds(10),
+            cross_check_peers: 1,
+            sample_chunks: 0,
+            sample_ahead: 100,
+            progress_store: None,
+            genesis_check: None,
         }
     }
 
@@ -53,4 +120,68 @@ impl Config {
         self.sleep_interval_for_get_block = interval;
         Ok(self)
     }
+
+    /// Sets the number of peers from which the justification of every block is fetched
+    /// and cross-checked before the payload is fetched. Peers serving an invalid
+    /// justification are detected before the payload is downloaded.
+    pub fn with_cross_check_peers(mut self, peers: usize) -> anyhow::Result<Self> {
+        anyhow::ensure!(peers > 0, "Number of peers must be positive");
+        self.cross_check_peers = peers;
+        Ok(self)
+    }
+
+    /// Sets the number of random chunks of the payload which are sampled from a peer
+    /// and verified against the payload root in the block header. The blocks are sampled
+    /// ahead of the stored ones (see `with_sample_ahead()`), while the download of their
+    /// full payloads is deferred until they are next to be stored. Peers which don't have
+    /// the payload are detected without downloading it. Blocks without the payload root
+    /// are not sampled. 0 disables the sampling.
+    pub fn with_payload_sampling(mut self, chunks: usize) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            chunks <= MAX_PAYLOAD_SAMPLES,
+            "Number of chunks must be at most {MAX_PAYLOAD_SAMPLES}"
+        );
+        self.sample_chunks = chunks;
+        Ok(self)
+    }
+
+    /// Sets the number of blocks ahead of the stored ones which are sampled
+    /// (see `with_payload_sampling()`).
+    pub fn with_sample_ahead(mut self, blocks: usize) -> anyhow::Result<Self> {
+        anyhow::ensure!(blocks > 0, "Number of blocks must be positive");
+        self.sample_ahead = blocks;
+        Ok(self)
+    }
+
+    /// Sets the store in which the sync progress is persisted.
+    pub fn with_progress_store(mut self, store: Arc<dyn SyncProgressStore>) -> Self {
+        self.progress_store = Some(store);
+        self
+    }
+
+    /// Requires `quorum` of the `peers` (node keys and addresses, reached over `transport`)
+    /// to confirm the genesis of the node before any blocks are fetched, so that the node
+    /// doesn't sync a chain with a genesis that the network doesn't agree on. The peers
+    /// authenticate with their node keys, so each of them is counted once. Fetching is
+    /// postponed until the check passes, and the actor fails if a quorum of the peers
+    /// reports a different genesis.
+    pub fn with_genesis_check(
+        mut self,
+        transport: Transport,
+        peers: HashMap<node::PublicKey, std::net::SocketAddr>,
+        quorum: usize,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(quorum > 0, "Quorum must be positive");
+        anyhow::ensure!(
+            peers.len() >= quorum,
+            "{} peers are not enough for quorum {quorum}",
+            peers.len()
+        );
+        self.genesis_check = Some(GenesisCheck {
+            transport,
+            peers,
+            quorum,
+        });
+        Ok(self)
+    }
 }
@@ -26,9 +26,22 @@ impl Config {
         mut pipe: ActorPipe<InputMessage, OutputMessage>,
         storage: Arc<BlockStore>,
     ) -> anyhow::Result<()> {
+        let genesis_check = self.genesis_check.clone();
         let peer_states = PeerStates::new(self, storage.clone(), pipe.send);
         let result: ctx::Result<()> = scope::run!(ctx, |ctx, s| async {
-            s.spawn_bg(async { peer_states.run_block_fetcher(ctx).await });
+            peer_states.restore(ctx).await?;
+            s.spawn_bg(async {
+                // The peer states are tracked in the meantime.
+                if let Some(check) = &genesis_check {
+                    check.run(ctx, &storage.genesis()).await?;
+                }
+                scope::run!(ctx, |ctx, s| async {
+                    s.spawn_bg(peer_states.run_payload_sampler(ctx));
+                    peer_states.run_block_fetcher(ctx).await
+                })
+                .await
+            });
+            s.spawn_bg(async { peer_states.run_progress_saver(ctx).await });
             loop {
                 match pipe.recv.recv(ctx).await? {
                     InputMessage::Network(SyncBlocksRequest::UpdatePeerSyncState {
@@ -42,6 +55,9 @@ impl Config {
                         }
                         response.send(()).ok();
                     }
+                    InputMessage::Network(SyncBlocksRequest::UpdatePeerRtt { peer, rtt }) => {
+                        peer_states.update_rtt(&peer, rtt);
+                    }
                 }
             }
         })
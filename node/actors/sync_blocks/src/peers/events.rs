@@ -15,4 +15,6 @@ pub(super) enum PeerStateEvent {
     },
     /// Peer was disconnected (i.e., it has dropped a request).
     PeerDropped(node::PublicKey),
+    /// Peer has served invalid data and has been reported to the network.
+    Misbehaved(node::PublicKey),
 }
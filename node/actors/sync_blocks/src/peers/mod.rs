@@ -2,29 +2,41 @@
 use self::events::PeerStateEvent;
 use crate::{io, Config};
 use anyhow::Context as _;
+use rand::{seq::IteratorRandom as _, Rng as _};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     sync::{Arc, Mutex},
 };
 use zksync_concurrency::{
     ctx::{self, channel},
-    oneshot, scope, sync,
+    oneshot, scope, sync, time,
 };
-use zksync_consensus_network::io::SyncBlocksInputMessage;
+use zksync_consensus_network::io::{PayloadSample, SyncBlocksInputMessage};
 use zksync_consensus_roles::{
     node, validator,
     validator::{BlockNumber, FinalBlock},
 };
-use zksync_consensus_storage::{BlockStore, BlockStoreState};
+use zksync_consensus_storage::{BlockStore, BlockStoreState, FetchCursor, SyncProgress};
 
 mod events;
 #[cfg(test)]
 mod tests;
 
+/// Interval between the saves of the sync progress.
+const SAVE_PROGRESS_INTERVAL: time::Duration = time::Duration::seconds(5);
+/// Peers with RTT up to `RTT_TOLERANCE` times the lowest RTT are considered equally close.
+/// Blocks are fetched from a random one of them, so that the load is spread.
+const RTT_TOLERANCE: i32 = 2;
+
 #[derive(Debug)]
 struct PeerState {
     state: BlockStoreState,
     get_block_semaphore: Arc<sync::Semaphore>,
+    /// Latest round trip time to the peer, if known.
+    rtt: Option<time::Duration>,
+    /// Whether the state has been restored from the persisted sync progress,
+    /// i.e. it hasn't been advertised by the peer since the start.
+    restored: bool,
 }
 
 /// Handle for [`PeerStates`] allowing to send updates to it.
@@ -36,11 +48,13 @@ pub(crate) struct PeerStates {
 
     peers: Mutex<HashMap<node::PublicKey, PeerState>>,
     highest_peer_block: sync::watch::Sender<BlockNumber>,
+    /// Verified headers of the blocks which have been sampled, but not stored yet.
+    sampled: sync::watch::Sender<BTreeMap<BlockNumber, validator::BlockHeader>>,
     events_sender: Option<channel::UnboundedSender<PeerStateEvent>>,
 }
 
 impl PeerStates {
-    fn genesis(&self) -> &validator::Genesis {
+    fn genesis(&self) -> Arc<validator::Genesis> {
         self.storage.genesis()
     }
 
@@ -57,6 +71,7 @@ impl PeerStates {
 
             peers: Mutex::default(),
             highest_peer_block: sync::watch::channel(BlockNumber(0)).0,
+            sampled: sync::watch::channel(BTreeMap::new()).0,
             events_sender: None,
         }
     }
@@ -68,20 +83,42 @@ impl PeerStates {
         &self,
         peer: &node::PublicKey,
         state: BlockStoreState,
+    ) -> anyhow::Result<()> {
+        self.set_state(peer, state, false)
+    }
+
+    /// Sets the known `BlockStore` state of the given peer, see `update()`.
+    fn set_state(
+        &self,
+        peer: &node::PublicKey,
+        state: BlockStoreState,
+        restored: bool,
     ) -> anyhow::Result<()> {
         use std::collections::hash_map::Entry;
         let Some(last) = &state.last else {
             return Ok(());
         };
-        last.verify(self.genesis()).context("state.last.verify()")?;
+        if let Err(err) = last.verify(&self.genesis()) {
+            // The QC may be signed with keys rotated by blocks which haven't been synced yet.
+            // Such a state is accepted: the blocks fetched from the peer are verified anyway.
+            if last.header().number < self.storage.subscribe().borrow().next() {
+                return Err(anyhow::Error::from(err).context("state.last.verify()"));
+            }
+        }
         let mut peers = self.peers.lock().unwrap();
         match peers.entry(peer.clone()) {
-            Entry::Occupied(mut e) => e.get_mut().state = state.clone(),
+            Entry::Occupied(mut e) => {
+                let e = e.get_mut();
+                e.state = state.clone();
+                e.restored = restored;
+            }
             Entry::Vacant(e) => {
                 let permits = self.config.max_concurrent_blocks_per_peer;
                 e.insert(PeerState {
                     state: state.clone(),
                     get_block_semaphore: Arc::new(sync::Semaphore::new(permits)),
+                    rtt: None,
+                    restored,
                 });
             }
         }
@@ -96,6 +133,74 @@ impl PeerStates {
         Ok(())
     }
 
+    /// Updates the round trip time to the given peer.
+    /// Peers with lower RTT are preferred when fetching blocks.
+    /// Measurements of peers with unknown `BlockStore` state are ignored.
+    pub(crate) fn update_rtt(&self, peer: &node::PublicKey, rtt: time::Duration) {
+        if let Some(state) = self.peers.lock().unwrap().get_mut(peer) {
+            state.rtt = Some(rtt);
+        }
+    }
+
+    /// Restores the peer states from the persisted sync progress, so that the fetching
+    /// is resumed without waiting for the peers to advertise their states again.
+    /// Cursors which don't verify (e.g. after a fork) are dropped.
+    /// The peers might not be connected yet right after the restart, so the restored states
+    /// are not dropped when fetching fails, but kept until the peers advertise fresh ones.
+    pub(crate) async fn restore(&self, ctx: &ctx::Ctx) -> ctx::Result<()> {
+        let Some(store) = &self.config.progress_store else {
+            return Ok(());
+        };
+        for cursor in store.progress(ctx).await?.cursors {
+            if let Err(err) = self.set_state(&cursor.peer, cursor.state, true) {
+                tracing::info!(%err, peer = ?cursor.peer, "dropping a persisted fetch cursor");
+            }
+        }
+        Ok(())
+    }
+
+    /// Current sync progress: the states of the peers having blocks
+    /// which are not present in storage, ordered by the peer key.
+    fn progress(&self) -> SyncProgress {
+        let next = self.storage.subscribe().borrow().next();
+        let mut cursors: Vec<_> = self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, state)| {
+                state
+                    .state
+                    .last
+                    .as_ref()
+                    .is_some_and(|last| last.header().number >= next)
+            })
+            .map(|(peer, state)| FetchCursor {
+                peer: peer.clone(),
+                state: state.state.clone(),
+            })
+            .collect();
+        cursors.sort_by(|a, b| a.peer.cmp(&b.peer));
+        SyncProgress { cursors }
+    }
+
+    /// Task persisting the sync progress whenever it changes,
+    /// at most once per `SAVE_PROGRESS_INTERVAL`.
+    pub(crate) async fn run_progress_saver(&self, ctx: &ctx::Ctx) -> ctx::Result<()> {
+        let Some(store) = &self.config.progress_store else {
+            return Ok(());
+        };
+        let mut saved = store.progress(ctx).await?;
+        loop {
+            ctx.sleep(SAVE_PROGRESS_INTERVAL).await?;
+            let progress = self.progress();
+            if progress != saved {
+                store.set_progress(ctx, &progress).await?;
+                saved = progress;
+            }
+        }
+    }
+
     /// Task fetching blocks from peers which are not present in storage.
     pub(crate) async fn run_block_fetcher(&self, ctx: &ctx::Ctx) -> ctx::Result<()> {
         let sem = sync::Semaphore::new(self.config.max_concurrent_blocks);
@@ -119,12 +224,124 @@ impl PeerStates {
         .await
     }
 
+    /// Task sampling the payloads of the blocks ahead of the fetched ones,
+    /// up to `Config::sample_ahead` blocks ahead of the stored ones.
+    /// Does nothing if the sampling is disabled.
+    pub(crate) async fn run_payload_sampler(&self, ctx: &ctx::Ctx) -> ctx::Result<()> {
+        if self.config.sample_chunks == 0 {
+            return Ok(());
+        }
+        let sem = sync::Semaphore::new(self.config.max_concurrent_blocks);
+        scope::run!(ctx, |ctx, s| async {
+            let mut next = self.storage.subscribe().borrow().next();
+            let mut highest_peer_block = self.highest_peer_block.subscribe();
+            let mut storage = self.storage.subscribe();
+            loop {
+                sync::wait_for(ctx, &mut highest_peer_block, |highest_peer_block| {
+                    highest_peer_block >= &next
+                })
+                .await?;
+                sync::wait_for(ctx, &mut storage, |state| {
+                    next.0 < state.next().0 + self.config.sample_ahead as u64
+                })
+                .await?;
+                let permit = sync::acquire(ctx, &sem).await?;
+                let block_number = ctx::NoCopy(next);
+                next = next.next();
+                s.spawn(async {
+                    let _permit = permit;
+                    self.sample_block(ctx, block_number.into()).await
+                });
+            }
+        })
+        .await
+    }
+
+    /// Samples the block from peers, until a peer serves a valid justification and valid
+    /// samples of the payload. Early exits if the block appeared in storage from other source.
+    async fn sample_block(&self, ctx: &ctx::Ctx, number: BlockNumber) -> ctx::Result<()> {
+        while self.storage.subscribe().borrow().next() <= number {
+            let Some((peer, permit)) = self.try_acquire_peer_permit(&mut ctx.rng(), number) else {
+                let sleep_interval = self.config.sleep_interval_for_get_block;
+                ctx.sleep(sleep_interval).await?;
+                continue;
+            };
+            let res = self.sample_block_from_peer(ctx, &peer, number).await;
+            drop(permit);
+            match res {
+                Ok(header) => {
+                    let next = self.storage.subscribe().borrow().next();
+                    self.sampled.send_modify(|sampled| {
+                        // Headers of the stored blocks are no longer needed.
+                        sampled.retain(|number, _| *number >= next);
+                        if number >= next {
+                            sampled.insert(number, header);
+                        }
+                    });
+                    return Ok(());
+                }
+                Err(ctx::Error::Canceled(_)) => {
+                    tracing::info!(%number, ?peer, "sampling canceled");
+                }
+                Err(err) => {
+                    tracing::info!(%err, %number, ?peer, "sampling failed");
+                    self.peer_failed(ctx, &peer, number).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetches the justification of the block from `peer` (cross-checking it if
+    /// `Config::cross_check_peers` > 1) and samples the payload committed to by its header
+    /// from `peer`, see `sample_payload()`. Returns the verified header.
+    async fn sample_block_from_peer(
+        &self,
+        ctx: &ctx::Ctx,
+        peer: &node::PublicKey,
+        number: BlockNumber,
+    ) -> ctx::Result<validator::BlockHeader> {
+        let header = if self.config.cross_check_peers > 1 {
+            self.cross_check(ctx, peer, number).await?
+        } else {
+            *self
+                .fetch_justification_from_peer(ctx, peer, number)
+                .await?
+                .header()
+        };
+        if let Some(root) = &header.payload_root {
+            self.sample_payload(ctx, peer, number, root).await?;
+        }
+        Ok(header)
+    }
+
+    /// Waits until the block is sampled and returns its verified header.
+    async fn wait_until_sampled(
+        &self,
+        ctx: &ctx::Ctx,
+        number: BlockNumber,
+    ) -> ctx::OrCanceled<validator::BlockHeader> {
+        let sampled = sync::wait_for(ctx, &mut self.sampled.subscribe(), |sampled| {
+            sampled.contains_key(&number)
+        })
+        .await?;
+        Ok(sampled[&number])
+    }
+
     /// Fetches the block from peers and puts it to storage.
     /// Early exits if the block appeared in storage from other source.
+    /// With the payload sampling enabled, the download is deferred until the block is sampled,
+    /// and the downloaded block has to match the sampled header.
     async fn fetch_block(&self, ctx: &ctx::Ctx, block_number: BlockNumber) -> ctx::Result<()> {
         let _ = scope::run!(ctx, |ctx, s| async {
             s.spawn_bg(async {
-                let block = self.fetch_block_from_peers(ctx, block_number).await?;
+                let sampled = match self.config.sample_chunks {
+                    0 => None,
+                    _ => Some(self.wait_until_sampled(ctx, block_number).await?),
+                };
+                let block = self
+                    .fetch_block_from_peers(ctx, block_number, sampled.as_ref())
+                    .await?;
                 self.storage.queue_block(ctx, block).await
             });
             // Cancel fetching as soon as block is queued for storage.
@@ -141,14 +358,17 @@ impl PeerStates {
         &self,
         ctx: &ctx::Ctx,
         number: BlockNumber,
+        sampled: Option<&validator::BlockHeader>,
     ) -> ctx::OrCanceled<FinalBlock> {
         while ctx.is_active() {
-            let Some((peer, permit)) = self.try_acquire_peer_permit(number) else {
+            let Some((peer, permit)) = self.try_acquire_peer_permit(&mut ctx.rng(), number) else {
                 let sleep_interval = self.config.sleep_interval_for_get_block;
                 ctx.sleep(sleep_interval).await?;
                 continue;
             };
-            let res = self.fetch_block_from_peer(ctx, &peer, number).await;
+            let res = self
+                .fetch_block_from_peer(ctx, &peer, number, sampled)
+                .await;
             drop(permit);
             match res {
                 Ok(block) => {
@@ -162,26 +382,60 @@ impl PeerStates {
                 }
                 Err(err) => {
                     tracing::info!(%err, %number, ?peer, "get_block() failed");
-                    if let Some(send) = &self.events_sender {
-                        send.send(PeerStateEvent::RpcFailed {
-                            peer_key: peer.clone(),
-                            block_number: number,
-                        });
-                    }
-                    self.drop_peer(&peer);
+                    self.peer_failed(ctx, &peer, number).await?;
                 }
             }
         }
         Err(ctx::Canceled)
     }
 
+    /// Handles a failed request for block `number` to `peer`: the peer is dropped,
+    /// unless its state has been restored (see `restore()`), in which case the request
+    /// is retried after `Config::sleep_interval_for_get_block`.
+    async fn peer_failed(
+        &self,
+        ctx: &ctx::Ctx,
+        peer: &node::PublicKey,
+        number: BlockNumber,
+    ) -> ctx::OrCanceled<()> {
+        if let Some(send) = &self.events_sender {
+            send.send(PeerStateEvent::RpcFailed {
+                peer_key: peer.clone(),
+                block_number: number,
+            });
+        }
+        let restored = self
+            .peers
+            .lock()
+            .unwrap()
+            .get(peer)
+            .is_some_and(|state| state.restored);
+        if restored {
+            ctx.sleep(self.config.sleep_interval_for_get_block).await?;
+        } else {
+            self.drop_peer(peer);
+        }
+        Ok(())
+    }
+
     /// Fetches a block from the specified peer.
+    /// If the block has been `sampled`, it has to match the sampled header. Otherwise,
+    /// if `Config::cross_check_peers` > 1, the justification of the block is cross-checked
+    /// with other peers first, see `cross_check()`.
     async fn fetch_block_from_peer(
         &self,
         ctx: &ctx::Ctx,
         peer: &node::PublicKey,
         number: BlockNumber,
+        sampled: Option<&validator::BlockHeader>,
     ) -> ctx::Result<FinalBlock> {
+        let header = match sampled {
+            Some(header) => Some(*header),
+            None if self.config.cross_check_peers > 1 => {
+                Some(self.cross_check(ctx, peer, number).await?)
+            }
+            None => None,
+        };
         let (response, response_receiver) = oneshot::channel();
         let message = SyncBlocksInputMessage::GetBlock {
             recipient: peer.clone(),
@@ -195,37 +449,235 @@ impl PeerStates {
             .context("no response")?
             .context("RPC error")?;
         if block.header().number != number {
+            self.misbehaved(peer);
             return Err(anyhow::anyhow!(
                 "block does not have requested number (requested: {number}, got: {})",
                 block.header().number
             )
             .into());
         }
-        block.verify(self.genesis()).context("block.validate()")?;
+        if let Err(err) = self
+            .verify_after_prev(ctx, number, |genesis| block.verify(genesis))
+            .await?
+        {
+            self.misbehaved(peer);
+            return Err(anyhow::Error::from(err).context("block.validate()").into());
+        }
+        if header.is_some_and(|header| &header != block.header()) {
+            self.misbehaved(peer);
+            return Err(anyhow::anyhow!("block does not match the verified header").into());
+        }
         Ok(block)
     }
 
+    /// Fetches the justification of the block from `peer` and from up to
+    /// `Config::cross_check_peers - 1` other peers having the block, and cross-checks them.
+    /// Peers serving an invalid justification are reported as misbehaved, while
+    /// the other peers failing to respond are skipped. Returns the justified block header.
+    async fn cross_check(
+        &self,
+        ctx: &ctx::Ctx,
+        peer: &node::PublicKey,
+        number: BlockNumber,
+    ) -> ctx::Result<validator::BlockHeader> {
+        let mut peers = vec![peer.clone()];
+        peers.extend(
+            self.peers
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(key, state)| *key != peer && state.state.contains(number))
+                .map(|(key, _)| key.clone())
+                .take(self.config.cross_check_peers - 1),
+        );
+        let results = scope::run!(ctx, |ctx, s| async {
+            let tasks: Vec<_> = peers
+                .iter()
+                .map(|peer| {
+                    s.spawn(async move {
+                        Ok(self.fetch_justification_from_peer(ctx, peer, number).await)
+                    })
+                })
+                .collect();
+            let mut results = vec![];
+            for task in tasks {
+                results.push(task.join(ctx).await?);
+            }
+            Ok::<_, ctx::Canceled>(results)
+        })
+        .await?;
+
+        let mut header: Option<validator::BlockHeader> = None;
+        for (key, res) in peers.iter().zip(results) {
+            let justification = match res {
+                Ok(justification) => justification,
+                Err(ctx::Error::Canceled(err)) => return Err(err.into()),
+                // The block is fetched from `peer`, so it has to serve a valid justification.
+                Err(err) if key == peer => return Err(err),
+                Err(err) => {
+                    tracing::info!(%err, %number, peer = ?key, "cross-check failed");
+                    continue;
+                }
+            };
+            match &header {
+                None => header = Some(*justification.header()),
+                Some(header) if header == justification.header() => {}
+                Some(header) => {
+                    // Both justifications are valid, i.e. the consensus has been violated.
+                    tracing::error!(
+                        %number, peer = ?key, ?header, other = ?justification.header(),
+                        "peers served valid justifications of conflicting blocks"
+                    );
+                    return Err(anyhow::anyhow!(
+                        "peers served valid justifications of conflicting blocks #{number}"
+                    )
+                    .into());
+                }
+            }
+        }
+        // `unwrap()` is safe, since the justification of `peer` has been checked above.
+        Ok(header.unwrap())
+    }
+
+    /// Fetches the justification of a block from the specified peer and verifies it.
+    async fn fetch_justification_from_peer(
+        &self,
+        ctx: &ctx::Ctx,
+        peer: &node::PublicKey,
+        number: BlockNumber,
+    ) -> ctx::Result<validator::CommitQC> {
+        let (response, response_receiver) = oneshot::channel();
+        let message = SyncBlocksInputMessage::GetJustification {
+            recipient: peer.clone(),
+            number,
+            response,
+        };
+        self.message_sender.send(message.into());
+        let justification = response_receiver
+            .recv_or_disconnected(ctx)
+            .await?
+            .context("no response")?
+            .context("RPC error")?;
+        if justification.header().number != number {
+            self.misbehaved(peer);
+            return Err(anyhow::anyhow!(
+                "justification does not have requested number (requested: {number}, got: {})",
+                justification.header().number
+            )
+            .into());
+        }
+        if let Err(err) = self
+            .verify_after_prev(ctx, number, |genesis| justification.verify(genesis))
+            .await?
+        {
+            self.misbehaved(peer);
+            return Err(anyhow::Error::from(err)
+                .context("justification.verify()")
+                .into());
+        }
+        Ok(justification)
+    }
+
+    /// Samples `Config::sample_chunks` random chunks of the payload of the block from `peer`
+    /// and verifies them against the payload root of the block header.
+    /// The peer is reported as misbehaved if it serves an invalid sample.
+    async fn sample_payload(
+        &self,
+        ctx: &ctx::Ctx,
+        peer: &node::PublicKey,
+        number: BlockNumber,
+        root: &validator::PayloadRoot,
+    ) -> ctx::Result<()> {
+        let samples: Vec<u64> = {
+            let rng = &mut ctx.rng();
+            (0..self.config.sample_chunks).map(|_| rng.gen()).collect()
+        };
+        let (response, response_receiver) = oneshot::channel();
+        let message = SyncBlocksInputMessage::SamplePayload {
+            recipient: peer.clone(),
+            number,
+            samples: samples.clone(),
+            response,
+        };
+        self.message_sender.send(message.into());
+        let got = response_receiver
+            .recv_or_disconnected(ctx)
+            .await?
+            .context("no response")?
+            .context("RPC error")?;
+        if let Err(err) = verify_samples(root, &samples, &got) {
+            self.misbehaved(peer);
+            return Err(err.context("payload sampling").into());
+        }
+        Ok(())
+    }
+
+    /// Verifies data of block `number` against the genesis. The block may be signed with keys
+    /// rotated by the preceding blocks, which are applied to the genesis once they are queued.
+    /// Hence, if the verification fails, it is retried once the preceding blocks are queued.
+    async fn verify_after_prev<E>(
+        &self,
+        ctx: &ctx::Ctx,
+        number: BlockNumber,
+        verify: impl Fn(&validator::Genesis) -> Result<(), E>,
+    ) -> ctx::OrCanceled<Result<(), E>> {
+        let res = verify(&self.genesis());
+        if res.is_ok() {
+            return Ok(res);
+        }
+        if let Some(prev) = number.prev() {
+            self.storage.wait_until_queued(ctx, prev).await?;
+        }
+        Ok(verify(&self.genesis()))
+    }
+
+    /// Reports a peer which has served invalid data to the network.
+    fn misbehaved(&self, peer: &node::PublicKey) {
+        tracing::info!(?peer, "peer served invalid data");
+        self.message_sender
+            .send(SyncBlocksInputMessage::Misbehaved { peer: peer.clone() }.into());
+        if let Some(events_sender) = &self.events_sender {
+            events_sender.send(PeerStateEvent::Misbehaved(peer.clone()));
+        }
+    }
+
     fn try_acquire_peer_permit(
         &self,
+        rng: &mut impl rand::Rng,
         block_number: BlockNumber,
     ) -> Option<(node::PublicKey, sync::OwnedSemaphorePermit)> {
         let peers = self.peers.lock().unwrap();
         let mut peers_with_no_permits = vec![];
-        let eligible_peers_info = peers.iter().filter(|(peer_key, state)| {
-            if !state.state.contains(block_number) {
-                return false;
-            }
-            let available_permits = state.get_block_semaphore.available_permits();
-            // ^ `available_permits()` provides a lower bound on the actual number of available permits.
-            // Some permits may be released before acquiring a new permit below, but no other permits
-            // are acquired since we hold an exclusive lock on `peers`.
-            if available_permits == 0 {
-                peers_with_no_permits.push(*peer_key);
-            }
-            available_permits > 0
-        });
+        let eligible_peers_info: Vec<_> = peers
+            .iter()
+            .filter(|(peer_key, state)| {
+                if !state.state.contains(block_number) {
+                    return false;
+                }
+                let available_permits = state.get_block_semaphore.available_permits();
+                // ^ `available_permits()` provides a lower bound on the actual number of available permits.
+                // Some permits may be released before acquiring a new permit below, but no other permits
+                // are acquired since we hold an exclusive lock on `peers`.
+                if available_permits == 0 {
+                    peers_with_no_permits.push(*peer_key);
+                }
+                available_permits > 0
+            })
+            .collect();
+        // Prefer the closest peers, picking randomly among the ones with a similar RTT.
+        // Peers with unknown RTT are picked only if no other peer is eligible.
+        let best_rtt = eligible_peers_info
+            .iter()
+            .filter_map(|(_, state)| state.rtt)
+            .min();
         let peer_to_query = eligible_peers_info
-            .max_by_key(|(_, state)| state.get_block_semaphore.available_permits());
+            .into_iter()
+            .filter(|(_, state)| match (best_rtt, state.rtt) {
+                (None, _) => true,
+                (Some(best), Some(rtt)) => rtt <= best * RTT_TOLERANCE,
+                (Some(_), None) => false,
+            })
+            .choose(rng);
 
         if let Some((peer_key, state)) = peer_to_query {
             let permit = state
@@ -255,3 +707,33 @@ impl PeerStates {
         }
     }
 }
+
+/// Verifies that `got` are the chunks of the payload committed to by `root`,
+/// requested by `samples`.
+fn verify_samples(
+    root: &validator::PayloadRoot,
+    samples: &[u64],
+    got: &[PayloadSample],
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        got.len() == samples.len(),
+        "got {} samples, want {}",
+        got.len(),
+        samples.len()
+    );
+    let mut payload_size = None;
+    for (sample, got) in samples.iter().zip(got) {
+        let size = *payload_size.get_or_insert(got.proof.payload_size);
+        anyhow::ensure!(got.proof.payload_size == size, "inconsistent payload size");
+        let index = (sample % validator::payload_chunk_count(size) as u64) as usize;
+        anyhow::ensure!(
+            got.proof.index == index,
+            "got chunk {}, want {index}",
+            got.proof.index
+        );
+        got.proof
+            .verify(root, &got.chunk)
+            .with_context(|| format!("chunk {index}"))?;
+    }
+    Ok(())
+}
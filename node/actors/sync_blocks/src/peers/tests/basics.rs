@@ -6,6 +6,7 @@ use crate::{
     tests::{make_response, sync_state},
 };
 use rand::seq::SliceRandom as _;
+use zksync_consensus_storage::{testonly::in_memory, SyncProgressStore as _};
 
 #[derive(Debug)]
 struct UpdatingPeerStateWithSingleBlock;
@@ -36,7 +37,10 @@ impl Test for UpdatingPeerStateWithSingleBlock {
             recipient,
             number,
             response,
-        }) = message;
+        }) = message
+        else {
+            unreachable!("unexpected message")
+        };
         assert_eq!(recipient, peer_key);
         assert_eq!(number, setup.blocks[0].number());
 
@@ -83,7 +87,10 @@ impl Test for CancelingBlockRetrieval {
 
         // Check that the actor has sent a `get_block` request to the peer
         let io::OutputMessage::Network(SyncBlocksInputMessage::GetBlock { mut response, .. }) =
-            message_receiver.recv(ctx).await?;
+            message_receiver.recv(ctx).await?
+        else {
+            unreachable!("unexpected message")
+        };
 
         // Emulate receiving block using external means.
         storage.queue_block(ctx, setup.blocks[0].clone()).await?;
@@ -128,7 +135,10 @@ impl Test for FilteringBlockRetrieval {
         let message = message_receiver.recv(ctx).await?;
         let io::OutputMessage::Network(SyncBlocksInputMessage::GetBlock {
             recipient, number, ..
-        }) = message;
+        }) = message
+        else {
+            unreachable!("unexpected message")
+        };
         assert_eq!(recipient, peer_key);
         assert_eq!(number, setup.blocks[1].number());
         assert!(message_receiver.try_recv().is_none());
@@ -182,7 +192,10 @@ impl Test for UpdatingPeerStateWithMultipleBlocks {
                 recipient,
                 number,
                 response,
-            }) = message_receiver.recv(ctx).await.unwrap();
+            }) = message_receiver.recv(ctx).await.unwrap()
+            else {
+                unreachable!("unexpected message")
+            };
 
             tracing::trace!("Received request for block #{number}");
             assert_eq!(recipient, peer_key);
@@ -259,7 +272,10 @@ impl Test for DisconnectingPeer {
                 recipient,
                 number,
                 ..
-            }) = &msg;
+            }) = &msg
+            else {
+                unreachable!("unexpected message")
+            };
             assert_eq!(recipient, &peer_key);
             assert_eq!(number, &setup.blocks[0].number());
         }
@@ -291,7 +307,10 @@ impl Test for DisconnectingPeer {
                 recipient,
                 number,
                 response,
-            }) = message;
+            }) = message
+            else {
+                unreachable!("unexpected message")
+            };
             assert_eq!(recipient, peer_key);
             assert!(responses.insert(number, response).is_none());
         }
@@ -331,7 +350,10 @@ impl Test for DisconnectingPeer {
             recipient,
             number,
             response,
-        }) = message;
+        }) = message
+        else {
+            unreachable!("unexpected message")
+        };
         assert_eq!(recipient, peer_key);
         assert_eq!(number, setup.blocks[0].number());
         response.send(make_response(setup.blocks.first())).unwrap();
@@ -424,7 +446,10 @@ impl Test for DownloadingBlocksInGaps {
                     recipient,
                     number,
                     response,
-                }) = message_receiver.recv(ctx).await?;
+                }) = message_receiver.recv(ctx).await?
+                else {
+                    unreachable!("unexpected message")
+                };
 
                 assert_eq!(recipient, peer_key);
                 assert!(number <= last_peer_block.unwrap().number());
@@ -487,7 +512,10 @@ impl Test for LimitingGetBlockConcurrency {
                 recipient,
                 number,
                 response,
-            }) = message_receiver.recv(ctx).await?;
+            }) = message_receiver.recv(ctx).await?
+            else {
+                unreachable!("unexpected message")
+            };
             assert_eq!(recipient, peer_key);
             assert!(message_responses.insert(number, response).is_none());
         }
@@ -508,7 +536,10 @@ impl Test for LimitingGetBlockConcurrency {
         // The actor should now request another block.
         let io::OutputMessage::Network(SyncBlocksInputMessage::GetBlock {
             recipient, number, ..
-        }) = message_receiver.recv(ctx).await?;
+        }) = message_receiver.recv(ctx).await?
+        else {
+            unreachable!("unexpected message")
+        };
         assert_eq!(recipient, peer_key);
         assert_eq!(number, setup.blocks[3].number());
 
@@ -520,3 +551,159 @@ impl Test for LimitingGetBlockConcurrency {
 async fn limiting_get_block_concurrency() {
     test_peer_states(LimitingGetBlockConcurrency).await;
 }
+
+#[derive(Debug, Default)]
+struct ResumingFromPersistedProgress {
+    store: Arc<in_memory::SyncProgressStore>,
+}
+
+#[async_trait]
+impl Test for ResumingFromPersistedProgress {
+    const BLOCK_COUNT: usize = 2;
+
+    fn config(&self) -> Config {
+        Config::new().with_progress_store(self.store.clone())
+    }
+
+    async fn test(self, ctx: &ctx::Ctx, handles: TestHandles) -> anyhow::Result<()> {
+        let TestHandles {
+            setup,
+            peer_states,
+            storage,
+            mut message_receiver,
+            ..
+        } = handles;
+
+        let rng = &mut ctx.rng();
+        let peer_key = rng.gen::<node::SecretKey>().public();
+        let progress = SyncProgress {
+            cursors: vec![FetchCursor {
+                peer: peer_key.clone(),
+                state: sync_state(&setup, setup.blocks.last()),
+            }],
+        };
+        self.store.set_progress(ctx, &progress).await?;
+        peer_states.restore(ctx).await?;
+        assert_eq!(peer_states.progress(), progress);
+
+        // The blocks are requested without the peer advertising its state again.
+        for _ in 0..Self::BLOCK_COUNT {
+            let io::OutputMessage::Network(SyncBlocksInputMessage::GetBlock {
+                recipient,
+                number,
+                response,
+            }) = message_receiver.recv(ctx).await?
+            else {
+                unreachable!("unexpected message")
+            };
+            assert_eq!(recipient, peer_key);
+            response.send(make_response(setup.block(number))).unwrap();
+        }
+        storage
+            .wait_until_persisted(ctx, setup.blocks.last().unwrap().number())
+            .await?;
+        // The peer has no more blocks missing in storage.
+        assert_eq!(peer_states.progress(), SyncProgress::default());
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn resuming_from_persisted_progress() {
+    test_peer_states(ResumingFromPersistedProgress::default()).await;
+}
+
+#[tokio::test]
+async fn resuming_after_restart() {
+    abort_on_panic();
+    let _guard = set_timeout(TEST_TIMEOUT);
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 4);
+    setup.push_blocks(rng, 4);
+    let progress_store = Arc::new(in_memory::SyncProgressStore::default());
+    let config = Config::new()
+        .with_progress_store(progress_store.clone())
+        .with_sleep_interval_for_get_block(BLOCK_SLEEP_INTERVAL)
+        .unwrap();
+    let peer_key = rng.gen::<node::SecretKey>().public();
+    let (store, store_run) = new_store(ctx, &setup.genesis).await;
+    let stored = setup.blocks[1].number();
+
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(store_run.run(ctx));
+
+        // Before the restart, the peer serves only the first 2 blocks.
+        let (message_sender, mut message_receiver) = channel::unbounded();
+        let peer_states = PeerStates::new(config.clone(), store.clone(), message_sender);
+        scope::run!(ctx, |ctx, s| async {
+            s.spawn_bg(async {
+                peer_states.run_block_fetcher(ctx).await.ok();
+                Ok(())
+            });
+            peer_states.update(&peer_key, sync_state(&setup, setup.blocks.last()))?;
+            let mut pending = vec![];
+            for _ in 0..setup.blocks.len() {
+                let io::OutputMessage::Network(SyncBlocksInputMessage::GetBlock {
+                    number,
+                    response,
+                    ..
+                }) = message_receiver.recv(ctx).await?
+                else {
+                    unreachable!("unexpected message")
+                };
+                if number <= stored {
+                    response.send(make_response(setup.block(number))).unwrap();
+                } else {
+                    pending.push(response);
+                }
+            }
+            store.wait_until_persisted(ctx, stored).await?;
+            progress_store
+                .set_progress(ctx, &peer_states.progress())
+                .await?;
+            Ok::<_, ctx::Error>(())
+        })
+        .await?;
+
+        // After the restart, the fetching resumes right after the stored blocks, from the
+        // restored state of the peer, which is kept until the peer gets connected.
+        let (message_sender, mut message_receiver) = channel::unbounded();
+        let peer_states = PeerStates::new(config.clone(), store.clone(), message_sender);
+        peer_states.restore(ctx).await?;
+        scope::run!(ctx, |ctx, s| async {
+            s.spawn_bg(async {
+                peer_states.run_block_fetcher(ctx).await.ok();
+                Ok(())
+            });
+            let mut failed = HashSet::new();
+            let mut served = 0;
+            while served < setup.blocks.len() - 2 {
+                let io::OutputMessage::Network(SyncBlocksInputMessage::GetBlock {
+                    recipient,
+                    number,
+                    response,
+                }) = message_receiver.recv(ctx).await?
+                else {
+                    unreachable!("unexpected message")
+                };
+                assert_eq!(recipient, peer_key);
+                assert!(number > stored, "block #{number} requested again");
+                // The first request for each block fails, since the peer is not connected yet.
+                if failed.insert(number) {
+                    drop(response);
+                } else {
+                    response.send(make_response(setup.block(number))).unwrap();
+                    served += 1;
+                }
+            }
+            store
+                .wait_until_persisted(ctx, setup.blocks.last().unwrap().number())
+                .await?;
+            Ok::<_, ctx::Error>(())
+        })
+        .await
+    })
+    .await
+    .unwrap();
+}
@@ -1,7 +1,7 @@
 //! Tests focused on handling peers providing fake information to the node.
 
 use super::*;
-use crate::tests::sync_state;
+use crate::tests::{make_response, sync_state};
 use zksync_consensus_roles::{validator, validator::testonly::Setup};
 use zksync_consensus_storage::testonly::new_store;
 
@@ -113,7 +113,10 @@ impl Test for PeerWithFakeBlock {
                 recipient,
                 number,
                 response,
-            }) = message_receiver.recv(ctx).await?;
+            }) = message_receiver.recv(ctx).await?
+            else {
+                unreachable!("unexpected message")
+            };
             assert_eq!(recipient, key);
             assert_eq!(number, setup.blocks[0].number());
             response.send(Ok(fake_block)).unwrap();
@@ -127,6 +130,13 @@ impl Test for PeerWithFakeBlock {
                 )
             })
             .await?;
+            // The peer is reported to the network.
+            let io::OutputMessage::Network(SyncBlocksInputMessage::Misbehaved { peer }) =
+                message_receiver.recv(ctx).await?
+            else {
+                unreachable!("unexpected message")
+            };
+            assert_eq!(peer, key);
         }
 
         // The invalid block must not be saved.
@@ -139,3 +149,302 @@ impl Test for PeerWithFakeBlock {
 async fn receiving_fake_block_from_peer() {
     test_peer_states(PeerWithFakeBlock).await;
 }
+
+#[derive(Debug)]
+struct PeerWithFakeJustification;
+
+#[async_trait]
+impl Test for PeerWithFakeJustification {
+    const BLOCK_COUNT: usize = 10;
+
+    fn config(&self) -> Config {
+        let mut cfg = Config::new().with_cross_check_peers(2).unwrap();
+        cfg.sleep_interval_for_get_block = BLOCK_SLEEP_INTERVAL;
+        cfg
+    }
+
+    async fn test(self, ctx: &ctx::Ctx, handles: TestHandles) -> anyhow::Result<()> {
+        let TestHandles {
+            setup,
+            peer_states,
+            storage,
+            mut message_receiver,
+            mut events_receiver,
+            ..
+        } = handles;
+
+        let rng = &mut ctx.rng();
+        let honest = rng.gen::<node::SecretKey>().public();
+        let fake = rng.gen::<node::SecretKey>().public();
+        for key in [&honest, &fake] {
+            peer_states
+                .update(key, sync_state(&setup, setup.blocks.first()))
+                .unwrap();
+        }
+        let fake_justification = {
+            let mut s = Setup::new(rng, 4);
+            s.push_blocks(rng, 1);
+            s.blocks[0].justification.clone()
+        };
+
+        // Whichever peer is chosen to serve the block, the fake one is detected
+        // by the cross-check and the block is fetched from the honest one.
+        let mut reported = false;
+        loop {
+            let io::OutputMessage::Network(message) = message_receiver.recv(ctx).await?;
+            match message {
+                SyncBlocksInputMessage::GetJustification {
+                    recipient,
+                    number,
+                    response,
+                } => {
+                    assert_eq!(number, setup.blocks[0].number());
+                    let justification = if recipient == fake {
+                        fake_justification.clone()
+                    } else {
+                        setup.blocks[0].justification.clone()
+                    };
+                    response.send(Ok(justification)).unwrap();
+                }
+                SyncBlocksInputMessage::Misbehaved { peer } => {
+                    assert_eq!(peer, fake);
+                    reported = true;
+                }
+                SyncBlocksInputMessage::GetBlock {
+                    recipient,
+                    number,
+                    response,
+                } => {
+                    assert_eq!(recipient, honest);
+                    assert_eq!(number, setup.blocks[0].number());
+                    response.send(Ok(setup.blocks[0].clone())).unwrap();
+                    break;
+                }
+                SyncBlocksInputMessage::SamplePayload { .. } => unreachable!(),
+            }
+        }
+        assert!(reported);
+        wait_for_event(ctx, &mut events_receiver, |ev| {
+            matches!(ev, PeerStateEvent::GotBlock(number) if number == setup.blocks[0].number())
+        })
+        .await?;
+        storage
+            .wait_until_persisted(ctx, setup.blocks[0].number())
+            .await?;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn receiving_fake_justification_from_peer() {
+    test_peer_states(PeerWithFakeJustification).await;
+}
+
+/// Samples of the `payload`.
+fn sample(payload: &validator::Payload, samples: &[u64]) -> Vec<PayloadSample> {
+    let chunks = payload.chunks();
+    let indices: Vec<_> = samples
+        .iter()
+        .map(|s| (s % chunks.len() as u64) as usize)
+        .collect();
+    let proofs = payload.chunk_proofs(&indices).unwrap();
+    indices
+        .iter()
+        .zip(proofs)
+        .map(|(i, proof)| PayloadSample {
+            chunk: chunks[*i].to_vec(),
+            proof,
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct PeerWithoutPayload;
+
+#[async_trait]
+impl Test for PeerWithoutPayload {
+    const BLOCK_COUNT: usize = 10;
+    const PROTOCOL_VERSION: validator::ProtocolVersion = validator::ProtocolVersion::PAYLOAD_ROOT;
+
+    fn config(&self) -> Config {
+        let mut cfg = Config::new().with_payload_sampling(4).unwrap();
+        cfg.sleep_interval_for_get_block = BLOCK_SLEEP_INTERVAL;
+        cfg
+    }
+
+    async fn test(self, ctx: &ctx::Ctx, handles: TestHandles) -> anyhow::Result<()> {
+        let TestHandles {
+            setup,
+            peer_states,
+            storage,
+            mut message_receiver,
+            mut events_receiver,
+            ..
+        } = handles;
+
+        let rng = &mut ctx.rng();
+        let honest = rng.gen::<node::SecretKey>().public();
+        let fake = rng.gen::<node::SecretKey>().public();
+        let block = &setup.blocks[0];
+        peer_states
+            .update(&fake, sync_state(&setup, Some(block)))
+            .unwrap();
+
+        // The fake peer serves the chunks of a different payload, which is detected
+        // by the sampling. Then the block is fetched from the honest peer.
+        loop {
+            let io::OutputMessage::Network(message) = message_receiver.recv(ctx).await?;
+            match message {
+                SyncBlocksInputMessage::GetJustification {
+                    number, response, ..
+                } => {
+                    assert_eq!(number, block.number());
+                    response.send(Ok(block.justification.clone())).unwrap();
+                }
+                SyncBlocksInputMessage::SamplePayload {
+                    recipient,
+                    number,
+                    samples,
+                    response,
+                } => {
+                    assert_eq!(number, block.number());
+                    assert_eq!(samples.len(), 4);
+                    let payload = match recipient == fake {
+                        true => rng.gen(),
+                        false => block.payload.clone(),
+                    };
+                    response.send(Ok(sample(&payload, &samples))).unwrap();
+                }
+                SyncBlocksInputMessage::Misbehaved { peer } => {
+                    assert_eq!(peer, fake);
+                    peer_states
+                        .update(&honest, sync_state(&setup, Some(block)))
+                        .unwrap();
+                }
+                SyncBlocksInputMessage::GetBlock {
+                    recipient,
+                    number,
+                    response,
+                } => {
+                    // The payload is downloaded only from the peer which has it.
+                    assert_eq!(recipient, honest);
+                    assert_eq!(number, block.number());
+                    response.send(Ok(block.clone())).unwrap();
+                    break;
+                }
+            }
+        }
+        wait_for_event(
+            ctx,
+            &mut events_receiver,
+            |ev| matches!(ev, PeerStateEvent::GotBlock(number) if number == block.number()),
+        )
+        .await?;
+        storage.wait_until_persisted(ctx, block.number()).await?;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn sampling_payload_from_peer_without_it() {
+    test_peer_states(PeerWithoutPayload).await;
+}
+
+#[derive(Debug)]
+struct SamplingAheadOfDownload;
+
+#[async_trait]
+impl Test for SamplingAheadOfDownload {
+    const BLOCK_COUNT: usize = 10;
+    const PROTOCOL_VERSION: validator::ProtocolVersion = validator::ProtocolVersion::PAYLOAD_ROOT;
+
+    fn config(&self) -> Config {
+        Config::new()
+            .with_payload_sampling(4)
+            .unwrap()
+            .with_sample_ahead(Self::BLOCK_COUNT)
+            .unwrap()
+            .with_max_concurrent_blocks(2)
+            .unwrap()
+    }
+
+    async fn test(self, ctx: &ctx::Ctx, handles: TestHandles) -> anyhow::Result<()> {
+        let TestHandles {
+            setup,
+            peer_states,
+            storage,
+            mut message_receiver,
+            ..
+        } = handles;
+
+        let rng = &mut ctx.rng();
+        let peer = rng.gen::<node::SecretKey>().public();
+        peer_states
+            .update(&peer, sync_state(&setup, setup.blocks.last()))
+            .unwrap();
+
+        // All the blocks are sampled, while only the first ones are being downloaded.
+        let mut sampled = HashSet::new();
+        let mut downloads = vec![];
+        while sampled.len() < Self::BLOCK_COUNT {
+            let io::OutputMessage::Network(message) = message_receiver.recv(ctx).await?;
+            match message {
+                SyncBlocksInputMessage::GetJustification {
+                    number, response, ..
+                } => {
+                    response
+                        .send(Ok(setup.block(number).unwrap().justification.clone()))
+                        .unwrap();
+                }
+                SyncBlocksInputMessage::SamplePayload {
+                    number,
+                    samples,
+                    response,
+                    ..
+                } => {
+                    let payload = &setup.block(number).unwrap().payload;
+                    response.send(Ok(sample(payload, &samples))).unwrap();
+                    sampled.insert(number);
+                }
+                SyncBlocksInputMessage::GetBlock {
+                    number, response, ..
+                } => downloads.push((number, response)),
+                SyncBlocksInputMessage::Misbehaved { peer } => {
+                    unreachable!("{peer:?} reported as misbehaved")
+                }
+            }
+        }
+        assert!(
+            downloads.len() <= 2,
+            "{} blocks downloaded",
+            downloads.len()
+        );
+
+        // Then the download of the sampled blocks catches up.
+        let mut downloaded = downloads.len();
+        for (number, response) in downloads {
+            response.send(make_response(setup.block(number))).unwrap();
+        }
+        while downloaded < Self::BLOCK_COUNT {
+            let io::OutputMessage::Network(SyncBlocksInputMessage::GetBlock {
+                number,
+                response,
+                ..
+            }) = message_receiver.recv(ctx).await?
+            else {
+                unreachable!("unexpected message")
+            };
+            response.send(make_response(setup.block(number))).unwrap();
+            downloaded += 1;
+        }
+        storage
+            .wait_until_persisted(ctx, setup.blocks.last().unwrap().number())
+            .await?;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn sampling_ahead_of_download() {
+    test_peer_states(SamplingAheadOfDownload).await;
+}
@@ -43,6 +43,8 @@ trait Test: fmt::Debug + Send + Sync {
     const BLOCK_COUNT: usize;
     // TODO: move this to genesis
     const GENESIS_BLOCK_NUMBER: usize = 0;
+    /// Protocol version of the genesis.
+    const PROTOCOL_VERSION: validator::ProtocolVersion = validator::ProtocolVersion::EARLIEST;
 
     fn config(&self) -> Config {
         Config::new()
@@ -69,6 +71,7 @@ async fn test_peer_states<T: Test>(test: T) {
     let ctx = &ctx::test_root(&clock);
     let rng = &mut ctx.rng();
     let mut setup = validator::testonly::Setup::new(rng, 4);
+    setup.set_protocol_version(T::PROTOCOL_VERSION);
     setup.push_blocks(rng, T::BLOCK_COUNT);
     let (store, store_run) = new_store(ctx, &setup.genesis).await;
     test.initialize_storage(ctx, store.as_ref(), &setup).await;
@@ -93,6 +96,10 @@ async fn test_peer_states<T: Test>(test: T) {
             peer_states.run_block_fetcher(ctx).await.ok();
             Ok(())
         });
+        s.spawn_bg(async {
+            peer_states.run_payload_sampler(ctx).await.ok();
+            Ok(())
+        });
         test.test(ctx, test_handles).await
     })
     .await
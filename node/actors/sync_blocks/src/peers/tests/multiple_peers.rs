@@ -36,7 +36,10 @@ impl Test for RequestingBlocksFromTwoPeers {
             recipient,
             number: first_peer_block_number,
             response: first_peer_response,
-        }) = message_receiver.recv(ctx).await?;
+        }) = message_receiver.recv(ctx).await?
+        else {
+            unreachable!("unexpected message")
+        };
         assert_eq!(recipient, first_peer);
         assert!(setup.blocks[0..=1]
             .iter()
@@ -53,7 +56,10 @@ impl Test for RequestingBlocksFromTwoPeers {
             recipient,
             number: second_peer_block_number,
             response: second_peer_response,
-        }) = message_receiver.recv(ctx).await?;
+        }) = message_receiver.recv(ctx).await?
+        else {
+            unreachable!("unexpected message")
+        };
         assert_eq!(recipient, second_peer);
         assert!(setup.blocks[0..=1]
             .iter()
@@ -85,7 +91,10 @@ impl Test for RequestingBlocksFromTwoPeers {
             recipient,
             number: first_peer_block_number,
             response: first_peer_response,
-        }) = message_receiver.recv(ctx).await?;
+        }) = message_receiver.recv(ctx).await?
+        else {
+            unreachable!("unexpected message")
+        };
         assert_eq!(recipient, first_peer);
         assert!(setup.blocks[2..=3]
             .iter()
@@ -108,7 +117,10 @@ impl Test for RequestingBlocksFromTwoPeers {
             recipient,
             number: first_peer_block_number,
             response: first_peer_response,
-        }) = message_receiver.recv(ctx).await?;
+        }) = message_receiver.recv(ctx).await?
+        else {
+            unreachable!("unexpected message")
+        };
         assert_eq!(recipient, first_peer);
         assert!(setup.blocks[2..=3]
             .iter()
@@ -226,7 +238,12 @@ impl Test for RequestingBlocksFromMultiplePeers {
         scope::run!(ctx, |ctx, s| async {
             // Announce peer states.
             for (peer_key, peer) in peers {
-                peer_states.update(peer_key, sync_state(&setup, setup.blocks.get(peer.last_block))).unwrap();
+                peer_states
+                    .update(
+                        peer_key,
+                        sync_state(&setup, setup.blocks.get(peer.last_block)),
+                    )
+                    .unwrap();
             }
 
             s.spawn_bg(async {
@@ -240,7 +257,10 @@ impl Test for RequestingBlocksFromMultiplePeers {
                         recipient,
                         number,
                         response,
-                    }) = message;
+                    }) = message
+                    else {
+                        unreachable!("unexpected message")
+                    };
 
                     tracing::trace!("Block #{number} requested from {recipient:?}");
                     assert!(number <= setup.blocks[peers[&recipient].last_block].number());
@@ -297,11 +317,15 @@ impl Test for RequestingBlocksFromMultiplePeers {
                         );
                         clock.advance(BLOCK_SLEEP_INTERVAL);
                     }
-                    PeerStateEvent::RpcFailed{..} | PeerStateEvent::PeerDropped(_) => { /* Do nothing */ }
+                    PeerStateEvent::RpcFailed { .. }
+                    | PeerStateEvent::PeerDropped(_)
+                    | PeerStateEvent::Misbehaved(_) => { /* Do nothing */ }
                 }
             }
 
-            storage.wait_until_persisted(ctx,setup.blocks.last().unwrap().header().number).await?;
+            storage
+                .wait_until_persisted(ctx, setup.blocks.last().unwrap().header().number)
+                .await?;
             Ok(())
         })
         .await
@@ -343,3 +367,41 @@ async fn requesting_blocks_with_unreliable_peers(
     test.peer_behavior[1].last_block_to_return = 15;
     test_peer_states(test).await;
 }
+
+#[tokio::test]
+async fn requesting_blocks_prefers_peers_with_lower_rtt() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 4);
+    setup.push_blocks(rng, 2);
+    let (store, _store_run) = new_store(ctx, &setup.genesis).await;
+    let (message_sender, _message_receiver) = channel::unbounded();
+    let peer_states = PeerStates::new(Config::new(), store, message_sender);
+
+    let peers: Vec<_> = (0..3)
+        .map(|_| rng.gen::<node::SecretKey>().public())
+        .collect();
+    for peer in &peers {
+        peer_states
+            .update(peer, sync_state(&setup, setup.blocks.get(1)))
+            .unwrap();
+    }
+    // `peers[0]` has unknown RTT, so it goes last.
+    peer_states.update_rtt(&peers[1], time::Duration::milliseconds(50));
+    peer_states.update_rtt(&peers[2], time::Duration::milliseconds(10));
+
+    let number = setup.blocks[0].number();
+    for _ in 0..10 {
+        let (peer, _permit) = peer_states.try_acquire_peer_permit(rng, number).unwrap();
+        assert_eq!(peer, peers[2]);
+    }
+    // RTT of a peer can change. Peers with a similar RTT share the load.
+    peer_states.update_rtt(&peers[1], time::Duration::milliseconds(15));
+    let mut picked = HashSet::new();
+    for _ in 0..100 {
+        let (peer, _permit) = peer_states.try_acquire_peer_permit(rng, number).unwrap();
+        picked.insert(peer);
+    }
+    assert_eq!(picked, [peers[1].clone(), peers[2].clone()].into());
+}
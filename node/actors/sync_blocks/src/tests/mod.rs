@@ -14,6 +14,7 @@ pub(crate) fn sync_state(setup: &Setup, last: Option<&validator::FinalBlock>) ->
     BlockStoreState {
         first: setup.genesis.fork.first_block,
         last: last.map(|b| b.justification.clone()),
+        gaps: vec![],
     }
 }
 
@@ -218,6 +218,21 @@ impl Ctx {
         self.child((self.now() + d).into())
     }
 
+    /// Constructs a context with deadline `now() + d`, which is NOT canceled
+    /// together with `self`. It shares the clock with `self`.
+    /// Use it ONLY for bounded cleanup work which has to be completed after `self`
+    /// gets canceled (for example flushing buffered data to disk on shutdown).
+    pub fn detached_with_timeout(&self, d: time::Duration) -> Self {
+        let root = Self(Arc::new(Inner {
+            clock: self.0.clock.clone(),
+            rng_provider: self.0.rng_provider.split(),
+            canceled: Arc::new(signal::Once::new()),
+            deadline: time::Deadline::Infinite,
+            _parent: None,
+        }));
+        root.with_timeout(d)
+    }
+
     /// Current time according to the monotone clock.
     pub fn now(&self) -> time::Instant {
         self.0.clock.now()
@@ -88,3 +88,17 @@ async fn channel_send() {
     // Send should succeed, even if recv has been dropped.
     send.send(3);
 }
+
+#[tokio::test]
+async fn test_detached_with_timeout() {
+    testonly::abort_on_panic();
+    let clock = ManualClock::new();
+    let ctx = &test_root(&clock);
+    let sec = time::Duration::SECOND;
+    let canceled = &ctx.with_timeout(time::Duration::ZERO);
+    canceled.canceled().await;
+    let detached = &canceled.detached_with_timeout(10 * sec);
+    assert!(detached.is_active());
+    clock.advance(11 * sec);
+    detached.canceled().await;
+}
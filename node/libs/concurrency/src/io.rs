@@ -38,6 +38,18 @@ pub async fn write_all<S: AsyncWrite + Unpin>(
     ctx.wait(stream.write_all(buf)).await
 }
 
+/// Writes the whole content of `buf`, or returns an (ctx::Canceled or io::Error) error.
+/// Uses vectored writes if the stream supports them, so that the chunks of a chained `buf`
+/// don't have to be copied into a contiguous buffer first.
+/// In case of error some bytes might have been already written (and consumed from `buf`).
+pub async fn write_all_buf<S: AsyncWrite + Unpin, B: bytes::Buf>(
+    ctx: &ctx::Ctx,
+    stream: &mut S,
+    buf: &mut B,
+) -> ctx::OrCanceled<Result<()>> {
+    ctx.wait(stream.write_all_buf(buf)).await
+}
+
 /// Flushes the write buffer.
 pub async fn flush<S: AsyncWrite + Unpin>(
     ctx: &ctx::Ctx,
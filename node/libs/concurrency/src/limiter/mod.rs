@@ -6,7 +6,7 @@ use std::sync::Mutex;
 mod tests;
 
 /// Rate at which limiter should refresh the permits.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Rate {
     /// Maximal number of permits in a limiter.
     /// Whenever limiter already has `burst` fresh permits,
@@ -212,4 +212,39 @@ impl Limiter {
             limiter: self,
         })
     }
+
+    /// Acquires reservation for `permits` permits from the rate limiter,
+    /// if they are available immediately. Returns `None` otherwise
+    /// (also in case some `acquire()` call is already waiting for permits).
+    /// Useful for rejecting the excess load, rather than delaying it.
+    pub fn try_acquire<'a>(&'a self, ctx: &'a ctx::Ctx, permits: usize) -> Option<Permit<'a>> {
+        if self.burst < permits {
+            return None;
+        }
+        if self.refresh <= 0 {
+            return Some(Permit {
+                permits: 0,
+                ctx,
+                limiter: self,
+            });
+        }
+        // Don't overtake the pending acquire() calls.
+        let _acquire = self.acquire.try_lock().ok()?;
+        let refresh_ticks = (ctx.now() - self.start).whole_nanoseconds() / self.refresh;
+        let mut ok = false;
+        self.state.lock().unwrap().send_if_modified(|s| {
+            s.advance(refresh_ticks, self);
+            if s.permits - s.reserved >= permits {
+                s.reserved += permits;
+                ok = true;
+            }
+            // Nobody waits anyway.
+            false
+        });
+        ok.then_some(Permit {
+            permits,
+            ctx,
+            limiter: self,
+        })
+    }
 }
@@ -141,3 +141,37 @@ async fn delayed_permit_consumption() {
     .await
     .unwrap();
 }
+
+#[tokio::test]
+async fn try_acquire() {
+    testonly::abort_on_panic();
+    let clock = &ctx::ManualClock::new();
+    let ctx = &ctx::test_root(clock);
+    let rate = Rate {
+        burst: 3,
+        refresh: time::Duration::seconds(2),
+    };
+    let l = Limiter::new(ctx, rate);
+
+    // `burst` permits are available immediately.
+    for _ in 0..rate.burst {
+        assert!(l.try_acquire(ctx, 1).is_some());
+    }
+    assert!(l.try_acquire(ctx, 1).is_none());
+    // Too many permits are never available.
+    assert!(l.try_acquire(ctx, rate.burst + 1).is_none());
+
+    // Permits get refreshed over time.
+    clock.advance(rate.refresh);
+    assert!(l.try_acquire(ctx, 2).is_none());
+    assert!(l.try_acquire(ctx, 1).is_some());
+    assert!(l.try_acquire(ctx, 1).is_none());
+
+    // Reserved permits are not available until consumed and refreshed.
+    clock.advance(rate.refresh * 2);
+    let permit = l.try_acquire(ctx, 1).unwrap();
+    assert!(l.try_acquire(ctx, 1).is_some());
+    assert!(l.try_acquire(ctx, 1).is_none());
+    drop(permit);
+    assert!(l.try_acquire(ctx, 1).is_none());
+}
@@ -1,5 +1,7 @@
-//! Prunable, multi-producer, single-consumer, unbounded FIFO queue for communicating between asynchronous tasks.
+//! Prunable, multi-producer, single-consumer FIFO queue for communicating between asynchronous tasks.
 //! The pruning takes place whenever a new value is sent, based on a specified predicate.
+//! The queue is unbounded, unless created with [`bounded`], in which case the values are shed
+//! on overflow, based on their priority.
 //!
 //! The separation of [`Sender`] and [`Receiver`] is employed primarily because [`Receiver`] requires
 //! a mutable reference to the signaling channel, unlike [`Sender`], hence making it undesirable to
@@ -24,6 +26,31 @@ mod tests;
 /// based on a newly sent value (represented by the second `T`).
 pub fn channel<T>(
     pruning_predicate: impl 'static + Sync + Send + Fn(&T, &T) -> bool,
+) -> (Sender<T>, Receiver<T>) {
+    new(pruning_predicate, None)
+}
+
+/// Creates a channel like [`channel`], which buffers at most `capacity` (at least 1) values.
+/// When a value is sent while the buffer is full (after pruning), the pending value with
+/// the lowest `priority` (the oldest one among equal priorities) is shed to make room,
+/// if its priority is lower than the priority of the new value. Otherwise the new value is dropped.
+pub fn bounded<T>(
+    capacity: usize,
+    pruning_predicate: impl 'static + Sync + Send + Fn(&T, &T) -> bool,
+    priority: impl 'static + Sync + Send + Fn(&T) -> u64,
+) -> (Sender<T>, Receiver<T>) {
+    new(
+        pruning_predicate,
+        Some(Bound {
+            capacity: capacity.max(1),
+            priority: Box::new(priority),
+        }),
+    )
+}
+
+fn new<T>(
+    pruning_predicate: impl 'static + Sync + Send + Fn(&T, &T) -> bool,
+    bound: Option<Bound<T>>,
 ) -> (Sender<T>, Receiver<T>) {
     let buf = VecDeque::new();
     let (send, recv) = watch::channel(buf);
@@ -33,6 +60,7 @@ pub fn channel<T>(
     let send = Sender {
         shared: shared.clone(),
         pruning_predicate: Box::new(pruning_predicate),
+        bound,
     };
 
     let recv = Receiver {
@@ -47,23 +75,71 @@ struct Shared<T> {
     send: watch::Sender<VecDeque<T>>,
 }
 
+/// Capacity of a bounded channel.
+#[allow(clippy::type_complexity)]
+struct Bound<T> {
+    capacity: usize,
+    priority: Box<dyn Sync + Send + Fn(&T) -> u64>,
+}
+
+/// Outcome of [`Sender::send`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendResult {
+    /// Value has been buffered.
+    Sent,
+    /// Value has been buffered, in place of a lower priority pending value.
+    Shed,
+    /// Buffer is full of values with at least the same priority, so the value has been dropped.
+    Dropped,
+}
+
 /// Sends values to the associated [`Receiver`].
-/// Instances are created by the [`channel`] function.
+/// Instances are created by the [`channel`] and [`bounded`] functions.
 #[allow(clippy::type_complexity)]
 pub struct Sender<T> {
     shared: Arc<Shared<T>>,
     pruning_predicate: Box<dyn Sync + Send + Fn(&T, &T) -> bool>,
+    bound: Option<Bound<T>>,
 }
 
 impl<T> Sender<T> {
     /// Sends a value.
     /// This initiates the pruning procedure which operates in O(N) time complexity
     /// on the buffer of pending values.
-    pub fn send(&self, value: T) {
-        self.shared.send.send_modify(|buf| {
+    pub fn send(&self, value: T) -> SendResult {
+        let mut res = SendResult::Sent;
+        self.shared.send.send_if_modified(|buf| {
             buf.retain(|pending_value| !(self.pruning_predicate)(pending_value, &value));
+            if let Some(bound) = &self.bound {
+                if buf.len() >= bound.capacity {
+                    let (i, lowest) = buf
+                        .iter()
+                        .map(|v| (bound.priority)(v))
+                        .enumerate()
+                        .min_by_key(|(_, p)| *p)
+                        .unwrap();
+                    if lowest >= (bound.priority)(&value) {
+                        res = SendResult::Dropped;
+                        return false;
+                    }
+                    buf.remove(i);
+                    res = SendResult::Shed;
+                }
+            }
             buf.push_back(value);
+            true
         });
+        res
+    }
+
+    /// Number of the pending values.
+    pub fn len(&self) -> usize {
+        self.shared.send.borrow().len()
+    }
+
+    /// Checks if there are no pending values.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 }
 
@@ -74,7 +150,7 @@ impl<T> fmt::Debug for Sender<T> {
 }
 
 /// Receives values from the associated [`Sender`].
-/// Instances are created by the [`channel`] function.
+/// Instances are created by the [`channel`] and [`bounded`] functions.
 pub struct Receiver<T> {
     shared: Arc<Shared<T>>,
     recv: watch::Receiver<VecDeque<T>>,
@@ -92,6 +168,24 @@ impl<T> Receiver<T> {
         // `None` is unexpected because we waited for new values, and there's only a single receiver.
         Ok(value.unwrap())
     }
+
+    /// Receives up to `max` values from this receiver.
+    /// If there are no messages in the buffer, this method will hang until a message is sent.
+    pub async fn recv_many(&mut self, ctx: &ctx::Ctx, max: usize) -> ctx::OrCanceled<Vec<T>> {
+        sync::wait_for(ctx, &mut self.recv, |buf| !buf.is_empty()).await?;
+
+        let mut values = vec![];
+        self.shared.send.send_modify(|buf| {
+            let n = buf.len().min(max.max(1));
+            values.extend(buf.drain(..n));
+        });
+        Ok(values)
+    }
+
+    /// Calls `f` with the values currently buffered in the channel, without receiving them.
+    pub fn inspect<R>(&self, f: impl FnOnce(&VecDeque<T>) -> R) -> R {
+        f(&self.recv.borrow())
+    }
 }
 
 impl<T> fmt::Debug for Receiver<T> {
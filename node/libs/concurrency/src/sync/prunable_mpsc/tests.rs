@@ -66,3 +66,41 @@ async fn test_prunable_mpsc() {
     .await;
     assert_eq!(Ok(()), res);
 }
+
+#[tokio::test]
+async fn test_recv_many() {
+    crate::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let (send, mut recv) = channel(|_: &usize, _: &usize| false);
+    for i in 0..5 {
+        send.send(i);
+    }
+    assert_eq!(vec![0, 1, 2], recv.recv_many(ctx, 3).await.unwrap());
+    assert_eq!(vec![3, 4], recv.recv_many(ctx, 3).await.unwrap());
+    send.send(5);
+    assert_eq!(vec![5], recv.recv_many(ctx, 3).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_bounded() {
+    crate::testonly::abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+
+    // Values are (key, priority). Values with the same key are pruned.
+    let (send, mut recv) = bounded(3, |a: &(u64, u64), b: &(u64, u64)| a.0 == b.0, |v| v.1);
+    assert_eq!(SendResult::Sent, send.send((0, 5)));
+    assert_eq!(SendResult::Sent, send.send((1, 3)));
+    assert_eq!(SendResult::Sent, send.send((2, 3)));
+    // Pruning makes room for the new value.
+    assert_eq!(SendResult::Sent, send.send((0, 4)));
+    // Values with lower or equal priority are dropped.
+    assert_eq!(SendResult::Dropped, send.send((3, 3)));
+    // Values with higher priority replace the oldest lowest priority value.
+    assert_eq!(SendResult::Shed, send.send((4, 6)));
+    assert_eq!(3, send.len());
+    assert_eq!(
+        vec![(2, 3), (0, 4), (4, 6)],
+        recv.recv_many(ctx, 10).await.unwrap()
+    );
+    assert!(send.is_empty());
+}
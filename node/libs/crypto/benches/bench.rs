@@ -20,6 +20,28 @@ fn bench_bn254(c: &mut Criterion) {
         });
     });
 
+    let sks: Vec<SecretKey> = repeat_with(|| rng.gen::<SecretKey>()).take(100).collect();
+    let pks: Vec<PublicKey> = sks.iter().map(|k| k.public()).collect();
+    let msgs: Vec<[u8; 32]> = repeat_with(|| rng.gen()).take(4).collect();
+    let items: Vec<_> = sks
+        .iter()
+        .zip(&pks)
+        .enumerate()
+        .map(|(i, (sk, pk))| (&msgs[i % 4][..], pk, sk.sign(&msgs[i % 4])))
+        .collect();
+    group.bench_function("100 sig individual verification", |b| {
+        b.iter(|| {
+            for (msg, pk, sig) in &items {
+                sig.verify(msg, pk).unwrap();
+            }
+        });
+    });
+    group.bench_function("100 sig batch verification", |b| {
+        b.iter(|| {
+            Signature::verify_batch(items.iter().map(|(msg, pk, sig)| (*msg, *pk, sig))).unwrap()
+        });
+    });
+
     group.finish();
 }
 
@@ -39,8 +61,53 @@ fn bench_bls12_381(c: &mut Criterion) {
         });
     });
 
+    let sks: Vec<SecretKey> = repeat_with(|| SecretKey::generate(rng.gen()))
+        .take(100)
+        .collect();
+    let pks: Vec<PublicKey> = sks.iter().map(|k| k.public()).collect();
+    let msgs: Vec<[u8; 32]> = repeat_with(|| rng.gen()).take(100).collect();
+    let sigs: Vec<Signature> = sks.iter().zip(&msgs).map(|(k, m)| k.sign(m)).collect();
+    group.bench_function("100 sig individual verification", |b| {
+        b.iter(|| {
+            for ((msg, pk), sig) in msgs.iter().zip(&pks).zip(&sigs) {
+                sig.verify(msg, pk).unwrap();
+            }
+        });
+    });
+    group.bench_function("100 sig batch verification", |b| {
+        b.iter(|| {
+            let items = msgs.iter().zip(&pks).zip(&sigs);
+            Signature::verify_batch(items.map(|((msg, pk), sig)| (&msg[..], pk, sig))).unwrap()
+        });
+    });
+
+    group.finish();
+}
+
+fn bench_ed25519(c: &mut Criterion) {
+    use zksync_consensus_crypto::ed25519::{PublicKey, SecretKey, Signature};
+    let mut rng = rand::thread_rng();
+    let mut group = c.benchmark_group("ed25519");
+    let sks: Vec<SecretKey> = repeat_with(|| rng.gen::<SecretKey>()).take(100).collect();
+    let pks: Vec<PublicKey> = sks.iter().map(|k| k.public()).collect();
+    let msgs: Vec<[u8; 32]> = repeat_with(|| rng.gen()).take(100).collect();
+    let sigs: Vec<Signature> = sks.iter().zip(&msgs).map(|(k, m)| k.sign(m)).collect();
+    group.bench_function("100 sig individual verification", |b| {
+        b.iter(|| {
+            for ((msg, pk), sig) in msgs.iter().zip(&pks).zip(&sigs) {
+                pk.verify(msg, sig).unwrap();
+            }
+        });
+    });
+    group.bench_function("100 sig batch verification", |b| {
+        b.iter(|| {
+            let items = msgs.iter().zip(&pks).zip(&sigs);
+            Signature::verify_batch(items.map(|((msg, pk), sig)| (&msg[..], pk, sig))).unwrap()
+        });
+    });
+
     group.finish();
 }
 
-criterion_group!(benches, bench_bls12_381, bench_bn254);
+criterion_group!(benches, bench_bls12_381, bench_bn254, bench_ed25519);
 criterion_main!(benches);
@@ -0,0 +1,35 @@
+//! Wrappers for the Blake2b hash algorithm with a 256-bit output.
+use blake2::{
+    digest::{consts::U32, Update},
+    Blake2b, Digest as _,
+};
+
+#[cfg(test)]
+mod test;
+
+/// Incremental Blake2b-256 hasher. Has the same interface as [`crate::keccak256::Hasher`],
+/// so that the protocol versions can select either of them.
+#[derive(Clone, Default)]
+pub struct Hasher(Blake2b<U32>);
+
+impl Hasher {
+    /// Hasher of the messages in the given `domain`.
+    /// The domain is prefixed with its length, so that distinct (domain, message) pairs
+    /// are never hashed as the same input.
+    pub fn with_domain(domain: &[u8]) -> Self {
+        let mut hasher = Self::default();
+        hasher.update(&u32::try_from(domain.len()).unwrap().to_be_bytes());
+        hasher.update(domain);
+        hasher
+    }
+
+    /// Feeds the next part of the message to the hasher.
+    pub fn update(&mut self, data: &[u8]) {
+        Update::update(&mut self.0, data);
+    }
+
+    /// Computes the hash of the whole message.
+    pub fn finalize(self) -> [u8; 32] {
+        self.0.finalize().into()
+    }
+}
@@ -0,0 +1,43 @@
+#[test]
+fn test_blake2b256() {
+    use crate::blake2b256::Hasher;
+
+    // Test vectors obtained with Python's `hashlib.blake2b(msg, digest_size=32)`.
+    let test_vectors: Vec<(&[u8], &str)> = vec![
+        (
+            b"testing",
+            "99397ff32ae348b8b6536d5c213f343d7e9fdeaa10e8a23a9f90ab21a1658565",
+        ),
+        (
+            b"",
+            "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8",
+        ),
+        (
+            &[0x12, 0x34, 0x56],
+            "4f2e042d9b33f4100770d978547493190e7ef752ac0a74b1abcca18dd58e4d3b",
+        ),
+    ];
+    for (input, expected_hash) in test_vectors {
+        let mut hasher = Hasher::default();
+        hasher.update(input);
+        assert_eq!(expected_hash, hex::encode(hasher.finalize()));
+    }
+}
+
+#[test]
+fn test_blake2b256_hasher_with_domain() {
+    use crate::blake2b256::Hasher;
+
+    let mut hasher = Hasher::with_domain(b"domain");
+    hasher.update(b"message");
+    assert_eq!(
+        "06f00ffd4319a5a7b630c7e99e33a66a904b61d7f0432f81b4242ec1c5fa80f2",
+        hex::encode(hasher.finalize())
+    );
+    // The domain boundary is part of the hashed input.
+    let mut a = Hasher::with_domain(b"ab");
+    a.update(b"c");
+    let mut b = Hasher::with_domain(b"a");
+    b.update(b"bc");
+    assert!(a.finalize() != b.finalize());
+}
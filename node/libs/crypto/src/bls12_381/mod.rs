@@ -3,7 +3,8 @@
 
 use crate::ByteFmt;
 use anyhow::anyhow;
-use blst::{min_pk as bls, BLST_ERROR};
+use blst::{blst_scalar, min_pk as bls, BLST_ERROR};
+use rand::Rng as _;
 use std::collections::BTreeMap;
 
 #[cfg(test)]
@@ -95,6 +96,52 @@ impl Signature {
             err => Err(Error::SignatureVerification(err)),
         }
     }
+
+    /// Verifies a batch of signatures, each against its own message and public key.
+    ///
+    /// The signatures are combined with random 64-bit weights and checked with a single
+    /// multi-pairing, which is much cheaper than verifying them one by one. The result
+    /// doesn't tell which of the signatures are invalid.
+    pub fn verify_batch<'a>(
+        items: impl Iterator<Item = (&'a [u8], &'a PublicKey, &'a Signature)>,
+    ) -> Result<(), Error> {
+        let mut msgs = vec![];
+        let mut pks = vec![];
+        let mut sigs = vec![];
+        for (msg, pk, sig) in items {
+            msgs.push(msg);
+            pks.push(&pk.0);
+            sigs.push(&sig.0);
+        }
+        // `blst` fails on an empty batch.
+        if msgs.is_empty() {
+            return Ok(());
+        }
+        let rng = &mut rand::thread_rng();
+        let weights: Vec<blst_scalar> = msgs
+            .iter()
+            .map(|_| {
+                let mut b = [0; 32];
+                b[..8].copy_from_slice(&rng.gen_range(1..=u64::MAX).to_le_bytes());
+                blst_scalar { b }
+            })
+            .collect();
+        let result = bls::Signature::verify_multiple_aggregate_signatures(
+            &msgs,
+            &[],
+            &pks,
+            true,
+            &sigs,
+            true,
+            &weights,
+            64,
+        );
+
+        match result {
+            BLST_ERROR::BLST_SUCCESS => Ok(()),
+            err => Err(Error::SignatureVerification(err)),
+        }
+    }
 }
 
 impl ByteFmt for Signature {
@@ -68,3 +68,25 @@ fn aggregate_signature_failure_smoke() {
 
     assert!(agg_sig.verify(pks.iter().map(|pk| (&msg[..], pk))).is_err())
 }
+
+// Test verifying a batch of signatures of distinct messages
+#[test]
+fn batch_verification() {
+    let mut rng = StdRng::seed_from_u64(29483920);
+
+    let sks: Vec<SecretKey> = repeat_with(|| SecretKey::generate(rng.gen()))
+        .take(5)
+        .collect();
+    let pks: Vec<PublicKey> = sks.iter().map(|k| k.public()).collect();
+    let msgs: Vec<[u8; 32]> = repeat_with(|| rng.gen()).take(5).collect();
+    let mut sigs: Vec<Signature> = sks.iter().zip(&msgs).map(|(k, m)| k.sign(m)).collect();
+    let batch = |sigs: &[Signature]| {
+        Signature::verify_batch((0..sigs.len()).map(|i| (&msgs[i][..], &pks[i], &sigs[i])))
+    };
+
+    batch(&sigs).unwrap();
+    Signature::verify_batch(std::iter::empty()).unwrap();
+
+    sigs.swap(0, 1);
+    assert!(batch(&sigs).is_err());
+}
@@ -11,6 +11,7 @@ use pairing::{
     ff::{PrimeField, PrimeFieldRepr},
     CurveAffine as _, CurveProjective as _, EncodedPoint as _, Engine as _,
 };
+use rand::Rng as _;
 use std::{
     collections::HashMap,
     fmt::{Debug, Formatter},
@@ -130,14 +131,39 @@ impl Signature {
     /// This optimization is needed for ensuring that tests can run within a reasonable time frame.
     #[inline(never)]
     pub fn verify(&self, msg: &[u8], pk: &PublicKey) -> Result<(), Error> {
-        let hash_point = hash::hash_to_g1(msg);
-
-        // First pair: e(H(m): G1, pk: G2)
-        let a = Bn256::pairing(hash_point, pk.0);
-        // Second pair: e(sig: G1, generator: G2)
-        let b = Bn256::pairing(self.0, G2Affine::one());
+        if check_pairings(self.0, [(msg, pk.0)]) {
+            Ok(())
+        } else {
+            Err(Error::SignatureVerificationFailure)
+        }
+    }
 
-        if a == b {
+    /// Verifies a batch of signatures, each against its own message and public key.
+    ///
+    /// The signatures are combined with random 128-bit weights, so that the whole batch
+    /// is checked with a single multi-pairing (one pairing per distinct message), instead of
+    /// two pairings per signature. Invalid signatures cannot cancel each other out, because
+    /// the weights are not known to the signers. The result doesn't tell which of the
+    /// signatures are invalid, so on failure the caller has to bisect the batch.
+    #[inline(never)]
+    pub fn verify_batch<'a>(
+        items: impl Iterator<Item = (&'a [u8], &'a PublicKey, &'a Signature)>,
+    ) -> Result<(), Error> {
+        let rng = &mut rand::thread_rng();
+        let mut sig = G1::zero();
+        // Weighted public keys are summed up per message, so that the messages signed by
+        // multiple validators (i.e. votes) need a single pairing.
+        let mut pairs: HashMap<&[u8], G2> = HashMap::new();
+        for (msg, pk, s) in items {
+            let weight = FrRepr([rng.gen(), rng.gen(), 0, 0]);
+            let mut s = s.0;
+            s.mul_assign(weight);
+            sig.add_assign(&s);
+            let mut pk = pk.0;
+            pk.mul_assign(weight);
+            pairs.entry(msg).or_insert_with(G2::zero).add_assign(&pk);
+        }
+        if check_pairings(sig, pairs) {
             Ok(())
         } else {
             Err(Error::SignatureVerificationFailure)
@@ -171,6 +197,21 @@ impl Ord for Signature {
     }
 }
 
+/// Checks that `e(sig, generator) == e(H(m1), pk1) * ... * e(H(mn), pkn)`.
+///
+/// All the pairings are computed with a single Miller loop over `(-sig, generator)` and
+/// `(H(mi), pki)`, followed by a single final exponentiation, which is the expensive part.
+fn check_pairings<'a>(sig: G1, pairs: impl IntoIterator<Item = (&'a [u8], G2)>) -> bool {
+    let mut sig = sig.into_affine();
+    sig.negate();
+    let mut prepared = vec![(sig.prepare(), G2Affine::one().prepare())];
+    for (msg, pk) in pairs {
+        prepared.push((hash::hash_to_g1(msg).prepare(), pk.into_affine().prepare()));
+    }
+    let prepared: Vec<_> = prepared.iter().map(|(a, b)| (a, b)).collect();
+    Bn256::final_exponentiation(&Bn256::miller_loop(&prepared)) == Some(Fq12::one())
+}
+
 /// Type safety wrapper around [Signature] indicating that it is an aggregated signature.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct AggregateSignature(G1);
@@ -193,20 +234,11 @@ impl AggregateSignature {
     fn verify_raw(&self, msgs_and_pks: &[(&[u8], &PublicKey)]) -> Result<(), Error> {
         // Aggregate public keys if they are signing the same hash. Each public key aggregated
         // is one fewer pairing to calculate.
-        let mut pairs: HashMap<&[u8], PublicKey> = HashMap::new();
+        let mut pairs: HashMap<&[u8], G2> = HashMap::new();
         for (msg, pk) in msgs_and_pks {
-            pairs.entry(msg).or_default().0.add_assign(&pk.0);
-        }
-        // First pair: e(sig: G1, generator: G2)
-        let a = Bn256::pairing(self.0, G2::one());
-
-        // Second pair: e(H(m1): G1, pk1: G2) * ... * e(H(m1000): G1, pk1000: G2)
-        let mut b = Fq12::one();
-        for (msg, pk) in pairs {
-            b.mul_assign(&Bn256::pairing(hash::hash_to_g1(msg), pk.0))
+            pairs.entry(msg).or_insert_with(G2::zero).add_assign(&pk.0);
         }
-
-        if a == b {
+        if check_pairings(self.0, pairs) {
             Ok(())
         } else {
             Err(Error::AggregateSignatureVerificationFailure)
@@ -222,6 +254,38 @@ impl AggregateSignature {
     ) -> Result<(), Error> {
         self.verify_raw(&msgs_and_pks.collect::<Vec<_>>()[..])
     }
+
+    /// Verifies a batch of aggregate signatures, each against its own list of messages and
+    /// public keys, with a single multi-pairing, like `Signature::verify_batch()`.
+    /// The public keys of an aggregate are summed up per message before they are weighted,
+    /// so a QC costs a single scalar multiplication per distinct message.
+    #[inline(never)]
+    pub fn verify_batch(
+        items: &[(&AggregateSignature, &[(&[u8], &PublicKey)])],
+    ) -> Result<(), Error> {
+        let rng = &mut rand::thread_rng();
+        let mut sig = G1::zero();
+        let mut pairs: HashMap<&[u8], G2> = HashMap::new();
+        for (agg, msgs_and_pks) in items {
+            let weight = FrRepr([rng.gen(), rng.gen(), 0, 0]);
+            let mut s = agg.0;
+            s.mul_assign(weight);
+            sig.add_assign(&s);
+            let mut pks: HashMap<&[u8], G2> = HashMap::new();
+            for (msg, pk) in msgs_and_pks.iter() {
+                pks.entry(*msg).or_insert_with(G2::zero).add_assign(&pk.0);
+            }
+            for (msg, mut pk) in pks {
+                pk.mul_assign(weight);
+                pairs.entry(msg).or_insert_with(G2::zero).add_assign(&pk);
+            }
+        }
+        if check_pairings(sig, pairs) {
+            Ok(())
+        } else {
+            Err(Error::AggregateSignatureVerificationFailure)
+        }
+    }
 }
 
 impl ByteFmt for AggregateSignature {
@@ -2,6 +2,7 @@ use crate::{
     bn254::{AggregateSignature, PublicKey, SecretKey, Signature},
     ByteFmt,
 };
+use pairing::CurveProjective as _;
 use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::iter::repeat_with;
 
@@ -89,6 +90,76 @@ fn aggregate_signature_failure_smoke() {
     assert!(agg.verify(pks.iter().map(|pk| (&msg[..], pk))).is_err())
 }
 
+#[test]
+fn batch_verification() {
+    let mut rng = StdRng::seed_from_u64(29483920);
+
+    let sks: Vec<SecretKey> = repeat_with(|| rng.gen::<SecretKey>()).take(6).collect();
+    let pks: Vec<PublicKey> = sks.iter().map(|k| k.public()).collect();
+    let msgs: Vec<[u8; 32]> = repeat_with(|| rng.gen()).take(2).collect();
+    let msg = |i: usize| &msgs[i % msgs.len()][..];
+    let mut sigs: Vec<Signature> = sks
+        .iter()
+        .enumerate()
+        .map(|(i, k)| k.sign(msg(i)))
+        .collect();
+    let batch = |sigs: &[Signature]| {
+        Signature::verify_batch((0..sigs.len()).map(|i| (msg(i), &pks[i], &sigs[i])))
+    };
+
+    batch(&sigs).unwrap();
+    Signature::verify_batch(std::iter::empty()).unwrap();
+
+    // Signature of a different message.
+    let mut bad = sigs.clone();
+    bad[0] = sks[0].sign(msg(1));
+    assert!(batch(&bad).is_err());
+
+    // Errors cancelling each other out would pass an unweighted check.
+    let delta = rng.gen::<Signature>().0;
+    sigs[0].0.add_assign(&delta);
+    sigs[1].0.sub_assign(&delta);
+    assert!(batch(&sigs).is_err());
+}
+
+#[test]
+fn aggregate_batch_verification() {
+    let mut rng = StdRng::seed_from_u64(29483920);
+
+    let sks: Vec<SecretKey> = repeat_with(|| rng.gen::<SecretKey>()).take(4).collect();
+    let pks: Vec<PublicKey> = sks.iter().map(|k| k.public()).collect();
+    let msgs: Vec<[u8; 32]> = repeat_with(|| rng.gen()).take(3).collect();
+    // Every aggregate is signed by all the keys, over its own message.
+    let pairs: Vec<Vec<(&[u8], &PublicKey)>> = msgs
+        .iter()
+        .map(|msg| pks.iter().map(|pk| (&msg[..], pk)).collect())
+        .collect();
+    let mut aggs: Vec<AggregateSignature> = msgs
+        .iter()
+        .map(|msg| {
+            AggregateSignature::aggregate(&sks.iter().map(|k| k.sign(msg)).collect::<Vec<_>>())
+        })
+        .collect();
+    let batch = |aggs: &[AggregateSignature]| {
+        let items: Vec<_> = aggs.iter().zip(&pairs).map(|(a, p)| (a, &p[..])).collect();
+        AggregateSignature::verify_batch(&items)
+    };
+
+    batch(&aggs).unwrap();
+    AggregateSignature::verify_batch(&[]).unwrap();
+
+    // Aggregate of a different message.
+    let mut bad = aggs.clone();
+    bad.swap(0, 1);
+    assert!(batch(&bad).is_err());
+
+    // Errors cancelling each other out would pass an unweighted check.
+    let delta = rng.gen::<Signature>().0;
+    aggs[0].0.add_assign(&delta);
+    aggs[1].0.sub_assign(&delta);
+    assert!(batch(&aggs).is_err());
+}
+
 #[test]
 fn byte_fmt_correctness() {
     let mut rng = rand::thread_rng();
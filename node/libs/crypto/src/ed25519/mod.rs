@@ -101,6 +101,25 @@ impl Ord for PublicKey {
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Signature(ed::Signature);
 
+impl Signature {
+    /// Verifies a batch of signatures, each against its own message and public key.
+    /// It is considerably faster than verifying the signatures one by one,
+    /// but it doesn't tell which of the signatures are invalid.
+    pub fn verify_batch<'a>(
+        items: impl Iterator<Item = (&'a [u8], &'a PublicKey, &'a Signature)>,
+    ) -> Result<(), InvalidSignatureError> {
+        let mut msgs = vec![];
+        let mut pks = vec![];
+        let mut sigs = vec![];
+        for (msg, pk, sig) in items {
+            msgs.push(msg);
+            pks.push(pk.0);
+            sigs.push(sig.0);
+        }
+        ed::verify_batch(&msgs, &sigs, &pks).map_err(|_| InvalidSignatureError)
+    }
+}
+
 impl ByteFmt for Signature {
     fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
         let bytes: &[u8; ed::SIGNATURE_LENGTH] = bytes.try_into()?;
@@ -4,6 +4,7 @@ use anyhow::Context as _;
 /// Utility for parsing human-readable text representations via TextFmt::decode.
 /// It keeps a reference to the initial text and a reference to the remaining unparsed text.
 /// This allows to provide more context when a parsing error is encountered.
+#[derive(Clone, Copy)]
 pub struct Text<'a> {
     /// Initial text.
     context: &'a str,
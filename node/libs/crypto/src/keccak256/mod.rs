@@ -1,6 +1,6 @@
 //! Wrappers for the Keccak256 cryptographic hash algorithm.
 use crate::ByteFmt;
-use sha3::{digest::Update as _, Digest as _};
+use sha3::{digest::Update, Digest as _};
 
 #[cfg(test)]
 mod test;
@@ -28,6 +28,32 @@ impl Keccak256 {
     }
 }
 
+/// Incremental Keccak256 hasher, for messages which are not available in memory at once.
+#[derive(Clone, Default)]
+pub struct Hasher(sha3::Keccak256);
+
+impl Hasher {
+    /// Hasher of the messages in the given `domain`.
+    /// The domain is prefixed with its length, so that distinct (domain, message) pairs
+    /// are never hashed as the same input.
+    pub fn with_domain(domain: &[u8]) -> Self {
+        let mut hasher = Self::default();
+        hasher.update(&u32::try_from(domain.len()).unwrap().to_be_bytes());
+        hasher.update(domain);
+        hasher
+    }
+
+    /// Feeds the next part of the message to the hasher.
+    pub fn update(&mut self, data: &[u8]) {
+        Update::update(&mut self.0, data);
+    }
+
+    /// Computes the hash of the whole message.
+    pub fn finalize(self) -> Keccak256 {
+        Keccak256(self.0.finalize().into())
+    }
+}
+
 impl ByteFmt for Keccak256 {
     fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
         Ok(Self(bytes.try_into()?))
@@ -31,3 +31,35 @@ fn test_keccak256() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn test_keccak256_hasher() {
+    use crate::keccak256::{Hasher, Keccak256};
+
+    let msg: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+    for chunk_size in [1, 7, 136, 1000] {
+        let mut hasher = Hasher::default();
+        for chunk in msg.chunks(chunk_size) {
+            hasher.update(chunk);
+        }
+        assert!(Keccak256::new(&msg) == hasher.finalize());
+    }
+}
+
+#[test]
+fn test_keccak256_hasher_with_domain() {
+    use crate::keccak256::Hasher;
+
+    let mut hasher = Hasher::with_domain(b"domain");
+    hasher.update(b"message");
+    assert_eq!(
+        "4797b18d1de183ae79902d896e29135398c846b22062d5941ee39cadd10d82b4",
+        hex::encode(hasher.finalize().as_bytes())
+    );
+    // The domain boundary is part of the hashed input.
+    let mut a = Hasher::with_domain(b"ab");
+    a.update(b"c");
+    let mut b = Hasher::with_domain(b"a");
+    b.update(b"bc");
+    assert!(a.finalize() != b.finalize());
+}
@@ -5,7 +5,9 @@ pub use fmt::*;
 /// Currently replaced by [bn254] and unused.
 pub mod bls12_381;
 
+pub mod blake2b256;
 pub mod bn254;
 pub mod ed25519;
 mod fmt;
 pub mod keccak256;
+pub mod secp256k1;
@@ -0,0 +1,249 @@
+//! ECDSA signature scheme over the secp256k1 curve.
+//! This is just an adapter of k256, exposing zksync-bft-specific API.
+//!
+//! The signatures are compatible with the EVM `ecrecover` precompile: a message is signed
+//! as a 32-byte hash (without any prefix), and the signature is encoded as `r || s || v`,
+//! with `s` normalized to the lower half of the curve order and `v` being the recovery id.
+//! ECDSA signatures don't aggregate, so an [`AggregateSignature`] is just the list of
+//! the signatures, ordered by the positions of the signers.
+
+use crate::{keccak256::Keccak256, ByteFmt};
+use anyhow::Context as _;
+use k256::ecdsa;
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+mod testonly;
+#[cfg(test)]
+mod tests;
+
+/// Length of the encoded public key (SEC1, compressed).
+const PUBLIC_KEY_LEN: usize = 33;
+/// Length of the encoded signature (`r || s || v`).
+const SIGNATURE_LEN: usize = 65;
+
+/// Error type for verifying secp256k1 signatures.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("Signature verification failure")]
+    SignatureVerificationFailure,
+    #[error("Aggregate signature verification failure")]
+    AggregateSignatureVerificationFailure,
+}
+
+/// secp256k1 secret key.
+pub struct SecretKey(ecdsa::SigningKey);
+
+impl SecretKey {
+    /// Generates a secret key from a cryptographically-secure entropy source.
+    pub fn generate() -> Self {
+        Self(ecdsa::SigningKey::random(&mut rand::rngs::OsRng))
+    }
+
+    /// Computes a public key for this secret key.
+    pub fn public(&self) -> PublicKey {
+        PublicKey(*self.0.verifying_key())
+    }
+
+    /// Signs a 32-byte message hash.
+    pub fn sign(&self, hash: &[u8; 32]) -> Signature {
+        let (sig, recid) = self
+            .0
+            .sign_prehash_recoverable(hash)
+            .expect("signing a 32-byte prehash cannot fail");
+        Signature { sig, recid }
+    }
+}
+
+impl ByteFmt for SecretKey {
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        ecdsa::SigningKey::from_slice(bytes)
+            .context("invalid key material")
+            .map(Self)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        self.0.to_bytes().to_vec()
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretKey({:?})", self.public())
+    }
+}
+
+impl PartialEq for SecretKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.public() == other.public()
+    }
+}
+
+/// secp256k1 public key.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PublicKey(ecdsa::VerifyingKey);
+
+impl PublicKey {
+    /// Address of the EVM account controlled by this key,
+    /// i.e. the last 20 bytes of the keccak256 hash of the uncompressed key.
+    pub fn evm_address(&self) -> [u8; 20] {
+        let point = self.0.to_encoded_point(false);
+        let hash = Keccak256::new(&point.as_bytes()[1..]);
+        hash.as_bytes()[12..].try_into().unwrap()
+    }
+}
+
+impl ByteFmt for PublicKey {
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        anyhow::ensure!(bytes.len() == PUBLIC_KEY_LEN, "expected a compressed key");
+        ecdsa::VerifyingKey::from_sec1_bytes(bytes)
+            .context("invalid key material")
+            .map(Self)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        self.0.to_encoded_point(true).as_bytes().to_vec()
+    }
+}
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PublicKey({})", hex::encode(ByteFmt::encode(self)))
+    }
+}
+
+impl Hash for PublicKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write(&self.encode());
+    }
+}
+
+impl PartialOrd for PublicKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PublicKey {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        ByteFmt::encode(self).cmp(&ByteFmt::encode(other))
+    }
+}
+
+/// secp256k1 recoverable signature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Signature {
+    sig: ecdsa::Signature,
+    recid: ecdsa::RecoveryId,
+}
+
+impl Signature {
+    /// Verifies the signature of a message hash against the provided public key.
+    /// The signature is verified by recovering the public key, so that a signature
+    /// accepted here is also accepted by `ecrecover`.
+    pub fn verify(&self, hash: &[u8; 32], pk: &PublicKey) -> Result<(), Error> {
+        match ecdsa::VerifyingKey::recover_from_prehash(hash, &self.sig, self.recid) {
+            Ok(got) if got == pk.0 => Ok(()),
+            _ => Err(Error::SignatureVerificationFailure),
+        }
+    }
+}
+
+impl ByteFmt for Signature {
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        anyhow::ensure!(bytes.len() == SIGNATURE_LEN, "expected r || s || v");
+        let sig = ecdsa::Signature::from_slice(&bytes[..64]).context("invalid signature")?;
+        // High `s` values are rejected, so that the encoding of a signature is unique.
+        anyhow::ensure!(sig.normalize_s().is_none(), "non-normalized signature");
+        let recid = ecdsa::RecoveryId::from_byte(bytes[64]).context("invalid recovery id")?;
+        Ok(Self { sig, recid })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.sig.to_bytes().to_vec();
+        bytes.push(self.recid.to_byte());
+        bytes
+    }
+}
+
+impl Hash for Signature {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write(&self.encode());
+    }
+}
+
+impl PartialOrd for Signature {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Signature {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        ByteFmt::encode(self).cmp(&ByteFmt::encode(other))
+    }
+}
+
+/// Signatures of different signers, as a counterpart of the aggregate signatures
+/// of the schemes supporting aggregation. The signatures are ordered by the positions
+/// of their signers, so that the signers themselves don't need to be stored.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AggregateSignature(Vec<Signature>);
+
+impl AggregateSignature {
+    /// Number of the signatures.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns true if there are no signatures.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Inserts a signature of the signer at position `pos` among the signers,
+    /// i.e. after the signatures of the first `pos` signers.
+    /// Panics if `pos > self.len()`.
+    pub fn add(&mut self, pos: usize, sig: &Signature) {
+        self.0.insert(pos, *sig);
+    }
+
+    /// Verifies the signatures for multiple messages against the provided list of public keys,
+    /// ordered by the positions of the signers.
+    /// This method expects exactly one signature per public key, otherwise it will fail.
+    pub fn verify<'a>(
+        &self,
+        hashes_and_pks: impl Iterator<Item = (&'a [u8; 32], &'a PublicKey)>,
+    ) -> Result<(), Error> {
+        let mut sigs = self.0.iter();
+        for (hash, pk) in hashes_and_pks {
+            let sig = sigs
+                .next()
+                .ok_or(Error::AggregateSignatureVerificationFailure)?;
+            sig.verify(hash, pk)
+                .map_err(|_| Error::AggregateSignatureVerificationFailure)?;
+        }
+        if sigs.next().is_some() {
+            return Err(Error::AggregateSignatureVerificationFailure);
+        }
+        Ok(())
+    }
+}
+
+impl ByteFmt for AggregateSignature {
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        anyhow::ensure!(bytes.len() % SIGNATURE_LEN == 0, "invalid length");
+        bytes
+            .chunks(SIGNATURE_LEN)
+            .map(<Signature as ByteFmt>::decode)
+            .collect::<anyhow::Result<_>>()
+            .map(Self)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        self.0.iter().flat_map(ByteFmt::encode).collect()
+    }
+}
@@ -0,0 +1,51 @@
+//! Random key generation, intended for use in testing
+
+use super::{AggregateSignature, PublicKey, SecretKey, Signature};
+use k256::ecdsa;
+use rand::{distributions::Standard, prelude::Distribution, Rng};
+
+/// Generates a random SecretKey. This is meant for testing purposes.
+impl Distribution<SecretKey> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> SecretKey {
+        loop {
+            // Almost every 32-byte string is a valid scalar.
+            if let Ok(key) = ecdsa::SigningKey::from_slice(&rng.gen::<[u8; 32]>()) {
+                return SecretKey(key);
+            }
+        }
+    }
+}
+
+/// Generates a random PublicKey. This is meant for testing purposes.
+impl Distribution<PublicKey> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> PublicKey {
+        rng.gen::<SecretKey>().public()
+    }
+}
+
+/// Generates a random Signature. This is meant for testing purposes.
+impl Distribution<Signature> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Signature {
+        let key = rng.gen::<SecretKey>();
+        let hash = rng.gen::<[u8; 32]>();
+        key.sign(&hash)
+    }
+}
+
+/// Generates a random AggregateSignature. This is meant for testing purposes.
+impl Distribution<AggregateSignature> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> AggregateSignature {
+        let mut agg = AggregateSignature::default();
+        for i in 0..rng.gen_range(1..5) {
+            agg.add(i, &rng.gen());
+        }
+        agg
+    }
+}
+
+impl AggregateSignature {
+    /// Generate a new aggregate signature from a list of signatures, ordered by the signers.
+    pub fn aggregate<'a>(sigs: impl IntoIterator<Item = &'a Signature>) -> Self {
+        Self(sigs.into_iter().copied().collect())
+    }
+}
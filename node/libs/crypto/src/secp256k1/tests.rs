@@ -0,0 +1,164 @@
+use crate::{
+    keccak256::Keccak256,
+    secp256k1::{AggregateSignature, PublicKey, SecretKey, Signature},
+    ByteFmt,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::iter::repeat_with;
+
+#[test]
+fn signature_smoke() {
+    let mut rng = StdRng::seed_from_u64(29483920);
+    let sk = rng.gen::<SecretKey>();
+    let pk = sk.public();
+
+    let hash = rng.gen::<[u8; 32]>();
+    let sig = sk.sign(&hash);
+
+    sig.verify(&hash, &pk).unwrap()
+}
+
+#[test]
+fn signature_failure_smoke() {
+    let mut rng = StdRng::seed_from_u64(29483920);
+
+    let sk1 = rng.gen::<SecretKey>();
+    let sk2 = rng.gen::<SecretKey>();
+    let hash1 = rng.gen::<[u8; 32]>();
+    let hash2 = rng.gen::<[u8; 32]>();
+    let sig = sk1.sign(&hash1);
+
+    assert!(sig.verify(&hash1, &sk2.public()).is_err());
+    assert!(sig.verify(&hash2, &sk1.public()).is_err());
+}
+
+/// Signatures have to be accepted by the EVM `ecrecover` precompile,
+/// so the encoding and the RFC 6979 nonces are locked with golden values.
+#[test]
+fn evm_compatibility() {
+    let sk = SecretKey::decode(
+        &hex::decode("4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318").unwrap(),
+    )
+    .unwrap();
+    let pk = sk.public();
+    assert_eq!(
+        "024e3b81af9c2234cad09d679ce6035ed1392347ce64ce405f5dcd36228a25de6e",
+        hex::encode(pk.encode())
+    );
+    assert_eq!(
+        "2c7536e3605d9c16a7a3d7b1898e529396a65c23",
+        hex::encode(pk.evm_address())
+    );
+
+    let hash = *Keccak256::new(b"message").as_bytes();
+    let sig = sk.sign(&hash);
+    assert_eq!(
+        "a75451934e5442c7e088e4891679e2ff89845acf16520442f5ec4c00de97ffc2\
+         41c7352212614fb43b4cf4e653f5cdd328579ba9740501064169a9b028aba2a500",
+        hex::encode(sig.encode())
+    );
+    sig.verify(&hash, &pk).unwrap();
+}
+
+#[test]
+fn aggregate_signature_distinct_messages() {
+    let mut rng = StdRng::seed_from_u64(29483920);
+    let num_keys = 5;
+    let num_distinct = 2;
+
+    let sks: Vec<SecretKey> = repeat_with(|| rng.gen::<SecretKey>())
+        .take(num_keys)
+        .collect();
+    let pks: Vec<PublicKey> = sks.iter().map(|k| k.public()).collect();
+    let hashes: Vec<[u8; 32]> = repeat_with(|| rng.gen()).take(num_distinct).collect();
+
+    let mut sigs: Vec<Signature> = Vec::new();
+    let mut pairs: Vec<(&[u8; 32], &PublicKey)> = Vec::new();
+    for (i, sk) in sks.iter().enumerate() {
+        let hash = &hashes[i % num_distinct];
+        sigs.push(sk.sign(hash));
+        pairs.push((hash, &pks[i]))
+    }
+
+    let agg = AggregateSignature::aggregate(&sigs);
+    agg.verify(pairs.iter().copied()).unwrap();
+
+    // The signers have to be listed in the order of the signatures.
+    assert!(agg.verify(pairs.iter().rev().copied()).is_err());
+
+    // Mismatching message.
+    pairs[0].0 = &hashes[1];
+    assert!(agg.verify(pairs.iter().copied()).is_err());
+}
+
+#[test]
+fn aggregate_signature_failure_smoke() {
+    let mut rng = StdRng::seed_from_u64(29483920);
+
+    let sks: Vec<SecretKey> = repeat_with(|| rng.gen::<SecretKey>()).take(5).collect();
+    let pks: Vec<PublicKey> = sks.iter().map(|k| k.public()).collect();
+    let hash = rng.gen::<[u8; 32]>();
+    let sigs: Vec<Signature> = sks.iter().map(|k| k.sign(&hash)).collect();
+
+    // Missing signatures.
+    let agg = AggregateSignature::aggregate(&sigs[..3]);
+    assert!(agg.verify(pks.iter().map(|pk| (&hash, pk))).is_err());
+
+    // Extra signatures.
+    let agg = AggregateSignature::aggregate(&sigs);
+    assert!(agg
+        .verify(pks.iter().take(3).map(|pk| (&hash, pk)))
+        .is_err());
+
+    // Duplicate signers.
+    assert!(agg
+        .verify(pks.iter().take(4).chain(&pks[..1]).map(|pk| (&hash, pk)))
+        .is_err());
+}
+
+#[test]
+fn byte_fmt_correctness() {
+    let mut rng = rand::thread_rng();
+
+    let sk: SecretKey = rng.gen();
+    let bytes = sk.encode();
+    let sk_decoded = SecretKey::decode(&bytes).unwrap();
+    assert_eq!(sk, sk_decoded);
+
+    let pk: PublicKey = rng.gen();
+    let bytes = pk.encode();
+    let pk_decoded = PublicKey::decode(&bytes).unwrap();
+    assert_eq!(pk, pk_decoded);
+
+    let sig: Signature = rng.gen();
+    let bytes = sig.encode();
+    let sig_decoded = Signature::decode(&bytes).unwrap();
+    assert_eq!(sig, sig_decoded);
+
+    let agg: AggregateSignature = rng.gen();
+    let bytes = agg.encode();
+    let agg_decoded = AggregateSignature::decode(&bytes).unwrap();
+    assert_eq!(agg, agg_decoded);
+}
+
+#[test]
+fn byte_fmt_rejects_non_canonical() {
+    let mut rng = rand::thread_rng();
+
+    // The signature from `evm_compatibility()` with `s` replaced by `n - s`,
+    // which `ecrecover` accepts as well.
+    let high_s = hex::decode(
+        "a75451934e5442c7e088e4891679e2ff89845acf16520442f5ec4c00de97ffc2\
+         be38cadded9eb04bc4b30b19ac0a322b9257413d3b439f357e68b4dca78a9e9c01",
+    )
+    .unwrap();
+    assert!(Signature::decode(&high_s).is_err());
+
+    // Aggregate with a truncated signature.
+    let agg: AggregateSignature = rng.gen();
+    let bytes = agg.encode();
+    assert!(AggregateSignature::decode(&bytes[..bytes.len() - 1]).is_err());
+
+    // Aggregate with a non-canonical signature.
+    assert!(AggregateSignature::decode(&[&bytes[..], &high_s[..]].concat()).is_err());
+}
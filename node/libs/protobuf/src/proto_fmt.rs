@@ -205,7 +205,17 @@ pub fn canonical_raw(
     desc: &prost_reflect::MessageDescriptor,
 ) -> anyhow::Result<Vec<u8>> {
     let mut v = vec![];
-    let mut w = quick_protobuf::Writer::new(&mut v);
+    canonical_raw_to(buf, desc, &mut v)?;
+    Ok(v)
+}
+
+/// Like `canonical_raw`, but writes the canonical form to `out`.
+pub fn canonical_raw_to(
+    buf: &[u8],
+    desc: &prost_reflect::MessageDescriptor,
+    out: impl std::io::Write,
+) -> anyhow::Result<()> {
+    let mut w = quick_protobuf::Writer::new(out);
     // Append fields in ascending tags order.
     for (num, mut values) in read_fields(buf, desc)? {
         let fd = desc.get_field(num).unwrap();
@@ -241,7 +251,7 @@ pub fn canonical_raw(
             }
         }
     }
-    Ok(v)
+    Ok(())
 }
 
 /// Encodes a proto message of known schema (no unknown fields) into a canonical form.
@@ -260,6 +270,16 @@ pub fn encode<T: ProtoFmt>(x: &T) -> Vec<u8> {
     canonical(x)
 }
 
+/// Encodes a proto message to `out`, like `encode`.
+/// `scratch` holds the intermediate (non-canonical) encoding,
+/// so that the callers encoding many messages can reuse its allocation.
+pub fn encode_to<T: ProtoFmt>(x: &T, scratch: &mut Vec<u8>, out: impl std::io::Write) {
+    let msg = x.build();
+    scratch.clear();
+    msg.encode(scratch).unwrap();
+    canonical_raw_to(scratch, &msg.descriptor(), out).unwrap();
+}
+
 /// Decodes a proto message.
 pub fn decode<T: ProtoFmt>(bytes: &[u8]) -> anyhow::Result<T> {
     T::read(&<T as ProtoFmt>::Proto::decode(bytes)?)
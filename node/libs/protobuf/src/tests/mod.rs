@@ -77,6 +77,29 @@ fn test_encode_decode() {
     assert!(decode::<A>(&bytes).is_err());
 }
 
+#[test]
+fn test_encode_to() {
+    let a = A {
+        x: vec![1, 2, 3],
+        y: 76,
+        e: vec![8, 1, 9, 4],
+        b: B::V(Box::new(B::U(true))),
+    };
+    let b = A {
+        x: vec![],
+        y: 5,
+        e: vec![],
+        b: B::U(false),
+    };
+    // The scratch buffer is reused, and its previous content doesn't leak into the output.
+    let mut scratch = vec![];
+    for v in [&a, &b, &a] {
+        let mut out = vec![];
+        encode_to(v, &mut scratch, &mut out);
+        assert_eq!(encode(v), out);
+    }
+}
+
 #[test]
 fn test_timestamp() {
     let ctx = ctx::test_root(&ctx::RealClock);
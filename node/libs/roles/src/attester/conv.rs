@@ -0,0 +1,122 @@
+use super::{AggregateSignature, Batch, BatchNumber, BatchQC, Msg, PublicKey, Signature, Signed};
+use crate::{proto::attester as proto, validator};
+use anyhow::Context as _;
+use zksync_consensus_crypto::ByteFmt;
+use zksync_consensus_utils::enum_util::Variant;
+use zksync_protobuf::{read_required, required, ProtoFmt};
+
+/// Reads a hash encoded as raw bytes.
+fn read_hash(r: &Option<Vec<u8>>) -> anyhow::Result<validator::Digest> {
+    ByteFmt::decode(required(r)?)
+}
+
+impl ProtoFmt for Batch {
+    type Proto = proto::Batch;
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            genesis: validator::GenesisHash(read_hash(&r.genesis).context("genesis")?),
+            number: BatchNumber(*required(&r.number).context("number")?),
+            last_block: validator::BlockNumber(*required(&r.last_block).context("last_block")?),
+            last_block_hash: validator::BlockHeaderHash(
+                read_hash(&r.last_block_hash).context("last_block_hash")?,
+            ),
+        })
+    }
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            genesis: Some(self.genesis.0.encode()),
+            number: Some(self.number.0),
+            last_block: Some(self.last_block.0),
+            last_block_hash: Some(self.last_block_hash.0.encode()),
+        }
+    }
+}
+
+impl ProtoFmt for BatchQC {
+    type Proto = proto::BatchQc;
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            message: read_required(&r.msg).context("msg")?,
+            signers: read_required(&r.signers).context("signers")?,
+            signature: read_required(&r.sig).context("sig")?,
+        })
+    }
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            msg: Some(self.message.build()),
+            signers: Some(self.signers.build()),
+            sig: Some(self.signature.build()),
+        }
+    }
+}
+
+impl ProtoFmt for Msg {
+    type Proto = proto::Msg;
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        use proto::msg::T;
+        Ok(match required(&r.t)? {
+            T::Batch(r) => Self::Batch(ProtoFmt::read(r).context("batch")?),
+        })
+    }
+    fn build(&self) -> Self::Proto {
+        use proto::msg::T;
+        let t = match self {
+            Self::Batch(x) => T::Batch(x.build()),
+        };
+        Self::Proto { t: Some(t) }
+    }
+}
+
+impl<V: Variant<Msg> + Clone> ProtoFmt for Signed<V> {
+    type Proto = proto::Signed;
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            msg: V::extract(read_required::<Msg>(&r.msg).context("msg")?)?,
+            key: read_required(&r.key).context("key")?,
+            sig: read_required(&r.sig).context("sig")?,
+        })
+    }
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            msg: Some(self.msg.clone().insert().build()),
+            key: Some(self.key.build()),
+            sig: Some(self.sig.build()),
+        }
+    }
+}
+
+impl ProtoFmt for PublicKey {
+    type Proto = proto::PublicKey;
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self(ByteFmt::decode(required(&r.bn254)?)?))
+    }
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            bn254: Some(self.0.encode()),
+        }
+    }
+}
+
+impl ProtoFmt for Signature {
+    type Proto = proto::Signature;
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self(ByteFmt::decode(required(&r.bn254)?)?))
+    }
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            bn254: Some(self.0.encode()),
+        }
+    }
+}
+
+impl ProtoFmt for AggregateSignature {
+    type Proto = proto::AggregateSignature;
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self(ByteFmt::decode(required(&r.bn254)?)?))
+    }
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            bn254: Some(self.0.encode()),
+        }
+    }
+}
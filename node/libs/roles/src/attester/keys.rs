@@ -0,0 +1,193 @@
+//! Cryptographic keys representing the attester role.
+use super::{Msg, MsgHash, Signed};
+use std::{fmt, sync::Arc};
+use zksync_consensus_crypto::{bn254, ByteFmt, Text, TextFmt};
+use zksync_consensus_utils::enum_util::Variant;
+
+/// Error type returned by attester key operations.
+pub type Error = bn254::Error;
+
+/// A secret key for the attester role.
+/// SecretKey is put into an Arc, so that we can clone it,
+/// without copying the secret all over the RAM.
+#[derive(Clone)]
+pub struct SecretKey(pub(crate) Arc<bn254::SecretKey>);
+
+impl SecretKey {
+    /// Generates a secret key from a cryptographically-secure entropy source.
+    pub fn generate() -> Self {
+        Self(Arc::new(bn254::SecretKey::generate()))
+    }
+
+    /// Public key corresponding to this secret key.
+    pub fn public(&self) -> PublicKey {
+        PublicKey(self.0.public())
+    }
+
+    /// Signs a strongly typed message.
+    pub fn sign_msg<V: Variant<Msg>>(&self, msg: V) -> Signed<V> {
+        let msg = msg.insert();
+        Signed {
+            sig: self.sign_hash(&msg.hash()),
+            key: self.public(),
+            msg: V::extract(msg).unwrap(),
+        }
+    }
+
+    /// Signs a message hash.
+    pub fn sign_hash(&self, msg_hash: &MsgHash) -> Signature {
+        Signature(self.0.sign(&ByteFmt::encode(msg_hash)))
+    }
+}
+
+impl ByteFmt for SecretKey {
+    fn encode(&self) -> Vec<u8> {
+        ByteFmt::encode(&*self.0)
+    }
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        ByteFmt::decode(bytes).map(Arc::new).map(Self)
+    }
+}
+
+impl TextFmt for SecretKey {
+    fn encode(&self) -> String {
+        format!(
+            "attester:secret:bn254:{}",
+            hex::encode(ByteFmt::encode(&*self.0))
+        )
+    }
+    fn decode(text: Text) -> anyhow::Result<Self> {
+        text.strip("attester:secret:bn254:")?
+            .decode_hex()
+            .map(Arc::new)
+            .map(Self)
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        // The secret itself should never be logged.
+        write!(fmt, "<secret for {}>", TextFmt::encode(&self.public()))
+    }
+}
+
+/// A public key for an attester.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PublicKey(pub(crate) bn254::PublicKey);
+
+impl ByteFmt for PublicKey {
+    fn encode(&self) -> Vec<u8> {
+        ByteFmt::encode(&self.0)
+    }
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        ByteFmt::decode(bytes).map(Self)
+    }
+}
+
+impl TextFmt for PublicKey {
+    fn encode(&self) -> String {
+        format!(
+            "attester:public:bn254:{}",
+            hex::encode(ByteFmt::encode(&self.0))
+        )
+    }
+    fn decode(text: Text) -> anyhow::Result<Self> {
+        text.strip("attester:public:bn254:")?.decode_hex().map(Self)
+    }
+}
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(&TextFmt::encode(self))
+    }
+}
+
+/// A signature from an attester.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Signature(pub(crate) bn254::Signature);
+
+impl Signature {
+    /// Verifies a message hash against a public key.
+    pub fn verify_hash(&self, msg_hash: &MsgHash, pk: &PublicKey) -> Result<(), Error> {
+        self.0.verify(&ByteFmt::encode(msg_hash), &pk.0)
+    }
+}
+
+impl ByteFmt for Signature {
+    fn encode(&self) -> Vec<u8> {
+        ByteFmt::encode(&self.0)
+    }
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        ByteFmt::decode(bytes).map(Self)
+    }
+}
+
+impl TextFmt for Signature {
+    fn encode(&self) -> String {
+        format!(
+            "attester:signature:bn254:{}",
+            hex::encode(ByteFmt::encode(&self.0))
+        )
+    }
+    fn decode(text: Text) -> anyhow::Result<Self> {
+        text.strip("attester:signature:bn254:")?
+            .decode_hex()
+            .map(Self)
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(&TextFmt::encode(self))
+    }
+}
+
+/// An aggregate signature from the attesters.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct AggregateSignature(pub(crate) bn254::AggregateSignature);
+
+impl AggregateSignature {
+    /// Adds a signature to the aggregation.
+    pub fn add(&mut self, sig: &Signature) {
+        self.0.add(&sig.0)
+    }
+
+    /// Verifies a message hash signed by all the given keys.
+    pub fn verify_hash<'a>(
+        &self,
+        msg_hash: &MsgHash,
+        keys: impl Iterator<Item = &'a PublicKey>,
+    ) -> Result<(), Error> {
+        let bytes = ByteFmt::encode(msg_hash);
+        self.0.verify(keys.map(|pk| (&bytes[..], &pk.0)))
+    }
+}
+
+impl ByteFmt for AggregateSignature {
+    fn encode(&self) -> Vec<u8> {
+        ByteFmt::encode(&self.0)
+    }
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        ByteFmt::decode(bytes).map(Self)
+    }
+}
+
+impl TextFmt for AggregateSignature {
+    fn encode(&self) -> String {
+        format!(
+            "attester:aggregate_signature:bn254:{}",
+            hex::encode(ByteFmt::encode(&self.0))
+        )
+    }
+    fn decode(text: Text) -> anyhow::Result<Self> {
+        text.strip("attester:aggregate_signature:bn254:")?
+            .decode_hex()
+            .map(Self)
+    }
+}
+
+impl fmt::Debug for AggregateSignature {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(&TextFmt::encode(self))
+    }
+}
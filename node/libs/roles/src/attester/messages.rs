@@ -0,0 +1,278 @@
+//! Messages signed by the attesters.
+use super::{AggregateSignature, Error, PublicKey, Signature};
+use crate::validator;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+use zksync_consensus_crypto::{keccak256::Keccak256, ByteFmt, Text, TextFmt};
+use zksync_consensus_utils::enum_util::{BadVariantError, Variant};
+
+/// Sequential number of a batch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BatchNumber(pub u64);
+
+impl BatchNumber {
+    /// Returns the next batch number.
+    pub fn next(self) -> Self {
+        Self(self.0 + 1)
+    }
+}
+
+/// Batch of the finalized blocks, identified by its last block.
+/// Attesters sign a batch once its last block is finalized.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Batch {
+    /// Hash of the genesis of the chain the batch belongs to.
+    pub genesis: validator::GenesisHash,
+    /// Number of the batch.
+    pub number: BatchNumber,
+    /// Number of the last block of the batch.
+    pub last_block: validator::BlockNumber,
+    /// Hash of the header of the last block of the batch.
+    pub last_block_hash: validator::BlockHeaderHash,
+}
+
+impl Batch {
+    /// Constructs the batch ending at the given finalized block.
+    pub fn new(
+        genesis: &validator::Genesis,
+        number: BatchNumber,
+        last_block: &validator::BlockHeader,
+    ) -> Self {
+        Self {
+            genesis: genesis.hash(),
+            number,
+            last_block: last_block.number,
+            last_block_hash: last_block.hash(),
+        }
+    }
+}
+
+/// A message that can be signed by an attester.
+#[allow(missing_docs)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Msg {
+    Batch(Batch),
+}
+
+impl Msg {
+    /// Returns the hash of the message.
+    pub fn hash(&self) -> MsgHash {
+        MsgHash(Keccak256::new(&zksync_protobuf::canonical(self)))
+    }
+}
+
+impl Variant<Msg> for Batch {
+    fn insert(self) -> Msg {
+        Msg::Batch(self)
+    }
+    fn extract(msg: Msg) -> Result<Self, BadVariantError> {
+        let Msg::Batch(this) = msg;
+        Ok(this)
+    }
+}
+
+/// Hash of a message.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct MsgHash(pub(crate) Keccak256);
+
+impl ByteFmt for MsgHash {
+    fn encode(&self) -> Vec<u8> {
+        ByteFmt::encode(&self.0)
+    }
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        ByteFmt::decode(bytes).map(Self)
+    }
+}
+
+impl TextFmt for MsgHash {
+    fn encode(&self) -> String {
+        format!(
+            "attester_msg:keccak256:{}",
+            hex::encode(ByteFmt::encode(&self.0))
+        )
+    }
+    fn decode(text: Text) -> anyhow::Result<Self> {
+        text.strip("attester_msg:keccak256:")?
+            .decode_hex()
+            .map(Self)
+    }
+}
+
+impl fmt::Debug for MsgHash {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(&TextFmt::encode(self))
+    }
+}
+
+/// Strongly typed signed message.
+/// WARNING: signature is not guaranteed to be valid.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signed<V: Variant<Msg>> {
+    /// The message that was signed.
+    pub msg: V,
+    /// The public key of the signer.
+    pub key: PublicKey,
+    /// The signature.
+    pub sig: Signature,
+}
+
+impl<V: Variant<Msg> + Clone> Signed<V> {
+    /// Verifies the signature.
+    pub fn verify(&self) -> Result<(), Error> {
+        self.sig
+            .verify_hash(&self.msg.clone().insert().hash(), &self.key)
+    }
+}
+
+/// Committee of the attesters of a chain, declared in the genesis.
+/// Attesters are ordered by their public keys.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Committee {
+    /// Attesters, ordered.
+    vec: Vec<PublicKey>,
+    /// Indices of the attesters in `vec`.
+    map: BTreeMap<PublicKey, usize>,
+}
+
+impl Committee {
+    /// Creates a committee from a list of attester public keys.
+    pub fn new(attesters: impl IntoIterator<Item = PublicKey>) -> anyhow::Result<Self> {
+        let mut set = BTreeSet::new();
+        for attester in attesters {
+            anyhow::ensure!(set.insert(attester), "Duplicate attester in Committee");
+        }
+        anyhow::ensure!(
+            !set.is_empty(),
+            "Committee must contain at least one attester"
+        );
+        Ok(Self {
+            vec: set.iter().cloned().collect(),
+            map: set.into_iter().enumerate().map(|(i, pk)| (pk, i)).collect(),
+        })
+    }
+
+    /// Iterates over the attesters.
+    pub fn iter(&self) -> impl Iterator<Item = &PublicKey> {
+        self.vec.iter()
+    }
+
+    /// Returns the number of attesters.
+    #[allow(clippy::len_without_is_empty)] // a valid `Committee` is always non-empty by construction
+    pub fn len(&self) -> usize {
+        self.vec.len()
+    }
+
+    /// Returns true if the given attester is in the committee.
+    pub fn contains(&self, attester: &PublicKey) -> bool {
+        self.map.contains_key(attester)
+    }
+
+    /// Gets the attester by its index in the committee.
+    pub fn get(&self, index: usize) -> Option<&PublicKey> {
+        self.vec.get(index)
+    }
+
+    /// Gets the index of the attester in the committee.
+    pub fn index(&self, attester: &PublicKey) -> Option<usize> {
+        self.map.get(attester).copied()
+    }
+
+    /// Number of signatures required to certify a batch.
+    /// Same as the consensus threshold of a validator set of the same size.
+    pub fn threshold(&self) -> usize {
+        validator::threshold(self.len())
+    }
+}
+
+/// Certificate of a batch: aggregate of the attester signatures of the same batch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchQC {
+    /// The batch that the certificate is for.
+    pub message: Batch,
+    /// The attesters that signed the batch.
+    pub signers: validator::Signers,
+    /// The aggregate signature of the batch.
+    pub signature: AggregateSignature,
+}
+
+/// Error returned by `BatchQC::verify()`.
+#[derive(thiserror::Error, Debug)]
+pub enum BatchQCVerifyError {
+    /// Batch of a different chain.
+    #[error("genesis mismatch")]
+    GenesisMismatch,
+    /// The chain has no attesters.
+    #[error("no attesters in genesis")]
+    NoAttesters,
+    /// Bad signer set.
+    #[error("signers set doesn't match the attester committee")]
+    BadSignersSet,
+    /// Not enough signers.
+    #[error("not enough signers: got {got}, want {want}")]
+    NotEnoughSigners {
+        /// Got signers.
+        got: usize,
+        /// Want signers.
+        want: usize,
+    },
+    /// Bad signature.
+    #[error("bad signature: {0:#}")]
+    BadSignature(#[source] Error),
+}
+
+impl BatchQC {
+    /// Creates an empty certificate of the batch, for the given committee.
+    pub fn new(message: Batch, committee: &Committee) -> Self {
+        Self {
+            message,
+            signers: validator::Signers::new(committee.len()),
+            signature: AggregateSignature::default(),
+        }
+    }
+
+    /// Adds an attester's signature.
+    /// Signature is assumed to be already verified.
+    pub fn add(&mut self, msg: &Signed<Batch>, committee: &Committee) {
+        if self.message != msg.msg {
+            return;
+        }
+        let Some(i) = committee.index(&msg.key) else {
+            return;
+        };
+        if self.signers.0[i] {
+            return;
+        }
+        self.signers.0.set(i, true);
+        self.signature.add(&msg.sig);
+    }
+
+    /// Verifies the certificate against the attester committee of the genesis.
+    pub fn verify(&self, genesis: &validator::Genesis) -> Result<(), BatchQCVerifyError> {
+        use BatchQCVerifyError as Error;
+        if self.message.genesis != genesis.hash() {
+            return Err(Error::GenesisMismatch);
+        }
+        let committee = genesis.attesters.as_ref().ok_or(Error::NoAttesters)?;
+        if self.signers.len() != committee.len() {
+            return Err(Error::BadSignersSet);
+        }
+        let num_signers = self.signers.count();
+        let threshold = committee.threshold();
+        if num_signers < threshold {
+            return Err(Error::NotEnoughSigners {
+                got: num_signers,
+                want: threshold,
+            });
+        }
+        let keys = committee
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.signers.0[*i])
+            .map(|(_, pk)| pk);
+        self.signature
+            .verify_hash(&self.message.clone().insert().hash(), keys)
+            .map_err(Error::BadSignature)
+    }
+}
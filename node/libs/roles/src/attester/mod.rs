@@ -0,0 +1,14 @@
+//! Attester role implementation.
+//! Attesters sign the batches of the finalized blocks (e.g. L1 batches), and their signatures
+//! are aggregated into batch certificates. Unlike validators, attesters don't participate
+//! in the consensus, so the role is cheap to run.
+
+#[cfg(test)]
+mod tests;
+
+mod conv;
+mod keys;
+mod messages;
+pub mod testonly;
+
+pub use self::{keys::*, messages::*};
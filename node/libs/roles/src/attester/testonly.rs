@@ -0,0 +1,101 @@
+//! Test-only utilities.
+use super::{
+    AggregateSignature, Batch, BatchNumber, BatchQC, Committee, Msg, MsgHash, PublicKey, SecretKey,
+    Signature, Signed,
+};
+use rand::{
+    distributions::{Distribution, Standard},
+    Rng,
+};
+use std::sync::Arc;
+use zksync_consensus_utils::enum_util::Variant;
+
+impl AggregateSignature {
+    /// Generates a new aggregate signature from a list of signatures.
+    pub fn aggregate<'a>(sigs: impl IntoIterator<Item = &'a Signature>) -> Self {
+        let mut agg = Self::default();
+        for sig in sigs {
+            agg.add(sig);
+        }
+        agg
+    }
+}
+
+impl Distribution<SecretKey> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> SecretKey {
+        SecretKey(Arc::new(rng.gen()))
+    }
+}
+
+impl Distribution<PublicKey> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> PublicKey {
+        PublicKey(rng.gen())
+    }
+}
+
+impl Distribution<Signature> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Signature {
+        Signature(rng.gen())
+    }
+}
+
+impl Distribution<AggregateSignature> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> AggregateSignature {
+        AggregateSignature(rng.gen())
+    }
+}
+
+impl Distribution<MsgHash> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> MsgHash {
+        MsgHash(rng.gen())
+    }
+}
+
+impl Distribution<Committee> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Committee {
+        let count = rng.gen_range(1..11);
+        Committee::new((0..count).map(|_| rng.gen())).unwrap()
+    }
+}
+
+impl Distribution<BatchNumber> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> BatchNumber {
+        BatchNumber(rng.gen())
+    }
+}
+
+impl Distribution<Batch> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Batch {
+        Batch {
+            genesis: rng.gen(),
+            number: rng.gen(),
+            last_block: rng.gen(),
+            last_block_hash: rng.gen(),
+        }
+    }
+}
+
+impl Distribution<BatchQC> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> BatchQC {
+        BatchQC {
+            message: rng.gen(),
+            signers: rng.gen(),
+            signature: rng.gen(),
+        }
+    }
+}
+
+impl Distribution<Msg> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Msg {
+        Msg::Batch(rng.gen())
+    }
+}
+
+impl<V: Variant<Msg> + Clone> Distribution<Signed<V>> for Standard
+where
+    Standard: Distribution<V>,
+{
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Signed<V> {
+        rng.gen::<SecretKey>().sign_msg(rng.gen())
+    }
+}
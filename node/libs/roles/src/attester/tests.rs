@@ -0,0 +1,132 @@
+use super::*;
+use crate::validator::{self, testonly::Setup};
+use assert_matches::assert_matches;
+use rand::Rng;
+use zksync_concurrency::ctx;
+use zksync_consensus_crypto::{ByteFmt, Text, TextFmt};
+use zksync_protobuf::testonly::test_encode_random;
+
+#[test]
+fn test_byte_encoding() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+
+    let sk: SecretKey = rng.gen();
+    assert_eq!(
+        sk.public(),
+        <SecretKey as ByteFmt>::decode(&ByteFmt::encode(&sk))
+            .unwrap()
+            .public()
+    );
+    let pk: PublicKey = rng.gen();
+    assert_eq!(pk, ByteFmt::decode(&ByteFmt::encode(&pk)).unwrap());
+    let sig: Signature = rng.gen();
+    assert_eq!(sig, ByteFmt::decode(&ByteFmt::encode(&sig)).unwrap());
+    let agg_sig: AggregateSignature = rng.gen();
+    assert_eq!(
+        agg_sig,
+        ByteFmt::decode(&ByteFmt::encode(&agg_sig)).unwrap()
+    );
+}
+
+#[test]
+fn test_text_encoding() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+
+    let sk: SecretKey = rng.gen();
+    let t = TextFmt::encode(&sk);
+    assert_eq!(
+        sk.public(),
+        Text::new(&t).decode::<SecretKey>().unwrap().public()
+    );
+    let pk: PublicKey = rng.gen();
+    let t = TextFmt::encode(&pk);
+    assert_eq!(pk, Text::new(&t).decode::<PublicKey>().unwrap());
+    // Attester keys are not interchangeable with the validator keys.
+    assert!(Text::new(&t).decode::<validator::PublicKey>().is_err());
+    let sig: Signature = rng.gen();
+    let t = TextFmt::encode(&sig);
+    assert_eq!(sig, Text::new(&t).decode::<Signature>().unwrap());
+    let msg_hash: MsgHash = rng.gen();
+    let t = TextFmt::encode(&msg_hash);
+    assert_eq!(msg_hash, Text::new(&t).decode::<MsgHash>().unwrap());
+}
+
+#[test]
+fn test_schema_encoding() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    test_encode_random::<Batch>(rng);
+    test_encode_random::<BatchQC>(rng);
+    test_encode_random::<Msg>(rng);
+    test_encode_random::<Signed<Batch>>(rng);
+    test_encode_random::<PublicKey>(rng);
+    test_encode_random::<Signature>(rng);
+    test_encode_random::<AggregateSignature>(rng);
+}
+
+#[test]
+fn test_genesis_attesters() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 4);
+    let hash = setup.genesis.hash();
+    setup.add_attesters(rng, 3);
+
+    // Attesters are a part of the genesis hash, and are preserved by the encoding.
+    assert_ne!(hash, setup.genesis.hash());
+    let genesis: validator::Genesis =
+        zksync_protobuf::decode(&zksync_protobuf::encode(&setup.genesis)).unwrap();
+    assert_eq!(setup.genesis, genesis);
+}
+
+#[test]
+fn test_batch_qc() {
+    use BatchQCVerifyError as Error;
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+
+    let mut setup1 = Setup::new(rng, 1);
+    setup1.add_attesters(rng, 6);
+    setup1.push_blocks(rng, 1);
+    let mut setup2 = setup1.clone();
+    setup2.add_attesters(rng, 6);
+    let committee = setup1.genesis.attesters.clone().unwrap();
+    let batch = Batch::new(&setup1.genesis, BatchNumber(7), setup1.blocks[0].header());
+
+    for i in 0..setup1.attester_keys.len() + 1 {
+        let mut qc = BatchQC::new(batch.clone(), &committee);
+        for key in &setup1.attester_keys[0..i] {
+            let vote = key.sign_msg(batch.clone());
+            vote.verify().unwrap();
+            qc.add(&vote, &committee);
+        }
+        if i >= committee.threshold() {
+            qc.verify(&setup1.genesis).unwrap();
+        } else {
+            assert_matches!(
+                qc.verify(&setup1.genesis),
+                Err(Error::NotEnoughSigners { .. })
+            );
+        }
+        // Different attester committee.
+        assert_matches!(qc.verify(&setup2.genesis), Err(Error::GenesisMismatch));
+    }
+
+    // Signatures of a different batch are ignored.
+    let mut qc = BatchQC::new(batch.clone(), &committee);
+    let mut other = batch.clone();
+    other.number = other.number.next();
+    for key in &setup1.attester_keys {
+        qc.add(&key.sign_msg(other.clone()), &committee);
+    }
+    assert_eq!(0, qc.signers.count());
+
+    // Chain without attesters.
+    let mut genesis = setup1.genesis.clone();
+    genesis.attesters = None;
+    let batch = Batch::new(&genesis, BatchNumber(7), setup1.blocks[0].header());
+    let qc = BatchQC::new(batch, &committee);
+    assert_matches!(qc.verify(&genesis), Err(Error::NoAttesters));
+}
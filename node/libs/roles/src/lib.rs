@@ -6,7 +6,10 @@
 //! - `Validator`: a node that participates in the consensus protocol, so it votes for blocks and produces blocks.
 //!                It also participates in the validator network, which is a mesh network just for validators. Not
 //!                every node has this role.
+//! - `Attester`: a node that signs the batches of the finalized blocks, so that the batches can be certified
+//!               (e.g. for the L1). Not every node has this role.
 
+pub mod attester;
 pub mod node;
 pub mod proto;
 pub mod validator;
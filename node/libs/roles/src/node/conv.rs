@@ -10,17 +10,53 @@ impl ProtoFmt for node::Msg {
         use proto::msg::T;
         Ok(match required(&r.t)? {
             T::SessionId(r) => Self::SessionId(node::SessionId(r.clone())),
+            T::RelayedBlock(r) => Self::RelayedBlock(ProtoFmt::read(r).context("relayed_block")?),
+            T::NodeAddr(r) => Self::NodeAddr(ProtoFmt::read(r).context("node_addr")?),
         })
     }
     fn build(&self) -> Self::Proto {
         use proto::msg::T;
         let t = match self {
             Self::SessionId(x) => T::SessionId(x.0.clone()),
+            Self::RelayedBlock(x) => T::RelayedBlock(x.build()),
+            Self::NodeAddr(x) => T::NodeAddr(x.build()),
         };
         Self::Proto { t: Some(t) }
     }
 }
 
+impl ProtoFmt for node::RelayedBlock {
+    type Proto = proto::RelayedBlock;
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            genesis: read_required(&r.genesis).context("genesis")?,
+            block: read_required(&r.block).context("block")?,
+        })
+    }
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            genesis: Some(self.genesis.build()),
+            block: Some(self.block.build()),
+        }
+    }
+}
+
+impl ProtoFmt for node::NodeAddr {
+    type Proto = proto::NodeAddr;
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            addr: read_required(&r.addr).context("addr")?,
+            timestamp: read_required(&r.timestamp).context("timestamp")?,
+        })
+    }
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            addr: Some(self.addr.build()),
+            timestamp: Some(self.timestamp.build()),
+        }
+    }
+}
+
 impl ProtoFmt for node::PublicKey {
     type Proto = proto::PublicKey;
     fn read(r: &Self::Proto) -> anyhow::Result<Self> {
@@ -102,6 +102,19 @@ impl fmt::Debug for PublicKey {
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Signature(pub(super) ed25519::Signature);
 
+impl Signature {
+    /// Verifies a batch of message hashes, each against its own public key.
+    /// On failure it doesn't tell which of the signatures are invalid.
+    pub fn verify_batch<'a>(
+        items: impl Iterator<Item = (&'a MsgHash, &'a PublicKey, &'a Signature)>,
+    ) -> Result<(), InvalidSignatureError> {
+        let items: Vec<_> = items
+            .map(|(msg_hash, pk, sig)| (ByteFmt::encode(msg_hash), &pk.0, &sig.0))
+            .collect();
+        ed25519::Signature::verify_batch(items.iter().map(|(msg, pk, sig)| (&msg[..], *pk, *sig)))
+    }
+}
+
 impl ByteFmt for Signature {
     fn encode(&self) -> Vec<u8> {
         ByteFmt::encode(&self.0)
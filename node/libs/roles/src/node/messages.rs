@@ -1,4 +1,6 @@
-use crate::node;
+use crate::{node, validator};
+use std::net;
+use zksync_concurrency::time;
 use zksync_consensus_crypto::{keccak256, ByteFmt, Text, TextFmt};
 use zksync_consensus_utils::enum_util::{BadVariantError, Variant};
 
@@ -6,12 +8,52 @@ use zksync_consensus_utils::enum_util::{BadVariantError, Variant};
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct SessionId(pub Vec<u8>);
 
+/// Finalized block relayed by a node to its peers.
+/// The `CommitQC` of the block authenticates the data itself,
+/// while the node signature over this message authenticates the relay path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelayedBlock {
+    /// Hash of the genesis of the chain the block belongs to.
+    pub genesis: validator::GenesisHash,
+    /// The relayed block.
+    pub block: validator::FinalBlock,
+}
+
+/// Public address of a node, announced by the node itself.
+/// Nodes exchange the signed addresses of their peers to discover new peers.
+/// The address with the highest timestamp is considered to be the newest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeAddr {
+    /// Address at which the node accepts gossip connections.
+    pub addr: net::SocketAddr,
+    /// Time at which this message has been signed.
+    pub timestamp: time::Utc,
+}
+
+/// Error returned by `Signed<RelayedBlock>::verify_relayed()`.
+#[derive(Debug, thiserror::Error)]
+pub enum RelayedBlockError {
+    /// Block was relayed for a different genesis.
+    #[error("genesis mismatch")]
+    GenesisMismatch,
+    /// Invalid signature of the relaying node.
+    #[error("relay signature: {0:#}")]
+    Signature(#[source] node::InvalidSignatureError),
+    /// Invalid block.
+    #[error("block: {0:#}")]
+    Block(#[source] validator::BlockValidationError),
+}
+
 /// A message that can be sent between nodes.
 #[allow(missing_docs)]
 #[derive(Debug)]
 pub enum Msg {
     // Authentication
     SessionId(SessionId),
+    // Block relaying
+    RelayedBlock(RelayedBlock),
+    // Peer discovery
+    NodeAddr(NodeAddr),
 }
 
 impl Msg {
@@ -26,7 +68,33 @@ impl Variant<Msg> for SessionId {
         Msg::SessionId(self)
     }
     fn extract(msg: Msg) -> Result<Self, BadVariantError> {
-        let Msg::SessionId(this) = msg;
+        let Msg::SessionId(this) = msg else {
+            return Err(BadVariantError);
+        };
+        Ok(this)
+    }
+}
+
+impl Variant<Msg> for RelayedBlock {
+    fn insert(self) -> Msg {
+        Msg::RelayedBlock(self)
+    }
+    fn extract(msg: Msg) -> Result<Self, BadVariantError> {
+        let Msg::RelayedBlock(this) = msg else {
+            return Err(BadVariantError);
+        };
+        Ok(this)
+    }
+}
+
+impl Variant<Msg> for NodeAddr {
+    fn insert(self) -> Msg {
+        Msg::NodeAddr(self)
+    }
+    fn extract(msg: Msg) -> Result<Self, BadVariantError> {
+        let Msg::NodeAddr(this) = msg else {
+            return Err(BadVariantError);
+        };
         Ok(this)
     }
 }
@@ -49,6 +117,34 @@ impl<V: Variant<Msg> + Clone> Signed<V> {
         self.key
             .verify(&self.msg.clone().insert().hash(), &self.sig)
     }
+
+    /// Verifies the signatures on multiple messages at once.
+    /// It is cheaper than calling `verify()` on every message, but on failure
+    /// it doesn't tell which of the signatures are invalid.
+    pub fn verify_batch<'a>(
+        msgs: impl Iterator<Item = &'a Self>,
+    ) -> Result<(), node::InvalidSignatureError>
+    where
+        V: 'a,
+    {
+        let msgs: Vec<_> = msgs.map(|m| (m.msg.clone().insert().hash(), m)).collect();
+        node::Signature::verify_batch(msgs.iter().map(|(hash, m)| (hash, &m.key, &m.sig)))
+    }
+}
+
+impl Signed<RelayedBlock> {
+    /// Verifies both the relay path (signature of the relaying node)
+    /// and the data (justification of the block) against `genesis`.
+    pub fn verify_relayed(&self, genesis: &validator::Genesis) -> Result<(), RelayedBlockError> {
+        if self.msg.genesis != genesis.hash() {
+            return Err(RelayedBlockError::GenesisMismatch);
+        }
+        self.verify().map_err(RelayedBlockError::Signature)?;
+        self.msg
+            .block
+            .verify(genesis)
+            .map_err(RelayedBlockError::Block)
+    }
 }
 
 /// The hash of a message.
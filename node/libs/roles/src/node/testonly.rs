@@ -1,9 +1,10 @@
-use super::{Msg, MsgHash, SecretKey, SessionId, Signature, Signed};
+use super::{Msg, MsgHash, NodeAddr, RelayedBlock, SecretKey, SessionId, Signature, Signed};
 use rand::{
     distributions::{Distribution, Standard},
     Rng,
 };
 use std::sync::Arc;
+use zksync_concurrency::time;
 use zksync_consensus_utils::enum_util::Variant;
 
 impl Distribution<MsgHash> for Standard {
@@ -39,3 +40,24 @@ impl Distribution<SessionId> for Standard {
         SessionId((0..n).map(|_| rng.gen()).collect())
     }
 }
+
+impl Distribution<RelayedBlock> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> RelayedBlock {
+        RelayedBlock {
+            genesis: rng.gen(),
+            block: rng.gen(),
+        }
+    }
+}
+
+impl Distribution<NodeAddr> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> NodeAddr {
+        NodeAddr {
+            addr: std::net::SocketAddr::new(
+                std::net::IpAddr::from(rng.gen::<[u8; 16]>()),
+                rng.gen(),
+            ),
+            timestamp: time::UNIX_EPOCH + time::Duration::seconds(rng.gen_range(0..1000000000)),
+        }
+    }
+}
@@ -1,4 +1,6 @@
 use super::*;
+use crate::validator;
+use assert_matches::assert_matches;
 use rand::Rng;
 use zksync_concurrency::ctx;
 use zksync_consensus_crypto::{ByteFmt, Text, TextFmt};
@@ -38,6 +40,8 @@ fn test_schema_encoding() {
     let ctx = &ctx::test_root(&ctx::RealClock);
     let rng = &mut ctx.rng();
     test_encode_random::<Signed<SessionId>>(rng);
+    test_encode_random::<Signed<RelayedBlock>>(rng);
+    test_encode_random::<Signed<NodeAddr>>(rng);
     let key = rng.gen::<SecretKey>().public();
     test_encode(rng, &key);
     test_encode_random::<Signature>(rng);
@@ -59,3 +63,59 @@ fn test_public_verify() {
     // Mismatching key.
     assert!(key2.public().verify(&msg1, &sig1).is_err());
 }
+
+#[test]
+fn test_verify_batch() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut addrs: Vec<Signed<NodeAddr>> = (0..5)
+        .map(|_| SecretKey::generate().sign_msg(rng.gen::<NodeAddr>()))
+        .collect();
+    Signed::verify_batch(addrs.iter()).unwrap();
+    Signed::<NodeAddr>::verify_batch([].iter()).unwrap();
+
+    // Signature of another node.
+    addrs[0].sig = addrs[1].sig.clone();
+    assert!(Signed::verify_batch(addrs.iter()).is_err());
+}
+
+#[test]
+fn test_verify_relayed() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = validator::testonly::Setup::new(rng, 3);
+    setup.push_blocks(rng, 1);
+    let key: SecretKey = rng.gen();
+    let relayed = key.sign_msg(RelayedBlock {
+        genesis: setup.genesis.hash(),
+        block: setup.blocks[0].clone(),
+    });
+    relayed.verify_relayed(&setup.genesis).unwrap();
+
+    // Mismatching relay key.
+    let mut bad = relayed.clone();
+    bad.key = rng.gen::<SecretKey>().public();
+    assert_matches!(
+        bad.verify_relayed(&setup.genesis),
+        Err(RelayedBlockError::Signature(_))
+    );
+
+    // Mismatching genesis.
+    let other = validator::testonly::Setup::new(rng, 3);
+    assert_matches!(
+        relayed.verify_relayed(&other.genesis),
+        Err(RelayedBlockError::GenesisMismatch)
+    );
+
+    // Block not matching its justification, signed by the relay.
+    let mut block = setup.blocks[0].clone();
+    block.payload = rng.gen();
+    let bad = key.sign_msg(RelayedBlock {
+        genesis: setup.genesis.hash(),
+        block,
+    });
+    assert_matches!(
+        bad.verify_relayed(&setup.genesis),
+        Err(RelayedBlockError::Block(_))
+    );
+}
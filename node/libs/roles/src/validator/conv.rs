@@ -1,10 +1,14 @@
 use super::{
     AggregateSignature, BlockHeader, BlockHeaderHash, BlockNumber, CommitQC, ConsensusMsg,
-    FinalBlock, Fork, ForkNumber, Genesis, GenesisHash, LeaderCommit, LeaderPrepare, Msg, MsgHash,
-    NetAddress, Payload, PayloadHash, Phase, PrepareQC, ProtocolVersion, PublicKey, ReplicaCommit,
-    ReplicaPrepare, Signature, Signed, Signers, ValidatorSet, View, ViewNumber,
+    DoubleSignProof, FinalBlock, FinalityProof, Fork, ForkNumber, Genesis, GenesisHash, Heartbeat,
+    KeyRotation, KeyRotationCert, KeyRotationHash, KeyRotations, LeaderCommit, LeaderPrepare,
+    LeaderTimeout, Msg, MsgHash, NetAddress, Payload, PayloadChunkProof, PayloadHash, PayloadRoot,
+    Phase, PrepareQC, ProtocolUpgrade, ProtocolUpgradeHash, ProtocolUpgradeQC, ProtocolUpgrades,
+    ProtocolVersion, PublicKey, ReplicaCommit, ReplicaPrepare, ReplicaTimeout, Signature,
+    SignatureScheme, Signed, Signers, TimeoutQC, ValidatorSet, ValidatorSetCommitment,
+    ValidatorSetMembershipProof, View, ViewNumber,
 };
-use crate::{node::SessionId, proto::validator as proto};
+use crate::{attester, node::SessionId, proto::validator as proto};
 use anyhow::Context as _;
 use std::collections::BTreeMap;
 use zksync_consensus_crypto::ByteFmt;
@@ -39,19 +43,103 @@ impl ProtoFmt for Genesis {
             .map(|(i, v)| PublicKey::read(v).context(i))
             .collect::<Result<_, _>>()
             .context("validators")?;
-        Ok(Self {
+        let attesters: Vec<_> = r
+            .attesters
+            .iter()
+            .enumerate()
+            .map(|(i, v)| attester::PublicKey::read(v).context(i))
+            .collect::<Result<_, _>>()
+            .context("attesters")?;
+        let mut genesis = Self {
             fork: read_required(&r.fork).context("fork")?,
             validators: ValidatorSet::new(validators.into_iter()).context("validators")?,
-        })
+            max_payload_size: r
+                .max_payload_size
+                .map(usize::try_from)
+                .transpose()
+                .context("max_payload_size")?,
+            key_rotations: KeyRotations::default(),
+            attesters: match attesters.is_empty() {
+                true => None,
+                false => Some(attester::Committee::new(attesters).context("attesters")?),
+            },
+            protocol_version: r
+                .protocol_version
+                .map_or(ProtocolVersion::EARLIEST, ProtocolVersion),
+            protocol_upgrades: ProtocolUpgrades::default(),
+            signature_scheme: read_signature_scheme(&r.signature_scheme)
+                .context("signature_scheme")?,
+        };
+        anyhow::ensure!(
+            genesis.validators.scheme() == genesis.signature_scheme,
+            "validators don't match signature_scheme"
+        );
+        for (i, cert) in r.key_rotations.iter().enumerate() {
+            let cert = KeyRotationCert::read(cert)
+                .context(i)
+                .context("key_rotations")?;
+            let mut key_rotations = std::mem::take(&mut genesis.key_rotations);
+            key_rotations
+                .add(&genesis, cert)
+                .context(i)
+                .context("key_rotations")?;
+            genesis.key_rotations = key_rotations;
+        }
+        for (i, qc) in r.protocol_upgrades.iter().enumerate() {
+            let qc = ProtocolUpgradeQC::read(qc)
+                .context(i)
+                .context("protocol_upgrades")?;
+            let mut protocol_upgrades = std::mem::take(&mut genesis.protocol_upgrades);
+            // The upgrades of the genesis config are scheduled before the first block of the fork.
+            let head = genesis.fork.first_block;
+            protocol_upgrades
+                .add(&genesis, qc, head)
+                .context(i)
+                .context("protocol_upgrades")?;
+            genesis.protocol_upgrades = protocol_upgrades;
+        }
+        Ok(genesis)
     }
     fn build(&self) -> Self::Proto {
         Self::Proto {
             fork: Some(self.fork.build()),
             validators: self.validators.iter().map(|x| x.build()).collect(),
+            max_payload_size: self.max_payload_size.map(|x| x.try_into().unwrap()),
+            key_rotations: self.key_rotations.certs().map(|x| x.build()).collect(),
+            attesters: self
+                .attesters
+                .iter()
+                .flat_map(|c| c.iter().map(|x| x.build()))
+                .collect(),
+            // Omitted for the earliest version, so that the hashes of the older geneses
+            // are preserved.
+            protocol_version: (self.protocol_version != ProtocolVersion::EARLIEST)
+                .then_some(self.protocol_version.0),
+            protocol_upgrades: self.protocol_upgrades.certs().map(|x| x.build()).collect(),
+            // Omitted for bn254, so that the hashes of the older geneses are preserved.
+            signature_scheme: (self.signature_scheme != SignatureScheme::Bn254)
+                .then(|| build_signature_scheme(self.signature_scheme).into()),
         }
     }
 }
 
+fn read_signature_scheme(r: &Option<i32>) -> anyhow::Result<SignatureScheme> {
+    let Some(r) = r else {
+        return Ok(SignatureScheme::Bn254);
+    };
+    Ok(match proto::SignatureScheme::try_from(*r)? {
+        proto::SignatureScheme::Bn254 => SignatureScheme::Bn254,
+        proto::SignatureScheme::Secp256k1 => SignatureScheme::Secp256k1,
+    })
+}
+
+fn build_signature_scheme(x: SignatureScheme) -> proto::SignatureScheme {
+    match x {
+        SignatureScheme::Bn254 => proto::SignatureScheme::Bn254,
+        SignatureScheme::Secp256k1 => proto::SignatureScheme::Secp256k1,
+    }
+}
+
 impl ProtoFmt for GenesisHash {
     type Proto = proto::GenesisHash;
     fn read(r: &Self::Proto) -> anyhow::Result<Self> {
@@ -88,6 +176,82 @@ impl ProtoFmt for PayloadHash {
     }
 }
 
+impl ProtoFmt for PayloadRoot {
+    type Proto = proto::PayloadRoot;
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self(ByteFmt::decode(required(&r.keccak256)?)?))
+    }
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            keccak256: Some(self.0.encode()),
+        }
+    }
+}
+
+impl ProtoFmt for PayloadChunkProof {
+    type Proto = proto::PayloadChunkProof;
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        let mut path = vec![];
+        for (i, hash) in r.path.iter().enumerate() {
+            path.push(ByteFmt::decode(hash).with_context(|| format!("path[{i}]"))?);
+        }
+        Ok(Self {
+            payload_size: (*required(&r.payload_size).context("payload_size")?)
+                .try_into()
+                .context("payload_size")?,
+            index: (*required(&r.index).context("index")?)
+                .try_into()
+                .context("index")?,
+            path,
+        })
+    }
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            payload_size: Some(self.payload_size.try_into().unwrap()),
+            index: Some(self.index.try_into().unwrap()),
+            path: self.path.iter().map(ByteFmt::encode).collect(),
+        }
+    }
+}
+
+impl ProtoFmt for ValidatorSetCommitment {
+    type Proto = proto::ValidatorSetCommitment;
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self(ByteFmt::decode(required(&r.keccak256)?)?))
+    }
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            keccak256: Some(self.0.encode()),
+        }
+    }
+}
+
+impl ProtoFmt for ValidatorSetMembershipProof {
+    type Proto = proto::ValidatorSetMembershipProof;
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        let mut path = vec![];
+        for (i, hash) in r.path.iter().enumerate() {
+            path.push(ByteFmt::decode(hash).with_context(|| format!("path[{i}]"))?);
+        }
+        Ok(Self {
+            size: (*required(&r.size).context("size")?)
+                .try_into()
+                .context("size")?,
+            index: (*required(&r.index).context("index")?)
+                .try_into()
+                .context("index")?,
+            path,
+        })
+    }
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            size: Some(self.size.try_into().unwrap()),
+            index: Some(self.index.try_into().unwrap()),
+            path: self.path.iter().map(ByteFmt::encode).collect(),
+        }
+    }
+}
+
 impl ProtoFmt for BlockHeader {
     type Proto = proto::BlockHeader;
     fn read(r: &Self::Proto) -> anyhow::Result<Self> {
@@ -95,6 +259,9 @@ impl ProtoFmt for BlockHeader {
             parent: read_optional(&r.parent).context("parent")?,
             number: BlockNumber(*required(&r.number).context("number")?),
             payload: read_required(&r.payload).context("payload")?,
+            payload_root: read_optional(&r.payload_root).context("payload_root")?,
+            key_rotation: read_optional(&r.key_rotation).context("key_rotation")?,
+            protocol_upgrade: read_optional(&r.protocol_upgrade).context("protocol_upgrade")?,
         })
     }
     fn build(&self) -> Self::Proto {
@@ -102,6 +269,9 @@ impl ProtoFmt for BlockHeader {
             parent: self.parent.as_ref().map(ProtoFmt::build),
             number: Some(self.number.0),
             payload: Some(self.payload.build()),
+            payload_root: self.payload_root.as_ref().map(ProtoFmt::build),
+            key_rotation: self.key_rotation.as_ref().map(ProtoFmt::build),
+            protocol_upgrade: self.protocol_upgrade.as_ref().map(ProtoFmt::build),
         }
     }
 }
@@ -112,6 +282,8 @@ impl ProtoFmt for FinalBlock {
         Ok(Self {
             payload: Payload(required(&r.payload).context("payload")?.clone()),
             justification: read_required(&r.justification).context("justification")?,
+            key_rotation: read_optional(&r.key_rotation).context("key_rotation")?,
+            protocol_upgrade: read_optional(&r.protocol_upgrade).context("protocol_upgrade")?,
         })
     }
 
@@ -119,6 +291,8 @@ impl ProtoFmt for FinalBlock {
         Self::Proto {
             payload: Some(self.payload.0.clone()),
             justification: Some(self.justification.build()),
+            key_rotation: self.key_rotation.as_ref().map(ProtoFmt::build),
+            protocol_upgrade: self.protocol_upgrade.as_ref().map(ProtoFmt::build),
         }
     }
 }
@@ -135,6 +309,10 @@ impl ProtoFmt for ConsensusMsg {
             T::ReplicaCommit(r) => Self::ReplicaCommit(ProtoFmt::read(r).context("ReplicaCommit")?),
             T::LeaderPrepare(r) => Self::LeaderPrepare(ProtoFmt::read(r).context("LeaderPrepare")?),
             T::LeaderCommit(r) => Self::LeaderCommit(ProtoFmt::read(r).context("LeaderCommit")?),
+            T::ReplicaTimeout(r) => {
+                Self::ReplicaTimeout(ProtoFmt::read(r).context("ReplicaTimeout")?)
+            }
+            T::LeaderTimeout(r) => Self::LeaderTimeout(ProtoFmt::read(r).context("LeaderTimeout")?),
         })
     }
 
@@ -146,6 +324,8 @@ impl ProtoFmt for ConsensusMsg {
             Self::ReplicaCommit(x) => T::ReplicaCommit(x.build()),
             Self::LeaderPrepare(x) => T::LeaderPrepare(x.build()),
             Self::LeaderCommit(x) => T::LeaderCommit(x.build()),
+            Self::ReplicaTimeout(x) => T::ReplicaTimeout(x.build()),
+            Self::LeaderTimeout(x) => T::LeaderTimeout(x.build()),
         };
 
         Self::Proto { t: Some(t) }
@@ -218,6 +398,11 @@ impl ProtoFmt for LeaderPrepare {
             proposal: read_required(&r.proposal).context("proposal")?,
             proposal_payload: r.proposal_payload.as_ref().map(|p| Payload(p.clone())),
             justification: read_required(&r.justification).context("justification")?,
+            proposal_key_rotation: read_optional(&r.proposal_key_rotation)
+                .context("proposal_key_rotation")?,
+            timeout_qc: read_optional(&r.timeout_qc).context("timeout_qc")?,
+            proposal_protocol_upgrade: read_optional(&r.proposal_protocol_upgrade)
+                .context("proposal_protocol_upgrade")?,
         })
     }
 
@@ -226,6 +411,9 @@ impl ProtoFmt for LeaderPrepare {
             proposal: Some(self.proposal.build()),
             proposal_payload: self.proposal_payload.as_ref().map(|p| p.0.clone()),
             justification: Some(self.justification.build()),
+            proposal_key_rotation: self.proposal_key_rotation.as_ref().map(ProtoFmt::build),
+            timeout_qc: self.timeout_qc.as_ref().map(ProtoFmt::build),
+            proposal_protocol_upgrade: self.proposal_protocol_upgrade.as_ref().map(ProtoFmt::build),
         }
     }
 }
@@ -246,6 +434,38 @@ impl ProtoFmt for LeaderCommit {
     }
 }
 
+impl ProtoFmt for ReplicaTimeout {
+    type Proto = proto::ReplicaTimeout;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            view: read_required(&r.view).context("view")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            view: Some(self.view.build()),
+        }
+    }
+}
+
+impl ProtoFmt for LeaderTimeout {
+    type Proto = proto::LeaderTimeout;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            justification: read_required(&r.justification).context("justification")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            justification: Some(self.justification.build()),
+        }
+    }
+}
+
 impl ProtoFmt for Signers {
     type Proto = zksync_protobuf::proto::std::BitVector;
 
@@ -274,6 +494,7 @@ impl ProtoFmt for PrepareQC {
         Ok(Self {
             view: read_required(&r.view).context("view")?,
             map,
+            rotated: read_optional(&r.rotated).context("rotated")?,
             signature: read_required(&r.sig).context("sig")?,
         })
     }
@@ -290,6 +511,7 @@ impl ProtoFmt for PrepareQC {
             msgs,
             signers,
             sig: Some(self.signature.build()),
+            rotated: self.rotated.as_ref().map(|x| x.build()),
         }
     }
 }
@@ -298,9 +520,41 @@ impl ProtoFmt for CommitQC {
     type Proto = proto::CommitQc;
 
     fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        let signers: Signers = read_required(&r.signers).context("signers")?;
+        let rotated = read_optional(&r.rotated)
+            .context("rotated")?
+            .unwrap_or_else(|| Signers::new(signers.len()));
+        Ok(Self {
+            message: read_required(&r.msg).context("msg")?,
+            signers,
+            rotated,
+            signature: read_required(&r.sig).context("sig")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            msg: Some(self.message.build()),
+            signers: Some(self.signers.build()),
+            sig: Some(self.signature.build()),
+            // Omitted if empty, to keep the encoding of QCs without rotated keys unchanged.
+            rotated: (!self.rotated.is_empty()).then(|| self.rotated.build()),
+        }
+    }
+}
+
+impl ProtoFmt for TimeoutQC {
+    type Proto = proto::TimeoutQc;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        let signers: Signers = read_required(&r.signers).context("signers")?;
+        let rotated = read_optional(&r.rotated)
+            .context("rotated")?
+            .unwrap_or_else(|| Signers::new(signers.len()));
         Ok(Self {
             message: read_required(&r.msg).context("msg")?,
-            signers: read_required(&r.signers).context("signers")?,
+            signers,
+            rotated,
             signature: read_required(&r.sig).context("sig")?,
         })
     }
@@ -310,6 +564,7 @@ impl ProtoFmt for CommitQC {
             msg: Some(self.message.build()),
             signers: Some(self.signers.build()),
             sig: Some(self.signature.build()),
+            rotated: (!self.rotated.is_empty()).then(|| self.rotated.build()),
         }
     }
 }
@@ -355,6 +610,136 @@ impl ProtoFmt for NetAddress {
     }
 }
 
+impl ProtoFmt for Heartbeat {
+    type Proto = proto::Heartbeat;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            genesis: read_required(&r.genesis).context("genesis")?,
+            timestamp: read_required(&r.timestamp).context("timestamp")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            genesis: Some(self.genesis.build()),
+            timestamp: Some(self.timestamp.build()),
+        }
+    }
+}
+
+impl ProtoFmt for KeyRotation {
+    type Proto = proto::KeyRotation;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            genesis: read_required(&r.genesis).context("genesis")?,
+            old_key: read_required(&r.old_key).context("old_key")?,
+            new_key: read_required(&r.new_key).context("new_key")?,
+            activation: ViewNumber(*required(&r.activation).context("activation")?),
+            grace_period: *required(&r.grace_period).context("grace_period")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            genesis: Some(self.genesis.build()),
+            old_key: Some(self.old_key.build()),
+            new_key: Some(self.new_key.build()),
+            activation: Some(self.activation.0),
+            grace_period: Some(self.grace_period),
+        }
+    }
+}
+
+impl ProtoFmt for KeyRotationHash {
+    type Proto = proto::KeyRotationHash;
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self(ByteFmt::decode(required(&r.keccak256)?)?))
+    }
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            keccak256: Some(self.0.encode()),
+        }
+    }
+}
+
+impl ProtoFmt for KeyRotationCert {
+    type Proto = proto::KeyRotationCert;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            rotation: read_required(&r.rotation).context("rotation")?,
+            new_key_sig: read_required(&r.new_key_sig).context("new_key_sig")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            rotation: Some(self.rotation.build()),
+            new_key_sig: Some(self.new_key_sig.build()),
+        }
+    }
+}
+
+impl ProtoFmt for ProtocolUpgrade {
+    type Proto = proto::ProtocolUpgrade;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            genesis: read_required(&r.genesis).context("genesis")?,
+            version: ProtocolVersion(*required(&r.version).context("version")?),
+            activation: BlockNumber(*required(&r.activation).context("activation")?),
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            genesis: Some(self.genesis.build()),
+            version: Some(self.version.0),
+            activation: Some(self.activation.0),
+        }
+    }
+}
+
+impl ProtoFmt for ProtocolUpgradeHash {
+    type Proto = proto::ProtocolUpgradeHash;
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self(ByteFmt::decode(required(&r.keccak256)?)?))
+    }
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            keccak256: Some(self.0.encode()),
+        }
+    }
+}
+
+impl ProtoFmt for ProtocolUpgradeQC {
+    type Proto = proto::ProtocolUpgradeQc;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        let signers: Signers = read_required(&r.signers).context("signers")?;
+        let rotated = read_optional(&r.rotated)
+            .context("rotated")?
+            .unwrap_or_else(|| Signers::new(signers.len()));
+        Ok(Self {
+            message: read_required(&r.msg).context("msg")?,
+            signers,
+            rotated,
+            signature: read_required(&r.sig).context("sig")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            msg: Some(self.message.build()),
+            signers: Some(self.signers.build()),
+            sig: Some(self.signature.build()),
+            rotated: (!self.rotated.is_empty()).then(|| self.rotated.build()),
+        }
+    }
+}
+
 impl ProtoFmt for Msg {
     type Proto = proto::Msg;
 
@@ -364,6 +749,11 @@ impl ProtoFmt for Msg {
             T::Consensus(r) => Self::Consensus(ProtoFmt::read(r).context("Consensus")?),
             T::SessionId(r) => Self::SessionId(SessionId(r.clone())),
             T::NetAddress(r) => Self::NetAddress(ProtoFmt::read(r).context("NetAddress")?),
+            T::Heartbeat(r) => Self::Heartbeat(ProtoFmt::read(r).context("Heartbeat")?),
+            T::KeyRotation(r) => Self::KeyRotation(ProtoFmt::read(r).context("KeyRotation")?),
+            T::ProtocolUpgrade(r) => {
+                Self::ProtocolUpgrade(ProtoFmt::read(r).context("ProtocolUpgrade")?)
+            }
         })
     }
 
@@ -374,12 +764,51 @@ impl ProtoFmt for Msg {
             Self::Consensus(x) => T::Consensus(x.build()),
             Self::SessionId(x) => T::SessionId(x.0.clone()),
             Self::NetAddress(x) => T::NetAddress(x.build()),
+            Self::Heartbeat(x) => T::Heartbeat(x.build()),
+            Self::KeyRotation(x) => T::KeyRotation(x.build()),
+            Self::ProtocolUpgrade(x) => T::ProtocolUpgrade(x.build()),
         };
 
         Self::Proto { t: Some(t) }
     }
 }
 
+impl ProtoFmt for DoubleSignProof {
+    type Proto = proto::DoubleSignProof;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            first: read_required(&r.first).context("first")?,
+            second: read_required(&r.second).context("second")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            first: Some(self.first.build()),
+            second: Some(self.second.build()),
+        }
+    }
+}
+
+impl ProtoFmt for FinalityProof {
+    type Proto = proto::FinalityProof;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            genesis: read_required(&r.genesis).context("genesis")?,
+            justification: read_required(&r.justification).context("justification")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            genesis: Some(self.genesis.build()),
+            justification: Some(self.justification.build()),
+        }
+    }
+}
+
 impl ProtoFmt for MsgHash {
     type Proto = proto::MsgHash;
 
@@ -416,13 +845,20 @@ impl ProtoFmt for PublicKey {
     type Proto = proto::PublicKey;
 
     fn read(r: &Self::Proto) -> anyhow::Result<Self> {
-        Ok(Self(ByteFmt::decode(required(&r.bn254)?)?))
+        use proto::public_key::T;
+        Ok(match r.t.as_ref().context("missing")? {
+            T::Bn254(r) => Self::Bn254(ByteFmt::decode(r).context("bn254")?),
+            T::Secp256k1(r) => Self::Secp256k1(ByteFmt::decode(r).context("secp256k1")?),
+        })
     }
 
     fn build(&self) -> Self::Proto {
-        Self::Proto {
-            bn254: Some(self.0.encode()),
-        }
+        use proto::public_key::T;
+        let t = match self {
+            Self::Bn254(x) => T::Bn254(x.encode()),
+            Self::Secp256k1(x) => T::Secp256k1(x.encode()),
+        };
+        Self::Proto { t: Some(t) }
     }
 }
 
@@ -430,13 +866,20 @@ impl ProtoFmt for Signature {
     type Proto = proto::Signature;
 
     fn read(r: &Self::Proto) -> anyhow::Result<Self> {
-        Ok(Self(ByteFmt::decode(required(&r.bn254)?)?))
+        use proto::signature::T;
+        Ok(match r.t.as_ref().context("missing")? {
+            T::Bn254(r) => Self::Bn254(ByteFmt::decode(r).context("bn254")?),
+            T::Secp256k1(r) => Self::Secp256k1(ByteFmt::decode(r).context("secp256k1")?),
+        })
     }
 
     fn build(&self) -> Self::Proto {
-        Self::Proto {
-            bn254: Some(self.0.encode()),
-        }
+        use proto::signature::T;
+        let t = match self {
+            Self::Bn254(x) => T::Bn254(x.encode()),
+            Self::Secp256k1(x) => T::Secp256k1(x.encode()),
+        };
+        Self::Proto { t: Some(t) }
     }
 }
 
@@ -444,12 +887,19 @@ impl ProtoFmt for AggregateSignature {
     type Proto = proto::AggregateSignature;
 
     fn read(r: &Self::Proto) -> anyhow::Result<Self> {
-        Ok(Self(ByteFmt::decode(required(&r.bn254)?)?))
+        use proto::aggregate_signature::T;
+        Ok(match r.t.as_ref().context("missing")? {
+            T::Bn254(r) => Self::Bn254(ByteFmt::decode(r).context("bn254")?),
+            T::Secp256k1(r) => Self::Secp256k1(ByteFmt::decode(r).context("secp256k1")?),
+        })
     }
 
     fn build(&self) -> Self::Proto {
-        Self::Proto {
-            bn254: Some(self.0.encode()),
-        }
+        use proto::aggregate_signature::T;
+        let t = match self {
+            Self::Bn254(x) => T::Bn254(x.encode()),
+            Self::Secp256k1(x) => T::Secp256k1(x.encode()),
+        };
+        Self::Proto { t: Some(t) }
     }
 }
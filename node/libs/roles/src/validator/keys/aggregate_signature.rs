@@ -1,20 +1,58 @@
-use super::{Error, PublicKey, Signature};
+use super::{Error, PublicKey, Signature, SignatureScheme, SECP256K1_TAG};
 use crate::validator::messages::{Msg, MsgHash};
+use anyhow::Context as _;
 use std::fmt;
-use zksync_consensus_crypto::{bn254, ByteFmt, Text, TextFmt};
+use zksync_consensus_crypto::{bn254, secp256k1, ByteFmt, Text, TextFmt};
 use zksync_consensus_utils::enum_util::Variant;
 
 /// An aggregate signature from a validator.
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
-pub struct AggregateSignature(pub(crate) bn254::AggregateSignature);
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AggregateSignature {
+    /// bn254 aggregate signature.
+    Bn254(bn254::AggregateSignature),
+    /// Set of secp256k1 signatures.
+    Secp256k1(secp256k1::AggregateSignature),
+}
+
+/// Empty bn254 aggregate signature. It adopts the scheme of the first added signature.
+impl Default for AggregateSignature {
+    fn default() -> Self {
+        Self::Bn254(bn254::AggregateSignature::default())
+    }
+}
 
 impl AggregateSignature {
-    /// Add a signature to the aggregation.
-    pub fn add(&mut self, sig: &Signature) {
-        self.0.add(&sig.0)
+    /// Signature scheme of the aggregate signature.
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            Self::Bn254(_) => SignatureScheme::Bn254,
+            Self::Secp256k1(_) => SignatureScheme::Secp256k1,
+        }
     }
 
-    /// Verify a list of messages against a list of public keys.
+    /// Add a signature to the aggregation. `pos` is the position of the signer among
+    /// the signers of the aggregate, i.e. the number of signers with a lower index.
+    /// An empty aggregate adopts the scheme of the signature, otherwise a signature
+    /// of a different scheme is rejected.
+    pub fn add(&mut self, pos: usize, sig: &Signature) -> Result<(), Error> {
+        match (&mut *self, sig) {
+            (Self::Bn254(agg), Signature::Bn254(sig)) => agg.add(sig),
+            (Self::Secp256k1(agg), Signature::Secp256k1(sig)) => agg.add(pos, sig),
+            // The emptiness is checked only on a scheme mismatch, which for a secp256k1
+            // aggregate happens just once, so that adding a vote stays cheap.
+            (Self::Bn254(agg), Signature::Secp256k1(sig))
+                if *agg == bn254::AggregateSignature::default() =>
+            {
+                let mut agg = secp256k1::AggregateSignature::default();
+                agg.add(pos, sig);
+                *self = Self::Secp256k1(agg);
+            }
+            _ => return Err(Error::SchemeMismatch),
+        }
+        Ok(())
+    }
+
+    /// Verify a list of messages against a list of public keys, ordered by the signers.
     pub(crate) fn verify_messages<'a, V: Variant<Msg>>(
         &self,
         messages_and_keys: impl Iterator<Item = (V, &'a PublicKey)>,
@@ -24,43 +62,110 @@ impl AggregateSignature {
         self.verify_hash(hashes_and_keys)
     }
 
-    /// Verify a message hash against a list of public keys.
+    /// Verify a message hash against a list of public keys, ordered by the signers.
     pub(crate) fn verify_hash<'a>(
         &self,
         hashes_and_keys: impl Iterator<Item = (MsgHash, &'a PublicKey)>,
     ) -> Result<(), Error> {
-        let bytes_and_pks: Vec<_> = hashes_and_keys
-            .map(|(hash, pk)| (hash.0.as_bytes().to_owned(), &pk.0))
-            .collect();
+        match self {
+            Self::Bn254(agg) => {
+                let bytes_and_pks: Vec<_> = hashes_and_keys
+                    .map(|(hash, pk)| match pk {
+                        PublicKey::Bn254(pk) => Ok((hash.0.as_bytes().to_owned(), pk)),
+                        PublicKey::Secp256k1(_) => Err(Error::SchemeMismatch),
+                    })
+                    .collect::<Result<_, _>>()?;
 
-        let bytes_and_pks = bytes_and_pks.iter().map(|(bytes, pk)| (&bytes[..], *pk));
+                let bytes_and_pks = bytes_and_pks.iter().map(|(bytes, pk)| (&bytes[..], *pk));
 
-        self.0.verify(bytes_and_pks)
+                Ok(agg.verify(bytes_and_pks)?)
+            }
+            Self::Secp256k1(agg) => {
+                let hashes_and_pks: Vec<_> = hashes_and_keys
+                    .map(|(hash, pk)| match pk {
+                        PublicKey::Secp256k1(pk) => Ok((*hash.0.as_bytes(), pk)),
+                        PublicKey::Bn254(_) => Err(Error::SchemeMismatch),
+                    })
+                    .collect::<Result<_, _>>()?;
+
+                Ok(agg.verify(hashes_and_pks.iter().map(|(hash, pk)| (hash, *pk)))?)
+            }
+        }
+    }
+
+    /// Verifies multiple aggregate signatures, each against its own list of message hashes
+    /// and public keys, ordered by the signers. The bn254 aggregates are checked at once,
+    /// while the secp256k1 ones don't support batching and are verified one by one.
+    pub(crate) fn verify_batch_hash(
+        items: &[(&Self, Vec<(MsgHash, &PublicKey)>)],
+    ) -> Result<(), Error> {
+        let mut batch = vec![];
+        for (agg, hashes_and_keys) in items {
+            match agg {
+                Self::Bn254(agg) => {
+                    let bytes_and_pks: Vec<_> = hashes_and_keys
+                        .iter()
+                        .map(|(hash, pk)| match pk {
+                            PublicKey::Bn254(pk) => Ok((&hash.0.as_bytes()[..], pk)),
+                            PublicKey::Secp256k1(_) => Err(Error::SchemeMismatch),
+                        })
+                        .collect::<Result<_, _>>()?;
+                    batch.push((agg, bytes_and_pks));
+                }
+                Self::Secp256k1(_) => agg.verify_hash(hashes_and_keys.iter().copied())?,
+            }
+        }
+        let batch: Vec<_> = batch
+            .iter()
+            .map(|(agg, pairs)| (*agg, &pairs[..]))
+            .collect();
+        Ok(bn254::AggregateSignature::verify_batch(&batch)?)
     }
 }
 
+/// bn254 aggregate signatures are encoded without a tag, for compatibility.
+const BN254_LEN: usize = 32;
+
 impl ByteFmt for AggregateSignature {
     fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
-        ByteFmt::decode(bytes).map(Self)
+        if bytes.len() == BN254_LEN {
+            return ByteFmt::decode(bytes).map(Self::Bn254);
+        }
+        let bytes = bytes
+            .strip_prefix(&[SECP256K1_TAG])
+            .context("unknown scheme")?;
+        ByteFmt::decode(bytes).map(Self::Secp256k1)
     }
 
     fn encode(&self) -> Vec<u8> {
-        ByteFmt::encode(&self.0)
+        match self {
+            Self::Bn254(agg) => ByteFmt::encode(agg),
+            Self::Secp256k1(agg) => [&[SECP256K1_TAG][..], &ByteFmt::encode(agg)].concat(),
+        }
     }
 }
 
 impl TextFmt for AggregateSignature {
     fn decode(text: Text) -> anyhow::Result<Self> {
+        if let Ok(text) = text.strip("validator:aggregate_signature:secp256k1:") {
+            return text.decode_hex().map(Self::Secp256k1);
+        }
         text.strip("validator:aggregate_signature:bn254:")?
             .decode_hex()
-            .map(Self)
+            .map(Self::Bn254)
     }
 
     fn encode(&self) -> String {
-        format!(
-            "validator:aggregate_signature:bn254:{}",
-            hex::encode(ByteFmt::encode(&self.0))
-        )
+        match self {
+            Self::Bn254(agg) => format!(
+                "validator:aggregate_signature:bn254:{}",
+                hex::encode(ByteFmt::encode(agg))
+            ),
+            Self::Secp256k1(agg) => format!(
+                "validator:aggregate_signature:secp256k1:{}",
+                hex::encode(ByteFmt::encode(agg))
+            ),
+        }
     }
 }
 
@@ -4,11 +4,52 @@ mod aggregate_signature;
 mod public_key;
 mod secret_key;
 mod signature;
+mod signer;
 
 pub use aggregate_signature::AggregateSignature;
 pub use public_key::PublicKey;
 pub use secret_key::SecretKey;
 pub use signature::Signature;
+pub use signer::ValidatorSigner;
+use zksync_consensus_crypto::{bn254, secp256k1};
+
+/// Signature scheme of the validator keys. All the validators of a chain use the same scheme,
+/// declared in the genesis, so that their signatures can be aggregated into the QCs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SignatureScheme {
+    /// BLS signatures over the BN254 curve.
+    #[default]
+    Bn254,
+    /// ECDSA signatures over the secp256k1 curve, compatible with the EVM `ecrecover`.
+    /// They don't aggregate, so the aggregate signatures are sets of the signatures.
+    Secp256k1,
+}
+
+impl SignatureScheme {
+    /// Whether the signatures of the scheme can be verified in a batch
+    /// cheaper than one by one.
+    pub fn supports_batching(self) -> bool {
+        match self {
+            Self::Bn254 => true,
+            Self::Secp256k1 => false,
+        }
+    }
+}
+
+/// Prefix of the byte encodings of the secp256k1 keys and signatures.
+/// The bn254 encodings are not prefixed, for compatibility; they are recognized by their length.
+const SECP256K1_TAG: u8 = 1;
 
 /// Error type returned by validator key operations.
-pub type Error = zksync_consensus_crypto::bn254::Error;
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// bn254 signature verification failure.
+    #[error(transparent)]
+    Bn254(#[from] bn254::Error),
+    /// secp256k1 signature verification failure.
+    #[error(transparent)]
+    Secp256k1(#[from] secp256k1::Error),
+    /// The signature and the public key belong to different signature schemes.
+    #[error("signature scheme mismatch")]
+    SchemeMismatch,
+}
@@ -1,30 +1,68 @@
+use super::{SignatureScheme, SECP256K1_TAG};
+use anyhow::Context as _;
 use std::fmt;
-use zksync_consensus_crypto::{bn254, ByteFmt, Text, TextFmt};
+use zksync_consensus_crypto::{bn254, secp256k1, ByteFmt, Text, TextFmt};
 
 /// A public key for a validator.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct PublicKey(pub(crate) bn254::PublicKey);
+pub enum PublicKey {
+    /// bn254 public key.
+    Bn254(bn254::PublicKey),
+    /// secp256k1 public key.
+    Secp256k1(secp256k1::PublicKey),
+}
+
+impl PublicKey {
+    /// Signature scheme of the key.
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            Self::Bn254(_) => SignatureScheme::Bn254,
+            Self::Secp256k1(_) => SignatureScheme::Secp256k1,
+        }
+    }
+}
+
+/// bn254 keys are encoded without a tag, for compatibility.
+const BN254_LEN: usize = 64;
 
 impl ByteFmt for PublicKey {
     fn encode(&self) -> Vec<u8> {
-        ByteFmt::encode(&self.0)
+        match self {
+            Self::Bn254(pk) => ByteFmt::encode(pk),
+            Self::Secp256k1(pk) => [&[SECP256K1_TAG][..], &ByteFmt::encode(pk)].concat(),
+        }
     }
     fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
-        ByteFmt::decode(bytes).map(Self)
+        if bytes.len() == BN254_LEN {
+            return ByteFmt::decode(bytes).map(Self::Bn254);
+        }
+        let bytes = bytes
+            .strip_prefix(&[SECP256K1_TAG])
+            .context("unknown scheme")?;
+        ByteFmt::decode(bytes).map(Self::Secp256k1)
     }
 }
 
 impl TextFmt for PublicKey {
     fn encode(&self) -> String {
-        format!(
-            "validator:public:bn254:{}",
-            hex::encode(ByteFmt::encode(&self.0))
-        )
+        match self {
+            Self::Bn254(pk) => format!(
+                "validator:public:bn254:{}",
+                hex::encode(ByteFmt::encode(pk))
+            ),
+            Self::Secp256k1(pk) => format!(
+                "validator:public:secp256k1:{}",
+                hex::encode(ByteFmt::encode(pk))
+            ),
+        }
     }
     fn decode(text: Text) -> anyhow::Result<Self> {
+        if let Ok(text) = text.strip("validator:public:secp256k1:") {
+            return text.decode_hex().map(Self::Secp256k1);
+        }
         text.strip("validator:public:bn254:")?
             .decode_hex()
-            .map(Self)
+            .map(Self::Bn254)
     }
 }
 
@@ -1,24 +1,51 @@
-use super::{PublicKey, Signature};
+use super::{PublicKey, Signature, SignatureScheme, SECP256K1_TAG};
 use crate::validator::messages::{Msg, MsgHash, Signed};
+use anyhow::Context as _;
 use std::{fmt, sync::Arc};
-use zksync_consensus_crypto::{bn254, ByteFmt, Text, TextFmt};
+use zksync_consensus_crypto::{bn254, secp256k1, ByteFmt, Text, TextFmt};
 use zksync_consensus_utils::enum_util::Variant;
 
 /// A secret key for the validator role.
 /// SecretKey is put into an Arc, so that we can clone it,
 /// without copying the secret all over the RAM.
 #[derive(Clone)]
-pub struct SecretKey(pub(crate) Arc<bn254::SecretKey>);
+pub enum SecretKey {
+    /// bn254 secret key.
+    Bn254(Arc<bn254::SecretKey>),
+    /// secp256k1 secret key.
+    Secp256k1(Arc<secp256k1::SecretKey>),
+}
 
 impl SecretKey {
-    /// Generates a secret key from a cryptographically-secure entropy source.
+    /// Generates a bn254 secret key from a cryptographically-secure entropy source.
     pub fn generate() -> Self {
-        Self(Arc::new(bn254::SecretKey::generate()))
+        Self::generate_with_scheme(SignatureScheme::Bn254)
+    }
+
+    /// Generates a secret key of the given scheme from a cryptographically-secure entropy source.
+    pub fn generate_with_scheme(scheme: SignatureScheme) -> Self {
+        match scheme {
+            SignatureScheme::Bn254 => Self::Bn254(Arc::new(bn254::SecretKey::generate())),
+            SignatureScheme::Secp256k1 => {
+                Self::Secp256k1(Arc::new(secp256k1::SecretKey::generate()))
+            }
+        }
+    }
+
+    /// Signature scheme of the key.
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            Self::Bn254(_) => SignatureScheme::Bn254,
+            Self::Secp256k1(_) => SignatureScheme::Secp256k1,
+        }
     }
 
     /// Public key corresponding to this secret key.
     pub fn public(&self) -> PublicKey {
-        PublicKey(self.0.public())
+        match self {
+            Self::Bn254(sk) => PublicKey::Bn254(sk.public()),
+            Self::Secp256k1(sk) => PublicKey::Secp256k1(sk.public()),
+        }
     }
 
     /// Signs a strongly typed message.
@@ -33,33 +60,57 @@ impl SecretKey {
 
     /// Sign a message hash.
     pub fn sign_hash(&self, msg_hash: &MsgHash) -> Signature {
-        Signature(self.0.sign(&ByteFmt::encode(msg_hash)))
+        match self {
+            Self::Bn254(sk) => Signature::Bn254(sk.sign(&ByteFmt::encode(msg_hash))),
+            Self::Secp256k1(sk) => Signature::Secp256k1(sk.sign(msg_hash.0.as_bytes())),
+        }
     }
 }
 
+/// bn254 keys are encoded without a tag, for compatibility.
+const BN254_LEN: usize = 32;
+
 impl ByteFmt for SecretKey {
     fn encode(&self) -> Vec<u8> {
-        ByteFmt::encode(&*self.0)
+        match self {
+            Self::Bn254(sk) => ByteFmt::encode(&**sk),
+            Self::Secp256k1(sk) => [&[SECP256K1_TAG][..], &ByteFmt::encode(&**sk)].concat(),
+        }
     }
 
     fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
-        ByteFmt::decode(bytes).map(Arc::new).map(Self)
+        if bytes.len() == BN254_LEN {
+            return ByteFmt::decode(bytes).map(Arc::new).map(Self::Bn254);
+        }
+        let bytes = bytes
+            .strip_prefix(&[SECP256K1_TAG])
+            .context("unknown scheme")?;
+        ByteFmt::decode(bytes).map(Arc::new).map(Self::Secp256k1)
     }
 }
 
 impl TextFmt for SecretKey {
     fn encode(&self) -> String {
-        format!(
-            "validator:secret:bn254:{}",
-            hex::encode(ByteFmt::encode(&*self.0))
-        )
+        match self {
+            Self::Bn254(sk) => format!(
+                "validator:secret:bn254:{}",
+                hex::encode(ByteFmt::encode(&**sk))
+            ),
+            Self::Secp256k1(sk) => format!(
+                "validator:secret:secp256k1:{}",
+                hex::encode(ByteFmt::encode(&**sk))
+            ),
+        }
     }
 
     fn decode(text: Text) -> anyhow::Result<Self> {
+        if let Ok(text) = text.strip("validator:secret:secp256k1:") {
+            return text.decode_hex().map(Arc::new).map(Self::Secp256k1);
+        }
         text.strip("validator:secret:bn254:")?
             .decode_hex()
             .map(Arc::new)
-            .map(Self)
+            .map(Self::Bn254)
     }
 }
 
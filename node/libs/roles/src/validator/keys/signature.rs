@@ -1,13 +1,27 @@
-use super::{Error, PublicKey};
+use super::{Error, PublicKey, SignatureScheme, SECP256K1_TAG};
 use crate::validator::messages::{Msg, MsgHash};
+use anyhow::Context as _;
 use std::fmt;
-use zksync_consensus_crypto::{bn254, ByteFmt, Text, TextFmt};
+use zksync_consensus_crypto::{bn254, secp256k1, ByteFmt, Text, TextFmt};
 
 /// A signature from a validator.
 #[derive(Clone, PartialEq, Eq)]
-pub struct Signature(pub(crate) bn254::Signature);
+pub enum Signature {
+    /// bn254 signature.
+    Bn254(bn254::Signature),
+    /// secp256k1 signature.
+    Secp256k1(secp256k1::Signature),
+}
 
 impl Signature {
+    /// Signature scheme of the signature.
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            Self::Bn254(_) => SignatureScheme::Bn254,
+            Self::Secp256k1(_) => SignatureScheme::Secp256k1,
+        }
+    }
+
     /// Verify a message against a public key.
     pub fn verify_msg(&self, msg: &Msg, pk: &PublicKey) -> Result<(), Error> {
         self.verify_hash(&msg.hash(), pk)
@@ -15,30 +29,85 @@ impl Signature {
 
     /// Verify a message hash against a public key.
     pub fn verify_hash(&self, msg_hash: &MsgHash, pk: &PublicKey) -> Result<(), Error> {
-        self.0.verify(&ByteFmt::encode(msg_hash), &pk.0)
+        match (self, pk) {
+            (Self::Bn254(sig), PublicKey::Bn254(pk)) => {
+                Ok(sig.verify(&ByteFmt::encode(msg_hash), pk)?)
+            }
+            (Self::Secp256k1(sig), PublicKey::Secp256k1(pk)) => {
+                Ok(sig.verify(msg_hash.0.as_bytes(), pk)?)
+            }
+            _ => Err(Error::SchemeMismatch),
+        }
+    }
+
+    /// Verifies a batch of message hashes, each against its own public key.
+    /// It is considerably cheaper than verifying the signatures one by one,
+    /// but on failure it doesn't tell which of the signatures are invalid.
+    /// secp256k1 signatures don't support batch verification, so they are
+    /// verified one by one; callers bisecting the failing batches should verify
+    /// them separately (see `SignatureScheme::supports_batching()`).
+    pub fn verify_batch<'a>(
+        items: impl Iterator<Item = (&'a MsgHash, &'a PublicKey, &'a Signature)>,
+    ) -> Result<(), Error> {
+        let mut batch = vec![];
+        for (msg_hash, pk, sig) in items {
+            match (sig, pk) {
+                (Self::Bn254(sig), PublicKey::Bn254(pk)) => {
+                    batch.push((ByteFmt::encode(msg_hash), pk, sig))
+                }
+                (Self::Secp256k1(sig), PublicKey::Secp256k1(pk)) => {
+                    sig.verify(msg_hash.0.as_bytes(), pk)?
+                }
+                _ => return Err(Error::SchemeMismatch),
+            }
+        }
+        Ok(bn254::Signature::verify_batch(
+            batch.iter().map(|(msg, pk, sig)| (&msg[..], *pk, *sig)),
+        )?)
     }
 }
 
+/// bn254 signatures are encoded without a tag, for compatibility.
+const BN254_LEN: usize = 32;
+
 impl ByteFmt for Signature {
     fn encode(&self) -> Vec<u8> {
-        ByteFmt::encode(&self.0)
+        match self {
+            Self::Bn254(sig) => ByteFmt::encode(sig),
+            Self::Secp256k1(sig) => [&[SECP256K1_TAG][..], &ByteFmt::encode(sig)].concat(),
+        }
     }
     fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
-        ByteFmt::decode(bytes).map(Self)
+        if bytes.len() == BN254_LEN {
+            return ByteFmt::decode(bytes).map(Self::Bn254);
+        }
+        let bytes = bytes
+            .strip_prefix(&[SECP256K1_TAG])
+            .context("unknown scheme")?;
+        ByteFmt::decode(bytes).map(Self::Secp256k1)
     }
 }
 
 impl TextFmt for Signature {
     fn encode(&self) -> String {
-        format!(
-            "validator:signature:bn254:{}",
-            hex::encode(ByteFmt::encode(&self.0))
-        )
+        match self {
+            Self::Bn254(sig) => format!(
+                "validator:signature:bn254:{}",
+                hex::encode(ByteFmt::encode(sig))
+            ),
+            Self::Secp256k1(sig) => format!(
+                "validator:signature:secp256k1:{}",
+                hex::encode(ByteFmt::encode(sig))
+            ),
+        }
     }
     fn decode(text: Text) -> anyhow::Result<Self> {
+        if let Ok(text) = text.strip("validator:signature:secp256k1:") {
+            return text.decode_hex().map(Self::Secp256k1);
+        }
         text.strip("validator:signature:bn254:")?
             .decode_hex()
-            .map(Self)
+            .map(Self::Bn254)
     }
 }
 
@@ -0,0 +1,48 @@
+use super::{PublicKey, SecretKey, Signature};
+use crate::validator::messages::{Msg, MsgHash, Signed};
+use std::fmt;
+use zksync_concurrency::ctx;
+use zksync_consensus_utils::enum_util::Variant;
+
+/// Signer of the validator messages.
+/// Decouples signing from the in-memory `SecretKey`, so that the validator key
+/// can be kept outside of the node (e.g. in an HSM or a dedicated signing service).
+#[async_trait::async_trait]
+pub trait ValidatorSigner: 'static + fmt::Debug + Send + Sync {
+    /// Public key of the signer.
+    fn public(&self) -> PublicKey;
+    /// Signs a message hash.
+    async fn sign_hash(&self, ctx: &ctx::Ctx, msg_hash: &MsgHash) -> ctx::Result<Signature>;
+    /// Signs a message. Signers which need to inspect the message before signing it
+    /// (e.g. to enforce a signing watermark) should override this method.
+    async fn sign(&self, ctx: &ctx::Ctx, msg: &Msg) -> ctx::Result<Signature> {
+        self.sign_hash(ctx, &msg.hash()).await
+    }
+}
+
+impl dyn ValidatorSigner {
+    /// Signs a strongly typed message.
+    pub async fn sign_msg<V: Variant<Msg>>(
+        &self,
+        ctx: &ctx::Ctx,
+        msg: V,
+    ) -> ctx::Result<Signed<V>> {
+        let msg = msg.insert();
+        Ok(Signed {
+            sig: self.sign(ctx, &msg).await?,
+            key: self.public(),
+            msg: V::extract(msg).unwrap(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl ValidatorSigner for SecretKey {
+    fn public(&self) -> PublicKey {
+        SecretKey::public(self)
+    }
+
+    async fn sign_hash(&self, _ctx: &ctx::Ctx, msg_hash: &MsgHash) -> ctx::Result<Signature> {
+        Ok(SecretKey::sign_hash(self, msg_hash))
+    }
+}
@@ -1,8 +1,14 @@
 //! Messages related to blocks.
 
-use super::{CommitQC, CommitQCVerifyError};
+use super::{
+    CommitQC, CommitQCVerifyError, Digest, HashScheme, KeyRotationCert, KeyRotationHash,
+    PayloadRoot, ProtocolUpgradeHash, ProtocolUpgradeQC,
+};
 use std::fmt;
-use zksync_consensus_crypto::{keccak256::Keccak256, ByteFmt, Text, TextFmt};
+use zksync_consensus_crypto::{
+    keccak256::{self, Keccak256},
+    ByteFmt, Text, TextFmt,
+};
 
 /// Payload of the block. Consensus algorithm does not interpret the payload
 /// (except for imposing a size limit for the payload). Proposing a payload
@@ -51,6 +57,22 @@ impl Payload {
     }
 }
 
+/// Incremental hasher of a payload received in chunks.
+#[derive(Clone, Default)]
+pub struct PayloadHasher(keccak256::Hasher);
+
+impl PayloadHasher {
+    /// Feeds the next chunk of the payload to the hasher.
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.0.update(chunk);
+    }
+
+    /// Hash of the whole payload.
+    pub fn finalize(self) -> PayloadHash {
+        PayloadHash(self.0.finalize())
+    }
+}
+
 /// Sequential number of the block.
 /// Genesis block can have an arbitrary block number.
 /// For blocks other than genesis: block.number = block.parent.number + 1.
@@ -77,13 +99,13 @@ impl fmt::Display for BlockNumber {
 
 /// Hash of the block header.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct BlockHeaderHash(pub(crate) Keccak256);
+pub struct BlockHeaderHash(pub(crate) Digest);
 
 impl BlockHeaderHash {
     /// Interprets the specified `bytes` as a block header hash digest (i.e., a reverse operation to [`Self::as_bytes()`]).
     /// It is caller's responsibility to ensure that `bytes` are actually a block header hash digest.
     pub fn from_bytes(bytes: [u8; 32]) -> Self {
-        Self(Keccak256::from_bytes(bytes))
+        Self(Digest(bytes))
     }
 
     /// Returns a reference to the bytes of this hash.
@@ -94,14 +116,12 @@ impl BlockHeaderHash {
 
 impl TextFmt for BlockHeaderHash {
     fn decode(text: Text) -> anyhow::Result<Self> {
-        text.strip("block_header_hash:keccak256:")?
-            .decode_hex()
-            .map(Self)
+        Digest::decode_text(text.strip("block_header_hash:")?).map(Self)
     }
 
     fn encode(&self) -> String {
         format!(
-            "block_header_hash:keccak256:{}",
+            "block_header_hash:{}",
             hex::encode(ByteFmt::encode(&self.0))
         )
     }
@@ -122,20 +142,35 @@ pub struct BlockHeader {
     pub number: BlockNumber,
     /// Payload of the block.
     pub payload: PayloadHash,
+    /// Merkle root over the chunks of the payload, allowing to verify the chunks
+    /// of the payload separately (see `PayloadChunkProof`). Present iff the protocol
+    /// version of the block commits to it, see `ProtocolVersion::commits_payload_root`.
+    pub payload_root: Option<PayloadRoot>,
+    /// Key rotation committed by the block, see `KeyRotation`.
+    /// The rotation is applied to the genesis once the block is finalized.
+    pub key_rotation: Option<KeyRotationHash>,
+    /// Protocol upgrade committed by the block, see `ProtocolUpgrade`.
+    /// The upgrade is applied to the genesis once the block is finalized.
+    pub protocol_upgrade: Option<ProtocolUpgradeHash>,
 }
 
 impl BlockHeader {
-    /// Returns the hash of the block.
+    /// Returns the hash of the block under the legacy hashing scheme.
+    /// The blocks of a chain which may follow `ProtocolVersion::DOMAIN_SEPARATED_HASHING`
+    /// have to be hashed with `Genesis::header_hash` instead.
     pub fn hash(&self) -> BlockHeaderHash {
-        BlockHeaderHash(Keccak256::new(&zksync_protobuf::canonical(self)))
+        self.hash_with(HashScheme::Legacy)
     }
 
-    /// Creates a child block for the given parent.
+    /// Creates a child block for the given parent, linked with the legacy hash of the parent.
     pub fn next(parent: &BlockHeader, payload: PayloadHash) -> Self {
         Self {
             parent: Some(parent.hash()),
             number: parent.number.next(),
             payload,
+            payload_root: None,
+            key_rotation: None,
+            protocol_upgrade: None,
         }
     }
 }
@@ -147,15 +182,24 @@ pub struct FinalBlock {
     pub payload: Payload,
     /// Justification for the block. What guarantees that the block is final.
     pub justification: CommitQC,
+    /// Key rotation committed by the block. Should match `header.key_rotation` hash.
+    pub key_rotation: Option<KeyRotationCert>,
+    /// Protocol upgrade committed by the block. Should match `header.protocol_upgrade` hash.
+    pub protocol_upgrade: Option<ProtocolUpgradeQC>,
 }
 
 impl FinalBlock {
-    /// Creates a new finalized block.
+    /// Creates a new finalized block, which doesn't commit to a key rotation
+    /// nor to a protocol upgrade.
     pub fn new(payload: Payload, justification: CommitQC) -> Self {
         assert_eq!(justification.header().payload, payload.hash());
+        assert_eq!(justification.header().key_rotation, None);
+        assert_eq!(justification.header().protocol_upgrade, None);
         Self {
             payload,
             justification,
+            key_rotation: None,
+            protocol_upgrade: None,
         }
     }
 
@@ -170,6 +214,9 @@ impl FinalBlock {
     }
 
     /// Verifies internal consistency of this block.
+    /// `genesis` has to include the key rotations and the protocol upgrades committed by
+    /// the preceding blocks. It may also include the ones committed by this block and the later blocks,
+    /// so that the stored blocks can be verified against the current genesis.
     pub fn verify(&self, genesis: &super::Genesis) -> Result<(), BlockValidationError> {
         let payload_hash = self.payload.hash();
         if payload_hash != self.header().payload {
@@ -178,6 +225,51 @@ impl FinalBlock {
                 payload_hash,
             });
         }
+        let root_required = genesis
+            .protocol_version_at(self.header().number)
+            .commits_payload_root();
+        if self.header().payload_root.is_some() != root_required {
+            return Err(BlockValidationError::PayloadRootPresence {
+                required: root_required,
+            });
+        }
+        if let Some(header_root) = self.header().payload_root {
+            let payload_root = self.payload.root();
+            if payload_root != header_root {
+                return Err(BlockValidationError::RootMismatch {
+                    header_root,
+                    payload_root,
+                });
+            }
+        }
+        if let Some(max) = genesis.max_payload_size {
+            if self.payload.0.len() > max {
+                return Err(BlockValidationError::OversizedPayload {
+                    payload_size: self.payload.0.len(),
+                    max,
+                });
+            }
+        }
+        if self.header().key_rotation != self.key_rotation.as_ref().map(|r| r.hash()) {
+            return Err(BlockValidationError::KeyRotationMismatch);
+        }
+        if let Some(cert) = &self.key_rotation {
+            if !genesis.key_rotations.contains(cert) {
+                genesis
+                    .check_key_rotation(cert)
+                    .map_err(BlockValidationError::KeyRotation)?;
+            }
+        }
+        if self.header().protocol_upgrade != self.protocol_upgrade.as_ref().map(|u| u.hash()) {
+            return Err(BlockValidationError::ProtocolUpgradeMismatch);
+        }
+        if let Some(qc) = &self.protocol_upgrade {
+            if !genesis.protocol_upgrades.contains(qc) {
+                genesis
+                    .check_protocol_upgrade(qc, self.header().number)
+                    .map_err(BlockValidationError::ProtocolUpgrade)?;
+            }
+        }
         self.justification
             .verify(genesis)
             .map_err(BlockValidationError::Justification)
@@ -219,6 +311,43 @@ pub enum BlockValidationError {
         /// Hash of the payload.
         payload_hash: PayloadHash,
     },
+    /// Block payload doesn't match the payload root in the block header.
+    #[error(
+        "block payload doesn't match the block header (root in header: {header_root:?}, \
+             payload root: {payload_root:?})"
+    )]
+    RootMismatch {
+        /// Payload root in block header.
+        header_root: PayloadRoot,
+        /// Root of the payload.
+        payload_root: PayloadRoot,
+    },
+    /// Block header has the payload root iff the protocol version doesn't commit to it.
+    #[error("payload root in block header: required {required}")]
+    PayloadRootPresence {
+        /// Whether the payload root is required by the protocol version.
+        required: bool,
+    },
+    /// Block payload exceeds the limit from genesis.
+    #[error("block payload too large: got {payload_size}B, max {max}B")]
+    OversizedPayload {
+        /// Size of the payload.
+        payload_size: usize,
+        /// Maximal payload size.
+        max: usize,
+    },
+    /// Key rotation doesn't match the block header.
+    #[error("key rotation doesn't match the block header")]
+    KeyRotationMismatch,
+    /// Key rotation cannot be committed, given the rotations committed by the preceding blocks.
+    #[error("invalid key rotation: {0:#}")]
+    KeyRotation(#[source] anyhow::Error),
+    /// Protocol upgrade doesn't match the block header.
+    #[error("protocol upgrade doesn't match the block header")]
+    ProtocolUpgradeMismatch,
+    /// Protocol upgrade cannot be committed, given the upgrades committed by the preceding blocks.
+    #[error("invalid protocol upgrade: {0:#}")]
+    ProtocolUpgrade(#[source] anyhow::Error),
     /// Failed verifying quorum certificate.
     #[error("failed verifying quorum certificate: {0:#?}")]
     Justification(#[source] CommitQCVerifyError),
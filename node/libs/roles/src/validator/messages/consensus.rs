@@ -1,14 +1,15 @@
 //! Messages related to the consensus protocol.
 use super::{
-    BlockHeaderHash, BlockNumber, LeaderCommit, LeaderPrepare, Msg, ReplicaCommit, ReplicaPrepare,
+    hashing, BlockHeaderHash, BlockNumber, Digest, KeyRotations, LeaderCommit, LeaderPrepare,
+    LeaderTimeout, Msg, ProtocolUpgrades, ReplicaCommit, ReplicaPrepare, ReplicaTimeout,
 };
-use crate::validator;
+use crate::{attester, validator};
 use bit_vec::BitVec;
 use std::{
     collections::{BTreeMap, BTreeSet},
     fmt,
 };
-use zksync_consensus_crypto::{keccak256::Keccak256, ByteFmt, Text, TextFmt};
+use zksync_consensus_crypto::{ByteFmt, Text, TextFmt};
 use zksync_consensus_utils::enum_util::{BadVariantError, Variant};
 
 /// Version of the consensus algorithm that the validator is using.
@@ -20,6 +21,15 @@ pub struct ProtocolVersion(pub u32);
 impl ProtocolVersion {
     /// Earliest protocol version.
     pub const EARLIEST: Self = Self(0);
+    /// Earliest protocol version in which the block headers commit to the Merkle root
+    /// of the payload (see `PayloadRoot`).
+    pub const PAYLOAD_ROOT: Self = Self(1);
+    /// Earliest protocol version in which the genesis, the block headers and the consensus
+    /// messages are hashed in separate domains (see `HashScheme`).
+    pub const DOMAIN_SEPARATED_HASHING: Self = Self(2);
+    /// Earliest protocol version in which the objects hashed in separate domains
+    /// are hashed with blake2b-256 instead of keccak256 (see `HashScheme`).
+    pub const BLAKE2_HASHING: Self = Self(3);
 
     /// Returns the integer corresponding to this version.
     pub fn as_u32(self) -> u32 {
@@ -32,6 +42,11 @@ impl ProtocolVersion {
         // This can be changed later to apply a minimum supported version.
         self.0 == other.0
     }
+
+    /// Whether the block headers have to commit to the payload root in this version.
+    pub fn commits_payload_root(self) -> bool {
+        self >= Self::PAYLOAD_ROOT
+    }
 }
 
 impl TryFrom<u32> for ProtocolVersion {
@@ -86,6 +101,7 @@ pub struct ValidatorSet {
 
 impl ValidatorSet {
     /// Creates a new ValidatorSet from a list of validator public keys.
+    /// All the keys have to be of the same signature scheme.
     pub fn new(validators: impl IntoIterator<Item = validator::PublicKey>) -> anyhow::Result<Self> {
         let mut set = BTreeSet::new();
         for validator in validators {
@@ -95,12 +111,22 @@ impl ValidatorSet {
             !set.is_empty(),
             "ValidatorSet must contain at least one validator"
         );
+        anyhow::ensure!(
+            set.iter()
+                .all(|v| v.scheme() == set.first().unwrap().scheme()),
+            "ValidatorSet must not mix signature schemes"
+        );
         Ok(Self {
             vec: set.iter().cloned().collect(),
             map: set.into_iter().enumerate().map(|(i, pk)| (pk, i)).collect(),
         })
     }
 
+    /// Signature scheme of the validator keys.
+    pub fn scheme(&self) -> validator::SignatureScheme {
+        self.vec[0].scheme()
+    }
+
     /// Iterates over validators.
     pub fn iter(&self) -> impl Iterator<Item = &validator::PublicKey> {
         self.vec.iter()
@@ -170,31 +196,61 @@ pub struct Genesis {
     pub validators: ValidatorSet,
     /// Fork of the chain to follow.
     pub fork: Fork,
+    /// Maximal size of a block payload, in bytes, agreed on by all validators.
+    /// Blocks with larger payloads are rejected. `None` means that the limit
+    /// is left to the local configuration of each node.
+    pub max_payload_size: Option<usize>,
+    /// Rotations of the validator keys.
+    /// They are NOT included in the genesis hash.
+    pub key_rotations: KeyRotations,
+    /// Attesters of the chain, signing the batches of the finalized blocks.
+    /// `None` if the chain has no attesters.
+    pub attesters: Option<attester::Committee>,
+    /// Version of the protocol followed by the chain since the first block of the fork.
+    /// It determines the format of the blocks, e.g. whether the headers commit to the
+    /// payload root. See `protocol_version_at` for the version of a given block.
+    pub protocol_version: ProtocolVersion,
+    /// Certified upgrades of the protocol version committed by the blocks of the chain,
+    /// see `BlockHeader::protocol_upgrade`. They are NOT included in the genesis hash.
+    pub protocol_upgrades: ProtocolUpgrades,
+    /// Signature scheme of the validator keys. It has to match the scheme of `validators`.
+    pub signature_scheme: validator::SignatureScheme,
 }
 
 /// Hash of the genesis specification.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct GenesisHash(pub(crate) Keccak256);
+pub struct GenesisHash(pub(crate) Digest);
 
 impl Genesis {
-    /// Hash of the genesis.
+    /// Hash of the genesis, under the hashing scheme of `protocol_version`.
+    /// Key rotations and protocol upgrades are excluded,
+    /// so that they can be added without changing the hash.
     pub fn hash(&self) -> GenesisHash {
-        GenesisHash(Keccak256::new(&zksync_protobuf::canonical(self)))
+        let mut genesis = self.clone();
+        genesis.key_rotations = KeyRotations::default();
+        genesis.protocol_upgrades = ProtocolUpgrades::default();
+        GenesisHash(
+            self.protocol_version
+                .hash_scheme()
+                .hash(hashing::Domain::Genesis, &genesis),
+        )
+    }
+
+    /// Maximal size of a block payload, in bytes.
+    /// The limit from genesis takes precedence over the locally configured `fallback`,
+    /// so that all validators reject the same oversized payloads.
+    pub fn payload_size_limit(&self, fallback: usize) -> usize {
+        self.max_payload_size.unwrap_or(fallback)
     }
 }
 
 impl TextFmt for GenesisHash {
     fn decode(text: Text) -> anyhow::Result<Self> {
-        text.strip("genesis_hash:keccak256:")?
-            .decode_hex()
-            .map(Self)
+        Digest::decode_text(text.strip("genesis_hash:")?).map(Self)
     }
 
     fn encode(&self) -> String {
-        format!(
-            "genesis_hash:keccak256:{}",
-            hex::encode(ByteFmt::encode(&self.0))
-        )
+        format!("genesis_hash:{}", hex::encode(ByteFmt::encode(&self.0)))
     }
 }
 
@@ -212,6 +268,8 @@ pub enum ConsensusMsg {
     ReplicaCommit(ReplicaCommit),
     LeaderPrepare(LeaderPrepare),
     LeaderCommit(LeaderCommit),
+    ReplicaTimeout(ReplicaTimeout),
+    LeaderTimeout(LeaderTimeout),
 }
 
 impl ConsensusMsg {
@@ -222,6 +280,8 @@ impl ConsensusMsg {
             Self::ReplicaCommit(_) => "ReplicaCommit",
             Self::LeaderPrepare(_) => "LeaderPrepare",
             Self::LeaderCommit(_) => "LeaderCommit",
+            Self::ReplicaTimeout(_) => "ReplicaTimeout",
+            Self::LeaderTimeout(_) => "LeaderTimeout",
         }
     }
 
@@ -232,6 +292,8 @@ impl ConsensusMsg {
             Self::ReplicaCommit(m) => &m.view,
             Self::LeaderPrepare(m) => m.view(),
             Self::LeaderCommit(m) => m.view(),
+            Self::ReplicaTimeout(m) => &m.view,
+            Self::LeaderTimeout(m) => m.view(),
         }
     }
 
@@ -289,6 +351,30 @@ impl Variant<Msg> for LeaderCommit {
     }
 }
 
+impl Variant<Msg> for ReplicaTimeout {
+    fn insert(self) -> Msg {
+        ConsensusMsg::ReplicaTimeout(self).insert()
+    }
+    fn extract(msg: Msg) -> Result<Self, BadVariantError> {
+        let ConsensusMsg::ReplicaTimeout(this) = Variant::extract(msg)? else {
+            return Err(BadVariantError);
+        };
+        Ok(this)
+    }
+}
+
+impl Variant<Msg> for LeaderTimeout {
+    fn insert(self) -> Msg {
+        ConsensusMsg::LeaderTimeout(self).insert()
+    }
+    fn extract(msg: Msg) -> Result<Self, BadVariantError> {
+        let ConsensusMsg::LeaderTimeout(this) = Variant::extract(msg)? else {
+            return Err(BadVariantError);
+        };
+        Ok(this)
+    }
+}
+
 /// View specification.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct View {
@@ -324,6 +410,12 @@ impl Signers {
         self.0.iter().filter(|b| *b).count()
     }
 
+    /// Returns the number of signers with an index lower than `i`,
+    /// i.e. the position of the validator `i` among the signers.
+    pub fn position(&self, i: usize) -> usize {
+        self.0.iter().take(i).filter(|b| *b).count()
+    }
+
     /// Size of the corresponding ValidatorSet.
     pub fn len(&self) -> usize {
         self.0.len()
@@ -0,0 +1,141 @@
+//! Proofs of double signing, i.e. of a validator signing 2 conflicting consensus messages.
+//! A proof is self-contained: anyone knowing the genesis (e.g. a slashing contract)
+//! can verify it, without any other context.
+use super::{ConsensusMsg, Genesis, Phase, Signed, View};
+use crate::validator;
+use zksync_consensus_utils::enum_util::Variant as _;
+
+/// Two different consensus messages of the same kind, signed by the same validator key
+/// in the same view. Messages are ordered by their hash, so that the proof is canonical.
+///
+/// `LeaderCommit` and `LeaderTimeout` messages are not slashable: a leader may legitimately
+/// broadcast different (valid) QCs for the same view. `ReplicaTimeout` messages are not slashable
+/// either: all the timeout votes for the same view are identical.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DoubleSignProof {
+    /// Message with the lower hash.
+    pub first: Signed<ConsensusMsg>,
+    /// Message with the higher hash.
+    pub second: Signed<ConsensusMsg>,
+}
+
+/// Error returned by `DoubleSignProof::verify()`.
+#[derive(thiserror::Error, Debug)]
+pub enum DoubleSignProofError {
+    /// Messages are signed by different keys.
+    #[error("messages signed by different keys")]
+    DifferentKeys,
+    /// Messages are of a kind which is not slashable.
+    #[error("{0} messages are not slashable")]
+    NotSlashable(&'static str),
+    /// Messages are of different kinds.
+    #[error("messages of different kinds: {0} and {1}")]
+    DifferentKinds(&'static str, &'static str),
+    /// Messages belong to different views.
+    #[error("messages from different views: {0:?} and {1:?}")]
+    DifferentViews(View, View),
+    /// Messages are identical.
+    #[error("identical messages")]
+    SameMessage,
+    /// Messages are not ordered by their hash.
+    #[error("messages are not ordered by hash")]
+    NotCanonical,
+    /// Messages belong to a different fork.
+    #[error("bad fork: got {got:?}, want {want:?}")]
+    BadFork {
+        /// got
+        got: validator::ForkNumber,
+        /// want
+        want: validator::ForkNumber,
+    },
+    /// Key is not a validator key in the view of the messages.
+    #[error("key is not a validator key in view {0:?}")]
+    NotValidator(validator::ViewNumber),
+    /// Invalid signature.
+    #[error("signature: {0:#}")]
+    Signature(#[source] validator::Error),
+}
+
+impl DoubleSignProof {
+    /// Constructs a proof from 2 signed messages, in any order, and verifies it.
+    /// Returns an error if the messages don't prove double signing.
+    pub fn new(
+        genesis: &Genesis,
+        a: Signed<ConsensusMsg>,
+        b: Signed<ConsensusMsg>,
+    ) -> Result<Self, DoubleSignProofError> {
+        let (first, second) = match hash(&a) <= hash(&b) {
+            true => (a, b),
+            false => (b, a),
+        };
+        let proof = Self { first, second };
+        proof.verify(genesis)?;
+        Ok(proof)
+    }
+
+    /// Key of the validator which has signed both messages.
+    pub fn key(&self) -> &validator::PublicKey {
+        &self.first.key
+    }
+
+    /// View in which both messages have been signed.
+    pub fn view(&self) -> &View {
+        self.first.msg.view()
+    }
+
+    /// Phase of the conflicting messages.
+    pub fn phase(&self) -> Phase {
+        match &self.first.msg {
+            ConsensusMsg::ReplicaCommit(_) => Phase::Commit,
+            _ => Phase::Prepare,
+        }
+    }
+
+    /// Verifies the proof.
+    pub fn verify(&self, genesis: &Genesis) -> Result<(), DoubleSignProofError> {
+        use DoubleSignProofError as Error;
+        let (a, b) = (&self.first, &self.second);
+        if a.key != b.key {
+            return Err(Error::DifferentKeys);
+        }
+        if a.msg.label() != b.msg.label() {
+            return Err(Error::DifferentKinds(a.msg.label(), b.msg.label()));
+        }
+        match &a.msg {
+            ConsensusMsg::ReplicaPrepare(_)
+            | ConsensusMsg::ReplicaCommit(_)
+            | ConsensusMsg::LeaderPrepare(_) => {}
+            ConsensusMsg::LeaderCommit(_)
+            | ConsensusMsg::ReplicaTimeout(_)
+            | ConsensusMsg::LeaderTimeout(_) => return Err(Error::NotSlashable(a.msg.label())),
+        }
+        let view = a.msg.view();
+        if view != b.msg.view() {
+            return Err(Error::DifferentViews(view.clone(), b.msg.view().clone()));
+        }
+        if view.fork != genesis.fork.number {
+            return Err(Error::BadFork {
+                got: view.fork,
+                want: genesis.fork.number,
+            });
+        }
+        let (ha, hb) = (hash(a), hash(b));
+        if ha == hb {
+            return Err(Error::SameMessage);
+        }
+        if ha > hb {
+            return Err(Error::NotCanonical);
+        }
+        if !genesis.is_validator_key(&a.key, view.number) {
+            return Err(Error::NotValidator(view.number));
+        }
+        a.verify().map_err(Error::Signature)?;
+        b.verify().map_err(Error::Signature)?;
+        Ok(())
+    }
+}
+
+/// Hash of the signed message, as bytes.
+fn hash(m: &Signed<ConsensusMsg>) -> [u8; 32] {
+    *m.msg.clone().insert().hash().0.as_bytes()
+}
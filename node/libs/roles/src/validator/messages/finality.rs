@@ -0,0 +1,70 @@
+//! Proofs of finality of the blocks, for the external verifiers (bridges, light clients).
+//! A proof is self-contained: anyone knowing the genesis hash of the chain can verify it
+//! with [`verify_finality`], without running a node or knowing the genesis specification.
+use super::{BlockHeader, CommitQC, CommitQCVerifyError, FinalBlock, Genesis, GenesisHash};
+
+/// Proof that a block has been finalized by the consensus.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FinalityProof {
+    /// Genesis of the chain, i.e. the commitment to the validator set which has
+    /// finalized the block. It is bound to the chain by its hash. Includes the key
+    /// rotations of the validators, which are not part of the hash, but are signed
+    /// by the validator keys.
+    pub genesis: Genesis,
+    /// Justification of the block, certifying its header.
+    pub justification: CommitQC,
+}
+
+/// Error returned by [`verify_finality()`].
+#[derive(thiserror::Error, Debug)]
+pub enum FinalityProofError {
+    /// Proof is for a chain with a different genesis.
+    #[error("genesis mismatch: got {got:?}, want {want:?}")]
+    GenesisMismatch {
+        /// got
+        got: GenesisHash,
+        /// want
+        want: GenesisHash,
+    },
+    /// Justification is invalid.
+    #[error("justification: {0:#}")]
+    Justification(#[source] CommitQCVerifyError),
+}
+
+impl FinalityProof {
+    /// Header of the finalized block.
+    pub fn header(&self) -> &BlockHeader {
+        self.justification.header()
+    }
+}
+
+impl FinalBlock {
+    /// Proof of finality of this block, for the chain with the given genesis.
+    /// The payload is not part of the proof: it is committed to by the header.
+    pub fn finality_proof(&self, genesis: &Genesis) -> FinalityProof {
+        FinalityProof {
+            genesis: genesis.clone(),
+            justification: self.justification.clone(),
+        }
+    }
+}
+
+/// Verifies that the block with the returned header has been finalized
+/// on the chain with the given genesis hash.
+pub fn verify_finality(
+    genesis_hash: GenesisHash,
+    proof: &FinalityProof,
+) -> Result<&BlockHeader, FinalityProofError> {
+    let got = proof.genesis.hash();
+    if got != genesis_hash {
+        return Err(FinalityProofError::GenesisMismatch {
+            got,
+            want: genesis_hash,
+        });
+    }
+    proof
+        .justification
+        .verify(&proof.genesis)
+        .map_err(FinalityProofError::Justification)?;
+    Ok(proof.header())
+}
@@ -0,0 +1,147 @@
+//! Hashing of the genesis, the block headers and the signed messages.
+//! The hashing scheme is determined by the protocol version:
+//! * before `ProtocolVersion::DOMAIN_SEPARATED_HASHING`, an object is hashed as keccak256
+//!   of its canonical proto encoding (`HashScheme::Legacy`),
+//! * since then, the canonical proto encoding is prefixed with a tag of the hashed object type
+//!   (`HashScheme::DomainSeparated`), so that the encoding of one object type can never be
+//!   passed off as the encoding of another (e.g. a signed message as a block header),
+//! * since `ProtocolVersion::BLAKE2_HASHING`, the domain separated encoding is hashed
+//!   with blake2b-256 instead of keccak256.
+//!
+//! The hashes of all the schemes are 32 bytes long and are carried in the same types
+//! (e.g. `BlockHeaderHash`, wrapping a `Digest`), so a hash is only meaningful together
+//! with its protocol version.
+//!
+//! Both schemes are locked with golden vectors: changing the hash of an existing object
+//! breaks the existing signatures and chains, and requires a new protocol version.
+use super::{BlockHeader, BlockHeaderHash, Genesis, ProtocolVersion};
+use zksync_consensus_crypto::{
+    blake2b256,
+    keccak256::{self, Keccak256},
+    ByteFmt, Text,
+};
+use zksync_protobuf::ProtoFmt;
+
+/// 32-byte hash computed under a `HashScheme`. It doesn't record the hash function,
+/// which is determined by the protocol version.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Digest(pub(crate) [u8; 32]);
+
+impl Digest {
+    /// Returns a reference to the bytes of this hash.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Decodes the hex encoded digest. The `keccak256:` prefix, which the text encodings
+    /// contained before blake2b-256 was introduced, is accepted for compatibility.
+    pub(crate) fn decode_text(text: Text) -> anyhow::Result<Self> {
+        text.strip("keccak256:").unwrap_or(text).decode_hex()
+    }
+}
+
+impl ByteFmt for Digest {
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        Ok(Self(bytes.try_into()?))
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+}
+
+/// Hashing scheme, see the module docs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashScheme {
+    /// keccak256 of the canonical proto encoding.
+    Legacy,
+    /// Hash of the canonical proto encoding, in the domain of the hashed object type.
+    DomainSeparated(HashFunction),
+}
+
+/// Hash function of the domain separated scheme.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashFunction {
+    /// keccak256.
+    Keccak256,
+    /// blake2b with a 256-bit output.
+    Blake2b256,
+}
+
+/// Type of the hashed object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Domain {
+    Genesis,
+    BlockHeader,
+    Msg,
+    KeyRotation,
+    ProtocolUpgrade,
+}
+
+impl Domain {
+    /// Tag of the domain. Tags must never change.
+    fn tag(self) -> &'static [u8] {
+        match self {
+            Self::Genesis => b"zksync_consensus:genesis",
+            Self::BlockHeader => b"zksync_consensus:block_header",
+            Self::Msg => b"zksync_consensus:msg",
+            Self::KeyRotation => b"zksync_consensus:key_rotation",
+            Self::ProtocolUpgrade => b"zksync_consensus:protocol_upgrade",
+        }
+    }
+}
+
+impl HashScheme {
+    /// Hashes an object of the given type.
+    pub(crate) fn hash<T: ProtoFmt>(self, domain: Domain, x: &T) -> Digest {
+        match self {
+            Self::Legacy => Digest(*Keccak256::new(&zksync_protobuf::canonical(x)).as_bytes()),
+            Self::DomainSeparated(HashFunction::Keccak256) => {
+                Digest(*keccak256_in_domain(domain, x).as_bytes())
+            }
+            Self::DomainSeparated(HashFunction::Blake2b256) => {
+                let mut hasher = blake2b256::Hasher::with_domain(domain.tag());
+                hasher.update(&zksync_protobuf::canonical(x));
+                Digest(hasher.finalize())
+            }
+        }
+    }
+}
+
+/// keccak256 of an object in the domain of its type, regardless of the protocol version.
+/// Used for the objects committed to by the blocks of different protocol versions.
+pub(crate) fn keccak256_in_domain<T: ProtoFmt>(domain: Domain, x: &T) -> Keccak256 {
+    let mut hasher = keccak256::Hasher::with_domain(domain.tag());
+    hasher.update(&zksync_protobuf::canonical(x));
+    hasher.finalize()
+}
+
+impl ProtocolVersion {
+    /// Hashing scheme of this protocol version.
+    pub fn hash_scheme(self) -> HashScheme {
+        if self >= Self::BLAKE2_HASHING {
+            HashScheme::DomainSeparated(HashFunction::Blake2b256)
+        } else if self >= Self::DOMAIN_SEPARATED_HASHING {
+            HashScheme::DomainSeparated(HashFunction::Keccak256)
+        } else {
+            HashScheme::Legacy
+        }
+    }
+}
+
+impl BlockHeader {
+    /// Hash of the block header under the given scheme.
+    /// The scheme of a block of a chain is determined by the protocol version of the block,
+    /// see `Genesis::header_hash`.
+    pub fn hash_with(&self, scheme: HashScheme) -> BlockHeaderHash {
+        BlockHeaderHash(scheme.hash(Domain::BlockHeader, self))
+    }
+}
+
+impl Genesis {
+    /// Hash of the block header of this chain, under the hashing scheme
+    /// of the protocol version of the block.
+    pub fn header_hash(&self, header: &BlockHeader) -> BlockHeaderHash {
+        header.hash_with(self.protocol_version_at(header.number).hash_scheme())
+    }
+}
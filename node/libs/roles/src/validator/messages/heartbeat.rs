@@ -0,0 +1,16 @@
+//! Liveness messages exchanged between validators.
+use super::GenesisHash;
+use zksync_concurrency::time;
+
+/// A message periodically sent by a validator to the other validators,
+/// announcing that it is online. Heartbeats are exchanged independently
+/// of the consensus views, so that the liveness of the committee can be
+/// estimated even when the chain is stalled.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Heartbeat {
+    /// Genesis of the chain that the validator participates in.
+    /// Prevents replaying the heartbeat on a different chain.
+    pub genesis: GenesisHash,
+    /// Time at which this message has been signed.
+    pub timestamp: time::Utc,
+}
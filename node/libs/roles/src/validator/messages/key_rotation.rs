@@ -0,0 +1,284 @@
+//! Messages related to the rotation of the validator keys.
+use super::{hashing, Genesis, GenesisHash, Signed, ViewNumber};
+use crate::validator;
+use std::{collections::BTreeMap, fmt};
+use zksync_consensus_crypto::{keccak256::Keccak256, ByteFmt, Text, TextFmt};
+
+/// A request of a validator to replace its consensus key.
+/// Rotations are chain state: a rotation is proposed by the leader together with
+/// a new block, the block header commits to it (see `BlockHeader::key_rotation`)
+/// and the rotation is applied to the genesis once the block is finalized.
+/// Hence the rotations are NOT a part of the genesis hash, so that a compromised key
+/// can be replaced without restarting the chain with a new genesis.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyRotation {
+    /// Genesis of the chain that the rotation applies to.
+    /// Prevents replaying the rotation on a different chain.
+    pub genesis: GenesisHash,
+    /// Key of the validator in the genesis validator set.
+    pub old_key: validator::PublicKey,
+    /// Key replacing `old_key`.
+    pub new_key: validator::PublicKey,
+    /// First view in which `new_key` is accepted.
+    pub activation: ViewNumber,
+    /// Number of views (starting at `activation`) in which `old_key` is still accepted,
+    /// so that the validator can switch its key without missing any view.
+    pub grace_period: u64,
+}
+
+impl KeyRotation {
+    /// Checks whether `old_key` is accepted in the given view.
+    pub fn accepts_old_key(&self, view: ViewNumber) -> bool {
+        view.0 < self.activation.0.saturating_add(self.grace_period)
+    }
+
+    /// Checks whether `new_key` is accepted in the given view.
+    pub fn accepts_new_key(&self, view: ViewNumber) -> bool {
+        self.activation <= view
+    }
+}
+
+/// Key rotation authorized by both keys:
+/// * signed by the old key, which authorizes the rotation.
+/// * signed by the new key, which proves possession of the new key
+///   (required for the security of the aggregated signatures).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyRotationCert {
+    /// Rotation signed by `rotation.msg.old_key`.
+    pub rotation: Signed<KeyRotation>,
+    /// Signature of `rotation.msg` by `rotation.msg.new_key`.
+    pub new_key_sig: validator::Signature,
+}
+
+/// Hash of a `KeyRotationCert`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct KeyRotationHash(pub(crate) Keccak256);
+
+impl TextFmt for KeyRotationHash {
+    fn decode(text: Text) -> anyhow::Result<Self> {
+        text.strip("key_rotation:keccak256:")?
+            .decode_hex()
+            .map(Self)
+    }
+
+    fn encode(&self) -> String {
+        format!(
+            "key_rotation:keccak256:{}",
+            hex::encode(ByteFmt::encode(&self.0))
+        )
+    }
+}
+
+impl fmt::Debug for KeyRotationHash {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(&TextFmt::encode(self))
+    }
+}
+
+impl KeyRotationCert {
+    /// Hash of the certificate, which the block header committing to the rotation contains.
+    /// Rotations have been introduced after the legacy hashing scheme, so they are always
+    /// hashed in their own domain, with keccak256 in every protocol version.
+    pub fn hash(&self) -> KeyRotationHash {
+        KeyRotationHash(hashing::keccak256_in_domain(
+            hashing::Domain::KeyRotation,
+            self,
+        ))
+    }
+
+    /// Verifies the rotation against the genesis validator set.
+    /// `genesis_hash` is passed explicitly, because rotations are not part of the genesis hash.
+    pub fn verify(
+        &self,
+        validators: &validator::ValidatorSet,
+        genesis_hash: GenesisHash,
+    ) -> anyhow::Result<()> {
+        let rotation = &self.rotation.msg;
+        anyhow::ensure!(rotation.genesis == genesis_hash, "genesis mismatch");
+        anyhow::ensure!(
+            validators.contains(&rotation.old_key),
+            "old_key is not a validator"
+        );
+        anyhow::ensure!(
+            !validators.contains(&rotation.new_key),
+            "new_key is already a validator"
+        );
+        anyhow::ensure!(
+            rotation.new_key.scheme() == rotation.old_key.scheme(),
+            "new_key has a different signature scheme"
+        );
+        anyhow::ensure!(
+            self.rotation.key == rotation.old_key,
+            "rotation not signed by old_key"
+        );
+        self.rotation.verify()?;
+        Signed {
+            msg: rotation.clone(),
+            key: rotation.new_key.clone(),
+            sig: self.new_key_sig.clone(),
+        }
+        .verify()?;
+        Ok(())
+    }
+}
+
+/// Collection of key rotations, at most one per validator.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeyRotations(BTreeMap<validator::PublicKey, KeyRotationCert>);
+
+impl KeyRotations {
+    /// Verifies that the rotation can be added to the collection.
+    pub fn check(&self, genesis: &Genesis, cert: &KeyRotationCert) -> anyhow::Result<()> {
+        cert.verify(&genesis.validators, genesis.hash())?;
+        let rotation = &cert.rotation.msg;
+        anyhow::ensure!(
+            !self.0.contains_key(&rotation.old_key),
+            "validator has already rotated its key"
+        );
+        anyhow::ensure!(
+            self.iter().all(|r| r.new_key != rotation.new_key),
+            "new_key is already used by a different validator"
+        );
+        Ok(())
+    }
+
+    /// Adds a verified rotation to the collection.
+    pub fn add(&mut self, genesis: &Genesis, cert: KeyRotationCert) -> anyhow::Result<()> {
+        self.check(genesis, &cert)?;
+        self.0.insert(cert.rotation.msg.old_key.clone(), cert);
+        Ok(())
+    }
+
+    /// Rotation of the given genesis validator key.
+    pub fn get(&self, old_key: &validator::PublicKey) -> Option<&KeyRotation> {
+        self.0.get(old_key).map(|cert| &cert.rotation.msg)
+    }
+
+    /// Iterates over the rotations.
+    pub fn iter(&self) -> impl Iterator<Item = &KeyRotation> {
+        self.0.values().map(|cert| &cert.rotation.msg)
+    }
+
+    /// Finds the rotation certificate with the given hash.
+    pub fn find(&self, hash: &KeyRotationHash) -> Option<&KeyRotationCert> {
+        self.certs().find(|cert| &cert.hash() == hash)
+    }
+
+    /// Checks whether the collection contains the given rotation certificate.
+    pub fn contains(&self, cert: &KeyRotationCert) -> bool {
+        self.0.get(&cert.rotation.msg.old_key) == Some(cert)
+    }
+
+    /// Iterates over the rotation certificates.
+    pub fn certs(&self) -> impl Iterator<Item = &KeyRotationCert> {
+        self.0.values()
+    }
+
+    /// Checks if there are no rotations.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Identity of a message signer within the validator set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SignerIndex {
+    /// Index of the validator in the genesis validator set.
+    pub index: usize,
+    /// Whether the validator has signed with its rotated key.
+    pub rotated: bool,
+}
+
+impl Genesis {
+    /// Verifies that the rotation can be committed by the next block of the chain,
+    /// given the rotations committed so far.
+    pub fn check_key_rotation(&self, cert: &KeyRotationCert) -> anyhow::Result<()> {
+        self.key_rotations.check(self, cert)
+    }
+
+    /// Genesis with the rotation committed by a block applied.
+    pub fn with_key_rotation(&self, cert: KeyRotationCert) -> anyhow::Result<Self> {
+        let mut genesis = self.clone();
+        genesis.key_rotations.add(self, cert)?;
+        Ok(genesis)
+    }
+
+    /// Resolves the key which has signed a message in the given `view`
+    /// to the validator in the genesis validator set. Returns `None` if `key`
+    /// is not accepted in `view` (i.e. it doesn't belong to a validator,
+    /// the rotation is not active yet, or the grace period of the old key has passed).
+    pub fn signer(&self, key: &validator::PublicKey, view: ViewNumber) -> Option<SignerIndex> {
+        if let Some(index) = self.validators.index(key) {
+            let accepted = self
+                .key_rotations
+                .get(key)
+                .map_or(true, |r| r.accepts_old_key(view));
+            return accepted.then_some(SignerIndex {
+                index,
+                rotated: false,
+            });
+        }
+        let rotation = self
+            .key_rotations
+            .iter()
+            .find(|r| &r.new_key == key && r.accepts_new_key(view))?;
+        Some(SignerIndex {
+            index: self.validators.index(&rotation.old_key)?,
+            rotated: true,
+        })
+    }
+
+    /// Checks whether `key` belongs to a validator and is accepted in the given `view`.
+    pub fn is_validator_key(&self, key: &validator::PublicKey, view: ViewNumber) -> bool {
+        self.signer(key, view).is_some()
+    }
+
+    /// Key that the validator `signer` has signed with in the given `view`.
+    /// Returns `None` if such a key is not accepted in `view`.
+    pub fn signer_key(
+        &self,
+        signer: SignerIndex,
+        view: ViewNumber,
+    ) -> Option<&validator::PublicKey> {
+        let key = self.validators.get(signer.index)?;
+        let rotation = self.key_rotations.get(key);
+        match (signer.rotated, rotation) {
+            (false, None) => Some(key),
+            (false, Some(r)) => r.accepts_old_key(view).then_some(key),
+            (true, Some(r)) => r.accepts_new_key(view).then_some(&r.new_key),
+            (true, None) => None,
+        }
+    }
+
+    /// Key that the leader of the given `view` is expected to use:
+    /// the rotated key if it is already active, the genesis key otherwise.
+    pub fn view_leader(&self, view: ViewNumber) -> validator::PublicKey {
+        let leader = self.validators.view_leader(view);
+        match self.key_rotations.get(&leader) {
+            Some(r) if r.accepts_new_key(view) => r.new_key.clone(),
+            _ => leader,
+        }
+    }
+
+    /// Checks whether `key` belongs to the leader of the given `view`
+    /// and is accepted in that view.
+    pub fn is_view_leader(&self, key: &validator::PublicKey, view: ViewNumber) -> bool {
+        self.signer(key, view)
+            .and_then(|s| self.validators.get(s.index))
+            .map_or(false, |k| k == &self.validators.view_leader(view))
+    }
+
+    /// Checks whether `key` is either the genesis key or the rotated key of a validator.
+    /// Used to authenticate validators outside of the consensus views
+    /// (e.g. on the network layer), where both keys are accepted.
+    pub fn accepts_validator_key(&self, key: &validator::PublicKey) -> bool {
+        self.validators.contains(key) || self.key_rotations.iter().any(|r| &r.new_key == key)
+    }
+
+    /// Iterates over all keys accepted by `accepts_validator_key`.
+    pub fn validator_keys(&self) -> impl Iterator<Item = &validator::PublicKey> {
+        self.validators
+            .iter()
+            .chain(self.key_rotations.iter().map(|r| &r.new_key))
+    }
+}
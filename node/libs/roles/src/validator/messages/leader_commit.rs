@@ -1,5 +1,6 @@
-use super::{BlockHeader, Genesis, ReplicaCommit, Signed, Signers, View};
+use super::{BlockHeader, Genesis, MsgHash, ReplicaCommit, Signed, SignerIndex, Signers, View};
 use crate::validator;
+use zksync_consensus_utils::enum_util::Variant as _;
 
 /// A Commit message from a leader.
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -28,6 +29,8 @@ pub struct CommitQC {
     pub message: ReplicaCommit,
     /// The validators that signed this message.
     pub signers: Signers,
+    /// Subset of `signers` which have signed with their rotated keys.
+    pub rotated: Signers,
     /// The aggregate signature of the signed replica messages.
     pub signature: validator::AggregateSignature,
 }
@@ -41,6 +44,9 @@ pub enum CommitQCVerifyError {
     /// Bad signer set.
     #[error("signers set doesn't match genesis")]
     BadSignersSet,
+    /// Key of a signer is not accepted in the view of the QC.
+    #[error("key of signer {0} is not accepted in this view")]
+    KeyNotAccepted(usize),
     /// Not enough signers.
     #[error("not enough signers: got {got}, want {want}")]
     NotEnoughSigners {
@@ -70,6 +76,7 @@ impl CommitQC {
         Self {
             message,
             signers: Signers::new(genesis.validators.len()),
+            rotated: Signers::new(genesis.validators.len()),
             signature: validator::AggregateSignature::default(),
         }
     }
@@ -80,23 +87,57 @@ impl CommitQC {
         if self.message != msg.msg {
             return;
         };
-        let Some(i) = genesis.validators.index(&msg.key) else {
+        let Some(signer) = genesis.signer(&msg.key, self.message.view.number) else {
             return;
         };
+        let i = signer.index;
         if self.signers.0[i] {
             return;
         };
+        if self
+            .signature
+            .add(self.signers.position(i), &msg.sig)
+            .is_err()
+        {
+            return;
+        }
         self.signers.0.set(i, true);
-        self.signature.add(&msg.sig);
+        self.rotated.0.set(i, signer.rotated);
     }
 
     /// Verifies the signature of the CommitQC.
     pub fn verify(&self, genesis: &Genesis) -> Result<(), CommitQCVerifyError> {
+        Self::verify_batch(genesis, [self])
+    }
+
+    /// Verifies multiple CommitQCs, checking all their signatures at once.
+    /// On failure, it doesn't tell which of the QCs is invalid.
+    pub fn verify_batch<'a>(
+        genesis: &Genesis,
+        qcs: impl IntoIterator<Item = &'a CommitQC>,
+    ) -> Result<(), CommitQCVerifyError> {
+        let mut batch = vec![];
+        for qc in qcs {
+            batch.push((&qc.signature, qc.verify_signers(genesis)?));
+        }
+        validator::AggregateSignature::verify_batch_hash(&batch)
+            .map_err(CommitQCVerifyError::BadSignature)
+    }
+
+    /// Verifies the CommitQC except for its signature. Returns the message hashes and
+    /// the keys of the signers, which the signature has to be verified against.
+    pub(crate) fn verify_signers<'a>(
+        &self,
+        genesis: &'a Genesis,
+    ) -> Result<Vec<(MsgHash, &'a validator::PublicKey)>, CommitQCVerifyError> {
         use CommitQCVerifyError as Error;
         self.message
             .verify(genesis)
             .map_err(Error::InvalidMessage)?;
-        if self.signers.len() != genesis.validators.len() {
+        if self.signers.len() != genesis.validators.len()
+            || self.rotated.len() != genesis.validators.len()
+            || (&self.rotated & &self.signers) != self.rotated
+        {
             return Err(Error::BadSignersSet);
         }
 
@@ -110,16 +151,19 @@ impl CommitQC {
             });
         }
 
-        // Now we can verify the signature.
-        let messages_and_keys = genesis
-            .validators
-            .iter()
-            .enumerate()
-            .filter(|(i, _)| self.signers.0[*i])
-            .map(|(_, pk)| (self.message.clone(), pk));
-
-        self.signature
-            .verify_messages(messages_and_keys)
-            .map_err(Error::BadSignature)
+        let view = self.message.view.number;
+        let hash = self.message.clone().insert().hash();
+        let mut hashes_and_keys = vec![];
+        for index in (0..self.signers.len()).filter(|i| self.signers.0[*i]) {
+            let signer = SignerIndex {
+                index,
+                rotated: self.rotated.0[index],
+            };
+            let pk = genesis
+                .signer_key(signer, view)
+                .ok_or(Error::KeyNotAccepted(index))?;
+            hashes_and_keys.push((hash, pk));
+        }
+        Ok(hashes_and_keys)
     }
 }
@@ -1,9 +1,11 @@
 use super::{
-    BlockHeader, BlockHeaderHash, BlockNumber, CommitQC, Genesis, Payload, ReplicaPrepare,
-    ReplicaPrepareVerifyError, Signed, Signers, View,
+    BlockHeader, BlockHeaderHash, BlockNumber, CommitQC, CommitQCVerifyError, Genesis,
+    KeyRotationCert, Payload, ProtocolUpgradeQC, ReplicaPrepare, ReplicaPrepareVerifyError, Signed,
+    SignerIndex, Signers, TimeoutQC, TimeoutQCVerifyError, View, ViewNumber,
 };
 use crate::validator;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use zksync_consensus_utils::enum_util::Variant as _;
 
 /// A quorum certificate of replica Prepare messages. Since not all Prepare messages are
 /// identical (they have different high blocks and high QCs), we need to keep the high blocks
@@ -14,6 +16,9 @@ pub struct PrepareQC {
     pub view: View,
     /// Map from replica Prepare messages to the validators that signed them.
     pub map: BTreeMap<ReplicaPrepare, Signers>,
+    /// Validators which have signed with their rotated keys.
+    /// `None` is equivalent to an empty set.
+    pub rotated: Option<Signers>,
     /// Aggregate signature of the replica Prepare messages.
     pub signature: validator::AggregateSignature,
 }
@@ -27,6 +32,10 @@ pub enum PrepareQCVerifyError {
     /// Invalid message.
     #[error("msg[{0}]: {1:#}")]
     InvalidMessage(usize, ReplicaPrepareVerifyError),
+    /// Invalid high QC of a message. Bad signatures of the high QCs are reported
+    /// as `BadSignature`, since they are verified together with the QC signature.
+    #[error("high QC: {0:#}")]
+    HighQC(CommitQCVerifyError),
     /// Bad message format.
     #[error(transparent)]
     BadFormat(anyhow::Error),
@@ -49,6 +58,7 @@ impl PrepareQC {
         Self {
             view,
             map: BTreeMap::new(),
+            rotated: None,
             signature: validator::AggregateSignature::default(),
         }
     }
@@ -83,18 +93,28 @@ impl PrepareQC {
         if msg.msg.view != self.view {
             return;
         }
-        let Some(i) = genesis.validators.index(&msg.key) else {
+        let Some(signer) = genesis.signer(&msg.key, self.view.number) else {
             return;
         };
-        let e = self
-            .map
-            .entry(msg.msg.clone())
-            .or_insert_with(|| Signers::new(genesis.validators.len()));
-        if e.0[i] {
+        let i = signer.index;
+        if self.map.get(&msg.msg).is_some_and(|signers| signers.0[i]) {
             return;
-        };
-        e.0.set(i, true);
-        self.signature.add(&msg.sig);
+        }
+        let pos = self.map.values().map(|signers| signers.position(i)).sum();
+        if self.signature.add(pos, &msg.sig).is_err() {
+            return;
+        }
+        self.map
+            .entry(msg.msg.clone())
+            .or_insert_with(|| Signers::new(genesis.validators.len()))
+            .0
+            .set(i, true);
+        if signer.rotated {
+            self.rotated
+                .get_or_insert_with(|| Signers::new(genesis.validators.len()))
+                .0
+                .set(i, true);
+        }
     }
 
     /// Verifies the integrity of the PrepareQC.
@@ -121,11 +141,20 @@ impl PrepareQC {
                     "overlapping signature sets for different messages"
                 )));
             }
-            msg.verify(genesis)
+            // The high QCs are verified below, all at once.
+            msg.verify_with(genesis, |_| Ok(()))
                 .map_err(|err| Error::InvalidMessage(i, err))?;
             sum |= signers;
         }
 
+        if let Some(rotated) = &self.rotated {
+            if rotated.len() != sum.len() || (rotated & &sum) != *rotated {
+                return Err(Error::BadFormat(anyhow::format_err!(
+                    "rotated is not a subset of signers"
+                )));
+            }
+        }
+
         // Verify that we have enough signers.
         let threshold = genesis.validators.threshold();
         if sum.count() < threshold {
@@ -134,20 +163,48 @@ impl PrepareQC {
                 want: threshold,
             });
         }
-        // Now we can verify the signature.
-        let messages_and_keys = self.map.clone().into_iter().flat_map(|(msg, signers)| {
-            genesis
-                .validators
-                .iter()
-                .enumerate()
-                .filter(|(i, _)| signers.0[*i])
-                .map(|(_, pk)| (msg.clone(), pk))
-                .collect::<Vec<_>>()
-        });
-        // TODO(gprusak): This reaggregating is suboptimal.
-        self.signature
-            .verify_messages(messages_and_keys)
-            .map_err(Error::BadSignature)
+
+        // Now we can verify the signature. The signatures are ordered by the signers.
+        let mut hashes_and_keys = vec![];
+        for (msg, signers) in &self.map {
+            let hash = msg.clone().insert().hash();
+            for index in (0..signers.len()).filter(|i| signers.0[*i]) {
+                let signer = SignerIndex {
+                    index,
+                    rotated: self.rotated.as_ref().map_or(false, |r| r.0[index]),
+                };
+                let pk = genesis
+                    .signer_key(signer, self.view.number)
+                    .ok_or_else(|| {
+                        Error::BadFormat(anyhow::format_err!(
+                            "key of signer {index} is not accepted in this view"
+                        ))
+                    })?;
+                hashes_and_keys.push((index, hash, pk));
+            }
+        }
+        hashes_and_keys.sort_by_key(|(index, _, _)| *index);
+        let mut batch = vec![(
+            &self.signature,
+            hashes_and_keys
+                .into_iter()
+                .map(|(_, hash, pk)| (hash, pk))
+                .collect::<Vec<_>>(),
+        )];
+        // The signatures of the high QCs are verified together with the signature of the
+        // PrepareQC. Replicas mostly send the same high QC, so every distinct QC is verified once.
+        let high_qcs: BTreeSet<_> = self
+            .map
+            .keys()
+            .filter_map(|msg| msg.high_qc.as_ref())
+            .collect();
+        for qc in high_qcs {
+            batch.push((
+                &qc.signature,
+                qc.verify_signers(genesis).map_err(Error::HighQC)?,
+            ));
+        }
+        validator::AggregateSignature::verify_batch_hash(&batch).map_err(Error::BadSignature)
     }
 }
 
@@ -161,6 +218,16 @@ pub struct LeaderPrepare {
     pub proposal_payload: Option<Payload>,
     /// The PrepareQC that justifies this proposal from the leader.
     pub justification: PrepareQC,
+    /// Key rotation committed by the proposed block, see `BlockHeader::key_rotation`.
+    /// `None` if this is a reproposal or the block doesn't commit to a rotation.
+    pub proposal_key_rotation: Option<KeyRotationCert>,
+    /// TimeoutQC of the view preceding the view of the proposal, which proves that the leader
+    /// has entered its view because the previous view has timed out.
+    /// `None` if the leader hasn't aggregated one (e.g. the previous view has finalized a block).
+    pub timeout_qc: Option<TimeoutQC>,
+    /// Protocol upgrade committed by the proposed block, see `BlockHeader::protocol_upgrade`.
+    /// `None` if this is a reproposal or the block doesn't commit to an upgrade.
+    pub proposal_protocol_upgrade: Option<ProtocolUpgradeQC>,
 }
 
 /// Error returned by `LeaderPrepare::verify()`.
@@ -169,6 +236,15 @@ pub enum LeaderPrepareVerifyError {
     /// Justification
     #[error("justification: {0:#}")]
     Justification(PrepareQCVerifyError),
+    /// Invalid TimeoutQC.
+    #[error("timeout_qc: {0:#}")]
+    TimeoutQC(TimeoutQCVerifyError),
+    /// TimeoutQC which is not for the view preceding the view of the proposal.
+    #[error("timeout_qc for view {got:?}, which doesn't precede the view of the proposal")]
+    TimeoutQCBadView {
+        /// View of the TimeoutQC.
+        got: View,
+    },
     /// Bad block number.
     #[error("bad block number: got {got:?}, want {want:?}")]
     BadBlockNumber {
@@ -191,6 +267,26 @@ pub enum LeaderPrepareVerifyError {
     /// Mismatched payload.
     #[error("block proposal with mismatched payload")]
     ProposalMismatchedPayload,
+    /// Mismatched key rotation.
+    #[error("block proposal with mismatched key rotation")]
+    ProposalMismatchedKeyRotation,
+    /// Key rotation which doesn't activate after the view of the proposal.
+    #[error("key rotation activated at view {activation:?}, which is not after the proposal view")]
+    ProposalKeyRotationActivation {
+        /// Activation view of the rotation.
+        activation: ViewNumber,
+    },
+    /// Mismatched protocol upgrade.
+    #[error("block proposal with mismatched protocol upgrade")]
+    ProposalMismatchedProtocolUpgrade,
+    /// Protocol upgrade which doesn't activate after the proposed block.
+    #[error(
+        "protocol upgrade activated at block {activation:?}, which is not after the proposed block"
+    )]
+    ProposalProtocolUpgradeActivation {
+        /// Activation block of the upgrade.
+        activation: BlockNumber,
+    },
     /// Re-proposal without quorum.
     #[error("block re-proposal without quorum for the re-proposal")]
     ReproposalWithoutQuorum,
@@ -214,6 +310,14 @@ impl LeaderPrepare {
         self.justification
             .verify(genesis)
             .map_err(Error::Justification)?;
+        if let Some(qc) = &self.timeout_qc {
+            if qc.view().fork != self.view().fork || qc.view().number.next() != self.view().number {
+                return Err(Error::TimeoutQCBadView {
+                    got: qc.view().clone(),
+                });
+            }
+            qc.verify(genesis).map_err(Error::TimeoutQC)?;
+        }
         let high_vote = self.justification.high_vote(genesis);
         let high_qc = self.justification.high_qc();
 
@@ -225,6 +329,40 @@ impl LeaderPrepare {
                 if self.proposal.payload != payload.hash() {
                     return Err(Error::ProposalMismatchedPayload);
                 }
+                // Check that the header commits to the payload root iff the protocol version requires it.
+                let payload_root = genesis
+                    .protocol_version_at(self.proposal.number)
+                    .commits_payload_root()
+                    .then(|| payload.root());
+                if self.proposal.payload_root != payload_root {
+                    return Err(Error::ProposalMismatchedPayload);
+                }
+                // Check that the header commits to the key rotation. Whether the rotation
+                // can be committed depends on the rotations committed by the preceding blocks,
+                // which is verified by the replica once it has stored them.
+                if self.proposal.key_rotation
+                    != self.proposal_key_rotation.as_ref().map(|r| r.hash())
+                {
+                    return Err(Error::ProposalMismatchedKeyRotation);
+                }
+                if let Some(cert) = &self.proposal_key_rotation {
+                    let activation = cert.rotation.msg.activation;
+                    if activation <= self.view().number {
+                        return Err(Error::ProposalKeyRotationActivation { activation });
+                    }
+                }
+                // Same for the protocol upgrade, which has to activate after the proposed block.
+                if self.proposal.protocol_upgrade
+                    != self.proposal_protocol_upgrade.as_ref().map(|u| u.hash())
+                {
+                    return Err(Error::ProposalMismatchedProtocolUpgrade);
+                }
+                if let Some(qc) = &self.proposal_protocol_upgrade {
+                    let activation = qc.message.activation;
+                    if activation <= self.proposal.number {
+                        return Err(Error::ProposalProtocolUpgradeActivation { activation });
+                    }
+                }
                 // Check that we finalized the previous block.
                 if high_vote.is_some()
                     && high_vote.as_ref() != high_qc.map(|qc| &qc.message.proposal)
@@ -232,7 +370,10 @@ impl LeaderPrepare {
                     return Err(Error::ProposalWhenPreviousNotFinalized);
                 }
                 let (want_parent, want_number) = match high_qc {
-                    Some(qc) => (Some(qc.header().hash()), qc.header().number.next()),
+                    Some(qc) => (
+                        Some(genesis.header_hash(qc.header())),
+                        qc.header().number.next(),
+                    ),
                     None => (genesis.fork.first_parent, genesis.fork.first_block),
                 };
                 if self.proposal.parent != want_parent {
@@ -249,6 +390,12 @@ impl LeaderPrepare {
                 }
             }
             None => {
+                if self.proposal_key_rotation.is_some() {
+                    return Err(Error::ProposalMismatchedKeyRotation);
+                }
+                if self.proposal_protocol_upgrade.is_some() {
+                    return Err(Error::ProposalMismatchedProtocolUpgrade);
+                }
                 let Some(high_vote) = &high_vote else {
                     return Err(Error::ReproposalWithoutQuorum);
                 };
@@ -0,0 +1,145 @@
+use super::{Genesis, ReplicaTimeout, Signed, SignerIndex, Signers, View};
+use crate::validator;
+
+/// A Timeout message from the leader of the next view.
+/// It proves to the replicas that the view has timed out,
+/// so that they can move on to the next view without waiting for their own timeout.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LeaderTimeout {
+    /// The TimeoutQC that justifies the message from the leader.
+    pub justification: TimeoutQC,
+}
+
+impl LeaderTimeout {
+    /// Verifies LeaderTimeout.
+    pub fn verify(&self, genesis: &Genesis) -> Result<(), TimeoutQCVerifyError> {
+        self.justification.verify(genesis)
+    }
+
+    /// View of this message (i.e. the view which has timed out).
+    pub fn view(&self) -> &View {
+        self.justification.view()
+    }
+}
+
+/// A Timeout Quorum Certificate. It is an aggregate of signed replica Timeout messages.
+/// All the messages for a given view are identical, so we only need one message.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeoutQC {
+    /// The replica Timeout message that the QC is for.
+    pub message: ReplicaTimeout,
+    /// The validators that signed this message.
+    pub signers: Signers,
+    /// Subset of `signers` which have signed with their rotated keys.
+    pub rotated: Signers,
+    /// The aggregate signature of the signed replica messages.
+    pub signature: validator::AggregateSignature,
+}
+
+/// Error returned by `TimeoutQC::verify()`.
+#[derive(thiserror::Error, Debug)]
+pub enum TimeoutQCVerifyError {
+    /// Invalid message.
+    #[error("invalid message: {0:#}")]
+    InvalidMessage(#[source] anyhow::Error),
+    /// Bad signer set.
+    #[error("signers set doesn't match genesis")]
+    BadSignersSet,
+    /// Key of a signer is not accepted in the view of the QC.
+    #[error("key of signer {0} is not accepted in this view")]
+    KeyNotAccepted(usize),
+    /// Not enough signers.
+    #[error("not enough signers: got {got}, want {want}")]
+    NotEnoughSigners {
+        /// Got signers.
+        got: usize,
+        /// Want signers.
+        want: usize,
+    },
+    /// Bad signature.
+    #[error("bad signature: {0:#}")]
+    BadSignature(#[source] validator::Error),
+}
+
+impl TimeoutQC {
+    /// View of this QC.
+    pub fn view(&self) -> &View {
+        &self.message.view
+    }
+
+    /// Create a new empty instance for a given `ReplicaTimeout` message and a validator set size.
+    pub fn new(message: ReplicaTimeout, genesis: &Genesis) -> Self {
+        Self {
+            message,
+            signers: Signers::new(genesis.validators.len()),
+            rotated: Signers::new(genesis.validators.len()),
+            signature: validator::AggregateSignature::default(),
+        }
+    }
+
+    /// Add a validator's signature.
+    /// Signature is assumed to be already verified.
+    pub fn add(&mut self, msg: &Signed<ReplicaTimeout>, genesis: &Genesis) {
+        if self.message != msg.msg {
+            return;
+        };
+        let Some(signer) = genesis.signer(&msg.key, self.message.view.number) else {
+            return;
+        };
+        let i = signer.index;
+        if self.signers.0[i] {
+            return;
+        };
+        if self
+            .signature
+            .add(self.signers.position(i), &msg.sig)
+            .is_err()
+        {
+            return;
+        }
+        self.signers.0.set(i, true);
+        self.rotated.0.set(i, signer.rotated);
+    }
+
+    /// Verifies the signature of the TimeoutQC.
+    pub fn verify(&self, genesis: &Genesis) -> Result<(), TimeoutQCVerifyError> {
+        use TimeoutQCVerifyError as Error;
+        self.message
+            .verify(genesis)
+            .map_err(Error::InvalidMessage)?;
+        if self.signers.len() != genesis.validators.len()
+            || self.rotated.len() != genesis.validators.len()
+            || (&self.rotated & &self.signers) != self.rotated
+        {
+            return Err(Error::BadSignersSet);
+        }
+
+        // Verify that we have enough signers.
+        let num_signers = self.signers.count();
+        let threshold = genesis.validators.threshold();
+        if num_signers < threshold {
+            return Err(Error::NotEnoughSigners {
+                got: num_signers,
+                want: threshold,
+            });
+        }
+
+        // Now we can verify the signature.
+        let view = self.message.view.number;
+        let mut messages_and_keys = vec![];
+        for index in (0..self.signers.len()).filter(|i| self.signers.0[*i]) {
+            let signer = SignerIndex {
+                index,
+                rotated: self.rotated.0[index],
+            };
+            let pk = genesis
+                .signer_key(signer, view)
+                .ok_or(Error::KeyNotAccepted(index))?;
+            messages_and_keys.push((self.message.clone(), pk));
+        }
+
+        self.signature
+            .verify_messages(messages_and_keys.into_iter())
+            .map_err(Error::BadSignature)
+    }
+}
@@ -3,17 +3,37 @@
 mod block;
 mod consensus;
 mod discovery;
+mod double_sign;
+mod finality;
+mod hashing;
+mod heartbeat;
+mod key_rotation;
 mod leader_commit;
 mod leader_prepare;
+mod leader_timeout;
 mod msg;
+mod payload_root;
+mod protocol_upgrade;
 mod replica_commit;
 mod replica_prepare;
+mod replica_timeout;
+mod validator_set_commitment;
 
 pub use block::*;
 pub use consensus::*;
 pub use discovery::*;
+pub use double_sign::*;
+pub use finality::*;
+pub use hashing::*;
+pub use heartbeat::*;
+pub use key_rotation::*;
 pub use leader_commit::*;
 pub use leader_prepare::*;
+pub use leader_timeout::*;
 pub use msg::*;
+pub use payload_root::*;
+pub use protocol_upgrade::*;
 pub use replica_commit::*;
 pub use replica_prepare::*;
+pub use replica_timeout::*;
+pub use validator_set_commitment::*;
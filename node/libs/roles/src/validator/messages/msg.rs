@@ -1,8 +1,10 @@
 //! Generic message types.
-use super::{ConsensusMsg, NetAddress};
+use super::{
+    hashing, ConsensusMsg, Digest, HashScheme, Heartbeat, KeyRotation, NetAddress, ProtocolUpgrade,
+};
 use crate::{node::SessionId, validator, validator::Error};
 use std::fmt;
-use zksync_consensus_crypto::{keccak256, ByteFmt, Text, TextFmt};
+use zksync_consensus_crypto::{ByteFmt, Text, TextFmt};
 use zksync_consensus_utils::enum_util::{BadVariantError, Variant};
 
 /// Generic message type for a validator.
@@ -14,12 +16,24 @@ pub enum Msg {
     SessionId(SessionId),
     /// validator discovery
     NetAddress(NetAddress),
+    /// validator liveness
+    Heartbeat(Heartbeat),
+    /// validator key rotation
+    KeyRotation(KeyRotation),
+    /// protocol upgrade announcement
+    ProtocolUpgrade(ProtocolUpgrade),
 }
 
 impl Msg {
-    /// Returns the hash of the message.
+    /// Returns the hash of the message, which is signed by the validators.
+    /// Consensus messages are hashed under the hashing scheme of their protocol version,
+    /// the other messages are not bound to a protocol version and use the legacy scheme.
     pub fn hash(&self) -> MsgHash {
-        MsgHash(keccak256::Keccak256::new(&zksync_protobuf::canonical(self)))
+        let scheme = match self {
+            Self::Consensus(msg) => msg.protocol_version().hash_scheme(),
+            _ => HashScheme::Legacy,
+        };
+        MsgHash(scheme.hash(hashing::Domain::Msg, self))
     }
 }
 
@@ -59,9 +73,45 @@ impl Variant<Msg> for NetAddress {
     }
 }
 
+impl Variant<Msg> for Heartbeat {
+    fn insert(self) -> Msg {
+        Msg::Heartbeat(self)
+    }
+    fn extract(msg: Msg) -> Result<Self, BadVariantError> {
+        let Msg::Heartbeat(this) = msg else {
+            return Err(BadVariantError);
+        };
+        Ok(this)
+    }
+}
+
+impl Variant<Msg> for KeyRotation {
+    fn insert(self) -> Msg {
+        Msg::KeyRotation(self)
+    }
+    fn extract(msg: Msg) -> Result<Self, BadVariantError> {
+        let Msg::KeyRotation(this) = msg else {
+            return Err(BadVariantError);
+        };
+        Ok(this)
+    }
+}
+
+impl Variant<Msg> for ProtocolUpgrade {
+    fn insert(self) -> Msg {
+        Msg::ProtocolUpgrade(self)
+    }
+    fn extract(msg: Msg) -> Result<Self, BadVariantError> {
+        let Msg::ProtocolUpgrade(this) = msg else {
+            return Err(BadVariantError);
+        };
+        Ok(this)
+    }
+}
+
 /// Hash of a message.
 #[derive(Clone, Copy, PartialEq, Eq)]
-pub struct MsgHash(pub(crate) keccak256::Keccak256);
+pub struct MsgHash(pub(crate) Digest);
 
 impl ByteFmt for MsgHash {
     fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
@@ -75,16 +125,11 @@ impl ByteFmt for MsgHash {
 
 impl TextFmt for MsgHash {
     fn decode(text: Text) -> anyhow::Result<Self> {
-        text.strip("validator_msg:keccak256:")?
-            .decode_hex()
-            .map(Self)
+        Digest::decode_text(text.strip("validator_msg:")?).map(Self)
     }
 
     fn encode(&self) -> String {
-        format!(
-            "validator_msg:keccak256:{}",
-            hex::encode(ByteFmt::encode(&self.0))
-        )
+        format!("validator_msg:{}", hex::encode(ByteFmt::encode(&self.0)))
     }
 }
 
@@ -0,0 +1,222 @@
+//! Merkle commitment to the payload of a block.
+//! The payload is split into chunks of `PAYLOAD_CHUNK_SIZE` bytes (the last one may be shorter,
+//! an empty payload consists of a single empty chunk) and the root commits to the Merkle tree
+//! over the chunks together with the payload size. A single chunk can then be verified against
+//! the block header without the rest of the payload, e.g. for the payload availability sampling.
+//!
+//! The tree is binary; a node without a sibling is promoted to the next level unchanged.
+//! Leaves, inner nodes and the root are hashed with distinct prefixes.
+use super::Payload;
+use anyhow::Context as _;
+use std::fmt;
+use zksync_consensus_crypto::{
+    keccak256::{self, Keccak256},
+    ByteFmt, Text, TextFmt,
+};
+
+/// Size of the payload chunks committed to by [`PayloadRoot`].
+pub const PAYLOAD_CHUNK_SIZE: usize = 4 * 1024;
+
+/// Merkle root over the chunks of a payload, see the module docs.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PayloadRoot(pub(crate) Keccak256);
+
+impl TextFmt for PayloadRoot {
+    fn decode(text: Text) -> anyhow::Result<Self> {
+        text.strip("payload_root:keccak256:")?
+            .decode_hex()
+            .map(Self)
+    }
+
+    fn encode(&self) -> String {
+        format!(
+            "payload_root:keccak256:{}",
+            hex::encode(ByteFmt::encode(&self.0))
+        )
+    }
+}
+
+impl fmt::Debug for PayloadRoot {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(&TextFmt::encode(self))
+    }
+}
+
+/// Proof that a chunk belongs to the payload committed to by a [`PayloadRoot`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct PayloadChunkProof {
+    /// Size of the whole payload.
+    pub payload_size: usize,
+    /// Index of the chunk.
+    pub index: usize,
+    /// Siblings of the nodes on the path from the chunk to the root, bottom-up.
+    /// The promoted nodes have no sibling.
+    pub path: Vec<Keccak256>,
+}
+
+impl fmt::Debug for PayloadChunkProof {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("PayloadChunkProof")
+            .field("payload_size", &self.payload_size)
+            .field("index", &self.index)
+            .field("path_len", &self.path.len())
+            .finish()
+    }
+}
+
+/// Number of the chunks of a payload of the given size.
+pub fn payload_chunk_count(payload_size: usize) -> usize {
+    payload_size.div_ceil(PAYLOAD_CHUNK_SIZE).max(1)
+}
+
+fn hash(prefix: u8, parts: &[&[u8]]) -> Keccak256 {
+    let mut hasher = keccak256::Hasher::default();
+    hasher.update(&[prefix]);
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize()
+}
+
+fn leaf(chunk: &[u8]) -> Keccak256 {
+    hash(0, &[chunk])
+}
+
+fn node(left: &Keccak256, right: &Keccak256) -> Keccak256 {
+    hash(1, &[left.as_bytes(), right.as_bytes()])
+}
+
+fn root(payload_size: usize, tree_root: &Keccak256) -> PayloadRoot {
+    let size = u64::try_from(payload_size).unwrap().to_be_bytes();
+    PayloadRoot(hash(2, &[&size, tree_root.as_bytes()]))
+}
+
+impl Payload {
+    /// Chunks of the payload, see [`PayloadRoot`].
+    pub fn chunks(&self) -> Vec<&[u8]> {
+        if self.0.is_empty() {
+            return vec![&self.0[..]];
+        }
+        self.0.chunks(PAYLOAD_CHUNK_SIZE).collect()
+    }
+
+    /// Merkle tree over the chunks of the payload.
+    pub fn tree(&self) -> PayloadTree {
+        let mut levels = vec![self.chunks().into_iter().map(leaf).collect::<Vec<_>>()];
+        while levels.last().unwrap().len() > 1 {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+        PayloadTree {
+            payload_size: self.0.len(),
+            levels,
+        }
+    }
+
+    /// Merkle root over the chunks of the payload.
+    pub fn root(&self) -> PayloadRoot {
+        self.tree().root()
+    }
+
+    /// Proof of the chunk with the given index, `None` if out of range.
+    pub fn chunk_proof(&self, index: usize) -> Option<PayloadChunkProof> {
+        self.chunk_proofs(&[index])?.pop()
+    }
+
+    /// Proofs of the chunks with the given indices, `None` if any is out of range.
+    /// The Merkle tree is computed once for all the proofs.
+    pub fn chunk_proofs(&self, indices: &[usize]) -> Option<Vec<PayloadChunkProof>> {
+        self.tree().chunk_proofs(indices)
+    }
+}
+
+/// Merkle tree over the chunks of a payload, see [`PayloadRoot`].
+/// Computing it takes hashing the whole payload, so it can be kept
+/// to compute the proofs of multiple chunks over time.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PayloadTree {
+    /// Size of the payload.
+    payload_size: usize,
+    /// Levels of the tree, from the leaves to the root.
+    levels: Vec<Vec<Keccak256>>,
+}
+
+impl fmt::Debug for PayloadTree {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("PayloadTree")
+            .field("payload_size", &self.payload_size)
+            .field("root", &self.root())
+            .finish()
+    }
+}
+
+impl PayloadTree {
+    /// Merkle root of the tree.
+    pub fn root(&self) -> PayloadRoot {
+        root(self.payload_size, &self.levels.last().unwrap()[0])
+    }
+
+    /// Proofs of the chunks with the given indices, `None` if any is out of range.
+    pub fn chunk_proofs(&self, indices: &[usize]) -> Option<Vec<PayloadChunkProof>> {
+        let count = payload_chunk_count(self.payload_size);
+        if indices.iter().any(|i| *i >= count) {
+            return None;
+        }
+        let proof = |index: usize| {
+            let mut path = vec![];
+            let mut i = index;
+            for level in self.levels.iter().take_while(|level| level.len() > 1) {
+                if let Some(sibling) = level.get(i ^ 1) {
+                    path.push(*sibling);
+                }
+                i /= 2;
+            }
+            PayloadChunkProof {
+                payload_size: self.payload_size,
+                index,
+                path,
+            }
+        };
+        Some(indices.iter().map(|i| proof(*i)).collect())
+    }
+}
+
+impl PayloadChunkProof {
+    /// Verifies that `chunk` is the chunk with index `self.index`
+    /// of the payload committed to by `want`.
+    pub fn verify(&self, want: &PayloadRoot, chunk: &[u8]) -> anyhow::Result<()> {
+        let count = payload_chunk_count(self.payload_size);
+        anyhow::ensure!(self.index < count, "chunk index out of range");
+        let start = self.index * PAYLOAD_CHUNK_SIZE;
+        anyhow::ensure!(
+            chunk.len() == PAYLOAD_CHUNK_SIZE.min(self.payload_size - start),
+            "bad chunk size"
+        );
+        let mut path = self.path.iter();
+        let mut current = leaf(chunk);
+        let (mut i, mut len) = (self.index, count);
+        while len > 1 {
+            if i ^ 1 < len {
+                let sibling = path.next().context("path too short")?;
+                current = match i % 2 {
+                    0 => node(&current, sibling),
+                    _ => node(sibling, &current),
+                };
+            }
+            i /= 2;
+            len = len.div_ceil(2);
+        }
+        anyhow::ensure!(path.next().is_none(), "path too long");
+        anyhow::ensure!(&root(self.payload_size, &current) == want, "root mismatch");
+        Ok(())
+    }
+}
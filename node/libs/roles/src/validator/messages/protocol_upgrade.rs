@@ -0,0 +1,319 @@
+//! Messages related to the upgrades of the protocol version.
+//! An upgrade is coordinated by a block height rather than a flag day: every validator
+//! announces that it supports the next version by signing a [`ProtocolUpgrade`], the
+//! announcements are aggregated into a [`ProtocolUpgradeQC`] once a quorum of validators
+//! has signed the same upgrade, and the certified upgrade is committed to the chain by a block
+//! (see `BlockHeader::protocol_upgrade`), which activates the new version starting with
+//! the `activation` block. Hence all the nodes following the chain agree on the upgrades.
+use super::{
+    hashing, BlockNumber, Genesis, GenesisHash, ProtocolVersion, Signed, SignerIndex, Signers,
+};
+use crate::validator;
+use std::fmt;
+use zksync_consensus_crypto::{keccak256::Keccak256, ByteFmt, Text, TextFmt};
+
+/// Announcement of a validator that it supports the protocol `version`
+/// and agrees to activate it at the `activation` block.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ProtocolUpgrade {
+    /// Genesis of the chain that the upgrade applies to.
+    /// Prevents replaying the announcement on a different chain.
+    pub genesis: GenesisHash,
+    /// Protocol version to upgrade to.
+    pub version: ProtocolVersion,
+    /// First block of the new protocol version.
+    pub activation: BlockNumber,
+}
+
+/// Protocol upgrade signed by a quorum of validators.
+/// Like the key rotations, the upgrades are chain state: an upgrade is proposed by the leader
+/// together with a new block and it is applied to the genesis once the block is finalized.
+/// Hence the upgrades are NOT a part of the genesis hash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProtocolUpgradeQC {
+    /// The certified upgrade.
+    pub message: ProtocolUpgrade,
+    /// The validators that signed the upgrade.
+    pub signers: Signers,
+    /// Subset of `signers` which have signed with their rotated keys.
+    pub rotated: Signers,
+    /// The aggregate signature of the upgrade.
+    pub signature: validator::AggregateSignature,
+}
+
+/// Error returned by `ProtocolUpgradeQC::verify()`.
+#[derive(thiserror::Error, Debug)]
+pub enum ProtocolUpgradeQCVerifyError {
+    /// Upgrade of a different chain.
+    #[error("genesis mismatch")]
+    GenesisMismatch,
+    /// Bad signer set.
+    #[error("signers set doesn't match genesis")]
+    BadSignersSet,
+    /// Signer has signed with a rotated key, which doesn't exist.
+    #[error("signer {0} has no rotated key")]
+    KeyNotAccepted(usize),
+    /// Not enough signers.
+    #[error("not enough signers: got {got}, want {want}")]
+    NotEnoughSigners {
+        /// Got signers.
+        got: usize,
+        /// Want signers.
+        want: usize,
+    },
+    /// Bad signature.
+    #[error("bad signature: {0:#}")]
+    BadSignature(#[source] validator::Error),
+}
+
+/// Hash of a `ProtocolUpgradeQC`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ProtocolUpgradeHash(pub(crate) Keccak256);
+
+impl TextFmt for ProtocolUpgradeHash {
+    fn decode(text: Text) -> anyhow::Result<Self> {
+        text.strip("protocol_upgrade:keccak256:")?
+            .decode_hex()
+            .map(Self)
+    }
+
+    fn encode(&self) -> String {
+        format!(
+            "protocol_upgrade:keccak256:{}",
+            hex::encode(ByteFmt::encode(&self.0))
+        )
+    }
+}
+
+impl fmt::Debug for ProtocolUpgradeHash {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(&TextFmt::encode(self))
+    }
+}
+
+impl Genesis {
+    /// Resolves the key which has signed a message outside of the consensus views
+    /// to the validator in the genesis validator set. Both the genesis key and
+    /// the rotated key are accepted, see `accepts_validator_key`.
+    fn any_view_signer(&self, key: &validator::PublicKey) -> Option<SignerIndex> {
+        if let Some(index) = self.validators.index(key) {
+            return Some(SignerIndex {
+                index,
+                rotated: false,
+            });
+        }
+        let rotation = self.key_rotations.iter().find(|r| &r.new_key == key)?;
+        Some(SignerIndex {
+            index: self.validators.index(&rotation.old_key)?,
+            rotated: true,
+        })
+    }
+
+    /// Protocol version of the block with the given number:
+    /// the version of the latest upgrade activated at or before the block,
+    /// or `protocol_version` if there is none.
+    pub fn protocol_version_at(&self, number: BlockNumber) -> ProtocolVersion {
+        self.protocol_upgrades
+            .iter()
+            .filter(|u| u.activation <= number)
+            .map(|u| u.version)
+            .fold(self.protocol_version, ProtocolVersion::max)
+    }
+
+    /// Latest protocol version that the chain is going to follow,
+    /// including the upgrades which are not active yet.
+    pub fn latest_protocol_version(&self) -> ProtocolVersion {
+        self.protocol_upgrades
+            .iter()
+            .map(|u| u.version)
+            .fold(self.protocol_version, ProtocolVersion::max)
+    }
+
+    /// Verifies that the upgrade can be committed by the block with the given number,
+    /// given the upgrades committed so far.
+    pub fn check_protocol_upgrade(
+        &self,
+        qc: &ProtocolUpgradeQC,
+        number: BlockNumber,
+    ) -> anyhow::Result<()> {
+        self.protocol_upgrades.check(self, qc, number)
+    }
+
+    /// Genesis with the upgrade committed by the block with the given number applied.
+    pub fn with_protocol_upgrade(
+        &self,
+        qc: ProtocolUpgradeQC,
+        number: BlockNumber,
+    ) -> anyhow::Result<Self> {
+        let mut genesis = self.clone();
+        genesis.protocol_upgrades.add(self, qc, number)?;
+        Ok(genesis)
+    }
+}
+
+impl ProtocolUpgradeQC {
+    /// Create a new empty instance for a given upgrade.
+    pub fn new(message: ProtocolUpgrade, genesis: &Genesis) -> Self {
+        Self {
+            message,
+            signers: Signers::new(genesis.validators.len()),
+            rotated: Signers::new(genesis.validators.len()),
+            signature: validator::AggregateSignature::default(),
+        }
+    }
+
+    /// Hash of the certificate, which the block header committing to the upgrade contains.
+    /// Upgrades are always hashed in their own domain with keccak256, like the key rotations,
+    /// since the hash is committed to by the blocks of different protocol versions.
+    pub fn hash(&self) -> ProtocolUpgradeHash {
+        ProtocolUpgradeHash(hashing::keccak256_in_domain(
+            hashing::Domain::ProtocolUpgrade,
+            self,
+        ))
+    }
+
+    /// Add a validator's signature.
+    /// Signature is assumed to be already verified.
+    pub fn add(&mut self, msg: &Signed<ProtocolUpgrade>, genesis: &Genesis) {
+        if self.message != msg.msg {
+            return;
+        };
+        let Some(signer) = genesis.any_view_signer(&msg.key) else {
+            return;
+        };
+        let i = signer.index;
+        if self.signers.0[i] {
+            return;
+        };
+        if self
+            .signature
+            .add(self.signers.position(i), &msg.sig)
+            .is_err()
+        {
+            return;
+        }
+        self.signers.0.set(i, true);
+        self.rotated.0.set(i, signer.rotated);
+    }
+
+    /// Verifies the signature of the ProtocolUpgradeQC.
+    /// `genesis.hash()` is expected in the upgrade, since the upgrades
+    /// are not part of the genesis hash.
+    pub fn verify(&self, genesis: &Genesis) -> Result<(), ProtocolUpgradeQCVerifyError> {
+        use ProtocolUpgradeQCVerifyError as Error;
+        if self.message.genesis != genesis.hash() {
+            return Err(Error::GenesisMismatch);
+        }
+        if self.signers.len() != genesis.validators.len()
+            || self.rotated.len() != genesis.validators.len()
+            || (&self.rotated & &self.signers) != self.rotated
+        {
+            return Err(Error::BadSignersSet);
+        }
+
+        // Verify that we have enough signers.
+        let num_signers = self.signers.count();
+        let threshold = genesis.validators.threshold();
+        if num_signers < threshold {
+            return Err(Error::NotEnoughSigners {
+                got: num_signers,
+                want: threshold,
+            });
+        }
+
+        // Now we can verify the signature.
+        let mut messages_and_keys = vec![];
+        for index in (0..self.signers.len()).filter(|i| self.signers.0[*i]) {
+            let key = genesis.validators.get(index).unwrap();
+            let key = match self.rotated.0[index] {
+                false => key,
+                true => {
+                    &genesis
+                        .key_rotations
+                        .get(key)
+                        .ok_or(Error::KeyNotAccepted(index))?
+                        .new_key
+                }
+            };
+            messages_and_keys.push((self.message.clone(), key));
+        }
+        self.signature
+            .verify_messages(messages_and_keys.into_iter())
+            .map_err(Error::BadSignature)
+    }
+}
+
+/// Collection of the certified protocol upgrades, ordered by activation.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProtocolUpgrades(Vec<ProtocolUpgradeQC>);
+
+impl ProtocolUpgrades {
+    /// Verifies that the upgrade can be added to the collection by the block `head`.
+    /// The upgrade has to be to a newer version than all the known ones and has to be
+    /// activated after `head` and after all the known upgrades, so that it doesn't change
+    /// the version of the blocks which may have been already finalized.
+    pub fn check(
+        &self,
+        genesis: &Genesis,
+        qc: &ProtocolUpgradeQC,
+        head: BlockNumber,
+    ) -> anyhow::Result<()> {
+        qc.verify(genesis)?;
+        let upgrade = &qc.message;
+        let latest = self
+            .iter()
+            .map(|u| u.version)
+            .fold(genesis.protocol_version, ProtocolVersion::max);
+        anyhow::ensure!(
+            upgrade.version > latest,
+            "upgrade to an older protocol version"
+        );
+        anyhow::ensure!(
+            upgrade.activation > head,
+            "upgrade activated at {:?}, which is not after the head {head:?}",
+            upgrade.activation
+        );
+        anyhow::ensure!(
+            self.iter().all(|u| u.activation < upgrade.activation),
+            "upgrade activated before a known upgrade"
+        );
+        Ok(())
+    }
+
+    /// Adds a verified upgrade to the collection, see `check`.
+    pub fn add(
+        &mut self,
+        genesis: &Genesis,
+        qc: ProtocolUpgradeQC,
+        head: BlockNumber,
+    ) -> anyhow::Result<()> {
+        self.check(genesis, &qc, head)?;
+        self.0.push(qc);
+        Ok(())
+    }
+
+    /// Iterates over the upgrades.
+    pub fn iter(&self) -> impl Iterator<Item = &ProtocolUpgrade> {
+        self.0.iter().map(|qc| &qc.message)
+    }
+
+    /// Finds the upgrade certificate with the given hash.
+    pub fn find(&self, hash: &ProtocolUpgradeHash) -> Option<&ProtocolUpgradeQC> {
+        self.certs().find(|qc| &qc.hash() == hash)
+    }
+
+    /// Checks whether the collection contains the given upgrade certificate.
+    pub fn contains(&self, qc: &ProtocolUpgradeQC) -> bool {
+        self.0.contains(qc)
+    }
+
+    /// Iterates over the upgrade certificates.
+    pub fn certs(&self) -> impl Iterator<Item = &ProtocolUpgradeQC> {
+        self.0.iter()
+    }
+
+    /// Checks if there are no upgrades.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
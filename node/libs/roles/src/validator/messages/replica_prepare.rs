@@ -39,6 +39,17 @@ pub enum ReplicaPrepareVerifyError {
 impl ReplicaPrepare {
     /// Verifies the message.
     pub fn verify(&self, genesis: &Genesis) -> Result<(), ReplicaPrepareVerifyError> {
+        self.verify_with(genesis, |qc| qc.verify(genesis))
+    }
+
+    /// Verifies the message, using `verify_high_qc` to verify the high QC.
+    /// Allows the caller to reuse the result of a QC verification done earlier,
+    /// since many replicas send the same high QC.
+    pub fn verify_with(
+        &self,
+        genesis: &Genesis,
+        verify_high_qc: impl FnOnce(&CommitQC) -> Result<(), CommitQCVerifyError>,
+    ) -> Result<(), ReplicaPrepareVerifyError> {
         use ReplicaPrepareVerifyError as Error;
         if self.view.fork != genesis.fork.number {
             return Err(Error::BadFork {
@@ -56,7 +67,7 @@ impl ReplicaPrepare {
             if self.view.number <= qc.view().number {
                 return Err(Error::HighQCFutureView);
             }
-            qc.verify(genesis).map_err(Error::HighQC)?;
+            verify_high_qc(qc).map_err(Error::HighQC)?;
         }
         Ok(())
     }
@@ -0,0 +1,17 @@
+use super::{Genesis, View};
+
+/// A Timeout message from a replica, signed when its view has timed out.
+/// It is sent to the leader of the next view, which aggregates the votes into a `TimeoutQC`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ReplicaTimeout {
+    /// View which has timed out.
+    pub view: View,
+}
+
+impl ReplicaTimeout {
+    /// Verifies the message.
+    pub fn verify(&self, genesis: &Genesis) -> anyhow::Result<()> {
+        anyhow::ensure!(self.view.fork == genesis.fork.number);
+        Ok(())
+    }
+}
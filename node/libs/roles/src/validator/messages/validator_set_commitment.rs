@@ -0,0 +1,167 @@
+//! Merkle commitment to a validator set.
+//! The commitment allows external systems (e.g. L1 contracts or light clients) to verify
+//! that a validator (e.g. a signer of a QC) belongs to the committee, given just the commitment
+//! and a membership proof, without the full list of validators.
+//!
+//! The leaves of the tree are the public keys of the validators, in the order of the
+//! `ValidatorSet` (validators are unweighted, so there are no weights to commit to).
+//! The tree is binary; a node without a sibling is promoted to the next level unchanged.
+//! The commitment is the hash of the tree root together with the number of validators.
+//! Leaves, inner nodes and the commitment are hashed with distinct prefixes.
+use super::ValidatorSet;
+use crate::validator;
+use anyhow::Context as _;
+use std::fmt;
+use zksync_consensus_crypto::{
+    keccak256::{self, Keccak256},
+    ByteFmt, Text, TextFmt,
+};
+
+/// Merkle commitment to a validator set, see the module docs.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ValidatorSetCommitment(pub(crate) Keccak256);
+
+impl TextFmt for ValidatorSetCommitment {
+    fn decode(text: Text) -> anyhow::Result<Self> {
+        text.strip("validator_set:keccak256:")?
+            .decode_hex()
+            .map(Self)
+    }
+
+    fn encode(&self) -> String {
+        format!(
+            "validator_set:keccak256:{}",
+            hex::encode(ByteFmt::encode(&self.0))
+        )
+    }
+}
+
+impl fmt::Debug for ValidatorSetCommitment {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(&TextFmt::encode(self))
+    }
+}
+
+/// Proof that a validator belongs to the validator set committed to by a
+/// [`ValidatorSetCommitment`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct ValidatorSetMembershipProof {
+    /// Number of validators in the set.
+    pub size: usize,
+    /// Index of the validator in the set.
+    pub index: usize,
+    /// Siblings of the nodes on the path from the validator to the root, bottom-up.
+    /// The promoted nodes have no sibling.
+    pub path: Vec<Keccak256>,
+}
+
+impl fmt::Debug for ValidatorSetMembershipProof {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ValidatorSetMembershipProof")
+            .field("size", &self.size)
+            .field("index", &self.index)
+            .field("path_len", &self.path.len())
+            .finish()
+    }
+}
+
+fn hash(prefix: u8, parts: &[&[u8]]) -> Keccak256 {
+    let mut hasher = keccak256::Hasher::default();
+    hasher.update(&[prefix]);
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize()
+}
+
+fn leaf(validator: &validator::PublicKey) -> Keccak256 {
+    hash(0, &[&ByteFmt::encode(validator)])
+}
+
+fn node(left: &Keccak256, right: &Keccak256) -> Keccak256 {
+    hash(1, &[left.as_bytes(), right.as_bytes()])
+}
+
+fn commitment(size: usize, tree_root: &Keccak256) -> ValidatorSetCommitment {
+    let size = u64::try_from(size).unwrap().to_be_bytes();
+    ValidatorSetCommitment(hash(2, &[&size, tree_root.as_bytes()]))
+}
+
+impl ValidatorSet {
+    /// Levels of the Merkle tree over the validators, from the leaves to the root.
+    fn tree(&self) -> Vec<Vec<Keccak256>> {
+        let mut levels = vec![self.iter().map(leaf).collect::<Vec<_>>()];
+        while levels.last().unwrap().len() > 1 {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+        levels
+    }
+
+    /// Merkle commitment to the validator set.
+    pub fn commitment(&self) -> ValidatorSetCommitment {
+        commitment(self.len(), &self.tree().last().unwrap()[0])
+    }
+
+    /// Proof that `validator` belongs to the set, `None` if it doesn't.
+    pub fn membership_proof(
+        &self,
+        validator: &validator::PublicKey,
+    ) -> Option<ValidatorSetMembershipProof> {
+        let index = self.index(validator)?;
+        let mut path = vec![];
+        let mut i = index;
+        for level in self.tree().iter().take_while(|level| level.len() > 1) {
+            if let Some(sibling) = level.get(i ^ 1) {
+                path.push(*sibling);
+            }
+            i /= 2;
+        }
+        Some(ValidatorSetMembershipProof {
+            size: self.len(),
+            index,
+            path,
+        })
+    }
+}
+
+impl ValidatorSetMembershipProof {
+    /// Verifies that `validator` is the validator with index `self.index`
+    /// of the validator set committed to by `want`.
+    pub fn verify(
+        &self,
+        want: &ValidatorSetCommitment,
+        validator: &validator::PublicKey,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(self.index < self.size, "validator index out of range");
+        let mut path = self.path.iter();
+        let mut current = leaf(validator);
+        let (mut i, mut len) = (self.index, self.size);
+        while len > 1 {
+            if i ^ 1 < len {
+                let sibling = path.next().context("path too short")?;
+                current = match i % 2 {
+                    0 => node(&current, sibling),
+                    _ => node(sibling, &current),
+                };
+            }
+            i /= 2;
+            len = len.div_ceil(2);
+        }
+        anyhow::ensure!(path.next().is_none(), "path too long");
+        anyhow::ensure!(
+            &commitment(self.size, &current) == want,
+            "commitment mismatch"
+        );
+        Ok(())
+    }
+}
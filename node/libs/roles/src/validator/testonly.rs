@@ -1,10 +1,15 @@
 //! Test-only utilities.
 use super::{
-    AggregateSignature, BlockHeader, BlockHeaderHash, BlockNumber, CommitQC, ConsensusMsg,
-    FinalBlock, Fork, ForkNumber, Genesis, GenesisHash, LeaderCommit, LeaderPrepare, Msg, MsgHash,
-    NetAddress, Payload, PayloadHash, Phase, PrepareQC, ProtocolVersion, PublicKey, ReplicaCommit,
-    ReplicaPrepare, SecretKey, Signature, Signed, Signers, ValidatorSet, View, ViewNumber,
+    AggregateSignature, BlockHeader, BlockHeaderHash, BlockNumber, CommitQC, ConsensusMsg, Digest,
+    DoubleSignProof, FinalBlock, FinalityProof, Fork, ForkNumber, Genesis, GenesisHash, Heartbeat,
+    KeyRotation, KeyRotationCert, KeyRotationHash, KeyRotations, LeaderCommit, LeaderPrepare,
+    LeaderTimeout, Msg, MsgHash, NetAddress, Payload, PayloadChunkProof, PayloadHash, PayloadRoot,
+    Phase, PrepareQC, ProtocolUpgrade, ProtocolUpgradeHash, ProtocolUpgradeQC, ProtocolUpgrades,
+    ProtocolVersion, PublicKey, ReplicaCommit, ReplicaPrepare, ReplicaTimeout, SecretKey,
+    Signature, SignatureScheme, Signed, Signers, TimeoutQC, ValidatorSet, ValidatorSetCommitment,
+    ValidatorSetMembershipProof, View, ViewNumber,
 };
+use crate::attester;
 use bit_vec::BitVec;
 use rand::{
     distributions::{Distribution, Standard},
@@ -21,13 +26,32 @@ pub struct Setup(SetupInner);
 impl Setup {
     /// New `Setup` with a given `fork`.
     pub fn new_with_fork(rng: &mut impl Rng, validators: usize, fork: Fork) -> Self {
-        let keys: Vec<SecretKey> = (0..validators).map(|_| rng.gen()).collect();
+        Self::new_with_fork_and_scheme(rng, validators, fork, SignatureScheme::Bn254)
+    }
+
+    /// New `Setup` with a given `fork` and validator keys of the given `scheme`.
+    pub fn new_with_fork_and_scheme(
+        rng: &mut impl Rng,
+        validators: usize,
+        fork: Fork,
+        scheme: SignatureScheme,
+    ) -> Self {
+        let keys: Vec<SecretKey> = (0..validators)
+            .map(|_| SecretKey::gen_with_scheme(rng, scheme))
+            .collect();
         let genesis = Genesis {
             validators: ValidatorSet::new(keys.iter().map(|k| k.public())).unwrap(),
             fork,
+            max_payload_size: None,
+            key_rotations: KeyRotations::default(),
+            attesters: None,
+            protocol_version: ProtocolVersion::EARLIEST,
+            protocol_upgrades: ProtocolUpgrades::default(),
+            signature_scheme: scheme,
         };
         Self(SetupInner {
             keys,
+            attester_keys: vec![],
             genesis,
             blocks: vec![],
         })
@@ -35,12 +59,17 @@ impl Setup {
 
     /// New `Setup`.
     pub fn new(rng: &mut impl Rng, validators: usize) -> Self {
+        Self::new_with_scheme(rng, validators, SignatureScheme::Bn254)
+    }
+
+    /// New `Setup` with validator keys of the given `scheme`.
+    pub fn new_with_scheme(rng: &mut impl Rng, validators: usize, scheme: SignatureScheme) -> Self {
         let fork = Fork {
             number: ForkNumber(rng.gen_range(0..100)),
             first_block: BlockNumber(rng.gen_range(0..100)),
             first_parent: Some(rng.gen()),
         };
-        Self::new_with_fork(rng, validators, fork)
+        Self::new_with_fork_and_scheme(rng, validators, fork, scheme)
     }
 
     /// Next block to finalize.
@@ -51,10 +80,71 @@ impl Setup {
         }
     }
 
+    /// Sets the protocol version of the genesis.
+    /// Has to be called before any blocks are pushed.
+    pub fn set_protocol_version(&mut self, version: ProtocolVersion) {
+        assert!(self.0.blocks.is_empty());
+        self.0.genesis.protocol_version = version;
+    }
+
     /// Pushes the next block with the given payload.
+    /// The header commits to the payload root iff the protocol version of the block requires it.
     pub fn push_block(&mut self, payload: Payload) {
+        self.push_block_with_key_rotation(payload, None);
+    }
+
+    /// Pushes the next block with the given payload, committing to the given key rotation.
+    /// The rotation is not applied to `genesis`: the stores apply it, once they store the block.
+    pub fn push_block_with_key_rotation(
+        &mut self,
+        payload: Payload,
+        key_rotation: Option<KeyRotationCert>,
+    ) {
+        self.push_block_committing(payload, key_rotation, None);
+    }
+
+    /// Pushes the next block with the given payload, committing to the given protocol upgrade.
+    /// Like the key rotations, the upgrade is not applied to `genesis`, but the blocks
+    /// pushed afterwards follow the version of the upgrade once it is activated.
+    pub fn push_block_with_protocol_upgrade(
+        &mut self,
+        payload: Payload,
+        protocol_upgrade: Option<ProtocolUpgradeQC>,
+    ) {
+        self.push_block_committing(payload, None, protocol_upgrade);
+    }
+
+    /// Genesis with the protocol upgrades committed by the pushed blocks applied.
+    pub fn chain_genesis(&self) -> Genesis {
+        let mut genesis = self.genesis.clone();
+        for b in &self.0.blocks {
+            if let Some(qc) = &b.protocol_upgrade {
+                if !genesis.protocol_upgrades.contains(qc) {
+                    genesis = genesis
+                        .with_protocol_upgrade(qc.clone(), b.number())
+                        .unwrap();
+                }
+            }
+        }
+        genesis
+    }
+
+    fn push_block_committing(
+        &mut self,
+        payload: Payload,
+        key_rotation: Option<KeyRotationCert>,
+        protocol_upgrade: Option<ProtocolUpgradeQC>,
+    ) {
+        let key_rotation_hash = key_rotation.as_ref().map(|r| r.hash());
+        let protocol_upgrade_hash = protocol_upgrade.as_ref().map(|u| u.hash());
+        let genesis = self.chain_genesis();
+        let payload_root = genesis
+            .protocol_version_at(self.next())
+            .commits_payload_root()
+            .then(|| payload.root());
+        // The views are stamped with the protocol version of the block, like the consensus does.
         let view = View {
-            protocol_version: ProtocolVersion::EARLIEST,
+            protocol_version: genesis.protocol_version_at(self.next()),
             fork: self.genesis.fork.number,
             number: self
                 .0
@@ -64,11 +154,21 @@ impl Setup {
                 .unwrap_or(ViewNumber(0)),
         };
         let proposal = match self.0.blocks.last() {
-            Some(b) => BlockHeader::next(b.header(), payload.hash()),
+            Some(b) => BlockHeader {
+                parent: Some(genesis.header_hash(b.header())),
+                number: b.number().next(),
+                payload: payload.hash(),
+                payload_root,
+                key_rotation: key_rotation_hash,
+                protocol_upgrade: protocol_upgrade_hash,
+            },
             None => BlockHeader {
                 parent: self.genesis.fork.first_parent,
                 number: self.genesis.fork.first_block,
                 payload: payload.hash(),
+                payload_root,
+                key_rotation: key_rotation_hash,
+                protocol_upgrade: protocol_upgrade_hash,
             },
         };
         let msg = ReplicaCommit { view, proposal };
@@ -82,6 +182,8 @@ impl Setup {
         self.0.blocks.push(FinalBlock {
             payload,
             justification,
+            key_rotation,
+            protocol_upgrade,
         });
     }
 
@@ -92,6 +194,89 @@ impl Setup {
         }
     }
 
+    /// Rotates the key of the `i`-th validator: generates a new key and adds
+    /// the rotation to the genesis, as if it was committed before the first block.
+    /// Returns the new key.
+    pub fn rotate_key(
+        &mut self,
+        rng: &mut impl Rng,
+        i: usize,
+        activation: ViewNumber,
+        grace_period: u64,
+    ) -> SecretKey {
+        let (new_key, cert) = self.key_rotation(rng, i, activation, grace_period);
+        let mut key_rotations = self.0.genesis.key_rotations.clone();
+        key_rotations.add(&self.0.genesis, cert).unwrap();
+        self.0.genesis.key_rotations = key_rotations;
+        new_key
+    }
+
+    /// Generates a new key for the `i`-th validator and a certificate of the rotation
+    /// to the new key, to be committed by a block (see `push_block_with_key_rotation`).
+    pub fn key_rotation(
+        &self,
+        rng: &mut impl Rng,
+        i: usize,
+        activation: ViewNumber,
+        grace_period: u64,
+    ) -> (SecretKey, KeyRotationCert) {
+        let old_key = &self.0.keys[i];
+        let new_key: SecretKey = rng.gen();
+        let rotation = KeyRotation {
+            genesis: self.0.genesis.hash(),
+            old_key: old_key.public(),
+            new_key: new_key.public(),
+            activation,
+            grace_period,
+        };
+        let cert = KeyRotationCert {
+            rotation: old_key.sign_msg(rotation.clone()),
+            new_key_sig: new_key.sign_msg(rotation).sig,
+        };
+        (new_key, cert)
+    }
+
+    /// Schedules an upgrade to the protocol `version` at the `activation` block,
+    /// certified by all the validators, as if it was committed before the first block.
+    pub fn schedule_protocol_upgrade(&mut self, version: ProtocolVersion, activation: BlockNumber) {
+        let qc = self.protocol_upgrade(version, activation);
+        let mut protocol_upgrades = self.0.genesis.protocol_upgrades.clone();
+        protocol_upgrades
+            .add(&self.0.genesis, qc, self.0.genesis.fork.first_block)
+            .unwrap();
+        self.0.genesis.protocol_upgrades = protocol_upgrades;
+    }
+
+    /// Certificate of an upgrade to the protocol `version` at the `activation` block,
+    /// signed by all the validators, to be committed by a block
+    /// (see `push_block_with_protocol_upgrade`).
+    pub fn protocol_upgrade(
+        &self,
+        version: ProtocolVersion,
+        activation: BlockNumber,
+    ) -> ProtocolUpgradeQC {
+        let upgrade = ProtocolUpgrade {
+            genesis: self.0.genesis.hash(),
+            version,
+            activation,
+        };
+        let mut qc = ProtocolUpgradeQC::new(upgrade.clone(), &self.0.genesis);
+        for key in &self.0.keys {
+            qc.add(&key.sign_msg(upgrade.clone()), &self.0.genesis);
+        }
+        qc
+    }
+
+    /// Replaces the attesters of the genesis with `count` new attesters.
+    /// Returns their keys.
+    pub fn add_attesters(&mut self, rng: &mut impl Rng, count: usize) -> Vec<attester::SecretKey> {
+        let keys: Vec<attester::SecretKey> = (0..count).map(|_| rng.gen()).collect();
+        self.0.genesis.attesters =
+            Some(attester::Committee::new(keys.iter().map(|k| k.public())).unwrap());
+        self.0.attester_keys = keys.clone();
+        keys
+    }
+
     /// Finds the block by the number.
     pub fn block(&self, n: BlockNumber) -> Option<&FinalBlock> {
         let first = self.0.blocks.first()?.number();
@@ -104,6 +289,8 @@ impl Setup {
 pub struct SetupInner {
     /// Validators' secret keys.
     pub keys: Vec<SecretKey>,
+    /// Attesters' secret keys.
+    pub attester_keys: Vec<attester::SecretKey>,
     /// Past blocks.
     pub blocks: Vec<FinalBlock>,
     /// Genesis config.
@@ -118,37 +305,66 @@ impl std::ops::Deref for Setup {
 }
 
 impl AggregateSignature {
-    /// Generate a new aggregate signature from a list of signatures.
+    /// Generate a new aggregate signature from a list of signatures, ordered by the signers.
     pub fn aggregate<'a>(sigs: impl IntoIterator<Item = &'a Signature>) -> Self {
         let mut agg = Self::default();
-        for sig in sigs {
-            agg.add(sig);
+        for (pos, sig) in sigs.into_iter().enumerate() {
+            agg.add(pos, sig).unwrap();
         }
         agg
     }
 }
 
+impl SecretKey {
+    /// Generates a random secret key of the given scheme.
+    pub fn gen_with_scheme<R: Rng + ?Sized>(rng: &mut R, scheme: SignatureScheme) -> Self {
+        match scheme {
+            SignatureScheme::Bn254 => Self::Bn254(Arc::new(rng.gen())),
+            SignatureScheme::Secp256k1 => Self::Secp256k1(Arc::new(rng.gen())),
+        }
+    }
+}
+
+impl Distribution<SignatureScheme> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> SignatureScheme {
+        match rng.gen_range(0..2) {
+            0 => SignatureScheme::Bn254,
+            _ => SignatureScheme::Secp256k1,
+        }
+    }
+}
+
 impl Distribution<AggregateSignature> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> AggregateSignature {
-        AggregateSignature(rng.gen())
+        match rng.gen() {
+            SignatureScheme::Bn254 => AggregateSignature::Bn254(rng.gen()),
+            SignatureScheme::Secp256k1 => AggregateSignature::Secp256k1(rng.gen()),
+        }
     }
 }
 
 impl Distribution<PublicKey> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> PublicKey {
-        PublicKey(rng.gen())
+        match rng.gen() {
+            SignatureScheme::Bn254 => PublicKey::Bn254(rng.gen()),
+            SignatureScheme::Secp256k1 => PublicKey::Secp256k1(rng.gen()),
+        }
     }
 }
 
 impl Distribution<Signature> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Signature {
-        Signature(rng.gen())
+        match rng.gen() {
+            SignatureScheme::Bn254 => Signature::Bn254(rng.gen()),
+            SignatureScheme::Secp256k1 => Signature::Secp256k1(rng.gen()),
+        }
     }
 }
 
+/// Generates bn254 keys, see `SecretKey::gen_with_scheme()` for the other schemes.
 impl Distribution<SecretKey> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> SecretKey {
-        SecretKey(Arc::new(rng.gen()))
+        SecretKey::gen_with_scheme(rng, SignatureScheme::Bn254)
     }
 }
 
@@ -170,6 +386,12 @@ impl Distribution<ForkNumber> for Standard {
     }
 }
 
+impl Distribution<Digest> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Digest {
+        Digest(rng.gen())
+    }
+}
+
 impl Distribution<GenesisHash> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> GenesisHash {
         GenesisHash(rng.gen())
@@ -188,9 +410,16 @@ impl Distribution<Fork> for Standard {
 
 impl Distribution<Genesis> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Genesis {
+        let validators: ValidatorSet = rng.gen();
         Genesis {
-            validators: rng.gen(),
+            signature_scheme: validators.scheme(),
+            validators,
             fork: rng.gen(),
+            max_payload_size: rng.gen(),
+            key_rotations: KeyRotations::default(),
+            attesters: rng.gen::<bool>().then(|| rng.gen()),
+            protocol_version: ProtocolVersion(rng.gen_range(0..4)),
+            protocol_upgrades: ProtocolUpgrades::default(),
         }
     }
 }
@@ -201,6 +430,40 @@ impl Distribution<PayloadHash> for Standard {
     }
 }
 
+impl Distribution<PayloadRoot> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> PayloadRoot {
+        PayloadRoot(rng.gen())
+    }
+}
+
+impl Distribution<PayloadChunkProof> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> PayloadChunkProof {
+        let n = rng.gen_range(0..10);
+        PayloadChunkProof {
+            payload_size: rng.gen(),
+            index: rng.gen(),
+            path: (0..n).map(|_| rng.gen()).collect(),
+        }
+    }
+}
+
+impl Distribution<ValidatorSetCommitment> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> ValidatorSetCommitment {
+        ValidatorSetCommitment(rng.gen())
+    }
+}
+
+impl Distribution<ValidatorSetMembershipProof> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> ValidatorSetMembershipProof {
+        let n = rng.gen_range(0..10);
+        ValidatorSetMembershipProof {
+            size: rng.gen(),
+            index: rng.gen(),
+            path: (0..n).map(|_| rng.gen()).collect(),
+        }
+    }
+}
+
 impl Distribution<BlockHeaderHash> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> BlockHeaderHash {
         BlockHeaderHash(rng.gen())
@@ -213,6 +476,9 @@ impl Distribution<BlockHeader> for Standard {
             parent: rng.gen(),
             number: rng.gen(),
             payload: rng.gen(),
+            payload_root: rng.gen(),
+            key_rotation: rng.gen(),
+            protocol_upgrade: rng.gen(),
         }
     }
 }
@@ -229,6 +495,8 @@ impl Distribution<FinalBlock> for Standard {
         FinalBlock {
             payload: rng.gen(),
             justification: rng.gen(),
+            key_rotation: rng.gen(),
+            protocol_upgrade: rng.gen(),
         }
     }
 }
@@ -258,6 +526,9 @@ impl Distribution<LeaderPrepare> for Standard {
             proposal: rng.gen(),
             proposal_payload: rng.gen(),
             justification: rng.gen(),
+            proposal_key_rotation: rng.gen(),
+            timeout_qc: rng.gen(),
+            proposal_protocol_upgrade: rng.gen(),
         }
     }
 }
@@ -270,6 +541,20 @@ impl Distribution<LeaderCommit> for Standard {
     }
 }
 
+impl Distribution<ReplicaTimeout> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> ReplicaTimeout {
+        ReplicaTimeout { view: rng.gen() }
+    }
+}
+
+impl Distribution<LeaderTimeout> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> LeaderTimeout {
+        LeaderTimeout {
+            justification: rng.gen(),
+        }
+    }
+}
+
 impl Distribution<PrepareQC> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> PrepareQC {
         let n = rng.gen_range(1..11);
@@ -278,6 +563,7 @@ impl Distribution<PrepareQC> for Standard {
         PrepareQC {
             view: rng.gen(),
             map,
+            rotated: Some(rng.gen()),
             signature: rng.gen(),
         }
     }
@@ -288,6 +574,18 @@ impl Distribution<CommitQC> for Standard {
         CommitQC {
             message: rng.gen(),
             signers: rng.gen(),
+            rotated: rng.gen(),
+            signature: rng.gen(),
+        }
+    }
+}
+
+impl Distribution<TimeoutQC> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> TimeoutQC {
+        TimeoutQC {
+            message: rng.gen(),
+            signers: rng.gen(),
+            rotated: rng.gen(),
             signature: rng.gen(),
         }
     }
@@ -302,7 +600,10 @@ impl Distribution<Signers> for Standard {
 impl Distribution<ValidatorSet> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> ValidatorSet {
         let count = rng.gen_range(1..11);
-        let public_keys = (0..count).map(|_| rng.gen());
+        let scheme = rng.gen();
+        let public_keys: Vec<_> = (0..count)
+            .map(|_| SecretKey::gen_with_scheme(rng, scheme).public())
+            .collect();
         ValidatorSet::new(public_keys).unwrap()
     }
 }
@@ -348,12 +649,96 @@ impl Distribution<NetAddress> for Standard {
     }
 }
 
+impl Distribution<Heartbeat> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Heartbeat {
+        Heartbeat {
+            genesis: rng.gen(),
+            timestamp: time::UNIX_EPOCH + time::Duration::seconds(rng.gen_range(0..1000000000)),
+        }
+    }
+}
+
+impl Distribution<KeyRotation> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> KeyRotation {
+        KeyRotation {
+            genesis: rng.gen(),
+            old_key: rng.gen(),
+            new_key: rng.gen(),
+            activation: rng.gen(),
+            grace_period: rng.gen(),
+        }
+    }
+}
+
+impl Distribution<ProtocolUpgrade> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> ProtocolUpgrade {
+        ProtocolUpgrade {
+            genesis: rng.gen(),
+            version: rng.gen(),
+            activation: rng.gen(),
+        }
+    }
+}
+
+impl Distribution<ProtocolUpgradeQC> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> ProtocolUpgradeQC {
+        ProtocolUpgradeQC {
+            message: rng.gen(),
+            signers: rng.gen(),
+            rotated: rng.gen(),
+            signature: rng.gen(),
+        }
+    }
+}
+
+impl Distribution<ProtocolUpgradeHash> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> ProtocolUpgradeHash {
+        ProtocolUpgradeHash(rng.gen())
+    }
+}
+
+impl Distribution<KeyRotationHash> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> KeyRotationHash {
+        KeyRotationHash(rng.gen())
+    }
+}
+
+impl Distribution<KeyRotationCert> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> KeyRotationCert {
+        KeyRotationCert {
+            rotation: rng.gen(),
+            new_key_sig: rng.gen(),
+        }
+    }
+}
+
+impl Distribution<DoubleSignProof> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> DoubleSignProof {
+        DoubleSignProof {
+            first: rng.gen(),
+            second: rng.gen(),
+        }
+    }
+}
+
+impl Distribution<FinalityProof> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> FinalityProof {
+        FinalityProof {
+            genesis: rng.gen(),
+            justification: rng.gen(),
+        }
+    }
+}
+
 impl Distribution<Msg> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Msg {
-        match rng.gen_range(0..3) {
+        match rng.gen_range(0..6) {
             0 => Msg::Consensus(rng.gen()),
             1 => Msg::SessionId(rng.gen()),
             2 => Msg::NetAddress(rng.gen()),
+            3 => Msg::Heartbeat(rng.gen()),
+            4 => Msg::KeyRotation(rng.gen()),
+            5 => Msg::ProtocolUpgrade(rng.gen()),
             _ => unreachable!(),
         }
     }
@@ -361,11 +746,13 @@ impl Distribution<Msg> for Standard {
 
 impl Distribution<ConsensusMsg> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> ConsensusMsg {
-        match rng.gen_range(0..4) {
+        match rng.gen_range(0..6) {
             0 => ConsensusMsg::ReplicaPrepare(rng.gen()),
             1 => ConsensusMsg::ReplicaCommit(rng.gen()),
             2 => ConsensusMsg::LeaderPrepare(rng.gen()),
             3 => ConsensusMsg::LeaderCommit(rng.gen()),
+            4 => ConsensusMsg::ReplicaTimeout(rng.gen()),
+            5 => ConsensusMsg::LeaderTimeout(rng.gen()),
             _ => unreachable!(),
         }
     }
@@ -4,7 +4,7 @@ use assert_matches::assert_matches;
 use rand::{seq::SliceRandom, Rng};
 use std::vec;
 use zksync_concurrency::ctx;
-use zksync_consensus_crypto::{ByteFmt, Text, TextFmt};
+use zksync_consensus_crypto::{keccak256::Keccak256, ByteFmt, Text, TextFmt};
 use zksync_protobuf::testonly::test_encode_random;
 
 #[test]
@@ -87,9 +87,23 @@ fn test_text_encoding() {
     let t = TextFmt::encode(&msg_hash);
     assert_eq!(msg_hash, Text::new(&t).decode::<MsgHash>().unwrap());
 
+    let commitment: ValidatorSetCommitment = rng.gen();
+    let t = TextFmt::encode(&commitment);
+    assert_eq!(
+        commitment,
+        Text::new(&t).decode::<ValidatorSetCommitment>().unwrap()
+    );
+
     let genesis_hash: GenesisHash = rng.gen();
     let t = TextFmt::encode(&genesis_hash);
     assert_eq!(genesis_hash, Text::new(&t).decode::<GenesisHash>().unwrap());
+
+    // The hashes encoded before blake2b-256 was introduced are still accepted.
+    let t = format!(
+        "genesis_hash:keccak256:{}",
+        hex::encode(genesis_hash.0.as_bytes())
+    );
+    assert_eq!(genesis_hash, Text::new(&t).decode::<GenesisHash>().unwrap());
 }
 
 #[test]
@@ -98,12 +112,25 @@ fn test_schema_encoding() {
     let rng = &mut ctx.rng();
     test_encode_random::<PayloadHash>(rng);
     test_encode_random::<BlockHeader>(rng);
+    test_encode_random::<PayloadRoot>(rng);
+    test_encode_random::<PayloadChunkProof>(rng);
+    test_encode_random::<ValidatorSetCommitment>(rng);
+    test_encode_random::<ValidatorSetMembershipProof>(rng);
     test_encode_random::<BlockHeaderHash>(rng);
     test_encode_random::<FinalBlock>(rng);
     test_encode_random::<Signed<ConsensusMsg>>(rng);
     test_encode_random::<PrepareQC>(rng);
     test_encode_random::<CommitQC>(rng);
+    test_encode_random::<TimeoutQC>(rng);
     test_encode_random::<Msg>(rng);
+    test_encode_random::<Heartbeat>(rng);
+    test_encode_random::<KeyRotationCert>(rng);
+    test_encode_random::<KeyRotationHash>(rng);
+    test_encode_random::<ProtocolUpgradeHash>(rng);
+    test_encode_random::<ProtocolUpgrade>(rng);
+    test_encode_random::<ProtocolUpgradeQC>(rng);
+    test_encode_random::<DoubleSignProof>(rng);
+    test_encode_random::<FinalityProof>(rng);
     test_encode_random::<MsgHash>(rng);
     test_encode_random::<Signers>(rng);
     test_encode_random::<PublicKey>(rng);
@@ -119,22 +146,68 @@ fn test_signature_verify() {
     let ctx = ctx::test_root(&ctx::RealClock);
     let rng = &mut ctx.rng();
 
-    let msg1: MsgHash = rng.gen();
-    let msg2: MsgHash = rng.gen();
+    for scheme in [SignatureScheme::Bn254, SignatureScheme::Secp256k1] {
+        let msg1: MsgHash = rng.gen();
+        let msg2: MsgHash = rng.gen();
+
+        let key1 = SecretKey::gen_with_scheme(rng, scheme);
+        let key2 = SecretKey::gen_with_scheme(rng, scheme);
 
-    let key1: SecretKey = rng.gen();
-    let key2: SecretKey = rng.gen();
+        let sig1 = key1.sign_hash(&msg1);
 
-    let sig1 = key1.sign_hash(&msg1);
+        // Matching key and message.
+        sig1.verify_hash(&msg1, &key1.public()).unwrap();
 
-    // Matching key and message.
-    sig1.verify_hash(&msg1, &key1.public()).unwrap();
+        // Mismatching message.
+        assert!(sig1.verify_hash(&msg2, &key1.public()).is_err());
 
-    // Mismatching message.
-    assert!(sig1.verify_hash(&msg2, &key1.public()).is_err());
+        // Mismatching key.
+        assert!(sig1.verify_hash(&msg1, &key2.public()).is_err());
+    }
 
-    // Mismatching key.
-    assert!(sig1.verify_hash(&msg1, &key2.public()).is_err());
+    // Mismatching scheme.
+    let msg: MsgHash = rng.gen();
+    let sig = SecretKey::gen_with_scheme(rng, SignatureScheme::Secp256k1).sign_hash(&msg);
+    assert_matches!(
+        sig.verify_hash(&msg, &rng.gen::<SecretKey>().public()),
+        Err(Error::SchemeMismatch)
+    );
+}
+
+#[test]
+fn test_signature_verify_batch() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+
+    for scheme in [SignatureScheme::Bn254, SignatureScheme::Secp256k1] {
+        let keys: Vec<_> = (0..5)
+            .map(|_| SecretKey::gen_with_scheme(rng, scheme))
+            .collect();
+        let pks: Vec<_> = keys.iter().map(|k| k.public()).collect();
+        // Votes share the message hash, so use a few distinct ones.
+        let msgs: Vec<MsgHash> = (0..2).map(|_| rng.gen()).collect();
+        let mut sigs: Vec<_> = (0..keys.len())
+            .map(|i| keys[i].sign_hash(&msgs[i % 2]))
+            .collect();
+        let batch = |sigs: &[Signature]| {
+            Signature::verify_batch((0..sigs.len()).map(|i| (&msgs[i % 2], &pks[i], &sigs[i])))
+        };
+
+        batch(&sigs).unwrap();
+
+        // A single invalid signature fails the whole batch.
+        sigs[3] = keys[3].sign_hash(&msgs[0]);
+        assert!(batch(&sigs).is_err());
+    }
+
+    // Mismatching scheme.
+    let msg: MsgHash = rng.gen();
+    let sig = SecretKey::gen_with_scheme(rng, SignatureScheme::Secp256k1).sign_hash(&msg);
+    let pk = rng.gen::<SecretKey>().public();
+    assert_matches!(
+        Signature::verify_batch([(&msg, &pk, &sig)].into_iter()),
+        Err(Error::SchemeMismatch)
+    );
 }
 
 #[test]
@@ -142,31 +215,51 @@ fn test_agg_signature_verify() {
     let ctx = ctx::test_root(&ctx::RealClock);
     let rng = &mut ctx.rng();
 
-    let msg1: MsgHash = rng.gen();
-    let msg2: MsgHash = rng.gen();
+    for scheme in [SignatureScheme::Bn254, SignatureScheme::Secp256k1] {
+        let msg1: MsgHash = rng.gen();
+        let msg2: MsgHash = rng.gen();
 
-    let key1: SecretKey = rng.gen();
-    let key2: SecretKey = rng.gen();
+        let key1 = SecretKey::gen_with_scheme(rng, scheme);
+        let key2 = SecretKey::gen_with_scheme(rng, scheme);
 
-    let sig1 = key1.sign_hash(&msg1);
-    let sig2 = key2.sign_hash(&msg2);
+        let sig1 = key1.sign_hash(&msg1);
+        let sig2 = key2.sign_hash(&msg2);
 
-    let agg_sig = AggregateSignature::aggregate(vec![&sig1, &sig2]);
+        let agg_sig = AggregateSignature::aggregate([&sig1, &sig2]);
+        assert_eq!(scheme, agg_sig.scheme());
 
-    // Matching key and message.
-    agg_sig
-        .verify_hash([(msg1, &key1.public()), (msg2, &key2.public())].into_iter())
-        .unwrap();
+        // Matching key and message.
+        agg_sig
+            .verify_hash([(msg1, &key1.public()), (msg2, &key2.public())].into_iter())
+            .unwrap();
 
-    // Mismatching message.
-    assert!(agg_sig
-        .verify_hash([(msg2, &key1.public()), (msg1, &key2.public())].into_iter())
-        .is_err());
+        // Mismatching message.
+        assert!(agg_sig
+            .verify_hash([(msg2, &key1.public()), (msg1, &key2.public())].into_iter())
+            .is_err());
 
-    // Mismatching key.
-    assert!(agg_sig
-        .verify_hash([(msg1, &key2.public()), (msg2, &key1.public())].into_iter())
-        .is_err());
+        // Mismatching key.
+        assert!(agg_sig
+            .verify_hash([(msg1, &key2.public()), (msg2, &key1.public())].into_iter())
+            .is_err());
+    }
+}
+
+#[test]
+fn test_agg_signature_scheme_mismatch() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let msg: MsgHash = rng.gen();
+    let bn254 = SecretKey::gen_with_scheme(rng, SignatureScheme::Bn254).sign_hash(&msg);
+    let secp256k1 = SecretKey::gen_with_scheme(rng, SignatureScheme::Secp256k1).sign_hash(&msg);
+
+    let mut agg = AggregateSignature::aggregate([&bn254]);
+    assert_matches!(agg.add(1, &secp256k1), Err(Error::SchemeMismatch));
+    assert_eq!(SignatureScheme::Bn254, agg.scheme());
+
+    let mut agg = AggregateSignature::aggregate([&secp256k1]);
+    assert_matches!(agg.add(1, &bn254), Err(Error::SchemeMismatch));
+    assert_eq!(SignatureScheme::Secp256k1, agg.scheme());
 }
 
 fn make_view(number: ViewNumber, setup: &Setup) -> View {
@@ -217,6 +310,12 @@ fn test_commit_qc() {
     let genesis3 = Genesis {
         validators: ValidatorSet::new(setup1.genesis.validators.iter().take(3).cloned()).unwrap(),
         fork: setup1.genesis.fork.clone(),
+        max_payload_size: None,
+        key_rotations: KeyRotations::default(),
+        attesters: None,
+        protocol_version: ProtocolVersion::EARLIEST,
+        protocol_upgrades: ProtocolUpgrades::default(),
+        signature_scheme: SignatureScheme::Bn254,
     };
 
     for i in 0..setup1.keys.len() + 1 {
@@ -240,6 +339,118 @@ fn test_commit_qc() {
     }
 }
 
+#[test]
+fn test_commit_qc_verify_batch() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+
+    for scheme in [SignatureScheme::Bn254, SignatureScheme::Secp256k1] {
+        let setup = Setup::new_with_scheme(rng, 6, scheme);
+        let mut qcs: Vec<_> = (0..3)
+            .map(|_| {
+                let view = rng.gen();
+                let mut qc = CommitQC::new(make_replica_commit(rng, view, &setup), &setup.genesis);
+                for key in &setup.keys {
+                    qc.add(&key.sign_msg(qc.message.clone()), &setup.genesis);
+                }
+                qc
+            })
+            .collect();
+        CommitQC::verify_batch(&setup.genesis, &qcs).unwrap();
+        CommitQC::verify_batch(&setup.genesis, []).unwrap();
+
+        // A single QC with a signature of a different message fails the batch.
+        qcs[0].signature = qcs[1].signature.clone();
+        assert_matches!(
+            CommitQC::verify_batch(&setup.genesis, &qcs),
+            Err(CommitQCVerifyError::BadSignature(_))
+        );
+    }
+}
+
+#[test]
+fn test_secp256k1_validators() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+
+    let setup = Setup::new_with_scheme(rng, 6, SignatureScheme::Secp256k1);
+    assert_eq!(
+        SignatureScheme::Secp256k1,
+        setup.genesis.validators.scheme()
+    );
+    let view = rng.gen();
+    let qc = make_commit_qc(rng, view, &setup);
+    assert_eq!(SignatureScheme::Secp256k1, qc.signature.scheme());
+    qc.verify(&setup.genesis).unwrap();
+
+    let mut qc = TimeoutQC::new(
+        ReplicaTimeout {
+            view: make_view(rng.gen(), &setup),
+        },
+        &setup.genesis,
+    );
+    let threshold = setup.genesis.validators.threshold();
+    for key in &setup.keys[..threshold - 1] {
+        qc.add(&key.sign_msg(qc.message.clone()), &setup.genesis);
+    }
+    assert_matches!(
+        qc.verify(&setup.genesis),
+        Err(TimeoutQCVerifyError::NotEnoughSigners { .. })
+    );
+    qc.add(
+        &setup.keys[threshold - 1].sign_msg(qc.message.clone()),
+        &setup.genesis,
+    );
+    qc.verify(&setup.genesis).unwrap();
+
+    // The genesis has to declare the scheme of its validators.
+    let encoded = zksync_protobuf::encode(&setup.genesis);
+    assert_eq!(setup.genesis, zksync_protobuf::decode(&encoded).unwrap());
+    let mut genesis = setup.genesis.clone();
+    genesis.signature_scheme = SignatureScheme::Bn254;
+    assert!(zksync_protobuf::decode::<Genesis>(&zksync_protobuf::encode(&genesis)).is_err());
+
+    // Validator sets can't mix the schemes.
+    let mixed = setup
+        .genesis
+        .validators
+        .iter()
+        .cloned()
+        .chain([rng.gen::<SecretKey>().public()]);
+    assert!(ValidatorSet::new(mixed).is_err());
+}
+
+#[test]
+fn test_timeout_qc() {
+    use TimeoutQCVerifyError as Error;
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+
+    let setup1 = Setup::new(rng, 6);
+    let setup2 = Setup::new(rng, 6);
+
+    for i in 0..setup1.keys.len() + 1 {
+        let msg = ReplicaTimeout {
+            view: make_view(rng.gen(), &setup1),
+        };
+        let mut qc = TimeoutQC::new(msg, &setup1.genesis);
+        for key in &setup1.keys[0..i] {
+            qc.add(&key.sign_msg(qc.message.clone()), &setup1.genesis);
+        }
+        if i >= setup1.genesis.validators.threshold() {
+            qc.verify(&setup1.genesis).unwrap();
+        } else {
+            assert_matches!(
+                qc.verify(&setup1.genesis),
+                Err(Error::NotEnoughSigners { .. })
+            );
+        }
+
+        // Mismatching validator set.
+        assert!(qc.verify(&setup2.genesis).is_err());
+    }
+}
+
 #[test]
 fn test_prepare_qc() {
     use PrepareQCVerifyError as Error;
@@ -251,6 +462,12 @@ fn test_prepare_qc() {
     let genesis3 = Genesis {
         validators: ValidatorSet::new(setup1.genesis.validators.iter().take(3).cloned()).unwrap(),
         fork: setup1.genesis.fork.clone(),
+        max_payload_size: None,
+        key_rotations: KeyRotations::default(),
+        attesters: None,
+        protocol_version: ProtocolVersion::EARLIEST,
+        protocol_upgrades: ProtocolUpgrades::default(),
+        signature_scheme: SignatureScheme::Bn254,
     };
 
     let view: ViewNumber = rng.gen();
@@ -280,3 +497,734 @@ fn test_prepare_qc() {
         assert!(qc.verify(&genesis3).is_err());
     }
 }
+
+#[test]
+fn test_key_rotation() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 4);
+    let hash = setup.genesis.hash();
+    let old_key = setup.keys[0].clone();
+    let new_key = setup.rotate_key(rng, 0, ViewNumber(10), 5);
+
+    // Rotations don't affect the genesis hash, but are preserved by the encoding.
+    assert_eq!(hash, setup.genesis.hash());
+    let genesis: Genesis =
+        zksync_protobuf::decode(&zksync_protobuf::encode(&setup.genesis)).unwrap();
+    assert_eq!(setup.genesis, genesis);
+
+    // Old key is accepted until the end of the grace period,
+    // new key is accepted since the activation.
+    for (view, want_old, want_new) in [(9, true, false), (14, true, true), (15, false, true)] {
+        let view = ViewNumber(view);
+        assert_eq!(want_old, genesis.is_validator_key(&old_key.public(), view));
+        assert_eq!(want_new, genesis.is_validator_key(&new_key.public(), view));
+    }
+    assert!(genesis.accepts_validator_key(&old_key.public()));
+    assert!(genesis.accepts_validator_key(&new_key.public()));
+
+    // QCs can be signed with either key within the grace period.
+    let mut keys = setup.keys.clone();
+    for key in [&old_key, &new_key] {
+        keys[0] = key.clone();
+        let mut qc = CommitQC::new(make_replica_commit(rng, ViewNumber(12), &setup), &genesis);
+        for key in &keys {
+            qc.add(&key.sign_msg(qc.message.clone()), &genesis);
+        }
+        qc.verify(&genesis).unwrap();
+
+        // Signatures don't match if the rotated bit is flipped.
+        let mut qc = qc.clone();
+        let rotated = qc.rotated.0[0];
+        qc.rotated.0.set(0, !rotated);
+        assert!(qc.verify(&genesis).is_err());
+    }
+
+    // Old key is not accepted after the grace period.
+    keys[0] = old_key.clone();
+    let mut qc = CommitQC::new(make_replica_commit(rng, ViewNumber(20), &setup), &genesis);
+    for key in &keys {
+        qc.add(&key.sign_msg(qc.message.clone()), &genesis);
+    }
+    assert_eq!(keys.len() - 1, qc.signers.count());
+
+    // Rotation of a non-validator key is rejected.
+    let rotation = KeyRotation {
+        genesis: genesis.hash(),
+        old_key: rng.gen::<SecretKey>().public(),
+        new_key: new_key.public(),
+        activation: ViewNumber(0),
+        grace_period: 0,
+    };
+    let cert = KeyRotationCert {
+        rotation: setup.keys[1].sign_msg(rotation.clone()),
+        new_key_sig: new_key.sign_msg(rotation).sig,
+    };
+    assert!(genesis.key_rotations.clone().add(&genesis, cert).is_err());
+}
+
+#[test]
+fn test_block_key_rotation() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 4);
+    let (new_key, cert) = setup.key_rotation(rng, 0, ViewNumber(10), 5);
+    setup.push_blocks(rng, 1);
+    setup.push_block_with_key_rotation(rng.gen(), Some(cert.clone()));
+    let genesis = setup.genesis.clone();
+    let block = setup.blocks[1].clone();
+    assert_eq!(Some(cert.hash()), block.header().key_rotation);
+    block.verify(&genesis).unwrap();
+
+    // The rotation takes effect once it is applied by the block.
+    assert!(!genesis.accepts_validator_key(&new_key.public()));
+    let rotated = genesis.with_key_rotation(cert.clone()).unwrap();
+    assert!(rotated.accepts_validator_key(&new_key.public()));
+    assert_eq!(genesis.hash(), rotated.hash());
+    assert!(rotated.with_key_rotation(cert.clone()).is_err());
+
+    // The block can be verified against the genesis with its rotation already applied,
+    // but not against the genesis with a different rotation of the same validator.
+    block.verify(&rotated).unwrap();
+    let (_, other) = setup.key_rotation(rng, 0, ViewNumber(10), 5);
+    assert_matches!(
+        block.verify(&genesis.with_key_rotation(other).unwrap()),
+        Err(BlockValidationError::KeyRotation(_))
+    );
+
+    // The rotation has to match the header.
+    let mut bad = block.clone();
+    bad.key_rotation = None;
+    assert_matches!(
+        bad.verify(&genesis),
+        Err(BlockValidationError::KeyRotationMismatch)
+    );
+    let mut bad = setup.blocks[0].clone();
+    bad.key_rotation = Some(cert);
+    assert_matches!(
+        bad.verify(&genesis),
+        Err(BlockValidationError::KeyRotationMismatch)
+    );
+}
+
+#[test]
+fn test_block_protocol_upgrade() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 4);
+    let genesis = setup.genesis.clone();
+    let activation = BlockNumber(genesis.fork.first_block.0 + 3);
+    let qc = setup.protocol_upgrade(ProtocolVersion::PAYLOAD_ROOT, activation);
+    setup.push_blocks(rng, 1);
+    setup.push_block_with_protocol_upgrade(rng.gen(), Some(qc.clone()));
+    setup.push_blocks(rng, 3);
+    let block = setup.blocks[1].clone();
+    assert_eq!(Some(qc.hash()), block.header().protocol_upgrade);
+    block.verify(&genesis).unwrap();
+
+    // The upgrade takes effect once it is applied by the block.
+    assert_eq!(
+        ProtocolVersion::EARLIEST,
+        genesis.protocol_version_at(activation)
+    );
+    let upgraded = genesis
+        .with_protocol_upgrade(qc.clone(), block.number())
+        .unwrap();
+    assert_eq!(
+        ProtocolVersion::PAYLOAD_ROOT,
+        upgraded.protocol_version_at(activation)
+    );
+    assert_eq!(genesis.hash(), upgraded.hash());
+    assert_eq!(upgraded, setup.chain_genesis());
+    assert!(upgraded
+        .with_protocol_upgrade(qc.clone(), block.number())
+        .is_err());
+
+    // The blocks since the activation follow the new version.
+    for block in &setup.blocks {
+        assert_eq!(
+            block.number() >= activation,
+            block.header().payload_root.is_some()
+        );
+        block.verify(&upgraded).unwrap();
+    }
+    assert_matches!(
+        setup.blocks[3].verify(&genesis),
+        Err(BlockValidationError::PayloadRootPresence { required: false })
+    );
+
+    // The upgrade has to match the header.
+    let mut bad = block.clone();
+    bad.protocol_upgrade = None;
+    assert_matches!(
+        bad.verify(&genesis),
+        Err(BlockValidationError::ProtocolUpgradeMismatch)
+    );
+    let mut bad = setup.blocks[0].clone();
+    bad.protocol_upgrade = Some(qc);
+    assert_matches!(
+        bad.verify(&genesis),
+        Err(BlockValidationError::ProtocolUpgradeMismatch)
+    );
+}
+
+#[test]
+fn test_protocol_upgrade() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 4);
+    let hash = setup.genesis.hash();
+    let activation = BlockNumber(setup.genesis.fork.first_block.0 + 3);
+    setup.schedule_protocol_upgrade(ProtocolVersion::PAYLOAD_ROOT, activation);
+
+    // Upgrades don't affect the genesis hash, but are preserved by the encoding.
+    assert_eq!(hash, setup.genesis.hash());
+    let genesis: Genesis =
+        zksync_protobuf::decode(&zksync_protobuf::encode(&setup.genesis)).unwrap();
+    assert_eq!(setup.genesis, genesis);
+
+    // The new version applies since the activation block.
+    assert_eq!(
+        ProtocolVersion::EARLIEST,
+        genesis.protocol_version_at(activation.prev().unwrap())
+    );
+    assert_eq!(
+        ProtocolVersion::PAYLOAD_ROOT,
+        genesis.protocol_version_at(activation)
+    );
+    assert_eq!(
+        ProtocolVersion::PAYLOAD_ROOT,
+        genesis.latest_protocol_version()
+    );
+    setup.push_blocks(rng, 5);
+    for block in &setup.blocks {
+        assert_eq!(
+            block.number() >= activation,
+            block.header().payload_root.is_some()
+        );
+        block.verify(&genesis).unwrap();
+    }
+
+    // The upgrade has to be signed by a quorum of validators.
+    let upgrade = ProtocolUpgrade {
+        genesis: hash,
+        version: ProtocolVersion(2),
+        activation: BlockNumber(activation.0 + 10),
+    };
+    let mut qc = ProtocolUpgradeQC::new(upgrade.clone(), &genesis);
+    for key in &setup.keys[..genesis.validators.threshold() - 1] {
+        qc.add(&key.sign_msg(upgrade.clone()), &genesis);
+    }
+    assert_matches!(
+        qc.verify(&genesis),
+        Err(ProtocolUpgradeQCVerifyError::NotEnoughSigners { .. })
+    );
+    let key = &setup.keys[genesis.validators.threshold() - 1];
+    qc.add(&key.sign_msg(upgrade.clone()), &genesis);
+    qc.verify(&genesis).unwrap();
+
+    // The upgrade has to be to a newer version.
+    let mut stale = ProtocolUpgradeQC::new(
+        ProtocolUpgrade {
+            version: ProtocolVersion::PAYLOAD_ROOT,
+            ..upgrade.clone()
+        },
+        &genesis,
+    );
+    for key in &setup.keys {
+        stale.add(&key.sign_msg(stale.message.clone()), &genesis);
+    }
+    assert!(genesis
+        .protocol_upgrades
+        .clone()
+        .add(&genesis, stale, genesis.fork.first_block)
+        .is_err());
+
+    // The upgrade has to be activated after the block committing it.
+    assert!(genesis
+        .check_protocol_upgrade(&qc, upgrade.activation)
+        .is_err());
+    assert!(genesis
+        .protocol_upgrades
+        .clone()
+        .add(&genesis, qc.clone(), BlockNumber(upgrade.activation.0 + 1))
+        .is_err());
+    genesis
+        .check_protocol_upgrade(&qc, upgrade.activation.prev().unwrap())
+        .unwrap();
+
+    // Upgrades of a different chain are rejected.
+    let other = Setup::new(rng, 4);
+    assert_matches!(
+        qc.verify(&other.genesis),
+        Err(ProtocolUpgradeQCVerifyError::GenesisMismatch)
+    );
+}
+
+#[test]
+fn test_double_sign_proof() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let setup = Setup::new(rng, 4);
+    let genesis = &setup.genesis;
+    let key = &setup.keys[0];
+    let view = ViewNumber(rng.gen_range(1..100));
+    let commit = |rng: &mut _| {
+        key.sign_msg(ConsensusMsg::ReplicaCommit(make_replica_commit(
+            rng, view, &setup,
+        )))
+    };
+    let (a, b) = (commit(rng), commit(rng));
+
+    // Conflicting commits are accepted in any order and survive the encoding.
+    let proof = DoubleSignProof::new(genesis, a.clone(), b.clone()).unwrap();
+    assert_eq!(
+        proof,
+        DoubleSignProof::new(genesis, b.clone(), a.clone()).unwrap()
+    );
+    assert_eq!(&key.public(), proof.key());
+    assert_eq!(view, proof.view().number);
+    assert_eq!(Phase::Commit, proof.phase());
+    let decoded: DoubleSignProof =
+        zksync_protobuf::decode(&zksync_protobuf::encode(&proof)).unwrap();
+    decoded.verify(genesis).unwrap();
+
+    // Conflicting prepares are slashable too.
+    let prepare = |rng: &mut _| {
+        key.sign_msg(ConsensusMsg::ReplicaPrepare(make_replica_prepare(
+            rng, view, &setup,
+        )))
+    };
+    let proof = DoubleSignProof::new(genesis, prepare(rng), prepare(rng)).unwrap();
+    assert_eq!(Phase::Prepare, proof.phase());
+
+    // Swapped messages are not canonical.
+    let swapped = DoubleSignProof {
+        first: proof.second.clone(),
+        second: proof.first.clone(),
+    };
+    assert_matches!(
+        swapped.verify(genesis),
+        Err(DoubleSignProofError::NotCanonical)
+    );
+
+    // Identical messages.
+    assert_matches!(
+        DoubleSignProof::new(genesis, a.clone(), a.clone()),
+        Err(DoubleSignProofError::SameMessage)
+    );
+
+    // Different views.
+    let mut msg = make_replica_commit(rng, view.next(), &setup);
+    let other_view = key.sign_msg(ConsensusMsg::ReplicaCommit(msg.clone()));
+    assert_matches!(
+        DoubleSignProof::new(genesis, a.clone(), other_view),
+        Err(DoubleSignProofError::DifferentViews(..))
+    );
+
+    // Different kinds of messages.
+    assert_matches!(
+        DoubleSignProof::new(genesis, a.clone(), prepare(rng)),
+        Err(DoubleSignProofError::DifferentKinds(..))
+    );
+
+    // Different keys.
+    msg.view.number = view;
+    let other_key = setup.keys[1].sign_msg(ConsensusMsg::ReplicaCommit(msg.clone()));
+    assert_matches!(
+        DoubleSignProof::new(genesis, a.clone(), other_key),
+        Err(DoubleSignProofError::DifferentKeys)
+    );
+
+    // Non-validator key.
+    let non_validator: SecretKey = rng.gen();
+    assert_matches!(
+        DoubleSignProof::new(
+            genesis,
+            non_validator.sign_msg(ConsensusMsg::ReplicaCommit(msg.clone())),
+            non_validator.sign_msg(a.msg.clone()),
+        ),
+        Err(DoubleSignProofError::NotValidator(_))
+    );
+
+    // Invalid signature.
+    let mut forged = key.sign_msg(ConsensusMsg::ReplicaCommit(msg));
+    forged.sig = b.sig.clone();
+    assert_matches!(
+        DoubleSignProof::new(genesis, a.clone(), forged),
+        Err(DoubleSignProofError::Signature(_))
+    );
+
+    // LeaderCommit is not slashable.
+    let leader_commit = |rng: &mut _| {
+        key.sign_msg(ConsensusMsg::LeaderCommit(LeaderCommit {
+            justification: make_commit_qc(rng, view, &setup),
+        }))
+    };
+    assert_matches!(
+        DoubleSignProof::new(genesis, leader_commit(rng), leader_commit(rng)),
+        Err(DoubleSignProofError::NotSlashable(_))
+    );
+
+    // Timeout votes for the same view are identical.
+    let timeout = key.sign_msg(ConsensusMsg::ReplicaTimeout(ReplicaTimeout {
+        view: make_view(view, &setup),
+    }));
+    assert_matches!(
+        DoubleSignProof::new(genesis, timeout.clone(), timeout),
+        Err(DoubleSignProofError::NotSlashable(_))
+    );
+}
+
+#[test]
+fn test_finality_proof() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 4);
+    setup.push_blocks(rng, 3);
+    let genesis_hash = setup.genesis.hash();
+    let block = &setup.blocks[2];
+
+    // Proof is verifiable after the encoding.
+    let proof = block.finality_proof(&setup.genesis);
+    let decoded: FinalityProof = zksync_protobuf::decode(&zksync_protobuf::encode(&proof)).unwrap();
+    assert_eq!(
+        block.header(),
+        verify_finality(genesis_hash, &decoded).unwrap()
+    );
+
+    // Proof for a different chain.
+    assert_matches!(
+        verify_finality(rng.gen(), &proof),
+        Err(FinalityProofError::GenesisMismatch { .. })
+    );
+    let mut bad = proof.clone();
+    bad.genesis.validators = rng.gen();
+    assert_matches!(
+        verify_finality(genesis_hash, &bad),
+        Err(FinalityProofError::GenesisMismatch { .. })
+    );
+
+    // Header not certified by the justification.
+    let mut bad = proof.clone();
+    bad.justification.message.proposal.payload = rng.gen();
+    assert_matches!(
+        verify_finality(genesis_hash, &bad),
+        Err(FinalityProofError::Justification(_))
+    );
+}
+
+#[test]
+fn test_max_payload_size() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 4);
+    setup.push_block(Payload(vec![0; 10]));
+    let block = setup.blocks[0].clone();
+
+    // The limit is a part of the genesis hash.
+    let mut genesis = setup.genesis.clone();
+    genesis.max_payload_size = Some(10);
+    assert_ne!(setup.genesis.hash(), genesis.hash());
+    assert_eq!(10, genesis.payload_size_limit(5));
+    assert_eq!(5, setup.genesis.payload_size_limit(5));
+
+    block.verify(&setup.genesis).unwrap();
+    block.verify(&genesis).unwrap();
+    genesis.max_payload_size = Some(9);
+    assert_matches!(
+        block.verify(&genesis),
+        Err(BlockValidationError::OversizedPayload {
+            payload_size: 10,
+            max: 9
+        })
+    );
+}
+
+/// Golden encodings of the signed messages. Signatures are computed over these exact bytes,
+/// so any change of the encoding (field numbers, field ordering, etc.) breaks compatibility
+/// with the existing signatures, and has to be introduced with a new protocol version.
+#[test]
+fn test_golden_encoding() {
+    let view = View {
+        protocol_version: ProtocolVersion(1),
+        fork: ForkNumber(2),
+        number: ViewNumber(3),
+    };
+    assert_eq!(
+        "080110021803",
+        hex::encode(zksync_protobuf::canonical(&view))
+    );
+
+    let payload = Payload(b"payload".to_vec()).hash();
+    assert_eq!(
+        "ebc84cbd75ba5516bf45e7024a9e12bc3c5c880f73e3a5beca7ebba52b2867a7",
+        hex::encode(ByteFmt::encode(&payload.0))
+    );
+
+    let header = BlockHeader {
+        parent: Some(BlockHeaderHash(ByteFmt::decode(&[0x11; 32]).unwrap())),
+        number: BlockNumber(4),
+        payload,
+        payload_root: None,
+        key_rotation: None,
+        protocol_upgrade: None,
+    };
+    assert_eq!(
+        "12220a201111111111111111111111111111111111111111111111111111111111111111\
+         180422220a20ebc84cbd75ba5516bf45e7024a9e12bc3c5c880f73e3a5beca7ebba52b2867a7",
+        hex::encode(zksync_protobuf::canonical(&header))
+    );
+    assert_eq!(
+        "ca0ecb3a092b46ad2ba60a226984ed515ac4f4f3bb0d866d91c01d1a4cf362f3",
+        hex::encode(ByteFmt::encode(&header.hash().0))
+    );
+
+    let msg = Msg::Consensus(ConsensusMsg::ReplicaCommit(ReplicaCommit {
+        view,
+        proposal: header,
+    }));
+    let encoded = zksync_protobuf::canonical(&msg);
+    assert_eq!(
+        "0a5612540a06080110021803124a12220a2011111111111111111111111111111111111111111111\
+         11111111111111111111180422220a20ebc84cbd75ba5516bf45e7024a9e12bc3c5c880f73e3a5bec\
+         a7ebba52b2867a7",
+        hex::encode(&encoded)
+    );
+    assert_eq!(
+        "d1fab944dcb3408bafaed1c98791d18ce96dea9c57cff3c5dacee14b768d8b56",
+        hex::encode(ByteFmt::encode(&msg.hash().0))
+    );
+    assert_eq!(msg, zksync_protobuf::decode::<Msg>(&encoded).unwrap());
+}
+
+/// Golden hashes of the domain separated hashing scheme, see `test_golden_encoding`.
+#[test]
+fn test_golden_domain_separated_hashes() {
+    let view = View {
+        protocol_version: ProtocolVersion::DOMAIN_SEPARATED_HASHING,
+        fork: ForkNumber(2),
+        number: ViewNumber(3),
+    };
+    let header = BlockHeader {
+        parent: Some(BlockHeaderHash(ByteFmt::decode(&[0x11; 32]).unwrap())),
+        number: BlockNumber(4),
+        payload: Payload(b"payload".to_vec()).hash(),
+        payload_root: None,
+        key_rotation: None,
+        protocol_upgrade: None,
+    };
+    assert_eq!(
+        "644412fd628d4e5850d0ad9a29333feb9a2d524d442e0154069af84ee4fb9f9e",
+        hex::encode(ByteFmt::encode(
+            &header
+                .hash_with(HashScheme::DomainSeparated(HashFunction::Keccak256))
+                .0
+        ))
+    );
+
+    let msg = Msg::Consensus(ConsensusMsg::ReplicaCommit(ReplicaCommit {
+        view,
+        proposal: header,
+    }));
+    assert_eq!(
+        "0a5612540a06080210021803124a12220a2011111111111111111111111111111111111111111111\
+         11111111111111111111180422220a20ebc84cbd75ba5516bf45e7024a9e12bc3c5c880f73e3a5bec\
+         a7ebba52b2867a7",
+        hex::encode(zksync_protobuf::canonical(&msg))
+    );
+    assert_eq!(
+        "6320c46360fd11a8bdd11d6604eb09d26d970ad67ea85ce30407d78b752830da",
+        hex::encode(ByteFmt::encode(&msg.hash().0))
+    );
+}
+
+/// Golden hashes of the domain separated hashing scheme with blake2b-256,
+/// see `test_golden_domain_separated_hashes`.
+#[test]
+fn test_golden_blake2_hashes() {
+    let view = View {
+        protocol_version: ProtocolVersion::BLAKE2_HASHING,
+        fork: ForkNumber(2),
+        number: ViewNumber(3),
+    };
+    let header = BlockHeader {
+        parent: Some(BlockHeaderHash(ByteFmt::decode(&[0x11; 32]).unwrap())),
+        number: BlockNumber(4),
+        payload: Payload(b"payload".to_vec()).hash(),
+        payload_root: None,
+        key_rotation: None,
+        protocol_upgrade: None,
+    };
+    assert_eq!(
+        "e8742a8789ad0adb7f4f667b01a0bec6d0d92dfc587b1e9c2903c501e8799f3c",
+        hex::encode(ByteFmt::encode(
+            &header
+                .hash_with(HashScheme::DomainSeparated(HashFunction::Blake2b256))
+                .0
+        ))
+    );
+
+    let msg = Msg::Consensus(ConsensusMsg::ReplicaCommit(ReplicaCommit {
+        view,
+        proposal: header,
+    }));
+    assert_eq!(
+        "0a5612540a06080310021803124a12220a2011111111111111111111111111111111111111111111\
+         11111111111111111111180422220a20ebc84cbd75ba5516bf45e7024a9e12bc3c5c880f73e3a5bec\
+         a7ebba52b2867a7",
+        hex::encode(zksync_protobuf::canonical(&msg))
+    );
+    assert_eq!(
+        "e7671ccd55f0b9c076080265e0b8fe18ed58284cff5fb87bb4d99d9b58cc20a4",
+        hex::encode(ByteFmt::encode(&msg.hash().0))
+    );
+}
+
+/// Genesis hashes of the legacy protocol versions must not change,
+/// while the newer versions hash the genesis and the block headers in separate domains.
+#[test]
+fn test_genesis_hash_scheme() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut genesis: Genesis = rng.gen();
+    let header: BlockHeader = rng.gen();
+
+    genesis.protocol_version = ProtocolVersion::PAYLOAD_ROOT;
+    assert!(
+        Keccak256::new(&zksync_protobuf::canonical(&genesis)).as_bytes()
+            == genesis.hash().0.as_bytes()
+    );
+    assert_eq!(header.hash(), genesis.header_hash(&header));
+
+    genesis.protocol_version = ProtocolVersion::DOMAIN_SEPARATED_HASHING;
+    assert!(
+        Keccak256::new(&zksync_protobuf::canonical(&genesis)).as_bytes()
+            != genesis.hash().0.as_bytes()
+    );
+    let keccak = genesis.header_hash(&header);
+    assert_eq!(
+        header.hash_with(HashScheme::DomainSeparated(HashFunction::Keccak256)),
+        keccak
+    );
+    assert_ne!(header.hash(), keccak);
+
+    genesis.protocol_version = ProtocolVersion::BLAKE2_HASHING;
+    let blake2 = genesis.header_hash(&header);
+    assert_eq!(
+        header.hash_with(HashScheme::DomainSeparated(HashFunction::Blake2b256)),
+        blake2
+    );
+    assert_ne!(keccak, blake2);
+}
+
+/// Messages are hashed (and signed) in their canonical encoding, which is computed
+/// after decoding, so the hashes don't depend on how the sender encoded the message.
+#[test]
+fn test_non_canonical_encoding() {
+    // Fields in reverse order.
+    let reordered = hex::decode("180310020801").unwrap();
+    let view: View = zksync_protobuf::decode(&reordered).unwrap();
+    let canonical = zksync_protobuf::canonical(&view);
+    assert_eq!("080110021803", hex::encode(&canonical));
+
+    // Unknown field 9.
+    let unknown = [canonical.clone(), hex::decode("4801").unwrap()].concat();
+    let view: View = zksync_protobuf::decode(&unknown).unwrap();
+    assert_eq!(canonical, zksync_protobuf::canonical(&view));
+}
+
+#[test]
+fn test_payload_chunk_proofs() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    for size in [
+        0,
+        1,
+        PAYLOAD_CHUNK_SIZE,
+        PAYLOAD_CHUNK_SIZE + 1,
+        5 * PAYLOAD_CHUNK_SIZE + 17,
+    ] {
+        let payload = Payload((0..size).map(|_| rng.gen()).collect());
+        let root = payload.root();
+        let chunks = payload.chunks();
+        assert_eq!(payload_chunk_count(size), chunks.len());
+        for (i, chunk) in chunks.iter().enumerate() {
+            let proof = payload.chunk_proof(i).unwrap();
+            proof.verify(&root, chunk).unwrap();
+            // Wrong chunk.
+            let mut bad = chunk.to_vec();
+            bad.push(0);
+            assert!(proof.verify(&root, &bad).is_err());
+            // Wrong root.
+            assert!(proof.verify(&rng.gen(), chunk).is_err());
+            // Wrong index.
+            if chunks.len() > 1 {
+                let mut proof = proof.clone();
+                proof.index = (i + 1) % chunks.len();
+                assert!(proof.verify(&root, chunk).is_err());
+            }
+        }
+        assert!(payload.chunk_proof(chunks.len()).is_none());
+    }
+    // The root commits to the payload size.
+    assert_ne!(Payload(vec![]).root(), Payload(vec![0]).root());
+}
+
+#[test]
+fn test_validator_set_membership_proofs() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    for size in [1, 2, 3, 7, 16] {
+        let validators =
+            ValidatorSet::new((0..size).map(|_| rng.gen::<SecretKey>().public())).unwrap();
+        let commitment = validators.commitment();
+        for (i, key) in validators.iter().enumerate() {
+            let proof = validators.membership_proof(key).unwrap();
+            assert_eq!(proof.index, i);
+            proof.verify(&commitment, key).unwrap();
+            // Wrong validator.
+            assert!(proof.verify(&commitment, &rng.gen()).is_err());
+            // Wrong commitment.
+            assert!(proof.verify(&rng.gen(), key).is_err());
+            // Wrong index.
+            if size > 1 {
+                let mut proof = proof.clone();
+                proof.index = (i + 1) % size;
+                assert!(proof.verify(&commitment, key).is_err());
+            }
+        }
+        assert!(validators.membership_proof(&rng.gen()).is_none());
+    }
+    // The commitment depends on the whole set.
+    let a = ValidatorSet::new([rng.gen()]).unwrap();
+    let b = ValidatorSet::new([rng.gen()]).unwrap();
+    assert_ne!(a.commitment(), b.commitment());
+}
+
+#[test]
+fn test_payload_root_gated_by_protocol_version() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    for (version, other) in [
+        (ProtocolVersion::EARLIEST, ProtocolVersion::PAYLOAD_ROOT),
+        (ProtocolVersion::PAYLOAD_ROOT, ProtocolVersion::EARLIEST),
+    ] {
+        let mut setup = Setup::new(rng, 1);
+        setup.set_protocol_version(version);
+        setup.push_blocks(rng, 1);
+        let block = &setup.blocks[0];
+        assert_eq!(
+            version.commits_payload_root(),
+            block.header().payload_root.is_some()
+        );
+        block.verify(&setup.genesis).unwrap();
+
+        let mut genesis = setup.genesis.clone();
+        genesis.protocol_version = other;
+        assert_matches!(
+            block.verify(&genesis),
+            Err(BlockValidationError::PayloadRootPresence { .. })
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! LRU cache of the blocks recently read from the persistent storage.
+//! Syncing nodes tend to request the same ranges of blocks from multiple peers,
+//! so caching the served blocks saves repeated reads of the (potentially large) payloads.
+use std::collections::HashMap;
+use zksync_consensus_roles::validator;
+
+/// Approximate size of the block in memory.
+/// The justification is small compared to the payload, so it is not accounted for.
+fn block_size(block: &validator::FinalBlock) -> usize {
+    block.payload.0.len()
+}
+
+/// Cached block, linked into the list of the blocks ordered by the recency of use.
+#[derive(Debug)]
+struct Entry {
+    block: validator::FinalBlock,
+    /// Slot of the less recently used neighbour.
+    prev: Option<usize>,
+    /// Slot of the more recently used neighbour.
+    next: Option<usize>,
+}
+
+/// LRU cache of the persisted blocks, bounded by the total size and the number of the blocks.
+/// All the operations take O(1).
+#[derive(Debug)]
+pub(super) struct BlockCache {
+    /// Max total size of the cached blocks in bytes.
+    budget: usize,
+    /// Max number of the cached blocks.
+    capacity: usize,
+    /// Total size of the cached blocks in bytes.
+    size: usize,
+    /// Slots of the cached blocks. `None` slots are free.
+    slots: Vec<Option<Entry>>,
+    /// Indices of the free slots.
+    free: Vec<usize>,
+    /// Slots of the cached blocks, by the block number.
+    index: HashMap<validator::BlockNumber, usize>,
+    /// Slot of the least recently used block.
+    head: Option<usize>,
+    /// Slot of the most recently used block.
+    tail: Option<usize>,
+}
+
+impl BlockCache {
+    /// Constructs a cache holding at most `capacity` blocks of at most `budget` bytes in total.
+    /// Zero budget or capacity disables caching.
+    pub(super) fn new(budget: usize, capacity: usize) -> Self {
+        Self {
+            budget,
+            capacity,
+            size: 0,
+            slots: vec![],
+            free: vec![],
+            index: HashMap::new(),
+            head: None,
+            tail: None,
+        }
+    }
+
+    /// Total size of the cached blocks in bytes.
+    pub(super) fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of the cached blocks.
+    pub(super) fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn entry(&mut self, slot: usize) -> &mut Entry {
+        self.slots[slot].as_mut().unwrap()
+    }
+
+    /// Unlinks the entry from the recency list.
+    fn unlink(&mut self, slot: usize) {
+        let entry = self.entry(slot);
+        let (prev, next) = (entry.prev.take(), entry.next.take());
+        match prev {
+            Some(prev) => self.entry(prev).next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => self.entry(next).prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    /// Links the (unlinked) entry as the most recently used one.
+    fn push_back(&mut self, slot: usize) {
+        let tail = self.tail;
+        self.entry(slot).prev = tail;
+        match tail {
+            Some(tail) => self.entry(tail).next = Some(slot),
+            None => self.head = Some(slot),
+        }
+        self.tail = Some(slot);
+    }
+
+    /// Removes the entry from the cache.
+    fn remove(&mut self, slot: usize) {
+        self.unlink(slot);
+        let entry = self.slots[slot].take().unwrap();
+        self.free.push(slot);
+        self.index.remove(&entry.block.number());
+        self.size -= block_size(&entry.block);
+    }
+
+    /// Gets a block from the cache, marking it as the most recently used one.
+    pub(super) fn get(&mut self, number: validator::BlockNumber) -> Option<validator::FinalBlock> {
+        let slot = *self.index.get(&number)?;
+        self.unlink(slot);
+        self.push_back(slot);
+        Some(self.entry(slot).block.clone())
+    }
+
+    /// Inserts a block into the cache, evicting the least recently used blocks
+    /// until the cache fits into the budget and the capacity.
+    /// Blocks larger than the budget are not cached.
+    /// Returns the number of evicted blocks.
+    pub(super) fn insert(&mut self, block: validator::FinalBlock) -> usize {
+        let size = block_size(&block);
+        if self.budget == 0 || self.capacity == 0 || size > self.budget {
+            return 0;
+        }
+        let number = block.number();
+        if let Some(slot) = self.index.get(&number).copied() {
+            self.remove(slot);
+        }
+        let entry = Entry {
+            block,
+            prev: None,
+            next: None,
+        };
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.slots[slot] = Some(entry);
+                slot
+            }
+            None => {
+                self.slots.push(Some(entry));
+                self.slots.len() - 1
+            }
+        };
+        self.index.insert(number, slot);
+        self.size += size;
+        self.push_back(slot);
+        let mut evicted = 0;
+        while self.size > self.budget || self.index.len() > self.capacity {
+            let Some(head) = self.head else {
+                break;
+            };
+            self.remove(head);
+            evicted += 1;
+        }
+        evicted
+    }
+}
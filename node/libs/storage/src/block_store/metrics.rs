@@ -1,6 +1,16 @@
 //! Storage metrics.
 use std::time;
 
+/// Result of an operation on the persistent storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, vise::EncodeLabelSet, vise::EncodeLabelValue)]
+#[metrics(label = "result", rename_all = "snake_case")]
+pub(super) enum ResultLabel {
+    /// Successful call.
+    Ok,
+    /// Failed call.
+    Err,
+}
+
 #[derive(Debug, vise::Metrics)]
 #[metrics(prefix = "zksync_consensus_storage_persistent_block_store")]
 pub(super) struct PersistentBlockStore {
@@ -13,19 +23,102 @@ pub(super) struct PersistentBlockStore {
     /// Latency of a successful `block()` call.
     #[metrics(unit = vise::Unit::Seconds, buckets = vise::Buckets::LATENCIES)]
     pub(super) block_latency: vise::Histogram<time::Duration>,
-    /// Latency of a successful `store_next_block()` call.
+    /// Latency of a successful `justification()` call.
+    #[metrics(unit = vise::Unit::Seconds, buckets = vise::Buckets::LATENCIES)]
+    pub(super) justification_latency: vise::Histogram<time::Duration>,
+    /// Latency of a successful `prune()` call.
     #[metrics(unit = vise::Unit::Seconds, buckets = vise::Buckets::LATENCIES)]
-    pub(super) store_next_block_latency: vise::Histogram<time::Duration>,
+    pub(super) prune_latency: vise::Histogram<time::Duration>,
+    /// Latency of a `store_blocks()` call (storing a whole batch), labeled by the result.
+    /// Calls interrupted by cancellation are not observed.
+    #[metrics(unit = vise::Unit::Seconds, buckets = vise::Buckets::LATENCIES)]
+    pub(super) store_next_block_latency: vise::Family<ResultLabel, vise::Histogram<time::Duration>>,
+    /// Number of failed `store_blocks()` calls.
+    pub(super) store_next_block_errors: vise::Counter,
+    /// Number of `store_blocks()` calls retried after a failure.
+    pub(super) store_next_block_retries: vise::Counter,
+    /// Number of blocks passed to a `store_blocks()` call.
+    #[metrics(buckets = vise::Buckets::exponential(1.0..=1024.0, 2.0))]
+    pub(super) store_batch_size: vise::Histogram<usize>,
 }
 
 #[vise::register]
 pub(super) static PERSISTENT_BLOCK_STORE: vise::Global<PersistentBlockStore> = vise::Global::new();
 
+/// Stage of the block lifecycle, from receiving its proposal to persisting it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, vise::EncodeLabelSet, vise::EncodeLabelValue)]
+#[metrics(label = "stage", rename_all = "snake_case")]
+pub(super) enum BlockStage {
+    /// From receiving the proposal to receiving the CommitQC.
+    Certify,
+    /// From receiving the CommitQC to queueing the block in `BlockStore`.
+    Queue,
+    /// From queueing the block to persisting it.
+    Persist,
+    /// From the earliest known timestamp of the block to persisting it.
+    Total,
+}
+
+#[derive(Debug, vise::Metrics)]
+#[metrics(prefix = "zksync_consensus_block")]
+pub(super) struct Block {
+    /// End-to-end latency of the finalized blocks, broken down by stage.
+    #[metrics(unit = vise::Unit::Seconds, buckets = vise::Buckets::LATENCIES)]
+    pub(super) latency: vise::Family<BlockStage, vise::Histogram<time::Duration>>,
+}
+
+#[vise::register]
+pub(super) static BLOCK: vise::Global<Block> = vise::Global::new();
+
 #[derive(Debug, vise::Metrics)]
 #[metrics(prefix = "zksync_consensus_storage_block_store")]
 pub(super) struct BlockStore {
+    /// BlockNumber of the first stored block, i.e. the blocks before it have been pruned.
+    pub(super) first_block: vise::Gauge<u64>,
+    /// Number of the blocks missing in the gaps of the stored range.
+    pub(super) missing_blocks: vise::Gauge<u64>,
     /// BlockNumber of the next block to queue.
     pub(super) next_queued_block: vise::Gauge<u64>,
     /// BlockNumber of the next block to persist.
     pub(super) next_persisted_block: vise::Gauge<u64>,
+    /// Number of queued blocks which are not persisted yet,
+    /// i.e. the lag of the persistent storage behind the queue.
+    pub(super) queue_len: vise::Gauge<usize>,
+    /// Total payload size of the queued blocks which are not persisted yet.
+    #[metrics(unit = vise::Unit::Bytes)]
+    pub(super) queue_payload_size: vise::Gauge<usize>,
+    /// Number of the blocks in the cache.
+    pub(super) cache_len: vise::Gauge<usize>,
+    /// Total size of the blocks in the cache.
+    #[metrics(unit = vise::Unit::Bytes)]
+    pub(super) cache_size: vise::Gauge<usize>,
 }
+
+#[derive(Debug, vise::Metrics)]
+#[metrics(prefix = "zksync_consensus_storage_block_store_admission")]
+pub(super) struct Admission {
+    /// Number of times the persistent storage started lagging behind the queue.
+    pub(super) lagging: vise::Counter,
+    /// Number of `queue_block()` calls delayed, because the lag reached `slow_down_lag`.
+    pub(super) slowed_down: vise::Counter,
+    /// Time `queue_block()` calls spent paused, because the lag reached `pause_lag`.
+    #[metrics(unit = vise::Unit::Seconds, buckets = vise::Buckets::LATENCIES)]
+    pub(super) paused_latency: vise::Histogram<time::Duration>,
+}
+
+#[vise::register]
+pub(super) static ADMISSION: vise::Global<Admission> = vise::Global::new();
+
+#[derive(Debug, vise::Metrics)]
+#[metrics(prefix = "zksync_consensus_storage_block_cache")]
+pub(super) struct BlockCache {
+    /// Number of `BlockStore::block()` calls served from the cache.
+    pub(super) hits: vise::Counter,
+    /// Number of `BlockStore::block()` calls which had to read the persistent storage.
+    pub(super) misses: vise::Counter,
+    /// Number of blocks evicted from the cache to fit into the budget.
+    pub(super) evictions: vise::Counter,
+}
+
+#[vise::register]
+pub(super) static BLOCK_CACHE: vise::Global<BlockCache> = vise::Global::new();
@@ -1,27 +1,132 @@
 //! Defines storage layer for finalized blocks.
 use anyhow::Context as _;
-use std::{collections::VecDeque, fmt, sync::Arc};
-use zksync_concurrency::{ctx, error::Wrap as _, sync};
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+};
+use zksync_concurrency::{ctx, error::Wrap as _, metrics::LatencyHistogramExt as _, sync, time};
 use zksync_consensus_roles::validator;
 
+mod cache;
 mod metrics;
+mod reader;
+mod wal;
 
-/// State of the `BlockStore`: continuous range of blocks.
+pub use reader::BlockStoreReader;
+pub use wal::{BlockQueueWal, FileWal};
+
+/// Range of block numbers `[first, last]` (inclusive).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockRange {
+    /// Lowest block number in the range.
+    pub first: validator::BlockNumber,
+    /// Highest block number in the range.
+    pub last: validator::BlockNumber,
+}
+
+impl BlockRange {
+    /// Checks whether the range contains the given block number.
+    pub fn contains(&self, number: validator::BlockNumber) -> bool {
+        self.first <= number && number <= self.last
+    }
+}
+
+/// State of the `BlockStore`: set of ranges of blocks, represented as
+/// the range `[first, last]` with the missing `gaps` cut out.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BlockStoreState {
     /// Stored block with the lowest number.
-    /// Currently always same as `genesis.first_block`.
+    /// Same as `genesis.first_block`, unless the store has been pruned.
     pub first: validator::BlockNumber,
     /// Stored block with the highest number.
     /// None iff store is empty.
     pub last: Option<validator::CommitQC>,
+    /// Ranges of the blocks within `(first, last)` which are NOT stored,
+    /// sorted and disjoint. Stores have gaps for example after a snapshot sync,
+    /// when the node has the blocks since genesis and the recent range of blocks.
+    pub gaps: Vec<BlockRange>,
 }
 
 impl BlockStoreState {
     /// Checks whether block with the given number is stored in the `BlockStore`.
     pub fn contains(&self, number: validator::BlockNumber) -> bool {
         let Some(last) = &self.last else { return false };
-        self.first <= number && number <= last.header().number
+        self.first <= number
+            && number <= last.header().number
+            && !self.gaps.iter().any(|gap| gap.contains(number))
+    }
+
+    /// Ranges of the stored blocks, in ascending order.
+    pub fn ranges(&self) -> Vec<BlockRange> {
+        let Some(last) = &self.last else {
+            return vec![];
+        };
+        let mut ranges = vec![];
+        let mut first = self.first;
+        for gap in &self.gaps {
+            if first < gap.first {
+                ranges.push(BlockRange {
+                    first,
+                    last: validator::BlockNumber(gap.first.0 - 1),
+                });
+            }
+            first = first.max(gap.last.next());
+        }
+        if first <= last.header().number {
+            ranges.push(BlockRange {
+                first,
+                last: last.header().number,
+            });
+        }
+        ranges
+    }
+
+    /// Checks that the gaps are non-empty, sorted, disjoint and within `(first, last)`.
+    pub fn verify_gaps(&self) -> anyhow::Result<()> {
+        if self.gaps.is_empty() {
+            return Ok(());
+        }
+        let last = self.last.as_ref().context("gaps in an empty store")?;
+        let mut prev = self.first;
+        for gap in &self.gaps {
+            anyhow::ensure!(gap.first <= gap.last, "empty gap {gap:?}");
+            anyhow::ensure!(
+                prev < gap.first,
+                "gap {gap:?} is not sorted, disjoint and after the first block"
+            );
+            prev = gap.last.next();
+        }
+        anyhow::ensure!(
+            prev <= last.header().number,
+            "gaps do not end before the last block"
+        );
+        Ok(())
+    }
+
+    /// Number of the blocks within the gaps, i.e. missing between `first` and `last`.
+    pub fn missing_blocks(&self) -> u64 {
+        self.gaps
+            .iter()
+            .map(|gap| gap.last.0 - gap.first.0 + 1)
+            .sum()
+    }
+
+    /// Drops the blocks with numbers lower than `first` from the state.
+    /// `first` should not exceed the last block.
+    pub fn prune(&mut self, first: validator::BlockNumber) {
+        if first <= self.first {
+            return;
+        }
+        self.first = first;
+        // Cut off the gaps preceding the new first block.
+        while let Some(gap) = self.gaps.first() {
+            if gap.first > self.first {
+                break;
+            }
+            self.first = self.first.max(gap.last.next());
+            self.gaps.remove(0);
+        }
     }
 
     /// Number of the next block that can be stored in the `BlockStore`.
@@ -37,10 +142,22 @@ impl BlockStoreState {
 /// Storage of a continuous range of L2 blocks.
 ///
 /// Implementations **must** propagate context cancellation using [`StorageError::Canceled`].
+///
+/// Recommended storage layout is to keep the justifications (`CommitQC`, which contain
+/// the block headers) separately from the payloads (which may be multiple MBs each),
+/// so that header-only queries (`last()`, `justification()`) and maintenance of the
+/// justifications don't read or rewrite the payloads.
+///
+/// Stores which keep the payloads in a different storage altogether (e.g. a blob storage
+/// or cheaper disks), while the justifications stay in the fast database, should implement
+/// the optional payload methods (`stores_payloads_separately()`, `payload()` and
+/// `store_payloads()`). `BlockStore` then stitches the blocks together itself.
 #[async_trait::async_trait]
 pub trait PersistentBlockStore: fmt::Debug + Send + Sync {
-    /// Genesis matching the block store content.
-    /// Consensus code calls this method only once.
+    /// Genesis matching the block store content, including the key rotations and
+    /// the protocol upgrades committed by the stored blocks (see `validator::BlockHeader`),
+    /// which have to be kept even if the blocks get pruned.
+    /// Consensus code calls this method only once and then tracks them internally.
     async fn genesis(&self, ctx: &ctx::Ctx) -> ctx::Result<validator::Genesis>;
 
     /// Last block available in storage.
@@ -48,6 +165,30 @@ pub trait PersistentBlockStore: fmt::Debug + Send + Sync {
     /// range of available blocks internally.
     async fn last(&self, ctx: &ctx::Ctx) -> ctx::Result<Option<validator::CommitQC>>;
 
+    /// Ranges of the missing blocks between the first block of the fork and `last()`,
+    /// sorted and disjoint (see `BlockStoreState::gaps`).
+    /// Consensus code calls this method only once; the gaps are never filled by the `BlockStore`.
+    /// Default implementation reports a continuous range of blocks.
+    async fn gaps(&self, _ctx: &ctx::Ctx) -> ctx::Result<Vec<BlockRange>> {
+        Ok(vec![])
+    }
+
+    /// Lowest number of the stored blocks, if the store has been pruned (see `prune()`).
+    /// Consensus code calls this method only once and then tracks the range
+    /// of available blocks internally.
+    /// Default implementation reports that no blocks have been pruned.
+    async fn first(&self, _ctx: &ctx::Ctx) -> ctx::Result<Option<validator::BlockNumber>> {
+        Ok(None)
+    }
+
+    /// Removes the blocks with numbers lower than `first` from storage.
+    /// `BlockStore` never prunes the last stored block, so that the store is never emptied.
+    /// Payloads stored separately (see `store_payloads()`) should be removed as well.
+    /// Default implementation doesn't support pruning.
+    async fn prune(&self, _ctx: &ctx::Ctx, _first: validator::BlockNumber) -> ctx::Result<()> {
+        Err(anyhow::format_err!("pruning is not supported").into())
+    }
+
     /// Gets a block by its number.
     /// Returns error if block is missing.
     /// Caller is expected to know the state (by calling `state()`)
@@ -58,6 +199,55 @@ pub trait PersistentBlockStore: fmt::Debug + Send + Sync {
         number: validator::BlockNumber,
     ) -> ctx::Result<validator::FinalBlock>;
 
+    /// Gets the justification of a block by its number, without reading the payload.
+    /// Returns error if block is missing.
+    /// Default implementation extracts the justification from `block()`,
+    /// implementations storing justifications separately should override it.
+    async fn justification(
+        &self,
+        ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<validator::CommitQC> {
+        Ok(self.block(ctx, number).await?.justification)
+    }
+
+    /// Whether the payloads are stored separately from the justifications, via `payload()`
+    /// and `store_payloads()`. If so, `BlockStore` never calls `block()`: it reads blocks
+    /// by stitching `justification()` and `payload()`, and it stores the payloads of
+    /// the blocks with `store_payloads()` before storing the blocks themselves, so that
+    /// `store_next_block()` and `store_blocks()` may skip the payloads.
+    /// Default implementation keeps the payloads together with the justifications.
+    fn stores_payloads_separately(&self) -> bool {
+        false
+    }
+
+    /// Gets the payload of a block by its number.
+    /// Only called if `stores_payloads_separately()`.
+    /// Returns error if the payload is missing.
+    async fn payload(
+        &self,
+        _ctx: &ctx::Ctx,
+        _number: validator::BlockNumber,
+    ) -> ctx::Result<validator::Payload> {
+        Err(anyhow::format_err!("storing payloads separately is not supported").into())
+    }
+
+    /// Persistently stores the payloads of a batch of consecutive blocks,
+    /// which are about to be stored with `store_blocks()`.
+    /// Only called if `stores_payloads_separately()`.
+    /// The same payloads may be stored again (e.g. when retrying after a failure),
+    /// and the payloads of the blocks which end up not being stored may be left behind
+    /// (they are overwritten once the blocks are stored).
+    /// Same as `store_next_block()`, it should return only after the payloads are stored
+    /// PERSISTENTLY.
+    async fn store_payloads(
+        &self,
+        _ctx: &ctx::Ctx,
+        _blocks: &[validator::FinalBlock],
+    ) -> ctx::Result<()> {
+        Err(anyhow::format_err!("storing payloads separately is not supported").into())
+    }
+
     /// Persistently store a block.
     /// Implementations are only required to accept a block directly after the current last block,
     /// so that the stored blocks always constitute a continuous range.
@@ -68,22 +258,229 @@ pub trait PersistentBlockStore: fmt::Debug + Send + Sync {
         ctx: &ctx::Ctx,
         block: &validator::FinalBlock,
     ) -> ctx::Result<()>;
+
+    /// Persistently store a batch of consecutive blocks, the first of which
+    /// directly follows the current last block.
+    /// Implementations can override it to make the whole batch durable at once
+    /// (e.g. with a single fsync), which speeds up catching up considerably.
+    /// Same as `store_next_block()`, it should return only after all the blocks are stored
+    /// PERSISTENTLY. In case of an error, a prefix of the batch may have been stored:
+    /// `BlockStore` checks `last()` before retrying.
+    /// Default implementation stores the blocks one by one.
+    async fn store_blocks(
+        &self,
+        ctx: &ctx::Ctx,
+        blocks: &[validator::FinalBlock],
+    ) -> ctx::Result<()> {
+        for block in blocks {
+            self.store_next_block(ctx, block).await?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
-struct Inner {
-    queued_state: sync::watch::Sender<BlockStoreState>,
-    persisted_state: BlockStoreState,
-    queue: VecDeque<validator::FinalBlock>,
+/// Configuration of the `BlockStore`.
+#[derive(Debug, Clone)]
+pub struct BlockStoreConfig {
+    /// Time limit for persisting the queued blocks, once the `BlockStoreRunner` gets canceled.
+    /// Blocks which were not persisted within this time are dropped.
+    /// Zero disables draining the queue on shutdown.
+    pub shutdown_drain_timeout: time::Duration,
+    /// Delay before retrying a failed `store_blocks()` call.
+    /// The delay doubles with every consecutive failure, up to `store_retry_max_delay`.
+    pub store_retry_initial_delay: time::Duration,
+    /// Upper bound on the delay between the retries of `store_blocks()`.
+    pub store_retry_max_delay: time::Duration,
+    /// Number of consecutive failed `store_blocks()` calls for the same block,
+    /// after which the failure is considered fatal and `BlockStoreRunner` returns an error.
+    /// Values `<= 1` disable retrying.
+    pub store_max_attempts: usize,
+    /// Max number of the queued blocks passed to a single `store_blocks()` call.
+    /// Values `<= 1` make the blocks be stored one at a time.
+    pub store_batch_size: usize,
+    /// Max number of `PersistentBlockStore::block()` calls executed concurrently.
+    /// Should match the number of concurrent reads that the persistent storage handles well.
+    pub max_concurrent_reads: usize,
+    /// Max total payload size (in bytes) of the blocks recently read from
+    /// the persistent storage to keep in memory. Zero disables the cache.
+    pub block_cache_bytes: usize,
+    /// Max number of the blocks kept in the cache, regardless of their size,
+    /// so that the blocks with small payloads don't accumulate without bound.
+    /// Zero disables the cache.
+    pub block_cache_capacity: usize,
+    /// Admission control of the queue, based on the lag of the persistent storage.
+    pub admission: AdmissionConfig,
+}
+
+impl Default for BlockStoreConfig {
+    fn default() -> Self {
+        Self {
+            shutdown_drain_timeout: time::Duration::seconds(5),
+            store_retry_initial_delay: time::Duration::milliseconds(100),
+            store_retry_max_delay: time::Duration::seconds(10),
+            store_max_attempts: 10,
+            store_batch_size: 32,
+            max_concurrent_reads: 8,
+            block_cache_bytes: 64 << 20,
+            block_cache_capacity: 1024,
+            admission: AdmissionConfig::default(),
+        }
+    }
+}
+
+/// Admission control of the `BlockStore` queue.
+/// The lag is the number of the queued blocks which are not persisted yet.
+/// Once it grows too large, `queue_block()` gets slowed down or paused, which propagates
+/// the backpressure to the caller (e.g. consensus), instead of growing the queue without bound.
+/// Default config admits all the blocks immediately.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdmissionConfig {
+    /// Lag at which every `queue_block()` call gets delayed by `slow_down_delay`.
+    /// `None` disables slowing down.
+    pub slow_down_lag: Option<usize>,
+    /// Delay of the `queue_block()` calls while the lag is at least `slow_down_lag`.
+    pub slow_down_delay: time::Duration,
+    /// Lag at which `queue_block()` calls wait until the lag drops below it.
+    /// `None` disables pausing.
+    pub pause_lag: Option<usize>,
+}
+
+impl AdmissionConfig {
+    /// Lag at which the queue is paused. Zero lag would block the queue forever.
+    fn pause_lag(&self) -> Option<usize> {
+        self.pause_lag.map(|lag| lag.max(1))
+    }
+
+    /// Admission state of the queue with the given lag.
+    /// Returns `None` if the blocks are admitted immediately.
+    fn lagging(&self, lag: usize) -> Option<StorageLagging> {
+        let paused = self.pause_lag().is_some_and(|max| lag >= max);
+        let slowed_down = self.slow_down_lag.is_some_and(|max| lag >= max);
+        (paused || slowed_down).then_some(StorageLagging { lag, paused })
+    }
+}
+
+/// Event emitted while the persistent storage lags behind the queue
+/// more than allowed by `AdmissionConfig`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageLagging {
+    /// Number of the queued blocks which are not persisted yet.
+    pub lag: usize,
+    /// Whether `queue_block()` is paused (otherwise it is only slowed down).
+    pub paused: bool,
+}
+
+/// Timing metadata of a block, collected before the block is queued in the `BlockStore`.
+/// Used to export the end-to-end latency of blocks, broken down by stage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockTimings {
+    /// Time at which the block proposal has been received.
+    pub proposed: Option<time::Instant>,
+    /// Time at which the CommitQC for the block has been received.
+    pub certified: Option<time::Instant>,
+}
+
+impl BlockTimings {
+    /// Latency of the stages of a block which has been queued at `queued`
+    /// and persisted at `persisted`.
+    pub(crate) fn latency(&self, queued: time::Instant, persisted: time::Instant) -> BlockLatency {
+        let start = self.proposed.or(self.certified).unwrap_or(queued);
+        BlockLatency {
+            certify: self
+                .proposed
+                .zip(self.certified)
+                .map(|(proposed, certified)| certified - proposed),
+            queue: self.certified.map(|certified| queued - certified),
+            persist: persisted - queued,
+            total: persisted - start,
+        }
+    }
+}
+
+/// Latency of the stages of a persisted block, see `metrics::BlockStage`.
+/// The stages which haven't been observed by this node (e.g. the proposal of a block
+/// fetched from a peer) are `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BlockLatency {
+    pub(crate) certify: Option<time::Duration>,
+    pub(crate) queue: Option<time::Duration>,
+    pub(crate) persist: time::Duration,
+    pub(crate) total: time::Duration,
+}
+
+impl BlockLatency {
+    /// Exports the latency to the metrics.
+    fn observe(&self) {
+        use metrics::BlockStage as S;
+        let m = &metrics::BLOCK.latency;
+        if let Some(certify) = self.certify {
+            m[&S::Certify].observe_latency(certify);
+        }
+        if let Some(queue) = self.queue {
+            m[&S::Queue].observe_latency(queue);
+        }
+        m[&S::Persist].observe_latency(self.persist);
+        m[&S::Total].observe_latency(self.total);
+    }
+}
+
+/// Blocks queued to be persisted, which are not persisted yet, in order.
+#[derive(Debug, Default)]
+struct Queue {
+    blocks: VecDeque<validator::FinalBlock>,
+    /// Timings of the blocks, together with the time they have been queued at.
+    timings: VecDeque<(BlockTimings, time::Instant)>,
+}
+
+impl Queue {
+    /// Finds the queued block with the given number.
+    fn get(&self, number: validator::BlockNumber) -> Option<&validator::FinalBlock> {
+        let first = self.blocks.front()?.number();
+        let idx = number.0.checked_sub(first.0)?;
+        self.blocks.get(usize::try_from(idx).ok()?)
+    }
 }
 
 /// A wrapper around a PersistentBlockStore which adds caching blocks in-memory
 /// and other useful utilities.
+///
+/// The state is split, so that the readers, `queue_block()` and the persisting loop
+/// don't contend on a single lock:
+/// * `queued_state` is modified only by `queue_block()` (and `prune()`) and notifies the subscribers,
+/// * `persisted_state` is modified only by the persisting loop (and `prune()`),
+/// * `queue` is locked only for the short, non-blocking queue operations.
+///
+/// A block is pushed to `queue` before it is added to `queued_state` and it is popped from `queue`
+/// only after it has been persisted (and before it is added to `persisted_state`), so every block
+/// in `queued_state` can be found either in `queue` or in the persistent storage.
+/// Locks are never nested, except for `queue` being locked while `queued_state` is modified.
 #[derive(Debug)]
 pub struct BlockStore {
-    inner: sync::watch::Sender<Inner>,
+    /// State including the queued blocks.
+    queued_state: sync::watch::Sender<BlockStoreState>,
+    /// State of the persisted blocks.
+    persisted_state: sync::watch::Sender<BlockStoreState>,
+    /// Queued blocks which are not persisted yet.
+    queue: Mutex<Queue>,
     persistent: Box<dyn PersistentBlockStore>,
-    genesis: validator::Genesis,
+    /// Genesis including the key rotations committed by the queued blocks.
+    /// It is updated together with `queued_state`.
+    genesis: sync::watch::Sender<Arc<validator::Genesis>>,
+    config: BlockStoreConfig,
+    /// Limits the number of concurrent `persistent.block()` calls.
+    reads: sync::Semaphore,
+    cache: Mutex<cache::BlockCache>,
+    wal: Option<Wal>,
+    /// `Some` while the persistent storage is lagging, see `AdmissionConfig`.
+    lagging: sync::watch::Sender<Option<StorageLagging>>,
+}
+
+/// Write-ahead log of the queue.
+#[derive(Debug)]
+struct Wal {
+    log: Box<dyn BlockQueueWal>,
+    /// Serializes appending blocks to the log with truncating it.
+    lock: sync::Mutex<()>,
 }
 
 /// Runner of the BlockStore background tasks.
@@ -92,6 +489,8 @@ pub struct BlockStoreRunner(Arc<BlockStore>);
 
 impl BlockStoreRunner {
     /// Runs the background tasks of the BlockStore.
+    /// On cancellation, the blocks remaining in the queue are persisted
+    /// (within `BlockStoreConfig::shutdown_drain_timeout`) before returning.
     pub async fn run(self, ctx: &ctx::Ctx) -> anyhow::Result<()> {
         #[vise::register]
         static COLLECTOR: vise::Collector<Option<metrics::BlockStore>> = vise::Collector::new();
@@ -99,48 +498,92 @@ impl BlockStoreRunner {
         let _ = COLLECTOR.before_scrape(move || Some(store_ref.upgrade()?.scrape_metrics()));
 
         let res = async {
-            let inner = &mut self.0.inner.subscribe();
+            let queued = &mut self.0.queued_state.subscribe();
             loop {
-                let block = sync::wait_for(ctx, inner, |inner| !inner.queue.is_empty())
-                    .await?
-                    .queue[0]
-                    .clone();
-
-                // TODO: monitor errors as well.
-                let t = metrics::PERSISTENT_BLOCK_STORE
-                    .store_next_block_latency
-                    .start();
-                self.0.persistent.store_next_block(ctx, &block).await?;
-                t.observe();
-                tracing::info!(
-                    "stored block #{}: {:#?}",
-                    block.header().number,
-                    block.header().hash()
-                );
-
-                self.0.inner.send_modify(|inner| {
-                    debug_assert_eq!(inner.persisted_state.next(), block.header().number);
-                    inner.persisted_state.last = Some(block.justification.clone());
-                    inner.queue.pop_front();
-                });
+                // The value is marked as seen before the queue is checked,
+                // so that a block queued in between is not missed.
+                queued.borrow_and_update();
+                if self.0.queue_len() == 0 {
+                    sync::changed(ctx, queued).await?;
+                    continue;
+                }
+                self.0.persist_with_retry(ctx).await?;
             }
         }
         .await;
         match res {
-            Ok(()) | Err(ctx::Error::Canceled(_)) => Ok(()),
+            Ok(()) | Err(ctx::Error::Canceled(_)) => self.drain(ctx).await,
             Err(ctx::Error::Internal(err)) => Err(err),
         }
     }
+
+    /// Persists the blocks remaining in the queue, using a context detached from
+    /// the (already canceled) `ctx`, bounded by `shutdown_drain_timeout`.
+    async fn drain(&self, ctx: &ctx::Ctx) -> anyhow::Result<()> {
+        let timeout = self.0.config.shutdown_drain_timeout;
+        if timeout <= time::Duration::ZERO {
+            return Ok(());
+        }
+        let ctx = &ctx.detached_with_timeout(timeout);
+        loop {
+            if self.0.queue_len() == 0 {
+                return Ok(());
+            }
+            match self.0.persist_with_retry(ctx).await {
+                Ok(()) => {}
+                Err(ctx::Error::Canceled(_)) => {
+                    let queued = self.0.queue_len();
+                    tracing::warn!(
+                        "shutdown drain timed out, {queued} queued blocks were not persisted"
+                    );
+                    return Ok(());
+                }
+                Err(ctx::Error::Internal(err)) => return Err(err),
+            }
+        }
+    }
 }
 
 impl BlockStore {
-    /// Constructs a BlockStore.
+    /// Constructs a BlockStore with the default config.
     /// BlockStore takes ownership of the passed PersistentBlockStore,
     /// i.e. caller should modify the underlying persistent storage
     /// ONLY through the constructed BlockStore.
     pub async fn new(
         ctx: &ctx::Ctx,
         persistent: Box<dyn PersistentBlockStore>,
+    ) -> ctx::Result<(Arc<Self>, BlockStoreRunner)> {
+        Self::new_with_config(ctx, persistent, BlockStoreConfig::default()).await
+    }
+
+    /// Constructs a BlockStore with the given config.
+    pub async fn new_with_config(
+        ctx: &ctx::Ctx,
+        persistent: Box<dyn PersistentBlockStore>,
+        config: BlockStoreConfig,
+    ) -> ctx::Result<(Arc<Self>, BlockStoreRunner)> {
+        Self::build(ctx, persistent, config, None).await
+    }
+
+    /// Constructs a BlockStore with the given config, which logs the queued blocks to `wal`.
+    /// The blocks which were queued, but not persisted before the previous shutdown (or crash)
+    /// are read from `wal` and queued again.
+    pub async fn new_with_wal(
+        ctx: &ctx::Ctx,
+        persistent: Box<dyn PersistentBlockStore>,
+        config: BlockStoreConfig,
+        wal: Box<dyn BlockQueueWal>,
+    ) -> ctx::Result<(Arc<Self>, BlockStoreRunner)> {
+        let (this, runner) = Self::build(ctx, persistent, config, Some(wal)).await?;
+        this.replay_wal(ctx).await?;
+        Ok((this, runner))
+    }
+
+    async fn build(
+        ctx: &ctx::Ctx,
+        persistent: Box<dyn PersistentBlockStore>,
+        config: BlockStoreConfig,
+        wal: Option<Box<dyn BlockQueueWal>>,
     ) -> ctx::Result<(Arc<Self>, BlockStoreRunner)> {
         let t = metrics::PERSISTENT_BLOCK_STORE.genesis_latency.start();
         let genesis = persistent.genesis(ctx).await.wrap("persistent.genesis()")?;
@@ -148,115 +591,415 @@ impl BlockStore {
         let t = metrics::PERSISTENT_BLOCK_STORE.last_latency.start();
         let last = persistent.last(ctx).await.wrap("persistent.last()")?;
         t.observe();
+        // Blocks preceding the fork belong to the previous genesis (the store
+        // has been reopened after a fork), so the fork starts with an empty range.
+        let last = last.filter(|last| last.header().number >= genesis.fork.first_block);
         if let Some(last) = &last {
             last.verify(&genesis).context("last.verify()")?;
         }
-        let state = BlockStoreState {
+        let gaps = match &last {
+            Some(_) => persistent.gaps(ctx).await.wrap("persistent.gaps()")?,
+            None => vec![],
+        };
+        let mut state = BlockStoreState {
             first: genesis.fork.first_block,
             last,
+            gaps,
         };
+        state.verify_gaps().context("state.verify_gaps()")?;
+        if let Some(last) = &state.last {
+            let last = last.header().number;
+            if let Some(first) = persistent.first(ctx).await.wrap("persistent.first()")? {
+                state.prune(first.min(last));
+            }
+        }
+        // The gaps are never filled, so make sure that the operators know about them.
+        if !state.gaps.is_empty() {
+            tracing::warn!(
+                "{} blocks are missing in the gaps {:?} of the stored blocks; \
+                 they won't be synced nor served to the peers",
+                state.missing_blocks(),
+                state.gaps
+            );
+        }
         let this = Arc::new(Self {
-            inner: sync::watch::channel(Inner {
-                queued_state: sync::watch::channel(state.clone()).0,
-                persisted_state: state,
-                queue: VecDeque::new(),
-            })
-            .0,
-            genesis,
+            queued_state: sync::watch::channel(state.clone()).0,
+            persisted_state: sync::watch::channel(state).0,
+            queue: Mutex::default(),
+            genesis: sync::watch::channel(Arc::new(genesis)).0,
             persistent,
+            reads: sync::Semaphore::new(config.max_concurrent_reads.max(1)),
+            cache: Mutex::new(cache::BlockCache::new(
+                config.block_cache_bytes,
+                config.block_cache_capacity,
+            )),
+            wal: wal.map(|log| Wal {
+                log,
+                lock: sync::Mutex::new(()),
+            }),
+            lagging: sync::watch::channel(None).0,
+            config,
         });
         // Verify the first block.
-        if let Some(block) = this.block(ctx, this.genesis.fork.first_block).await? {
+        let genesis = this.genesis();
+        if let Some(block) = this.block(ctx, genesis.fork.first_block).await? {
             block
-                .verify(&this.genesis)
-                .with_context(|| format!("verify({:?})", this.genesis.fork.first_block))?;
+                .verify(&genesis)
+                .with_context(|| format!("verify({:?})", genesis.fork.first_block))?;
         }
         Ok((this.clone(), BlockStoreRunner(this)))
     }
 
-    /// Genesis specification for this block store.
-    pub fn genesis(&self) -> &validator::Genesis {
-        &self.genesis
+    /// Queues the blocks from the write-ahead log, which haven't been persisted yet.
+    /// Replaying stops at the first block which doesn't extend the queue,
+    /// so that a corrupted log can't prevent the node from starting.
+    async fn replay_wal(&self, ctx: &ctx::Ctx) -> ctx::Result<()> {
+        let Some(wal) = &self.wal else {
+            return Ok(());
+        };
+        let blocks = wal.log.read(ctx).await.wrap("wal.read()")?;
+        let mut replayed = 0;
+        for block in blocks {
+            let next = self.subscribe().borrow().next();
+            if block.number() < next {
+                continue;
+            }
+            if block.number() > next {
+                tracing::warn!("block #{next} is missing in the write-ahead log, dropping the subsequent blocks");
+                break;
+            }
+            match self.queue(ctx, block, BlockTimings::default(), false).await {
+                Ok(_) => replayed += 1,
+                Err(ctx::Error::Internal(err)) => {
+                    tracing::warn!("invalid block in the write-ahead log, dropping the subsequent blocks: {err:#}");
+                    break;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        tracing::info!("queued {replayed} blocks from the write-ahead log");
+        Ok(())
+    }
+
+    /// Genesis specification for this block store, including the key rotations
+    /// committed by the queued blocks.
+    pub fn genesis(&self) -> Arc<validator::Genesis> {
+        self.genesis.borrow().clone()
     }
 
-    /// Fetches a block (from queue or persistent storage).
+    /// Fetches a block (from queue, cache or persistent storage).
+    /// Up to `BlockStoreConfig::max_concurrent_reads` blocks are read from
+    /// the persistent storage concurrently, the remaining calls wait for their turn.
     pub async fn block(
         &self,
         ctx: &ctx::Ctx,
         number: validator::BlockNumber,
     ) -> ctx::Result<Option<validator::FinalBlock>> {
-        {
-            let inner = self.inner.borrow();
-            if !inner.queued_state.borrow().contains(number) {
-                return Ok(None);
-            }
-            if !inner.persisted_state.contains(number) {
-                // Subtraction is safe, because we know that the block
-                // is in inner.queue at this point.
-                let idx = number.0 - inner.persisted_state.next().0;
-                return Ok(inner.queue.get(idx as usize).cloned());
-            }
+        if !self.queued_state.borrow().contains(number) {
+            return Ok(None);
         }
+        // If the block is not in the queue, it has already been persisted.
+        if let Some(block) = self.queue.lock().unwrap().get(number) {
+            return Ok(Some(block.clone()));
+        }
+        let m = &metrics::BLOCK_CACHE;
+        if let Some(block) = self.cache.lock().unwrap().get(number) {
+            m.hits.inc();
+            return Ok(Some(block));
+        }
+        let _permit = sync::acquire(ctx, &self.reads).await?;
+        // The block might have been read by another call, while we were waiting.
+        if let Some(block) = self.cache.lock().unwrap().get(number) {
+            m.hits.inc();
+            return Ok(Some(block));
+        }
+        m.misses.inc();
         let t = metrics::PERSISTENT_BLOCK_STORE.block_latency.start();
-        let block = self
+        let block = self.persistent_block(ctx, number).await?;
+        t.observe();
+        let evicted = self.cache.lock().unwrap().insert(block.clone());
+        m.evictions.inc_by(evicted as u64);
+        Ok(Some(block))
+    }
+
+    /// Reads a block from the persistent storage. If the payloads are stored separately,
+    /// stitches the block together from its justification and its payload.
+    async fn persistent_block(
+        &self,
+        ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<validator::FinalBlock> {
+        if !self.persistent.stores_payloads_separately() {
+            return self
+                .persistent
+                .block(ctx, number)
+                .await
+                .wrap("persistent.block()");
+        }
+        let justification = self
+            .persistent
+            .justification(ctx, number)
+            .await
+            .wrap("persistent.justification()")?;
+        let payload = self
+            .persistent
+            .payload(ctx, number)
+            .await
+            .wrap("persistent.payload()")?;
+        // The payloads might be kept on a less reliable storage, so they are checked
+        // against the header before they are served.
+        if payload.hash() != justification.header().payload {
+            return Err(anyhow::format_err!(
+                "stored payload of block {number} doesn't match its header"
+            )
+            .into());
+        }
+        // The committed key rotations and protocol upgrades are kept in the genesis.
+        let key_rotation = match &justification.header().key_rotation {
+            Some(hash) => Some(
+                self.genesis()
+                    .key_rotations
+                    .find(hash)
+                    .cloned()
+                    .with_context(|| format!("key rotation of block {number} is missing"))?,
+            ),
+            None => None,
+        };
+        let protocol_upgrade = match &justification.header().protocol_upgrade {
+            Some(hash) => Some(
+                self.genesis()
+                    .protocol_upgrades
+                    .find(hash)
+                    .cloned()
+                    .with_context(|| format!("protocol upgrade of block {number} is missing"))?,
+            ),
+            None => None,
+        };
+        Ok(validator::FinalBlock {
+            payload,
+            justification,
+            key_rotation,
+            protocol_upgrade,
+        })
+    }
+
+    /// Fetches the justification of a block (from queue or persistent storage),
+    /// without fetching its payload.
+    pub async fn justification(
+        &self,
+        ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<Option<validator::CommitQC>> {
+        if !self.queued_state.borrow().contains(number) {
+            return Ok(None);
+        }
+        // If the block is not in the queue, it has already been persisted.
+        if let Some(block) = self.queue.lock().unwrap().get(number) {
+            return Ok(Some(block.justification.clone()));
+        }
+        let t = metrics::PERSISTENT_BLOCK_STORE
+            .justification_latency
+            .start();
+        let justification = self
             .persistent
-            .block(ctx, number)
+            .justification(ctx, number)
             .await
-            .wrap("persistent.block()")?;
+            .wrap("persistent.justification()")?;
         t.observe();
-        Ok(Some(block))
+        Ok(Some(justification))
     }
 
     /// Insert block to a queue to be persisted eventually.
     /// Since persisting a block may take a significant amount of time,
     /// BlockStore contains a queue of blocks waiting to be persisted.
     /// `queue_block()` adds a block to the queue as soon as all intermediate
-    /// blocks are queued_state as well. Queue is unbounded, so it is caller's
-    /// responsibility to manage the queue size.
+    /// blocks are queued_state as well. Queue is unbounded by default, so it is caller's
+    /// responsibility to manage the queue size, unless `BlockStoreConfig::admission` is set.
     pub async fn queue_block(
         &self,
         ctx: &ctx::Ctx,
         block: validator::FinalBlock,
     ) -> ctx::Result<()> {
+        self.queue_block_with_timings(ctx, block, BlockTimings::default())
+            .await
+    }
+
+    /// Same as `queue_block()`, but additionally takes the timing metadata of the block,
+    /// which is used to observe its end-to-end latency once it gets persisted.
+    pub async fn queue_block_with_timings(
+        &self,
+        ctx: &ctx::Ctx,
+        block: validator::FinalBlock,
+        timings: BlockTimings,
+    ) -> ctx::Result<()> {
+        self.queue(ctx, block, timings, true).await?;
+        Ok(())
+    }
+
+    /// Same as `queue_block()`, but reports whether the block has been queued by this call.
+    /// Returns `false` if a block with the same number has been queued (e.g. by another source)
+    /// or pruned already. That block might differ from `block`, so callers which don't trust
+    /// the source of `block` should compare it against the stored one.
+    /// The check and the insertion are atomic.
+    pub async fn try_queue_block(
+        &self,
+        ctx: &ctx::Ctx,
+        block: validator::FinalBlock,
+    ) -> ctx::Result<bool> {
+        self.queue(ctx, block, BlockTimings::default(), true).await
+    }
+
+    /// Queues the block, appending it to the write-ahead log first if `log` is set.
+    /// Returns whether the block has been queued by this call.
+    async fn queue(
+        &self,
+        ctx: &ctx::Ctx,
+        block: validator::FinalBlock,
+        timings: BlockTimings,
+        log: bool,
+    ) -> ctx::Result<bool> {
         let number = block.number();
-        {
+        // Genesis with the key rotation and the protocol upgrade committed by the block applied.
+        // `queued_state` is borrowed until it is computed, so that the genesis doesn't change
+        // in the meantime.
+        let updated = {
             let sub = &mut self.subscribe();
             let queued_state =
                 sync::wait_for(ctx, sub, |queued_state| queued_state.next() >= number).await?;
             if queued_state.next() > number {
-                return Ok(());
+                return Ok(false);
             }
-            block.verify(&self.genesis).context("block.verify()")?;
+            let genesis = self.genesis();
+            block.verify(&genesis).context("block.verify()")?;
             // Verify parent hash, if previous block is available.
             if let Some(last) = queued_state.last.as_ref() {
-                if Some(last.header().hash()) != block.header().parent {
+                let want = genesis.header_hash(last.header());
+                if Some(want) != block.header().parent {
                     return Err(anyhow::format_err!(
-                        "block.parent = {:?}, want {:?}",
+                        "block.parent = {:?}, want {want:?}",
                         block.header().parent,
-                        last.header().hash()
                     )
                     .into());
                 }
             }
+            let mut updated = None;
+            if let Some(cert) = &block.key_rotation {
+                updated = Some(
+                    genesis
+                        .with_key_rotation(cert.clone())
+                        .context("genesis.with_key_rotation()")?,
+                );
+            }
+            if let Some(qc) = &block.protocol_upgrade {
+                updated = Some(
+                    updated
+                        .as_ref()
+                        .unwrap_or(&*genesis)
+                        .with_protocol_upgrade(qc.clone(), number)
+                        .context("genesis.with_protocol_upgrade()")?,
+                );
+            }
+            updated.map(Arc::new)
+        };
+        // Blocks replayed from the write-ahead log are queued before the runner starts,
+        // so they bypass the admission control.
+        if log {
+            self.admit(ctx).await?;
         }
-        self.inner.send_if_modified(|inner| {
-            let modified = inner.queued_state.send_if_modified(|queued_state| {
-                // It may happen that the same block is queued_state by 2 calls.
-                if queued_state.next() != number {
-                    return false;
-                }
-                queued_state.last = Some(block.justification.clone());
-                true
-            });
-            if !modified {
+        // The lock is held until the block is queued, so that the log doesn't get
+        // cleared in between.
+        let _guard = match &self.wal {
+            Some(wal) if log => {
+                let guard = sync::lock(ctx, &wal.lock).await?.into_async();
+                wal.log.append(ctx, &block).await.wrap("wal.append()")?;
+                Some(guard)
+            }
+            _ => None,
+        };
+        let now = ctx.now();
+        let mut queue = self.queue.lock().unwrap();
+        let queued = self.queued_state.send_if_modified(|queued_state| {
+            // It may happen that the same block is queued_state by 2 calls.
+            if queued_state.next() != number {
                 return false;
             }
-            inner.queue.push_back(block);
+            queued_state.last = Some(block.justification.clone());
+            // The block is pushed and its key rotation and protocol upgrade are applied
+            // before the subscribers are notified.
+            if let Some(genesis) = updated {
+                self.genesis.send_replace(genesis);
+            }
+            queue.blocks.push_back(block);
+            queue.timings.push_back((timings, now));
             true
         });
+        drop(queue);
+        self.update_lagging();
+        Ok(queued)
+    }
+
+    /// Waits until the queue admits another block, according to `AdmissionConfig`.
+    async fn admit(&self, ctx: &ctx::Ctx) -> ctx::OrCanceled<()> {
+        let cfg = &self.config.admission;
+        let m = &metrics::ADMISSION;
+        if let Some(max) = cfg.pause_lag() {
+            if self.queue_len() >= max {
+                let t = m.paused_latency.start();
+                // The queue shrinks only when the blocks get persisted,
+                // which is announced by `persisted_state`.
+                let persisted = &mut self.persisted_state.subscribe();
+                loop {
+                    persisted.borrow_and_update();
+                    if self.queue_len() < max {
+                        break;
+                    }
+                    sync::changed(ctx, persisted).await?;
+                }
+                t.observe();
+            }
+        }
+        if let Some(max) = cfg.slow_down_lag {
+            if self.queue_len() >= max {
+                m.slowed_down.inc();
+                ctx.sleep(cfg.slow_down_delay).await?;
+            }
+        }
         Ok(())
     }
 
+    /// Number of the queued blocks which are not persisted yet.
+    pub fn queue_len(&self) -> usize {
+        self.queue.lock().unwrap().blocks.len()
+    }
+
+    /// Recomputes the admission state of the queue, emitting `StorageLagging` on changes.
+    fn update_lagging(&self) {
+        let lag = self.queue_len();
+        let lagging = self.config.admission.lagging(lag);
+        self.lagging.send_if_modified(|old| {
+            if *old == lagging {
+                return false;
+            }
+            match (&old, &lagging) {
+                (None, Some(new)) => {
+                    metrics::ADMISSION.lagging.inc();
+                    tracing::warn!("persistent storage is lagging: {new:?}");
+                }
+                (Some(_), None) => tracing::info!("persistent storage caught up"),
+                _ => {}
+            }
+            *old = lagging;
+            true
+        });
+    }
+
+    /// Subscribes to the `StorageLagging` events.
+    /// The value is `Some` while the persistent storage lags behind the queue
+    /// more than allowed by `BlockStoreConfig::admission`.
+    pub fn subscribe_lagging(&self) -> sync::watch::Receiver<Option<StorageLagging>> {
+        self.lagging.subscribe()
+    }
+
     /// Waits until the given block is queued to be stored.
     pub async fn wait_until_queued(
         &self,
@@ -276,25 +1019,270 @@ impl BlockStore {
         ctx: &ctx::Ctx,
         number: validator::BlockNumber,
     ) -> ctx::OrCanceled<()> {
-        sync::wait_for(ctx, &mut self.inner.subscribe(), |inner| {
-            number < inner.persisted_state.next()
-        })
+        sync::wait_for(
+            ctx,
+            &mut self.persisted_state.subscribe(),
+            |persisted_state| number < persisted_state.next(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Waits until all the blocks queued before this call are stored persistently.
+    /// Useful for embedders which need to force durability at checkpoints.
+    pub async fn flush(&self, ctx: &ctx::Ctx) -> ctx::OrCanceled<()> {
+        let next = self.queued_state.borrow().next();
+        sync::wait_for(
+            ctx,
+            &mut self.persisted_state.subscribe(),
+            |persisted_state| next <= persisted_state.next(),
+        )
         .await?;
         Ok(())
     }
 
+    /// Persists a batch of blocks from the front of the queue, retrying with exponential backoff
+    /// on failure. Returns an error once `store_max_attempts` consecutive attempts have failed.
+    async fn persist_with_retry(&self, ctx: &ctx::Ctx) -> ctx::Result<()> {
+        let mut delay = self.config.store_retry_initial_delay;
+        let mut attempt = 1;
+        loop {
+            // The batch is taken anew on every attempt,
+            // since a failed attempt might have stored some of the blocks.
+            let batch: Vec<_> = {
+                let n = self.config.store_batch_size.max(1);
+                let queue = self.queue.lock().unwrap();
+                queue.blocks.iter().take(n).cloned().collect()
+            };
+            let Some(first) = batch.first().map(|b| b.header().number) else {
+                return Ok(());
+            };
+            match self.persist(ctx, &batch).await {
+                Err(ctx::Error::Internal(err)) if attempt < self.config.store_max_attempts => {
+                    tracing::warn!(
+                        "storing block #{first} failed (attempt {attempt}/{}), retrying in {delay}: {err:#}",
+                        self.config.store_max_attempts,
+                    );
+                    metrics::PERSISTENT_BLOCK_STORE
+                        .store_next_block_retries
+                        .inc();
+                    ctx.sleep(delay).await?;
+                    delay = std::cmp::min(delay * 2, self.config.store_retry_max_delay);
+                    attempt += 1;
+                }
+                Err(ctx::Error::Internal(err)) => {
+                    return Err(err
+                        .context(format!("storing block #{first} failed {attempt} times"))
+                        .into())
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Persists a batch of blocks from the front of the queue.
+    async fn persist(&self, ctx: &ctx::Ctx, blocks: &[validator::FinalBlock]) -> ctx::Result<()> {
+        let m = &metrics::PERSISTENT_BLOCK_STORE;
+        let t = ctx.now();
+        let res = self.persistent_store(ctx, blocks).await;
+        let result = match &res {
+            Ok(()) => metrics::ResultLabel::Ok,
+            Err(ctx::Error::Internal(_)) => {
+                m.store_next_block_errors.inc();
+                metrics::ResultLabel::Err
+            }
+            Err(ctx::Error::Canceled(_)) => return res,
+        };
+        m.store_next_block_latency[&result].observe_latency(ctx.now() - t);
+        m.store_batch_size.observe(blocks.len());
+        let stored = match res {
+            Ok(()) => blocks.len(),
+            Err(err) => {
+                // Some prefix of the batch might have been stored before the failure.
+                if let Ok(Some(last)) = self.persistent.last(ctx).await {
+                    let next = self.persisted_state.borrow().next();
+                    let stored = last.header().number.next().0.saturating_sub(next.0);
+                    let stored = usize::try_from(stored).unwrap_or(usize::MAX);
+                    self.mark_persisted(ctx, &blocks[..stored.min(blocks.len())])
+                        .await?;
+                }
+                return Err(err);
+            }
+        };
+        self.mark_persisted(ctx, &blocks[..stored]).await?;
+        Ok(())
+    }
+
+    /// Stores a batch of blocks in the persistent storage. If the payloads are stored separately,
+    /// they are stored first, so that a stored justification never lacks its payload.
+    async fn persistent_store(
+        &self,
+        ctx: &ctx::Ctx,
+        blocks: &[validator::FinalBlock],
+    ) -> ctx::Result<()> {
+        if self.persistent.stores_payloads_separately() {
+            self.persistent
+                .store_payloads(ctx, blocks)
+                .await
+                .wrap("persistent.store_payloads()")?;
+        }
+        self.persistent.store_blocks(ctx, blocks).await
+    }
+
+    /// Removes the persisted blocks from the front of the queue.
+    async fn mark_persisted(
+        &self,
+        ctx: &ctx::Ctx,
+        blocks: &[validator::FinalBlock],
+    ) -> ctx::OrCanceled<()> {
+        let Some(last) = blocks.last() else {
+            return Ok(());
+        };
+        tracing::info!(
+            "stored blocks #{}..=#{}: {:#?}",
+            blocks[0].header().number,
+            last.header().number,
+            self.genesis().header_hash(last.header())
+        );
+        let now = ctx.now();
+        // The blocks are popped from the queue before they are added to `persisted_state`,
+        // so that the waiters notified by `persisted_state` observe the shrunk queue.
+        // In between, the blocks are read from the persistent storage.
+        {
+            let mut queue = self.queue.lock().unwrap();
+            for _ in blocks {
+                queue.blocks.pop_front();
+                if let Some((timings, queued)) = queue.timings.pop_front() {
+                    timings.latency(queued, now).observe();
+                }
+            }
+        }
+        self.persisted_state.send_modify(|persisted_state| {
+            for block in blocks {
+                debug_assert_eq!(persisted_state.next(), block.header().number);
+                persisted_state.last = Some(block.justification.clone());
+            }
+        });
+        self.update_lagging();
+        if let Some(wal) = &self.wal {
+            let _guard = sync::lock(ctx, &wal.lock).await?.into_async();
+            let next = self.persisted_state.borrow().next();
+            // Failing to truncate the log is not fatal: replaying it skips the persisted blocks.
+            if let Err(err) = wal.log.truncate(ctx, next).await {
+                tracing::warn!("wal.truncate(): {err:#}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Prunes the persisted blocks with numbers lower than `first`.
+    /// The last persisted block is never pruned. The blocks are removed from the state
+    /// before they are removed from the persistent storage, so that they are not served anymore.
+    /// Fails if the persistent storage doesn't support pruning.
+    pub async fn prune(&self, ctx: &ctx::Ctx, first: validator::BlockNumber) -> ctx::Result<()> {
+        let first = {
+            let persisted_state = self.persisted_state.borrow();
+            let Some(last) = &persisted_state.last else {
+                return Ok(());
+            };
+            let first = first.min(last.header().number);
+            if first <= persisted_state.first {
+                return Ok(());
+            }
+            first
+        };
+        // `queued_state` goes first, since the readers consult it first.
+        self.queued_state.send_modify(|state| state.prune(first));
+        self.persisted_state.send_modify(|state| state.prune(first));
+        let t = metrics::PERSISTENT_BLOCK_STORE.prune_latency.start();
+        self.persistent
+            .prune(ctx, first)
+            .await
+            .wrap("persistent.prune()")?;
+        t.observe();
+        tracing::info!("pruned blocks before #{first}");
+        Ok(())
+    }
+
+    /// Switches the store to a new fork (regenesis) described by `genesis`.
+    /// All the blocks of the current fork preceding `genesis.fork.first_block` have to be
+    /// persisted already, and `genesis.fork.first_parent` has to be the hash of the last one.
+    /// Afterwards the store behaves as if it was reopened with `genesis`: the blocks of the
+    /// previous fork are not served anymore. `genesis` has to be persisted by the caller,
+    /// so that the store is reopened with it after a restart.
+    pub fn switch_fork(&self, genesis: validator::Genesis) -> anyhow::Result<()> {
+        let current = self.genesis();
+        anyhow::ensure!(
+            genesis.fork.number > current.fork.number,
+            "fork number {:?} is not greater than the current one {:?}",
+            genesis.fork.number,
+            current.fork.number
+        );
+        // The queue is locked, so that no block gets queued in the meantime.
+        let queue = self.queue.lock().unwrap();
+        {
+            let queued_state = self.queued_state.borrow();
+            let persisted_state = self.persisted_state.borrow();
+            anyhow::ensure!(
+                *queued_state == *persisted_state,
+                "blocks of the current fork are not persisted yet"
+            );
+            let last = queued_state.last.as_ref().context("store is empty")?;
+            anyhow::ensure!(
+                last.header().number.next() == genesis.fork.first_block,
+                "fork starts at {:?}, want {:?}",
+                genesis.fork.first_block,
+                last.header().number.next()
+            );
+            let parent = current.header_hash(last.header());
+            anyhow::ensure!(
+                genesis.fork.first_parent == Some(parent),
+                "fork parent mismatch: got {:?}, want {parent:?}",
+                genesis.fork.first_parent
+            );
+        }
+        let state = BlockStoreState {
+            first: genesis.fork.first_block,
+            last: None,
+            gaps: vec![],
+        };
+        self.genesis.send_replace(Arc::new(genesis));
+        self.queued_state.send_replace(state.clone());
+        self.persisted_state.send_replace(state);
+        drop(queue);
+        tracing::info!("switched to fork {:?}", self.genesis().fork);
+        Ok(())
+    }
+
     /// Subscribes to the `BlockStoreState` changes.
     /// Note that this state includes both queue AND stored blocks.
     pub fn subscribe(&self) -> sync::watch::Receiver<BlockStoreState> {
-        self.inner.borrow().queued_state.subscribe()
+        self.queued_state.subscribe()
+    }
+
+    /// State of the persisted blocks, i.e. without the queued ones.
+    pub fn persisted(&self) -> BlockStoreState {
+        self.persisted_state.borrow().clone()
     }
 
     fn scrape_metrics(&self) -> metrics::BlockStore {
         let m = metrics::BlockStore::default();
-        let inner = self.inner.borrow();
-        m.next_queued_block
-            .set(inner.queued_state.borrow().next().0);
-        m.next_persisted_block.set(inner.persisted_state.next().0);
+        m.next_queued_block.set(self.queued_state.borrow().next().0);
+        {
+            let persisted_state = self.persisted_state.borrow();
+            m.next_persisted_block.set(persisted_state.next().0);
+            m.first_block.set(persisted_state.first.0);
+            m.missing_blocks.set(persisted_state.missing_blocks());
+        }
+        {
+            let queue = self.queue.lock().unwrap();
+            m.queue_len.set(queue.blocks.len());
+            m.queue_payload_size
+                .set(queue.blocks.iter().map(|b| b.payload.0.len()).sum());
+        }
+        let cache = self.cache.lock().unwrap();
+        m.cache_len.set(cache.len());
+        m.cache_size.set(cache.size());
         m
     }
 }
@@ -0,0 +1,71 @@
+//! Read-only handle of the `BlockStore`.
+//! Components serving the blocks to the peers and clients (network, RPC servers) take
+//! a `BlockStoreReader` instead of the whole `BlockStore`, so that they can't queue blocks.
+use super::{BlockStore, BlockStoreState};
+use std::sync::Arc;
+use zksync_concurrency::{ctx, sync};
+use zksync_consensus_roles::validator;
+
+/// Cheaply cloneable read-only handle of a `BlockStore`.
+#[derive(Debug, Clone)]
+pub struct BlockStoreReader(Arc<BlockStore>);
+
+impl BlockStore {
+    /// Constructs a read-only handle of the store.
+    pub fn reader(self: &Arc<Self>) -> BlockStoreReader {
+        BlockStoreReader(self.clone())
+    }
+}
+
+impl BlockStoreReader {
+    /// Genesis specification of the store, see `BlockStore::genesis()`.
+    pub fn genesis(&self) -> Arc<validator::Genesis> {
+        self.0.genesis()
+    }
+
+    /// Current state of the store, including the queued blocks.
+    pub fn state(&self) -> BlockStoreState {
+        self.0.subscribe().borrow().clone()
+    }
+
+    /// Subscribes to the `BlockStoreState` changes, including the queued blocks.
+    pub fn subscribe(&self) -> sync::watch::Receiver<BlockStoreState> {
+        self.0.subscribe()
+    }
+
+    /// Fetches a block, see `BlockStore::block()`.
+    pub async fn block(
+        &self,
+        ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<Option<validator::FinalBlock>> {
+        self.0.block(ctx, number).await
+    }
+
+    /// Fetches the justification of a block, see `BlockStore::justification()`.
+    pub async fn justification(
+        &self,
+        ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<Option<validator::CommitQC>> {
+        self.0.justification(ctx, number).await
+    }
+
+    /// Waits until the given block is queued to be stored.
+    pub async fn wait_until_queued(
+        &self,
+        ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::OrCanceled<()> {
+        self.0.wait_until_queued(ctx, number).await
+    }
+
+    /// Waits until the given block is stored persistently.
+    pub async fn wait_until_persisted(
+        &self,
+        ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::OrCanceled<()> {
+        self.0.wait_until_persisted(ctx, number).await
+    }
+}
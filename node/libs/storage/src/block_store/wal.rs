@@ -0,0 +1,168 @@
+//! Write-ahead log of the blocks queued in the `BlockStore`.
+//! Blocks are kept in the queue until the `PersistentBlockStore` stores them, which may take
+//! a while. Without the log, the queued blocks are lost on crash and have to be fetched
+//! (or even finalized) again after restart.
+use anyhow::Context as _;
+use std::{
+    fs,
+    io::{Read as _, Write as _},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use zksync_concurrency::{ctx, scope};
+use zksync_consensus_roles::validator;
+
+/// Write-ahead log of the queued blocks.
+///
+/// Implementations **must** propagate context cancellation using [`StorageError::Canceled`].
+#[async_trait::async_trait]
+pub trait BlockQueueWal: std::fmt::Debug + Send + Sync {
+    /// Appends a block to the log.
+    /// Implementation should return only after the block is stored PERSISTENTLY.
+    async fn append(&self, ctx: &ctx::Ctx, block: &validator::FinalBlock) -> ctx::Result<()>;
+
+    /// Removes the blocks with numbers lower than `next` from the log.
+    /// `BlockStore` calls it whenever blocks get persisted, with `next` being the number
+    /// of the first block which is not persisted yet, so that the log stays bounded
+    /// by the size of the queue.
+    async fn truncate(&self, ctx: &ctx::Ctx, next: validator::BlockNumber) -> ctx::Result<()>;
+
+    /// Reads the blocks from the log, in the order of appending.
+    /// The log may contain duplicates and the blocks which have already been persisted.
+    async fn read(&self, ctx: &ctx::Ctx) -> ctx::Result<Vec<validator::FinalBlock>>;
+}
+
+/// `BlockQueueWal` backed by a single file, which is a sequence of frames `L ++ msg`,
+/// where `L` is a little endian encoding of `msg.len() as u32` and `msg` is a
+/// protobuf-encoded `roles.validator.FinalBlock`.
+#[derive(Debug)]
+pub struct FileWal {
+    path: PathBuf,
+    file: Mutex<fs::File>,
+}
+
+/// Encodes a block as a frame of the log.
+fn encode_frame(block: &validator::FinalBlock) -> anyhow::Result<Vec<u8>> {
+    let msg = zksync_protobuf::encode(block);
+    let len = u32::try_from(msg.len()).context("block too large")?;
+    Ok([&len.to_le_bytes()[..], &msg].concat())
+}
+
+/// Decodes the frames of the log, stopping at the first truncated or invalid frame.
+/// Returns the decoded blocks and the length of the valid prefix of `data`.
+fn decode_frames(mut data: &[u8]) -> (Vec<validator::FinalBlock>, usize) {
+    let total = data.len();
+    let mut blocks = vec![];
+    while data.len() >= 4 {
+        let len = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+        let Some(msg) = data[4..].get(..len) else {
+            break;
+        };
+        match zksync_protobuf::decode(msg) {
+            Ok(block) => blocks.push(block),
+            Err(err) => {
+                tracing::warn!("invalid frame in the write-ahead log: {err:#}");
+                break;
+            }
+        }
+        data = &data[4 + len..];
+    }
+    (blocks, total - data.len())
+}
+
+impl FileWal {
+    /// Opens the log file, creating it if missing.
+    pub async fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let file = scope::wait_blocking(|| Self::open_file(&path)).await?;
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    fn open_file(path: &Path) -> anyhow::Result<fs::File> {
+        fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("open({path:?})"))
+    }
+
+    fn read_all(&self) -> anyhow::Result<Vec<u8>> {
+        let mut data = vec![];
+        fs::File::open(&self.path)
+            .and_then(|mut f| f.read_to_end(&mut data))
+            .with_context(|| format!("read({:?})", self.path))?;
+        Ok(data)
+    }
+}
+
+#[async_trait::async_trait]
+impl BlockQueueWal for FileWal {
+    async fn append(&self, _ctx: &ctx::Ctx, block: &validator::FinalBlock) -> ctx::Result<()> {
+        let frame = encode_frame(block)?;
+        scope::wait_blocking(|| {
+            let mut file = self.file.lock().unwrap();
+            file.write_all(&frame)?;
+            file.sync_data()
+        })
+        .await
+        .with_context(|| format!("append({:?})", self.path))?;
+        Ok(())
+    }
+
+    async fn truncate(&self, _ctx: &ctx::Ctx, next: validator::BlockNumber) -> ctx::Result<()> {
+        scope::wait_blocking(|| {
+            let mut file = self.file.lock().unwrap();
+            let (blocks, _) = decode_frames(&self.read_all()?);
+            if blocks.iter().all(|b| b.number() >= next) {
+                return Ok(());
+            }
+            let mut data = vec![];
+            for b in blocks.iter().filter(|b| b.number() >= next) {
+                data.extend(encode_frame(b)?);
+            }
+            if data.is_empty() {
+                file.set_len(0)?;
+                file.sync_data()?;
+                return Ok(());
+            }
+            // The remaining blocks are written to a temporary file, which then atomically
+            // replaces the log, so that a crash in between doesn't lose them.
+            let tmp = self.path.with_extension("tmp");
+            let mut f = fs::File::create(&tmp).with_context(|| format!("create({tmp:?})"))?;
+            f.write_all(&data)?;
+            f.sync_data()?;
+            fs::rename(&tmp, &self.path).with_context(|| format!("rename({tmp:?})"))?;
+            *file = Self::open_file(&self.path)?;
+            anyhow::Ok(())
+        })
+        .await
+        .with_context(|| format!("truncate({:?})", self.path))?;
+        Ok(())
+    }
+
+    async fn read(&self, _ctx: &ctx::Ctx) -> ctx::Result<Vec<validator::FinalBlock>> {
+        let data = scope::wait_blocking(|| self.read_all()).await?;
+        let (blocks, valid) = decode_frames(&data);
+        // A truncated or corrupted frame at the end of the log is the result of a crash during
+        // `append()`. The log is truncated at the last valid frame, so that the node can start
+        // and the frames appended later are not corrupted.
+        if valid < data.len() {
+            tracing::warn!(
+                "dropping {} invalid bytes at the end of {:?}",
+                data.len() - valid,
+                self.path
+            );
+            scope::wait_blocking(|| {
+                let file = self.file.lock().unwrap();
+                file.set_len(valid as u64)?;
+                file.sync_data()
+            })
+            .await
+            .with_context(|| format!("set_len({:?})", self.path))?;
+        }
+        Ok(blocks)
+    }
+}
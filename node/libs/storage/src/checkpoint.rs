@@ -0,0 +1,155 @@
+//! Checkpoints of the replica state.
+//! The replica state is persisted on every view, together with the rest of the node's database,
+//! so restoring the database from a backup (or from a snapshot of another node) silently rolls
+//! the replica back to views in which it has already voted. If the replica voted again in those
+//! views, it could sign conflicting messages. A checkpoint is a small record of the last voted
+//! view, written periodically to a separate storage, which lets the replica detect such a rollback
+//! at startup.
+use crate::{proto, ReplicaState};
+use anyhow::Context as _;
+use std::{fmt, fs, io::Write as _, path::PathBuf};
+use zksync_concurrency::{ctx, scope};
+use zksync_consensus_roles::validator;
+use zksync_protobuf::{read_optional, required, ProtoFmt};
+
+/// Checkpoint of the replica state in a given epoch (i.e. fork of the chain).
+/// View numbers of different forks are not comparable, hence the checkpoints are keyed by epoch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplicaCheckpoint {
+    /// Fork of the chain.
+    pub epoch: validator::ForkNumber,
+    /// Last view in which the replica has voted.
+    pub view: validator::ViewNumber,
+    /// The highest commit quorum certificate known to the replica.
+    pub high_qc: Option<validator::CommitQC>,
+}
+
+impl ReplicaCheckpoint {
+    /// Checkpoint of the given replica state.
+    pub fn new(epoch: validator::ForkNumber, state: &ReplicaState) -> Self {
+        Self {
+            epoch,
+            view: state.view,
+            high_qc: state.high_qc.clone(),
+        }
+    }
+
+    /// Whether `state` is older than this checkpoint, i.e. the replica running with `state`
+    /// could vote again in the views in which it has already voted.
+    pub fn is_ahead_of(&self, state: &ReplicaState) -> bool {
+        let qc_view = |qc: Option<&validator::CommitQC>| qc.map(|qc| qc.view().number);
+        self.view > state.view || qc_view(self.high_qc.as_ref()) > qc_view(state.high_qc.as_ref())
+    }
+}
+
+/// Storage for [`ReplicaCheckpoint`]s.
+/// It should be kept separately from the [`ReplicaStore`](crate::ReplicaStore),
+/// so that it is not rolled back together with it.
+///
+/// Implementations **must** propagate context cancellation using [`ctx::Error::Canceled`].
+#[async_trait::async_trait]
+pub trait CheckpointStore: fmt::Debug + Send + Sync {
+    /// Gets the checkpoint of the given epoch, if any.
+    async fn checkpoint(
+        &self,
+        ctx: &ctx::Ctx,
+        epoch: validator::ForkNumber,
+    ) -> ctx::Result<Option<ReplicaCheckpoint>>;
+
+    /// Stores the checkpoint, replacing the previous checkpoint of the same epoch.
+    async fn set_checkpoint(
+        &self,
+        ctx: &ctx::Ctx,
+        checkpoint: &ReplicaCheckpoint,
+    ) -> ctx::Result<()>;
+}
+
+/// [`CheckpointStore`] keeping every epoch's checkpoint in a separate file
+/// `epoch_<n>` in a directory. Files are replaced atomically.
+#[derive(Debug)]
+pub struct FileCheckpointStore {
+    dir: PathBuf,
+}
+
+impl FileCheckpointStore {
+    /// Opens the checkpoint directory, creating it if missing.
+    pub async fn open(dir: PathBuf) -> anyhow::Result<Self> {
+        scope::wait_blocking(|| fs::create_dir_all(&dir).with_context(|| format!("{dir:?}")))
+            .await?;
+        Ok(Self { dir })
+    }
+
+    /// Path of the checkpoint file of the given epoch.
+    fn path(&self, epoch: validator::ForkNumber) -> PathBuf {
+        self.dir.join(format!("epoch_{}", epoch.0))
+    }
+}
+
+#[async_trait::async_trait]
+impl CheckpointStore for FileCheckpointStore {
+    async fn checkpoint(
+        &self,
+        _ctx: &ctx::Ctx,
+        epoch: validator::ForkNumber,
+    ) -> ctx::Result<Option<ReplicaCheckpoint>> {
+        let path = self.path(epoch);
+        let data = scope::wait_blocking(|| match fs::read(&path) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        })
+        .await
+        .with_context(|| format!("read({path:?})"))?;
+        let Some(data) = data else { return Ok(None) };
+        let checkpoint: ReplicaCheckpoint =
+            zksync_protobuf::decode(&data).with_context(|| format!("decode({path:?})"))?;
+        if checkpoint.epoch != epoch {
+            return Err(anyhow::format_err!(
+                "{path:?} contains the checkpoint of epoch {:?}",
+                checkpoint.epoch
+            )
+            .into());
+        }
+        Ok(Some(checkpoint))
+    }
+
+    async fn set_checkpoint(
+        &self,
+        _ctx: &ctx::Ctx,
+        checkpoint: &ReplicaCheckpoint,
+    ) -> ctx::Result<()> {
+        let path = self.path(checkpoint.epoch);
+        let tmp = path.with_extension("tmp");
+        let data = zksync_protobuf::encode(checkpoint);
+        scope::wait_blocking(|| {
+            let mut file = fs::File::create(&tmp)?;
+            file.write_all(&data)?;
+            file.sync_all()?;
+            fs::rename(&tmp, &path)?;
+            fs::File::open(&self.dir)?.sync_all()
+        })
+        .await
+        .with_context(|| format!("write({path:?})"))?;
+        Ok(())
+    }
+}
+
+impl ProtoFmt for ReplicaCheckpoint {
+    type Proto = proto::ReplicaCheckpoint;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            epoch: validator::ForkNumber(*required(&r.epoch).context("epoch")?),
+            view: validator::ViewNumber(*required(&r.view).context("view")?),
+            high_qc: read_optional(&r.high_qc).context("high_qc")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            epoch: Some(self.epoch.0),
+            view: Some(self.view.0),
+            high_qc: self.high_qc.as_ref().map(|x| x.build()),
+        }
+    }
+}
@@ -1,13 +1,21 @@
 //! Abstraction for persistent data storage.
 //! It provides schema-aware type-safe database access.
 mod block_store;
+mod checkpoint;
 pub mod proto;
 mod replica_store;
+mod sync_progress;
 pub mod testonly;
 #[cfg(test)]
 mod tests;
 
 pub use crate::{
-    block_store::{BlockStore, BlockStoreRunner, BlockStoreState, PersistentBlockStore},
+    block_store::{
+        AdmissionConfig, BlockQueueWal, BlockRange, BlockStore, BlockStoreConfig, BlockStoreReader,
+        BlockStoreRunner, BlockStoreState, BlockTimings, FileWal, PersistentBlockStore,
+        StorageLagging,
+    },
+    checkpoint::{CheckpointStore, FileCheckpointStore, ReplicaCheckpoint},
     replica_store::{Proposal, ReplicaState, ReplicaStore},
+    sync_progress::{FetchCursor, SyncProgress, SyncProgressStore},
 };
@@ -0,0 +1,101 @@
+//! Progress of the block syncing, persisted across restarts.
+//! The blocks are fetched from the peers which have advertised them, so after a restart
+//! the syncing can't continue until the peers advertise their states again. With the progress
+//! persisted, a node restarted in the middle of catching up resumes fetching right away.
+use crate::{proto, BlockRange, BlockStoreState};
+use anyhow::Context as _;
+use std::fmt;
+use zksync_concurrency::ctx;
+use zksync_consensus_roles::{node, validator};
+use zksync_protobuf::{read_optional, read_required, required, ProtoFmt};
+
+/// Range of blocks advertised by a peer, from which the fetching can be resumed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FetchCursor {
+    /// Peer which has advertised the blocks.
+    pub peer: node::PublicKey,
+    /// Advertised state of the peer.
+    pub state: BlockStoreState,
+}
+
+/// Progress of the block syncing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncProgress {
+    /// Cursors of the peers which have blocks missing in the local store.
+    pub cursors: Vec<FetchCursor>,
+}
+
+/// Storage for [`SyncProgress`].
+///
+/// Implementations **must** propagate context cancellation using [`ctx::Error::Canceled`].
+#[async_trait::async_trait]
+pub trait SyncProgressStore: fmt::Debug + Send + Sync {
+    /// Gets the progress, or the default (empty) progress if none is stored.
+    async fn progress(&self, ctx: &ctx::Ctx) -> ctx::Result<SyncProgress>;
+
+    /// Stores the progress, replacing the previous one.
+    async fn set_progress(&self, ctx: &ctx::Ctx, progress: &SyncProgress) -> ctx::Result<()>;
+}
+
+impl ProtoFmt for FetchCursor {
+    type Proto = proto::FetchCursor;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            peer: read_required(&r.peer).context("peer")?,
+            state: BlockStoreState {
+                first: validator::BlockNumber(*required(&r.first).context("first")?),
+                last: read_optional(&r.last).context("last")?,
+                gaps: r
+                    .gaps
+                    .iter()
+                    .map(|g| {
+                        Ok(BlockRange {
+                            first: validator::BlockNumber(*required(&g.first).context("first")?),
+                            last: validator::BlockNumber(*required(&g.last).context("last")?),
+                        })
+                    })
+                    .collect::<anyhow::Result<_>>()
+                    .context("gaps")?,
+            },
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            peer: Some(self.peer.build()),
+            first: Some(self.state.first.0),
+            last: self.state.last.as_ref().map(|x| x.build()),
+            gaps: self
+                .state
+                .gaps
+                .iter()
+                .map(|g| proto::BlockRange {
+                    first: Some(g.first.0),
+                    last: Some(g.last.0),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl ProtoFmt for SyncProgress {
+    type Proto = proto::SyncProgress;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            cursors: r
+                .cursors
+                .iter()
+                .map(ProtoFmt::read)
+                .collect::<Result<_, _>>()
+                .context("cursors")?,
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            cursors: self.cursors.iter().map(|c| c.build()).collect(),
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Block store serving a deterministic chain of blocks, generated on demand.
+//! It allows to test syncing from a peer with a long chain (say 1M blocks),
+//! without materializing the whole chain in memory.
+use crate::PersistentBlockStore;
+use anyhow::Context as _;
+use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+use std::sync::{Arc, Mutex};
+use zksync_concurrency::ctx;
+use zksync_consensus_roles::validator;
+
+/// Every `CHECKPOINT_INTERVAL`-th parent hash is cached, so that a header
+/// can be recomputed by replaying at most that many headers.
+const CHECKPOINT_INTERVAL: u64 = 1024;
+
+#[derive(Debug)]
+struct BlockStoreInner {
+    genesis: validator::Genesis,
+    keys: Vec<validator::SecretKey>,
+    seed: u64,
+    /// Number of the generated blocks.
+    len: u64,
+    /// `checkpoints[k]` is the parent hash of the block `k * CHECKPOINT_INTERVAL` of the chain.
+    checkpoints: Mutex<Vec<Option<validator::BlockHeaderHash>>>,
+    /// Blocks stored on top of the generated chain.
+    stored: Mutex<Vec<validator::FinalBlock>>,
+}
+
+/// Block store starting with `len` blocks generated from a `seed`.
+/// The blocks are finalized by all the validators of the setup and are generated
+/// whenever they are read, so only the blocks stored on top of the chain are kept in memory.
+#[derive(Clone, Debug)]
+pub struct BlockStore(Arc<BlockStoreInner>);
+
+impl BlockStore {
+    /// New `BlockStore` with `len` blocks of the `setup` genesis.
+    /// Stores constructed with the same arguments serve the same blocks.
+    pub fn new(setup: &validator::testonly::Setup, seed: u64, len: u64) -> Self {
+        Self(Arc::new(BlockStoreInner {
+            genesis: setup.genesis.clone(),
+            keys: setup.keys.clone(),
+            seed,
+            len,
+            checkpoints: Mutex::new(vec![setup.genesis.fork.first_parent]),
+            stored: Mutex::default(),
+        }))
+    }
+}
+
+impl BlockStoreInner {
+    /// Payload of the `i`-th block of the chain.
+    /// Payloads are kept small, so that replaying the headers is cheap.
+    fn payload(&self, i: u64) -> validator::Payload {
+        let mut seed = [0; 32];
+        seed[..8].copy_from_slice(&self.seed.to_le_bytes());
+        seed[8..16].copy_from_slice(&i.to_le_bytes());
+        validator::Payload(StdRng::from_seed(seed).gen::<[u8; 32]>().to_vec())
+    }
+
+    /// Header of the `i`-th block of the chain, given its parent.
+    fn make_header(
+        &self,
+        i: u64,
+        parent: Option<validator::BlockHeaderHash>,
+        payload: &validator::Payload,
+    ) -> validator::BlockHeader {
+        let number = validator::BlockNumber(self.genesis.fork.first_block.0 + i);
+        validator::BlockHeader {
+            parent,
+            number,
+            payload: payload.hash(),
+            payload_root: self
+                .genesis
+                .protocol_version_at(number)
+                .commits_payload_root()
+                .then(|| payload.root()),
+            key_rotation: None,
+            protocol_upgrade: None,
+        }
+    }
+
+    /// Replays `n > 0` headers of the chain, starting with the `first`-th block,
+    /// whose parent is `parent`. Returns the last replayed header.
+    fn replay(
+        &self,
+        first: u64,
+        mut parent: Option<validator::BlockHeaderHash>,
+        n: u64,
+    ) -> validator::BlockHeader {
+        let mut header = None;
+        for i in first..first + n {
+            let h = self.make_header(i, parent, &self.payload(i));
+            parent = Some(self.genesis.header_hash(&h));
+            header = Some(h);
+        }
+        header.unwrap()
+    }
+
+    /// Header of the `i`-th block of the chain.
+    fn header(&self, i: u64) -> validator::BlockHeader {
+        let k = (i / CHECKPOINT_INTERVAL) as usize;
+        let parent = {
+            let mut checkpoints = self.checkpoints.lock().unwrap();
+            while checkpoints.len() <= k {
+                let first = (checkpoints.len() - 1) as u64 * CHECKPOINT_INTERVAL;
+                let last = self.replay(first, *checkpoints.last().unwrap(), CHECKPOINT_INTERVAL);
+                checkpoints.push(Some(self.genesis.header_hash(&last)));
+            }
+            checkpoints[k]
+        };
+        self.replay(
+            k as u64 * CHECKPOINT_INTERVAL,
+            parent,
+            i % CHECKPOINT_INTERVAL + 1,
+        )
+    }
+
+    /// Justification of the `i`-th block of the chain, signed by all the validators.
+    fn justification(&self, i: u64) -> validator::CommitQC {
+        let msg = validator::ReplicaCommit {
+            view: validator::View {
+                protocol_version: self.genesis.protocol_version,
+                fork: self.genesis.fork.number,
+                number: validator::ViewNumber(i),
+            },
+            proposal: self.header(i),
+        };
+        let mut qc = validator::CommitQC::new(msg, &self.genesis);
+        for key in &self.keys {
+            qc.add(&key.sign_msg(qc.message.clone()), &self.genesis);
+        }
+        qc
+    }
+
+    /// Index of the block `number` in the chain.
+    fn index(&self, number: validator::BlockNumber) -> anyhow::Result<u64> {
+        let i = number
+            .0
+            .checked_sub(self.genesis.fork.first_block.0)
+            .context("not found")?;
+        anyhow::ensure!(
+            i < self.len + self.stored.lock().unwrap().len() as u64,
+            "not found"
+        );
+        Ok(i)
+    }
+}
+
+#[async_trait::async_trait]
+impl PersistentBlockStore for BlockStore {
+    async fn genesis(&self, _ctx: &ctx::Ctx) -> ctx::Result<validator::Genesis> {
+        // Generated blocks don't commit to key rotations nor protocol upgrades,
+        // only the stored ones may.
+        let mut genesis = self.0.genesis.clone();
+        for block in self.0.stored.lock().unwrap().iter() {
+            if let Some(cert) = &block.key_rotation {
+                genesis = genesis
+                    .with_key_rotation(cert.clone())
+                    .context("with_key_rotation()")?;
+            }
+            if let Some(qc) = &block.protocol_upgrade {
+                genesis = genesis
+                    .with_protocol_upgrade(qc.clone(), block.number())
+                    .context("with_protocol_upgrade()")?;
+            }
+        }
+        Ok(genesis)
+    }
+
+    async fn last(&self, _ctx: &ctx::Ctx) -> ctx::Result<Option<validator::CommitQC>> {
+        if let Some(b) = self.0.stored.lock().unwrap().last() {
+            return Ok(Some(b.justification.clone()));
+        }
+        Ok(self.0.len.checked_sub(1).map(|i| self.0.justification(i)))
+    }
+
+    async fn block(
+        &self,
+        _ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<validator::FinalBlock> {
+        let i = self.0.index(number)?;
+        if let Some(j) = i.checked_sub(self.0.len) {
+            return Ok(self.0.stored.lock().unwrap()[j as usize].clone());
+        }
+        Ok(validator::FinalBlock {
+            payload: self.0.payload(i),
+            justification: self.0.justification(i),
+            key_rotation: None,
+            protocol_upgrade: None,
+        })
+    }
+
+    async fn justification(
+        &self,
+        _ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<validator::CommitQC> {
+        let i = self.0.index(number)?;
+        if let Some(j) = i.checked_sub(self.0.len) {
+            return Ok(self.0.stored.lock().unwrap()[j as usize]
+                .justification
+                .clone());
+        }
+        Ok(self.0.justification(i))
+    }
+
+    async fn store_next_block(
+        &self,
+        _ctx: &ctx::Ctx,
+        block: &validator::FinalBlock,
+    ) -> ctx::Result<()> {
+        let mut stored = self.0.stored.lock().unwrap();
+        let got = block.header().number;
+        let want = validator::BlockNumber(
+            self.0.genesis.fork.first_block.0 + self.0.len + stored.len() as u64,
+        );
+        if got != want {
+            return Err(anyhow::anyhow!("got block {got:?}, while expected {want:?}").into());
+        }
+        stored.push(block.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+//! Block store delegating to the in-memory storage, with hooks for the tests
+//! (failures, delays, gated writes and the like).
+use super::in_memory;
+use crate::PersistentBlockStore;
+use anyhow::Context as _;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use zksync_concurrency::{ctx, sync, time};
+use zksync_consensus_roles::validator;
+
+/// Statistics of the `PersistentBlockStore::block()` calls.
+#[derive(Debug, Default)]
+pub struct ReadStats {
+    /// Total number of calls.
+    pub reads: AtomicUsize,
+    /// Number of calls in progress.
+    pub inflight: AtomicUsize,
+    /// Max observed number of calls in progress.
+    pub max_inflight: AtomicUsize,
+}
+
+/// Hooks of the `BlockStore`. Default hooks don't change the behavior of the inner store.
+#[derive(Debug, Default)]
+pub struct Hooks {
+    /// Delay of every `block()` call.
+    pub read_delay: time::Duration,
+    /// Statistics of the `block()` calls.
+    pub read_stats: Arc<ReadStats>,
+    /// If set, every stored block consumes a permit, and the store waits for it.
+    pub store_permits: Option<Arc<sync::Semaphore>>,
+    /// Number of the next store calls to fail, before storing anything.
+    pub store_failures: AtomicUsize,
+    /// If set, the next store call fails after storing half of its blocks.
+    pub fail_midway: AtomicBool,
+    /// Sizes of the batches passed to the store calls.
+    pub batches: Arc<Mutex<Vec<usize>>>,
+    /// If set, the payloads are kept in this separate (blob) storage,
+    /// and the inner store keeps the blocks with their payloads stripped.
+    pub payloads: Option<Arc<Mutex<HashMap<validator::BlockNumber, validator::Payload>>>>,
+}
+
+/// Block store delegating to the in-memory store, with `Hooks` applied.
+#[derive(Debug)]
+pub struct BlockStore {
+    inner: in_memory::BlockStore,
+    hooks: Hooks,
+}
+
+impl BlockStore {
+    /// Wraps the in-memory store.
+    pub fn new(inner: in_memory::BlockStore, hooks: Hooks) -> Self {
+        Self { inner, hooks }
+    }
+
+    /// Stores a single block in the inner store.
+    async fn store_one(&self, ctx: &ctx::Ctx, block: &validator::FinalBlock) -> ctx::Result<()> {
+        if let Some(permits) = &self.hooks.store_permits {
+            sync::acquire(ctx, permits).await?.forget();
+        }
+        let Some(payloads) = &self.hooks.payloads else {
+            return self.inner.store_next_block(ctx, block).await;
+        };
+        // The payload has to be already stored.
+        assert!(payloads.lock().unwrap().contains_key(&block.number()));
+        let block = validator::FinalBlock {
+            payload: validator::Payload(vec![]),
+            ..block.clone()
+        };
+        self.inner.store_next_block(ctx, &block).await
+    }
+}
+
+#[async_trait::async_trait]
+impl PersistentBlockStore for BlockStore {
+    async fn genesis(&self, ctx: &ctx::Ctx) -> ctx::Result<validator::Genesis> {
+        self.inner.genesis(ctx).await
+    }
+
+    async fn last(&self, ctx: &ctx::Ctx) -> ctx::Result<Option<validator::CommitQC>> {
+        self.inner.last(ctx).await
+    }
+
+    async fn block(
+        &self,
+        ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<validator::FinalBlock> {
+        if self.hooks.payloads.is_some() {
+            return Err(anyhow::anyhow!("blocks have to be stitched together").into());
+        }
+        let s = &self.hooks.read_stats;
+        s.reads.fetch_add(1, Ordering::SeqCst);
+        let inflight = s.inflight.fetch_add(1, Ordering::SeqCst) + 1;
+        s.max_inflight.fetch_max(inflight, Ordering::SeqCst);
+        let res = async {
+            ctx.sleep(self.hooks.read_delay).await?;
+            self.inner.block(ctx, number).await
+        }
+        .await;
+        s.inflight.fetch_sub(1, Ordering::SeqCst);
+        res
+    }
+
+    async fn justification(
+        &self,
+        ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<validator::CommitQC> {
+        self.inner.justification(ctx, number).await
+    }
+
+    fn stores_payloads_separately(&self) -> bool {
+        self.hooks.payloads.is_some()
+    }
+
+    async fn payload(
+        &self,
+        _ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<validator::Payload> {
+        let payloads = self
+            .hooks
+            .payloads
+            .as_ref()
+            .context("payloads not stored")?;
+        Ok(payloads
+            .lock()
+            .unwrap()
+            .get(&number)
+            .cloned()
+            .context("payload not found")?)
+    }
+
+    async fn store_payloads(
+        &self,
+        _ctx: &ctx::Ctx,
+        blocks: &[validator::FinalBlock],
+    ) -> ctx::Result<()> {
+        let payloads = self
+            .hooks
+            .payloads
+            .as_ref()
+            .context("payloads not stored")?;
+        let mut payloads = payloads.lock().unwrap();
+        for block in blocks {
+            payloads.insert(block.number(), block.payload.clone());
+        }
+        Ok(())
+    }
+
+    async fn store_next_block(
+        &self,
+        ctx: &ctx::Ctx,
+        block: &validator::FinalBlock,
+    ) -> ctx::Result<()> {
+        self.store_blocks(ctx, std::slice::from_ref(block)).await
+    }
+
+    async fn store_blocks(
+        &self,
+        ctx: &ctx::Ctx,
+        blocks: &[validator::FinalBlock],
+    ) -> ctx::Result<()> {
+        self.hooks.batches.lock().unwrap().push(blocks.len());
+        let failing = self
+            .hooks
+            .store_failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if failing {
+            return Err(anyhow::anyhow!("transient failure").into());
+        }
+        if self.hooks.fail_midway.swap(false, Ordering::SeqCst) {
+            for block in &blocks[..blocks.len() / 2] {
+                self.store_one(ctx, block).await?;
+            }
+            return Err(anyhow::anyhow!("failure in the middle of a batch").into());
+        }
+        for block in blocks {
+            self.store_one(ctx, block).await?;
+        }
+        Ok(())
+    }
+}
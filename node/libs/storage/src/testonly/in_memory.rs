@@ -1,17 +1,42 @@
 //! In-memory storage implementation.
-use crate::{PersistentBlockStore, ReplicaState};
+use crate::{PersistentBlockStore, ReplicaCheckpoint, ReplicaState, SyncProgress};
 use anyhow::Context as _;
 use std::{
-    collections::VecDeque,
+    collections::{BTreeMap, VecDeque},
     sync::{Arc, Mutex},
 };
 use zksync_concurrency::ctx;
 use zksync_consensus_roles::validator;
 
+/// Blocks stored in memory. Justifications and payloads are kept separately,
+/// following the recommended storage layout.
+#[derive(Debug, Default)]
+struct Blocks {
+    justifications: VecDeque<validator::CommitQC>,
+    payloads: VecDeque<validator::Payload>,
+    /// Key rotations committed by the blocks. They are not pruned.
+    key_rotations: BTreeMap<validator::BlockNumber, validator::KeyRotationCert>,
+    /// Protocol upgrades committed by the blocks. They are not pruned.
+    protocol_upgrades: BTreeMap<validator::BlockNumber, validator::ProtocolUpgradeQC>,
+}
+
+impl Blocks {
+    /// Index of the block `number` in the stored range.
+    fn index(&self, number: validator::BlockNumber) -> anyhow::Result<usize> {
+        let front = self.justifications.front().context("not found")?;
+        let idx = number
+            .0
+            .checked_sub(front.header().number.0)
+            .context("not found")? as usize;
+        anyhow::ensure!(idx < self.justifications.len(), "not found");
+        Ok(idx)
+    }
+}
+
 #[derive(Debug)]
 struct BlockStoreInner {
     genesis: validator::Genesis,
-    blocks: Mutex<VecDeque<validator::FinalBlock>>,
+    blocks: Mutex<Blocks>,
 }
 
 /// In-memory block store.
@@ -22,6 +47,14 @@ pub struct BlockStore(Arc<BlockStoreInner>);
 #[derive(Clone, Debug, Default)]
 pub struct ReplicaStore(Arc<Mutex<ReplicaState>>);
 
+/// In-memory checkpoint store.
+#[derive(Clone, Debug, Default)]
+pub struct CheckpointStore(Arc<Mutex<BTreeMap<validator::ForkNumber, ReplicaCheckpoint>>>);
+
+/// In-memory sync progress store.
+#[derive(Clone, Debug, Default)]
+pub struct SyncProgressStore(Arc<Mutex<SyncProgress>>);
+
 impl BlockStore {
     /// New In-memory `BlockStore`.
     pub fn new(genesis: validator::Genesis) -> Self {
@@ -35,17 +68,41 @@ impl BlockStore {
 #[async_trait::async_trait]
 impl PersistentBlockStore for BlockStore {
     async fn genesis(&self, _ctx: &ctx::Ctx) -> ctx::Result<validator::Genesis> {
-        Ok(self.0.genesis.clone())
+        let mut genesis = self.0.genesis.clone();
+        let blocks = self.0.blocks.lock().unwrap();
+        for cert in blocks.key_rotations.values() {
+            genesis = genesis
+                .with_key_rotation(cert.clone())
+                .context("with_key_rotation()")?;
+        }
+        for (number, qc) in &blocks.protocol_upgrades {
+            genesis = genesis
+                .with_protocol_upgrade(qc.clone(), *number)
+                .context("with_protocol_upgrade()")?;
+        }
+        Ok(genesis)
     }
 
     async fn last(&self, _ctx: &ctx::Ctx) -> ctx::Result<Option<validator::CommitQC>> {
-        Ok(self
-            .0
-            .blocks
-            .lock()
-            .unwrap()
-            .back()
-            .map(|b| b.justification.clone()))
+        Ok(self.0.blocks.lock().unwrap().justifications.back().cloned())
+    }
+
+    async fn first(&self, _ctx: &ctx::Ctx) -> ctx::Result<Option<validator::BlockNumber>> {
+        let blocks = self.0.blocks.lock().unwrap();
+        Ok(blocks.justifications.front().map(|qc| qc.header().number))
+    }
+
+    async fn prune(&self, _ctx: &ctx::Ctx, first: validator::BlockNumber) -> ctx::Result<()> {
+        let mut blocks = self.0.blocks.lock().unwrap();
+        while blocks
+            .justifications
+            .front()
+            .is_some_and(|qc| qc.header().number < first)
+        {
+            blocks.justifications.pop_front();
+            blocks.payloads.pop_front();
+        }
+        Ok(())
     }
 
     async fn block(
@@ -54,12 +111,23 @@ impl PersistentBlockStore for BlockStore {
         number: validator::BlockNumber,
     ) -> ctx::Result<validator::FinalBlock> {
         let blocks = self.0.blocks.lock().unwrap();
-        let front = blocks.front().context("not found")?;
-        let idx = number
-            .0
-            .checked_sub(front.header().number.0)
-            .context("not found")?;
-        Ok(blocks.get(idx as usize).context("not found")?.clone())
+        let idx = blocks.index(number)?;
+        Ok(validator::FinalBlock {
+            payload: blocks.payloads[idx].clone(),
+            justification: blocks.justifications[idx].clone(),
+            key_rotation: blocks.key_rotations.get(&number).cloned(),
+            protocol_upgrade: blocks.protocol_upgrades.get(&number).cloned(),
+        })
+    }
+
+    async fn justification(
+        &self,
+        _ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<validator::CommitQC> {
+        let blocks = self.0.blocks.lock().unwrap();
+        let idx = blocks.index(number)?;
+        Ok(blocks.justifications[idx].clone())
     }
 
     async fn store_next_block(
@@ -69,13 +137,20 @@ impl PersistentBlockStore for BlockStore {
     ) -> ctx::Result<()> {
         let mut blocks = self.0.blocks.lock().unwrap();
         let got = block.header().number;
-        if let Some(last) = blocks.back() {
+        if let Some(last) = blocks.justifications.back() {
             let want = last.header().number.next();
             if got != want {
                 return Err(anyhow::anyhow!("got block {got:?}, while expected {want:?}").into());
             }
         }
-        blocks.push_back(block.clone());
+        blocks.justifications.push_back(block.justification.clone());
+        blocks.payloads.push_back(block.payload.clone());
+        if let Some(cert) = &block.key_rotation {
+            blocks.key_rotations.insert(got, cert.clone());
+        }
+        if let Some(qc) = &block.protocol_upgrade {
+            blocks.protocol_upgrades.insert(got, qc.clone());
+        }
         Ok(())
     }
 }
@@ -91,3 +166,38 @@ impl crate::ReplicaStore for ReplicaStore {
         Ok(())
     }
 }
+
+#[async_trait::async_trait]
+impl crate::CheckpointStore for CheckpointStore {
+    async fn checkpoint(
+        &self,
+        _ctx: &ctx::Ctx,
+        epoch: validator::ForkNumber,
+    ) -> ctx::Result<Option<ReplicaCheckpoint>> {
+        Ok(self.0.lock().unwrap().get(&epoch).cloned())
+    }
+
+    async fn set_checkpoint(
+        &self,
+        _ctx: &ctx::Ctx,
+        checkpoint: &ReplicaCheckpoint,
+    ) -> ctx::Result<()> {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(checkpoint.epoch, checkpoint.clone());
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::SyncProgressStore for SyncProgressStore {
+    async fn progress(&self, _ctx: &ctx::Ctx) -> ctx::Result<SyncProgress> {
+        Ok(self.0.lock().unwrap().clone())
+    }
+
+    async fn set_progress(&self, _ctx: &ctx::Ctx, progress: &SyncProgress) -> ctx::Result<()> {
+        *self.0.lock().unwrap() = progress.clone();
+        Ok(())
+    }
+}
@@ -0,0 +1,1147 @@
+use super::*;
+use crate::{
+    block_store::BlockLatency,
+    testonly::{hooked, new_store},
+    CheckpointStore as _, ReplicaCheckpoint, ReplicaState, SyncProgress,
+};
+use rand::Rng as _;
+use std::sync::{atomic::Ordering, Arc};
+use zksync_concurrency::{ctx, scope, sync, testonly::abort_on_panic, time};
+use zksync_consensus_roles::validator::{self, testonly::Setup};
+
+mod state_machine;
+
+#[tokio::test]
+async fn test_inmemory_block_store() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 3);
+    setup.push_blocks(rng, 5);
+
+    let store = &testonly::in_memory::BlockStore::new(setup.genesis.clone());
+    let mut want = vec![];
+    for block in &setup.blocks {
+        store.store_next_block(ctx, block).await.unwrap();
+        want.push(block.clone());
+        assert_eq!(want, testonly::dump(ctx, store).await);
+    }
+}
+
+#[tokio::test]
+async fn test_generated_block_store() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let setup = Setup::new(rng, 1);
+    let seed = rng.gen();
+    let block = |i| validator::BlockNumber(setup.genesis.fork.first_block.0 + i);
+    let persistent = testonly::generated::BlockStore::new(&setup, seed, 1500);
+    let (store, runner) = BlockStore::new(ctx, Box::new(persistent.clone()))
+        .await
+        .unwrap();
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(runner.run(ctx));
+        assert_eq!(store.subscribe().borrow().first, block(0));
+        assert_eq!(store.subscribe().borrow().next(), block(1500));
+        testonly::verify(ctx, &store).await.unwrap();
+        Ok(())
+    })
+    .await
+    .unwrap();
+
+    // The chain is deterministic and its blocks can be read in any order.
+    let other = testonly::generated::BlockStore::new(&setup, seed, 1500);
+    for i in [1400, 3, 1024, 1023] {
+        assert_eq!(
+            persistent.block(ctx, block(i)).await.unwrap(),
+            other.block(ctx, block(i)).await.unwrap()
+        );
+    }
+    assert!(other.block(ctx, block(1500)).await.is_err());
+}
+
+#[test]
+fn test_schema_encode_decode() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    zksync_protobuf::testonly::test_encode_random::<ReplicaState>(rng);
+    zksync_protobuf::testonly::test_encode_random::<ReplicaCheckpoint>(rng);
+    zksync_protobuf::testonly::test_encode_random::<SyncProgress>(rng);
+}
+
+#[test]
+fn test_state_with_gaps() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    setup.push_blocks(rng, 10);
+    let n = |i: usize| setup.blocks[i].number();
+    let mut state = BlockStoreState {
+        first: n(0),
+        last: Some(setup.blocks[9].justification.clone()),
+        gaps: vec![
+            BlockRange {
+                first: n(2),
+                last: n(3),
+            },
+            BlockRange {
+                first: n(5),
+                last: n(7),
+            },
+        ],
+    };
+    state.verify_gaps().unwrap();
+    let stored: Vec<_> = (0..10).filter(|i| state.contains(n(*i))).collect();
+    assert_eq!(vec![0, 1, 4, 8, 9], stored);
+    assert_eq!(n(9).next(), state.next());
+    let ranges = [(0, 1), (4, 4), (8, 9)].map(|(a, b)| BlockRange {
+        first: n(a),
+        last: n(b),
+    });
+    assert_eq!(ranges.to_vec(), state.ranges());
+    assert_eq!(5, state.missing_blocks());
+
+    // Overlapping gaps.
+    state.gaps[1].first = n(3);
+    assert!(state.verify_gaps().is_err());
+    // Gap covering the last block.
+    state.gaps[1] = BlockRange {
+        first: n(5),
+        last: n(9),
+    };
+    assert!(state.verify_gaps().is_err());
+    // Gap covering the first block.
+    state.gaps = vec![BlockRange {
+        first: n(0),
+        last: n(1),
+    }];
+    assert!(state.verify_gaps().is_err());
+}
+
+#[tokio::test]
+async fn test_state_updates() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    setup.push_blocks(rng, 1);
+    let (store, runner) = new_store(ctx, &setup.genesis).await;
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(runner.run(ctx));
+        let sub = &mut store.subscribe();
+        let state = sub.borrow().clone();
+        assert_eq!(state.first, setup.genesis.fork.first_block);
+        assert_eq!(state.last, None);
+
+        store
+            .queue_block(ctx, setup.blocks[0].clone())
+            .await
+            .unwrap();
+
+        let state = sync::wait_for(ctx, sub, |state| {
+            state.last.as_ref() == Some(&setup.blocks[0].justification)
+        })
+        .await?
+        .clone();
+        assert_eq!(state.first, setup.blocks[0].header().number);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_try_queue_block() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    let mut other = setup.clone();
+    setup.push_blocks(rng, 2);
+    other.push_blocks(rng, 1);
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        // Concurrent calls queue the block exactly once.
+        let tasks: Vec<_> = (0..4)
+            .map(|_| s.spawn(store.try_queue_block(ctx, setup.blocks[1].clone())))
+            .collect();
+        assert!(store.try_queue_block(ctx, setup.blocks[0].clone()).await?);
+        let mut queued = 0;
+        for task in tasks {
+            queued += usize::from(task.join(ctx).await?);
+        }
+        assert_eq!(queued, 1);
+        // A conflicting block is not queued once a block with the same number is.
+        assert!(!store.try_queue_block(ctx, other.blocks[0].clone()).await?);
+        let got = store.justification(ctx, setup.blocks[0].number()).await?;
+        assert_eq!(got.as_ref(), Some(&setup.blocks[0].justification));
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_drain_on_shutdown() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    setup.push_blocks(rng, 5);
+    let persistent = testonly::in_memory::BlockStore::new(setup.genesis.clone());
+    let (store, runner) = BlockStore::new(ctx, Box::new(persistent.clone()))
+        .await
+        .unwrap();
+    for block in &setup.blocks {
+        store.queue_block(ctx, block.clone()).await.unwrap();
+    }
+    // Run the runner with an already canceled context:
+    // it should still persist all the queued blocks before returning.
+    let canceled = &ctx.with_timeout(time::Duration::ZERO);
+    canceled.canceled().await;
+    runner.run(canceled).await.unwrap();
+    assert_eq!(setup.blocks, testonly::dump(ctx, &persistent).await);
+    store.flush(ctx).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_flush() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    setup.push_blocks(rng, 3);
+    let persistent = testonly::in_memory::BlockStore::new(setup.genesis.clone());
+    let (store, runner) = BlockStore::new(ctx, Box::new(persistent.clone()))
+        .await
+        .unwrap();
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(runner.run(ctx));
+        for block in &setup.blocks {
+            store.queue_block(ctx, block.clone()).await.unwrap();
+        }
+        store.flush(ctx).await?;
+        assert_eq!(setup.blocks, testonly::dump(ctx, &persistent).await);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_prune() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    setup.push_blocks(rng, 5);
+    let persistent = testonly::in_memory::BlockStore::new(setup.genesis.clone());
+    let (store, runner) = BlockStore::new(ctx, Box::new(persistent.clone()))
+        .await
+        .unwrap();
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(runner.run(ctx));
+        for block in &setup.blocks {
+            store.queue_block(ctx, block.clone()).await.unwrap();
+        }
+        store.flush(ctx).await?;
+
+        store.prune(ctx, setup.blocks[2].number()).await.unwrap();
+        assert_eq!(store.subscribe().borrow().first, setup.blocks[2].number());
+        assert_eq!(None, store.block(ctx, setup.blocks[1].number()).await?);
+        assert_eq!(
+            Some(&setup.blocks[2]),
+            store.block(ctx, setup.blocks[2].number()).await?.as_ref()
+        );
+
+        // The last block is never pruned.
+        let last = setup.blocks.last().unwrap().number();
+        store.prune(ctx, last.next()).await.unwrap();
+        assert_eq!(store.subscribe().borrow().first, last);
+        Ok(())
+    })
+    .await
+    .unwrap();
+
+    // Pruning is persistent.
+    let (store, _) = BlockStore::new(ctx, Box::new(persistent)).await.unwrap();
+    let state = store.subscribe().borrow().clone();
+    assert_eq!(state.first, setup.blocks.last().unwrap().number());
+    assert_eq!(
+        state.last.as_ref(),
+        Some(&setup.blocks.last().unwrap().justification)
+    );
+}
+
+#[tokio::test]
+async fn test_key_rotation_committed_by_block() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 4);
+    let (new_key, cert) = setup.key_rotation(rng, 0, validator::ViewNumber(10), 5);
+    setup.push_blocks(rng, 1);
+    setup.push_block_with_key_rotation(rng.gen(), Some(cert.clone()));
+    setup.push_blocks(rng, 2);
+    let persistent = testonly::in_memory::BlockStore::new(setup.genesis.clone());
+    let (store, runner) = BlockStore::new(ctx, Box::new(persistent.clone()))
+        .await
+        .unwrap();
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(runner.run(ctx));
+        store.queue_block(ctx, setup.blocks[0].clone()).await?;
+        assert!(!store.genesis().accepts_validator_key(&new_key.public()));
+        store.queue_block(ctx, setup.blocks[1].clone()).await?;
+        assert!(store.genesis().accepts_validator_key(&new_key.public()));
+        for block in &setup.blocks[2..] {
+            store.queue_block(ctx, block.clone()).await?;
+        }
+        store.flush(ctx).await?;
+        // The rotation is pruned together with the block, but it stays in the genesis.
+        store.prune(ctx, setup.blocks[2].number()).await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+
+    // The rotation is recovered from the persistent store.
+    let (store, _) = BlockStore::new(ctx, Box::new(persistent)).await.unwrap();
+    assert!(store.genesis().key_rotations.contains(&cert));
+    assert_eq!(setup.genesis.hash(), store.genesis().hash());
+
+    // A block committing the same rotation again is rejected.
+    let mut setup2 = setup.clone();
+    setup2.push_block_with_key_rotation(rng.gen(), Some(cert));
+    let block = setup2.blocks.last().unwrap().clone();
+    assert!(store.queue_block(ctx, block).await.is_err());
+}
+
+/// Testing that the chains hashed in separate domains (with keccak256 and with blake2b-256)
+/// are stored and verified, including a chain upgraded to the latest hashing scheme.
+#[tokio::test]
+async fn test_domain_separated_chains() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setups = vec![];
+    for version in [
+        validator::ProtocolVersion::DOMAIN_SEPARATED_HASHING,
+        validator::ProtocolVersion::BLAKE2_HASHING,
+    ] {
+        let mut setup = Setup::new(rng, 3);
+        setup.set_protocol_version(version);
+        setup.push_blocks(rng, 5);
+        setups.push(setup);
+    }
+    let mut setup = Setup::new(rng, 3);
+    let activation = validator::BlockNumber(setup.genesis.fork.first_block.0 + 3);
+    let qc = setup.protocol_upgrade(validator::ProtocolVersion::BLAKE2_HASHING, activation);
+    setup.push_block_with_protocol_upgrade(rng.gen(), Some(qc));
+    setup.push_blocks(rng, 4);
+    setups.push(setup);
+
+    for setup in &setups {
+        // The blocks are linked with the hashes of the scheme of their protocol version.
+        let genesis = setup.chain_genesis();
+        for (parent, block) in setup.blocks.iter().zip(&setup.blocks[1..]) {
+            assert_eq!(
+                Some(genesis.header_hash(parent.header())),
+                block.header().parent
+            );
+        }
+        assert_ne!(
+            setup.blocks[4].header().parent,
+            Some(setup.blocks[3].header().hash())
+        );
+        scope::run!(ctx, |ctx, s| async {
+            let (store, runner) = new_store(ctx, &setup.genesis).await;
+            s.spawn_bg(runner.run(ctx));
+            for block in &setup.blocks {
+                store.queue_block(ctx, block.clone()).await?;
+            }
+            store.flush(ctx).await?;
+            testonly::verify(ctx, &store).await?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_protocol_upgrade_committed_by_block() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 4);
+    let activation = validator::BlockNumber(setup.genesis.fork.first_block.0 + 3);
+    let qc = setup.protocol_upgrade(validator::ProtocolVersion::PAYLOAD_ROOT, activation);
+    setup.push_blocks(rng, 1);
+    setup.push_block_with_protocol_upgrade(rng.gen(), Some(qc.clone()));
+    setup.push_blocks(rng, 2);
+    let persistent = testonly::in_memory::BlockStore::new(setup.genesis.clone());
+    let (store, runner) = BlockStore::new(ctx, Box::new(persistent.clone()))
+        .await
+        .unwrap();
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(runner.run(ctx));
+        store.queue_block(ctx, setup.blocks[0].clone()).await?;
+        assert_eq!(
+            validator::ProtocolVersion::EARLIEST,
+            store.genesis().protocol_version_at(activation)
+        );
+        store.queue_block(ctx, setup.blocks[1].clone()).await?;
+        assert_eq!(
+            validator::ProtocolVersion::PAYLOAD_ROOT,
+            store.genesis().protocol_version_at(activation)
+        );
+        // The block activating the upgrade follows the new version.
+        for block in &setup.blocks[2..] {
+            store.queue_block(ctx, block.clone()).await?;
+        }
+        store.flush(ctx).await?;
+        assert_eq!(
+            setup.blocks[1],
+            store.block(ctx, setup.blocks[1].number()).await?
+        );
+        store.prune(ctx, setup.blocks[2].number()).await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+
+    // The upgrade is recovered from the persistent store.
+    let (store, _) = BlockStore::new(ctx, Box::new(persistent)).await.unwrap();
+    assert!(store.genesis().protocol_upgrades.contains(&qc));
+    assert_eq!(setup.genesis.hash(), store.genesis().hash());
+
+    // An upgrade activated at or before the committing block is rejected.
+    let mut setup2 = setup.clone();
+    let stale = setup2.protocol_upgrade(
+        validator::ProtocolVersion::DOMAIN_SEPARATED_HASHING,
+        setup2.next(),
+    );
+    setup2.push_block_with_protocol_upgrade(rng.gen(), Some(stale));
+    let block = setup2.blocks.last().unwrap().clone();
+    assert!(store.queue_block(ctx, block).await.is_err());
+}
+
+#[tokio::test]
+async fn test_switch_fork() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 4);
+    setup.push_blocks(rng, 3);
+    let last = setup.blocks.last().unwrap();
+    let fork = validator::Fork {
+        number: setup.genesis.fork.number.next(),
+        first_block: last.number().next(),
+        first_parent: Some(setup.genesis.header_hash(last.header())),
+    };
+    let mut new_setup = Setup::new_with_fork(rng, 4, fork.clone());
+    new_setup.push_blocks(rng, 2);
+
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        store.queue_block(ctx, setup.blocks[0].clone()).await?;
+
+        // Blocks of the current fork have to be persisted up to the cut-over.
+        assert!(store.switch_fork(new_setup.genesis.clone()).is_err());
+        for block in &setup.blocks[1..] {
+            store.queue_block(ctx, block.clone()).await?;
+        }
+        store.flush(ctx).await?;
+        let mut wrong = new_setup.genesis.clone();
+        wrong.fork.first_parent = Some(rng.gen());
+        assert!(store.switch_fork(wrong).is_err());
+
+        store.switch_fork(new_setup.genesis.clone())?;
+        assert_eq!(new_setup.genesis.hash(), store.genesis().hash());
+        assert_eq!(None, store.block(ctx, setup.blocks[0].number()).await?);
+        // The blocks of the new fork are accepted.
+        for block in &new_setup.blocks {
+            store.queue_block(ctx, block.clone()).await?;
+        }
+        store.flush(ctx).await?;
+        for block in &new_setup.blocks {
+            assert_eq!(
+                Some(block),
+                store.block(ctx, block.number()).await?.as_ref()
+            );
+        }
+        assert_eq!(fork.first_block, store.subscribe().borrow().first);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_justification() {
+    async fn check(ctx: &ctx::Ctx, store: &BlockStore, blocks: &[validator::FinalBlock]) {
+        for block in blocks {
+            let got = store.justification(ctx, block.number()).await.unwrap();
+            assert_eq!(Some(&block.justification), got.as_ref());
+        }
+        let next = blocks.last().unwrap().number().next();
+        assert_eq!(None, store.justification(ctx, next).await.unwrap());
+    }
+
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    setup.push_blocks(rng, 3);
+    let (store, runner) = new_store(ctx, &setup.genesis).await;
+    for block in &setup.blocks {
+        store.queue_block(ctx, block.clone()).await.unwrap();
+    }
+    // Justifications of the queued blocks.
+    check(ctx, &store, &setup.blocks).await;
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(runner.run(ctx));
+        store.flush(ctx).await?;
+        // Justifications of the persisted blocks.
+        check(ctx, &store, &setup.blocks).await;
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_payloads_stored_separately() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    setup.push_blocks(rng, 3);
+    let payloads = Arc::new(std::sync::Mutex::default());
+    let persistent = hooked::BlockStore::new(
+        testonly::in_memory::BlockStore::new(setup.genesis.clone()),
+        hooked::Hooks {
+            payloads: Some(payloads.clone()),
+            ..hooked::Hooks::default()
+        },
+    );
+    // Disable the cache, so that the blocks are read from the persistent storage.
+    let cfg = BlockStoreConfig {
+        block_cache_bytes: 0,
+        ..BlockStoreConfig::default()
+    };
+    let (store, runner) = BlockStore::new_with_config(ctx, Box::new(persistent), cfg)
+        .await
+        .unwrap();
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(runner.run(ctx));
+        for block in &setup.blocks {
+            store.queue_block(ctx, block.clone()).await.unwrap();
+        }
+        store.flush(ctx).await?;
+        for block in &setup.blocks {
+            let got = store.block(ctx, block.number()).await.unwrap();
+            assert_eq!(Some(block), got.as_ref());
+        }
+        // Payloads not matching the header are rejected.
+        let block = &setup.blocks[1];
+        payloads
+            .lock()
+            .unwrap()
+            .insert(block.number(), validator::Payload(vec![1, 2, 3]));
+        assert!(store.block(ctx, block.number()).await.is_err());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+fn retry_config(store_max_attempts: usize) -> BlockStoreConfig {
+    BlockStoreConfig {
+        store_retry_initial_delay: time::Duration::milliseconds(1),
+        store_retry_max_delay: time::Duration::milliseconds(4),
+        store_max_attempts,
+        ..BlockStoreConfig::default()
+    }
+}
+
+#[tokio::test]
+async fn test_retry_on_store_failure() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    setup.push_blocks(rng, 3);
+    let persistent = testonly::in_memory::BlockStore::new(setup.genesis.clone());
+    let flaky = hooked::BlockStore::new(
+        persistent.clone(),
+        hooked::Hooks {
+            store_failures: 4.into(),
+            ..hooked::Hooks::default()
+        },
+    );
+    let (store, runner) = BlockStore::new_with_config(ctx, Box::new(flaky), retry_config(5))
+        .await
+        .unwrap();
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(runner.run(ctx));
+        for block in &setup.blocks {
+            store.queue_block(ctx, block.clone()).await.unwrap();
+        }
+        store.flush(ctx).await?;
+        assert_eq!(setup.blocks, testonly::dump(ctx, &persistent).await);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_store_failure_poison_threshold() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    setup.push_blocks(rng, 1);
+    let flaky = hooked::BlockStore::new(
+        testonly::in_memory::BlockStore::new(setup.genesis.clone()),
+        hooked::Hooks {
+            store_failures: 3.into(),
+            ..hooked::Hooks::default()
+        },
+    );
+    let (store, runner) = BlockStore::new_with_config(ctx, Box::new(flaky), retry_config(3))
+        .await
+        .unwrap();
+    store
+        .queue_block(ctx, setup.blocks[0].clone())
+        .await
+        .unwrap();
+    assert!(runner.run(ctx).await.is_err());
+}
+
+#[tokio::test]
+async fn test_store_blocks_in_batches() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    setup.push_blocks(rng, 10);
+
+    for fail_midway in [false, true] {
+        let persistent = testonly::in_memory::BlockStore::new(setup.genesis.clone());
+        let batches = Arc::new(std::sync::Mutex::default());
+        let batching = hooked::BlockStore::new(
+            persistent.clone(),
+            hooked::Hooks {
+                batches: batches.clone(),
+                fail_midway: fail_midway.into(),
+                ..hooked::Hooks::default()
+            },
+        );
+        let config = BlockStoreConfig {
+            store_batch_size: 4,
+            ..retry_config(2)
+        };
+        let (store, runner) = BlockStore::new_with_config(ctx, Box::new(batching), config)
+            .await
+            .unwrap();
+        // Queue all the blocks before starting the runner, so that the batches are full.
+        for block in &setup.blocks {
+            store.queue_block(ctx, block.clone()).await.unwrap();
+        }
+        scope::run!(ctx, |ctx, s| async {
+            s.spawn_bg(runner.run(ctx));
+            store.flush(ctx).await?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(setup.blocks, testonly::dump(ctx, &persistent).await);
+        // The retry continues right after the blocks stored by the failed batch.
+        let want = match fail_midway {
+            false => vec![4, 4, 2],
+            true => vec![4, 4, 4],
+        };
+        assert_eq!(want, *batches.lock().unwrap());
+    }
+}
+
+#[tokio::test]
+async fn test_admission_pauses_queue() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    setup.push_blocks(rng, 3);
+    let persistent = testonly::in_memory::BlockStore::new(setup.genesis.clone());
+    let config = BlockStoreConfig {
+        admission: AdmissionConfig {
+            pause_lag: Some(2),
+            ..AdmissionConfig::default()
+        },
+        ..BlockStoreConfig::default()
+    };
+    let (store, runner) = BlockStore::new_with_config(ctx, Box::new(persistent.clone()), config)
+        .await
+        .unwrap();
+    let lagging = &mut store.subscribe_lagging();
+    assert_eq!(None, *lagging.borrow());
+    for block in &setup.blocks[..2] {
+        store.queue_block(ctx, block.clone()).await.unwrap();
+    }
+    assert_eq!(
+        Some(StorageLagging {
+            lag: 2,
+            paused: true
+        }),
+        *lagging.borrow()
+    );
+    // The queue is full and the runner is not running, so the next block is not admitted.
+    let res = store
+        .queue_block(
+            &ctx.with_timeout(time::Duration::milliseconds(100)),
+            setup.blocks[2].clone(),
+        )
+        .await;
+    assert!(matches!(res, Err(ctx::Error::Canceled(_))));
+    assert_eq!(setup.blocks[2].number(), store.subscribe().borrow().next());
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(runner.run(ctx));
+        store.queue_block(ctx, setup.blocks[2].clone()).await?;
+        store.flush(ctx).await?;
+        sync::wait_for(ctx, lagging, |l| l.is_none()).await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+    assert_eq!(setup.blocks, testonly::dump(ctx, &persistent).await);
+}
+
+/// Persistent store with slow `block()` calls, collecting the read statistics.
+fn slow_read_store(
+    inner: testonly::in_memory::BlockStore,
+) -> (hooked::BlockStore, Arc<hooked::ReadStats>) {
+    let stats = Arc::new(hooked::ReadStats::default());
+    let hooks = hooked::Hooks {
+        read_delay: time::Duration::milliseconds(10),
+        read_stats: stats.clone(),
+        ..hooked::Hooks::default()
+    };
+    (hooked::BlockStore::new(inner, hooks), stats)
+}
+
+#[tokio::test]
+async fn test_concurrent_cached_reads() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    setup.push_blocks(rng, 10);
+    let inner = testonly::in_memory::BlockStore::new(setup.genesis.clone());
+    for block in &setup.blocks {
+        inner.store_next_block(ctx, block).await.unwrap();
+    }
+    let (persistent, stats) = slow_read_store(inner);
+    let cfg = BlockStoreConfig {
+        max_concurrent_reads: 3,
+        block_cache_bytes: 1 << 20,
+        ..BlockStoreConfig::default()
+    };
+    let (store, _runner) = BlockStore::new_with_config(ctx, Box::new(persistent), cfg)
+        .await
+        .unwrap();
+    // `new_with_config()` has read (and cached) the first block.
+    assert_eq!(1, stats.reads.load(Ordering::SeqCst));
+    scope::run!(ctx, |ctx, s| async {
+        for block in &setup.blocks {
+            let store = &store;
+            s.spawn(async move {
+                let got = store.block(ctx, block.number()).await.unwrap();
+                assert_eq!(Some(block), got.as_ref());
+                anyhow::Ok(())
+            });
+        }
+        Ok(())
+    })
+    .await
+    .unwrap();
+    assert!(stats.max_inflight.load(Ordering::SeqCst) <= 3);
+    assert_eq!(setup.blocks.len(), stats.reads.load(Ordering::SeqCst));
+    // All the blocks are served from the cache now.
+    for block in &setup.blocks {
+        let got = store.block(ctx, block.number()).await.unwrap();
+        assert_eq!(Some(block), got.as_ref());
+    }
+    assert_eq!(setup.blocks.len(), stats.reads.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn test_reads_while_persisting() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    setup.push_blocks(rng, 20);
+    // Disable the cache, so that the persisted blocks are read from the persistent storage.
+    let cfg = BlockStoreConfig {
+        block_cache_bytes: 0,
+        ..BlockStoreConfig::default()
+    };
+    let persistent = testonly::in_memory::BlockStore::new(setup.genesis.clone());
+    let (store, runner) = BlockStore::new_with_config(ctx, Box::new(persistent), cfg)
+        .await
+        .unwrap();
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(runner.run(ctx));
+        // Every block announced by the state has to be available,
+        // regardless of whether it has been persisted yet.
+        for _ in 0..3 {
+            let store = &store;
+            let blocks = &setup.blocks;
+            s.spawn(async move {
+                let mut sub = store.subscribe();
+                for block in blocks {
+                    sync::wait_for(ctx, &mut sub, |state| state.contains(block.number())).await?;
+                    let got = store.block(ctx, block.number()).await.unwrap();
+                    assert_eq!(Some(block), got.as_ref());
+                }
+                Ok(())
+            });
+        }
+        for block in &setup.blocks {
+            store.queue_block(ctx, block.clone()).await.unwrap();
+        }
+        store.flush(ctx).await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_block_cache_budget() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    for _ in 0..3 {
+        setup.push_block(validator::Payload(vec![0; 100]));
+    }
+    let inner = testonly::in_memory::BlockStore::new(setup.genesis.clone());
+    for block in &setup.blocks {
+        inner.store_next_block(ctx, block).await.unwrap();
+    }
+    let (persistent, stats) = slow_read_store(inner);
+    // Budget fits 2 blocks.
+    let cfg = BlockStoreConfig {
+        block_cache_bytes: 250,
+        ..BlockStoreConfig::default()
+    };
+    let (store, _runner) = BlockStore::new_with_config(ctx, Box::new(persistent), cfg)
+        .await
+        .unwrap();
+    // (block index, whether it should be read from the persistent storage).
+    // `new_with_config()` has already read block 0.
+    let reads = [
+        (1, true),
+        (2, true),
+        (1, false),
+        (0, true),
+        (1, false),
+        (2, true),
+    ];
+    let mut want = 1;
+    for (i, miss) in reads {
+        let block = &setup.blocks[i];
+        let got = store.block(ctx, block.number()).await.unwrap();
+        assert_eq!(Some(block), got.as_ref());
+        want += miss as usize;
+        assert_eq!(want, stats.reads.load(Ordering::SeqCst), "block {i}");
+    }
+}
+
+#[tokio::test]
+async fn test_block_cache_capacity() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    // Empty payloads don't count towards the byte budget.
+    for _ in 0..3 {
+        setup.push_block(validator::Payload(vec![]));
+    }
+    let inner = testonly::in_memory::BlockStore::new(setup.genesis.clone());
+    for block in &setup.blocks {
+        inner.store_next_block(ctx, block).await.unwrap();
+    }
+    let (persistent, stats) = slow_read_store(inner);
+    let cfg = BlockStoreConfig {
+        block_cache_bytes: 1 << 20,
+        block_cache_capacity: 2,
+        ..BlockStoreConfig::default()
+    };
+    let (store, _runner) = BlockStore::new_with_config(ctx, Box::new(persistent), cfg)
+        .await
+        .unwrap();
+    // (block index, whether it should be read from the persistent storage).
+    // `new_with_config()` has already read block 0.
+    let reads = [(1, true), (2, true), (1, false), (0, true), (2, true)];
+    let mut want = 1;
+    for (i, miss) in reads {
+        let block = &setup.blocks[i];
+        let got = store.block(ctx, block.number()).await.unwrap();
+        assert_eq!(Some(block), got.as_ref());
+        want += miss as usize;
+        assert_eq!(want, stats.reads.load(Ordering::SeqCst), "block {i}");
+    }
+}
+
+/// Checks the block cache against a model of an LRU cache, over random reads.
+#[tokio::test]
+async fn test_block_cache_lru() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    setup.push_blocks(rng, 10);
+    let inner = testonly::in_memory::BlockStore::new(setup.genesis.clone());
+    for block in &setup.blocks {
+        inner.store_next_block(ctx, block).await.unwrap();
+    }
+    let (persistent, stats) = slow_read_store(inner);
+    let capacity = 4;
+    let cfg = BlockStoreConfig {
+        block_cache_bytes: 1 << 20,
+        block_cache_capacity: capacity,
+        ..BlockStoreConfig::default()
+    };
+    let (store, _runner) = BlockStore::new_with_config(ctx, Box::new(persistent), cfg)
+        .await
+        .unwrap();
+    // `new_with_config()` has already read block 0.
+    let mut model = std::collections::VecDeque::from([0]);
+    let mut want = 1;
+    for _ in 0..200 {
+        let i = rng.gen_range(0..setup.blocks.len());
+        let block = &setup.blocks[i];
+        let got = store.block(ctx, block.number()).await.unwrap();
+        assert_eq!(Some(block), got.as_ref());
+        match model.iter().position(|j| *j == i) {
+            Some(pos) => {
+                model.remove(pos);
+            }
+            None => want += 1,
+        }
+        model.push_back(i);
+        if model.len() > capacity {
+            model.pop_front();
+        }
+        assert_eq!(want, stats.reads.load(Ordering::SeqCst), "block {i}");
+    }
+}
+
+#[tokio::test]
+async fn test_wal_replay() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    setup.push_blocks(rng, 3);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("wal");
+    let persistent = testonly::in_memory::BlockStore::new(setup.genesis.clone());
+
+    // Queue the blocks without persisting them, as if the node crashed.
+    let wal = FileWal::open(path.clone()).await.unwrap();
+    let (store, _runner) = BlockStore::new_with_wal(
+        ctx,
+        Box::new(persistent.clone()),
+        BlockStoreConfig::default(),
+        Box::new(wal),
+    )
+    .await
+    .unwrap();
+    for block in &setup.blocks {
+        store.queue_block(ctx, block.clone()).await.unwrap();
+    }
+    drop(store);
+    assert!(testonly::dump(ctx, &persistent).await.is_empty());
+
+    // After restart, the blocks should be queued again and persisted.
+    let wal = FileWal::open(path.clone()).await.unwrap();
+    let (store, runner) = BlockStore::new_with_wal(
+        ctx,
+        Box::new(persistent.clone()),
+        BlockStoreConfig::default(),
+        Box::new(wal),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        setup.blocks.last().unwrap().number().next(),
+        store.subscribe().borrow().next()
+    );
+    scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(runner.run(ctx));
+        store.flush(ctx).await?;
+        assert_eq!(setup.blocks, testonly::dump(ctx, &persistent).await);
+        Ok(())
+    })
+    .await
+    .unwrap();
+    // The log gets cleared once all the blocks are persisted.
+    let wal = FileWal::open(path).await.unwrap();
+    assert!(wal.read(ctx).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_wal_truncate() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    setup.push_blocks(rng, 4);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("wal");
+
+    let wal = FileWal::open(path.clone()).await.unwrap();
+    for block in &setup.blocks[..3] {
+        wal.append(ctx, block).await.unwrap();
+    }
+    // Truncation drops only the blocks below `next`.
+    wal.truncate(ctx, setup.blocks[2].number()).await.unwrap();
+    assert_eq!(setup.blocks[2..3], wal.read(ctx).await.unwrap());
+    wal.append(ctx, &setup.blocks[3]).await.unwrap();
+    assert_eq!(setup.blocks[2..4], wal.read(ctx).await.unwrap());
+
+    // A corrupted frame at the end of the log is cut off on startup.
+    {
+        use std::io::Write as _;
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(&[3, 0, 0, 0, 0xff, 0xff, 0xff]).unwrap();
+    }
+    let wal = FileWal::open(path.clone()).await.unwrap();
+    assert_eq!(setup.blocks[2..4], wal.read(ctx).await.unwrap());
+    wal.append(ctx, &setup.blocks[0]).await.unwrap();
+    let mut want = setup.blocks[2..4].to_vec();
+    want.push(setup.blocks[0].clone());
+    assert_eq!(want, wal.read(ctx).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_file_checkpoint_store() {
+    abort_on_panic();
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("checkpoints");
+
+    let store = FileCheckpointStore::open(path.clone()).await.unwrap();
+    let mut want: ReplicaCheckpoint = rng.gen();
+    assert_eq!(None, store.checkpoint(ctx, want.epoch).await.unwrap());
+    store.set_checkpoint(ctx, &want).await.unwrap();
+    want.view = want.view.next();
+    store.set_checkpoint(ctx, &want).await.unwrap();
+
+    // Checkpoints survive a restart and are kept per epoch.
+    let store = FileCheckpointStore::open(path).await.unwrap();
+    assert_eq!(
+        Some(&want),
+        store.checkpoint(ctx, want.epoch).await.unwrap().as_ref()
+    );
+    assert_eq!(
+        None,
+        store.checkpoint(ctx, want.epoch.next()).await.unwrap()
+    );
+}
+
+#[test]
+fn test_checkpoint_is_ahead_of() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 1);
+    setup.push_blocks(rng, 2);
+    let state = ReplicaState {
+        view: validator::ViewNumber(10),
+        high_qc: Some(setup.blocks[0].justification.clone()),
+        ..ReplicaState::default()
+    };
+    let checkpoint = ReplicaCheckpoint::new(setup.genesis.fork.number, &state);
+    assert!(!checkpoint.is_ahead_of(&state));
+
+    let mut old = state.clone();
+    old.view = validator::ViewNumber(9);
+    assert!(checkpoint.is_ahead_of(&old));
+
+    let mut old = state.clone();
+    old.high_qc = None;
+    assert!(checkpoint.is_ahead_of(&old));
+
+    let mut new = state.clone();
+    new.view = validator::ViewNumber(11);
+    new.high_qc = Some(setup.blocks[1].justification.clone());
+    assert!(!checkpoint.is_ahead_of(&new));
+}
+
+#[test]
+fn test_block_latency() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let proposed = ctx.now();
+    let certified = proposed + time::Duration::milliseconds(300);
+    let queued = certified + time::Duration::milliseconds(20);
+    let persisted = queued + time::Duration::milliseconds(100);
+
+    // Block proposed and certified by this node: every stage is observed
+    // and the stages add up to the total latency.
+    let timings = BlockTimings {
+        proposed: Some(proposed),
+        certified: Some(certified),
+    };
+    let latency = timings.latency(queued, persisted);
+    assert_eq!(
+        latency,
+        BlockLatency {
+            certify: Some(time::Duration::milliseconds(300)),
+            queue: Some(time::Duration::milliseconds(20)),
+            persist: time::Duration::milliseconds(100),
+            total: time::Duration::milliseconds(420),
+        }
+    );
+    assert_eq!(
+        latency.certify.unwrap() + latency.queue.unwrap() + latency.persist,
+        latency.total
+    );
+
+    // Proposal not received (e.g. the replica has joined the view late):
+    // the total latency starts at the certification.
+    let timings = BlockTimings {
+        proposed: None,
+        certified: Some(certified),
+    };
+    assert_eq!(
+        timings.latency(queued, persisted),
+        BlockLatency {
+            certify: None,
+            queue: Some(time::Duration::milliseconds(20)),
+            persist: time::Duration::milliseconds(100),
+            total: time::Duration::milliseconds(120),
+        }
+    );
+
+    // Block fetched from a peer: only the persistence is observed.
+    assert_eq!(
+        BlockTimings::default().latency(queued, persisted),
+        BlockLatency {
+            certify: None,
+            queue: None,
+            persist: time::Duration::milliseconds(100),
+            total: time::Duration::milliseconds(100),
+        }
+    );
+}
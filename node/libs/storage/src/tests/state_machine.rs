@@ -0,0 +1,294 @@
+//! Property-based tests of the `BlockStore`, checking random sequences of operations
+//! (queueing blocks, also concurrently; persisting them; reads and restarts) against a model.
+use crate::{testonly, BlockStore, BlockStoreConfig, BlockStoreRunner, FileWal};
+use anyhow::Context as _;
+use proptest::{collection, prelude::*, test_runner::TestCaseError};
+use rand::SeedableRng as _;
+use std::{path::Path, sync::Arc};
+use zksync_concurrency::{ctx, scope, sync, time};
+use zksync_consensus_roles::validator::{self, testonly::Setup};
+
+/// Block to queue: the block of one of the 2 competing chains,
+/// `offset` blocks before the next block expected by the model.
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    /// Whether to take the block from the competing chain.
+    fork: bool,
+    /// Number of blocks before the next expected block.
+    offset: usize,
+}
+
+/// Operation on the `BlockStore`.
+#[derive(Debug, Clone)]
+enum Op {
+    /// Queues a block.
+    Queue(Candidate),
+    /// Queues 2 blocks concurrently.
+    QueueConcurrently(Candidate, Candidate),
+    /// Lets the runner persist the next queued block.
+    Persist,
+    /// Reads the block `offset` blocks before the next expected block.
+    /// Offsets 0 and 1 point to the not yet queued blocks.
+    Read(usize),
+    /// Restarts the store (with the same persistent storage and write-ahead log).
+    Restart,
+}
+
+/// Strategy generating the `Candidate`s.
+fn candidate() -> impl Strategy<Value = Candidate> {
+    (any::<bool>(), 0..3usize).prop_map(|(fork, offset)| Candidate { fork, offset })
+}
+
+/// Strategy generating the `Op`s.
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => candidate().prop_map(Op::Queue),
+        1 => (candidate(), candidate()).prop_map(|(a, b)| Op::QueueConcurrently(a, b)),
+        3 => Just(Op::Persist),
+        2 => (0..5usize).prop_map(Op::Read),
+        1 => Just(Op::Restart),
+    ]
+}
+
+/// Expected content of the `BlockStore`.
+#[derive(Debug)]
+struct Model {
+    /// Genesis of the chain.
+    genesis: validator::Genesis,
+    /// First block of the fork.
+    first: validator::BlockNumber,
+    /// Queued blocks, including the persisted ones.
+    queued: Vec<validator::FinalBlock>,
+    /// Number of the persisted blocks (a prefix of `queued`).
+    persisted: usize,
+}
+
+impl Model {
+    /// Number of the `idx`-th block of the fork.
+    fn number(&self, idx: usize) -> validator::BlockNumber {
+        validator::BlockNumber(self.first.0 + idx as u64)
+    }
+
+    /// Whether `block` is the next block of the queued chain.
+    fn extends(&self, block: &validator::FinalBlock) -> bool {
+        block.number() == self.number(self.queued.len())
+            && self.queued.last().map_or(true, |last| {
+                Some(self.genesis.header_hash(last.header())) == block.header().parent
+            })
+    }
+}
+
+/// Test environment: 2 chains competing for the same block numbers,
+/// and the storage surviving the restarts of the `BlockStore`.
+struct Env {
+    /// Main chain and the competing chain.
+    chains: [Vec<validator::FinalBlock>; 2],
+    /// Persistent storage.
+    persistent: testonly::in_memory::BlockStore,
+    /// Permits to store blocks, see `hooked::Hooks::store_permits`.
+    permits: Arc<sync::Semaphore>,
+}
+
+impl Env {
+    /// Block denoted by `c`.
+    fn candidate(&self, model: &Model, c: Candidate) -> &validator::FinalBlock {
+        &self.chains[usize::from(c.fork)][model.queued.len().saturating_sub(c.offset)]
+    }
+
+    /// Opens a `BlockStore` on top of the persistent storage and the write-ahead log.
+    async fn open(&self, ctx: &ctx::Ctx, wal: &Path) -> (Arc<BlockStore>, BlockStoreRunner) {
+        let config = BlockStoreConfig {
+            // Blocks are persisted one by one, as the test releases the permits.
+            store_batch_size: 1,
+            // A restart is a crash: the queued blocks are not drained.
+            shutdown_drain_timeout: time::Duration::ZERO,
+            ..BlockStoreConfig::default()
+        };
+        let persistent = testonly::hooked::BlockStore::new(
+            self.persistent.clone(),
+            testonly::hooked::Hooks {
+                store_permits: Some(self.permits.clone()),
+                ..testonly::hooked::Hooks::default()
+            },
+        );
+        let wal = FileWal::open(wal.to_path_buf()).await.unwrap();
+        BlockStore::new_with_wal(ctx, Box::new(persistent), config, Box::new(wal))
+            .await
+            .unwrap()
+    }
+
+    /// Checks that the state of `store` matches `model`.
+    async fn check(&self, ctx: &ctx::Ctx, store: &BlockStore, model: &Model) -> anyhow::Result<()> {
+        let state = store.subscribe().borrow().clone();
+        anyhow::ensure!(
+            state.first == model.first,
+            "state.first = {:?}",
+            state.first
+        );
+        anyhow::ensure!(
+            state.last.as_ref() == model.queued.last().map(|b| &b.justification),
+            "state.last = {:?}, want {:?}",
+            state.last.as_ref().map(|qc| qc.header().number),
+            model.queued.last().map(|b| b.number()),
+        );
+        let persisted = testonly::dump(ctx, &self.persistent).await;
+        anyhow::ensure!(
+            persisted == model.queued[..model.persisted],
+            "{} blocks persisted, want {}",
+            persisted.len(),
+            model.persisted
+        );
+        Ok(())
+    }
+
+    /// Executes `op` against `store`, updating `model` accordingly.
+    async fn execute(
+        &self,
+        ctx: &ctx::Ctx,
+        store: &BlockStore,
+        model: &mut Model,
+        op: &Op,
+    ) -> anyhow::Result<()> {
+        match op {
+            Op::Queue(c) => {
+                let block = self.candidate(model, *c);
+                let res = store.queue_block(ctx, block.clone()).await;
+                if block.number() < model.number(model.queued.len()) {
+                    // Already queued blocks are ignored, even if they are different.
+                    res.context("queue_block(<old block>)")?;
+                } else if model.extends(block) {
+                    res.context("queue_block(<next block>)")?;
+                    model.queued.push(block.clone());
+                } else {
+                    anyhow::ensure!(res.is_err(), "queued a block with a wrong parent");
+                }
+            }
+            Op::QueueConcurrently(a, b) => {
+                let blocks = [self.candidate(model, *a), self.candidate(model, *b)];
+                let res = scope::run!(ctx, |ctx, s| async {
+                    let tasks = blocks.map(|block| {
+                        s.spawn(async move { Ok(store.queue_block(ctx, block.clone()).await) })
+                    });
+                    let mut res = vec![];
+                    for t in tasks {
+                        res.push(t.join(ctx).await?);
+                    }
+                    ctx::OrCanceled::Ok(res)
+                })
+                .await?;
+                // The queued blocks should form a valid chain, in any order of queueing.
+                let mut sorted: Vec<_> = blocks.iter().zip(&res).collect();
+                sorted.sort_by_key(|(block, _)| block.number());
+                for (block, res) in sorted {
+                    let next = model.number(model.queued.len());
+                    if block.number() != next {
+                        continue;
+                    }
+                    let got = store.block(ctx, next).await?;
+                    if got.as_ref() == Some(*block) {
+                        anyhow::ensure!(model.extends(block), "queued a block with a wrong parent");
+                        anyhow::ensure!(
+                            res.is_ok(),
+                            "queue_block() failed, but the block got queued"
+                        );
+                        model.queued.push((*block).clone());
+                    }
+                }
+                let next = store.subscribe().borrow().next();
+                anyhow::ensure!(
+                    next == model.number(model.queued.len()),
+                    "unexpected block queued: next = {next:?}"
+                );
+            }
+            Op::Persist => {
+                if model.persisted < model.queued.len() {
+                    self.permits.add_permits(1);
+                    store
+                        .wait_until_persisted(ctx, model.number(model.persisted))
+                        .await?;
+                    model.persisted += 1;
+                }
+            }
+            Op::Read(offset) => {
+                let Some(idx) = (model.queued.len() + 1).checked_sub(*offset) else {
+                    return Ok(());
+                };
+                let number = model.number(idx);
+                let want = model.queued.get(idx);
+                let got = store.block(ctx, number).await?;
+                anyhow::ensure!(got.as_ref() == want, "block({number:?}) = {got:?}");
+                let got = store.justification(ctx, number).await?;
+                anyhow::ensure!(
+                    got.as_ref() == want.map(|b| &b.justification),
+                    "justification({number:?}) = {got:?}"
+                );
+            }
+            // Handled by `run()`.
+            Op::Restart => {}
+        }
+        Ok(())
+    }
+}
+
+/// Executes `ops` on a fresh `BlockStore`, checking its state against the model after each step.
+async fn run(seed: u64, ops: &[Op]) -> anyhow::Result<()> {
+    let ctx = &ctx::test_root(&ctx::RealClock).with_timeout(time::Duration::seconds(20));
+    let rng = &mut rand::rngs::StdRng::seed_from_u64(seed);
+    let mut setup = Setup::new(rng, 1);
+    let mut fork = setup.clone();
+    setup.push_blocks(rng, ops.len() + 1);
+    fork.push_blocks(rng, ops.len() + 1);
+    let env = Env {
+        persistent: testonly::in_memory::BlockStore::new(setup.genesis.clone()),
+        chains: [setup.blocks.clone(), fork.blocks.clone()],
+        permits: Arc::new(sync::Semaphore::new(0)),
+    };
+    let dir = tempfile::tempdir()?;
+    let wal = dir.path().join("wal");
+    let mut model = Model {
+        genesis: setup.genesis.clone(),
+        first: setup.genesis.fork.first_block,
+        queued: vec![],
+        persisted: 0,
+    };
+    let mut ops = ops.iter();
+    loop {
+        let (store, runner) = env.open(ctx, &wal).await;
+        env.check(ctx, &store, &model)
+            .await
+            .context("after restart")?;
+        let restart = scope::run!(ctx, |ctx, s| async {
+            s.spawn_bg(runner.run(ctx));
+            for op in ops.by_ref() {
+                if let Op::Restart = op {
+                    return Ok(true);
+                }
+                env.execute(ctx, &store, &mut model, op)
+                    .await
+                    .with_context(|| format!("{op:?}"))?;
+                env.check(ctx, &store, &model)
+                    .await
+                    .with_context(|| format!("after {op:?}"))?;
+            }
+            anyhow::Ok(false)
+        })
+        .await?;
+        if !restart {
+            return Ok(());
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn test_block_store_model(seed in any::<u64>(), ops in collection::vec(op(), 1..40)) {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(run(seed, &ops))
+            .map_err(|err| TestCaseError::fail(format!("{err:#}")))?;
+    }
+}
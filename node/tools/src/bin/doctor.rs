@@ -0,0 +1,91 @@
+//! Network doctor: dials the gossip peers configured in the node config,
+//! performs the handshakes, measures the latency and prints a diagnostic report.
+#![allow(clippy::print_stdout)]
+use anyhow::Context as _;
+use clap::Parser;
+use rand::Rng as _;
+use std::{fs, path::PathBuf};
+use zksync_concurrency::ctx;
+use zksync_consensus_crypto::TextFmt as _;
+use zksync_consensus_network::{gossip::doctor, GossipConfig, MAX_CLOCK_SKEW};
+use zksync_consensus_tools::{
+    decode_json,
+    keystore::{self, Passphrase},
+    AppConfig,
+};
+use zksync_protobuf::serde::Serde;
+
+/// Command line arguments.
+#[derive(Debug, Parser)]
+struct Args {
+    /// Path to a JSON file with node configuration.
+    #[arg(long, default_value = "./config.json")]
+    config_file: PathBuf,
+    /// Path to a node key file to dial the peers with.
+    /// If not set, a fresh key is generated, so that the probe doesn't interfere with
+    /// a running node. Note that peers accept connections from unknown keys only
+    /// if they have free dynamic inbound slots.
+    #[arg(long)]
+    node_key: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let ctx = &ctx::root();
+    let cfg = fs::read_to_string(&args.config_file).context("failed reading config")?;
+    let cfg: AppConfig = decode_json::<Serde<AppConfig>>(&cfg)
+        .context("failed decoding config")?
+        .0;
+    let key = match &args.node_key {
+        Some(path) => keystore::read_key(path, Passphrase::from_env().as_ref())
+            .with_context(|| path.display().to_string())?,
+        None => ctx.rng().gen(),
+    };
+    let gossip = GossipConfig {
+        key,
+        dynamic_inbound_limit: cfg.gossip_dynamic_inbound_limit,
+        static_inbound: cfg.gossip_static_inbound.clone(),
+        static_outbound: cfg.gossip_static_outbound.clone(),
+        dynamic_outbound_limit: cfg.gossip_dynamic_outbound_limit,
+        relay_auth: cfg.gossip_relay_auth,
+        peer_bandwidth_cap: cfg.gossip_peer_bandwidth_cap,
+        protocol_version: zksync_consensus_bft::PROTOCOL_VERSION,
+    };
+    let genesis = cfg.genesis.hash();
+    println!("genesis: {}", genesis.encode());
+    println!("node key: {}", gossip.key.public().encode());
+
+    let mut failed = 0;
+    for (peer, addr) in &gossip.static_outbound {
+        let report = doctor::probe_peer(ctx, &cfg.transport(), &gossip, genesis, peer, *addr).await;
+        println!("peer {} at {addr}:", peer.encode());
+        if let Some(latency) = report.connect_latency {
+            println!("  connect: {latency}");
+        }
+        if let Some(latency) = report.handshake_latency {
+            println!("  handshake: {latency}");
+        }
+        for rtt in &report.ping_rtts {
+            println!("  ping: {rtt}");
+        }
+        if let Some(skew) = report.clock_skew {
+            println!("  clock skew: {skew}");
+            if skew.abs() > MAX_CLOCK_SKEW {
+                println!("  WARNING: clock skew exceeds {}", MAX_CLOCK_SKEW);
+            }
+        }
+        match &report.error {
+            None => println!("  OK"),
+            Some(err) => {
+                failed += 1;
+                println!("  FAILED: {err}");
+            }
+        }
+    }
+    if gossip.static_outbound.is_empty() {
+        println!("no gossip_static_outbound peers configured");
+    }
+    anyhow::ensure!(failed == 0, "{failed} peer(s) failed the diagnostics");
+    Ok(())
+}
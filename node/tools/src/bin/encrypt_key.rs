@@ -0,0 +1,35 @@
+//! This tool encrypts a plaintext node or validator secret key file with a passphrase,
+//! provided in the `CONSENSUS_KEY_PASSPHRASE` env var.
+#![allow(clippy::print_stdout)]
+use anyhow::Context as _;
+use clap::Parser;
+use std::path::PathBuf;
+use zksync_consensus_roles::{node, validator};
+use zksync_consensus_tools::keystore::{self, KdfParams, Passphrase, PASSPHRASE_ENV};
+
+/// Command line arguments.
+#[derive(Debug, Parser)]
+struct Args {
+    /// Path to the plaintext key file.
+    #[arg(long)]
+    input: PathBuf,
+    /// Path to write the encrypted key file to.
+    #[arg(long)]
+    output: PathBuf,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let passphrase =
+        Passphrase::from_env().with_context(|| format!("{PASSPHRASE_ENV} is not set"))?;
+    let rng = &mut rand::thread_rng();
+    if let Ok(key) = keystore::read_key::<validator::SecretKey>(&args.input, None) {
+        keystore::write_key(rng, &args.output, &key, &passphrase, KdfParams::default())?;
+    } else {
+        let key: node::SecretKey = keystore::read_key(&args.input, None)
+            .with_context(|| args.input.display().to_string())?;
+        keystore::write_key(rng, &args.output, &key, &passphrase, KdfParams::default())?;
+    }
+    println!("encrypted key written to {}", args.output.display());
+    Ok(())
+}
@@ -0,0 +1,44 @@
+//! Prints the hash of the genesis, so that operators can independently verify
+//! that they are configuring the same chain. Optionally writes the genesis
+//! in the canonical genesis file format.
+#![allow(clippy::print_stdout)]
+use anyhow::Context as _;
+use clap::Parser;
+use std::{fs, path::PathBuf};
+use zksync_consensus_crypto::TextFmt as _;
+use zksync_consensus_tools::{decode_json, genesis, AppConfig};
+use zksync_protobuf::serde::Serde;
+
+/// Command line arguments.
+#[derive(Debug, Parser)]
+struct Args {
+    /// Path to a JSON file with node configuration.
+    /// Used only if `--genesis-file` is not set.
+    #[arg(long, default_value = "./config.json")]
+    config_file: PathBuf,
+    /// Path to a JSON genesis file.
+    #[arg(long)]
+    genesis_file: Option<PathBuf>,
+    /// Path to write the genesis to, in the canonical genesis file format.
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let genesis = match &args.genesis_file {
+        Some(path) => genesis::read(path)?,
+        None => {
+            let cfg = fs::read_to_string(&args.config_file).context("failed reading config")?;
+            decode_json::<Serde<AppConfig>>(&cfg)
+                .context("failed decoding config")?
+                .0
+                .genesis
+        }
+    };
+    println!("{}", genesis.hash().encode());
+    if let Some(path) = &args.output {
+        fs::write(path, genesis::encode(&genesis)).context("fs::write()")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,133 @@
+//! Block store inspector: lists the stored block range, dumps blocks, verifies
+//! the stored chain against the genesis and exports/imports block ranges for backfill.
+#![allow(clippy::print_stdout)]
+use anyhow::Context as _;
+use clap::{Parser, Subcommand};
+use std::{fs, io, path::PathBuf};
+use zksync_concurrency::ctx;
+use zksync_consensus_roles::validator;
+use zksync_consensus_storage::PersistentBlockStore as _;
+use zksync_consensus_tools::{decode_json, encode_json, inspector, AppConfig, RocksDB};
+use zksync_protobuf::serde::Serde;
+
+/// Command line arguments.
+#[derive(Debug, Parser)]
+#[command(name = "inspector")]
+struct Args {
+    /// Path to a JSON file with node configuration (the genesis is taken from it).
+    #[arg(long, default_value = "./config.json")]
+    config_file: PathBuf,
+    /// Path to the rocksdb database of the node.
+    /// The node has to be stopped, since rocksdb doesn't allow concurrent access.
+    #[arg(long, default_value = "./database")]
+    database: PathBuf,
+    /// Subcommand to run.
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Range of blocks. Defaults to all the stored blocks.
+#[derive(Debug, clap::Args)]
+struct RangeArgs {
+    /// First block of the range.
+    #[arg(long)]
+    from: Option<u64>,
+    /// Last block of the range (inclusive).
+    #[arg(long)]
+    to: Option<u64>,
+}
+
+/// Subcommands.
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Prints the range of the stored blocks.
+    Range,
+    /// Prints a block with its justification as JSON.
+    Dump {
+        /// Number of the block.
+        #[arg(long)]
+        number: u64,
+    },
+    /// Verifies the hash chain and all the justifications against the genesis.
+    Verify(RangeArgs),
+    /// Exports blocks to a file.
+    Export {
+        #[command(flatten)]
+        range: RangeArgs,
+        /// Path of the output file.
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Imports blocks from a file created by `export`, appending them to the stored blocks.
+    Import {
+        /// Path of the input file.
+        #[arg(long)]
+        input: PathBuf,
+    },
+}
+
+impl RangeArgs {
+    /// Resolves the range against the stored blocks.
+    async fn resolve(
+        &self,
+        ctx: &ctx::Ctx,
+        store: &RocksDB,
+    ) -> anyhow::Result<(validator::BlockNumber, validator::BlockNumber)> {
+        let state = inspector::state(ctx, store).await?;
+        let last = state.last.context("block store is empty")?.header().number;
+        let from = self.from.map_or(state.first, validator::BlockNumber);
+        let to = self.to.map_or(last, validator::BlockNumber);
+        anyhow::ensure!(
+            state.first <= from && from <= to && to <= last,
+            "range [{from:?},{to:?}] is not within the stored blocks [{:?},{last:?}]",
+            state.first
+        );
+        Ok((from, to))
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let ctx = &ctx::root();
+    let cfg = fs::read_to_string(&args.config_file).context("failed reading config")?;
+    let cfg: AppConfig = decode_json::<Serde<AppConfig>>(&cfg)
+        .context("failed decoding config")?
+        .0;
+    let store = RocksDB::open(cfg.genesis, &args.database)
+        .await
+        .context("RocksDB::open()")?;
+    match &args.command {
+        Command::Range => {
+            let state = inspector::state(ctx, &store).await?;
+            let ranges = state.ranges();
+            if ranges.is_empty() {
+                println!("no blocks");
+            }
+            for r in ranges {
+                println!("blocks: [{},{}]", r.first.0, r.last.0);
+            }
+        }
+        Command::Dump { number } => {
+            let block = store.block(ctx, validator::BlockNumber(*number)).await?;
+            println!("{}", encode_json(&Serde(block)));
+        }
+        Command::Verify(range) => {
+            let (from, to) = range.resolve(ctx, &store).await?;
+            inspector::verify(ctx, &store, from, to).await?;
+            println!("blocks [{},{}]: OK", from.0, to.0);
+        }
+        Command::Export { range, output } => {
+            let (from, to) = range.resolve(ctx, &store).await?;
+            let mut w = io::BufWriter::new(fs::File::create(output).context("File::create()")?);
+            let count = inspector::export(ctx, &store, from, to, &mut w).await?;
+            println!("exported {count} blocks to {}", output.display());
+        }
+        Command::Import { input } => {
+            let mut r = io::BufReader::new(fs::File::open(input).context("File::open()")?);
+            let count = inspector::import(ctx, &store, &mut r).await?;
+            println!("imported {count} blocks from {}", input.display());
+        }
+    }
+    Ok(())
+}
@@ -1,17 +1,131 @@
-//! This tool generates a validator key pair and prints it to stdout.
+//! This tool generates the node and validator keys and prints them to stdout.
+//! Without a subcommand it generates random keys. The `generate` and `derive` subcommands
+//! derive the keys from a BIP39 mnemonic instead, so that they can be recovered from it.
 #![allow(clippy::print_stdout)]
-
+use anyhow::Context as _;
+use clap::{Parser, Subcommand, ValueEnum};
 use crypto::TextFmt as _;
+use std::io::Read as _;
 use zksync_consensus_crypto as crypto;
 use zksync_consensus_roles::{node, validator};
+use zksync_consensus_tools::derivation::{
+    self, DerivationPath, Mnemonic, DEFAULT_NODE_PATH, DEFAULT_VALIDATOR_PATH, MNEMONIC_ENV,
+};
+
+/// Command line arguments.
+#[derive(Debug, Parser)]
+#[command(name = "keys")]
+struct Cli {
+    /// Subcommand to run. Random keys are generated if not provided.
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Subcommands.
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Generate a new mnemonic and derive the keys from it.
+    Generate {
+        /// Number of words of the mnemonic.
+        #[arg(long, default_value_t = 24)]
+        words: usize,
+        #[command(flatten)]
+        args: DeriveArgs,
+    },
+    /// Derive the keys from an existing mnemonic, read from the `CONSENSUS_KEY_MNEMONIC`
+    /// env var, or from stdin if it is not set.
+    Derive(DeriveArgs),
+}
+
+/// Signature scheme of the validator key.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Scheme {
+    /// BLS signatures over the bn254 curve.
+    Bn254,
+    /// ECDSA signatures over the secp256k1 curve.
+    Secp256k1,
+}
+
+impl From<Scheme> for validator::SignatureScheme {
+    fn from(x: Scheme) -> Self {
+        match x {
+            Scheme::Bn254 => Self::Bn254,
+            Scheme::Secp256k1 => Self::Secp256k1,
+        }
+    }
+}
+
+/// Arguments of the key derivation.
+#[derive(Debug, clap::Args)]
+struct DeriveArgs {
+    /// Signature scheme of the validator key.
+    #[arg(long, value_enum, default_value_t = Scheme::Bn254)]
+    scheme: Scheme,
+    /// Derivation path of the validator key.
+    #[arg(long, default_value = DEFAULT_VALIDATOR_PATH)]
+    validator_path: DerivationPath,
+    /// Derivation path of the node key.
+    #[arg(long, default_value = DEFAULT_NODE_PATH)]
+    node_path: DerivationPath,
+    /// Print the secret keys as well. Only the public keys are printed by default.
+    #[arg(long)]
+    secret: bool,
+}
+
+impl DeriveArgs {
+    /// Derives the keys from the mnemonic and prints them.
+    fn run(&self, mnemonic: &Mnemonic) -> anyhow::Result<()> {
+        let seed = mnemonic.to_seed();
+        let validator_key =
+            derivation::derive_validator_key(&seed, &self.validator_path, self.scheme.into())
+                .context("validator key")?;
+        let node_key = derivation::derive_node_key(&seed, &self.node_path).context("node key")?;
+        println!("validator key ({}):", self.validator_path);
+        if self.secret {
+            println!("{}", validator_key.encode());
+        }
+        println!("{}", validator_key.public().encode());
+        if let validator::PublicKey::Secp256k1(key) = validator_key.public() {
+            println!("evm address: 0x{}", hex::encode(key.evm_address()));
+        }
+        println!("node key ({}):", self.node_path);
+        if self.secret {
+            println!("{}", node_key.encode());
+        }
+        println!("{}", node_key.public().encode());
+        Ok(())
+    }
+}
 
-/// This tool generates a validator key pair and prints it to stdout.
-fn main() {
-    let validator_key = validator::SecretKey::generate();
-    let node_key = node::SecretKey::generate();
-    println!("keys:");
-    println!("{}", validator_key.encode());
-    println!("{}", validator_key.public().encode());
-    println!("{}", node_key.encode());
-    println!("{}", node_key.public().encode());
+fn main() -> anyhow::Result<()> {
+    match Cli::parse().command {
+        None => {
+            let validator_key = validator::SecretKey::generate();
+            let node_key = node::SecretKey::generate();
+            println!("keys:");
+            println!("{}", validator_key.encode());
+            println!("{}", validator_key.public().encode());
+            println!("{}", node_key.encode());
+            println!("{}", node_key.public().encode());
+        }
+        Some(Command::Generate { words, args }) => {
+            let mnemonic = Mnemonic::generate(&mut rand::thread_rng(), words)?;
+            println!("mnemonic: {}", mnemonic.phrase());
+            args.run(&mnemonic)?;
+        }
+        Some(Command::Derive(args)) => {
+            let mnemonic = match Mnemonic::from_env() {
+                Some(mnemonic) => mnemonic.with_context(|| MNEMONIC_ENV.to_string())?,
+                None => {
+                    let mut phrase = String::new();
+                    std::io::stdin()
+                        .read_to_string(&mut phrase)
+                        .context("failed reading the mnemonic from stdin")?;
+                    Mnemonic::parse(&phrase)?
+                }
+            };
+            args.run(&mnemonic)?;
+        }
+    }
+    Ok(())
 }
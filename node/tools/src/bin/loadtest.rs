@@ -0,0 +1,211 @@
+//! Consensus throughput benchmark: runs a committee of in-process validators
+//! (connected over the in-memory transport), proposes payloads of the configured size
+//! at the configured rate and reports the finalization latency and the throughput.
+//! The report is printed as JSON, so that CI can track the performance over time.
+//! It is built on the test utilities of the consensus crates, hence it requires
+//! the `loadtest` feature: `cargo run --release --features loadtest --bin loadtest`.
+#![allow(clippy::print_stdout)]
+use anyhow::Context as _;
+use clap::Parser;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use zksync_concurrency::{ctx, scope, time};
+use zksync_consensus_bft as bft;
+use zksync_consensus_executor as executor;
+use zksync_consensus_network::testonly::new_configs;
+use zksync_consensus_roles::validator;
+use zksync_consensus_storage::testonly::{in_memory, new_store};
+
+/// Command line arguments.
+#[derive(Debug, Parser)]
+struct Args {
+    /// Number of validators in the committee.
+    #[arg(long, default_value_t = 4)]
+    validators: usize,
+    /// Size of the proposed payloads in bytes.
+    #[arg(long, default_value_t = 1024)]
+    payload_size: usize,
+    /// Max number of proposed payloads per second. 0 means unlimited.
+    #[arg(long, default_value_t = 0.)]
+    rate: f64,
+    /// Duration of the measurement in seconds.
+    #[arg(long, default_value_t = 30)]
+    duration_secs: u64,
+    /// Number of the initial blocks excluded from the measurement.
+    #[arg(long, default_value_t = 5)]
+    warmup_blocks: u64,
+}
+
+/// Payload manager proposing payloads of a fixed size at a fixed rate.
+/// It records when each block was proposed, to measure the finalization latency.
+#[derive(Debug)]
+struct LoadPayload {
+    size: usize,
+    /// Min interval between the proposals.
+    interval: time::Duration,
+    /// Earliest time of the next proposal.
+    next: Mutex<time::Instant>,
+    proposed: Arc<Mutex<HashMap<validator::BlockNumber, time::Instant>>>,
+}
+
+#[async_trait::async_trait]
+impl bft::PayloadManager for LoadPayload {
+    async fn propose(
+        &self,
+        ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<validator::Payload> {
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let slot = (*next).max(ctx.now());
+            *next = slot + self.interval;
+            slot
+        };
+        ctx.sleep_until(slot).await?;
+        self.proposed
+            .lock()
+            .unwrap()
+            .entry(number)
+            .or_insert(ctx.now());
+        Ok(validator::Payload(vec![0; self.size]))
+    }
+
+    async fn verify(
+        &self,
+        _ctx: &ctx::Ctx,
+        _number: validator::BlockNumber,
+        _payload: &validator::Payload,
+    ) -> ctx::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the `p`-th percentile of the sorted `samples`.
+fn percentile(samples: &[time::Duration], p: usize) -> time::Duration {
+    samples[(samples.len() - 1) * p / 100]
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    anyhow::ensure!(args.validators > 0, "at least 1 validator is required");
+    let ctx = &ctx::root();
+    let rng = &mut ctx.rng();
+
+    let setup = validator::testonly::Setup::new(rng, args.validators);
+    let cfgs = new_configs(rng, &setup, 1);
+    let interval = match args.rate {
+        r if r > 0. => time::Duration::seconds_f64(1. / r),
+        _ => time::Duration::ZERO,
+    };
+    let proposed = Arc::new(Mutex::new(HashMap::new()));
+
+    let report = scope::run!(ctx, |ctx, s| async {
+        let mut stores = vec![];
+        for cfg in &cfgs {
+            let (store, runner) = new_store(ctx, &setup.genesis).await;
+            s.spawn_bg(runner.run(ctx));
+            stores.push(store.clone());
+            let config = executor::Config {
+                server_addr: *cfg.server_addr,
+                transport: cfg.transport.clone(),
+                public_addr: cfg.public_addr,
+                public_addr_detection: cfg.public_addr_detection,
+                max_payload_size: args.payload_size,
+                node_key: cfg.gossip.key.clone(),
+                gossip_dynamic_inbound_limit: cfg.gossip.dynamic_inbound_limit,
+                gossip_static_inbound: cfg.gossip.static_inbound.clone(),
+                gossip_static_outbound: cfg.gossip.static_outbound.clone(),
+                gossip_dynamic_outbound_limit: cfg.gossip.dynamic_outbound_limit,
+                gossip_relay_auth: cfg.gossip.relay_auth,
+                gossip_peer_bandwidth_cap: cfg.gossip.peer_bandwidth_cap,
+                genesis_mismatch_quarantine: cfg.genesis_mismatch_quarantine,
+                consensus_replay_window: cfg.consensus_replay_window,
+                consensus_relay_max_hops: cfg.consensus_relay_max_hops,
+                serve_blocks_bandwidth: cfg.serve_blocks_bandwidth,
+                serve_blocks_bandwidth_per_peer: cfg.serve_blocks_bandwidth_per_peer,
+                per_peer_metrics: cfg.per_peer_metrics,
+                rpc: cfg.rpc.clone(),
+                sentry: None,
+                restart_policy: executor::RestartPolicy::default(),
+                crash_dir: None,
+                ntp_servers: vec![],
+                max_clock_skew: zksync_consensus_network::MAX_CLOCK_SKEW,
+                sync_blocks_cross_check_peers: 1,
+                sync_blocks_sample_chunks: 0,
+                sync_blocks_genesis_check_quorum: None,
+                role: executor::NodeRole::Archive,
+            };
+            let mut builder = executor::Executor::builder(config, store);
+            if let Some(key) = &cfg.validator_key {
+                builder = builder.validator(executor::Validator {
+                    key: key.clone(),
+                    replica_store: Box::new(in_memory::ReplicaStore::default()),
+                    payload_manager: Box::new(LoadPayload {
+                        size: args.payload_size,
+                        interval,
+                        next: Mutex::new(ctx.now()),
+                        proposed: proposed.clone(),
+                    }),
+                    shadow_proposer: false,
+                    verifier_threads: 2,
+                    catch_up_threshold: None,
+                    observer: false,
+                    max_payload_wait: None,
+                    checkpoint: None,
+                    consensus_lock: None,
+                    protocol_upgrade_activation: None,
+                    consensus_trace: None,
+                    key_rotation: None,
+                });
+            }
+            let executor = builder.build().context("build()")?;
+            s.spawn_bg(executor.run(ctx));
+        }
+
+        // Measure the blocks finalized by the first validator.
+        let store = &stores[0];
+        let first = validator::BlockNumber(setup.genesis.fork.first_block.0 + args.warmup_blocks);
+        store.wait_until_queued(ctx, first).await?;
+        let start = ctx.now();
+        let end = start + time::Duration::seconds(args.duration_secs.try_into()?);
+        let mut latencies = vec![];
+        let mut next = first.next();
+        while let Ok(()) = store
+            .wait_until_queued(&ctx.with_deadline(end.into()), next)
+            .await
+        {
+            let finalized = ctx.now();
+            if let Some(proposed) = proposed.lock().unwrap().get(&next) {
+                latencies.push(finalized - *proposed);
+            }
+            next = next.next();
+        }
+        let elapsed = ctx.now() - start;
+        anyhow::ensure!(!latencies.is_empty(), "no blocks finalized");
+        latencies.sort();
+        let blocks = latencies.len();
+        let ms = |d: time::Duration| d.as_seconds_f64() * 1000.;
+        Ok(serde_json::json!({
+            "validators": args.validators,
+            "payload_size": args.payload_size,
+            "rate": args.rate,
+            "blocks": blocks,
+            "duration_s": elapsed.as_seconds_f64(),
+            "blocks_per_s": blocks as f64 / elapsed.as_seconds_f64(),
+            "bytes_per_s": (blocks * args.payload_size) as f64 / elapsed.as_seconds_f64(),
+            "latency_ms": {
+                "p50": ms(percentile(&latencies, 50)),
+                "p90": ms(percentile(&latencies, 90)),
+                "p99": ms(percentile(&latencies, 99)),
+                "max": ms(*latencies.last().unwrap()),
+            },
+        }))
+    })
+    .await
+    .context("loadtest")?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
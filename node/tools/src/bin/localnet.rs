@@ -0,0 +1,345 @@
+//! Localnet orchestration: generates the keys, the genesis and the configs of a local network
+//! of the given topology, and runs it as local processes (or via docker compose),
+//! optionally pausing and resuming the node processes according to a script.
+use anyhow::Context as _;
+use clap::{Parser, Subcommand, ValueEnum};
+use rand::Rng as _;
+use std::{
+    fmt::Write as _,
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    process,
+};
+use zksync_concurrency::{ctx, scope, time};
+use zksync_consensus_crypto::TextFmt;
+use zksync_consensus_roles::{node, validator};
+use zksync_consensus_tools::AppConfig;
+
+/// Subnet of the docker compose network. Node `i` gets the address `172.12.0.{10+i}`.
+const DOCKER_SUBNET: [u8; 3] = [172, 12, 0];
+
+/// Command line arguments.
+#[derive(Debug, Parser)]
+#[command(name = "localnet")]
+struct Cli {
+    /// Subcommand to run.
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Subcommands.
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Generate the keys, the genesis and the configs of the nodes.
+    Generate(GenerateArgs),
+    /// Run the nodes generated with `generate` as local processes.
+    Run(RunArgs),
+}
+
+/// Gossip network topology.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Topology {
+    /// Every node is connected to every other node.
+    Mesh,
+    /// Node `i` is connected to node `i+1`, and the last node to the first one.
+    Ring,
+    /// All nodes are connected to node 0.
+    Star,
+    /// Every node dials 2 peers, so that the network has a low diameter.
+    Optimal,
+}
+
+impl Topology {
+    /// Returns the gossip connections `(i,j)` (node `i` dials node `j`) in a network of `n` nodes.
+    fn edges(self, n: usize) -> Vec<(usize, usize)> {
+        match self {
+            Self::Mesh => (0..n)
+                .flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+                .collect(),
+            Self::Ring if n > 1 => (0..n).map(|i| (i, (i + 1) % n)).collect(),
+            Self::Star => (1..n).map(|i| (i, 0)).collect(),
+            Self::Optimal if n > 1 => (0..n)
+                .flat_map(|i| (0..2).map(move |j| (i, (i * 2 + j + 1) % n)))
+                .filter(|(i, j)| i != j)
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/// Arguments of the `generate` subcommand.
+#[derive(Debug, Parser)]
+struct GenerateArgs {
+    /// Number of validators. Nodes `0..validators` are the validators.
+    #[arg(long, default_value_t = 4)]
+    validators: usize,
+    /// Number of full nodes (non-validators).
+    #[arg(long, default_value_t = 0)]
+    full_nodes: usize,
+    /// Gossip network topology.
+    #[arg(long, value_enum, default_value_t = Topology::Optimal)]
+    topology: Topology,
+    /// Port of the first node. Local nodes listen on consecutive ports.
+    #[arg(long, default_value_t = 3054)]
+    base_port: u16,
+    /// Generate the configs for docker compose (one container per node, all listening
+    /// on `base_port`) rather than for the local processes.
+    #[arg(long)]
+    docker: bool,
+    /// Block payload size in bytes.
+    #[arg(long, default_value_t = 1000000)]
+    payload_size: usize,
+    /// Directory in which the configs should be created.
+    /// Config of node `i` is in directory `<output_dir>/node_<i>/`.
+    #[arg(long, default_value = "./localnet")]
+    output_dir: PathBuf,
+}
+
+/// Arguments of the `run` subcommand.
+#[derive(Debug, Parser)]
+struct RunArgs {
+    /// Directory with the configs generated by `generate`.
+    #[arg(long, default_value = "./localnet")]
+    dir: PathBuf,
+    /// Path to the executor binary.
+    #[arg(long, default_value = "./executor")]
+    executor: PathBuf,
+    /// Path to a pause script. Every line has the form `<seconds> <pause|resume> <nodes>`,
+    /// where `<nodes>` is a comma separated list of node indices. Paused processes are
+    /// stopped (SIGSTOP) until resumed: they don't handle any traffic, but their connections
+    /// stay open, so this simulates stalled nodes rather than a network partition.
+    /// Lines starting with `#` are ignored.
+    #[arg(long)]
+    pause_script: Option<PathBuf>,
+}
+
+/// Generates the configs of the nodes.
+fn generate(args: &GenerateArgs) -> anyhow::Result<()> {
+    let nodes = args.validators + args.full_nodes;
+    anyhow::ensure!(args.validators > 0, "at least 1 validator is required");
+    if args.docker {
+        anyhow::ensure!(nodes <= 240, "too many nodes for the docker subnet");
+    }
+    let rng = &mut rand::thread_rng();
+    let setup = validator::testonly::Setup::new(rng, args.validators);
+    let node_keys: Vec<node::SecretKey> = (0..nodes).map(|_| rng.gen()).collect();
+    let addrs: Vec<SocketAddr> = (0..nodes)
+        .map(|i| match args.docker {
+            true => {
+                let [a, b, c] = DOCKER_SUBNET;
+                SocketAddr::new(Ipv4Addr::new(a, b, c, 10 + i as u8).into(), args.base_port)
+            }
+            false => SocketAddr::new(
+                Ipv4Addr::LOCALHOST.into(),
+                args.base_port + u16::try_from(i).unwrap(),
+            ),
+        })
+        .collect();
+
+    let mut default_config = AppConfig::default_for(setup.genesis.clone());
+    default_config.with_max_payload_size(args.payload_size);
+    let mut cfgs: Vec<_> = addrs
+        .iter()
+        .map(|addr| {
+            let mut cfg = default_config.clone();
+            cfg.with_public_addr(*addr);
+            cfg.with_server_addr(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), addr.port()));
+            cfg
+        })
+        .collect();
+    for (i, j) in args.topology.edges(nodes) {
+        cfgs[i].add_gossip_static_outbound(node_keys[j].public(), addrs[j]);
+        cfgs[j].add_gossip_static_inbound(node_keys[i].public());
+    }
+
+    let _ = fs::remove_dir_all(&args.output_dir);
+    for (i, cfg) in cfgs.into_iter().enumerate() {
+        let root = args.output_dir.join(format!("node_{i}"));
+        fs::create_dir_all(&root).with_context(|| format!("create_dir_all({root:?})"))?;
+        cfg.write_to_file(&root)?;
+        fs::write(root.join("node_key"), TextFmt::encode(&node_keys[i])).context("fs::write()")?;
+        if let Some(key) = setup.keys.get(i) {
+            fs::write(root.join("validator_key"), TextFmt::encode(key)).context("fs::write()")?;
+        }
+    }
+    if args.docker {
+        let compose = docker_compose(&addrs, args.validators);
+        fs::write(args.output_dir.join("compose.yaml"), compose).context("fs::write()")?;
+    }
+    Ok(())
+}
+
+/// Generates a docker compose file running a container per node.
+/// The configs are mounted from the directory of the compose file.
+/// Nodes `0..validators` are the validators.
+fn docker_compose(addrs: &[SocketAddr], validators: usize) -> String {
+    let [a, b, c] = DOCKER_SUBNET;
+    let mut out = String::from("version: \"3.9\"\n\nservices:\n");
+    for (i, addr) in addrs.iter().enumerate() {
+        let IpAddr::V4(ip) = addr.ip() else {
+            unreachable!()
+        };
+        let _ = write!(
+            out,
+            "  node-{i}:
+    image: consensus-node
+    container_name: consensus-node-{i}
+    environment:
+      - NODE_ID=node_{i}
+    volumes:
+      - ./node_{i}:/node/docker_config/node_{i}
+    networks:
+      node_net:
+        ipv4_address: {ip}
+"
+        );
+        if i >= validators {
+            out.push_str("    command: [\"--validator-key\", \"\"]\n");
+        }
+    }
+    let _ = write!(
+        out,
+        "
+networks:
+  node_net:
+    name: node-net
+    ipam:
+      config:
+        - subnet: \"{a}.{b}.{c}.0/24\"
+          gateway: \"{a}.{b}.{c}.1\"
+"
+    );
+    out
+}
+
+/// Step of a pause script.
+#[derive(Debug)]
+struct PauseStep {
+    /// Time since the start of the network.
+    at: time::Duration,
+    /// Whether to pause (or resume) the nodes.
+    pause: bool,
+    /// Indices of the nodes.
+    nodes: Vec<usize>,
+}
+
+/// Parses a pause script.
+fn parse_pause_script(script: &str) -> anyhow::Result<Vec<PauseStep>> {
+    let mut steps = vec![];
+    for line in script.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let ctx = || format!("invalid line {line:?}");
+        let mut parts = line.split_whitespace();
+        let (Some(at), Some(action), Some(nodes), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            anyhow::bail!(ctx());
+        };
+        steps.push(PauseStep {
+            at: time::Duration::seconds_f64(at.parse().with_context(ctx)?),
+            pause: match action {
+                "pause" => true,
+                "resume" => false,
+                _ => anyhow::bail!(ctx()),
+            },
+            nodes: nodes
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .with_context(ctx)?,
+        });
+    }
+    steps.sort_by_key(|s| s.at);
+    Ok(steps)
+}
+
+/// Runs the nodes as local processes, until interrupted.
+async fn run(args: &RunArgs) -> anyhow::Result<()> {
+    let executor = fs::canonicalize(&args.executor)
+        .with_context(|| format!("canonicalize({:?})", args.executor))?;
+    let steps = match &args.pause_script {
+        Some(path) => {
+            parse_pause_script(&fs::read_to_string(path).context("fs::read_to_string()")?)?
+        }
+        None => vec![],
+    };
+    let mut dirs = vec![];
+    loop {
+        let dir = args.dir.join(format!("node_{}", dirs.len()));
+        if !dir.is_dir() {
+            break;
+        }
+        dirs.push(dir);
+    }
+    anyhow::ensure!(!dirs.is_empty(), "no node configs in {:?}", args.dir);
+
+    let mut children = vec![];
+    for dir in &dirs {
+        children.push(spawn_node(&executor, dir)?);
+    }
+    let ctx = &ctx::root();
+    let res = scope::run!(ctx, |ctx, s| async {
+        s.spawn_bg(async {
+            let start = ctx.now();
+            for step in &steps {
+                ctx.sleep_until(start + step.at).await?;
+                for i in &step.nodes {
+                    let child = children.get(*i).with_context(|| format!("no node {i}"))?;
+                    signal(child, if step.pause { "STOP" } else { "CONT" })?;
+                }
+                tracing::info!(
+                    "{} nodes {:?}",
+                    if step.pause { "paused" } else { "resumed" },
+                    step.nodes
+                );
+            }
+            Ok(())
+        });
+        ctx.wait(tokio::signal::ctrl_c()).await??;
+        anyhow::Ok(())
+    })
+    .await;
+    for child in &mut children {
+        // Paused processes have to be resumed to handle the termination.
+        let _ = signal(child, "CONT");
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+    res
+}
+
+/// Starts a node in `dir`.
+fn spawn_node(executor: &Path, dir: &Path) -> anyhow::Result<process::Child> {
+    let mut cmd = process::Command::new(executor);
+    cmd.current_dir(dir)
+        .args(["--config-file", "config.json", "--node-key", "node_key"])
+        .args(["--database", "database"]);
+    // Full nodes have no validator key.
+    if !dir.join("validator_key").exists() {
+        cmd.args(["--validator-key", ""]);
+    }
+    cmd.spawn().with_context(|| format!("spawn({dir:?})"))
+}
+
+/// Sends a signal to a node process.
+fn signal(child: &process::Child, signal: &str) -> anyhow::Result<()> {
+    let status = process::Command::new("kill")
+        .arg(format!("-{signal}"))
+        .arg(child.id().to_string())
+        .status()
+        .context("kill")?;
+    anyhow::ensure!(status.success(), "kill -{signal} {}: {status}", child.id());
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    match Cli::parse().command {
+        Command::Generate(args) => generate(&args),
+        Command::Run(args) => run(&args).await,
+    }
+}
@@ -1,5 +1,12 @@
 //! Node configuration.
-use crate::{proto, store};
+use crate::{
+    genesis,
+    keystore::{self, Passphrase},
+    loader::{self, ValidationErrors},
+    proto,
+    remote_signer::{RemoteSigner, RemoteSignerConfig},
+    store,
+};
 use anyhow::Context as _;
 use serde_json::{ser::Formatter, Serializer};
 use std::net::Ipv4Addr;
@@ -9,14 +16,15 @@ use std::{
     fs,
     net::SocketAddr,
     path::{Path, PathBuf},
+    sync::Arc,
 };
-use zksync_concurrency::ctx;
+use zksync_concurrency::{ctx, limiter, time};
 use zksync_consensus_bft as bft;
 use zksync_consensus_crypto::{read_optional_text, read_required_text, Text, TextFmt};
 use zksync_consensus_executor as executor;
 use zksync_consensus_roles::{node, validator};
-use zksync_consensus_storage::{BlockStore, BlockStoreRunner};
-use zksync_protobuf::{read_required, required, serde::Serde, ProtoFmt};
+use zksync_consensus_storage::{BlockStore, BlockStoreRunner, FileCheckpointStore};
+use zksync_protobuf::{read_optional, read_required, required, serde::Serde, ProtoFmt};
 
 /// Ports for the nodes to listen on kubernetes pod.
 pub const NODES_PORT: u16 = 3054;
@@ -30,7 +38,7 @@ pub fn decode_json<T: serde::de::DeserializeOwned>(json: &str) -> anyhow::Result
 }
 
 /// Encodes a generated proto message to json for arbitrary ProtoFmt.
-pub(crate) fn encode_json<T: serde::ser::Serialize>(x: &T) -> String {
+pub fn encode_json<T: serde::ser::Serialize>(x: &T) -> String {
     let s = serde_json::Serializer::pretty(vec![]);
     encode_with_serializer(x, s)
 }
@@ -68,13 +76,167 @@ impl ProtoFmt for NodeAddr {
     }
 }
 
+impl ProtoFmt for RemoteSignerConfig {
+    type Proto = proto::RemoteSigner;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            url: required(&r.url).context("url")?.clone(),
+            public_key: read_required_text(&r.public_key).context("public_key")?,
+            request_timeout: match r.request_timeout_ms {
+                Some(ms) => {
+                    time::Duration::milliseconds(ms.try_into().context("request_timeout_ms")?)
+                }
+                None => Self::DEFAULT_REQUEST_TIMEOUT,
+            },
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            url: Some(self.url.clone()),
+            public_key: Some(TextFmt::encode(&self.public_key)),
+            request_timeout_ms: Some(
+                self.request_timeout
+                    .whole_milliseconds()
+                    .try_into()
+                    .unwrap(),
+            ),
+        }
+    }
+}
+
+fn read_relay_auth(r: &Option<i32>) -> anyhow::Result<executor::RelayAuth> {
+    let Some(r) = r else {
+        return Ok(executor::RelayAuth::default());
+    };
+    Ok(match proto::RelayAuth::try_from(*r)? {
+        proto::RelayAuth::Disabled => executor::RelayAuth::Disabled,
+        proto::RelayAuth::Sign => executor::RelayAuth::Sign,
+        proto::RelayAuth::Require => executor::RelayAuth::Require,
+    })
+}
+
+fn build_relay_auth(x: executor::RelayAuth) -> proto::RelayAuth {
+    match x {
+        executor::RelayAuth::Disabled => proto::RelayAuth::Disabled,
+        executor::RelayAuth::Sign => proto::RelayAuth::Sign,
+        executor::RelayAuth::Require => proto::RelayAuth::Require,
+    }
+}
+
+fn read_node_role(
+    role: &Option<i32>,
+    retention: &Option<u64>,
+) -> anyhow::Result<executor::NodeRole> {
+    let Some(role) = role else {
+        return Ok(executor::NodeRole::default());
+    };
+    Ok(match proto::NodeRole::try_from(*role)? {
+        proto::NodeRole::Archive => executor::NodeRole::Archive,
+        proto::NodeRole::Pruned => {
+            let retention = *required(retention).context("node_retention")?;
+            anyhow::ensure!(retention > 0, "node_retention has to be positive");
+            executor::NodeRole::Pruned { retention }
+        }
+        proto::NodeRole::Minimal => executor::NodeRole::Minimal,
+    })
+}
+
+fn build_node_role(x: executor::NodeRole) -> (proto::NodeRole, Option<u64>) {
+    match x {
+        executor::NodeRole::Archive => (proto::NodeRole::Archive, None),
+        executor::NodeRole::Pruned { retention } => (proto::NodeRole::Pruned, Some(retention)),
+        executor::NodeRole::Minimal => (proto::NodeRole::Minimal, None),
+    }
+}
+
+fn read_rate(r: &proto::RateLimit) -> anyhow::Result<limiter::Rate> {
+    Ok(limiter::Rate {
+        burst: (*required(&r.burst).context("burst")?).try_into()?,
+        refresh: time::Duration::milliseconds(
+            (*required(&r.refresh_ms).context("refresh_ms")?).try_into()?,
+        ),
+    })
+}
+
+fn build_rate(x: &limiter::Rate) -> proto::RateLimit {
+    proto::RateLimit {
+        burst: Some(x.burst.try_into().unwrap()),
+        refresh_ms: Some(x.refresh.whole_milliseconds().try_into().unwrap()),
+    }
+}
+
+/// Reads the rate limits, taking the defaults for the unset ones.
+fn read_rpc_rate_limits(r: &Option<proto::RpcRateLimits>) -> anyhow::Result<executor::RpcConfig> {
+    let mut cfg = executor::RpcConfig::default();
+    let Some(r) = r else {
+        return Ok(cfg);
+    };
+    for (rate, r, name) in [
+        (
+            &mut cfg.push_validator_addrs_rate,
+            &r.push_validator_addrs,
+            "push_validator_addrs",
+        ),
+        (
+            &mut cfg.push_block_store_state_rate,
+            &r.push_block_store_state,
+            "push_block_store_state",
+        ),
+        (&mut cfg.get_block_rate, &r.get_block, "get_block"),
+        (
+            &mut cfg.get_block_chunk_rate,
+            &r.get_block_chunk,
+            "get_block_chunk",
+        ),
+        (&mut cfg.consensus_rate, &r.consensus, "consensus"),
+        (
+            &mut cfg.push_validator_addrs_server_rate,
+            &r.push_validator_addrs_server,
+            "push_validator_addrs_server",
+        ),
+        (
+            &mut cfg.push_block_store_state_server_rate,
+            &r.push_block_store_state_server,
+            "push_block_store_state_server",
+        ),
+        (
+            &mut cfg.get_block_server_rate,
+            &r.get_block_server,
+            "get_block_server",
+        ),
+    ] {
+        if let Some(r) = r {
+            *rate = read_rate(r).context(name)?;
+        }
+    }
+    Ok(cfg)
+}
+
+fn build_rpc_rate_limits(x: &executor::RpcConfig) -> proto::RpcRateLimits {
+    proto::RpcRateLimits {
+        push_validator_addrs: Some(build_rate(&x.push_validator_addrs_rate)),
+        push_block_store_state: Some(build_rate(&x.push_block_store_state_rate)),
+        get_block: Some(build_rate(&x.get_block_rate)),
+        get_block_chunk: Some(build_rate(&x.get_block_chunk_rate)),
+        consensus: Some(build_rate(&x.consensus_rate)),
+        push_validator_addrs_server: Some(build_rate(&x.push_validator_addrs_server_rate)),
+        push_block_store_state_server: Some(build_rate(&x.push_block_store_state_server_rate)),
+        get_block_server: Some(build_rate(&x.get_block_server_rate)),
+    }
+}
+
 /// Node configuration including executor configuration, optional validator configuration,
 /// and application-specific settings (e.g. metrics scraping).
 #[derive(Debug, PartialEq, Clone)]
 pub struct AppConfig {
     pub server_addr: SocketAddr,
+    pub unix_socket_dir: Option<PathBuf>,
     pub public_addr: SocketAddr,
+    pub public_addr_detection: Option<executor::PublicAddrDetection>,
     pub metrics_server_addr: Option<SocketAddr>,
+    pub log_filter: Option<String>,
 
     pub genesis: validator::Genesis,
     pub max_payload_size: usize,
@@ -82,51 +244,243 @@ pub struct AppConfig {
     pub gossip_dynamic_inbound_limit: usize,
     pub gossip_static_inbound: HashSet<node::PublicKey>,
     pub gossip_static_outbound: HashMap<node::PublicKey, SocketAddr>,
+    pub gossip_dynamic_outbound_limit: usize,
+    pub gossip_relay_auth: executor::RelayAuth,
+    pub gossip_peer_bandwidth_cap: Option<usize>,
+    pub genesis_mismatch_quarantine: time::Duration,
+    pub consensus_replay_window: u64,
+    pub consensus_relay_max_hops: u32,
+    pub serve_blocks_bandwidth: Option<usize>,
+    pub serve_blocks_bandwidth_per_peer: Option<usize>,
+    pub per_peer_metrics: bool,
+    pub rpc_rate_limits: executor::RpcConfig,
+
+    pub remote_signer: Option<RemoteSignerConfig>,
+    pub shadow_proposer: bool,
+    pub verifier_threads: usize,
+    pub catch_up_threshold: Option<u64>,
+    pub observer: bool,
+    pub max_payload_wait: Option<time::Duration>,
+    pub replica_checkpoint_dir: Option<PathBuf>,
+    pub consensus_lock_dir: Option<PathBuf>,
+    pub protocol_upgrade_activation: Option<validator::BlockNumber>,
+    pub consensus_trace: Option<PathBuf>,
+    pub key_rotation: Option<validator::KeyRotationCert>,
+    pub crash_dir: Option<PathBuf>,
+    pub ntp_servers: Vec<String>,
+    pub max_clock_skew: time::Duration,
+    pub sync_blocks_cross_check_peers: usize,
+    pub sync_blocks_sample_chunks: usize,
+    pub sync_blocks_genesis_check_quorum: Option<usize>,
+    pub node_role: executor::NodeRole,
 }
 
 impl ProtoFmt for AppConfig {
     type Proto = proto::AppConfig;
 
+    /// Decodes all the fields independently, so that the errors in all of them are reported
+    /// at once (as `ValidationErrors`), rather than just the first one.
     fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        let mut errs = ValidationErrors::default();
+        let server_addr = errs.check(read_required_text(&r.server_addr).context("server_addr"));
+        let unix_socket_dir = errs.check(
+            r.unix_socket_dir
+                .as_ref()
+                .map(|dir| {
+                    anyhow::ensure!(
+                        cfg!(unix),
+                        "unix sockets are not supported on this platform"
+                    );
+                    Ok(PathBuf::from(dir))
+                })
+                .transpose()
+                .context("unix_socket_dir"),
+        );
+        let public_addr = errs.check(read_required_text(&r.public_addr).context("public_addr"));
+        let public_addr_detection = errs.check(
+            r.public_addr_detection_min_reports
+                .map(|x| {
+                    anyhow::Ok(executor::PublicAddrDetection {
+                        min_reports: x.try_into()?,
+                    })
+                })
+                .transpose()
+                .context("public_addr_detection_min_reports"),
+        );
+        let metrics_server_addr =
+            errs.check(read_optional_text(&r.metrics_server_addr).context("metrics_server_addr"));
+
+        let genesis = errs.check(read_required(&r.genesis).context("genesis"));
+        let max_payload_size = errs.check(
+            required(&r.max_payload_size)
+                .and_then(|x| Ok((*x).try_into()?))
+                .context("max_payload_size"),
+        );
+
+        let gossip_dynamic_inbound_limit = errs.check(
+            required(&r.gossip_dynamic_inbound_limit)
+                .and_then(|x| Ok((*x).try_into()?))
+                .context("gossip_dynamic_inbound_limit"),
+        );
         let mut gossip_static_inbound = HashSet::new();
         for (i, v) in r.gossip_static_inbound.iter().enumerate() {
-            gossip_static_inbound.insert(
+            if let Ok(key) = errs.check(
                 Text::new(v)
                     .decode()
-                    .with_context(|| format!("gossip_static_inbound[{i}]"))?,
-            );
+                    .with_context(|| format!("gossip_static_inbound[{i}]")),
+            ) {
+                gossip_static_inbound.insert(key);
+            }
         }
-
         let mut gossip_static_outbound = HashMap::new();
         for (i, e) in r.gossip_static_outbound.iter().enumerate() {
-            let node_addr: NodeAddr =
-                ProtoFmt::read(e).with_context(|| format!("gossip_static_outbound[{i}]"))?;
-            gossip_static_outbound.insert(node_addr.key, node_addr.addr);
+            if let Ok(node_addr) = errs.check::<NodeAddr>(
+                ProtoFmt::read(e).with_context(|| format!("gossip_static_outbound[{i}]")),
+            ) {
+                gossip_static_outbound.insert(node_addr.key, node_addr.addr);
+            }
         }
+        let gossip_dynamic_outbound_limit = errs.check(
+            r.gossip_dynamic_outbound_limit
+                .map(usize::try_from)
+                .transpose()
+                .context("gossip_dynamic_outbound_limit"),
+        );
+        let gossip_relay_auth =
+            errs.check(read_relay_auth(&r.gossip_relay_auth).context("gossip_relay_auth"));
+        let genesis_mismatch_quarantine = errs.check(
+            r.genesis_mismatch_quarantine_ms
+                .map(|ms| anyhow::Ok(time::Duration::milliseconds(ms.try_into()?)))
+                .transpose()
+                .context("genesis_mismatch_quarantine_ms"),
+        );
+        let max_clock_skew = errs.check(
+            r.max_clock_skew_ms
+                .map(|ms| anyhow::Ok(time::Duration::milliseconds(ms.try_into()?)))
+                .transpose()
+                .context("max_clock_skew_ms"),
+        );
+        let node_role =
+            errs.check(read_node_role(&r.node_role, &r.node_retention).context("node_role"));
+        let sync_blocks_cross_check_peers = errs.check(
+            r.sync_blocks_cross_check_peers
+                .map(usize::try_from)
+                .transpose()
+                .context("sync_blocks_cross_check_peers"),
+        );
+        let sync_blocks_sample_chunks = errs.check(
+            r.sync_blocks_sample_chunks
+                .map(usize::try_from)
+                .transpose()
+                .context("sync_blocks_sample_chunks"),
+        );
+        let sync_blocks_genesis_check_quorum = errs.check(
+            r.sync_blocks_genesis_check_quorum
+                .map(usize::try_from)
+                .transpose()
+                .context("sync_blocks_genesis_check_quorum"),
+        );
+        let gossip_peer_bandwidth_cap = errs.check(
+            r.gossip_peer_bandwidth_cap
+                .map(usize::try_from)
+                .transpose()
+                .context("gossip_peer_bandwidth_cap"),
+        );
+        let serve_blocks_bandwidth = errs.check(
+            r.serve_blocks_bandwidth
+                .map(usize::try_from)
+                .transpose()
+                .context("serve_blocks_bandwidth"),
+        );
+        let serve_blocks_bandwidth_per_peer = errs.check(
+            r.serve_blocks_bandwidth_per_peer
+                .map(usize::try_from)
+                .transpose()
+                .context("serve_blocks_bandwidth_per_peer"),
+        );
+        let rpc_rate_limits =
+            errs.check(read_rpc_rate_limits(&r.rpc_rate_limits).context("rpc_rate_limits"));
+
+        let remote_signer = errs.check(read_optional(&r.remote_signer).context("remote_signer"));
+        let key_rotation = errs.check(read_optional(&r.key_rotation).context("key_rotation"));
+        let verifier_threads = errs.check(
+            r.verifier_threads
+                .map(usize::try_from)
+                .transpose()
+                .context("verifier_threads"),
+        );
+        let max_payload_wait = errs.check(
+            r.max_payload_wait_ms
+                .map(|ms| anyhow::Ok(time::Duration::milliseconds(ms.try_into()?)))
+                .transpose()
+                .context("max_payload_wait_ms"),
+        );
+        errs.finish()?;
+
+        // All the fields have been validated above.
         Ok(Self {
-            server_addr: read_required_text(&r.server_addr).context("server_addr")?,
-            public_addr: read_required_text(&r.public_addr).context("public_addr")?,
-            metrics_server_addr: read_optional_text(&r.metrics_server_addr)
-                .context("metrics_server_addr")?,
+            server_addr: server_addr?,
+            unix_socket_dir: unix_socket_dir?,
+            public_addr: public_addr?,
+            public_addr_detection: public_addr_detection?,
+            metrics_server_addr: metrics_server_addr?,
+            log_filter: r.log_filter.clone(),
 
-            genesis: read_required(&r.genesis).context("genesis")?,
-            max_payload_size: required(&r.max_payload_size)
-                .and_then(|x| Ok((*x).try_into()?))
-                .context("max_payload_size")?,
+            genesis: genesis?,
+            max_payload_size: max_payload_size?,
 
-            gossip_dynamic_inbound_limit: required(&r.gossip_dynamic_inbound_limit)
-                .and_then(|x| Ok((*x).try_into()?))
-                .context("gossip_dynamic_inbound_limit")?,
+            gossip_dynamic_inbound_limit: gossip_dynamic_inbound_limit?,
             gossip_static_inbound,
             gossip_static_outbound,
+            gossip_dynamic_outbound_limit: gossip_dynamic_outbound_limit?.unwrap_or(0),
+            gossip_relay_auth: gossip_relay_auth?,
+            gossip_peer_bandwidth_cap: gossip_peer_bandwidth_cap?,
+            genesis_mismatch_quarantine: genesis_mismatch_quarantine?
+                .unwrap_or(Self::DEFAULT_GENESIS_MISMATCH_QUARANTINE),
+            consensus_replay_window: r
+                .consensus_replay_window
+                .unwrap_or(Self::DEFAULT_CONSENSUS_REPLAY_WINDOW),
+            consensus_relay_max_hops: r.consensus_relay_max_hops.unwrap_or(0),
+            serve_blocks_bandwidth: serve_blocks_bandwidth?,
+            serve_blocks_bandwidth_per_peer: serve_blocks_bandwidth_per_peer?,
+            per_peer_metrics: r.per_peer_metrics.unwrap_or(false),
+            rpc_rate_limits: rpc_rate_limits?,
+
+            remote_signer: remote_signer?,
+            shadow_proposer: r.shadow_proposer.unwrap_or(false),
+            verifier_threads: verifier_threads?.unwrap_or(Self::DEFAULT_VERIFIER_THREADS),
+            catch_up_threshold: r.catch_up_threshold,
+            observer: r.observer.unwrap_or(false),
+            max_payload_wait: max_payload_wait?,
+            replica_checkpoint_dir: r.replica_checkpoint_dir.as_ref().map(PathBuf::from),
+            consensus_lock_dir: r.consensus_lock_dir.as_ref().map(PathBuf::from),
+            protocol_upgrade_activation: r.protocol_upgrade_activation.map(validator::BlockNumber),
+            consensus_trace: r.consensus_trace.as_ref().map(PathBuf::from),
+            key_rotation: key_rotation?,
+            crash_dir: r.crash_dir.as_ref().map(PathBuf::from),
+            ntp_servers: r.ntp_servers.clone(),
+            max_clock_skew: max_clock_skew?.unwrap_or(Self::DEFAULT_MAX_CLOCK_SKEW),
+            sync_blocks_cross_check_peers: sync_blocks_cross_check_peers?.unwrap_or(1),
+            sync_blocks_sample_chunks: sync_blocks_sample_chunks?.unwrap_or(0),
+            sync_blocks_genesis_check_quorum: sync_blocks_genesis_check_quorum?,
+            node_role: node_role?,
         })
     }
 
     fn build(&self) -> Self::Proto {
+        let (node_role, node_retention) = build_node_role(self.node_role);
         Self::Proto {
             server_addr: Some(self.server_addr.encode()),
+            unix_socket_dir: self
+                .unix_socket_dir
+                .as_ref()
+                .map(|dir| dir.to_string_lossy().into()),
             public_addr: Some(self.public_addr.encode()),
+            public_addr_detection_min_reports: self
+                .public_addr_detection
+                .map(|x| x.min_reports.try_into().unwrap()),
             metrics_server_addr: self.metrics_server_addr.as_ref().map(TextFmt::encode),
+            log_filter: self.log_filter.clone(),
 
             genesis: Some(self.genesis.build()),
             max_payload_size: Some(self.max_payload_size.try_into().unwrap()),
@@ -147,6 +501,65 @@ impl ProtoFmt for AppConfig {
                     addr: Some(TextFmt::encode(addr)),
                 })
                 .collect(),
+            gossip_dynamic_outbound_limit: Some(
+                self.gossip_dynamic_outbound_limit.try_into().unwrap(),
+            ),
+            gossip_relay_auth: Some(build_relay_auth(self.gossip_relay_auth).into()),
+            gossip_peer_bandwidth_cap: self
+                .gossip_peer_bandwidth_cap
+                .map(|x| x.try_into().unwrap()),
+            genesis_mismatch_quarantine_ms: Some(
+                self.genesis_mismatch_quarantine
+                    .whole_milliseconds()
+                    .try_into()
+                    .unwrap(),
+            ),
+            consensus_replay_window: Some(self.consensus_replay_window),
+            consensus_relay_max_hops: Some(self.consensus_relay_max_hops),
+            serve_blocks_bandwidth: self.serve_blocks_bandwidth.map(|x| x.try_into().unwrap()),
+            serve_blocks_bandwidth_per_peer: self
+                .serve_blocks_bandwidth_per_peer
+                .map(|x| x.try_into().unwrap()),
+            per_peer_metrics: Some(self.per_peer_metrics),
+            rpc_rate_limits: Some(build_rpc_rate_limits(&self.rpc_rate_limits)),
+
+            remote_signer: self.remote_signer.as_ref().map(ProtoFmt::build),
+            shadow_proposer: Some(self.shadow_proposer),
+            verifier_threads: Some(self.verifier_threads.try_into().unwrap()),
+            catch_up_threshold: self.catch_up_threshold,
+            observer: Some(self.observer),
+            max_payload_wait_ms: self
+                .max_payload_wait
+                .map(|d| d.whole_milliseconds().try_into().unwrap()),
+            replica_checkpoint_dir: self
+                .replica_checkpoint_dir
+                .as_ref()
+                .map(|dir| dir.to_string_lossy().into()),
+            consensus_lock_dir: self
+                .consensus_lock_dir
+                .as_ref()
+                .map(|dir| dir.to_string_lossy().into()),
+            protocol_upgrade_activation: self.protocol_upgrade_activation.map(|n| n.0),
+            consensus_trace: self
+                .consensus_trace
+                .as_ref()
+                .map(|path| path.to_string_lossy().into()),
+            key_rotation: self.key_rotation.as_ref().map(ProtoFmt::build),
+            crash_dir: self
+                .crash_dir
+                .as_ref()
+                .map(|dir| dir.to_string_lossy().into()),
+            ntp_servers: self.ntp_servers.clone(),
+            max_clock_skew_ms: Some(self.max_clock_skew.whole_milliseconds().try_into().unwrap()),
+            sync_blocks_cross_check_peers: Some(
+                self.sync_blocks_cross_check_peers.try_into().unwrap(),
+            ),
+            sync_blocks_sample_chunks: Some(self.sync_blocks_sample_chunks.try_into().unwrap()),
+            sync_blocks_genesis_check_quorum: self
+                .sync_blocks_genesis_check_quorum
+                .map(|x| x.try_into().unwrap()),
+            node_role: Some(node_role.into()),
+            node_retention,
         }
     }
 }
@@ -156,12 +569,16 @@ impl ProtoFmt for AppConfig {
 pub struct ConfigPaths<'a> {
     /// Path to a JSON file with node configuration.
     pub app: &'a Path,
+    /// Path to a JSON genesis file. If set, it overrides the genesis from the node configuration.
+    pub genesis: Option<&'a Path>,
     /// Path to a validator key file.
     pub validator_key: Option<&'a Path>,
     /// Path to a node key file.
     pub node_key: &'a Path,
     /// Path to the rocksdb database.
     pub database: &'a Path,
+    /// Passphrase of the encrypted key files.
+    pub key_passphrase: Option<Passphrase>,
 }
 
 pub struct Configs {
@@ -169,48 +586,77 @@ pub struct Configs {
     pub validator_key: Option<validator::SecretKey>,
     pub node_key: node::SecretKey,
     pub database: PathBuf,
+    /// Whether to start even if the replica state is older than its checkpoint.
+    /// See `bft::CheckpointConfig::allow_stale_state`.
+    pub allow_stale_replica_state: bool,
 }
 
 impl<'a> ConfigPaths<'a> {
+    /// Loads the node configuration (without the keys) from the file system.
+    /// The file can be in JSON, YAML or TOML format (see `loader::Format`),
+    /// and its fields can be overridden with the env vars (see `loader::ENV_PREFIX`).
+    pub fn load_app(&self) -> anyhow::Result<AppConfig> {
+        let mut app = loader::load(self.app, std::env::vars())
+            .with_context(|| self.app.display().to_string())?;
+        if let Some(path) = self.genesis {
+            app.genesis = genesis::read(path)?;
+        }
+        Ok(app)
+    }
+
     // Loads configs from the file system.
     pub fn load(self) -> anyhow::Result<Configs> {
         Ok(Configs {
-            app: (|| {
-                let app = fs::read_to_string(self.app).context("failed reading file")?;
-                decode_json::<Serde<AppConfig>>(&app).context("failed decoding JSON")
-            })()
-            .with_context(|| self.app.display().to_string())?
-            .0,
+            app: self.load_app()?,
 
             validator_key: self
                 .validator_key
                 .as_ref()
                 .map(|file| {
-                    (|| {
-                        let key = fs::read_to_string(file).context("failed reading file")?;
-                        Text::new(&key).decode().context("failed decoding key")
-                    })()
-                    .with_context(|| file.display().to_string())
+                    keystore::read_key(file, self.key_passphrase.as_ref())
+                        .with_context(|| file.display().to_string())
                 })
                 .transpose()?,
 
-            node_key: (|| {
-                let key = fs::read_to_string(self.node_key).context("failed reading file")?;
-                Text::new(&key).decode().context("failed decoding key")
-            })()
-            .with_context(|| self.node_key.display().to_string())?,
+            node_key: keystore::read_key(self.node_key, self.key_passphrase.as_ref())
+                .with_context(|| self.node_key.display().to_string())?,
 
             database: self.database.into(),
+            allow_stale_replica_state: false,
         })
     }
 }
 
 impl AppConfig {
+    /// Default time for which a peer with a different genesis is not redialed.
+    pub const DEFAULT_GENESIS_MISMATCH_QUARANTINE: time::Duration = time::Duration::minutes(10);
+    /// Default number of views within which the consensus messages are deduplicated.
+    pub const DEFAULT_CONSENSUS_REPLAY_WINDOW: u64 = 16;
+    /// Default max offset of the local clock against the NTP servers.
+    pub const DEFAULT_MAX_CLOCK_SKEW: time::Duration = time::Duration::seconds(5);
+    /// Default number of tasks verifying the signatures and the QCs of the received messages.
+    pub const DEFAULT_VERIFIER_THREADS: usize = 4;
+    /// Min interval between the checkpoints of the replica state.
+    pub const REPLICA_CHECKPOINT_INTERVAL: time::Duration = time::Duration::seconds(10);
+
+    /// Transport over which the node connects to the other nodes.
+    pub fn transport(&self) -> executor::Transport {
+        match &self.unix_socket_dir {
+            #[cfg(unix)]
+            Some(dir) => executor::Transport::Unix(dir.clone()),
+            // `unix_socket_dir` is rejected on the other platforms when reading the config.
+            _ => executor::Transport::Tcp,
+        }
+    }
+
     pub fn default_for(genesis: validator::Genesis) -> AppConfig {
         Self {
             server_addr: SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), NODES_PORT),
+            unix_socket_dir: None,
             public_addr: SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), NODES_PORT),
+            public_addr_detection: None,
             metrics_server_addr: None,
+            log_filter: None,
 
             genesis,
             max_payload_size: 1000000,
@@ -218,6 +664,35 @@ impl AppConfig {
             gossip_dynamic_inbound_limit: 2,
             gossip_static_inbound: [].into(),
             gossip_static_outbound: [].into(),
+            gossip_dynamic_outbound_limit: 0,
+            gossip_relay_auth: executor::RelayAuth::default(),
+            gossip_peer_bandwidth_cap: None,
+            genesis_mismatch_quarantine: Self::DEFAULT_GENESIS_MISMATCH_QUARANTINE,
+            consensus_replay_window: Self::DEFAULT_CONSENSUS_REPLAY_WINDOW,
+            consensus_relay_max_hops: 0,
+            serve_blocks_bandwidth: None,
+            serve_blocks_bandwidth_per_peer: None,
+            per_peer_metrics: false,
+            rpc_rate_limits: executor::RpcConfig::default(),
+
+            remote_signer: None,
+            shadow_proposer: false,
+            verifier_threads: Self::DEFAULT_VERIFIER_THREADS,
+            catch_up_threshold: None,
+            observer: false,
+            max_payload_wait: None,
+            replica_checkpoint_dir: None,
+            consensus_lock_dir: None,
+            protocol_upgrade_activation: None,
+            consensus_trace: None,
+            key_rotation: None,
+            crash_dir: None,
+            ntp_servers: vec![],
+            max_clock_skew: Self::DEFAULT_MAX_CLOCK_SKEW,
+            sync_blocks_cross_check_peers: 1,
+            sync_blocks_sample_chunks: 0,
+            sync_blocks_genesis_check_quorum: None,
+            node_role: executor::NodeRole::default(),
         }
     }
 
@@ -267,6 +742,49 @@ impl AppConfig {
         self
     }
 
+    /// Part of the config which can be changed without restarting the node
+    /// (in addition to `log_filter`).
+    pub fn reloadable(&self) -> executor::ReloadableConfig {
+        executor::ReloadableConfig {
+            gossip_static_inbound: self.gossip_static_inbound.clone(),
+            gossip_static_outbound: self.gossip_static_outbound.clone(),
+            gossip_dynamic_inbound_limit: self.gossip_dynamic_inbound_limit,
+            serve_blocks_bandwidth: self.serve_blocks_bandwidth,
+            serve_blocks_bandwidth_per_peer: self.serve_blocks_bandwidth_per_peer,
+            rpc: self.rpc_rate_limits.clone(),
+        }
+    }
+
+    /// Verifies that `new` config differs from this one only in the fields
+    /// which can be changed without restarting the node.
+    /// Changes to any other field (consensus-critical ones in particular) are rejected.
+    pub fn check_reload(&self, new: &AppConfig) -> anyhow::Result<()> {
+        let mut fixed = new.clone();
+        fixed.log_filter = self.log_filter.clone();
+        fixed.gossip_static_inbound = self.gossip_static_inbound.clone();
+        fixed.gossip_static_outbound = self.gossip_static_outbound.clone();
+        fixed.gossip_dynamic_inbound_limit = self.gossip_dynamic_inbound_limit;
+        fixed.serve_blocks_bandwidth = self.serve_blocks_bandwidth;
+        fixed.serve_blocks_bandwidth_per_peer = self.serve_blocks_bandwidth_per_peer;
+        fixed.rpc_rate_limits = self.rpc_rate_limits.clone();
+        if &fixed == self {
+            return Ok(());
+        }
+        // Compare the JSON encodings to report the names of the changed fields.
+        let old = serde_json::to_value(Serde(self.clone()))?;
+        let new = serde_json::to_value(Serde(fixed))?;
+        let changed: Vec<_> = match (&old, &new) {
+            (serde_json::Value::Object(old), serde_json::Value::Object(new)) => old
+                .keys()
+                .chain(new.keys().filter(|k| !old.contains_key(*k)))
+                .filter(|k| old.get(*k) != new.get(*k))
+                .cloned()
+                .collect(),
+            _ => vec![],
+        };
+        anyhow::bail!("fields {changed:?} cannot be changed without restarting the node")
+    }
+
     pub fn check_public_addr(&mut self) -> anyhow::Result<()> {
         if let Ok(public_addr) = std::env::var("PUBLIC_ADDR") {
             self.public_addr = SocketAddr::from_str(&format!("{public_addr}:{NODES_PORT}"))?;
@@ -276,29 +794,88 @@ impl AppConfig {
 }
 
 impl Configs {
+    /// Signer of the validator messages, if the node is a validator.
+    fn validator_signer(&self) -> anyhow::Result<Option<Arc<dyn validator::ValidatorSigner>>> {
+        match (&self.validator_key, &self.app.remote_signer) {
+            (Some(_), Some(_)) => {
+                anyhow::bail!("validator key and remote signer are mutually exclusive")
+            }
+            (Some(key), None) => Ok(Some(Arc::new(key.clone()))),
+            (None, Some(cfg)) => Ok(Some(Arc::new(
+                RemoteSigner::new(cfg.clone()).context("RemoteSigner::new()")?,
+            ))),
+            (None, None) => Ok(None),
+        }
+    }
+
     pub async fn make_executor(
         &self,
         ctx: &ctx::Ctx,
-    ) -> ctx::Result<(executor::Executor, BlockStoreRunner)> {
+    ) -> ctx::Result<(executor::ExecutorBuilder, BlockStoreRunner)> {
+        let signer = self.validator_signer()?;
         let store = store::RocksDB::open(self.app.genesis.clone(), &self.database).await?;
         let (block_store, runner) = BlockStore::new(ctx, Box::new(store.clone())).await?;
-        let e = executor::Executor {
-            config: executor::Config {
-                server_addr: self.app.server_addr,
-                public_addr: self.app.public_addr,
-                node_key: self.node_key.clone(),
-                gossip_dynamic_inbound_limit: self.app.gossip_dynamic_inbound_limit,
-                gossip_static_inbound: self.app.gossip_static_inbound.clone(),
-                gossip_static_outbound: self.app.gossip_static_outbound.clone(),
-                max_payload_size: self.app.max_payload_size,
-            },
-            block_store,
-            validator: self.validator_key.as_ref().map(|key| executor::Validator {
-                key: key.clone(),
-                replica_store: Box::new(store),
-                payload_manager: Box::new(bft::testonly::RandomPayload(self.app.max_payload_size)),
+        let checkpoint = match &self.app.replica_checkpoint_dir {
+            Some(dir) => Some(bft::CheckpointConfig {
+                store: Box::new(
+                    FileCheckpointStore::open(dir.clone())
+                        .await
+                        .context("FileCheckpointStore::open()")?,
+                ),
+                interval: AppConfig::REPLICA_CHECKPOINT_INTERVAL,
+                allow_stale_state: self.allow_stale_replica_state,
             }),
+            None => None,
         };
-        Ok((e, runner))
+        let config = executor::Config {
+            server_addr: self.app.server_addr,
+            transport: self.app.transport(),
+            public_addr: self.app.public_addr,
+            public_addr_detection: self.app.public_addr_detection,
+            node_key: self.node_key.clone(),
+            gossip_dynamic_inbound_limit: self.app.gossip_dynamic_inbound_limit,
+            gossip_static_inbound: self.app.gossip_static_inbound.clone(),
+            gossip_static_outbound: self.app.gossip_static_outbound.clone(),
+            gossip_dynamic_outbound_limit: self.app.gossip_dynamic_outbound_limit,
+            gossip_relay_auth: self.app.gossip_relay_auth,
+            gossip_peer_bandwidth_cap: self.app.gossip_peer_bandwidth_cap,
+            genesis_mismatch_quarantine: self.app.genesis_mismatch_quarantine,
+            consensus_replay_window: self.app.consensus_replay_window,
+            consensus_relay_max_hops: self.app.consensus_relay_max_hops,
+            serve_blocks_bandwidth: self.app.serve_blocks_bandwidth,
+            serve_blocks_bandwidth_per_peer: self.app.serve_blocks_bandwidth_per_peer,
+            per_peer_metrics: self.app.per_peer_metrics,
+            rpc: self.app.rpc_rate_limits.clone(),
+            sentry: None,
+            restart_policy: executor::RestartPolicy::default(),
+            crash_dir: self.app.crash_dir.clone(),
+            ntp_servers: self.app.ntp_servers.clone(),
+            max_clock_skew: self.app.max_clock_skew,
+            sync_blocks_cross_check_peers: self.app.sync_blocks_cross_check_peers,
+            sync_blocks_sample_chunks: self.app.sync_blocks_sample_chunks,
+            sync_blocks_genesis_check_quorum: self.app.sync_blocks_genesis_check_quorum,
+            role: self.app.node_role,
+            max_payload_size: self.app.max_payload_size,
+        };
+        let mut builder = executor::Executor::builder(config, block_store)
+            .sync_progress_store(Arc::new(store.clone()));
+        if let Some(key) = signer {
+            builder = builder.validator(executor::Validator {
+                key,
+                replica_store: Box::new(store),
+                payload_manager: Box::new(bft::testonly::RandomPayload(self.app.max_payload_size)),
+                shadow_proposer: self.app.shadow_proposer,
+                verifier_threads: self.app.verifier_threads,
+                catch_up_threshold: self.app.catch_up_threshold,
+                observer: self.app.observer,
+                max_payload_wait: self.app.max_payload_wait,
+                checkpoint,
+                consensus_lock: self.app.consensus_lock_dir.clone(),
+                protocol_upgrade_activation: self.app.protocol_upgrade_activation,
+                consensus_trace: self.app.consensus_trace.clone(),
+                key_rotation: self.app.key_rotation.clone(),
+            });
+        }
+        Ok((builder, runner))
     }
 }
@@ -0,0 +1,217 @@
+//! Deterministic derivation of the node and validator secret keys from a BIP39 mnemonic,
+//! so that the key material can be backed up and restored with the standard mnemonic workflows.
+//!
+//! The keys are derived under the dedicated purpose `12381'` (like the EIP-2334 paths of
+//! the Ethereum validator keys) and the coin type `324'`, i.e. `m/12381'/324'/<account>'/<use>'`.
+//! Other purposes are rejected, so that a consensus key, which is kept hot on the node,
+//! never coincides with a wallet key derived from the same mnemonic.
+//!
+//! * secp256k1 validator keys are derived with BIP32.
+//! * ed25519 node keys are derived with SLIP-0010.
+//! * bn254 validator keys don't have a standard derivation scheme. They are derived like
+//!   the SLIP-0010 keys, with `bn254 seed` as the curve key and with the top 3 bits of every
+//!   derived key cleared, so that it is always a valid scalar (`2^253 < r`).
+//!
+//! Only hardened derivation is supported for ed25519 and bn254 keys.
+use anyhow::Context as _;
+use hmac::{Hmac, Mac as _};
+use k256::{
+    ecdsa,
+    elliptic_curve::{Field as _, PrimeField as _},
+    Scalar,
+};
+use rand::Rng;
+use sha2::Sha512;
+use std::{fmt, str::FromStr, sync::Arc};
+use zksync_consensus_crypto::{bn254, secp256k1, ByteFmt};
+use zksync_consensus_roles::{node, validator};
+
+/// Environment variable with the mnemonic to derive the keys from.
+pub const MNEMONIC_ENV: &str = "CONSENSUS_KEY_MNEMONIC";
+/// Default derivation path of the validator key.
+pub const DEFAULT_VALIDATOR_PATH: &str = "m/12381'/324'/0'/0'";
+/// Default derivation path of the node key.
+pub const DEFAULT_NODE_PATH: &str = "m/12381'/324'/0'/1'";
+
+/// Offset of the hardened child indices.
+const HARDENED: u32 = 1 << 31;
+/// Purpose of the consensus key derivation paths.
+const PURPOSE: u32 = 12381 + HARDENED;
+
+/// BIP39 mnemonic.
+pub struct Mnemonic(bip39::Mnemonic);
+
+impl fmt::Debug for Mnemonic {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("<redacted>")
+    }
+}
+
+impl Mnemonic {
+    /// Generates a random english mnemonic of `words` words.
+    pub fn generate(rng: &mut impl Rng, words: usize) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            [12, 15, 18, 21, 24].contains(&words),
+            "mnemonic has to have 12, 15, 18, 21 or 24 words"
+        );
+        // Every 3 words encode 4 bytes of entropy (and 1 bit of checksum).
+        let entropy: [u8; 32] = rng.gen();
+        Ok(Self(bip39::Mnemonic::from_entropy(
+            &entropy[..words / 3 * 4],
+        )?))
+    }
+
+    /// Parses an english mnemonic.
+    pub fn parse(phrase: &str) -> anyhow::Result<Self> {
+        let phrase = phrase.split_whitespace().collect::<Vec<_>>().join(" ");
+        Ok(Self(
+            bip39::Mnemonic::parse_normalized(&phrase).context("invalid mnemonic")?,
+        ))
+    }
+
+    /// Reads the mnemonic from the `MNEMONIC_ENV` environment variable.
+    pub fn from_env() -> Option<anyhow::Result<Self>> {
+        std::env::var(MNEMONIC_ENV).ok().map(|x| Self::parse(&x))
+    }
+
+    /// Space-separated words of the mnemonic.
+    pub fn phrase(&self) -> String {
+        self.0.to_string()
+    }
+
+    /// BIP39 seed of the mnemonic (with an empty passphrase).
+    pub fn to_seed(&self) -> [u8; 64] {
+        self.0.to_seed_normalized("")
+    }
+}
+
+/// BIP32 derivation path, for example `m/12381'/324'/0'/0'`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivationPath(Vec<u32>);
+
+impl FromStr for DerivationPath {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut parts = s.split('/');
+        anyhow::ensure!(parts.next() == Some("m"), "path has to start with \"m\"");
+        let mut path = vec![];
+        for part in parts {
+            let (index, hardened) = match part.strip_suffix(['\'', 'h']) {
+                Some(index) => (index, true),
+                None => (part, false),
+            };
+            let index: u32 = index
+                .parse()
+                .with_context(|| format!("invalid index {part:?}"))?;
+            anyhow::ensure!(index < HARDENED, "index {part:?} out of range");
+            path.push(if hardened { index + HARDENED } else { index });
+        }
+        Ok(Self(path))
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("m")?;
+        for i in &self.0 {
+            if *i >= HARDENED {
+                write!(fmt, "/{}'", i - HARDENED)?;
+            } else {
+                write!(fmt, "/{i}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DerivationPath {
+    /// Checks that the path has the purpose of the consensus keys.
+    fn check_purpose(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.0.first() == Some(&PURPOSE),
+            "consensus keys have to be derived under the purpose 12381'"
+        );
+        Ok(())
+    }
+}
+
+/// Derives the node key at `path` from a BIP39 seed.
+pub fn derive_node_key(seed: &[u8], path: &DerivationPath) -> anyhow::Result<node::SecretKey> {
+    path.check_purpose()?;
+    let key = slip10(b"ed25519 seed", seed, path, |_| {})?;
+    ByteFmt::decode(&key)
+}
+
+/// Derives the validator key of the given scheme at `path` from a BIP39 seed.
+pub fn derive_validator_key(
+    seed: &[u8],
+    path: &DerivationPath,
+    scheme: validator::SignatureScheme,
+) -> anyhow::Result<validator::SecretKey> {
+    path.check_purpose()?;
+    Ok(match scheme {
+        validator::SignatureScheme::Bn254 => {
+            let key = slip10(b"bn254 seed", seed, path, |k| k[0] &= 0x1f)?;
+            validator::SecretKey::Bn254(Arc::new(bn254::SecretKey::decode(&key)?))
+        }
+        validator::SignatureScheme::Secp256k1 => {
+            let key = bip32(seed, path)?;
+            validator::SecretKey::Secp256k1(Arc::new(secp256k1::SecretKey::decode(&key.to_repr())?))
+        }
+    })
+}
+
+/// Computes HMAC-SHA512 of the concatenated `data` and splits it into halves.
+fn hmac_sha512(key: &[u8], data: &[&[u8]]) -> ([u8; 32], [u8; 32]) {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key).unwrap();
+    for d in data {
+        mac.update(d);
+    }
+    let out = mac.finalize().into_bytes();
+    (out[..32].try_into().unwrap(), out[32..].try_into().unwrap())
+}
+
+/// SLIP-0010 derivation, with `fix` turning the left half of the HMAC into a valid key.
+pub(crate) fn slip10(
+    curve: &[u8],
+    seed: &[u8],
+    path: &DerivationPath,
+    fix: impl Fn(&mut [u8; 32]),
+) -> anyhow::Result<[u8; 32]> {
+    let (mut key, mut chain_code) = hmac_sha512(curve, &[seed]);
+    fix(&mut key);
+    for i in &path.0 {
+        anyhow::ensure!(
+            *i >= HARDENED,
+            "only hardened derivation is supported for this key type"
+        );
+        (key, chain_code) = hmac_sha512(&chain_code, &[&[0], &key, &i.to_be_bytes()]);
+        fix(&mut key);
+    }
+    Ok(key)
+}
+
+/// BIP32 derivation of a secp256k1 key.
+pub(crate) fn bip32(seed: &[u8], path: &DerivationPath) -> anyhow::Result<Scalar> {
+    // Probability of hitting an invalid key is lower than 2^-127,
+    // so we don't bother with skipping to the next index.
+    let to_scalar = |x: [u8; 32]| -> anyhow::Result<Scalar> {
+        Option::<Scalar>::from(Scalar::from_repr(x.into())).context("derived key out of range")
+    };
+    let (key, mut chain_code) = hmac_sha512(b"Bitcoin seed", &[seed]);
+    let mut key = to_scalar(key)?;
+    for i in &path.0 {
+        let (tweak, next) = if *i >= HARDENED {
+            hmac_sha512(&chain_code, &[&[0], &key.to_repr(), &i.to_be_bytes()])
+        } else {
+            let public = *ecdsa::SigningKey::from_bytes(&key.to_repr())?.verifying_key();
+            let public = public.to_encoded_point(true);
+            hmac_sha512(&chain_code, &[public.as_bytes(), &i.to_be_bytes()])
+        };
+        key += to_scalar(tweak)?;
+        anyhow::ensure!(!bool::from(key.is_zero()), "derived key is zero");
+        chain_code = next;
+    }
+    Ok(key)
+}
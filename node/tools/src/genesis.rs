@@ -0,0 +1,28 @@
+//! Canonical genesis file format: the proto-json encoding of `zksync.roles.validator.Genesis`.
+//!
+//! Decoding is strict: unknown fields and missing required fields are rejected,
+//! so that a typo in the genesis file cannot silently produce a different chain.
+//! Operators can compare the `GenesisHash` (see the `genesis` binary) to independently
+//! verify that they are configuring the same chain.
+use crate::config::{decode_json, encode_json};
+use anyhow::Context as _;
+use std::{fs, path::Path};
+use zksync_consensus_roles::validator;
+use zksync_protobuf::serde::Serde;
+
+/// Decodes a genesis file content. Apart from the schema, it validates the validator set
+/// (non-empty, no duplicates) and the key rotation certificates.
+pub fn decode(json: &str) -> anyhow::Result<validator::Genesis> {
+    Ok(decode_json::<Serde<validator::Genesis>>(json)?.0)
+}
+
+/// Encodes the genesis in the canonical format.
+pub fn encode(genesis: &validator::Genesis) -> String {
+    encode_json(&Serde(genesis.clone()))
+}
+
+/// Reads a genesis file.
+pub fn read(path: &Path) -> anyhow::Result<validator::Genesis> {
+    let json = fs::read_to_string(path).context("failed reading file")?;
+    decode(&json).with_context(|| path.display().to_string())
+}
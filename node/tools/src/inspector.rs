@@ -0,0 +1,191 @@
+//! Inspection and maintenance of the block store content.
+//!
+//! Blocks can be exported to a portable file format, which is a sequence of frames,
+//! each being `L ++ msg`, where `L` is a little endian encoding of `msg.len() as u32`
+//! and `msg` is a protobuf-encoded message. The first message is the
+//! `roles.validator.GenesisHash` of the chain, followed by the exported
+//! `roles.validator.FinalBlock` messages in ascending order of block numbers.
+use anyhow::Context as _;
+use std::io::{self, Read, Write};
+use zksync_concurrency::ctx;
+use zksync_consensus_roles::validator;
+use zksync_consensus_storage::{BlockStoreState, PersistentBlockStore};
+use zksync_protobuf::ProtoFmt;
+
+/// Max size of an exported frame. Exported files are not trusted,
+/// so that's the upper bound on what is read into memory.
+const MAX_FRAME_SIZE: usize = 64 * zksync_protobuf::MB;
+
+/// Returns the range of blocks stored in the block store.
+pub async fn state(
+    ctx: &ctx::Ctx,
+    store: &dyn PersistentBlockStore,
+) -> ctx::Result<BlockStoreState> {
+    let mut state = BlockStoreState {
+        first: store.genesis(ctx).await?.fork.first_block,
+        last: store.last(ctx).await?,
+        gaps: store.gaps(ctx).await?,
+    };
+    if let Some(last) = &state.last {
+        let last = last.header().number;
+        if let Some(first) = store.first(ctx).await? {
+            state.prune(first.min(last));
+        }
+    }
+    Ok(state)
+}
+
+/// Verifies that the block points to the parent (`None` iff `block` is the first block of the fork).
+fn verify_parent(
+    genesis: &validator::Genesis,
+    block: &validator::FinalBlock,
+    parent: Option<&validator::CommitQC>,
+) -> anyhow::Result<()> {
+    let want = match parent {
+        Some(qc) => Some(genesis.header_hash(qc.header())),
+        None => genesis.fork.first_parent,
+    };
+    anyhow::ensure!(
+        block.header().parent == want,
+        "parent hash mismatch: got {:?}, want {want:?}",
+        block.header().parent
+    );
+    Ok(())
+}
+
+/// Returns the justification of the parent of the block `number`.
+async fn parent(
+    ctx: &ctx::Ctx,
+    store: &dyn PersistentBlockStore,
+    genesis: &validator::Genesis,
+    number: validator::BlockNumber,
+) -> ctx::Result<Option<validator::CommitQC>> {
+    if number <= genesis.fork.first_block {
+        return Ok(None);
+    }
+    Ok(Some(
+        store.justification(ctx, number.prev().unwrap()).await?,
+    ))
+}
+
+/// Verifies the blocks `[first,last]`: the payload hashes, the `CommitQC`s against
+/// the genesis and that the blocks constitute a hash chain.
+pub async fn verify(
+    ctx: &ctx::Ctx,
+    store: &dyn PersistentBlockStore,
+    first: validator::BlockNumber,
+    last: validator::BlockNumber,
+) -> ctx::Result<()> {
+    let genesis = store.genesis(ctx).await?;
+    let mut parent = parent(ctx, store, &genesis, first).await?;
+    let mut number = first;
+    while number <= last {
+        let block = store.block(ctx, number).await?;
+        (|| {
+            anyhow::ensure!(
+                block.number() == number,
+                "unexpected block number {:?}",
+                block.number()
+            );
+            block.verify(&genesis)?;
+            verify_parent(&genesis, &block, parent.as_ref())
+        })()
+        .with_context(|| format!("block {number:?}"))?;
+        parent = Some(block.justification);
+        number = number.next();
+    }
+    Ok(())
+}
+
+/// Writes a frame with the protobuf-encoded `msg`.
+fn write_frame<T: ProtoFmt>(w: &mut impl Write, msg: &T) -> anyhow::Result<()> {
+    let msg = zksync_protobuf::encode(msg);
+    w.write_all(&u32::try_from(msg.len())?.to_le_bytes())?;
+    w.write_all(&msg)?;
+    Ok(())
+}
+
+/// Reads a frame with a protobuf-encoded message. Returns `None` at the end of the input.
+fn read_frame<T: ProtoFmt>(r: &mut impl Read) -> anyhow::Result<Option<T>> {
+    let mut len = [0u8; 4];
+    match r.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    anyhow::ensure!(
+        len <= MAX_FRAME_SIZE.min(T::max_size()),
+        "frame too large: {len}B"
+    );
+    // Read incrementally instead of allocating `len` bytes upfront,
+    // so that a corrupted length prefix doesn't cause a large allocation.
+    let mut msg = vec![];
+    r.take(len as u64).read_to_end(&mut msg)?;
+    anyhow::ensure!(msg.len() == len, "truncated frame");
+    Ok(Some(zksync_protobuf::decode(&msg)?))
+}
+
+/// Exports the blocks `[first,last]` to `w`. Returns the number of exported blocks.
+pub async fn export(
+    ctx: &ctx::Ctx,
+    store: &dyn PersistentBlockStore,
+    first: validator::BlockNumber,
+    last: validator::BlockNumber,
+    w: &mut impl Write,
+) -> ctx::Result<usize> {
+    let genesis = store.genesis(ctx).await?;
+    write_frame(w, &genesis.hash()).context("write_frame()")?;
+    let mut count = 0;
+    let mut number = first;
+    while number <= last {
+        let block = store.block(ctx, number).await?;
+        write_frame(w, &block).context("write_frame()")?;
+        count += 1;
+        number = number.next();
+    }
+    w.flush().context("flush()")?;
+    Ok(count)
+}
+
+/// Imports blocks exported with `export()` from `r`, to backfill the store.
+/// Blocks already present in the store are skipped. The imported blocks are verified
+/// and have to extend the blocks in the store without gaps.
+/// Returns the number of imported blocks.
+pub async fn import(
+    ctx: &ctx::Ctx,
+    store: &dyn PersistentBlockStore,
+    r: &mut impl Read,
+) -> ctx::Result<usize> {
+    let genesis = store.genesis(ctx).await?;
+    let got: validator::GenesisHash = read_frame(r)
+        .context("read_frame()")?
+        .context("missing genesis")?;
+    if got != genesis.hash() {
+        return Err(anyhow::format_err!(
+            "genesis mismatch: got {got:?}, want {:?}",
+            genesis.hash()
+        )
+        .into());
+    }
+    let mut next = state(ctx, store).await?.next();
+    let mut parent = parent(ctx, store, &genesis, next).await?;
+    let mut count = 0;
+    while let Some(block) = read_frame::<validator::FinalBlock>(r).context("read_frame()")? {
+        let number = block.number();
+        if number < next {
+            continue;
+        }
+        (|| {
+            anyhow::ensure!(number == next, "gap in blocks: want {next:?}");
+            block.verify(&genesis)?;
+            verify_parent(&genesis, &block, parent.as_ref())
+        })()
+        .with_context(|| format!("block {number:?}"))?;
+        store.store_next_block(ctx, &block).await?;
+        count += 1;
+        next = number.next();
+        parent = Some(block.justification);
+    }
+    Ok(count)
+}
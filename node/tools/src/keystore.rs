@@ -0,0 +1,209 @@
+//! Encrypted-at-rest storage of the node and validator secret keys.
+//!
+//! A key file contains either a plaintext text-encoded secret key (legacy format),
+//! or a JSON-encoded `EncryptedKey`: the text-encoded secret key encrypted with
+//! AES-256-GCM, under a key derived from a passphrase with scrypt.
+use crate::{
+    config::{decode_json, encode_json},
+    proto,
+};
+use aes_gcm::{
+    aead::{Aead as _, KeyInit as _},
+    Aes256Gcm, Nonce,
+};
+use anyhow::Context as _;
+use rand::Rng;
+use std::{fmt, fs, io::Write as _, path::Path};
+use zksync_consensus_crypto::{Text, TextFmt};
+use zksync_protobuf::{required, serde::Serde, ProtoFmt};
+
+/// Environment variable with the passphrase of the encrypted key files.
+pub const PASSPHRASE_ENV: &str = "CONSENSUS_KEY_PASSPHRASE";
+
+/// Size of the AES-GCM nonce in bytes.
+const NONCE_LEN: usize = 12;
+/// Size of the scrypt salt in bytes.
+const SALT_LEN: usize = 32;
+
+/// Passphrase protecting the key files.
+#[derive(Clone)]
+pub struct Passphrase(String);
+
+impl fmt::Debug for Passphrase {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("<redacted>")
+    }
+}
+
+impl From<String> for Passphrase {
+    fn from(passphrase: String) -> Self {
+        Self(passphrase)
+    }
+}
+
+impl Passphrase {
+    /// Reads the passphrase from the `PASSPHRASE_ENV` environment variable.
+    pub fn from_env() -> Option<Self> {
+        std::env::var(PASSPHRASE_ENV).ok().map(Self)
+    }
+}
+
+/// Parameters of the scrypt key derivation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    /// log2 of the CPU/memory cost.
+    pub log_n: u8,
+    /// Block size.
+    pub r: u32,
+    /// Parallelization.
+    pub p: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        // Standard parameters of the Web3 Secret Storage (keystore v3) files,
+        // which are recommended for the keys stored at rest.
+        Self {
+            log_n: 18,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Derives an AES-256 key from the passphrase.
+    fn derive(&self, passphrase: &Passphrase, salt: &[u8]) -> anyhow::Result<Aes256Gcm> {
+        let params = scrypt::Params::new(self.log_n, self.r, self.p, 32)
+            .map_err(|err| anyhow::format_err!("scrypt::Params::new(): {err}"))?;
+        let mut key = [0u8; 32];
+        scrypt::scrypt(passphrase.0.as_bytes(), salt, &params, &mut key)
+            .map_err(|err| anyhow::format_err!("scrypt::scrypt(): {err}"))?;
+        Ok(Aes256Gcm::new(&key.into()))
+    }
+}
+
+/// Secret key encrypted with a passphrase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptedKey {
+    /// Parameters of the key derivation.
+    pub kdf: KdfParams,
+    /// Salt of the key derivation.
+    pub salt: Vec<u8>,
+    /// AES-GCM nonce.
+    pub nonce: [u8; NONCE_LEN],
+    /// Encrypted text-encoded secret key.
+    pub ciphertext: Vec<u8>,
+}
+
+impl EncryptedKey {
+    /// Encrypts a secret key with the passphrase.
+    pub fn encrypt<K: TextFmt>(
+        rng: &mut impl Rng,
+        key: &K,
+        passphrase: &Passphrase,
+        kdf: KdfParams,
+    ) -> anyhow::Result<Self> {
+        let salt: [u8; SALT_LEN] = rng.gen();
+        let nonce: [u8; NONCE_LEN] = rng.gen();
+        let ciphertext = kdf
+            .derive(passphrase, &salt)?
+            .encrypt(Nonce::from_slice(&nonce), key.encode().as_bytes())
+            .map_err(|_| anyhow::format_err!("encryption failed"))?;
+        Ok(Self {
+            kdf,
+            salt: salt.into(),
+            nonce,
+            ciphertext,
+        })
+    }
+
+    /// Decrypts the secret key with the passphrase.
+    pub fn decrypt<K: TextFmt>(&self, passphrase: &Passphrase) -> anyhow::Result<K> {
+        let plaintext = self
+            .kdf
+            .derive(passphrase, &self.salt)?
+            .decrypt(Nonce::from_slice(&self.nonce), self.ciphertext.as_slice())
+            .map_err(|_| anyhow::format_err!("wrong passphrase or corrupted key"))?;
+        let plaintext = String::from_utf8(plaintext).context("key is not valid UTF-8")?;
+        Text::new(&plaintext)
+            .decode()
+            .context("failed decoding key")
+    }
+}
+
+impl ProtoFmt for EncryptedKey {
+    type Proto = proto::EncryptedKey;
+
+    fn read(r: &Self::Proto) -> anyhow::Result<Self> {
+        Ok(Self {
+            kdf: KdfParams {
+                log_n: required(&r.scrypt_log_n)
+                    .and_then(|x| Ok((*x).try_into()?))
+                    .context("scrypt_log_n")?,
+                r: *required(&r.scrypt_r).context("scrypt_r")?,
+                p: *required(&r.scrypt_p).context("scrypt_p")?,
+            },
+            salt: required(&r.salt).context("salt")?.clone(),
+            nonce: required(&r.nonce)
+                .and_then(|x| Ok(x.as_slice().try_into()?))
+                .context("nonce")?,
+            ciphertext: required(&r.ciphertext).context("ciphertext")?.clone(),
+        })
+    }
+
+    fn build(&self) -> Self::Proto {
+        Self::Proto {
+            scrypt_log_n: Some(self.kdf.log_n.into()),
+            scrypt_r: Some(self.kdf.r),
+            scrypt_p: Some(self.kdf.p),
+            salt: Some(self.salt.clone()),
+            nonce: Some(self.nonce.into()),
+            ciphertext: Some(self.ciphertext.clone()),
+        }
+    }
+}
+
+/// Reads a secret key from a file. Encrypted key files require a passphrase.
+/// Plaintext key files are still accepted, but a warning is logged.
+pub fn read_key<K: TextFmt>(path: &Path, passphrase: Option<&Passphrase>) -> anyhow::Result<K> {
+    let content = fs::read_to_string(path).context("failed reading file")?;
+    if let Ok(Serde(key)) = decode_json::<Serde<EncryptedKey>>(&content) {
+        let passphrase = passphrase
+            .with_context(|| format!("key is encrypted, but {PASSPHRASE_ENV} is not set"))?;
+        return key.decrypt(passphrase);
+    }
+    tracing::warn!(
+        "{}: secret key is stored in plaintext, consider encrypting it",
+        path.display()
+    );
+    Text::new(&content).decode().context("failed decoding key")
+}
+
+/// Encrypts a secret key with the passphrase and writes it to a file,
+/// which is readable only by its owner.
+pub fn write_key<K: TextFmt>(
+    rng: &mut impl Rng,
+    path: &Path,
+    key: &K,
+    passphrase: &Passphrase,
+    kdf: KdfParams,
+) -> anyhow::Result<()> {
+    let key = EncryptedKey::encrypt(rng, key, passphrase, kdf)?;
+    write_secret_file(path, encode_json(&Serde(key)).as_bytes())
+}
+
+/// Writes a file with secret content. On unix the file is created with mode 0600,
+/// and the permissions of an already existing file are restricted before it is overwritten.
+pub fn write_secret_file(path: &Path, content: &[u8]) -> anyhow::Result<()> {
+    let mut opts = fs::OpenOptions::new();
+    opts.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut opts, 0o600);
+    let mut file = opts.open(path).context("open()")?;
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))
+        .context("set_permissions()")?;
+    file.write_all(content).context("write_all()")?;
+    file.sync_all().context("sync_all()")
+}
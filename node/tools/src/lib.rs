@@ -1,13 +1,21 @@
 //! CLI tools for the consensus node.
 #![allow(missing_docs)]
 mod config;
+pub mod derivation;
+pub mod genesis;
+pub mod inspector;
 pub mod k8s;
+pub mod keystore;
+pub mod loader;
 mod proto;
+mod remote_signer;
 pub mod rpc;
 mod store;
 
 #[cfg(test)]
 mod tests;
 
-pub use config::{decode_json, AppConfig, ConfigPaths, NodeAddr, NODES_PORT};
-pub use rpc::server::RPCServer;
+pub use config::{decode_json, encode_json, AppConfig, ConfigPaths, NodeAddr, NODES_PORT};
+pub use remote_signer::{RemoteSigner, RemoteSignerConfig};
+pub use rpc::{methods::reload_config::ReloadRequest, server::RPCServer};
+pub use store::RocksDB;
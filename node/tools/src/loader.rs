@@ -0,0 +1,188 @@
+//! Loading of the node configuration.
+//! The configuration can be written in JSON, YAML (with the `yaml` feature) or TOML (with the `toml`
+//! feature), following the protobuf JSON mapping
+//! of `AppConfig` (fields can be named either in `camelCase` or in `snake_case`), and its fields
+//! can be overridden with the env vars. Instead of failing on the first invalid field,
+//! the loader reports all of them at once, qualified with their paths.
+use crate::{proto, AppConfig};
+use anyhow::Context as _;
+use std::{fmt, fs, path::Path};
+use zksync_protobuf::{serde::deserialize_proto, ProtoFmt as _};
+
+/// Prefix of the env vars overriding the config fields.
+/// `CONSENSUS_CONFIG_<FIELD>` overrides the top-level field `<field>` (case-insensitive,
+/// in `snake_case`). Path segments of the nested fields are separated with `__`,
+/// e.g. `CONSENSUS_CONFIG_REMOTE_SIGNER__URL`. The value is parsed as JSON
+/// (e.g. `10`, `["a","b"]`), and taken as a string if it is not valid JSON.
+pub const ENV_PREFIX: &str = "CONSENSUS_CONFIG_";
+
+/// Format of the config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `.json` (and any other extension).
+    Json,
+    /// `.yaml` or `.yml`.
+    Yaml,
+    /// `.toml`.
+    Toml,
+}
+
+impl Format {
+    /// Detects the format from the extension of the file. Defaults to JSON.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => Self::Yaml,
+            Some("toml") => Self::Toml,
+            _ => Self::Json,
+        }
+    }
+
+    /// Parses a document in this format.
+    /// Fails for the formats which are not enabled by the features of the crate.
+    pub fn parse(self, s: &str) -> anyhow::Result<serde_json::Value> {
+        Ok(match self {
+            Self::Json => serde_json::from_str(s)?,
+            #[cfg(feature = "yaml")]
+            Self::Yaml => serde_yaml::from_str(s)?,
+            #[cfg(feature = "toml")]
+            Self::Toml => toml::from_str(s)?,
+            #[allow(unreachable_patterns)]
+            format => anyhow::bail!("{format:?} config requires the corresponding feature"),
+        })
+    }
+}
+
+/// Error of a single config field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    /// Path of the field, e.g. `gossip_static_outbound[1].addr`.
+    pub path: String,
+    /// What is wrong with the field.
+    pub error: String,
+}
+
+/// Errors of all the invalid config fields.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationErrors(pub Vec<FieldError>);
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{} invalid field(s):", self.0.len())?;
+        for e in &self.0 {
+            write!(fmt, "\n  {}: {}", e.path, e.error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+impl ValidationErrors {
+    /// Records the error of `res` (if any) and passes `res` through.
+    /// The contexts of the error constitute the path of the field.
+    pub(crate) fn check<T>(&mut self, res: anyhow::Result<T>) -> anyhow::Result<T> {
+        if let Err(err) = &res {
+            let mut chain: Vec<_> = err.chain().map(|e| e.to_string()).collect();
+            let error = chain.pop().unwrap_or_default();
+            self.0.push(FieldError {
+                path: chain.join("."),
+                error,
+            });
+        }
+        res
+    }
+
+    /// Fails with the recorded errors, if any.
+    pub(crate) fn finish(&mut self) -> Result<(), Self> {
+        match self.0.is_empty() {
+            true => Ok(()),
+            false => Err(std::mem::take(self)),
+        }
+    }
+}
+
+/// Loads the config from the file at `path`, applying the overrides from `env`
+/// (see `ENV_PREFIX`).
+pub fn load(
+    path: &Path,
+    env: impl IntoIterator<Item = (String, String)>,
+) -> anyhow::Result<AppConfig> {
+    let content = fs::read_to_string(path).context("failed reading file")?;
+    let mut value = Format::from_path(path)
+        .parse(&content)
+        .context("failed parsing file")?;
+    apply_env_overrides(&mut value, env)?;
+    decode(value)
+}
+
+/// Overrides the config fields with the values of the env vars with `ENV_PREFIX`.
+pub(crate) fn apply_env_overrides(
+    value: &mut serde_json::Value,
+    env: impl IntoIterator<Item = (String, String)>,
+) -> anyhow::Result<()> {
+    for (key, val) in env {
+        let Some(path) = key.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let path: Vec<_> = path.split("__").map(str::to_lowercase).collect();
+        let (field, parents) = path.split_last().unwrap();
+        let mut obj = &mut *value;
+        for name in parents {
+            let map = obj
+                .as_object_mut()
+                .with_context(|| format!("{key}: not an object"))?;
+            let v = take(map, name)
+                .unwrap_or_else(|| serde_json::Value::Object(serde_json::Map::new()));
+            obj = map.entry(name.clone()).or_insert(v);
+        }
+        let map = obj
+            .as_object_mut()
+            .with_context(|| format!("{key}: not an object"))?;
+        take(map, field);
+        let v = serde_json::from_str(&val).unwrap_or(serde_json::Value::String(val));
+        map.insert(field.clone(), v);
+    }
+    Ok(())
+}
+
+/// Removes the field `name` (in `snake_case`) from `map`,
+/// whether it is named in `snake_case` or in `camelCase`.
+fn take(
+    map: &mut serde_json::Map<String, serde_json::Value>,
+    name: &str,
+) -> Option<serde_json::Value> {
+    let mut camel = String::new();
+    for (i, part) in name.split('_').enumerate() {
+        let mut chars = part.chars();
+        match (i, chars.next()) {
+            (0, _) => camel.push_str(part),
+            (_, Some(c)) => {
+                camel.extend(c.to_uppercase());
+                camel.push_str(chars.as_str());
+            }
+            (_, None) => {}
+        }
+    }
+    map.remove(name).or_else(|| map.remove(&camel))
+}
+
+/// Decodes the config, reporting all the invalid fields at once.
+pub(crate) fn decode(value: serde_json::Value) -> anyhow::Result<AppConfig> {
+    let serde_json::Value::Object(fields) = value else {
+        anyhow::bail!("config has to be an object");
+    };
+    // Every field is deserialized on its own first, so that all the fields
+    // of the wrong type (and the unknown fields) are reported.
+    let mut errs = ValidationErrors::default();
+    for (name, v) in &fields {
+        let field = serde_json::Value::Object([(name.clone(), v.clone())].into_iter().collect());
+        let _ = errs.check(
+            deserialize_proto::<proto::AppConfig, _>(field)
+                .map_err(anyhow::Error::from)
+                .context(name.clone()),
+        );
+    }
+    errs.finish()?;
+    let proto = deserialize_proto::<proto::AppConfig, _>(serde_json::Value::Object(fields))?;
+    AppConfig::read(&proto)
+}
@@ -3,13 +3,21 @@
 use anyhow::Context as _;
 use clap::Parser;
 use std::{fs, io::IsTerminal as _, path::PathBuf};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
 use tracing::metadata::LevelFilter;
-use tracing_subscriber::{prelude::*, Registry};
+use tracing_subscriber::{prelude::*, reload, EnvFilter, Registry};
 use vise_exporter::MetricsExporter;
-use zksync_concurrency::{ctx, scope};
-use zksync_consensus_tools::{decode_json, ConfigPaths, NodeAddr, RPCServer};
+use zksync_concurrency::{ctx, oneshot, scope, sync, time};
+use zksync_consensus_crypto::TextFmt as _;
+use zksync_consensus_tools::{
+    decode_json, keystore::Passphrase, AppConfig, ConfigPaths, NodeAddr, RPCServer, ReloadRequest,
+};
 use zksync_protobuf::serde::Serde;
 
+/// Interval at which the config file is checked for modifications.
+const CONFIG_POLL_INTERVAL: time::Duration = time::Duration::seconds(5);
+
 /// Wrapper for Vec<NodeAddr>.
 #[derive(Debug, Clone)]
 struct NodeAddrs(Vec<Serde<NodeAddr>>);
@@ -26,11 +34,15 @@ impl std::str::FromStr for NodeAddrs {
 struct Args {
     /// Path to a validator key file. If set to an empty string, validator key will not be read
     /// (i.e., a node will be initialized as a non-validator node).
+    /// Encrypted key files are decrypted with the passphrase from `CONSENSUS_KEY_PASSPHRASE` env var.
     #[arg(long, default_value = "./validator_key")]
     validator_key: PathBuf,
     /// Path to a JSON file with node configuration.
     #[arg(long, default_value = "./config.json")]
     config_file: PathBuf,
+    /// Path to a JSON genesis file. If set, it overrides the genesis from the node configuration.
+    #[arg(long)]
+    genesis_file: Option<PathBuf>,
     /// Path to a node key file.
     #[arg(long, default_value = "./node_key")]
     node_key: PathBuf,
@@ -40,9 +52,35 @@ struct Args {
     /// Port for the RPC server.
     #[arg(long)]
     rpc_port: Option<u16>,
+    /// Port for the admin RPC server, bound to localhost, which serves the methods changing
+    /// the node state (`log_filter`, `reload_config`, `wire_dump`). They are not served if not set.
+    #[arg(long)]
+    admin_rpc_port: Option<u16>,
+    /// Port for the gRPC stream of the finalized blocks. The stream is not served if not set.
+    #[cfg(feature = "grpc")]
+    #[arg(long)]
+    grpc_port: Option<u16>,
     /// IP address and key of the seed peers.
     #[arg(long)]
     add_gossip_static_outbound: Option<NodeAddrs>,
+    /// Start the validator even if its replica state is older than its checkpoint
+    /// (see `replica_checkpoint_dir`), fast-forwarding the state to the checkpoint.
+    /// The validator may equivocate in the views voted after the last checkpoint.
+    #[arg(long)]
+    allow_stale_replica_state: bool,
+    /// Latency (in milliseconds) injected into every frame sent by the node.
+    #[cfg(feature = "chaos")]
+    #[arg(long, default_value_t = 0)]
+    chaos_latency_ms: u64,
+    /// Max random latency (in milliseconds) injected on top of `chaos_latency_ms`,
+    /// which reorders the frames sent concurrently.
+    #[cfg(feature = "chaos")]
+    #[arg(long, default_value_t = 0)]
+    chaos_jitter_ms: u64,
+    /// Probability of dropping a frame sent by the node.
+    #[cfg(feature = "chaos")]
+    #[arg(long, default_value_t = 0.)]
+    chaos_drop_rate: f64,
 }
 
 impl Args {
@@ -50,11 +88,37 @@ impl Args {
     fn config_paths(&self) -> ConfigPaths<'_> {
         ConfigPaths {
             app: &self.config_file,
+            genesis: self.genesis_file.as_deref(),
             node_key: &self.node_key,
             validator_key: (!self.validator_key.as_os_str().is_empty())
                 .then_some(&self.validator_key),
             database: &self.database,
+            key_passphrase: Passphrase::from_env(),
+        }
+    }
+
+    /// Applies the overrides (from the env vars and the command line) to the node configuration.
+    fn apply_overrides(&self, app: &mut AppConfig) -> anyhow::Result<()> {
+        // if `PUBLIC_ADDR` env var is set, use it to override publicAddr in config
+        app.check_public_addr().context("Public Address")?;
+
+        // Add gossipStaticOutbound pairs from cli to config
+        if let Some(addrs) = &self.add_gossip_static_outbound {
+            app.gossip_static_outbound
+                .extend(addrs.0.iter().map(|e| (e.0.key.clone(), e.0.addr)));
         }
+        Ok(())
+    }
+
+    /// Extracts the network faults to inject from these args.
+    #[cfg(feature = "chaos")]
+    fn chaos_config(&self) -> Option<zksync_consensus_network::chaos::Config> {
+        let cfg = zksync_consensus_network::chaos::Config {
+            latency: time::Duration::milliseconds(self.chaos_latency_ms.try_into().ok()?),
+            jitter: time::Duration::milliseconds(self.chaos_jitter_ms.try_into().ok()?),
+            drop_rate: self.chaos_drop_rate,
+        };
+        (cfg != zksync_consensus_network::chaos::Config::default()).then_some(cfg)
     }
 }
 
@@ -69,13 +133,15 @@ async fn main() -> anyhow::Result<()> {
     let log_file = fs::File::create("logs/output.log")?;
 
     // Create the logger for stdout. This will produce human-readable logs for ERROR events.
-    // To see logs for other events, set the RUST_LOG environment to the desired level.
+    // To see logs for other events, set the RUST_LOG environment to the desired level
+    // (or `log_filter` in the config). The filter is replaced when the config is reloaded.
+    let (stdout_filter, stdout_filter_handle) = reload::Layer::new(EnvFilter::from_default_env());
     let stdout_log = tracing_subscriber::fmt::layer()
         .pretty()
         .with_ansi(std::env::var("NO_COLOR").is_err() && std::io::stdout().is_terminal())
         .with_file(false)
         .with_line_number(false)
-        .with_filter(tracing_subscriber::EnvFilter::from_default_env());
+        .with_filter(stdout_filter);
 
     // Create the logger for the log file. This will produce machine-readable logs for
     // all events of level DEBUG or higher.
@@ -100,22 +166,36 @@ async fn main() -> anyhow::Result<()> {
         .config_paths()
         .load()
         .context("config_paths().load()")?;
-
-    // if `PUBLIC_ADDR` env var is set, use it to override publicAddr in config
-    configs.app.check_public_addr().context("Public Address")?;
-
-    // Add gossipStaticOutbound pairs from cli to config
-    if let Some(addrs) = args.add_gossip_static_outbound {
-        configs
-            .app
-            .gossip_static_outbound
-            .extend(addrs.0.into_iter().map(|e| (e.0.key, e.0.addr)));
-    }
+    tracing::info!("genesis hash: {}", configs.app.genesis.hash().encode());
+    args.apply_overrides(&mut configs.app)?;
+    configs.allow_stale_replica_state = args.allow_stale_replica_state;
+    stdout_filter_handle.reload(log_filter(&configs.app)?)?;
 
     let (executor, runner) = configs
         .make_executor(ctx)
         .await
         .context("configs.into_executor()")?;
+    let (reload_send, reload_recv) = sync::watch::channel(configs.app.reloadable());
+    let log_filter = zksync_consensus_executor::LogFilter::new(stdout_filter_handle.clone());
+    let executor = executor.reload(reload_recv).log_filter(log_filter.clone());
+    #[cfg(feature = "chaos")]
+    let executor = match args.chaos_config() {
+        Some(cfg) => {
+            tracing::warn!("injecting network faults: {cfg:?}");
+            executor.chaos(cfg)
+        }
+        None => executor,
+    };
+    #[cfg(feature = "grpc")]
+    let executor = match args.grpc_port {
+        Some(port) => {
+            let mut addr = configs.app.server_addr;
+            addr.set_port(port);
+            executor.grpc(addr)
+        }
+        None => executor,
+    };
+    let executor = executor.build().context("executor.build()")?;
 
     let mut rpc_addr = configs.app.public_addr;
     if let Some(port) = args.rpc_port {
@@ -124,9 +204,25 @@ async fn main() -> anyhow::Result<()> {
         rpc_addr.set_port(rpc_addr.port() + 100);
     }
 
+    // Config reloads requested via the RPC server (or SIGHUP on unix).
+    let (reload_requests_send, mut reload_requests) = ctx::channel::unbounded::<ReloadRequest>();
+
     // cloning configuration to let RPCServer show it
     // TODO this should be queried in real time instead, to reflect any possible change in config
-    let rpc_server = RPCServer::new(rpc_addr, configs.app.clone());
+    let rpc_server = RPCServer::new(
+        rpc_addr,
+        configs.app.clone(),
+        executor.block_store().reader(),
+    )
+    .with_log_filter(log_filter)
+    .with_config_reload(reload_requests_send.clone())
+    .with_wire_dumps(executor.network_monitor().clone(), "logs/dumps".into())
+    .with_view_history(executor.view_history().clone())
+    .with_network_monitor(executor.network_monitor().clone());
+    let rpc_server = match args.admin_rpc_port {
+        Some(port) => rpc_server.with_admin_port(port),
+        None => rpc_server,
+    };
 
     // Initialize the storage.
     scope::run!(ctx, |ctx, s| async {
@@ -139,6 +235,56 @@ async fn main() -> anyhow::Result<()> {
                 Ok(())
             });
         }
+        #[cfg(unix)]
+        s.spawn_bg(async {
+            let mut hangup = signal(SignalKind::hangup())?;
+            while let Ok(Some(())) = ctx.wait(hangup.recv()).await {
+                // Nobody awaits the outcome, it is just logged.
+                reload_requests_send.send(oneshot::channel().0);
+            }
+            Ok(())
+        });
+        s.spawn_bg(async {
+            let mut current = configs.app.clone();
+            let mut modified = modified_time(&args.config_file);
+            loop {
+                // Reload on request or when the config file is modified.
+                let req = reload_requests
+                    .recv(&ctx.with_timeout(CONFIG_POLL_INTERVAL))
+                    .await
+                    .ok();
+                if !ctx.is_active() {
+                    return Ok(());
+                }
+                let m = modified_time(&args.config_file);
+                if req.is_none() && m == modified {
+                    continue;
+                }
+                modified = m;
+                let res = (|| {
+                    let mut new = args.config_paths().load_app()?;
+                    args.apply_overrides(&mut new)?;
+                    current.check_reload(&new)?;
+                    let filter = log_filter(&new)?;
+                    if new == current {
+                        return Ok(());
+                    }
+                    if new.log_filter != current.log_filter {
+                        stdout_filter_handle.reload(filter)?;
+                    }
+                    reload_send.send_replace(new.reloadable());
+                    tracing::info!("config reloaded");
+                    current = new;
+                    anyhow::Ok(())
+                })();
+                if let Err(err) = &res {
+                    tracing::error!("config reload rejected: {err:#}");
+                }
+                if let Some(req) = req {
+                    let _ = req.send(res);
+                }
+            }
+        });
         s.spawn_bg(runner.run(ctx));
         s.spawn(executor.run(ctx));
         s.spawn(rpc_server.run(ctx));
@@ -146,3 +292,16 @@ async fn main() -> anyhow::Result<()> {
     })
     .await
 }
+
+/// Filter of the logs printed to stdout.
+fn log_filter(app: &AppConfig) -> anyhow::Result<EnvFilter> {
+    Ok(match &app.log_filter {
+        Some(filter) => EnvFilter::try_new(filter).context("log_filter")?,
+        None => EnvFilter::from_default_env(),
+    })
+}
+
+/// Last modification time of the file.
+fn modified_time(path: &std::path::Path) -> Option<std::time::SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
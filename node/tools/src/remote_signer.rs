@@ -0,0 +1,87 @@
+//! Validator signer delegating the signing to an external service (e.g. backed by an HSM).
+use anyhow::Context as _;
+use jsonrpsee::{
+    core::client::ClientT,
+    http_client::{HttpClient, HttpClientBuilder},
+    rpc_params,
+};
+use std::fmt;
+use zksync_concurrency::{ctx, time};
+use zksync_consensus_crypto::{Text, TextFmt};
+use zksync_consensus_roles::validator;
+
+/// JSON-RPC method of the signing service.
+/// Params: `[<ValidatorPublicKey>, <validator message hash>]` (text encoded).
+/// Result: `<ValidatorSignature>` (text encoded).
+pub(crate) const SIGN_HASH_METHOD: &str = "validator_signHash";
+
+/// Config of the remote signer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteSignerConfig {
+    /// URL of the JSON-RPC endpoint of the signing service.
+    pub url: String,
+    /// Public key of the validator, which the service signs with.
+    pub public_key: validator::PublicKey,
+    /// Timeout of a single signing request.
+    pub request_timeout: time::Duration,
+}
+
+impl RemoteSignerConfig {
+    /// Default timeout of a single signing request.
+    pub const DEFAULT_REQUEST_TIMEOUT: time::Duration = time::Duration::seconds(5);
+}
+
+/// Validator signer which sends message hashes to be signed to an external service
+/// over JSON-RPC (HTTP). The returned signatures are verified before use, so that
+/// a misconfigured service cannot make the node broadcast invalid messages.
+pub struct RemoteSigner {
+    cfg: RemoteSignerConfig,
+    client: HttpClient,
+}
+
+impl fmt::Debug for RemoteSigner {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("RemoteSigner")
+            .field("url", &self.cfg.url)
+            .field("public_key", &self.cfg.public_key)
+            .finish()
+    }
+}
+
+impl RemoteSigner {
+    /// Constructs a new remote signer. Doesn't connect to the service yet.
+    pub fn new(cfg: RemoteSignerConfig) -> anyhow::Result<Self> {
+        let client = HttpClientBuilder::default()
+            .request_timeout(cfg.request_timeout.try_into().context("request_timeout")?)
+            .build(&cfg.url)
+            .context("HttpClientBuilder::build()")?;
+        Ok(Self { cfg, client })
+    }
+}
+
+#[async_trait::async_trait]
+impl validator::ValidatorSigner for RemoteSigner {
+    fn public(&self) -> validator::PublicKey {
+        self.cfg.public_key.clone()
+    }
+
+    async fn sign_hash(
+        &self,
+        ctx: &ctx::Ctx,
+        msg_hash: &validator::MsgHash,
+    ) -> ctx::Result<validator::Signature> {
+        let sig: String = ctx
+            .wait(self.client.request(
+                SIGN_HASH_METHOD,
+                rpc_params![self.cfg.public_key.encode(), msg_hash.encode()],
+            ))
+            .await?
+            .context(SIGN_HASH_METHOD)?;
+        let sig: validator::Signature = Text::new(&sig)
+            .decode()
+            .context("failed decoding signature")?;
+        sig.verify_hash(msg_hash, &self.cfg.public_key)
+            .context("signing service returned an invalid signature")?;
+        Ok(sig)
+    }
+}
@@ -0,0 +1,164 @@
+//! Read API of the chain for RPC server.
+//! Lets external indexers follow the finalized blocks over JSON-RPC (HTTP or WebSocket)
+//! without linking the consensus crates. Blocks and genesis are returned in the
+//! protobuf JSON mapping, so they can be verified by the callers.
+use jsonrpsee::{
+    types::{error::ErrorCode, Params},
+    PendingSubscriptionSink, SubscriptionMessage,
+};
+use zksync_concurrency::{ctx, time};
+use zksync_consensus_crypto::TextFmt;
+use zksync_consensus_roles::validator;
+use zksync_consensus_storage::BlockStoreReader;
+use zksync_protobuf::serde::Serde;
+
+/// Timeout for reading a block from storage.
+const READ_TIMEOUT: time::Duration = time::Duration::seconds(10);
+
+/// Encodes a finalized block.
+fn encode(genesis: &validator::Genesis, block: &validator::FinalBlock) -> serde_json::Value {
+    serde_json::json!({
+        "number": block.number().0,
+        "hash": genesis.header_hash(block.header()).encode(),
+        "block": Serde(block.clone()),
+    })
+}
+
+/// Block by number method for RPC server.
+pub(crate) struct GetBlock;
+
+impl GetBlock {
+    /// Returns the finalized block (payload and justification) with the given number,
+    /// or null if the block is not available.
+    pub(crate) async fn callback(
+        ctx: &ctx::Ctx,
+        params: Params<'_>,
+        block_store: &BlockStoreReader,
+    ) -> Result<serde_json::Value, ErrorCode> {
+        let number: u64 = params.one().map_err(|_| ErrorCode::InvalidParams)?;
+        let ctx = &ctx.with_timeout(READ_TIMEOUT);
+        let block = block_store
+            .block(ctx, validator::BlockNumber(number))
+            .await
+            .map_err(|_| ErrorCode::InternalError)?;
+        Ok(block.as_ref().map_or(serde_json::Value::Null, |b| {
+            encode(&block_store.genesis(), b)
+        }))
+    }
+
+    /// Get block method name.
+    pub(crate) fn method() -> &'static str {
+        "consensus_getBlock"
+    }
+}
+
+/// Genesis method for RPC server.
+pub(crate) struct GetGenesis;
+
+impl GetGenesis {
+    /// Returns the genesis of the chain and its hash.
+    pub(crate) fn callback(block_store: &BlockStoreReader) -> Result<serde_json::Value, ErrorCode> {
+        let genesis = block_store.genesis();
+        Ok(serde_json::json!({
+            "hash": genesis.hash().encode(),
+            "genesis": Serde((*genesis).clone()),
+        }))
+    }
+
+    /// Get genesis method name.
+    pub(crate) fn method() -> &'static str {
+        "consensus_getGenesis"
+    }
+
+    /// Method path for GET requests.
+    pub(crate) fn path() -> &'static str {
+        "/genesis"
+    }
+}
+
+/// Consensus status method for RPC server.
+pub(crate) struct Status;
+
+impl Status {
+    /// Returns the range of the stored blocks (with its gaps) and the latest finalized block.
+    pub(crate) fn callback(block_store: &BlockStoreReader) -> Result<serde_json::Value, ErrorCode> {
+        let state = block_store.state();
+        Ok(serde_json::json!({
+            "genesis_hash": block_store.genesis().hash().encode(),
+            "first_block": state.first.0,
+            "next_block": state.next().0,
+            "gaps": state.gaps.iter().map(|g| [g.first.0, g.last.0]).collect::<Vec<_>>(),
+            "missing_blocks": state.missing_blocks(),
+            "last_finalized": state.last.as_ref().map(|qc| serde_json::json!({
+                "number": qc.header().number.0,
+                "hash": block_store.genesis().header_hash(qc.header()).encode(),
+                "view": qc.view().number.0,
+            })),
+        }))
+    }
+
+    /// Status method name.
+    pub(crate) fn method() -> &'static str {
+        "consensus_status"
+    }
+
+    /// Method path for GET requests.
+    pub(crate) fn path() -> &'static str {
+        "/status"
+    }
+}
+
+/// Subscription to the newly finalized blocks.
+pub(crate) struct SubscribeBlocks;
+
+impl SubscribeBlocks {
+    /// Streams the finalized blocks in order, starting from the block with the given number
+    /// (or from the next block to be finalized, if no number is given).
+    /// The subscription ends when the client unsubscribes or disconnects, or the server stops.
+    pub(crate) async fn callback(
+        ctx: &ctx::Ctx,
+        params: Params<'static>,
+        pending: PendingSubscriptionSink,
+        block_store: &BlockStoreReader,
+    ) -> jsonrpsee::core::SubscriptionResult {
+        let from: Option<u64> = params.sequence().optional_next()?;
+        let sink = pending.accept().await?;
+        let state = block_store.state();
+        let mut next = from
+            .map_or(state.next(), validator::BlockNumber)
+            .max(state.first);
+        loop {
+            let block = tokio::select! {
+                () = sink.closed() => return Ok(()),
+                block = async {
+                    block_store.wait_until_queued(ctx, next).await?;
+                    block_store.block(ctx, next).await
+                } => block?,
+            };
+            // Blocks missing from the store (e.g. pruned) are skipped.
+            if let Some(block) = block {
+                sink.send(SubscriptionMessage::from_json(&encode(
+                    &block_store.genesis(),
+                    &block,
+                ))?)
+                .await?;
+            }
+            next = next.next();
+        }
+    }
+
+    /// Subscribe method name.
+    pub(crate) fn method() -> &'static str {
+        "consensus_subscribeFinalizedBlocks"
+    }
+
+    /// Name of the notifications sent to the subscribers.
+    pub(crate) fn notification() -> &'static str {
+        "consensus_finalizedBlock"
+    }
+
+    /// Unsubscribe method name.
+    pub(crate) fn unsubscribe_method() -> &'static str {
+        "consensus_unsubscribeFinalizedBlocks"
+    }
+}
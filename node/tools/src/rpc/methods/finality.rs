@@ -0,0 +1,73 @@
+//! Finality methods for RPC server.
+//! Responses contain the `CommitQC` of the requested block, so that the callers
+//! can verify them against the genesis themselves, rather than trusting the node.
+use jsonrpsee::types::{error::ErrorCode, Params};
+use zksync_concurrency::{ctx, time};
+use zksync_consensus_crypto::TextFmt;
+use zksync_consensus_roles::validator;
+use zksync_consensus_storage::BlockStoreReader;
+use zksync_protobuf::serde::Serde;
+
+/// Timeout for reading a block from storage.
+const READ_TIMEOUT: time::Duration = time::Duration::seconds(10);
+
+/// Encodes the finality proof of a block.
+fn encode(genesis: &validator::Genesis, qc: &validator::CommitQC) -> serde_json::Value {
+    serde_json::json!({
+        "number": qc.header().number.0,
+        "hash": genesis.header_hash(qc.header()).encode(),
+        "justification": Serde(qc.clone()),
+    })
+}
+
+/// Latest finalized block method for RPC server.
+pub(crate) struct LatestFinalized;
+
+impl LatestFinalized {
+    /// Returns the header and `CommitQC` of the latest finalized block,
+    /// or null if no block has been finalized yet.
+    pub(crate) fn callback(block_store: &BlockStoreReader) -> Result<serde_json::Value, ErrorCode> {
+        let state = block_store.state();
+        Ok(state.last.as_ref().map_or(serde_json::Value::Null, |qc| {
+            encode(&block_store.genesis(), qc)
+        }))
+    }
+
+    /// Latest finalized method name.
+    pub(crate) fn method() -> &'static str {
+        "latest_finalized"
+    }
+
+    /// Method path for GET requests.
+    pub(crate) fn path() -> &'static str {
+        "/finalized/latest"
+    }
+}
+
+/// Finalized block by number method for RPC server.
+pub(crate) struct Finalized;
+
+impl Finalized {
+    /// Returns the header and `CommitQC` of the finalized block with the given number,
+    /// or null if the block is not available.
+    pub(crate) async fn callback(
+        ctx: &ctx::Ctx,
+        params: Params<'_>,
+        block_store: &BlockStoreReader,
+    ) -> Result<serde_json::Value, ErrorCode> {
+        let number: u64 = params.one().map_err(|_| ErrorCode::InvalidParams)?;
+        let ctx = &ctx.with_timeout(READ_TIMEOUT);
+        let block = block_store
+            .block(ctx, validator::BlockNumber(number))
+            .await
+            .map_err(|_| ErrorCode::InternalError)?;
+        Ok(block.map_or(serde_json::Value::Null, |b| {
+            encode(&block_store.genesis(), &b.justification)
+        }))
+    }
+
+    /// Finalized method name.
+    pub(crate) fn method() -> &'static str {
+        "finalized"
+    }
+}
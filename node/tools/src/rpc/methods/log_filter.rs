@@ -0,0 +1,41 @@
+//! Log filter method for RPC server.
+use jsonrpsee::types::{error::ErrorCode, Params};
+use zksync_consensus_executor::LogFilter;
+
+/// Log filter method for RPC server.
+/// Returns the filter of the node logs. Called with a filter (in the `RUST_LOG` syntax),
+/// replaces it first, so that operators can raise the verbosity of a misbehaving peer
+/// without restarting the node. The filter is reset when `log_filter` in the config changes.
+pub(crate) struct LogFilterInfo;
+
+impl LogFilterInfo {
+    /// Log filter response for /log_filter endpoint.
+    pub(crate) fn callback(
+        params: Params,
+        log_filter: &LogFilter,
+    ) -> Result<serde_json::Value, ErrorCode> {
+        let directives: Option<String> = params
+            .sequence()
+            .optional_next()
+            .map_err(|_| ErrorCode::InvalidParams)?;
+        if let Some(directives) = directives {
+            log_filter
+                .set(&directives)
+                .map_err(|_| ErrorCode::InvalidParams)?;
+        }
+        let filter = log_filter.get().map_err(|_| ErrorCode::InternalError)?;
+        Ok(serde_json::json!({
+            "filter": filter
+        }))
+    }
+
+    /// Log filter method name.
+    pub(crate) fn method() -> &'static str {
+        "log_filter"
+    }
+
+    /// Method path for GET requests.
+    pub(crate) fn path() -> &'static str {
+        "/log_filter"
+    }
+}
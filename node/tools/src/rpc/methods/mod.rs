@@ -10,6 +10,14 @@ pub trait RPCMethod {
     fn path() -> &'static str;
 }
 
+pub(crate) mod chain;
 pub(crate) mod config;
+pub(crate) mod finality;
 pub mod health_check;
+pub(crate) mod log_filter;
+pub(crate) mod peer_pings;
+pub(crate) mod peer_traffic;
 pub(crate) mod peers;
+pub(crate) mod reload_config;
+pub(crate) mod view_history;
+pub(crate) mod wire_dump;
@@ -0,0 +1,42 @@
+//! Peer pings method for RPC server.
+use jsonrpsee::types::error::ErrorCode;
+use zksync_concurrency::time;
+use zksync_consensus_crypto::TextFmt;
+use zksync_consensus_network as network;
+
+/// Peer pings method for RPC server.
+/// Lists the round trip times and the clock skews of the connected gossip peers,
+/// so that operators can spot misconfigured clocks.
+pub(crate) struct PeerPings;
+
+impl PeerPings {
+    /// Peer pings response for /peer_pings endpoint.
+    pub(crate) fn callback(monitor: &network::Monitor) -> Result<serde_json::Value, ErrorCode> {
+        let peers: Vec<_> = monitor
+            .peer_pings()
+            .iter()
+            .map(|p| {
+                serde_json::json!({
+                    "key": p.key.encode(),
+                    "rtt_ms": p.rtt.whole_milliseconds(),
+                    "clock_skew_ms": p.clock_skew.map(|s| s.whole_milliseconds()),
+                    "clock_skewed": p.clock_skew.map(|s| s.abs() > network::MAX_CLOCK_SKEW),
+                    "measured": (p.measured - time::UNIX_EPOCH).whole_seconds(),
+                })
+            })
+            .collect();
+        Ok(serde_json::json!({
+            "peers": peers
+        }))
+    }
+
+    /// Peer pings method name.
+    pub(crate) fn method() -> &'static str {
+        "peer_pings"
+    }
+
+    /// Method path for GET requests.
+    pub(crate) fn path() -> &'static str {
+        "/peer_pings"
+    }
+}
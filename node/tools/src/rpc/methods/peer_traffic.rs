@@ -0,0 +1,46 @@
+//! Peer traffic method for RPC server.
+use jsonrpsee::types::error::ErrorCode;
+use zksync_consensus_crypto::TextFmt;
+use zksync_consensus_network as network;
+
+/// Peer traffic method for RPC server.
+/// Lists the bytes sent to and received from the connected gossip peers, per RPC,
+/// so that operators can spot the peers consuming most of the bandwidth.
+pub(crate) struct PeerTraffic;
+
+impl PeerTraffic {
+    /// Peer traffic response for /peer_traffic endpoint.
+    pub(crate) fn callback(monitor: &network::Monitor) -> Result<serde_json::Value, ErrorCode> {
+        let stats =
+            |s: &network::TrafficStats| serde_json::json!({"sent": s.sent, "received": s.received});
+        let peers: Vec<_> = monitor
+            .peer_traffic()
+            .iter()
+            .map(|p| {
+                let by_class: serde_json::Map<_, _> = p
+                    .by_class
+                    .iter()
+                    .map(|(class, s)| (class.to_string(), stats(s)))
+                    .collect();
+                serde_json::json!({
+                    "key": p.key.encode(),
+                    "total": stats(&p.total),
+                    "by_class": by_class,
+                })
+            })
+            .collect();
+        Ok(serde_json::json!({
+            "peers": peers
+        }))
+    }
+
+    /// Peer traffic method name.
+    pub(crate) fn method() -> &'static str {
+        "peer_traffic"
+    }
+
+    /// Method path for GET requests.
+    pub(crate) fn path() -> &'static str {
+        "/peer_traffic"
+    }
+}
@@ -0,0 +1,40 @@
+//! Config reload method for RPC server.
+use jsonrpsee::types::error::ErrorCode;
+use zksync_concurrency::{ctx, oneshot};
+
+/// Request to reload the node config, with the channel on which the outcome is reported.
+pub type ReloadRequest = oneshot::Sender<anyhow::Result<()>>;
+
+/// Config reload method for RPC server.
+/// Reloads the config of the node from its config file, like SIGHUP does on unix,
+/// so that the reload can be triggered on any platform. Returns whether the new
+/// config has been applied and the reason if it has been rejected.
+pub(crate) struct ReloadConfig;
+
+impl ReloadConfig {
+    /// Sends the reload request and awaits its outcome.
+    pub(crate) async fn callback(
+        ctx: &ctx::Ctx,
+        requests: &ctx::channel::UnboundedSender<ReloadRequest>,
+    ) -> Result<serde_json::Value, ErrorCode> {
+        let (send, recv) = oneshot::channel();
+        requests.send(send);
+        let res = recv
+            .recv_or_disconnected(ctx)
+            .await
+            .map_err(|_| ErrorCode::InternalError)?
+            .map_err(|_| ErrorCode::InternalError)?;
+        Ok(match res {
+            Ok(()) => serde_json::json!({ "reloaded": true }),
+            Err(err) => serde_json::json!({
+                "reloaded": false,
+                "error": format!("{err:#}"),
+            }),
+        })
+    }
+
+    /// Config reload method name.
+    pub(crate) fn method() -> &'static str {
+        "reload_config"
+    }
+}
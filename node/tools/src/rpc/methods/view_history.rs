@@ -0,0 +1,42 @@
+//! View history method for RPC server.
+use jsonrpsee::types::error::ErrorCode;
+use zksync_concurrency::time;
+use zksync_consensus_bft as bft;
+use zksync_consensus_crypto::TextFmt;
+
+/// View history method for RPC server.
+/// Lists the recent view transitions of the replica, so that operators
+/// can find out which leaders stall the consensus.
+pub(crate) struct ViewHistory;
+
+impl ViewHistory {
+    /// View history response for /view_history endpoint.
+    pub(crate) fn callback(history: &bft::ViewHistory) -> Result<serde_json::Value, ErrorCode> {
+        let transitions: Vec<_> = history
+            .get()
+            .iter()
+            .map(|t| {
+                serde_json::json!({
+                    "view": t.view.0,
+                    "leader": t.leader.encode(),
+                    "reason": t.reason.as_str(),
+                    "ended": (t.ended - time::UNIX_EPOCH).whole_seconds(),
+                    "duration_ms": t.duration.whole_milliseconds(),
+                })
+            })
+            .collect();
+        Ok(serde_json::json!({
+            "transitions": transitions
+        }))
+    }
+
+    /// View history method name.
+    pub(crate) fn method() -> &'static str {
+        "view_history"
+    }
+
+    /// Method path for GET requests.
+    pub(crate) fn path() -> &'static str {
+        "/view_history"
+    }
+}
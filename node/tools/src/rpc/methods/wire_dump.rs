@@ -0,0 +1,79 @@
+//! Wire dump method for RPC server.
+use jsonrpsee::types::{error::ErrorCode, Params};
+use std::path::Path;
+use zksync_concurrency::{ctx, time};
+use zksync_consensus_crypto::TextFmt;
+use zksync_consensus_network as network;
+use zksync_consensus_roles::{node, validator};
+
+/// Max duration of a dump, so that a forgotten dump doesn't fill up the disk.
+const MAX_DURATION: time::Duration = time::Duration::hours(1);
+
+/// Wire dump method for RPC server.
+/// Called with a peer key and a duration (in seconds), dumps the messages exchanged
+/// with the peer for that long (see `network::Monitor::start_dump`); a zero duration stops
+/// the dump. Returns the active dumps.
+pub(crate) struct WireDump;
+
+impl WireDump {
+    /// Wire dump response for /wire_dump endpoint.
+    pub(crate) async fn callback(
+        ctx: &ctx::Ctx,
+        params: Params<'_>,
+        monitor: &network::Monitor,
+        dir: &Path,
+    ) -> Result<serde_json::Value, ErrorCode> {
+        let now = ctx.now_utc();
+        let mut params = params.sequence();
+        let peer: Option<String> = params
+            .optional_next()
+            .map_err(|_| ErrorCode::InvalidParams)?;
+        if let Some(peer) = peer {
+            if node::PublicKey::decode(&peer).is_err()
+                && validator::PublicKey::decode(&peer).is_err()
+            {
+                return Err(ErrorCode::InvalidParams);
+            }
+            let secs: u32 = params.next().map_err(|_| ErrorCode::InvalidParams)?;
+            let duration = time::Duration::seconds(secs.into());
+            if duration > MAX_DURATION {
+                return Err(ErrorCode::InvalidParams);
+            }
+            if duration.is_zero() {
+                monitor
+                    .stop_dump(ctx, &peer)
+                    .await
+                    .map_err(|_| ErrorCode::InternalError)?;
+            } else {
+                monitor
+                    .start_dump(ctx, &peer, dir, now + duration)
+                    .await
+                    .map_err(|_| ErrorCode::InternalError)?;
+            }
+        }
+        let dumps: Vec<_> = monitor
+            .dumps(now)
+            .iter()
+            .map(|d| {
+                serde_json::json!({
+                    "peer": d.peer,
+                    "path": d.path,
+                    "until": (d.until - time::UNIX_EPOCH).whole_seconds(),
+                })
+            })
+            .collect();
+        Ok(serde_json::json!({
+            "dumps": dumps
+        }))
+    }
+
+    /// Wire dump method name.
+    pub(crate) fn method() -> &'static str {
+        "wire_dump"
+    }
+
+    /// Method path for GET requests.
+    pub(crate) fn path() -> &'static str {
+        "/wire_dump"
+    }
+}
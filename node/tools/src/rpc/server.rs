@@ -1,9 +1,29 @@
 use crate::AppConfig;
 
-use super::methods::{config::ConfigInfo, health_check::HealthCheck, peers::PeersInfo, RPCMethod};
+use super::methods::{
+    chain::{GetBlock, GetGenesis, Status, SubscribeBlocks},
+    config::ConfigInfo,
+    finality::{Finalized, LatestFinalized},
+    health_check::HealthCheck,
+    log_filter::LogFilterInfo,
+    peer_pings::PeerPings,
+    peer_traffic::PeerTraffic,
+    peers::PeersInfo,
+    reload_config::{ReloadConfig, ReloadRequest},
+    view_history::ViewHistory,
+    wire_dump::WireDump,
+    RPCMethod,
+};
 use jsonrpsee::server::{middleware::http::ProxyGetRequestLayer, RpcModule, Server};
-use std::net::SocketAddr;
-use zksync_concurrency::{ctx, scope};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+};
+use zksync_concurrency::{ctx, scope, time};
+use zksync_consensus_bft as bft;
+use zksync_consensus_executor::LogFilter;
+use zksync_consensus_network as network;
+use zksync_consensus_storage::BlockStoreReader;
 
 /// RPC server.
 pub struct RPCServer {
@@ -11,11 +31,78 @@ pub struct RPCServer {
     ip_address: SocketAddr,
     /// AppConfig
     config: AppConfig,
+    /// Block store, used to serve finality queries.
+    block_store: BlockStoreReader,
+    /// Filter of the node logs, adjustable via the `log_filter` method.
+    log_filter: Option<LogFilter>,
+    /// Network and directory of the wire dumps, started via the `wire_dump` method.
+    wire_dumps: Option<(network::Monitor, PathBuf)>,
+    /// History of the view transitions, served by the `view_history` method.
+    view_history: Option<bft::ViewHistory>,
+    /// Network state, served by the `peer_pings` and `peer_traffic` methods.
+    network_monitor: Option<network::Monitor>,
+    /// Requests of the config reloads, sent by the `reload_config` method.
+    config_reload: Option<ctx::channel::UnboundedSender<ReloadRequest>>,
+    /// Port of the admin RPC server, which serves the methods changing the node state.
+    /// It is bound to localhost. The admin methods are not served if not set.
+    admin_port: Option<u16>,
 }
 
 impl RPCServer {
-    pub fn new(ip_address: SocketAddr, config: AppConfig) -> Self {
-        Self { ip_address, config }
+    pub fn new(ip_address: SocketAddr, config: AppConfig, block_store: BlockStoreReader) -> Self {
+        Self {
+            ip_address,
+            config,
+            block_store,
+            log_filter: None,
+            wire_dumps: None,
+            view_history: None,
+            network_monitor: None,
+            config_reload: None,
+            admin_port: None,
+        }
+    }
+
+    /// Exposes the `view_history` method (see `Executor::view_history()`).
+    pub fn with_view_history(mut self, view_history: bft::ViewHistory) -> Self {
+        self.view_history = Some(view_history);
+        self
+    }
+
+    /// Exposes the `peer_pings` and `peer_traffic` methods (see `Executor::network_monitor()`).
+    pub fn with_network_monitor(mut self, network_monitor: network::Monitor) -> Self {
+        self.network_monitor = Some(network_monitor);
+        self
+    }
+
+    /// Serves the admin methods on `127.0.0.1:port`. The RPC server is not authenticated,
+    /// so the methods changing the node state are never served on the public address.
+    pub fn with_admin_port(mut self, port: u16) -> Self {
+        self.admin_port = Some(port);
+        self
+    }
+
+    /// Exposes the `log_filter` admin method, which allows to change the filter of the node logs.
+    pub fn with_log_filter(mut self, log_filter: LogFilter) -> Self {
+        self.log_filter = Some(log_filter);
+        self
+    }
+
+    /// Exposes the `wire_dump` admin method, which dumps the messages exchanged with a peer
+    /// of the network (see `Executor::network_monitor()`) to the files in `dir`.
+    pub fn with_wire_dumps(mut self, network_monitor: network::Monitor, dir: PathBuf) -> Self {
+        self.wire_dumps = Some((network_monitor, dir));
+        self
+    }
+
+    /// Exposes the `reload_config` admin method, which sends the config reload requests
+    /// to `requests`.
+    pub fn with_config_reload(
+        mut self,
+        requests: ctx::channel::UnboundedSender<ReloadRequest>,
+    ) -> Self {
+        self.config_reload = Some(requests);
+        self
     }
 
     /// Runs the RPC server.
@@ -34,18 +121,55 @@ impl RPCServer {
             .layer(ProxyGetRequestLayer::new(
                 ConfigInfo::path(),
                 ConfigInfo::method(),
-            )?);
+            )?)
+            .layer(ProxyGetRequestLayer::new(
+                LatestFinalized::path(),
+                LatestFinalized::method(),
+            )?)
+            .layer(ProxyGetRequestLayer::new(
+                ViewHistory::path(),
+                ViewHistory::method(),
+            )?)
+            .layer(ProxyGetRequestLayer::new(
+                PeerPings::path(),
+                PeerPings::method(),
+            )?)
+            .layer(ProxyGetRequestLayer::new(
+                PeerTraffic::path(),
+                PeerTraffic::method(),
+            )?)
+            .layer(ProxyGetRequestLayer::new(
+                GetGenesis::path(),
+                GetGenesis::method(),
+            )?)
+            .layer(ProxyGetRequestLayer::new(Status::path(), Status::method())?);
 
         let server = Server::builder()
             .set_http_middleware(service_builder)
             .build(self.ip_address)
             .await?;
 
-        let mut module = RpcModule::new(());
+        // Context of the request handlers, canceled together with the server.
+        let mut module = RpcModule::new(ctx.with_deadline(time::Deadline::Infinite));
         module.register_method(HealthCheck::method(), |params, _| {
             HealthCheck::callback(params)
         })?;
         module.register_method(PeersInfo::method(), |params, _| PeersInfo::callback(params))?;
+        if let Some(history) = self.view_history.clone() {
+            module.register_method(ViewHistory::method(), move |_params, _| {
+                ViewHistory::callback(&history)
+            })?;
+        }
+        if let Some(monitor) = self.network_monitor.clone() {
+            module.register_method(PeerPings::method(), move |_params, _| {
+                PeerPings::callback(&monitor)
+            })?;
+        }
+        if let Some(monitor) = self.network_monitor.clone() {
+            module.register_method(PeerTraffic::method(), move |_params, _| {
+                PeerTraffic::callback(&monitor)
+            })?;
+        }
 
         // TODO find a better way to implement this as I had to clone the clone and move it to pass the borrow checker
         let config = self.config.clone();
@@ -53,17 +177,95 @@ impl RPCServer {
             ConfigInfo::info(config.clone())
         })?;
 
-        let handle = server.start(module);
+        let block_store = self.block_store.clone();
+        module.register_method(LatestFinalized::method(), move |_params, _| {
+            LatestFinalized::callback(&block_store)
+        })?;
+        let block_store = self.block_store.clone();
+        module.register_async_method(Finalized::method(), move |params, ctx| {
+            let block_store = block_store.clone();
+            async move { Finalized::callback(&ctx, params, &block_store).await }
+        })?;
+
+        let block_store = self.block_store.clone();
+        module.register_async_method(GetBlock::method(), move |params, ctx| {
+            let block_store = block_store.clone();
+            async move { GetBlock::callback(&ctx, params, &block_store).await }
+        })?;
+        let block_store = self.block_store.clone();
+        module.register_method(GetGenesis::method(), move |_params, _| {
+            GetGenesis::callback(&block_store)
+        })?;
+        let block_store = self.block_store.clone();
+        module.register_method(Status::method(), move |_params, _| {
+            Status::callback(&block_store)
+        })?;
+        // Subscriptions are served over WebSocket connections.
+        let block_store = self.block_store.clone();
+        module.register_subscription(
+            SubscribeBlocks::method(),
+            SubscribeBlocks::notification(),
+            SubscribeBlocks::unsubscribe_method(),
+            move |params, pending, ctx| {
+                let block_store = block_store.clone();
+                async move { SubscribeBlocks::callback(&ctx, params, pending, &block_store).await }
+            },
+        )?;
+
+        let mut handles = vec![server.start(module)];
+        if let Some(port) = self.admin_port {
+            let service_builder = tower::ServiceBuilder::new()
+                .layer(ProxyGetRequestLayer::new(
+                    LogFilterInfo::path(),
+                    LogFilterInfo::method(),
+                )?)
+                .layer(ProxyGetRequestLayer::new(
+                    WireDump::path(),
+                    WireDump::method(),
+                )?);
+            let server = Server::builder()
+                .set_http_middleware(service_builder)
+                .build(SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+                .await?;
+            handles.push(server.start(self.admin_module(ctx)?));
+        }
         scope::run!(ctx, |ctx, s| async {
             s.spawn_bg(async {
                 ctx.canceled().await;
-                // Ignore `AlreadyStoppedError`.
-                let _ = handle.stop();
+                for handle in &handles {
+                    // Ignore `AlreadyStoppedError`.
+                    let _ = handle.stop();
+                }
                 Ok(())
             });
-            handle.clone().stopped().await;
+            for handle in &handles {
+                handle.clone().stopped().await;
+            }
             Ok(())
         })
         .await
     }
+
+    /// Module of the admin methods, which change the node state.
+    fn admin_module(&self, ctx: &ctx::Ctx) -> anyhow::Result<RpcModule<ctx::Ctx>> {
+        let mut module = RpcModule::new(ctx.with_deadline(time::Deadline::Infinite));
+        if let Some(log_filter) = self.log_filter.clone() {
+            module.register_method(LogFilterInfo::method(), move |params, _| {
+                LogFilterInfo::callback(params, &log_filter)
+            })?;
+        }
+        if let Some(requests) = self.config_reload.clone() {
+            module.register_async_method(ReloadConfig::method(), move |_params, ctx| {
+                let requests = requests.clone();
+                async move { ReloadConfig::callback(&ctx, &requests).await }
+            })?;
+        }
+        if let Some((monitor, dir)) = self.wire_dumps.clone() {
+            module.register_async_method(WireDump::method(), move |params, ctx| {
+                let (monitor, dir) = (monitor.clone(), dir.clone());
+                async move { WireDump::callback(&ctx, params, &monitor, &dir).await }
+            })?;
+        }
+        Ok(module)
+    }
 }
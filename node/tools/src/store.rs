@@ -1,4 +1,4 @@
-//! RocksDB-based implementation of PersistentBlockStore and ReplicaStore.
+//! RocksDB-based implementation of PersistentBlockStore, ReplicaStore and SyncProgressStore.
 use anyhow::Context as _;
 use rocksdb::{Direction, IteratorMode, ReadOptions};
 use std::{
@@ -8,7 +8,9 @@ use std::{
 };
 use zksync_concurrency::{ctx, error::Wrap as _, scope};
 use zksync_consensus_roles::validator;
-use zksync_consensus_storage::{PersistentBlockStore, ReplicaState, ReplicaStore};
+use zksync_consensus_storage::{
+    PersistentBlockStore, ReplicaState, ReplicaStore, SyncProgress, SyncProgressStore,
+};
 
 /// Enum used to represent a key in the database. It also acts as a separator between different stores.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -16,8 +18,15 @@ enum DatabaseKey {
     /// Key used to store the replica state.
     /// ReplicaState -> ReplicaState
     ReplicaState,
+    /// Key used to store the progress of the block syncing.
+    /// SyncProgress -> SyncProgress
+    SyncProgress,
     /// Key used to store the finalized blocks.
-    /// Block(validator::BlockNumber) -> validator::FinalBlock
+    /// Block(validator::BlockNumber) -> validator::CommitQC (in `JUSTIFICATIONS_CF`)
+    /// Block(validator::BlockNumber) -> validator::Payload (in `PAYLOADS_CF`)
+    /// Block(validator::BlockNumber) -> validator::KeyRotationCert (in `KEY_ROTATIONS_CF`)
+    /// Block(validator::BlockNumber) -> validator::ProtocolUpgradeQC (in `PROTOCOL_UPGRADES_CF`)
+    /// Block(validator::BlockNumber) -> validator::FinalBlock (legacy layout, in the default column family)
     Block(validator::BlockNumber),
 }
 
@@ -41,12 +50,26 @@ impl DatabaseKey {
         match self {
             // Keys for non-block entries must be smaller than all block keys.
             Self::ReplicaState => vec![0],
+            Self::SyncProgress => vec![0, 0],
             // Number encoding that monotonically increases with the number
             Self::Block(number) => number.0.to_be_bytes().to_vec(),
         }
     }
 }
 
+/// Column family storing the block justifications (which contain the block headers).
+/// Justifications are stored separately from the payloads, so that reading the headers
+/// doesn't require reading (potentially multi-MB) payloads.
+pub(crate) const JUSTIFICATIONS_CF: &str = "justifications";
+/// Column family storing the block payloads.
+pub(crate) const PAYLOADS_CF: &str = "payloads";
+/// Column family storing the key rotations committed by the blocks.
+/// Rotations are part of the chain state, so they are not pruned together with the blocks.
+pub(crate) const KEY_ROTATIONS_CF: &str = "key_rotations";
+/// Column family storing the protocol upgrades committed by the blocks.
+/// Like the rotations, the upgrades are not pruned together with the blocks.
+pub(crate) const PROTOCOL_UPGRADES_CF: &str = "protocol_upgrades";
+
 struct Inner {
     genesis: validator::Genesis,
     db: RwLock<rocksdb::DB>,
@@ -55,26 +78,41 @@ struct Inner {
 /// Main struct for the Storage module, it just contains the database. Provides a set of high-level
 /// atomic operations on the database. It "contains" the following data:
 ///
-/// - An append-only database of finalized blocks.
+/// - A database of finalized blocks (justifications and payloads stored separately),
+///   appended to at the end and pruned at the beginning.
 /// - A backup of the consensus replica state.
+/// - The progress of the block syncing.
 #[derive(Clone)]
-pub(crate) struct RocksDB(Arc<Inner>);
+pub struct RocksDB(Arc<Inner>);
 
 impl RocksDB {
     /// Create a new Storage. It first tries to open an existing database, and if that fails it just creates a
     /// a new one. We need the genesis block of the chain as input.
-    pub(crate) async fn open(genesis: validator::Genesis, path: &Path) -> ctx::Result<Self> {
+    /// Blocks stored in the legacy layout (whole blocks in the default column family)
+    /// are migrated to the current layout.
+    pub async fn open(genesis: validator::Genesis, path: &Path) -> ctx::Result<Self> {
         let mut options = rocksdb::Options::default();
         options.create_missing_column_families(true);
         options.create_if_missing(true);
+        let db = scope::wait_blocking(|| {
+            let db = rocksdb::DB::open_cf(
+                &options,
+                path,
+                [
+                    JUSTIFICATIONS_CF,
+                    PAYLOADS_CF,
+                    KEY_ROTATIONS_CF,
+                    PROTOCOL_UPGRADES_CF,
+                ],
+            )
+            .context("Failed opening RocksDB")?;
+            migrate_legacy_blocks(&db).context("Failed migrating blocks")?;
+            anyhow::Ok(db)
+        })
+        .await?;
         Ok(Self(Arc::new(Inner {
             genesis,
-            db: RwLock::new(
-                scope::wait_blocking(|| {
-                    rocksdb::DB::open(&options, path).context("Failed opening RocksDB")
-                })
-                .await?,
-            ),
+            db: RwLock::new(db),
         })))
     }
 
@@ -83,18 +121,153 @@ impl RocksDB {
         let mut options = ReadOptions::default();
         options.set_iterate_range(DatabaseKey::BLOCKS_START_KEY..);
         let Some(res) = db
-            .iterator_opt(DatabaseKey::BLOCK_HEAD_ITERATOR, options)
+            .iterator_cf_opt(
+                cf(&db, JUSTIFICATIONS_CF),
+                options,
+                DatabaseKey::BLOCK_HEAD_ITERATOR,
+            )
             .next()
         else {
             return Ok(None);
         };
         let (_, last) = res.context("RocksDB error reading head block")?;
-        let last: validator::FinalBlock =
-            zksync_protobuf::decode(&last).context("Failed decoding head block bytes")?;
-        Ok(Some(last.justification))
+        let last: validator::CommitQC =
+            zksync_protobuf::decode(&last).context("Failed decoding head block justification")?;
+        Ok(Some(last))
+    }
+
+    fn first_blocking(&self) -> anyhow::Result<Option<validator::BlockNumber>> {
+        let db = self.0.db.read().unwrap();
+        let Some(res) = db
+            .iterator_cf(cf(&db, JUSTIFICATIONS_CF), IteratorMode::Start)
+            .next()
+        else {
+            return Ok(None);
+        };
+        let (key, _) = res.context("RocksDB error reading first block")?;
+        let key = key[..].try_into().context("Malformed block key")?;
+        Ok(Some(validator::BlockNumber(u64::from_be_bytes(key))))
+    }
+
+    fn justification_blocking(
+        &self,
+        number: validator::BlockNumber,
+    ) -> anyhow::Result<validator::CommitQC> {
+        let db = self.0.db.read().unwrap();
+        let justification = db
+            .get_cf(
+                cf(&db, JUSTIFICATIONS_CF),
+                DatabaseKey::Block(number).encode_key(),
+            )
+            .context("RocksDB error")?
+            .context("not found")?;
+        zksync_protobuf::decode(&justification).context("failed decoding justification")
+    }
+
+    /// Genesis with the key rotations and the protocol upgrades committed by the stored blocks
+    /// applied. The ones committed under a different genesis (before a fork) are skipped.
+    fn genesis_blocking(&self) -> anyhow::Result<validator::Genesis> {
+        let db = self.0.db.read().unwrap();
+        let mut genesis = self.0.genesis.clone();
+        let hash = genesis.hash();
+        for res in db.iterator_cf(cf(&db, KEY_ROTATIONS_CF), IteratorMode::Start) {
+            let (_, cert) = res.context("RocksDB error reading key rotation")?;
+            let cert: validator::KeyRotationCert =
+                zksync_protobuf::decode(&cert).context("Failed decoding key rotation")?;
+            if cert.rotation.msg.genesis != hash {
+                continue;
+            }
+            genesis = genesis
+                .with_key_rotation(cert)
+                .context("with_key_rotation()")?;
+        }
+        for res in db.iterator_cf(cf(&db, PROTOCOL_UPGRADES_CF), IteratorMode::Start) {
+            let (key, qc) = res.context("RocksDB error reading protocol upgrade")?;
+            let key = key[..].try_into().context("Malformed block key")?;
+            let number = validator::BlockNumber(u64::from_be_bytes(key));
+            let qc: validator::ProtocolUpgradeQC =
+                zksync_protobuf::decode(&qc).context("Failed decoding protocol upgrade")?;
+            if qc.message.genesis != hash {
+                continue;
+            }
+            genesis = genesis
+                .with_protocol_upgrade(qc, number)
+                .context("with_protocol_upgrade()")?;
+        }
+        Ok(genesis)
     }
 }
 
+/// Returns a handle to the column family `name`.
+/// All the column families are created when opening the database.
+fn cf<'a>(db: &'a rocksdb::DB, name: &str) -> &'a rocksdb::ColumnFamily {
+    db.cf_handle(name).unwrap()
+}
+
+/// Maximal number of legacy blocks migrated in a single write batch.
+const MIGRATION_CHUNK: usize = 1000;
+
+/// Moves the blocks stored in the legacy layout (whole `FinalBlock` in the default column family)
+/// to the separate justification and payload column families.
+/// Blocks are migrated in chunks of bounded size. Every chunk is written atomically, together with
+/// the deletion of the migrated legacy entries, so an interrupted migration is resumed on the next start.
+fn migrate_legacy_blocks(db: &rocksdb::DB) -> anyhow::Result<()> {
+    let mut from = DatabaseKey::BLOCKS_START_KEY.to_vec();
+    while let Some(next) = migrate_legacy_chunk(db, &from, MIGRATION_CHUNK)? {
+        from = next;
+    }
+    Ok(())
+}
+
+/// Migrates up to `limit` legacy blocks with keys not lower than `from`.
+/// Returns the key to continue from, or `None` if there was nothing left to migrate.
+pub(crate) fn migrate_legacy_chunk(
+    db: &rocksdb::DB,
+    from: &[u8],
+    limit: usize,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut options = ReadOptions::default();
+    options.set_iterate_range(from..);
+    let mut write_batch = rocksdb::WriteBatch::default();
+    let mut last = None;
+    for res in db.iterator_opt(IteratorMode::Start, options).take(limit) {
+        let (key, block) = res.context("RocksDB error reading legacy block")?;
+        let block: validator::FinalBlock =
+            zksync_protobuf::decode(&block).context("Failed decoding legacy block")?;
+        write_batch.put_cf(
+            cf(db, JUSTIFICATIONS_CF),
+            &key,
+            zksync_protobuf::encode(&block.justification),
+        );
+        write_batch.put_cf(cf(db, PAYLOADS_CF), &key, &block.payload.0);
+        if let Some(cert) = &block.key_rotation {
+            write_batch.put_cf(
+                cf(db, KEY_ROTATIONS_CF),
+                &key,
+                zksync_protobuf::encode(cert),
+            );
+        }
+        if let Some(qc) = &block.protocol_upgrade {
+            write_batch.put_cf(
+                cf(db, PROTOCOL_UPGRADES_CF),
+                &key,
+                zksync_protobuf::encode(qc),
+            );
+        }
+        write_batch.delete(&key);
+        last = Some(key);
+    }
+    let Some(last) = last else {
+        return Ok(None);
+    };
+    db.write(write_batch)
+        .context("Failed writing migrated blocks")?;
+    // The smallest key greater than `last`.
+    let mut next = last.to_vec();
+    next.push(0);
+    Ok(Some(next))
+}
+
 impl fmt::Debug for RocksDB {
     fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
         formatter.write_str("RocksDB")
@@ -104,51 +277,145 @@ impl fmt::Debug for RocksDB {
 #[async_trait::async_trait]
 impl PersistentBlockStore for RocksDB {
     async fn genesis(&self, _ctx: &ctx::Ctx) -> ctx::Result<validator::Genesis> {
-        Ok(self.0.genesis.clone())
+        Ok(scope::wait_blocking(|| self.genesis_blocking()).await?)
     }
 
     async fn last(&self, _ctx: &ctx::Ctx) -> ctx::Result<Option<validator::CommitQC>> {
         Ok(scope::wait_blocking(|| self.last_blocking()).await?)
     }
 
+    async fn first(&self, _ctx: &ctx::Ctx) -> ctx::Result<Option<validator::BlockNumber>> {
+        Ok(scope::wait_blocking(|| self.first_blocking()).await?)
+    }
+
+    async fn prune(&self, _ctx: &ctx::Ctx, first: validator::BlockNumber) -> ctx::Result<()> {
+        scope::wait_blocking(|| {
+            let db = self.0.db.write().unwrap();
+            let end = DatabaseKey::Block(first).encode_key();
+            let mut write_batch = rocksdb::WriteBatch::default();
+            for name in [JUSTIFICATIONS_CF, PAYLOADS_CF] {
+                write_batch.delete_range_cf(cf(&db, name), DatabaseKey::BLOCKS_START_KEY, &end[..]);
+            }
+            db.write(write_batch).context("Failed pruning blocks")?;
+            Ok(())
+        })
+        .await
+        .wrap(first)
+    }
+
     async fn block(
         &self,
         _ctx: &ctx::Ctx,
         number: validator::BlockNumber,
     ) -> ctx::Result<validator::FinalBlock> {
         scope::wait_blocking(|| {
+            let justification = self.justification_blocking(number)?;
             let db = self.0.db.read().unwrap();
-            let block = db
-                .get(DatabaseKey::Block(number).encode_key())
+            let payload = db
+                .get_cf(
+                    cf(&db, PAYLOADS_CF),
+                    DatabaseKey::Block(number).encode_key(),
+                )
                 .context("RocksDB error")?
-                .context("not found")?;
-            Ok(zksync_protobuf::decode(&block).context("failed decoding block")?)
+                .context("payload not found")?;
+            let key_rotation = match justification.header().key_rotation {
+                Some(_) => {
+                    let cert = db
+                        .get_cf(
+                            cf(&db, KEY_ROTATIONS_CF),
+                            DatabaseKey::Block(number).encode_key(),
+                        )
+                        .context("RocksDB error")?
+                        .context("key rotation not found")?;
+                    Some(zksync_protobuf::decode(&cert).context("failed decoding key rotation")?)
+                }
+                None => None,
+            };
+            let protocol_upgrade = match justification.header().protocol_upgrade {
+                Some(_) => {
+                    let qc = db
+                        .get_cf(
+                            cf(&db, PROTOCOL_UPGRADES_CF),
+                            DatabaseKey::Block(number).encode_key(),
+                        )
+                        .context("RocksDB error")?
+                        .context("protocol upgrade not found")?;
+                    Some(zksync_protobuf::decode(&qc).context("failed decoding protocol upgrade")?)
+                }
+                None => None,
+            };
+            Ok(validator::FinalBlock {
+                payload: validator::Payload(payload),
+                justification,
+                key_rotation,
+                protocol_upgrade,
+            })
         })
         .await
         .wrap(number)
     }
 
+    async fn justification(
+        &self,
+        _ctx: &ctx::Ctx,
+        number: validator::BlockNumber,
+    ) -> ctx::Result<validator::CommitQC> {
+        scope::wait_blocking(|| Ok(self.justification_blocking(number)?))
+            .await
+            .wrap(number)
+    }
+
     #[tracing::instrument(level = "debug", skip(self))]
     async fn store_next_block(
         &self,
-        _ctx: &ctx::Ctx,
+        ctx: &ctx::Ctx,
         block: &validator::FinalBlock,
     ) -> ctx::Result<()> {
+        self.store_blocks(ctx, std::slice::from_ref(block)).await
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(len = blocks.len()))]
+    async fn store_blocks(
+        &self,
+        _ctx: &ctx::Ctx,
+        blocks: &[validator::FinalBlock],
+    ) -> ctx::Result<()> {
+        let Some(first) = blocks.first() else {
+            return Ok(());
+        };
         scope::wait_blocking(|| {
             let db = self.0.db.write().unwrap();
-            let block_number = block.header().number;
             let mut write_batch = rocksdb::WriteBatch::default();
-            write_batch.put(
-                DatabaseKey::Block(block_number).encode_key(),
-                zksync_protobuf::encode(block),
-            );
-            // Commit the transaction.
+            for block in blocks {
+                let key = DatabaseKey::Block(block.header().number).encode_key();
+                write_batch.put_cf(
+                    cf(&db, JUSTIFICATIONS_CF),
+                    &key,
+                    zksync_protobuf::encode(&block.justification),
+                );
+                write_batch.put_cf(cf(&db, PAYLOADS_CF), &key, &block.payload.0);
+                if let Some(cert) = &block.key_rotation {
+                    write_batch.put_cf(
+                        cf(&db, KEY_ROTATIONS_CF),
+                        &key,
+                        zksync_protobuf::encode(cert),
+                    );
+                }
+                if let Some(qc) = &block.protocol_upgrade {
+                    write_batch.put_cf(
+                        cf(&db, PROTOCOL_UPGRADES_CF),
+                        &key,
+                        zksync_protobuf::encode(qc),
+                    );
+                }
+            }
+            // Commit all the blocks in a single transaction.
             db.write(write_batch)
-                .context("Failed writing block to database")?;
+                .context("Failed writing blocks to database")?;
             Ok(())
         })
         .await
-        .wrap(block.header().number)
+        .wrap(first.header().number)
     }
 }
 
@@ -186,3 +453,38 @@ impl ReplicaStore for RocksDB {
         .await?)
     }
 }
+
+#[async_trait::async_trait]
+impl SyncProgressStore for RocksDB {
+    async fn progress(&self, _ctx: &ctx::Ctx) -> ctx::Result<SyncProgress> {
+        Ok(scope::wait_blocking(|| {
+            let Some(raw_progress) = self
+                .0
+                .db
+                .read()
+                .unwrap()
+                .get(DatabaseKey::SyncProgress.encode_key())
+                .context("Failed to get SyncProgress from RocksDB")?
+            else {
+                return Ok(SyncProgress::default());
+            };
+            zksync_protobuf::decode(&raw_progress).context("Failed to decode sync progress!")
+        })
+        .await?)
+    }
+
+    async fn set_progress(&self, _ctx: &ctx::Ctx, progress: &SyncProgress) -> ctx::Result<()> {
+        Ok(scope::wait_blocking(|| {
+            self.0
+                .db
+                .write()
+                .unwrap()
+                .put(
+                    DatabaseKey::SyncProgress.encode_key(),
+                    zksync_protobuf::encode(progress),
+                )
+                .context("Failed putting SyncProgress to RocksDB")
+        })
+        .await?)
+    }
+}
@@ -1,24 +1,60 @@
-use crate::{store, AppConfig};
+use crate::{
+    derivation::{self, DerivationPath, Mnemonic},
+    inspector,
+    keystore::{self, EncryptedKey, KdfParams, Passphrase},
+    loader,
+    remote_signer::SIGN_HASH_METHOD,
+    store, AppConfig, RPCServer, ReloadRequest, RemoteSigner, RemoteSignerConfig,
+};
+use jsonrpsee::{
+    core::client::ClientT,
+    http_client::HttpClientBuilder,
+    rpc_params,
+    server::{RpcModule, Server},
+    types::error::ErrorCode,
+};
+use k256::elliptic_curve::PrimeField as _;
 use rand::{
     distributions::{Distribution, Standard},
     Rng,
 };
+use std::sync::Arc;
 use tempfile::TempDir;
-use zksync_concurrency::ctx;
-use zksync_consensus_roles::{node, validator::testonly::Setup};
+use zksync_concurrency::{ctx, limiter, net, scope, time};
+use zksync_consensus_crypto::{secp256k1, ByteFmt, Text, TextFmt};
+use zksync_consensus_executor::{NodeRole, PublicAddrDetection, RelayAuth, RpcConfig};
+use zksync_consensus_roles::{
+    node,
+    validator::{self, testonly::Setup},
+};
 use zksync_consensus_storage::{testonly, PersistentBlockStore};
-use zksync_protobuf::testonly::test_encode_random;
+use zksync_protobuf::{
+    serde::Serde,
+    testonly::{test_encode, test_encode_random},
+};
 
 fn make_addr<R: Rng + ?Sized>(rng: &mut R) -> std::net::SocketAddr {
     std::net::SocketAddr::new(std::net::IpAddr::from(rng.gen::<[u8; 16]>()), rng.gen())
 }
 
+fn make_rate<R: Rng + ?Sized>(rng: &mut R) -> limiter::Rate {
+    limiter::Rate {
+        burst: rng.gen_range(1..100),
+        refresh: time::Duration::milliseconds(rng.gen_range(0..10000)),
+    }
+}
+
 impl Distribution<AppConfig> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> AppConfig {
         AppConfig {
             server_addr: make_addr(rng),
+            unix_socket_dir: cfg!(unix).then(|| format!("/tmp/{}", rng.gen::<u64>()).into()),
             public_addr: make_addr(rng),
+            public_addr_detection: Some(PublicAddrDetection {
+                min_reports: rng.gen_range(1..10),
+            }),
             metrics_server_addr: Some(make_addr(rng)),
+            log_filter: Some("info,zksync_consensus_network=debug".into()),
 
             genesis: rng.gen(),
 
@@ -29,7 +65,61 @@ impl Distribution<AppConfig> for Standard {
             gossip_static_outbound: (0..6)
                 .map(|_| (rng.gen::<node::SecretKey>().public(), make_addr(rng)))
                 .collect(),
+            gossip_dynamic_outbound_limit: rng.gen(),
+            gossip_relay_auth: match rng.gen_range(0..3) {
+                0 => RelayAuth::Disabled,
+                1 => RelayAuth::Sign,
+                _ => RelayAuth::Require,
+            },
+            gossip_peer_bandwidth_cap: rng.gen(),
+            genesis_mismatch_quarantine: time::Duration::milliseconds(rng.gen_range(1..1000000)),
+            consensus_replay_window: rng.gen(),
+            consensus_relay_max_hops: rng.gen(),
+            serve_blocks_bandwidth: rng.gen(),
+            serve_blocks_bandwidth_per_peer: rng.gen(),
+            per_peer_metrics: rng.gen(),
+            rpc_rate_limits: RpcConfig {
+                push_validator_addrs_rate: make_rate(rng),
+                push_block_store_state_rate: make_rate(rng),
+                get_block_rate: make_rate(rng),
+                get_block_chunk_rate: make_rate(rng),
+                consensus_rate: make_rate(rng),
+                push_validator_addrs_server_rate: make_rate(rng),
+                push_block_store_state_server_rate: make_rate(rng),
+                get_block_server_rate: make_rate(rng),
+                ..RpcConfig::default()
+            },
             max_payload_size: rng.gen(),
+            remote_signer: Some(RemoteSignerConfig {
+                url: format!("http://{}", make_addr(rng)),
+                public_key: rng.gen::<validator::SecretKey>().public(),
+                request_timeout: time::Duration::milliseconds(rng.gen_range(1..10000)),
+            }),
+            shadow_proposer: rng.gen(),
+            verifier_threads: rng.gen_range(1..16),
+            catch_up_threshold: rng.gen(),
+            observer: rng.gen(),
+            max_payload_wait: Some(time::Duration::milliseconds(rng.gen_range(0..10000))),
+            replica_checkpoint_dir: Some(format!("/tmp/{}", rng.gen::<u64>()).into()),
+            consensus_lock_dir: Some(format!("/tmp/{}", rng.gen::<u64>()).into()),
+            protocol_upgrade_activation: Some(validator::BlockNumber(rng.gen())),
+            consensus_trace: Some(format!("/tmp/{}", rng.gen::<u64>()).into()),
+            key_rotation: Some(rng.gen()),
+            crash_dir: Some(format!("/tmp/{}", rng.gen::<u64>()).into()),
+            ntp_servers: (0..rng.gen_range(0..3))
+                .map(|i| format!("ntp{i}.example.com:123"))
+                .collect(),
+            max_clock_skew: time::Duration::milliseconds(rng.gen_range(0..60000)),
+            sync_blocks_cross_check_peers: rng.gen_range(1..4),
+            sync_blocks_sample_chunks: rng.gen_range(0..5),
+            sync_blocks_genesis_check_quorum: Some(rng.gen_range(1..4)),
+            node_role: match rng.gen_range(0..3) {
+                0 => NodeRole::Archive,
+                1 => NodeRole::Pruned {
+                    retention: rng.gen_range(1..1000),
+                },
+                _ => NodeRole::Minimal,
+            },
         }
     }
 }
@@ -41,6 +131,137 @@ fn test_schema_encoding() {
     test_encode_random::<AppConfig>(rng);
 }
 
+#[test]
+fn test_genesis_file() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let setup = Setup::new(rng, 3);
+    let json = genesis::encode(&setup.genesis);
+    let got = genesis::decode(&json).unwrap();
+    assert_eq!(setup.genesis, got);
+    assert_eq!(setup.genesis.hash(), got.hash());
+
+    // Unknown fields are rejected.
+    let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    value
+        .as_object_mut()
+        .unwrap()
+        .insert("unknown".to_string(), 1.into());
+    assert!(genesis::decode(&value.to_string()).is_err());
+
+    // Required fields are enforced.
+    value.as_object_mut().unwrap().remove("unknown");
+    value.as_object_mut().unwrap().remove("fork");
+    assert!(genesis::decode(&value.to_string()).is_err());
+}
+
+#[test]
+fn test_check_reload() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let cfg: AppConfig = rng.gen();
+
+    let mut new = cfg.clone();
+    new.log_filter = None;
+    new.gossip_static_inbound.clear();
+    new.gossip_static_outbound = [(rng.gen::<node::SecretKey>().public(), make_addr(rng))].into();
+    new.gossip_dynamic_inbound_limit = new.gossip_dynamic_inbound_limit.wrapping_add(1);
+    new.serve_blocks_bandwidth = Some(1000);
+    new.serve_blocks_bandwidth_per_peer = None;
+    new.rpc_rate_limits.get_block_server_rate = make_rate(rng);
+    cfg.check_reload(&new).unwrap();
+    assert_eq!(
+        new.reloadable().gossip_static_outbound,
+        new.gossip_static_outbound
+    );
+    assert_eq!(new.reloadable().rpc, new.rpc_rate_limits);
+
+    let mut new = cfg.clone();
+    new.genesis = rng.gen();
+    new.max_payload_size = new.max_payload_size.wrapping_add(1);
+    let err = cfg.check_reload(&new).unwrap_err().to_string();
+    assert!(err.contains("genesis"), "{err}");
+    assert!(err.contains("maxPayloadSize"), "{err}");
+}
+
+#[test]
+fn test_config_formats() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let dir = TempDir::new().unwrap();
+    let cfg: AppConfig = rng.gen();
+    let value = serde_json::to_value(Serde(cfg.clone())).unwrap();
+    let mut files = vec![("config.json", serde_json::to_string(&value).unwrap())];
+    #[cfg(feature = "yaml")]
+    files.push(("config.yaml", serde_yaml::to_string(&value).unwrap()));
+    #[cfg(feature = "toml")]
+    files.push(("config.toml", toml::to_string(&value).unwrap()));
+    for (name, content) in files {
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        assert_eq!(cfg, loader::load(&path, []).unwrap(), "{name}");
+    }
+}
+
+#[test]
+fn test_config_env_overrides() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut want: AppConfig = rng.gen();
+    let mut value = serde_json::to_value(Serde(want.clone())).unwrap();
+    loader::apply_env_overrides(
+        &mut value,
+        [
+            ("CONSENSUS_CONFIG_GOSSIP_DYNAMIC_INBOUND_LIMIT", "7"),
+            ("CONSENSUS_CONFIG_LOG_FILTER", "debug"),
+            ("CONSENSUS_CONFIG_REMOTE_SIGNER__URL", "http://signer:1234"),
+            ("GOSSIP_DYNAMIC_INBOUND_LIMIT", "8"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string())),
+    )
+    .unwrap();
+    want.gossip_dynamic_inbound_limit = 7;
+    want.log_filter = Some("debug".into());
+    want.remote_signer.as_mut().unwrap().url = "http://signer:1234".into();
+    assert_eq!(want, loader::decode(value).unwrap());
+}
+
+#[test]
+fn test_config_validation_errors() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let cfg: AppConfig = rng.gen();
+    let paths = |value: serde_json::Value| -> Vec<String> {
+        let err = loader::decode(value).unwrap_err();
+        let mut paths: Vec<_> = err
+            .downcast_ref::<loader::ValidationErrors>()
+            .unwrap()
+            .0
+            .iter()
+            .map(|e| e.path.clone())
+            .collect();
+        paths.sort();
+        paths
+    };
+
+    // Fields of the wrong type and the unknown fields.
+    let mut value = serde_json::to_value(Serde(cfg.clone())).unwrap();
+    value["observer"] = "yes".into();
+    value["maxPayloadSize"] = serde_json::json!([1]);
+    value["unknownField"] = 1.into();
+    assert_eq!(paths(value), ["maxPayloadSize", "observer", "unknownField"]);
+
+    // Fields with invalid values.
+    let mut value = serde_json::to_value(Serde(cfg)).unwrap();
+    value["serverAddr"] = "not an address".into();
+    value["gossipStaticOutbound"][1]["addr"] = "not an address".into();
+    value.as_object_mut().unwrap().remove("genesis");
+    assert_eq!(
+        paths(value),
+        ["genesis", "gossip_static_outbound[1].addr", "server_addr"]
+    );
+}
+
 #[tokio::test]
 async fn test_reopen_rocksdb() {
     let ctx = &ctx::test_root(&ctx::RealClock);
@@ -58,3 +279,565 @@ async fn test_reopen_rocksdb() {
         assert_eq!(want, testonly::dump(ctx, &store).await);
     }
 }
+
+#[tokio::test]
+async fn test_prune_rocksdb() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let dir = TempDir::new().unwrap();
+    let mut setup = Setup::new(rng, 3);
+    setup.push_blocks(rng, 5);
+    let store = store::RocksDB::open(setup.genesis.clone(), dir.path())
+        .await
+        .unwrap();
+    for b in &setup.blocks {
+        store.store_next_block(ctx, b).await.unwrap();
+    }
+    assert_eq!(
+        Some(setup.blocks[0].number()),
+        store.first(ctx).await.unwrap()
+    );
+    store.prune(ctx, setup.blocks[2].number()).await.unwrap();
+    assert_eq!(
+        Some(setup.blocks[2].number()),
+        store.first(ctx).await.unwrap()
+    );
+    assert!(store.block(ctx, setup.blocks[1].number()).await.is_err());
+    assert_eq!(
+        setup.blocks[2..].to_vec(),
+        testonly::dump(ctx, &store).await
+    );
+}
+
+#[tokio::test]
+async fn test_rocksdb_key_rotations_and_protocol_upgrades() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let dir = TempDir::new().unwrap();
+    let mut setup = Setup::new(rng, 3);
+    let (_, cert) = setup.key_rotation(rng, 1, validator::ViewNumber(5), 2);
+    let activation = validator::BlockNumber(setup.genesis.fork.first_block.0 + 4);
+    let qc = setup.protocol_upgrade(validator::ProtocolVersion::PAYLOAD_ROOT, activation);
+    setup.push_blocks(rng, 1);
+    setup.push_block_with_key_rotation(rng.gen(), Some(cert.clone()));
+    setup.push_block_with_protocol_upgrade(rng.gen(), Some(qc.clone()));
+    setup.push_blocks(rng, 2);
+    {
+        let store = store::RocksDB::open(setup.genesis.clone(), dir.path())
+            .await
+            .unwrap();
+        for b in &setup.blocks {
+            store.store_next_block(ctx, b).await.unwrap();
+        }
+        assert_eq!(setup.blocks, testonly::dump(ctx, &store).await);
+        // Rotations and upgrades are kept when the blocks committing them are pruned.
+        store.prune(ctx, setup.blocks[3].number()).await.unwrap();
+    }
+    let store = store::RocksDB::open(setup.genesis.clone(), dir.path())
+        .await
+        .unwrap();
+    let genesis = store.genesis(ctx).await.unwrap();
+    assert!(genesis.key_rotations.contains(&cert));
+    assert!(genesis.protocol_upgrades.contains(&qc));
+    assert_eq!(setup.genesis.hash(), genesis.hash());
+}
+
+#[tokio::test]
+async fn test_migrate_legacy_rocksdb() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let dir = TempDir::new().unwrap();
+    let mut setup = Setup::new(rng, 3);
+    setup.push_blocks(rng, 5);
+    // Store the blocks in the legacy layout: whole blocks in the default column family.
+    {
+        let db = rocksdb::DB::open_default(dir.path()).unwrap();
+        for b in &setup.blocks {
+            db.put(
+                b.header().number.0.to_be_bytes(),
+                zksync_protobuf::encode(b),
+            )
+            .unwrap();
+        }
+    }
+    let store = store::RocksDB::open(setup.genesis.clone(), dir.path())
+        .await
+        .unwrap();
+    assert_eq!(setup.blocks, testonly::dump(ctx, &store).await);
+}
+
+#[tokio::test]
+async fn test_resume_legacy_migration() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let dir = TempDir::new().unwrap();
+    let mut setup = Setup::new(rng, 3);
+    setup.push_blocks(rng, 5);
+    {
+        let mut options = rocksdb::Options::default();
+        options.create_missing_column_families(true);
+        options.create_if_missing(true);
+        let db = rocksdb::DB::open_cf(
+            &options,
+            dir.path(),
+            [store::JUSTIFICATIONS_CF, store::PAYLOADS_CF],
+        )
+        .unwrap();
+        for b in &setup.blocks {
+            db.put(
+                b.header().number.0.to_be_bytes(),
+                zksync_protobuf::encode(b),
+            )
+            .unwrap();
+        }
+        // Migration gets interrupted after the first chunk.
+        let next = store::migrate_legacy_chunk(&db, &0u64.to_be_bytes(), 2).unwrap();
+        assert!(next.is_some());
+    }
+    let store = store::RocksDB::open(setup.genesis.clone(), dir.path())
+        .await
+        .unwrap();
+    assert_eq!(setup.blocks, testonly::dump(ctx, &store).await);
+}
+
+#[tokio::test]
+async fn test_inspector_export_import() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 3);
+    setup.push_blocks(rng, 6);
+    let dir = TempDir::new().unwrap();
+    let store = store::RocksDB::open(setup.genesis.clone(), dir.path())
+        .await
+        .unwrap();
+    for b in &setup.blocks {
+        store.store_next_block(ctx, b).await.unwrap();
+    }
+    let first = setup.blocks[0].number();
+    let last = setup.blocks.last().unwrap().number();
+    inspector::verify(ctx, &store, first, last).await.unwrap();
+
+    // Export the blocks in 2 overlapping ranges.
+    let mut head = vec![];
+    let got = inspector::export(ctx, &store, first, setup.blocks[2].number(), &mut head)
+        .await
+        .unwrap();
+    assert_eq!(3, got);
+    let mut all = vec![];
+    let got = inspector::export(ctx, &store, first, last, &mut all)
+        .await
+        .unwrap();
+    assert_eq!(setup.blocks.len(), got);
+
+    // Backfill an empty store.
+    let dir = TempDir::new().unwrap();
+    let backfilled = store::RocksDB::open(setup.genesis.clone(), dir.path())
+        .await
+        .unwrap();
+    let got = inspector::import(ctx, &backfilled, &mut &head[..])
+        .await
+        .unwrap();
+    assert_eq!(3, got);
+    let got = inspector::import(ctx, &backfilled, &mut &all[..])
+        .await
+        .unwrap();
+    assert_eq!(setup.blocks.len() - 3, got);
+    assert_eq!(setup.blocks, testonly::dump(ctx, &backfilled).await);
+    inspector::verify(ctx, &backfilled, first, last)
+        .await
+        .unwrap();
+
+    // Blocks of a different chain are rejected.
+    let dir = TempDir::new().unwrap();
+    let other = store::RocksDB::open(Setup::new(rng, 3).genesis.clone(), dir.path())
+        .await
+        .unwrap();
+    assert!(inspector::import(ctx, &other, &mut &all[..]).await.is_err());
+
+    // Frames with an oversized or truncated length prefix are rejected.
+    let huge = u32::MAX.to_le_bytes();
+    assert!(inspector::import(ctx, &backfilled, &mut &huge[..])
+        .await
+        .is_err());
+    let truncated = 1000u32.to_le_bytes();
+    assert!(inspector::import(ctx, &backfilled, &mut &truncated[..])
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_remote_signer() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let key: validator::SecretKey = rng.gen();
+
+    // Signing service holding `key`.
+    let server = Server::builder().build("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", server.local_addr().unwrap());
+    let mut module = RpcModule::new(key.clone());
+    module
+        .register_method(SIGN_HASH_METHOD, |params, key| {
+            let (_, hash): (String, String) =
+                params.parse().map_err(|_| ErrorCode::InvalidParams)?;
+            let hash: validator::MsgHash = Text::new(&hash)
+                .decode()
+                .map_err(|_| ErrorCode::InvalidParams)?;
+            Ok::<_, ErrorCode>(TextFmt::encode(&key.sign_hash(&hash)))
+        })
+        .unwrap();
+    let handle = server.start(module);
+
+    let cfg = RemoteSignerConfig {
+        url,
+        public_key: key.public(),
+        request_timeout: RemoteSignerConfig::DEFAULT_REQUEST_TIMEOUT,
+    };
+    let signer: Arc<dyn validator::ValidatorSigner> =
+        Arc::new(RemoteSigner::new(cfg.clone()).unwrap());
+    let hash: validator::MsgHash = rng.gen();
+    let sig = signer.sign_hash(ctx, &hash).await.unwrap();
+    sig.verify_hash(&hash, &key.public()).unwrap();
+
+    // Signatures made with a different key than configured should be rejected.
+    let signer = RemoteSigner::new(RemoteSignerConfig {
+        public_key: rng.gen::<validator::SecretKey>().public(),
+        ..cfg
+    })
+    .unwrap();
+    assert!(validator::ValidatorSigner::sign_hash(&signer, ctx, &hash)
+        .await
+        .is_err());
+    handle.stop().unwrap();
+}
+
+#[tokio::test]
+async fn test_rpc_finality() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 3);
+    setup.push_blocks(rng, 3);
+    let cfg: AppConfig = rng.gen();
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = testonly::new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        for b in &setup.blocks {
+            store.queue_block(ctx, b.clone()).await?;
+        }
+        let last = setup.blocks.last().unwrap();
+        store.wait_until_persisted(ctx, last.number()).await?;
+
+        let addr = *net::tcp::testonly::reserve_listener();
+        let server = RPCServer::new(addr, cfg.clone(), store.reader());
+        s.spawn_bg(async move { server.run(ctx).await });
+        let client = HttpClientBuilder::default().build(format!("http://{addr}"))?;
+
+        // The server might not be listening yet.
+        let latest: serde_json::Value = loop {
+            if let Ok(latest) = client.request("latest_finalized", rpc_params![]).await {
+                break latest;
+            }
+            ctx.sleep(time::Duration::milliseconds(100)).await?;
+        };
+        assert_eq!(latest["number"], last.number().0);
+        let qc: Serde<validator::CommitQC> =
+            serde_json::from_value(latest["justification"].clone())?;
+        qc.0.verify(&setup.genesis)?;
+        assert_eq!(qc.0, last.justification);
+
+        let want = &setup.blocks[1];
+        let got: serde_json::Value = client
+            .request("finalized", rpc_params![want.number().0])
+            .await?;
+        assert_eq!(got["number"], want.number().0);
+        let qc: Serde<validator::CommitQC> = serde_json::from_value(got["justification"].clone())?;
+        assert_eq!(qc.0, want.justification);
+
+        // Blocks which are not finalized yet are returned as null.
+        let got: serde_json::Value = client
+            .request("finalized", rpc_params![last.number().next().0])
+            .await?;
+        assert!(got.is_null());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_rpc_chain() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mut setup = Setup::new(rng, 3);
+    setup.push_blocks(rng, 3);
+    let cfg: AppConfig = rng.gen();
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = testonly::new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        for b in &setup.blocks {
+            store.queue_block(ctx, b.clone()).await?;
+        }
+        let last = setup.blocks.last().unwrap();
+        store.wait_until_persisted(ctx, last.number()).await?;
+
+        let addr = *net::tcp::testonly::reserve_listener();
+        let server = RPCServer::new(addr, cfg.clone(), store.reader());
+        s.spawn_bg(async move { server.run(ctx).await });
+        let client = HttpClientBuilder::default().build(format!("http://{addr}"))?;
+
+        // The server might not be listening yet.
+        let got: serde_json::Value = loop {
+            if let Ok(got) = client.request("consensus_getGenesis", rpc_params![]).await {
+                break got;
+            }
+            ctx.sleep(time::Duration::milliseconds(100)).await?;
+        };
+        assert_eq!(got["hash"], setup.genesis.hash().encode());
+        let genesis: Serde<validator::Genesis> = serde_json::from_value(got["genesis"].clone())?;
+        assert_eq!(genesis.0, setup.genesis);
+
+        let want = &setup.blocks[1];
+        let got: serde_json::Value = client
+            .request("consensus_getBlock", rpc_params![want.number().0])
+            .await?;
+        assert_eq!(got["number"], want.number().0);
+        assert_eq!(
+            got["hash"],
+            setup.genesis.header_hash(want.header()).encode()
+        );
+        let block: Serde<validator::FinalBlock> = serde_json::from_value(got["block"].clone())?;
+        assert_eq!(block.0, *want);
+
+        // Blocks which are not finalized yet are returned as null.
+        let got: serde_json::Value = client
+            .request("consensus_getBlock", rpc_params![last.number().next().0])
+            .await?;
+        assert!(got.is_null());
+
+        let got: serde_json::Value = client.request("consensus_status", rpc_params![]).await?;
+        assert_eq!(got["genesis_hash"], setup.genesis.hash().encode());
+        assert_eq!(got["first_block"], setup.genesis.first_block.0);
+        assert_eq!(got["next_block"], last.number().next().0);
+        assert_eq!(got["missing_blocks"], 0);
+        assert_eq!(got["last_finalized"]["number"], last.number().0);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[test]
+fn test_keystore() {
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let dir = TempDir::new().unwrap();
+    // Cheap key derivation to keep the test fast.
+    let kdf = KdfParams {
+        log_n: 4,
+        r: 8,
+        p: 1,
+    };
+    let passphrase = Passphrase::from("correct horse battery staple".to_string());
+
+    let key: validator::SecretKey = rng.gen();
+    let enc = EncryptedKey::encrypt(rng, &key, &passphrase, kdf).unwrap();
+    let got: validator::SecretKey = enc.decrypt(&passphrase).unwrap();
+    assert_eq!(key.public(), got.public());
+    let wrong = Passphrase::from("wrong".to_string());
+    assert!(enc.decrypt::<validator::SecretKey>(&wrong).is_err());
+    test_encode(rng, &enc);
+
+    // Encrypted key file.
+    let path = dir.path().join("node_key");
+    let key: node::SecretKey = rng.gen();
+    keystore::write_key(rng, &path, &key, &passphrase, kdf).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+    let got: node::SecretKey = keystore::read_key(&path, Some(&passphrase)).unwrap();
+    assert_eq!(key.public(), got.public());
+    assert!(keystore::read_key::<node::SecretKey>(&path, None).is_err());
+
+    // Plaintext key file.
+    std::fs::write(&path, TextFmt::encode(&key)).unwrap();
+    let got: node::SecretKey = keystore::read_key(&path, None).unwrap();
+    assert_eq!(key.public(), got.public());
+}
+
+#[test]
+fn test_key_derivation() {
+    let path = |s: &str| s.parse::<DerivationPath>().unwrap();
+    for s in ["m", "m/0", "m/44'/60'/0'/0/0", "m/2147483647'"] {
+        assert_eq!(s, path(s).to_string());
+    }
+    assert_eq!(path("m/44h/0h"), path("m/44'/0'"));
+    for s in ["", "44'/0'", "m/", "m/x", "m/2147483648"] {
+        assert!(s.parse::<DerivationPath>().is_err());
+    }
+
+    // Test vectors from BIP39, BIP32 and SLIP-0010.
+    let mnemonic = Mnemonic::parse(&format!("{} about", "abandon ".repeat(11))).unwrap();
+    let seed = mnemonic.to_seed();
+    assert_eq!(
+        "5eb00bbddcf069084889a8ab9155568165f5c453ccb85e70811aaed6f6da5fc1\
+         9a5ac40b389cd370d086206dec8aa6c43daea6690f20ad3d8d48b2d2ce9e38e4",
+        hex::encode(seed)
+    );
+    let key = derivation::bip32(&seed, &path("m/44'/60'/0'/0/0")).unwrap();
+    let key = secp256k1::SecretKey::decode(&key.to_repr()).unwrap();
+    assert_eq!(
+        "9858effd232b4033e47d90003d41ec34ecaeda94",
+        hex::encode(key.public().evm_address())
+    );
+    let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+    let key = derivation::slip10(
+        b"ed25519 seed",
+        &seed,
+        &path("m/0'/1'/2'/2'/1000000000'"),
+        |_| {},
+    )
+    .unwrap();
+    assert_eq!(
+        "8f94d394a8e8fd6b1bc2f3f49f5c47e385281d5c17e65324b0f62483e37e8793",
+        hex::encode(key)
+    );
+
+    // Consensus keys are derived only under their own purpose, and only with hardened
+    // derivation for ed25519 and bn254, so a wallet path is rejected.
+    let seed = mnemonic.to_seed();
+    for scheme in [
+        validator::SignatureScheme::Bn254,
+        validator::SignatureScheme::Secp256k1,
+    ] {
+        assert!(
+            derivation::derive_validator_key(&seed, &path("m/44'/60'/0'/0/0"), scheme).is_err()
+        );
+    }
+    assert!(derivation::derive_node_key(&seed, &path("m/44'/60'/1'/0'/0'")).is_err());
+    assert!(derivation::derive_node_key(&seed, &path("m/12381'/324'/0'/1")).is_err());
+
+    // bn254 keys don't have a standard derivation, so they are locked with a golden value.
+    let key = derivation::derive_validator_key(
+        &seed,
+        &path(derivation::DEFAULT_VALIDATOR_PATH),
+        validator::SignatureScheme::Bn254,
+    )
+    .unwrap();
+    assert_eq!(
+        "0a9238aa9e76846a0074a7800502554e622d07ea95d1c50de6f7913d212a687a",
+        hex::encode(ByteFmt::encode(&key))
+    );
+
+    // Generated mnemonics can be recovered.
+    let ctx = ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let mnemonic = Mnemonic::generate(rng, 24).unwrap();
+    assert_eq!(24, mnemonic.phrase().split(' ').count());
+    let got = Mnemonic::parse(&mnemonic.phrase()).unwrap();
+    assert_eq!(mnemonic.to_seed(), got.to_seed());
+    assert!(Mnemonic::generate(rng, 13).is_err());
+}
+
+#[tokio::test]
+async fn test_rpc_reload_config() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let setup = Setup::new(rng, 1);
+    let cfg: AppConfig = rng.gen();
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = testonly::new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+        let (send, mut recv) = ctx::channel::unbounded::<ReloadRequest>();
+        s.spawn_bg(async {
+            // Accept the first reload, reject the second.
+            recv.recv(ctx).await?.send(Ok(())).unwrap();
+            recv.recv(ctx)
+                .await?
+                .send(Err(anyhow::anyhow!("genesis changed")))
+                .unwrap();
+            Ok(())
+        });
+
+        let addr = *net::tcp::testonly::reserve_listener();
+        let admin_port = net::tcp::testonly::reserve_listener().port();
+        let server = RPCServer::new(addr, cfg.clone(), store.reader())
+            .with_config_reload(send)
+            .with_admin_port(admin_port);
+        s.spawn_bg(async move { server.run(ctx).await });
+        let client =
+            HttpClientBuilder::default().build(format!("http://127.0.0.1:{admin_port}"))?;
+
+        // The server might not be listening yet.
+        let got: serde_json::Value = loop {
+            if let Ok(got) = client.request("reload_config", rpc_params![]).await {
+                break got;
+            }
+            ctx.sleep(time::Duration::milliseconds(100)).await?;
+        };
+        assert_eq!(got["reloaded"], true);
+        let got: serde_json::Value = client.request("reload_config", rpc_params![]).await?;
+        assert_eq!(got["reloaded"], false);
+        assert_eq!(got["error"], "genesis changed");
+
+        // Admin methods are not served on the public address.
+        let client = HttpClientBuilder::default().build(format!("http://{addr}"))?;
+        let got: Result<serde_json::Value, _> =
+            client.request("reload_config", rpc_params![]).await;
+        assert!(got.is_err());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_rpc_wire_dump() {
+    let ctx = &ctx::test_root(&ctx::RealClock);
+    let rng = &mut ctx.rng();
+    let setup = Setup::new(rng, 1);
+    let cfg: AppConfig = rng.gen();
+    let dir = TempDir::new().unwrap();
+    let peer = rng.gen::<node::SecretKey>().public().encode();
+    scope::run!(ctx, |ctx, s| async {
+        let (store, runner) = testonly::new_store(ctx, &setup.genesis).await;
+        s.spawn_bg(runner.run(ctx));
+
+        let addr = *net::tcp::testonly::reserve_listener();
+        let admin_port = net::tcp::testonly::reserve_listener().port();
+        let monitor = zksync_consensus_network::Monitor::default();
+        let server = RPCServer::new(addr, cfg.clone(), store.reader())
+            .with_wire_dumps(monitor.clone(), dir.path().into())
+            .with_admin_port(admin_port);
+        s.spawn_bg(async move { server.run(ctx).await });
+        let client =
+            HttpClientBuilder::default().build(format!("http://127.0.0.1:{admin_port}"))?;
+
+        // The server might not be listening yet.
+        let got: serde_json::Value = loop {
+            if let Ok(got) = client.request("wire_dump", rpc_params![&peer, 60]).await {
+                break got;
+            }
+            ctx.sleep(time::Duration::milliseconds(100)).await?;
+        };
+        assert_eq!(got["dumps"][0]["peer"], peer.as_str());
+        assert_eq!(monitor.dumps(ctx.now_utc()).len(), 1);
+
+        // Dumps longer than an hour and of invalid keys are rejected.
+        let got: Result<serde_json::Value, _> =
+            client.request("wire_dump", rpc_params![&peer, 7200]).await;
+        assert!(got.is_err());
+        let got: Result<serde_json::Value, _> = client
+            .request("wire_dump", rpc_params!["node:public", 60])
+            .await;
+        assert!(got.is_err());
+
+        // A zero duration stops the dump.
+        let got: serde_json::Value = client.request("wire_dump", rpc_params![&peer, 0]).await?;
+        assert_eq!(got["dumps"], serde_json::json!([]));
+        Ok(())
+    })
+    .await
+    .unwrap();
+}